@@ -0,0 +1,39 @@
+//! Times hashing a file with different buffer sizes, with and without mmap
+//!
+//! Usage: `cargo run --release --example hash_benchmark -- <file>`
+//!
+//! Each setting hashes the whole file, so use one larger than memory (or drop the page cache
+//! between runs) to measure the disk rather than the cache.
+
+use std::time::Instant;
+
+use ndumplib::{FileHash, HashAlgorithm, HashingOptions, set_hashing_options};
+
+fn main() -> ndumplib::Result<()> {
+    let Some(path) = std::env::args().nth(1) else {
+        eprintln!("Usage: hash_benchmark <file>");
+        std::process::exit(2);
+    };
+    let size = std::fs::metadata(&path)
+        .expect("Failed to read the file's size")
+        .len();
+    for mmap in [false, true] {
+        for buffer_size in [8 << 10, 64 << 10, 256 << 10, 1 << 20, 4 << 20, 16 << 20] {
+            set_hashing_options(HashingOptions {
+                buffer_size,
+                mmap,
+                ..HashingOptions::default()
+            });
+            let start = Instant::now();
+            FileHash::of_file(HashAlgorithm::SHA1, &path)?;
+            let seconds = start.elapsed().as_secs_f64();
+            println!(
+                "{:>5} KiB{}: {:>8.1} MB/s",
+                buffer_size >> 10,
+                if mmap { ", mmap" } else { "" },
+                size as f64 / seconds / 1e6
+            );
+        }
+    }
+    Ok(())
+}
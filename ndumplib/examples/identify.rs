@@ -0,0 +1,41 @@
+//! Identifies and verifies dumps against the catalog in a data folder
+//!
+//! Usage: `cargo run --example identify -- <data folder> <dump>...`
+//!
+//! The catalog is downloaded into the data folder on the first run, which takes a while.
+
+use std::path::PathBuf;
+
+use ndumplib::{DumpManager, WhenLocked};
+
+fn main() -> ndumplib::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let Some(data_folder) = args.next() else {
+        eprintln!("Usage: identify <data folder> <dump>...");
+        std::process::exit(2);
+    };
+    std::fs::create_dir_all(&data_folder).expect("Failed to create the data folder");
+    let mut manager = DumpManager::init(&data_folder, WhenLocked::Wait)?;
+    manager.update()?;
+    for dump in args.map(PathBuf::from) {
+        if !manager.can_verify(&dump) {
+            println!("{}: not a dump ndumplib can identify", dump.display());
+            continue;
+        }
+        match manager.get_rom_info(&dump.to_string_lossy())? {
+            Some(info) => println!(
+                "{}: {} ({}), {:?}",
+                dump.display(),
+                info.game_name,
+                info.console.formal_name(),
+                manager.verify_file(&dump)?
+            ),
+            None => println!("{}: not in the catalog", dump.display()),
+        }
+    }
+    // warnings are collected rather than returned, so they're printed at the end
+    for warning in ndumplib::take_warnings() {
+        eprintln!("Warning: {}", warning.message);
+    }
+    Ok(())
+}
@@ -1,50 +1,662 @@
 use std::{
+    collections::{HashMap, HashSet},
     fs::File,
+    io::{Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
 };
 
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{debug, info, warn};
 use sha1::{Digest, Sha1};
 use tempfile::TempDir;
 
-use self::{catalog::Catalog, cuesheets::Cuesheets};
-use crate::{GameConsole, Result, ResultUtils};
+use self::{catalog::Catalog, conversion::ConversionSource, cuesheets::Cuesheets};
+use crate::{
+    Digests, Error, ErrorCategory, FileHash, GameConsole, Result, ResultUtils, hashing,
+    transfer::write_atomically,
+    utils::{
+        cartridge,
+        chdman::{self, ChdMedia},
+        dolphin::{self, DiscFormat},
+        maxcso::{self, CsoFormat, CsoOptions},
+        nintendo, serial, wii, work,
+        xbox360::{self, GodHeader},
+        zip,
+    },
+};
 
+mod archive;
 mod catalog;
-mod cuesheets;
+mod conversion;
+pub(crate) mod cuesheets;
+mod gdi;
+pub(crate) mod network;
+mod split;
+pub(crate) mod timings;
+mod warnings;
+
+pub use self::archive::ArchiveEntry;
+pub use self::catalog::{
+    AvailableDatafile, BlockRule, CatalogChange, CatalogROM, CatalogReader, ChangeKind,
+    CollectionPart, CustomGame, CustomROM, DatafileDiff, DatafileInfo, DatafileSource,
+    FetchedDatafile, FileDatafileSource, GameEntry, GameQuery, GameRename, IndexedGame,
+    LocalDatafile, NoIntroSource, ROMChange, RedumpSource, TrackIndex,
+};
+pub use self::conversion::{ConversionJob, ConversionLimits};
+pub use self::network::NetworkTimeouts;
+pub use self::split::{SplitDump, SplitKind, extract_archive, find_split_dumps};
+pub use self::timings::{RunTimings, Stage, run_timings};
+use self::warnings::WarningScope;
+pub use self::warnings::{Warning, WarningKind, collect_warnings, report_warning, take_warnings};
 
+#[derive(Clone)]
 pub struct ROMInfo {
     pub console: GameConsole,
     pub game_name: String,
     pub preferred_file_name: String,
+    pub serial: Option<String>,
+    /// The region tag of the game's name, for sorting dumps by region
+    pub region: Option<String>,
+    /// For dumps whose files must stay in a folder of their own (the data parts of Xbox 360 Games
+    /// on Demand packages), the name of that folder, which goes between the game's folder and the
+    /// file
+    pub subfolder: Option<String>,
+    /// The dump status the datafile gives the ROM ("verified" or "baddump"), if any
+    pub status: Option<&'static str>,
+}
+
+/// A dump patched by [DumpManager::patch_dump]
+pub struct PatchedDump {
+    /// The game of the dump the patch was applied to
+    pub base: ROMInfo,
+    /// The hashes of the patched file
+    pub digests: Digests,
+}
+
+/// A game's serial as printed on its disc's label, like "SLUS-00594"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiscSerial {
+    /// The console the disc's layout belongs to
+    pub console: GameConsole,
+    pub serial: String,
+}
+
+/// What happens to a dump's files once [DumpManager::convert_file] or
+/// [DumpManager::convert_disc_image] has converted it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceHandling {
+    Keep,
+    /// Deletes the dump's files, but only once the CHD passes `chdman verify` (or dolphin-tool can
+    /// read the disc image back) and its data SHA-1 is in the catalog
+    RemoveVerified,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ROMStatus {
     Verified,
+    /// Only the data partition of a Wii disc matched the catalog (see
+    /// [DumpManager::with_partition_hashing]), so the rest of the disc wasn't verified
+    DataPartitionVerified,
     Unverified,
+    /// The dump was changed on purpose, so it can't match the catalog however intact it is
+    Modified(ImageModification),
     Broken,
+    /// The dump matches a ROM its datafile marks as a bad dump, so the disc (or cartridge) should
+    /// be dumped again
+    KnownBadDump,
+}
+
+/// How a GameCube or Wii image was changed from the disc, which stops it from ever matching
+/// Redump's hashes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageModification {
+    /// Shrunk by NKit, which can rebuild the original image (`NKit.exe` with "Recover to ISO")
+    NKit,
+    /// The padding and unused data were blanked out, or trimmed off, which can't be undone
+    Scrubbed,
+}
+
+/// Catalog/cuesheet data to update even if it was updated recently
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpdateTarget {
+    All,
+    Console(GameConsole),
+    Redump,
+    NoIntro,
+}
+
+impl UpdateTarget {
+    pub(crate) fn covers_redump(&self, console: GameConsole) -> bool {
+        matches!(self, Self::All | Self::Redump) || *self == Self::Console(console)
+    }
+    pub(crate) fn covers_nointro(&self, console: GameConsole) -> bool {
+        matches!(self, Self::All | Self::NoIntro) || *self == Self::Console(console)
+    }
+}
+
+impl FromStr for UpdateTarget {
+    type Err = Error;
+
+    /// Parses "all", "redump", "no-intro", or a console name
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "all" => Ok(Self::All),
+            "redump" => Ok(Self::Redump),
+            "no-intro" | "nointro" => Ok(Self::NoIntro),
+            _ => value.parse().map(Self::Console).map_err(|_| {
+                Error::new_original(format!(
+                    "Unknown update target: \"{value}\" (expected \"all\", \"redump\", \"no-intro\", or a console)"
+                ))
+                .with_category(ErrorCategory::InvalidInput)
+            }),
+        }
+    }
+}
+
+/// The result of checking one of the dump manager's databases
+pub struct DatabaseCheck {
+    pub database: String,
+    /// Problems reported by SQLite's integrity and foreign key checks
+    pub problems: Vec<String>,
+    /// The problems the checks still report after repairing, which are the same as `problems` if
+    /// the database wasn't repaired
+    pub remaining_problems: Vec<String>,
+    /// The number of rows in each table that have lost the row they belong to
+    pub orphaned_rows: Vec<(String, usize)>,
+    /// Whether the orphaned rows were deleted and the indexes rebuilt
+    pub repaired: bool,
+}
+
+impl DatabaseCheck {
+    pub fn is_healthy(&self) -> bool {
+        self.problems.is_empty() && self.orphaned_rows.is_empty()
+    }
+}
+
+/// When the databases' write-ahead logs are written back into them (checkpointed), which keeps
+/// the logs from growing, like on a network share where a big log is slow to read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CheckpointPolicy {
+    /// Whether the logs are checkpointed when the databases are closed
+    pub on_close: bool,
+    /// Whether checkpoints on close empty the log files, rather than leaving them to be reused
+    pub truncate: bool,
+    /// How many pages a log may grow to before it's checkpointed as it's written (SQLite's
+    /// default is 1000), or `None` for SQLite's default
+    pub auto_checkpoint_pages: Option<u32>,
+}
+
+impl Default for CheckpointPolicy {
+    fn default() -> Self {
+        CheckpointPolicy {
+            on_close: true,
+            truncate: true,
+            auto_checkpoint_pages: None,
+        }
+    }
+}
+
+/// What maintaining one of the dump manager's databases did (see [DumpManager::maintain_databases])
+pub struct DatabaseMaintenance {
+    pub database: String,
+    /// The database's size before it was vacuumed, in bytes
+    pub size_before: u64,
+    /// The database's size after it was vacuumed, in bytes
+    pub size_after: u64,
+}
+
+/// The statements creating one of the dump manager's databases
+pub struct DatabaseSchema {
+    pub database: String,
+    /// The number of migrations applied, which increases whenever the schema changes
+    pub version: usize,
+    pub statements: Vec<String>,
+}
+
+/// A dump to be given its name from the catalog
+pub struct Rename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// What [DumpManager::plan_renames] found for a set of dumps
+#[derive(Default)]
+pub struct RenamePlan {
+    /// Dumps whose names differ from the catalog's, which can be renamed safely
+    pub renames: Vec<Rename>,
+    /// Dumps which can't be renamed, because another file already has (or would get) their name
+    pub collisions: Vec<Rename>,
+    /// Dumps which aren't in the catalog
+    pub unidentified: Vec<PathBuf>,
+}
+
+/// A game some of whose tracks were found by [DumpManager::match_tracks]
+pub struct GameTracks {
+    pub gid: i64,
+    pub console: GameConsole,
+    pub name: String,
+    /// The files found, with the names of the tracks they are
+    pub found: Vec<(PathBuf, String)>,
+    /// The names of the game's tracks which weren't found
+    pub missing: Vec<String>,
+}
+
+/// What [DumpManager::match_tracks] found for a set of files
+#[derive(Default)]
+pub struct TrackMatches {
+    /// The games with tracks among the files, sorted by name
+    pub games: Vec<GameTracks>,
+    /// Files which aren't tracks of any game in the catalog
+    pub unmatched: Vec<PathBuf>,
+}
+
+/// What was done on startup because the last run didn't shut down cleanly (see
+/// [DumpManager::recovery])
+pub struct Recovery {
+    /// The databases whose write-ahead logs still held transactions, which were replayed on opening
+    pub replayed_logs: Vec<String>,
+    /// The checks run on each database, with orphaned rows repaired
+    pub checks: Vec<DatabaseCheck>,
+    /// Problems the repair couldn't fix, like corruption
+    pub remaining_problems: Vec<String>,
+}
+
+impl Recovery {
+    pub fn is_complete(&self) -> bool {
+        self.remaining_problems.is_empty()
+    }
+}
+
+/// What [DumpManager::init] does if another process is using the data folder
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WhenLocked {
+    /// Returns an [ErrorCategory::Busy] error
+    Fail,
+    /// Waits for the other process to finish
+    Wait,
+}
+
+/// What a CHD is listed under in datafiles (see [DumpManager::chd_identity])
+///
+enum ChdIdentity {
+    /// The header's SHA-1 of the uncompressed data
+    Data([u8; 20]),
+    /// The SHA-1s of the disc's tracks, in order
+    Tracks(Vec<[u8; 20]>),
 }
 
+/// A file marking the data folder as in use, which is only left behind if a run doesn't shut down
+/// cleanly
+///
+/// The run holds the file locked, so the lock is released when its process ends however it ends,
+/// and a lock left behind is known to be stale. It also holds the PID of the run, to say which
+/// process is using the data folder.
+struct RunLock {
+    path: PathBuf,
+    file: File,
+    kept: bool,
+}
+
+impl RunLock {
+    /// Takes the lock, returning whether a previous run left it behind
+    ///
+    fn acquire(path: PathBuf, when_locked: WhenLocked) -> Result<(RunLock, bool)> {
+        let mut waiting = false;
+        loop {
+            let mut file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)
+                .ndl("Failed to lock data folder")?;
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(std::fs::TryLockError::WouldBlock) => {
+                    // a run which is still going (e.g. in another terminal) hasn't crashed
+                    let owner = std::fs::read_to_string(&path)
+                        .ok()
+                        .and_then(|content| content.trim().parse::<u32>().ok())
+                        .map_or(String::new(), |owner| format!(" (process {owner})"));
+                    if when_locked == WhenLocked::Fail {
+                        return Err(Error::new_original(format!(
+                            "Another instance of ndumpmgr{owner} is using the data folder"
+                        ))
+                        .with_category(ErrorCategory::Busy));
+                    }
+                    if !waiting {
+                        info!("Waiting for another instance of ndumpmgr{owner} to finish...");
+                        waiting = true;
+                    }
+                    std::thread::sleep(Duration::from_secs(1));
+                    continue;
+                }
+                Err(std::fs::TryLockError::Error(err)) => {
+                    return Err(err).ndl("Failed to lock data folder");
+                }
+            }
+            // a run finishing meanwhile removes the file it had locked, which is then no lock at
+            // all, so try again with the one at the path now
+            if !is_same_file(&file, &path) {
+                continue;
+            }
+            let mut owner = String::new();
+            file.read_to_string(&mut owner)
+                .ndl("Failed to lock data folder")?;
+            let unclean = !owner.trim().is_empty();
+            if unclean {
+                debug!("Taking over the stale lock \"{}\"", path.display());
+            }
+            file.set_len(0)
+                .and_then(|_| file.seek(SeekFrom::Start(0)))
+                .and_then(|_| file.write_all(std::process::id().to_string().as_bytes()))
+                .and_then(|_| file.sync_all())
+                .ndl("Failed to lock data folder")?;
+            return Ok((
+                RunLock {
+                    path,
+                    file,
+                    kept: false,
+                },
+                unclean,
+            ));
+        }
+    }
+
+    /// Leaves the lock behind on shutdown, so the next run recovers again
+    ///
+    fn keep(&mut self) {
+        self.kept = true;
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        // removed before it's unlocked, so another run can't lock the file just to be removed
+        if !self.kept {
+            let _ = std::fs::remove_file(&self.path);
+        }
+        let _ = self.file.unlock();
+    }
+}
+
+/// Whether `file` is still the file at `path`, rather than one which was removed (or replaced)
+fn is_same_file(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), std::fs::metadata(path)) {
+        (Ok(opened), Ok(current)) => opened.dev() == current.dev() && opened.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+/// Whether a database's write-ahead log still holds transactions which weren't written back
+fn has_pending_log(database_path: &Path) -> bool {
+    let mut log_path = database_path.as_os_str().to_owned();
+    log_path.push("-wal");
+    std::fs::metadata(log_path).is_ok_and(|metadata| metadata.len() > 0)
+}
+
+/// The files in a data folder holding the catalog and cuesheet databases
+const CATALOG_FILE_NAME: &str = "catalog.sqlite";
+const CUESHEETS_FILE_NAME: &str = "cuesheets.sqlite";
+
+/// The file in a data folder which stops two runs from using it at once (see [WhenLocked])
+const LOCK_FILE_NAME: &str = "ndumpmgr.lock";
+
 pub struct DumpManager {
     catalog: Catalog,
     cuesheets: Cuesheets,
+    deep_chd_verification: bool,
+    deep_chd_verification_interval: Option<TimeDelta>,
+    partition_hashing: bool,
+    cso_options: CsoOptions,
+    conversion_limits: ConversionLimits,
+    maintenance_interval: Option<TimeDelta>,
+    recovery: Option<Recovery>,
+    // dropped last, so the lock is only released once the databases are closed (read-only managers
+    // don't take it, as they don't write to the data folder)
+    _lock: Option<RunLock>,
 }
 
 impl DumpManager {
-    pub fn init(path: &impl AsRef<Path>) -> Result<DumpManager> {
+    /// Opens the databases in a data folder
+    ///
+    /// If the last run didn't shut down cleanly, the databases are checked and repaired before
+    /// returning (see [DumpManager::recovery]). Only one process can use a data folder at a time.
+    pub fn init(path: &impl AsRef<Path>, when_locked: WhenLocked) -> Result<DumpManager> {
+        let base_folder_path = PathBuf::from(path.as_ref());
+        let catalog_path = base_folder_path.join(CATALOG_FILE_NAME);
+        let cuesheets_path = base_folder_path.join(CUESHEETS_FILE_NAME);
+        let (lock, unclean) = RunLock::acquire(base_folder_path.join(LOCK_FILE_NAME), when_locked)?;
+        // SQLite replays these when the databases are opened, so they're noted beforehand
+        let replayed_logs: Vec<String> =
+            [("catalog", &catalog_path), ("cuesheet", &cuesheets_path)]
+                .into_iter()
+                .filter(|(_, path)| unclean && has_pending_log(path))
+                .map(|(name, _)| name.to_string())
+                .collect();
+        let mut manager = DumpManager {
+            catalog: Catalog::init(&catalog_path)?,
+            cuesheets: Cuesheets::init(&cuesheets_path)?,
+            deep_chd_verification: false,
+            deep_chd_verification_interval: None,
+            partition_hashing: false,
+            cso_options: CsoOptions::default(),
+            conversion_limits: ConversionLimits::default(),
+            maintenance_interval: None,
+            recovery: None,
+            _lock: Some(lock),
+        };
+        if unclean {
+            warn!("The last run didn't shut down cleanly. Checking databases...");
+            match manager.recover(replayed_logs) {
+                Ok(recovery) => {
+                    if !recovery.is_complete()
+                        && let Some(lock) = &mut manager._lock
+                    {
+                        lock.keep();
+                    }
+                    manager.recovery = Some(recovery);
+                }
+                Err(err) => {
+                    if let Some(lock) = &mut manager._lock {
+                        lock.keep();
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(manager)
+    }
+
+    /// Opens the databases in a data folder without writing to it, like a network share or a
+    /// snapshot
+    ///
+    /// Nothing is written to the data folder: it isn't locked, the databases' schemas aren't
+    /// brought up to date (opening fails if they're out of date), and they aren't optimized or
+    /// checkpointed when they're closed. Anything which would change the databases, like
+    /// [DumpManager::update], fails.
+    pub fn open_read_only(path: &impl AsRef<Path>) -> Result<DumpManager> {
         let base_folder_path = PathBuf::from(path.as_ref());
         Ok(DumpManager {
-            catalog: Catalog::init(&base_folder_path.join("./catalog.sqlite"))?,
-            cuesheets: Cuesheets::init(&base_folder_path.join("./cuesheets.sqlite"))?,
+            catalog: Catalog::open_read_only(&base_folder_path.join(CATALOG_FILE_NAME))?,
+            cuesheets: Cuesheets::open_read_only(&base_folder_path.join(CUESHEETS_FILE_NAME))?,
+            deep_chd_verification: false,
+            deep_chd_verification_interval: None,
+            partition_hashing: false,
+            cso_options: CsoOptions::default(),
+            conversion_limits: ConversionLimits::default(),
+            maintenance_interval: None,
+            recovery: None,
+            _lock: None,
+        })
+    }
+
+    /// Whether the databases were opened with [DumpManager::open_read_only]
+    ///
+    pub fn is_read_only(&self) -> bool {
+        self._lock.is_none()
+    }
+
+    /// Fails if the databases were opened read-only, before something which would change them
+    fn ensure_writable(&self, action: &str) -> Result<()> {
+        if self.is_read_only() {
+            return Err(Error::new_original(format!(
+                "Can't {action}, as the data folder was opened read-only"
+            ))
+            .with_category(ErrorCategory::InvalidInput));
+        }
+        Ok(())
+    }
+
+    /// Checks the databases after an unclean shutdown, repairing what can be repaired
+    ///
+    fn recover(&mut self, replayed_logs: Vec<String>) -> Result<Recovery> {
+        let checks = self.check_databases(true)?;
+        let remaining_problems = checks
+            .iter()
+            .flat_map(|check| {
+                check
+                    .remaining_problems
+                    .iter()
+                    .map(move |problem| format!("{} DB: {problem}", check.database))
+            })
+            .collect();
+        Ok(Recovery {
+            replayed_logs,
+            checks,
+            remaining_problems,
         })
     }
 
+    /// What was recovered on startup, if the last run didn't shut down cleanly
+    ///
+    pub fn recovery(&self) -> Option<&Recovery> {
+        self.recovery.as_ref()
+    }
+
+    /// Sets how long to wait before checking datafiles for updates again
+    ///
+    pub fn with_datafile_update_delay(mut self, delay: Duration) -> DumpManager {
+        self.catalog = self
+            .catalog
+            .with_update_delay(TimeDelta::from_std(delay).unwrap_or(TimeDelta::MAX));
+        self
+    }
+
+    /// Sets how long downloads may take (see [NetworkTimeouts])
+    ///
+    /// This replaces the datafile sources with No-Intro and Redump, so it has to come before
+    /// [DumpManager::with_datafile_sources].
+    pub fn with_network_timeouts(mut self, timeouts: NetworkTimeouts) -> DumpManager {
+        self.catalog = self.catalog.with_network_timeouts(timeouts);
+        self.cuesheets = self.cuesheets.with_network_timeouts(timeouts);
+        self
+    }
+
+    /// Replaces the sources datafiles are downloaded from (No-Intro and Redump by default)
+    ///
+    /// Local datafiles added with [DumpManager::add_local_datafile] are imported either way.
+    pub fn with_datafile_sources(mut self, sources: Vec<Arc<dyn DatafileSource>>) -> DumpManager {
+        self.catalog = self.catalog.with_datafile_sources(sources);
+        self
+    }
+
+    /// Sets how long to wait before checking cuesheets for updates again
+    ///
+    pub fn with_cuesheet_update_delay(mut self, delay: Duration) -> DumpManager {
+        self.cuesheets = self
+            .cuesheets
+            .with_update_delay(TimeDelta::from_std(delay).unwrap_or(TimeDelta::MAX));
+        self
+    }
+
+    /// Sets whether verifying a CHD always decompresses it with `chdman verify`, rather than only
+    /// checking its header
+    ///
+    pub fn with_deep_chd_verification(mut self, deep: bool) -> DumpManager {
+        self.deep_chd_verification = deep;
+        self
+    }
+
+    /// Sets how often each CHD is verified with `chdman verify` (otherwise only its header is
+    /// checked), or `None` to leave it to [DumpManager::with_deep_chd_verification]
+    ///
+    /// CHDs are told apart by their header's SHA-1, and when they last passed is kept in the catalog,
+    /// so a CHD which was moved or renamed isn't verified again before it's due.
+    pub fn with_deep_chd_verification_interval(
+        mut self,
+        interval: Option<Duration>,
+    ) -> DumpManager {
+        self.deep_chd_verification_interval =
+            interval.map(|interval| TimeDelta::from_std(interval).unwrap_or(TimeDelta::MAX));
+        self
+    }
+
+    /// Experimental: sets whether Wii images which don't match the catalog are checked again by only
+    /// their data partition
+    ///
+    /// Images with their update partition stripped never match a full disc hash, but their data
+    /// partition is untouched. This only finds a match if a datafile (e.g. a local datafile) lists the
+    /// data partition's SHA-1, and matches are reported as [ROMStatus::DataPartitionVerified].
+    pub fn with_partition_hashing(mut self, enabled: bool) -> DumpManager {
+        self.partition_hashing = enabled;
+        self
+    }
+
+    /// Sets how maxcso compresses ISOs and decompresses CSOs and ZSOs
+    ///
+    pub fn with_cso_options(mut self, options: CsoOptions) -> DumpManager {
+        self.cso_options = options;
+        self
+    }
+
+    /// Sets how many CHDs [DumpManager::convert_files] creates at once, and how many threads each
+    /// chdman gets
+    ///
+    pub fn with_conversion_limits(mut self, limits: ConversionLimits) -> DumpManager {
+        self.conversion_limits = limits;
+        self
+    }
+
+    /// Sets when the databases' write-ahead logs are checkpointed (see [CheckpointPolicy])
+    ///
+    pub fn with_checkpoint_policy(mut self, policy: CheckpointPolicy) -> DumpManager {
+        if !self.is_read_only() {
+            self.catalog = self.catalog.with_checkpoint_policy(policy);
+            self.cuesheets = self.cuesheets.with_checkpoint_policy(policy);
+        }
+        self
+    }
+
+    /// Sets how often [DumpManager::update] maintains the databases (see
+    /// [DumpManager::maintain_databases]), or `None` to leave it to the caller
+    ///
+    pub fn with_maintenance_interval(mut self, interval: Option<Duration>) -> DumpManager {
+        self.maintenance_interval =
+            interval.map(|interval| TimeDelta::from_std(interval).unwrap_or(TimeDelta::MAX));
+        self
+    }
+
+    /// Updates the given data on the next [DumpManager::update], ignoring the update delay
+    ///
+    pub fn force_update(&mut self, target: UpdateTarget) {
+        self.catalog.force_update(target);
+        self.cuesheets.force_update(target);
+    }
+
     pub fn can_convert(&self, path: &impl AsRef<Path>) -> bool {
         match path.as_ref().extension() {
             None => false,
             Some(extension) => {
                 let extension = extension.to_str().unwrap();
-                extension == "iso" || extension == "cue"
+                extension == "iso" || extension == "cue" || extension == "gdi"
             }
         }
     }
@@ -54,49 +666,1386 @@ impl DumpManager {
             None => false,
             Some(extension) => {
                 let extension = extension.to_str().unwrap();
-                extension == "iso" || extension == "cue" || extension == "chd"
+                matches!(
+                    extension,
+                    "iso" | "cue" | "gdi" | "chd" | "rvz" | "wbfs" | "gcz" | "cso" | "zso" | "zip"
+                ) || cartridge::normalizer_for(path.as_ref()).is_some()
             }
         }
     }
 
-    fn convert_iso(&self, iso_path: &str, output_directory: &str, remove: bool) -> Result<String> {
-        Ok("TODO".into())
+    /// Whether a file may be a cartridge ROM [DumpManager::convert_to_zip] can zip, going by its
+    /// extension
+    ///
+    /// Disc images and ZIPs never are, so they aren't read to find out.
+    pub fn can_zip(&self, path: &impl AsRef<Path>) -> bool {
+        match path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            None => false,
+            Some(extension) => !matches!(
+                extension,
+                "iso"
+                    | "bin"
+                    | "cue"
+                    | "gdi"
+                    | "chd"
+                    | "rvz"
+                    | "wbfs"
+                    | "gcz"
+                    | "cso"
+                    | "zso"
+                    | "zip"
+            ),
+        }
     }
 
-    fn convert_cue(&self, cue_path: &str, output_directory: &str, remove: bool) -> Result<String> {
-        Ok("TODO".into())
+    /// Converts a cuesheet, GDI, or ISO to a CHD of the same name in `output_directory`
+    ///
+    /// Returns the CHD's path, or `None` if the file can't be converted. Sources are only ever deleted
+    /// once the CHD checks out (see [SourceHandling::RemoveVerified]). A CHD which fails verification
+    /// is deleted and reported as an error, and one whose data isn't in the catalog is kept alongside
+    /// its sources with a warning.
+    pub fn convert_file(
+        &self,
+        path: &str,
+        output_directory: &str,
+        sources: SourceHandling,
+    ) -> Result<Option<PathBuf>> {
+        let path = Path::new(path);
+        if !self.can_convert(&path) {
+            return Ok(None);
+        }
+        // read before the sources can be removed
+        let source = ConversionSource::read(path).ok();
+        let result = conversion::convert_to_chd(
+            &self.catalog.reader(),
+            path,
+            Path::new(output_directory),
+            sources,
+            self.conversion_limits.chdman_threads,
+        );
+        if let (Ok(Some(output)), Some(source)) = (&result, &source) {
+            self.record_conversion(source, "chd", output);
+        }
+        result
     }
 
-    pub fn convert_file(
+    /// Adds a finished conversion's sizes to the catalog, for estimating how much room later ones
+    /// need
+    ///
+    /// Failing to is only logged, since the conversion itself went through.
+    fn record_conversion(&self, source: &ConversionSource, format: &str, output: &Path) {
+        let Ok(metadata) = std::fs::metadata(output) else {
+            return;
+        };
+        if let Err(err) =
+            self.catalog
+                .record_conversion(source.console, format, source.size, metadata.len())
+        {
+            warn!(
+                "Failed to record the conversion of \"{}\": {err}",
+                output.display()
+            );
+        }
+    }
+
+    /// Converts several dumps to CHDs, running chdman on several of them at once (see
+    /// [DumpManager::with_conversion_limits])
+    ///
+    /// Each job is handled like [DumpManager::convert_file], and the results are in the same order
+    /// as `jobs`. Jobs reading from the same disk wait for each other, so they don't fight over it.
+    pub fn convert_files(&self, jobs: &[ConversionJob]) -> Vec<Result<Option<PathBuf>>> {
+        let (convertible, others): (Vec<usize>, Vec<usize>) =
+            (0..jobs.len()).partition(|&index| self.can_convert(&jobs[index].path));
+        let queued: Vec<ConversionJob> = convertible
+            .iter()
+            .map(|&index| jobs[index].clone())
+            .collect();
+        let mut results: Vec<Option<Result<Option<PathBuf>>>> = Vec::with_capacity(jobs.len());
+        results.resize_with(jobs.len(), || None);
+        for index in others {
+            results[index] = Some(Ok(None));
+        }
+        let queued_sources: Vec<Option<ConversionSource>> = queued
+            .iter()
+            .map(|job| ConversionSource::read(&job.path).ok())
+            .collect();
+        let converted =
+            conversion::convert_all(&self.catalog.reader(), &queued, self.conversion_limits);
+        for ((index, result), source) in convertible.into_iter().zip(converted).zip(queued_sources)
+        {
+            if let (Ok(Some(output)), Some(source)) = (&result, &source) {
+                self.record_conversion(source, "chd", output);
+            }
+            results[index] = Some(result);
+        }
+        results.into_iter().map(|result| result.unwrap()).collect()
+    }
+
+    /// Converts a GameCube or Wii image (.iso, .rvz, .wbfs, or .gcz) to another format with
+    /// dolphin-tool, into a file of the same name in `output_directory`
+    ///
+    /// Returns the new image's path, or `None` if the file isn't a disc image dolphin-tool reads or
+    /// is already in that format. Sources are handled like [DumpManager::convert_file]'s, with the
+    /// new image hashed by dolphin-tool to check it.
+    pub fn convert_disc_image(
+        &self,
+        path: &str,
+        output_directory: &str,
+        format: DiscFormat,
+        sources: SourceHandling,
+    ) -> Result<Option<PathBuf>> {
+        let path = Path::new(path);
+        let Some(extension) = path.extension().and_then(|extension| extension.to_str()) else {
+            return Ok(None);
+        };
+        if !matches!(extension, "iso" | "rvz" | "wbfs" | "gcz") || extension == format.name() {
+            return Ok(None);
+        }
+        let mut output = Path::new(output_directory).join(path.file_name().unwrap());
+        output.set_extension(format.name());
+        if output.exists() {
+            return Err(Error::new_original(format!(
+                "Failed to convert \"{}\"\n\"{}\" already exists",
+                path.display(),
+                output.display()
+            ))
+            .with_category(ErrorCategory::IO));
+        }
+        let source = ConversionSource::of_files(&[path.to_path_buf()])?;
+        source.ensure_room(&self.catalog.reader(), format.name(), path, &output)?;
+        write_atomically(&output, |partial| {
+            dolphin::convert(
+                &path.to_str().unwrap(),
+                &partial.to_str().unwrap(),
+                format.default_options(),
+            )
+        })?;
+        debug!(
+            "Converted \"{}\" to \"{}\"",
+            path.display(),
+            output.display()
+        );
+        self.record_conversion(&source, format.name(), &output);
+        if sources == SourceHandling::Keep {
+            return Ok(Some(output));
+        }
+        let sha1 = match dolphin::verify(&output.to_str().unwrap()) {
+            Ok(sha1) => sha1,
+            Err(err) => {
+                std::fs::remove_file(&output).ndl("Failed to remove broken disc image")?;
+                return Err(Error::new_original(format!(
+                    "Failed to convert \"{}\"\nThe new image can't be read ({}), so the source was kept",
+                    path.display(),
+                    err.message()
+                ))
+                .with_category(ErrorCategory::InvalidData));
+            }
+        };
+        if self.catalog.is_rom(sha1)?.is_none() {
+            report_warning(
+                WarningKind::SkippedFile,
+                format!(
+                    "Keeping \"{}\": the new image's disc isn't in the catalog",
+                    path.display()
+                ),
+            );
+            return Ok(Some(output));
+        }
+        std::fs::remove_file(path).ndl("Failed to remove converted dump")?;
+        info!("Removed \"{}\" after converting it", path.display());
+        Ok(Some(output))
+    }
+
+    /// Compresses an ISO (like a PSP or PS2 disc) to a CSO or ZSO with maxcso, into a file of the
+    /// same name in `output_directory`
+    ///
+    /// Returns the new file's path, or `None` if the file isn't an ISO. Sources are handled like
+    /// [DumpManager::convert_file]'s, with the new file decompressed again to check it.
+    pub fn convert_to_cso(
         &self,
         path: &str,
         output_directory: &str,
-        remove: bool,
+        format: CsoFormat,
+        sources: SourceHandling,
     ) -> Result<Option<PathBuf>> {
-        Ok(None)
+        let path = Path::new(path);
+        if path.extension().is_none_or(|extension| extension != "iso") {
+            return Ok(None);
+        }
+        let mut output = Path::new(output_directory).join(path.file_name().unwrap());
+        output.set_extension(format.extension());
+        if output.exists() {
+            return Err(Error::new_original(format!(
+                "Failed to convert \"{}\"\n\"{}\" already exists",
+                path.display(),
+                output.display()
+            ))
+            .with_category(ErrorCategory::IO));
+        }
+        let source = ConversionSource::of_files(&[path.to_path_buf()])?;
+        source.ensure_room(&self.catalog.reader(), format.extension(), path, &output)?;
+        write_atomically(&output, |partial| {
+            maxcso::compress(
+                &path.to_str().unwrap(),
+                &partial.to_str().unwrap(),
+                format,
+                self.cso_options,
+            )
+        })?;
+        debug!(
+            "Converted \"{}\" to \"{}\"",
+            path.display(),
+            output.display()
+        );
+        self.record_conversion(&source, format.extension(), &output);
+        if sources == SourceHandling::Keep {
+            return Ok(Some(output));
+        }
+        let sha1 = match self.cso_sha1(&output) {
+            Ok(sha1) => sha1,
+            Err(err) => {
+                std::fs::remove_file(&output).ndl("Failed to remove broken CSO")?;
+                return Err(Error::new_original(format!(
+                    "Failed to convert \"{}\"\nThe new file can't be decompressed ({}), so the source was kept",
+                    path.display(),
+                    err.message()
+                ))
+                .with_category(ErrorCategory::InvalidData));
+            }
+        };
+        if self.catalog.is_rom(sha1)?.is_none() {
+            report_warning(
+                WarningKind::SkippedFile,
+                format!(
+                    "Keeping \"{}\": the ISO isn't in the catalog",
+                    path.display()
+                ),
+            );
+            return Ok(Some(output));
+        }
+        std::fs::remove_file(path).ndl("Failed to remove converted dump")?;
+        info!("Removed \"{}\" after converting it", path.display());
+        Ok(Some(output))
     }
 
+    /// Stores a cartridge ROM in the catalog in a ZIP named after it in `output_directory`, written
+    /// the way TorrentZip writes them, so the same ROM always makes the same ZIP
+    ///
+    /// The ROM is stored under its catalog name, keeping its own extension if it's stored
+    /// differently than No-Intro lists it. Returns the ZIP's path, or `None` if the file isn't a
+    /// cartridge ROM in the catalog. Sources are handled like [DumpManager::convert_file]'s, with
+    /// the ROM read back from the ZIP to check it.
+    pub fn convert_to_zip(
+        &self,
+        path: &str,
+        output_directory: &str,
+        sources: SourceHandling,
+    ) -> Result<Option<PathBuf>> {
+        let path = Path::new(path);
+        if !self.can_zip(&path) {
+            return Ok(None);
+        }
+        let sha1 = cartridge_sha1(path)?;
+        let Some(info) = self.get_rom_info_by_sha1(sha1, path)? else {
+            return Ok(None);
+        };
+        if !info.console.is_cartridge() {
+            return Ok(None);
+        }
+        let output = Path::new(output_directory)
+            .join(Path::new(&info.preferred_file_name).with_extension("zip"));
+        if output.exists() {
+            return Err(Error::new_original(format!(
+                "Failed to convert \"{}\"\n\"{}\" already exists",
+                path.display(),
+                output.display()
+            ))
+            .with_category(ErrorCategory::IO));
+        }
+        zip::write_torrentzip(
+            &[(info.preferred_file_name.clone(), path.to_path_buf())],
+            &output,
+        )?;
+        debug!(
+            "Converted \"{}\" to \"{}\"",
+            path.display(),
+            output.display()
+        );
+        if sources == SourceHandling::Keep {
+            return Ok(Some(output));
+        }
+        if !matches!(self.zip_sha1(&output), Ok(Some(zipped)) if zipped == sha1) {
+            std::fs::remove_file(&output).ndl("Failed to remove broken zip")?;
+            return Err(Error::new_original(format!(
+                "Failed to convert \"{}\"\nThe ROM in the new ZIP doesn't match it, so the source was kept",
+                path.display()
+            ))
+            .with_category(ErrorCategory::InvalidData));
+        }
+        std::fs::remove_file(path).ndl("Failed to remove converted dump")?;
+        info!("Removed \"{}\" after converting it", path.display());
+        Ok(Some(output))
+    }
+
+    /// Identifies a dump by its hash, returning the game it belongs to
+    ///
+    /// Xbox 360 dumps are also identified by their title ID, since Games on Demand packages aren't
+    /// in datafiles at all: they're matched to the catalog game with that serial if there's only
+    /// one, and are otherwise named after the package (or the ISO). Returns `None` if the dump isn't
+    /// in the catalog.
     pub fn get_rom_info(&self, path: &str) -> Result<Option<ROMInfo>> {
-        Ok(None)
+        let path = Path::new(path);
+        // packages are split into parts which are too big to hash for nothing
+        if let Some(info) = self.god_rom_info(path)? {
+            return Ok(Some(info));
+        }
+        // a CHD whose header can't be read is broken, rather than just unknown
+        let sha1 = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("chd") => match self.chd_identity(path)? {
+                Some(ChdIdentity::Data(sha1)) => Some(sha1),
+                // a disc is named after the game its first track belongs to, like GDIs are
+                Some(ChdIdentity::Tracks(sha1s)) => {
+                    let Some(&sha1) = sha1s.first() else {
+                        return Ok(None);
+                    };
+                    return Ok(self.get_rom_info_by_sha1(sha1, path)?.map(|info| ROMInfo {
+                        preferred_file_name: format!("{}.chd", info.game_name),
+                        ..info
+                    }));
+                }
+                None => None,
+            },
+            // GDIs aren't in datafiles, so they go by their first track
+            Some("gdi") => match track_paths(path)?.first().filter(|track| track.is_file()) {
+                Some(track) => Some(sha1_of_file(track)?),
+                None => None,
+            },
+            _ => self.dump_sha1(path)?,
+        };
+        let Some(sha1) = sha1 else {
+            return Ok(None);
+        };
+        match self.get_rom_info_by_sha1(sha1, path)? {
+            Some(info) => Ok(Some(info)),
+            None => self.xbox360_iso_rom_info(path),
+        }
+    }
+
+    /// Identifies a dump by a SHA-1 hashed beforehand (like with [DumpManager::stream_sha1]),
+    /// returning the game it belongs to
+    ///
+    /// `path` is only used for its extension, which the preferred file name keeps for compressed
+    /// images and cartridge ROMs stored differently than No-Intro lists them.
+    pub fn get_rom_info_by_sha1(&self, sha1: [u8; 20], path: &Path) -> Result<Option<ROMInfo>> {
+        let reader = self.catalog.reader();
+        let Some((game, rom_name)) = reader.find_rom_game(sha1)? else {
+            return Ok(None);
+        };
+        let preferred_file_name = match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension @ ("chd" | "rvz" | "wbfs" | "gcz" | "cso" | "zso" | "zip")) => {
+                Path::new(&rom_name)
+                    .with_extension(extension)
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            }
+            Some("gdi") => format!("{}.gdi", game.name),
+            // a normalized ROM is still stored the other way, which its extension tells
+            Some(extension) if cartridge::normalizer_for(path).is_some() => Path::new(&rom_name)
+                .with_extension(extension)
+                .to_str()
+                .unwrap()
+                .to_string(),
+            _ => rom_name,
+        };
+        Ok(Some(ROMInfo {
+            console: game.console,
+            game_name: game.name,
+            preferred_file_name,
+            serial: game.serial,
+            region: game.region,
+            subfolder: None,
+            status: reader.rom_dump_status(sha1)?,
+        }))
     }
 
-    pub fn update(&mut self) -> Result<()> {
-        self.catalog.update_all_consoles()?;
-        self.cuesheets.update_all_consoles()
+    /// Verifies a dump by a SHA-1 hashed beforehand (like with [DumpManager::stream_sha1])
+    ///
+    pub fn verify_sha1(&self, sha1: [u8; 20]) -> Result<ROMStatus> {
+        match self.catalog.is_rom(sha1)? {
+            Some(_) => matched_rom_status(&self.catalog.reader(), sha1),
+            None => Ok(ROMStatus::Unverified),
+        }
     }
 
-    fn verify_standard_file(&self, path: &impl AsRef<Path>) -> Result<ROMStatus> {
-        let mut file = File::open(path).ndl("Failed to verify file")?;
+    /// Hashes a dump read from a stream, like a file in a remote [crate::Storage], as
+    /// [DumpManager::get_rom_info] would hash it as a file
+    ///
+    /// `name` is the dump's file name, whose extension tells how it's hashed. Returns `None` for
+    /// dumps which can only be hashed as local files: cuesheets and GDIs (which go by their tracks),
+    /// compressed images (which need an external tool), and ZIPs.
+    pub fn stream_sha1(&self, name: &Path, mut reader: &mut dyn Read) -> Result<Option<[u8; 20]>> {
+        if hashed_as_local_file(name) {
+            return Ok(None);
+        }
+        let _timer = timings::StageTimer::start(Stage::Hashing);
         let mut hasher = Sha1::new();
-        let _bytes_written = std::io::copy(&mut file, &mut hasher).ndl("Failed to verify file")?;
-        let hash = hasher.finalize();
-        if self.catalog.is_rom(hash.into())? {
-            Ok(ROMStatus::Verified)
+        if let Some(normalizer) = cartridge::normalizer_for(name) {
+            let mut rom = Vec::new();
+            (&mut *reader)
+                .take(cartridge::MAX_ROM_SIZE + 1)
+                .read_to_end(&mut rom)
+                .ndl("Failed to hash file")?;
+            if rom.len() as u64 <= cartridge::MAX_ROM_SIZE
+                && let Some(normalized) = normalizer.normalize(&rom)
+            {
+                return Ok(Some(Sha1::digest(&normalized).into()));
+            }
+            hasher.update(&rom);
+        }
+        hashing::hash_reader(&mut reader, |chunk| hasher.update(chunk))
+            .ndl("Failed to hash file")?;
+        Ok(Some(hasher.finalize().into()))
+    }
+
+    /// Hashes the files in an archive (like a ZIP or 7z) as they're decompressed, the way
+    /// [DumpManager::get_rom_info] hashes files, without extracting them anywhere
+    ///
+    /// The files can be identified with [DumpManager::get_rom_info_by_sha1], and those worth
+    /// keeping extracted with [DumpManager::extract_archive_entry]. Archive volumes aren't read
+    /// together, so they still have to be extracted (see [SplitDump::extract]).
+    pub fn scan_archive(&self, archive: &impl AsRef<Path>) -> Result<Vec<ArchiveEntry>> {
+        archive::scan(archive.as_ref())
+    }
+
+    /// Extracts a file [DumpManager::scan_archive] found straight to `target`, checking it against
+    /// the hash it was scanned with as it's written
+    ///
+    /// `target` must not exist yet. A file which doesn't match is deleted.
+    pub fn extract_archive_entry(
+        &self,
+        archive: &impl AsRef<Path>,
+        entry: &ArchiveEntry,
+        target: &impl AsRef<Path>,
+    ) -> Result<()> {
+        archive::extract_entry(archive.as_ref(), entry, target.as_ref())
+    }
+
+    /// Identifies a Games on Demand package's header, or one of its data parts, by its title ID
+    ///
+    fn god_rom_info(&self, path: &Path) -> Result<Option<ROMInfo>> {
+        let (header, subfolder) = match xbox360::god_header_of_part(path) {
+            Some(header) => (header, path.parent().and_then(Path::file_name)),
+            None if path.extension().is_none() => (path.to_path_buf(), None),
+            None => return Ok(None),
+        };
+        let Some(package) = GodHeader::read(&header)? else {
+            return Ok(None);
+        };
+        let name = if package.display_name.is_empty() {
+            header.file_name().unwrap().to_string_lossy().into_owned()
         } else {
-            Ok(ROMStatus::Unverified)
+            package.display_name
+        };
+        let serial = serial::format_xbox_title_id(package.title_id);
+        let mut info = self.xbox360_rom_info(serial, name, path)?;
+        info.subfolder = subfolder.map(|folder| folder.to_string_lossy().into_owned());
+        Ok(Some(info))
+    }
+
+    /// Identifies an Xbox 360 ISO whose hash isn't in the catalog by its default.xex's title ID
+    ///
+    fn xbox360_iso_rom_info(&self, path: &Path) -> Result<Option<ROMInfo>> {
+        if path.extension().is_none_or(|extension| extension != "iso") {
+            return Ok(None);
+        }
+        let mut file = File::open(path).ndl("Failed to open disc image")?;
+        match serial::read_serial(&mut file)? {
+            Some(DiscSerial {
+                console: GameConsole::Xbox360,
+                serial,
+            }) => {
+                let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+                Ok(Some(self.xbox360_rom_info(serial, name, path)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Describes an Xbox 360 dump as the catalog game with its serial, if there's exactly one, or
+    /// else as a game called `name`
+    ///
+    fn xbox360_rom_info(&self, serial: String, name: String, path: &Path) -> Result<ROMInfo> {
+        let preferred_file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let mut games = self
+            .catalog
+            .reader()
+            .find_games_by_serial(GameConsole::Xbox360, &serial)?;
+        if games.len() == 1 {
+            let game = games.pop().unwrap();
+            debug!("\"{}\" is {} ({serial})", path.display(), game.name);
+            return Ok(ROMInfo {
+                console: game.console,
+                game_name: game.name,
+                preferred_file_name,
+                serial: Some(serial),
+                region: game.region,
+                subfolder: None,
+                status: None,
+            });
+        }
+        debug!(
+            "\"{}\" is an Xbox 360 dump of {serial}, which matches {} catalog game(s)",
+            path.display(),
+            games.len()
+        );
+        Ok(ROMInfo {
+            console: GameConsole::Xbox360,
+            game_name: name,
+            preferred_file_name,
+            serial: Some(serial),
+            region: None,
+            subfolder: None,
+            status: None,
+        })
+    }
+
+    /// Identifies a split dump by the hash of its parts joined, like [DumpManager::get_rom_info]
+    ///
+    /// The preferred file name is the joined file's, which [SplitDump::part_name] names the parts
+    /// after. WBFS parts are read together by dolphin-tool. Archive volumes have to be extracted
+    /// first (see [SplitDump::extract]), so they're `None`.
+    pub fn get_split_rom_info(&self, dump: &SplitDump) -> Result<Option<ROMInfo>> {
+        match dump.kind {
+            SplitKind::Wbfs => self.get_rom_info(dump.parts[0].to_str().unwrap()),
+            SplitKind::RarVolumes => Ok(None),
+            SplitKind::Numbered => {
+                let reader = self.catalog.reader();
+                let sha1 = split_sha1(dump)?;
+                let Some((game, rom_name)) = reader.find_rom_game(sha1)? else {
+                    return Ok(None);
+                };
+                Ok(Some(ROMInfo {
+                    console: game.console,
+                    game_name: game.name,
+                    preferred_file_name: rom_name,
+                    serial: game.serial,
+                    region: game.region,
+                    subfolder: None,
+                    status: reader.rom_dump_status(sha1)?,
+                }))
+            }
+        }
+    }
+
+    /// Verifies a split dump by the hash of its parts joined, like [DumpManager::verify_file]
+    ///
+    pub fn verify_split(&self, dump: &SplitDump) -> Result<ROMStatus> {
+        match dump.kind {
+            SplitKind::Wbfs => self.verify_file(&dump.parts[0]),
+            SplitKind::RarVolumes => Ok(ROMStatus::Unverified),
+            SplitKind::Numbered => {
+                let sha1 = split_sha1(dump)?;
+                if self.catalog.is_rom(sha1)?.is_some() {
+                    return matched_rom_status(&self.catalog.reader(), sha1);
+                }
+                let joined_name = dump.joined_name().unwrap();
+                Ok(
+                    nintendo::detect_modification(&mut dump.open()?, Path::new(&joined_name))?
+                        .map_or(ROMStatus::Unverified, ROMStatus::Modified),
+                )
+            }
+        }
+    }
+
+    /// Adds a datafile on disk as an extra source of games for a console
+    ///
+    /// It's imported (or re-imported, if it changed) on the next [DumpManager::update].
+    pub fn add_local_datafile(&mut self, datafile: LocalDatafile) {
+        self.catalog.add_local_datafile(datafile);
+    }
+
+    /// Adds games of the user's own to the catalog, like ROM hacks, translations, and homebrew, so
+    /// that their dumps verify
+    ///
+    /// Returns the consoles whose games changed.
+    pub fn add_custom_games(&mut self, games: Vec<CustomGame>) -> Result<Vec<GameConsole>> {
+        self.catalog.add_custom_games(games)
+    }
+
+    /// Removes a game added with [DumpManager::add_custom_games], returning whether there was one
+    ///
+    pub fn remove_custom_game(&mut self, console: GameConsole, name: &str) -> Result<bool> {
+        self.catalog.remove_custom_game(console, name)
+    }
+
+    /// Summarizes the datafiles stored in the catalog, including when they were last updated
+    ///
+    pub fn datafile_info(&self) -> Result<Vec<DatafileInfo>> {
+        self.catalog.datafile_info()
+    }
+
+    /// Lists the games datafile updates have renamed since `since`, which kept their gids
+    ///
+    pub fn game_renames(&self, since: DateTime<Utc>) -> Result<Vec<GameRename>> {
+        self.catalog.game_renames(since)
+    }
+
+    /// Lists what datafile updates changed in the catalog since `since`, oldest first
+    ///
+    pub fn catalog_changes(&self, since: DateTime<Utc>) -> Result<Vec<CatalogChange>> {
+        self.catalog.changes(since)
+    }
+
+    /// Finds which of `dumps` the catalog's changes outdated, as their ROMs were removed or given
+    /// different hashes, along with the change which outdated each
+    ///
+    /// Dumps of ROMs which another game in the catalog still has aren't outdated.
+    pub fn outdated_dumps(
+        &self,
+        changes: &[CatalogChange],
+        dumps: &[PathBuf],
+    ) -> Result<Vec<(PathBuf, CatalogChange)>> {
+        let removed: HashMap<[u8; 20], &CatalogChange> = changes
+            .iter()
+            .filter_map(|change| Some((change.removed_sha1()?, change)))
+            .collect();
+        let mut outdated = Vec::new();
+        if removed.is_empty() {
+            return Ok(outdated);
+        }
+        for path in dumps {
+            let Some(sha1) = self.dump_sha1(path)? else {
+                continue;
+            };
+            if let Some(change) = removed.get(&sha1)
+                && self.catalog.is_rom(sha1)?.is_none()
+            {
+                outdated.push((path.clone(), (*change).clone()));
+            }
+        }
+        Ok(outdated)
+    }
+
+    /// Excludes matching games from the lists of a console's games
+    ///
+    pub fn add_block_rule(&mut self, rule: BlockRule) {
+        self.catalog.add_block_rule(rule);
+    }
+
+    /// A read-only handle to the catalog, with the block rules added so far
+    ///
+    /// It can be sent to other threads to query the catalog while [DumpManager::update] runs.
+    pub fn catalog_reader(&self) -> CatalogReader {
+        self.catalog.reader()
+    }
+
+    /// Lists the names of a console's games in the catalog, excluding blocked games
+    ///
+    pub fn console_games(&self, console: GameConsole) -> Result<Vec<String>> {
+        self.catalog.resolved_game_names(console)
+    }
+
+    /// Finds the catalog game with a ROM matching the given hash, returning its gid
+    ///
+    /// `size` is only used for CRC32 hashes, which collide too often to be matched on their own.
+    pub fn find_rom(&self, hash: FileHash, size: usize) -> Result<Option<i64>> {
+        match hash {
+            FileHash::SHA1(sha1) => self.catalog.is_rom(sha1),
+            FileHash::MD5(md5) => self.catalog.is_rom_md5(md5),
+            FileHash::CRC32(crc32) => self.catalog.is_rom_size_crc(size, crc32),
+            FileHash::SHA256(sha256) => self.catalog.is_rom_sha256(sha256),
+        }
+    }
+
+    /// Checks the catalog and cuesheet databases for corruption and orphaned rows
+    ///
+    /// With `repair`, orphaned rows are deleted and the indexes are rebuilt.
+    pub fn check_databases(&mut self, repair: bool) -> Result<Vec<DatabaseCheck>> {
+        Ok(vec![
+            self.catalog.check(repair)?,
+            self.cuesheets.check(repair)?,
+        ])
+    }
+
+    /// Writes consistent copies of the catalog and cuesheet databases to a folder (as
+    /// "catalog.sqlite" and "cuesheets.sqlite"), returning the files written
+    ///
+    pub fn backup_databases(&self, folder: &Path) -> Result<Vec<PathBuf>> {
+        let catalog_path = folder.join(CATALOG_FILE_NAME);
+        let cuesheets_path = folder.join(CUESHEETS_FILE_NAME);
+        self.catalog.backup(&catalog_path)?;
+        self.cuesheets.backup(&cuesheets_path)?;
+        Ok(vec![catalog_path, cuesheets_path])
+    }
+
+    /// Replaces the databases in a data folder with copies from another folder, like the ones
+    /// [DumpManager::backup_databases] writes, returning the names of those replaced
+    ///
+    /// The data folder is locked meanwhile, so nothing uses the databases while they're replaced.
+    /// Databases without a copy are left as they are. Copies from older versions are brought up to
+    /// date when the data folder is next opened.
+    pub fn restore_databases(
+        path: &impl AsRef<Path>,
+        from: &Path,
+        when_locked: WhenLocked,
+    ) -> Result<Vec<String>> {
+        let base_folder_path = path.as_ref();
+        let (_lock, _) = RunLock::acquire(base_folder_path.join(LOCK_FILE_NAME), when_locked)?;
+        let mut restored = Vec::new();
+        for (name, file_name) in [
+            ("catalog", CATALOG_FILE_NAME),
+            ("cuesheet", CUESHEETS_FILE_NAME),
+        ] {
+            let copy = from.join(file_name);
+            if !copy.is_file() {
+                continue;
+            }
+            let failed = || format!("Failed to restore {name} DB");
+            let target = base_folder_path.join(file_name);
+            let mut partial_path = target.as_os_str().to_owned();
+            partial_path.push(".restoring");
+            std::fs::copy(&copy, &partial_path).ndl(failed())?;
+            // the old database's logs would otherwise be replayed into the copy
+            for suffix in ["-wal", "-shm"] {
+                let mut log_path = target.as_os_str().to_owned();
+                log_path.push(suffix);
+                match std::fs::remove_file(log_path) {
+                    Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                        let _ = std::fs::remove_file(&partial_path);
+                        return Err(err).ndl(failed());
+                    }
+                    _ => {}
+                }
+            }
+            std::fs::rename(&partial_path, &target).ndl(failed())?;
+            debug!("Restored {name} DB from \"{}\"", copy.display());
+            restored.push(name.to_string());
+        }
+        Ok(restored)
+    }
+
+    /// Reads the current schema of each database
+    ///
+    pub fn database_schemas(&self) -> Result<Vec<DatabaseSchema>> {
+        Ok(vec![self.catalog.schema()?, self.cuesheets.schema()?])
+    }
+
+    /// Updates the datafiles and cuesheets which are due
+    ///
+    /// Returns the consoles whose games changed in the catalog. Their dumps may verify differently
+    /// now, so only they need to be verified again.
+    pub fn update(&mut self) -> Result<Vec<GameConsole>> {
+        self.ensure_writable("update the catalog")?;
+        let changed_consoles = self.catalog.update_all_consoles()?;
+        self.cuesheets.update_all_consoles()?;
+        // updates churn the databases, so this is when they're due for maintenance
+        match self.maintain_databases_if_due() {
+            Ok(maintenances) => {
+                for maintenance in maintenances {
+                    info!(
+                        "Maintained {} DB ({:.1} MB to {:.1} MB)",
+                        maintenance.database,
+                        maintenance.size_before as f64 / 1e6,
+                        maintenance.size_after as f64 / 1e6
+                    );
+                }
+            }
+            Err(err) => warn!("{err}"),
+        }
+        Ok(changed_consoles)
+    }
+
+    /// Vacuums the databases, updates their query planner statistics, and writes their logs back
+    ///
+    /// This rewrites the databases, which takes a while for a big catalog, so it's left to an
+    /// explicit command or the maintenance interval (see [DumpManager::with_maintenance_interval])
+    /// rather than done every time they're closed.
+    pub fn maintain_databases(&mut self) -> Result<Vec<DatabaseMaintenance>> {
+        self.ensure_writable("maintain the databases")?;
+        Ok(vec![self.catalog.maintain()?, self.cuesheets.maintain()?])
+    }
+
+    /// Maintains the databases which haven't been within the maintenance interval, returning what
+    /// was done
+    ///
+    pub fn maintain_databases_if_due(&mut self) -> Result<Vec<DatabaseMaintenance>> {
+        let Some(interval) = self.maintenance_interval.filter(|_| !self.is_read_only()) else {
+            return Ok(Vec::new());
+        };
+        let is_due = |last_maintained: Option<DateTime<Utc>>| {
+            last_maintained.is_none_or(|last_maintained| Utc::now() - last_maintained >= interval)
+        };
+        let mut maintenances = Vec::new();
+        if is_due(self.catalog.last_maintained()?) {
+            maintenances.push(self.catalog.maintain()?);
+        }
+        if is_due(self.cuesheets.last_maintained()?) {
+            maintenances.push(self.cuesheets.maintain()?);
+        }
+        Ok(maintenances)
+    }
+
+    /// When each database was last maintained, or `None` for those which never have been
+    ///
+    pub fn last_maintained(&self) -> Result<Vec<(String, Option<DateTime<Utc>>)>> {
+        Ok(vec![
+            (String::from("catalog"), self.catalog.last_maintained()?),
+            (String::from("cuesheet"), self.cuesheets.last_maintained()?),
+        ])
+    }
+
+    /// Looks up the name a dump has in the catalog, including its region and revision tags
+    ///
+    /// Cuesheets which aren't in the cuesheet DB are named after the game their first track
+    /// belongs to, as are GDIs (which datafiles don't list). CHDs and compressed ISOs (like RVZs
+    /// and CSOs) keep their extension.
+    pub fn canonical_name(&self, path: &impl AsRef<Path>) -> Result<Option<String>> {
+        let path = path.as_ref();
+        let reader = self.catalog.reader();
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("cue") => {
+                let content = std::fs::read_to_string(path).ndl("Failed to read cue")?;
+                if let Some(hash) = self.cuesheets.find_cue_hash(&content, &path)?
+                    && let Some((_, rom_name)) = reader.find_rom_name(hash)?
+                {
+                    return Ok(Some(rom_name));
+                }
+                let Some(track) = self::cuesheets::get_track_filenames(&content)
+                    .into_iter()
+                    .next()
+                    .map(|filename| path.with_file_name(filename))
+                    .filter(|track| track.is_file())
+                else {
+                    return Ok(None);
+                };
+                let name = reader
+                    .find_rom_name(sha1_of_file(&track)?)?
+                    .map(|(game_name, _)| format!("{game_name}.cue"));
+                if let Some(name) = &name {
+                    report_warning(
+                        WarningKind::HeuristicMatch,
+                        format!(
+                            "\"{}\" isn't in the catalog, so it's named \"{name}\" after its first track",
+                            path.display()
+                        ),
+                    );
+                }
+                Ok(name)
+            }
+            Some("gdi") => {
+                let Some(track) = track_paths(path)?
+                    .into_iter()
+                    .next()
+                    .filter(|track| track.is_file())
+                else {
+                    return Ok(None);
+                };
+                Ok(reader
+                    .find_rom_name(sha1_of_file(&track)?)?
+                    .map(|(game_name, _)| format!("{game_name}.gdi")))
+            }
+            Some("chd") => match self.chd_identity(path)? {
+                Some(ChdIdentity::Data(sha1)) => {
+                    Ok(reader.find_rom_name(sha1)?.map(|(_, rom_name)| {
+                        Path::new(&rom_name)
+                            .with_extension("chd")
+                            .to_str()
+                            .unwrap()
+                            .to_string()
+                    }))
+                }
+                Some(ChdIdentity::Tracks(sha1s)) => match sha1s.first() {
+                    Some(&sha1) => Ok(reader
+                        .find_rom_name(sha1)?
+                        .map(|(game_name, _)| format!("{game_name}.chd"))),
+                    None => Ok(None),
+                },
+                None => Ok(None),
+            },
+            Some(extension @ ("rvz" | "wbfs" | "gcz" | "cso" | "zso")) => {
+                let Some(sha1) = self.dump_sha1(path)? else {
+                    return Ok(None);
+                };
+                Ok(reader.find_rom_name(sha1)?.map(|(_, rom_name)| {
+                    Path::new(&rom_name)
+                        .with_extension(extension)
+                        .to_str()
+                        .unwrap()
+                        .to_string()
+                }))
+            }
+            Some(extension) if cartridge::normalizer_for(path).is_some() => Ok(reader
+                .find_rom_name(cartridge_sha1(path)?)?
+                .map(|(_, rom_name)| {
+                    Path::new(&rom_name)
+                        .with_extension(extension)
+                        .to_str()
+                        .unwrap()
+                        .to_string()
+                })),
+            _ => Ok(reader
+                .find_rom_name(sha1_of_file(&path)?)?
+                .map(|(_, rom_name)| rom_name)),
         }
     }
 
+    /// Reads the game serial stored on a disc image (.iso, .bin, .cue, or .chd), or in the header
+    /// of an Xbox 360 Games on Demand package
+    ///
+    /// It's read from the disc itself, so it works even for dumps whose hashes aren't in the
+    /// catalog. CHDs are extracted to a temporary directory first, which only works for CDs.
+    /// Returns `None` for images of other consoles, or without a serial where it's expected.
+    pub fn get_disc_serial(&self, path: &impl AsRef<Path>) -> Result<Option<DiscSerial>> {
+        let path = path.as_ref();
+        let track = |cue: &Path| -> Result<Option<PathBuf>> {
+            let content = std::fs::read_to_string(cue).ndl("Failed to read cue")?;
+            Ok(self::cuesheets::get_track_filenames(&content)
+                .into_iter()
+                .next()
+                .map(|filename| cue.with_file_name(filename)))
+        };
+        let read = |image: &Path| -> Result<Option<DiscSerial>> {
+            let mut file = File::open(image).ndl("Failed to open disc image")?;
+            serial::read_serial(&mut file)
+        };
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("cue") => match track(path)? {
+                Some(track) => read(&track),
+                None => Ok(None),
+            },
+            Some("chd") => {
+                let directory = work::temp_dir(chdman::read_header(&path)?.logical_size)?;
+                let cue = directory.path().join("disc.cue");
+                chdman::extract_cd(
+                    &path.to_str().unwrap(),
+                    &cue.to_str().unwrap(),
+                    chdman::ExtractOptions::default(),
+                )?;
+                match track(&cue)? {
+                    Some(track) => read(&track),
+                    None => Ok(None),
+                }
+            }
+            None => match GodHeader::read(path)? {
+                Some(package) => Ok(Some(DiscSerial {
+                    console: GameConsole::Xbox360,
+                    serial: serial::format_xbox_title_id(package.title_id),
+                })),
+                None => read(path),
+            },
+            _ => read(path),
+        }
+    }
+
+    /// Writes the missing cuesheet of a bin, using its game's cuesheet from the cuesheet DB
+    ///
+    /// The cuesheet is named after the bin, and lists the game's other tracks named the same way: a
+    /// bin named "Game (Track 2).bin" gets "Game.cue", listing "Game (Track 1).bin" for track 1.
+    /// Returns the cuesheet's path, or `None` if the bin isn't in the catalog or the cuesheet DB
+    /// doesn't have its game's cuesheet.
+    pub fn reconstruct_cue(&self, bin: &impl AsRef<Path>) -> Result<Option<PathBuf>> {
+        let bin = bin.as_ref();
+        let reader = self.catalog.reader();
+        let Some((game, rom_name)) = reader.find_rom_game(sha1_of_file(&bin)?)? else {
+            return Ok(None);
+        };
+        let Some(cue_rom) = reader
+            .game_roms(&game)?
+            .into_iter()
+            .find(|rom| rom.name.ends_with(".cue"))
+        else {
+            return Ok(None);
+        };
+        let Some(template) = self.cuesheets.get_cue(cue_rom.sha1)? else {
+            debug!("The cuesheet DB doesn't have \"{}\"", cue_rom.name);
+            return Ok(None);
+        };
+        // the template has "$" in place of the cuesheet's name, which its tracks are named after
+        let catalog_stem = cue_rom.name.strip_suffix(".cue").unwrap();
+        let bin_name = bin.file_name().unwrap().to_str().unwrap();
+        let stem = rom_name
+            .strip_prefix(catalog_stem)
+            .and_then(|suffix| bin_name.strip_suffix(suffix))
+            .filter(|stem| !stem.is_empty())
+            .unwrap_or(catalog_stem);
+        let content = template.replace('$', stem);
+        // a bin named unlike its catalog name is listed under its own name
+        let track_name = rom_name.replacen(catalog_stem, stem, 1);
+        let content = self::cuesheets::rename_tracks(
+            &content,
+            &HashMap::from([(track_name, bin_name.to_string())]),
+        );
+        let cue = bin.with_file_name(format!("{stem}.cue"));
+        if cue.exists() {
+            return Err(Error::new_original(format!(
+                "Failed to reconstruct cue\n\"{}\" already exists",
+                cue.display()
+            ))
+            .with_category(ErrorCategory::IO));
+        }
+        // neutralized cuesheets keep their line endings, except after the last line
+        let line_ending = if content.contains('\r') { "\r\n" } else { "\n" };
+        write_atomically(&cue, |partial| {
+            std::fs::write(partial, content + line_ending).ndl("Failed to write cue")
+        })?;
+        debug!("Reconstructed \"{}\"", cue.display());
+        Ok(Some(cue))
+    }
+
+    /// Finds the SHA-1 a dump is listed under in datafiles
+    ///
+    /// Cuesheets are looked up in the cuesheet DB, and CHDs go by the raw data hash in their header,
+    /// or by their first track for CDs whose datafiles list them track by track.
+    /// Compressed GameCube and Wii images are hashed by dolphin-tool, as the disc they hold, and
+    /// CSOs and ZSOs are decompressed by maxcso to hash their ISO.
+    /// Cartridge ROMs are normalized first (see [cartridge::Normalizer]).
+    /// Returns `None` for unknown cuesheets, unreadable CHDs and images, and GDIs (which datafiles
+    /// don't list).
+    fn dump_sha1(&self, path: &Path) -> Result<Option<[u8; 20]>> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("cue") => {
+                let content = std::fs::read_to_string(path).ndl("Failed to read cue")?;
+                self.cuesheets.find_cue_hash(&content, &path)
+            }
+            Some("gdi") => Ok(None),
+            Some("chd") => match self.chd_identity(path) {
+                Ok(Some(ChdIdentity::Data(sha1))) => Ok(Some(sha1)),
+                Ok(Some(ChdIdentity::Tracks(sha1s))) => Ok(sha1s.first().copied()),
+                Ok(None) => Ok(None),
+                Err(err) => {
+                    report_warning(
+                        WarningKind::SkippedFile,
+                        format!("Skipping \"{}\": {err}", path.display()),
+                    );
+                    Ok(None)
+                }
+            },
+            Some("rvz" | "wbfs" | "gcz") => match dolphin::verify(&path.to_str().unwrap()) {
+                Ok(sha1) => Ok(Some(sha1)),
+                Err(err) if err.category() == ErrorCategory::DolphinToolMissing => Err(err),
+                Err(err) => {
+                    report_warning(
+                        WarningKind::SkippedFile,
+                        format!("Skipping \"{}\": {err}", path.display()),
+                    );
+                    Ok(None)
+                }
+            },
+            Some("cso" | "zso") => match self.cso_sha1(path) {
+                Ok(sha1) => Ok(Some(sha1)),
+                Err(err) if err.category() == ErrorCategory::MaxcsoMissing => Err(err),
+                Err(err) => {
+                    report_warning(
+                        WarningKind::SkippedFile,
+                        format!("Skipping \"{}\": {err}", path.display()),
+                    );
+                    Ok(None)
+                }
+            },
+            Some("zip") => match self.zip_sha1(path) {
+                Ok(sha1) => Ok(sha1),
+                Err(err) => {
+                    report_warning(
+                        WarningKind::SkippedFile,
+                        format!("Skipping \"{}\": {err}", path.display()),
+                    );
+                    Ok(None)
+                }
+            },
+            _ => cartridge_sha1(path).map(Some),
+        }
+    }
+
+    /// Hashes the only file in a ZIP as it would be hashed on its own
+    ///
+    /// Returns `None` for ZIPs which hold several files, or none.
+    fn zip_sha1(&self, path: &Path) -> Result<Option<[u8; 20]>> {
+        Ok(match archive::scan(path)?.as_slice() {
+            [entry] => entry.sha1,
+            _ => None,
+        })
+    }
+
+    /// Decompresses a CSO or ZSO to a temporary directory, and hashes the ISO
+    ///
+    fn cso_sha1(&self, path: &Path) -> Result<[u8; 20]> {
+        let directory = work::temp_dir(maxcso::uncompressed_size(&path)?)?;
+        let iso = directory.path().join("disc.iso");
+        maxcso::decompress(
+            &path.to_str().unwrap(),
+            &iso.to_str().unwrap(),
+            self.cso_options,
+        )?;
+        sha1_of_file(&iso)
+    }
+
+    /// Writes a Logiqx datafile of a console's games which aren't among `dumps`, for tracking what's
+    /// left to collect
+    ///
+    /// Games missing some of their ROMs are written with just the missing ones. Returns the number of
+    /// games written.
+    pub fn write_fixdat(
+        &self,
+        console: GameConsole,
+        dumps: &[PathBuf],
+        output: impl Write,
+    ) -> Result<usize> {
+        let owned = self.owned_sha1s(dumps)?;
+        self.catalog.write_fixdat(console, &owned, output)
+    }
+
+    /// Writes a Logiqx datafile of a console's games with just the ROMs which are among `dumps`, or
+    /// just those which aren't, for ROM managers like clrmamepro and RomVault
+    ///
+    /// Returns the number of games written.
+    pub fn write_collection_datafile(
+        &self,
+        console: GameConsole,
+        dumps: &[PathBuf],
+        part: CollectionPart,
+        output: impl Write,
+    ) -> Result<usize> {
+        let owned = self.owned_sha1s(dumps)?;
+        self.catalog
+            .write_collection_datafile(console, &owned, part, output)
+    }
+
+    /// Lists the names of a console's games which are complete among `dumps`, or which are missing
+    /// some of their ROMs, like clrmamepro's have and miss lists
+    ///
+    pub fn collection_game_names(
+        &self,
+        console: GameConsole,
+        dumps: &[PathBuf],
+        part: CollectionPart,
+    ) -> Result<Vec<String>> {
+        let owned = self.owned_sha1s(dumps)?;
+        self.catalog.collection_game_names(console, &owned, part)
+    }
+
+    /// Hashes dumps the way the catalog stores their ROMs, skipping those which can't be hashed
+    ///
+    fn owned_sha1s(&self, dumps: &[PathBuf]) -> Result<HashSet<[u8; 20]>> {
+        let mut owned = HashSet::new();
+        for path in dumps {
+            // a CD CHD holds all of its tracks
+            if path.extension().is_some_and(|extension| extension == "chd") {
+                match self.chd_identity(path) {
+                    Ok(Some(ChdIdentity::Data(sha1))) => {
+                        owned.insert(sha1);
+                    }
+                    Ok(Some(ChdIdentity::Tracks(sha1s))) => {
+                        // and stands in for the cuesheet, which chdman writes out anew
+                        if let Some(&sha1) = sha1s.first()
+                            && let Some((game, _)) = self.catalog.reader().find_rom_game(sha1)?
+                        {
+                            owned.extend(
+                                self.catalog
+                                    .reader()
+                                    .game_roms(&game)?
+                                    .into_iter()
+                                    .filter(|rom| rom.name.ends_with(".cue"))
+                                    .map(|rom| rom.sha1),
+                            );
+                        }
+                        owned.extend(sha1s);
+                    }
+                    Ok(None) => {}
+                    Err(err) => report_warning(
+                        WarningKind::SkippedFile,
+                        format!("Skipping \"{}\": {err}", path.display()),
+                    ),
+                }
+            } else if let Some(sha1) = self.dump_sha1(path)? {
+                owned.insert(sha1);
+            }
+        }
+        Ok(owned)
+    }
+
+    /// Matches files against the catalog's disc tracks by their contents, whatever they're named,
+    /// and groups them by game
+    ///
+    /// Each game lists which of its tracks were found and which are missing, so partially
+    /// collected games can be told apart from complete ones.
+    pub fn match_tracks(&self, paths: &[PathBuf]) -> Result<TrackMatches> {
+        let index = self.catalog.reader().track_index()?;
+        let mut matches = TrackMatches::default();
+        let mut found: HashMap<i64, Vec<(PathBuf, String)>> = HashMap::new();
+        for path in paths {
+            match index.find(path)? {
+                Some((gid, name)) => found
+                    .entry(gid)
+                    .or_default()
+                    .push((path.clone(), name.to_string())),
+                None => matches.unmatched.push(path.clone()),
+            }
+        }
+        for (gid, mut tracks) in found {
+            let game = index.game(gid).unwrap();
+            tracks.sort_by(|(_, a), (_, b)| a.cmp(b));
+            let missing = game
+                .tracks
+                .iter()
+                .filter(|name| !tracks.iter().any(|(_, found)| found == *name))
+                .cloned()
+                .collect();
+            matches.games.push(GameTracks {
+                gid,
+                console: game.console,
+                name: game.name.clone(),
+                found: tracks,
+                missing,
+            });
+        }
+        matches.games.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(matches)
+    }
+
+    /// Works out which dumps to rename to their names in the catalog
+    ///
+    /// Dumps are renamed within their folders. Nothing is changed on disk.
+    pub fn plan_renames(&self, paths: &[PathBuf]) -> Result<RenamePlan> {
+        let mut plan = RenamePlan::default();
+        let mut candidates = Vec::new();
+        for path in paths {
+            match self.canonical_name(path)? {
+                None => plan.unidentified.push(path.clone()),
+                Some(name) => {
+                    let to = path.with_file_name(name);
+                    if to != *path {
+                        candidates.push(Rename {
+                            from: path.clone(),
+                            to,
+                        });
+                    }
+                }
+            }
+        }
+        let mut claims: HashMap<PathBuf, usize> = HashMap::new();
+        for candidate in &candidates {
+            *claims.entry(candidate.to.clone()).or_default() += 1;
+        }
+        for candidate in candidates {
+            // on case-insensitive file systems, the target may be the dump itself
+            let is_taken = candidate.to.exists()
+                && candidate.to.canonicalize().ok() != candidate.from.canonicalize().ok();
+            if claims[&candidate.to] > 1 || is_taken {
+                plan.collisions.push(candidate);
+            } else {
+                plan.renames.push(candidate);
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Renames dumps, then points the cuesheets and GDIs next to them at their tracks' new names
+    ///
+    pub fn apply_renames(&self, renames: &[Rename]) -> Result<()> {
+        let mut track_renames: HashMap<PathBuf, HashMap<String, String>> = HashMap::new();
+        for rename in renames {
+            // cuesheets and GDIs are text, so they can't name a track whose name isn't UTF-8
+            let track_names = rename
+                .from
+                .extension()
+                .is_none_or(|extension| extension != "cue" && extension != "gdi")
+                .then(|| {
+                    Some((
+                        rename.from.file_name()?.to_str()?.to_string(),
+                        rename.to.file_name()?.to_str()?.to_string(),
+                    ))
+                })
+                .flatten();
+            std::fs::rename(&rename.from, &rename.to)
+                .ndl(format!("Failed to rename \"{}\"", rename.from.display()))?;
+            debug!(
+                "Renamed \"{}\" to \"{}\"",
+                rename.from.display(),
+                rename.to.display()
+            );
+            if let Some((from_name, to_name)) = track_names {
+                let directory = rename.from.parent().unwrap_or(Path::new("")).to_path_buf();
+                track_renames
+                    .entry(directory)
+                    .or_default()
+                    .insert(from_name, to_name);
+            }
+        }
+        for (directory, renames) in track_renames {
+            let listing_directory = if directory.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                directory.as_path()
+            };
+            let entries = std::fs::read_dir(listing_directory)
+                .ndl("Failed to update cuesheets after renaming")?;
+            for entry in entries {
+                let path = entry
+                    .ndl("Failed to update cuesheets after renaming")?
+                    .path();
+                let rename_tracks = match path.extension().and_then(|extension| extension.to_str())
+                {
+                    Some("cue") => self::cuesheets::rename_tracks,
+                    Some("gdi") => self::gdi::rename_tracks,
+                    _ => continue,
+                };
+                let content = std::fs::read_to_string(&path).ndl("Failed to read cue")?;
+                let updated = rename_tracks(&content, &renames);
+                if updated != content {
+                    write_atomically(&path, |partial| {
+                        std::fs::write(partial, updated).ndl("Failed to update cue")
+                    })?;
+                    debug!("Updated track names in \"{}\"", path.display());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The track files a cuesheet or GDI lists, which belong next to it wherever it goes
+    ///
+    /// Returns nothing for other files.
+    pub fn disc_tracks(&self, sheet: &impl AsRef<Path>) -> Result<Vec<PathBuf>> {
+        track_paths(sheet.as_ref())
+    }
+
+    /// Like [DumpManager::disc_tracks], for a sheet read from elsewhere (like a remote library)
+    ///
+    pub fn read_disc_tracks(&self, sheet: &Path, reader: &mut dyn Read) -> Result<Vec<PathBuf>> {
+        let mut content = String::new();
+        reader
+            .read_to_string(&mut content)
+            .ndl(format!("Failed to read \"{}\"", sheet.display()))?;
+        Ok(sheet_track_paths(sheet, &content))
+    }
+
+    /// Writes a copy of a cuesheet or GDI to `to`, pointing it at its tracks' new names
+    ///
+    /// `renames` maps tracks' file names to their new ones, and tracks it doesn't have keep their
+    /// names. What's already at `to` is replaced once the copy is written.
+    pub fn write_renamed_sheet(
+        &self,
+        sheet: &Path,
+        to: &Path,
+        renames: &HashMap<String, String>,
+    ) -> Result<()> {
+        let rename_tracks = match sheet.extension().and_then(|extension| extension.to_str()) {
+            Some("gdi") => self::gdi::rename_tracks,
+            _ => self::cuesheets::rename_tracks,
+        };
+        let content = std::fs::read_to_string(sheet)
+            .ndl(format!("Failed to read \"{}\"", sheet.display()))?;
+        let updated = rename_tracks(&content, renames);
+        write_atomically(to, |partial| {
+            std::fs::write(partial, updated).ndl(format!("Failed to write \"{}\"", to.display()))
+        })
+    }
+
     fn verify_cue(&self, path: &impl AsRef<Path>) -> Result<ROMStatus> {
         let content = std::fs::read_to_string(path).ndl("Failed to verify cue")?;
         let path_buffer = path.as_ref().to_path_buf();
@@ -107,19 +2056,251 @@ impl DumpManager {
         }
         match self.cuesheets.find_cue_hash(&content, path)? {
             None => Ok(ROMStatus::Unverified),
-            Some(hash) => {
-                if self.catalog.is_rom(hash)? {
-                    Ok(ROMStatus::Verified)
-                } else {
-                    Ok(ROMStatus::Unverified)
-                }
-            }
+            Some(hash) => self.verify_sha1(hash),
         }
     }
 
+    /// Checks a CHD's header against the catalog, extracting its tracks if it's a CD or GD-ROM
+    ///
+    /// The header's raw data SHA-1 is recorded by chdman when the CHD is created, so it can be compared
+    /// against the catalog without decompressing anything. That's the ISO's SHA-1 for DVD CHDs, but
+    /// a CD's frames are stored padded, so CD CHDs (even single-track ones) whose header doesn't
+    /// match are extracted and verified track by track. Deep verification additionally has chdman
+    /// decompress the CHD to confirm its contents still match the header, every time or once it's
+    /// due (see [DumpManager::with_deep_chd_verification_interval]).
+    ///
+    /// The header and track layout are read directly, so chdman is only needed for extracting and
+    /// deep verification.
     fn verify_chd(&self, path: &impl AsRef<Path>) -> Result<ROMStatus> {
-        let _directory = TempDir::new().ndl("Failed to verify chd")?;
-        Ok(ROMStatus::Broken)
+        let info = match chdman::info(path) {
+            Ok(info) => info,
+            Err(err) => {
+                debug!("{err}");
+                return Ok(ROMStatus::Broken);
+            }
+        };
+        if self.is_deep_chd_verification_due(&info)? {
+            if !timings::time(Stage::Hashing, || {
+                chdman::verify(&path.as_ref().to_str().unwrap())
+            })? {
+                return Ok(ROMStatus::Broken);
+            }
+            self.catalog.record_chd_verification(info.sha1())?;
+        }
+        if self.catalog.is_rom(info.data_sha1())?.is_some() {
+            return matched_rom_status(&self.catalog.reader(), info.data_sha1());
+        }
+        if !matches!(info.media(), ChdMedia::CD | ChdMedia::GDROM) {
+            // the header's SHA-1 is all there is to check
+            return Ok(ROMStatus::Unverified);
+        }
+        let Some((_directory, tracks)) = self.extract_chd_tracks(path.as_ref(), &info, "verify")?
+        else {
+            return Ok(ROMStatus::Unverified);
+        };
+        if tracks.len() != info.track_count() {
+            debug!(
+                "Extracted {} of the {} tracks of \"{}\"",
+                tracks.len(),
+                info.track_count(),
+                path.as_ref().display()
+            );
+            return Ok(ROMStatus::Broken);
+        }
+        self.verify_tracks(&tracks)
+    }
+
+    /// Whether a CHD should be decompressed to verify it, rather than only checked by its header
+    ///
+    fn is_deep_chd_verification_due(&self, info: &chdman::ChdInfo) -> Result<bool> {
+        if self.deep_chd_verification {
+            return Ok(true);
+        }
+        let Some(interval) = self.deep_chd_verification_interval else {
+            return Ok(false);
+        };
+        Ok(self
+            .catalog
+            .last_chd_verification(info.sha1())?
+            .is_none_or(|verified| Utc::now() - verified >= interval))
+    }
+
+    /// Extracts a CD or GD-ROM CHD's tracks to a temporary directory, which is removed when the
+    /// returned handle is dropped
+    ///
+    /// Returns `None` with a warning if chdman can't extract them, saying what they were extracted
+    /// to `purpose`.
+    fn extract_chd_tracks(
+        &self,
+        path: &Path,
+        info: &chdman::ChdInfo,
+        purpose: &str,
+    ) -> Result<Option<(TempDir, Vec<PathBuf>)>> {
+        let sheet = match info.media() {
+            ChdMedia::GDROM => "disc.gdi",
+            _ => "disc.cue",
+        };
+        debug!(
+            "Extracting {} track(s) of \"{}\" to {purpose} them",
+            info.track_count(),
+            path.display()
+        );
+        let directory = work::temp_dir(info.logical_size())?;
+        let cue = directory.path().join(sheet);
+        let extracted = chdman::extract_cd(
+            &path.to_str().unwrap(),
+            &cue.to_str().unwrap(),
+            chdman::ExtractOptions {
+                split_tracks: true,
+                ..Default::default()
+            },
+        );
+        match extracted {
+            Err(err) if err.category() == ErrorCategory::ChdmanMissing => {
+                // a chdman too old to split tracks says so in the message
+                let reason = match err.kind() {
+                    Some(crate::ErrorKind::ToolMissing { .. }) => "chdman isn't installed",
+                    _ => err.message(),
+                };
+                report_warning(
+                    WarningKind::SkippedFile,
+                    format!(
+                        "Can't extract the tracks of \"{}\" to {purpose} them: {reason}",
+                        path.display()
+                    ),
+                );
+                return Ok(None);
+            }
+            extracted => extracted?,
+        }
+        let tracks = track_paths(&cue)?;
+        Ok(Some((directory, tracks)))
+    }
+
+    /// Finds the SHA-1s a CHD is listed under in datafiles
+    ///
+    /// The header's data SHA-1 is used if it's in the catalog, or if the CHD isn't a disc with
+    /// tracks. Otherwise datafiles list the disc track by track, so CD and GD-ROM CHDs are extracted
+    /// and hashed by their tracks, like [DumpManager::verify_file] does. The tracks' SHA-1s are kept
+    /// in the catalog, so each CHD is only extracted once. Returns `None` if chdman can't extract
+    /// them.
+    fn chd_identity(&self, path: &Path) -> Result<Option<ChdIdentity>> {
+        let info = chdman::info(&path)?;
+        if self.catalog.is_rom(info.data_sha1())?.is_some()
+            || !matches!(info.media(), ChdMedia::CD | ChdMedia::GDROM)
+        {
+            return Ok(Some(ChdIdentity::Data(info.data_sha1())));
+        }
+        if let Some(sha1s) = self.catalog.chd_tracks(info.data_sha1())? {
+            return Ok(Some(ChdIdentity::Tracks(sha1s)));
+        }
+        let Some((_directory, tracks)) = self.extract_chd_tracks(path, &info, "identify")? else {
+            return Ok(None);
+        };
+        let mut sha1s = Vec::new();
+        for track in &tracks {
+            sha1s.push(sha1_of_file(track)?);
+        }
+        self.catalog.record_chd_tracks(info.data_sha1(), &sha1s)?;
+        Ok(Some(ChdIdentity::Tracks(sha1s)))
+    }
+
+    /// Verifies a CHD, like [DumpManager::verify_file] does
+    ///
+    /// CD CHDs whose header's data SHA-1 isn't in the catalog are extracted and verified track by
+    /// track, which [DumpManager::verify_file] now does too.
+    pub fn reverify_chd(&self, path: &impl AsRef<Path>) -> Result<ROMStatus> {
+        self.verify_chd(path)
+    }
+
+    /// Verifies a disc's tracks by their hashes, which must all be ROMs of the same game
+    ///
+    fn verify_tracks(&self, tracks: &[PathBuf]) -> Result<ROMStatus> {
+        let mut game = None;
+        let mut status = ROMStatus::Verified;
+        for track in tracks {
+            let sha1 = sha1_of_file(track)?;
+            let Some(gid) = self.catalog.is_rom(sha1)? else {
+                debug!("Track \"{}\" isn't in the catalog", track.display());
+                return Ok(ROMStatus::Unverified);
+            };
+            if game.is_some_and(|game| game != gid) {
+                debug!("Track \"{}\" belongs to another game", track.display());
+                return Ok(ROMStatus::Unverified);
+            }
+            game = Some(gid);
+            // one bad track makes the whole disc a bad dump
+            if matched_rom_status(&self.catalog.reader(), sha1)? == ROMStatus::KnownBadDump {
+                status = ROMStatus::KnownBadDump;
+            }
+        }
+        Ok(if game.is_some() {
+            status
+        } else {
+            ROMStatus::Unverified
+        })
+    }
+
+    /// Checks a GDI's tracks against the catalog
+    ///
+    /// Datafiles list a Dreamcast disc's tracks, but not its GDI, so the tracks are hashed.
+    fn verify_gdi(&self, path: &impl AsRef<Path>) -> Result<ROMStatus> {
+        let tracks = track_paths(path.as_ref())?;
+        if tracks.iter().any(|track| !track.is_file()) {
+            return Ok(ROMStatus::Broken);
+        }
+        self.verify_tracks(&tracks)
+    }
+
+    /// Checks a compressed GameCube or Wii image (.rvz, .wbfs, or .gcz) against the catalog
+    ///
+    /// Datafiles list the hashes of the uncompressed discs, so dolphin-tool decompresses the image
+    /// to hash it. Images it can't read are broken.
+    fn verify_disc_image(&self, path: &impl AsRef<Path>) -> Result<ROMStatus> {
+        let sha1 = match dolphin::verify(&path.as_ref().to_str().unwrap()) {
+            Ok(sha1) => sha1,
+            Err(err) if err.category() == ErrorCategory::DolphinToolMissing => return Err(err),
+            Err(err) => {
+                debug!("{err}");
+                return Ok(ROMStatus::Broken);
+            }
+        };
+        if self.catalog.is_rom(sha1)?.is_some() {
+            matched_rom_status(&self.catalog.reader(), sha1)
+        } else if nintendo::is_nkit_name(path.as_ref()) {
+            // dolphin-tool hashes NKit images as they're stored, not as the disc they were
+            Ok(ROMStatus::Modified(ImageModification::NKit))
+        } else {
+            Ok(ROMStatus::Unverified)
+        }
+    }
+
+    /// Checks a CSO or ZSO against the catalog, by decompressing it with maxcso and hashing the ISO
+    ///
+    /// Images maxcso can't decompress are broken.
+    fn verify_cso(&self, path: &impl AsRef<Path>) -> Result<ROMStatus> {
+        let sha1 = match self.cso_sha1(path.as_ref()) {
+            Ok(sha1) => sha1,
+            Err(err) if err.category() == ErrorCategory::MaxcsoMissing => return Err(err),
+            Err(err) => {
+                debug!("{err}");
+                return Ok(ROMStatus::Broken);
+            }
+        };
+        self.verify_sha1(sha1)
+    }
+
+    /// Checks a cartridge ROM against the catalog, as No-Intro lists it
+    ///
+    fn verify_cartridge(&self, path: &impl AsRef<Path>) -> Result<ROMStatus> {
+        self.verify_sha1(cartridge_sha1(path.as_ref())?)
+    }
+
+    fn verify_zip(&self, path: &impl AsRef<Path>) -> Result<ROMStatus> {
+        match self.zip_sha1(path.as_ref())? {
+            Some(sha1) => self.verify_sha1(sha1),
+            None => Ok(ROMStatus::Unverified),
+        }
     }
 
     pub fn verify_file(&self, path: &impl AsRef<Path>) -> Result<ROMStatus> {
@@ -129,11 +2310,315 @@ impl DumpManager {
                 let extension = extension.to_str().unwrap();
                 match extension {
                     "cue" => self.verify_cue(path),
+                    "gdi" => self.verify_gdi(path),
                     "chd" => self.verify_chd(path),
-                    "bin" | "iso" => self.verify_standard_file(path),
+                    "rvz" | "wbfs" | "gcz" => self.verify_disc_image(path),
+                    "cso" | "zso" => self.verify_cso(path),
+                    "zip" => self.verify_zip(path),
+                    "bin" | "iso" => {
+                        verify_standard_file(&self.catalog.reader(), path, self.partition_hashing)
+                    }
+                    _ if cartridge::normalizer_for(path.as_ref()).is_some() => {
+                        self.verify_cartridge(path)
+                    }
                     _ => Ok(ROMStatus::Unverified),
                 }
             }
         }
     }
+
+    /// Applies a patch (like a translation or a hack) to a dump, writing the patched file to a new
+    /// file at `output`
+    ///
+    /// The dump must check out against the catalog, since patches are made for one exact ROM. See
+    /// [crate::apply_patch] for how the output is checked against `expected`.
+    pub fn patch_dump(
+        &self,
+        dump: &impl AsRef<Path>,
+        patch: &impl AsRef<Path>,
+        output: &impl AsRef<Path>,
+        expected: Option<FileHash>,
+    ) -> Result<PatchedDump> {
+        let dump = dump.as_ref();
+        let unverified = || {
+            Error::new_original(format!(
+                "Failed to patch \"{}\"\nIt isn't a dump in the catalog, which patches are made for",
+                dump.display()
+            ))
+            .with_category(ErrorCategory::InvalidInput)
+        };
+        // a bad dump is still the ROM its patches were made for
+        if !matches!(
+            self.verify_file(&dump)?,
+            ROMStatus::Verified | ROMStatus::KnownBadDump
+        ) {
+            return Err(unverified());
+        }
+        let base = self
+            .get_rom_info(dump.to_str().unwrap())?
+            .ok_or_else(unverified)?;
+        let digests = crate::apply_patch(&dump, patch, output, expected)?;
+        Ok(PatchedDump { base, digests })
+    }
+
+    /// Verifies several files, hashing the standard (.bin/.iso) files on multiple threads
+    ///
+    /// The results are in the same order as `paths`.
+    pub fn verify_files(&self, paths: &[PathBuf]) -> Vec<Result<ROMStatus>> {
+        self.verify_files_with_progress(paths, |_| ())
+    }
+
+    /// Verifies several files like [DumpManager::verify_files], calling `on_verified` with each
+    /// file's index in `paths` as soon as it's verified (from whichever thread verified it)
+    pub fn verify_files_with_progress(
+        &self,
+        paths: &[PathBuf],
+        on_verified: impl Fn(usize) + Sync,
+    ) -> Vec<Result<ROMStatus>> {
+        let is_standard = |path: &PathBuf| {
+            path.extension()
+                .is_some_and(|extension| extension == "bin" || extension == "iso")
+        };
+        let standard_files: Vec<usize> = (0..paths.len())
+            .filter(|&index| is_standard(&paths[index]))
+            .collect();
+        let next_file = AtomicUsize::new(0);
+        let worker_count = std::thread::available_parallelism()
+            .map_or(1, |count| count.get())
+            .min(standard_files.len());
+        let mut results: Vec<Option<Result<ROMStatus>>> = Vec::with_capacity(paths.len());
+        results.resize_with(paths.len(), || None);
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..worker_count)
+                .map(|_| {
+                    let reader = self.catalog.reader();
+                    let partition_hashing = self.partition_hashing;
+                    let (standard_files, next_file) = (&standard_files, &next_file);
+                    let on_verified = &on_verified;
+                    let warnings = WarningScope::current();
+                    scope.spawn(move || {
+                        WarningScope::enter(warnings, || {
+                            let mut results = Vec::new();
+                            while let Some(&index) =
+                                standard_files.get(next_file.fetch_add(1, Ordering::Relaxed))
+                            {
+                                results.push((
+                                    index,
+                                    verify_standard_file(&reader, &paths[index], partition_hashing),
+                                ));
+                                on_verified(index);
+                            }
+                            results
+                        })
+                    })
+                })
+                .collect();
+            // cuesheets and CHDs need the cuesheet DB, so they're verified on this thread meanwhile
+            for (index, path) in paths.iter().enumerate() {
+                if !is_standard(path) {
+                    results[index] = Some(self.verify_file(path));
+                    on_verified(index);
+                }
+            }
+            for worker in workers {
+                for (index, result) in worker.join().unwrap() {
+                    results[index] = Some(result);
+                }
+            }
+        });
+        results.into_iter().map(|result| result.unwrap()).collect()
+    }
+}
+
+/// The files making up a dump: a cuesheet or GDI and its tracks, or just the file itself
+fn dump_files(path: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![path.to_path_buf()];
+    files.extend(track_paths(path)?);
+    Ok(files)
+}
+
+/// The track files listed by a cuesheet or GDI, or nothing for other files
+fn track_paths(path: &Path) -> Result<Vec<PathBuf>> {
+    let content = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("cue") => std::fs::read_to_string(path).ndl("Failed to read cue")?,
+        Some("gdi") => std::fs::read_to_string(path).ndl("Failed to read GDI")?,
+        _ => return Ok(Vec::new()),
+    };
+    Ok(sheet_track_paths(path, &content))
+}
+
+/// The track files listed by the content of the cuesheet or GDI at `path`
+fn sheet_track_paths(path: &Path, content: &str) -> Vec<PathBuf> {
+    let filenames = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("cue") => self::cuesheets::get_track_filenames(&content),
+        Some("gdi") => self::gdi::get_track_filenames(&content),
+        _ => Vec::new(),
+    };
+    filenames
+        .into_iter()
+        .map(|filename| path.with_file_name(filename))
+        .collect()
+}
+
+/// Hashes a split dump's parts as if they were joined
+fn split_sha1(dump: &SplitDump) -> Result<[u8; 20]> {
+    let _timer = timings::StageTimer::start(Stage::Hashing);
+    let mut hasher = Sha1::new();
+    hashing::hash_reader(&mut dump.open()?, |chunk| hasher.update(chunk))
+        .ndl("Failed to hash split dump")?;
+    Ok(hasher.finalize().into())
+}
+
+/// Hashes a cartridge ROM as No-Intro lists it, normalizing it first if it's stored differently
+/// Whether a dump can only be hashed as a local file, going by its name: cuesheets and GDIs go by
+/// their tracks, compressed images need an external tool, and ZIPs are archives
+fn hashed_as_local_file(name: &Path) -> bool {
+    matches!(
+        name.extension().and_then(|extension| extension.to_str()),
+        Some("cue" | "gdi" | "chd" | "rvz" | "wbfs" | "gcz" | "cso" | "zso" | "zip")
+    )
+}
+
+fn cartridge_sha1(path: &Path) -> Result<[u8; 20]> {
+    match cartridge::read_normalized(path)? {
+        Some(rom) => {
+            let _timer = timings::StageTimer::start(Stage::Hashing);
+            Ok(Sha1::digest(&rom).into())
+        }
+        None => sha1_of_file(&path),
+    }
+}
+
+fn sha1_of_file(path: &impl AsRef<Path>) -> Result<[u8; 20]> {
+    let _timer = timings::StageTimer::start(Stage::Hashing);
+    let mut hasher = Sha1::new();
+    hashing::hash_file(path.as_ref(), |chunk| hasher.update(chunk)).ndl("Failed to hash file")?;
+    Ok(hasher.finalize().into())
+}
+
+/// How a dump matching a ROM in the catalog verifies: as a known bad dump if its datafile marks
+/// the ROM as one
+fn matched_rom_status(reader: &CatalogReader, sha1: [u8; 20]) -> Result<ROMStatus> {
+    Ok(match reader.rom_dump_status(sha1)? {
+        Some("baddump") => ROMStatus::KnownBadDump,
+        _ => ROMStatus::Verified,
+    })
+}
+
+fn verify_standard_file(
+    reader: &CatalogReader,
+    path: &impl AsRef<Path>,
+    partition_hashing: bool,
+) -> Result<ROMStatus> {
+    let _timer = timings::StageTimer::start(Stage::Hashing);
+    let mut hasher = Sha1::new();
+    hashing::hash_file(path.as_ref(), |chunk| hasher.update(chunk)).ndl("Failed to verify file")?;
+    let sha1: [u8; 20] = hasher.finalize().into();
+    if reader.is_rom(sha1)?.is_some() {
+        return matched_rom_status(reader, sha1);
+    }
+    let mut file = File::open(path).ndl("Failed to verify file")?;
+    // images changed on purpose can't match, which is worth telling apart from unknown ones
+    let unmatched = |file: &mut File| -> Result<ROMStatus> {
+        Ok(nintendo::detect_modification(file, path.as_ref())?
+            .map_or(ROMStatus::Unverified, ROMStatus::Modified))
+    };
+    if !partition_hashing {
+        return unmatched(&mut file);
+    }
+    let Some((start, end)) = wii::data_partition_range(&mut file)? else {
+        return unmatched(&mut file);
+    };
+    debug!("Hashing data partition of \"{}\"", path.as_ref().display());
+    file.seek(SeekFrom::Start(start))
+        .ndl("Failed to verify file")?;
+    let mut hasher = Sha1::new();
+    let bytes_written = hashing::hash_reader(&mut (&mut file).take(end - start), |chunk| {
+        hasher.update(chunk)
+    })
+    .ndl("Failed to verify file")?;
+    if bytes_written != end - start {
+        return Ok(ROMStatus::Broken);
+    }
+    if reader.is_rom(hasher.finalize().into())?.is_some() {
+        Ok(ROMStatus::DataPartitionVerified)
+    } else {
+        unmatched(&mut file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_lock_is_only_taken_over_once_released() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join(LOCK_FILE_NAME);
+        let (mut lock, unclean) = RunLock::acquire(path.clone(), WhenLocked::Fail).unwrap();
+        assert!(!unclean);
+        let busy = RunLock::acquire(path.clone(), WhenLocked::Fail)
+            .err()
+            .unwrap();
+        assert_eq!(busy.category(), ErrorCategory::Busy);
+        // like a run which crashed, leaving the file with its PID behind
+        lock.keep();
+        drop(lock);
+        assert!(path.exists());
+        let (lock, unclean) = RunLock::acquire(path.clone(), WhenLocked::Fail).unwrap();
+        assert!(unclean);
+        drop(lock);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn verifies_wii_images_by_data_partition() {
+        let directory = tempfile::tempdir().unwrap();
+        // a Wii image whose update partition was stripped, leaving the data partition untouched
+        let mut image = vec![0u8; 0x60000];
+        let mut put = |offset: usize, value: u32| {
+            image[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+        };
+        put(0x18, wii::WII_MAGIC);
+        put(0x40000, 1);
+        put(0x40004, 0x40020 >> 2);
+        put(0x40020, 0x58000 >> 2);
+        put(0x40024, 0);
+        put(0x58000 + 0x2B8, 0x1000 >> 2);
+        put(0x58000 + 0x2BC, 0x2000 >> 2);
+        image[0x59000..0x5B000].fill(0xA5);
+        let path = directory.path().join("Game.iso");
+        std::fs::write(&path, &image).unwrap();
+        let mut manager = DumpManager::init(&directory.path(), WhenLocked::Fail).unwrap();
+        // like a local datafile listing the data partition's SHA-1
+        manager
+            .add_custom_games(vec![CustomGame {
+                console: GameConsole::Wii,
+                name: String::from("Game"),
+                roms: vec![CustomROM {
+                    name: String::from("Game.iso"),
+                    size: 0x3000,
+                    crc32: None,
+                    md5: None,
+                    sha1: Sha1::digest(&image[0x58000..0x5B000]).into(),
+                    sha256: None,
+                }],
+            }])
+            .unwrap();
+        assert_eq!(
+            manager.verify_file(&path).unwrap(),
+            ROMStatus::Modified(ImageModification::Scrubbed)
+        );
+        let manager = manager.with_partition_hashing(true);
+        assert_eq!(
+            manager.verify_file(&path).unwrap(),
+            ROMStatus::DataPartitionVerified
+        );
+        assert_eq!(
+            manager.verify_files(std::slice::from_ref(&path))[0]
+                .as_ref()
+                .unwrap(),
+            &ROMStatus::DataPartitionVerified
+        );
+    }
 }
@@ -0,0 +1,209 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use compress_tools::{ArchiveContents, ArchiveIterator};
+use sha1::{Digest, Sha1};
+
+use super::timings::{Stage, StageTimer};
+use crate::{
+    Error, ErrorCategory, ErrorKind, Result, ResultUtils,
+    transfer::write_atomically,
+    utils::{
+        cartridge::{self, Normalizer},
+        work,
+    },
+};
+
+/// A file in an archive, hashed as it was decompressed (see [crate::DumpManager::scan_archive])
+#[derive(Clone, Debug)]
+pub struct ArchiveEntry {
+    /// The file's path within the archive
+    pub name: String,
+    pub size: u64,
+    /// The file's SHA-1 the way the catalog stores its ROM, or `None` for files which can only be
+    /// hashed once they're extracted, like cuesheets and compressed images
+    pub sha1: Option<[u8; 20]>,
+    /// The SHA-1 of the file as it is, which extracting it is checked against
+    raw_sha1: [u8; 20],
+}
+
+/// Hashes a file in an archive as its chunks are decompressed
+struct EntryHasher {
+    name: String,
+    size: u64,
+    hasher: Sha1,
+    normalizer: Option<&'static dyn Normalizer>,
+    /// The ROM decompressed so far, kept for normalizing it until it's too big to be a cartridge ROM
+    rom: Option<Vec<u8>>,
+}
+
+impl EntryHasher {
+    fn new(name: String) -> EntryHasher {
+        let normalizer = cartridge::normalizer_for(Path::new(&name));
+        EntryHasher {
+            name,
+            size: 0,
+            hasher: Sha1::new(),
+            normalizer,
+            rom: normalizer.map(|_| Vec::new()),
+        }
+    }
+
+    fn update(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+        self.size += chunk.len() as u64;
+        if self.size > cartridge::MAX_ROM_SIZE {
+            self.rom = None;
+        } else if let Some(rom) = &mut self.rom {
+            rom.extend_from_slice(chunk);
+        }
+    }
+
+    fn finish(self) -> ArchiveEntry {
+        let raw_sha1: [u8; 20] = self.hasher.finalize().into();
+        let sha1 = if super::hashed_as_local_file(Path::new(&self.name)) {
+            None
+        } else {
+            let normalized = match (self.normalizer, &self.rom) {
+                (Some(normalizer), Some(rom)) => normalizer.normalize(rom),
+                _ => None,
+            };
+            Some(normalized.map_or(raw_sha1, |rom| Sha1::digest(&rom).into()))
+        };
+        ArchiveEntry {
+            name: self.name,
+            size: self.size,
+            sha1,
+            raw_sha1,
+        }
+    }
+}
+
+/// Hashes each file in an archive as it's decompressed, without writing anything to disk
+///
+pub(crate) fn scan(archive: &Path) -> Result<Vec<ArchiveEntry>> {
+    let _timer = StageTimer::start(Stage::Hashing);
+    let file = File::open(archive).ndl("Failed to open archive")?;
+    let mut iterator =
+        ArchiveIterator::from_read(BufReader::new(file)).ndl("Failed to read archive")?;
+    let mut entries = Vec::new();
+    let mut current = None;
+    for contents in &mut iterator {
+        match contents {
+            // folders have no data of their own
+            ArchiveContents::StartOfEntry(name, _) => {
+                current = (!name.ends_with('/')).then(|| EntryHasher::new(name));
+            }
+            ArchiveContents::DataChunk(chunk) => {
+                if let Some(hasher) = &mut current {
+                    hasher.update(&chunk);
+                }
+            }
+            ArchiveContents::EndOfEntry => {
+                if let Some(hasher) = current.take() {
+                    entries.push(hasher.finish());
+                }
+            }
+            ArchiveContents::Err(err) => return Err(err).ndl("Failed to read archive"),
+        }
+    }
+    iterator.close().ndl("Failed to read archive")?;
+    Ok(entries)
+}
+
+/// Passes writes through to a file, hashing them on the way
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha1,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Extracts a file found by [scan] straight to `target`, which must not exist yet, hashing it as
+/// it's written rather than reading it back
+///
+/// The file is written under a temporary name next to `target` (see [write_atomically]), and only
+/// renamed once it matches the hash it had when it was scanned.
+pub(crate) fn extract_entry(archive: &Path, entry: &ArchiveEntry, target: &Path) -> Result<()> {
+    if target.exists() {
+        return Err(Error::new_original(format!(
+            "Failed to extract \"{}\"\n\"{}\" already exists",
+            entry.name,
+            target.display()
+        ))
+        .with_category(ErrorCategory::IO));
+    }
+    work::ensure_free_space(target.parent().unwrap(), entry.size)?;
+    write_atomically(target, |partial| {
+        let source = File::open(archive).ndl("Failed to open archive")?;
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(partial)
+            .ndl("Failed to extract file")?;
+        let mut writer = HashingWriter {
+            inner: BufWriter::new(file),
+            hasher: Sha1::new(),
+        };
+        compress_tools::uncompress_archive_file(BufReader::new(source), &mut writer, &entry.name)
+            .ndl("Failed to extract file")?;
+        let HashingWriter { inner, hasher } = writer;
+        let file = inner
+            .into_inner()
+            .map_err(|err| err.into_error())
+            .ndl("Failed to extract file")?;
+        file.sync_all().ndl("Failed to extract file")?;
+        let actual: [u8; 20] = hasher.finalize().into();
+        if actual != entry.raw_sha1 {
+            return Err(Error::new(
+                format!(
+                    "Failed to extract \"{}\"\nIt doesn't match the file in the archive",
+                    entry.name
+                ),
+                ErrorKind::VerificationFailed {
+                    algorithm: "SHA-1",
+                    expected: hex::encode(entry.raw_sha1),
+                    actual: hex::encode(actual),
+                },
+            ));
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::zip::write_torrentzip;
+
+    #[test]
+    fn extracts_scanned_files() {
+        let folder = tempfile::tempdir().unwrap();
+        let rom = folder.path().join("Game.gba");
+        std::fs::write(&rom, [7u8; 2048]).unwrap();
+        let zip = folder.path().join("Game.zip");
+        write_torrentzip(&[("Game.gba".to_string(), rom)], &zip).unwrap();
+        let entries = scan(&zip).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "Game.gba");
+        assert_eq!(entries[0].size, 2048);
+        assert_eq!(entries[0].sha1, Some(Sha1::digest([7u8; 2048]).into()));
+        let target = folder.path().join("Extracted.gba");
+        extract_entry(&zip, &entries[0], &target).unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), vec![7u8; 2048]);
+        assert!(extract_entry(&zip, &entries[0], &target).is_err());
+    }
+}
@@ -1,24 +1,57 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     hash::*,
-    path::Path,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    time::{Duration, Instant},
 };
 
 use chrono::{DateTime, TimeDelta, Utc};
 use log::{debug, info};
 use logiqx::*;
 use rusqlite::{
-    Connection, OptionalExtension, ToSql,
+    Connection, OptionalExtension, ToSql, Transaction,
     types::{FromSql, FromSqlError, ToSqlOutput},
 };
-use ureq::{Agent, agent};
 
+use self::changes::{ChangeLog, changes_since};
 use self::logiqx::GameElement;
-use crate::{Error, GameConsole, Result, ResultUtils, utils::*};
+use super::{
+    UpdateTarget, WarningScope,
+    network::NetworkTimeouts,
+    timings::{self, Stage},
+};
+use crate::{
+    CheckpointPolicy, DatabaseCheck, DatabaseMaintenance, DatabaseSchema, Error, ErrorCategory,
+    GameConsole, MultiHasher, Result, ResultUtils,
+    utils::{migrations::*, *},
+};
 
+mod changes;
+mod conversions;
+mod diff;
 mod logiqx;
 mod nointro;
+mod query;
+mod reader;
 mod redump;
+mod source;
+mod tracks;
+mod verifications;
+
+pub use self::changes::{CatalogChange, ChangeKind};
+pub use self::diff::{DatafileDiff, ROMChange};
+pub use self::nointro::NoIntroSource;
+pub use self::query::GameQuery;
+pub use self::reader::{CatalogROM, CatalogReader, GameEntry};
+pub use self::redump::RedumpSource;
+pub use self::source::{AvailableDatafile, DatafileSource, FetchedDatafile, FileDatafileSource};
+pub use self::tracks::{IndexedGame, TrackIndex};
 
 fn decompress_rom_name(rom_name: &str, game_name: &str) -> String {
     if rom_name == "$c" {
@@ -99,23 +132,47 @@ impl FromSql for Category {
         }
     }
 }
+impl Category {
+    /// The category's name in datafiles, or `None` if it wasn't recognized
+    pub(super) fn name(&self) -> Option<&'static str> {
+        match self {
+            Self::Games => Some("Games"),
+            Self::Demos => Some("Demos"),
+            Self::Coverdiscs => Some("Coverdiscs"),
+            Self::Applications => Some("Applications"),
+            Self::Preproduction => Some("Preproduction"),
+            Self::Educational => Some("Educational"),
+            Self::BonusDiscs => Some("Bonus Discs"),
+            Self::Multimedia => Some("Multimedia"),
+            Self::Addons => Some("Add-Ons"),
+            Self::Audio => Some("Audio"),
+            Self::Video => Some("Video"),
+            Self::Unknown => None,
+        }
+    }
+
+    /// The integer the category is stored as in the catalog DB
+    pub(super) fn id(&self) -> i64 {
+        match self {
+            Self::Games => 0,
+            Self::Demos => 1,
+            Self::Coverdiscs => 2,
+            Self::Applications => 3,
+            Self::Preproduction => 4,
+            Self::Educational => 5,
+            Self::BonusDiscs => 6,
+            Self::Multimedia => 7,
+            Self::Addons => 8,
+            Self::Audio => 9,
+            Self::Video => 10,
+            Self::Unknown => 127,
+        }
+    }
+}
 impl ToSql for Category {
     fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
         Ok(ToSqlOutput::Owned(rusqlite::types::Value::Integer(
-            match self {
-                Self::Games => 0,
-                Self::Demos => 1,
-                Self::Coverdiscs => 2,
-                Self::Applications => 3,
-                Self::Preproduction => 4,
-                Self::Educational => 5,
-                Self::BonusDiscs => 6,
-                Self::Multimedia => 7,
-                Self::Addons => 8,
-                Self::Audio => 9,
-                Self::Video => 10,
-                Self::Unknown => 127,
-            },
+            self.id(),
         )))
     }
 }
@@ -135,6 +192,16 @@ impl From<&str> for Status {
         }
     }
 }
+impl Status {
+    /// The status's name in datafiles, or `None` if it wasn't recognized
+    pub(super) fn name(&self) -> Option<&'static str> {
+        match self {
+            Self::Verified => Some("verified"),
+            Self::BadDump => Some("baddump"),
+            Self::Unknown => None,
+        }
+    }
+}
 impl FromSql for Status {
     fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
         match value.as_i64()? {
@@ -157,18 +224,83 @@ impl ToSql for Status {
     }
 }
 
+/// The regions used in Redump and No-Intro game names, like "(USA)" or "(Japan, Korea)"
+const REGIONS: [&str; 40] = [
+    "Argentina",
+    "Asia",
+    "Australia",
+    "Austria",
+    "Belgium",
+    "Brazil",
+    "Canada",
+    "China",
+    "Croatia",
+    "Denmark",
+    "Europe",
+    "Finland",
+    "France",
+    "Germany",
+    "Greece",
+    "Hong Kong",
+    "India",
+    "Ireland",
+    "Israel",
+    "Italy",
+    "Japan",
+    "Korea",
+    "Latin America",
+    "Mexico",
+    "Netherlands",
+    "New Zealand",
+    "Norway",
+    "Poland",
+    "Portugal",
+    "Russia",
+    "Scandinavia",
+    "South Africa",
+    "Spain",
+    "Sweden",
+    "Switzerland",
+    "Taiwan",
+    "Turkey",
+    "UK",
+    "USA",
+    "World",
+];
+
+/// Finds the region tag in a game's name, like "USA" in "Crash Bandicoot (USA) (Rev 1)"
+///
+/// Names list regions before other tags, so the first tag made only of known regions is used.
+pub(crate) fn parse_region(name: &str) -> Option<String> {
+    name.split('(')
+        .skip(1)
+        .filter_map(|tag| tag.split_once(')').map(|(tag, _)| tag))
+        .find(|tag| {
+            tag.split(',')
+                .all(|region| REGIONS.contains(&region.trim()))
+        })
+        .map(str::to_string)
+}
+
 enum Author {
     Redump,
     NoIntro,
+    Local,
     Other(String),
 }
-impl FromSql for Author {
-    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
-        Ok(match value.as_str()? {
+impl From<&str> for Author {
+    fn from(value: &str) -> Self {
+        match value {
             "Redump" => Self::Redump,
             "No-Intro" => Self::NoIntro,
+            "Local" => Self::Local,
             v => Self::Other(v.to_string()),
-        })
+        }
+    }
+}
+impl FromSql for Author {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        Ok(Self::from(value.as_str()?))
     }
 }
 impl ToSql for Author {
@@ -177,6 +309,7 @@ impl ToSql for Author {
             match self {
                 Self::Redump => "Redump".to_string(),
                 Self::NoIntro => "No-Intro".to_string(),
+                Self::Local => "Local".to_string(),
                 Self::Other(str) => str.clone(),
             },
         )))
@@ -209,6 +342,10 @@ pub struct Game {
     pub categories: HashSet<Category>,
     pub roms: HashSet<ROM>,
     pub revision: i64,
+    /// The game's serial, for datafiles listing them
+    pub serial: Option<String>,
+    /// Parsed from the game's name, so it's only updated when the game's renamed
+    pub region: Option<String>,
     loaded: bool,
 }
 impl GameElement for Game {
@@ -227,11 +364,18 @@ impl GameElement for Game {
             categories: HashSet::new(),
             roms: HashSet::new(),
             revision: 0,
+            serial: None,
+            region: parse_region(name),
             loaded: true,
         };
         for node in node.get_tagged_children("category") {
             game.categories.insert(node.text().unwrap_or("").into());
         }
+        game.serial = node
+            .get_tagged_children("serial")
+            .filter_map(|node| node.text())
+            .map(|serial| serial.trim().to_string())
+            .find(|serial| !serial.is_empty());
         Ok(game)
     }
     fn parse_game_rom(node: &roxmltree::Node) -> Result<Self::ROM> {
@@ -259,6 +403,7 @@ impl GameElement for Game {
     }
 }
 impl Game {
+    /// Deletes the game, along with its categories and ROMs
     fn delete(&self, connection: &impl CanPrepare) -> Result<()> {
         let mut statement = connection
             .prepare_cached_common("DELETE FROM games WHERE gid = ?")
@@ -266,57 +411,92 @@ impl Game {
         statement
             .execute((self.gid.unwrap(),))
             .ndl("Failed to update games in catalog DB")?;
+        Ok(())
+    }
+    /// Renames the game, keeping its gid
+    fn rename(&mut self, connection: &impl CanPrepare, name: &str) -> Result<()> {
+        let region = parse_region(name);
         let mut statement = connection
-            .prepare_cached_common("DELETE FROM game_categories WHERE gid = ?")
-            .ndl("Failed to update game categories in catalog DB")?;
-        statement
-            .execute((self.gid.unwrap(),))
-            .ndl("Failed to update game ROMs in catalog DB")?;
-        let mut statement = connection
-            .prepare_cached_common("DELETE FROM roms WHERE gid = ?")
-            .ndl("Failed to update game ROMs in catalog DB")?;
+            .prepare_cached_common("UPDATE games SET name = ?, region = ? WHERE gid = ?")
+            .ndl("Failed to rename game in catalog DB")?;
         statement
-            .execute((self.gid.unwrap(),))
-            .ndl("Failed to update game ROMs in catalog DB")?;
+            .execute((name, &region, self.gid.unwrap()))
+            .ndl("Failed to rename game in catalog DB")?;
+        self.name = name.to_string();
+        self.region = region;
         Ok(())
     }
-    fn insert_categories(&self, connection: &impl CanPrepare) -> Result<()> {
-        let mut statement = connection
-            .prepare_cached_common("INSERT INTO game_categories (gid, category) VALUES (?, ?)")
-            .ndl("Failed to add game category to catalog DB")?;
+    /// The SHA-1s of the game's ROMs in order, which a renamed game keeps
+    fn rom_set(&self) -> Vec<[u8; 20]> {
+        let mut sha1s: Vec<[u8; 20]> = self.roms.iter().map(|rom| rom.sha1).collect();
+        sha1s.sort();
+        sha1s
+    }
+    /// The ROMs, by name, whose hashes differ in `game` (missing ROMs have no hash)
+    fn rom_changes(&self, game: &Game) -> Vec<ROMChange> {
+        let old_roms: BTreeMap<&str, [u8; 20]> = self
+            .roms
+            .iter()
+            .map(|rom| (rom.name.as_str(), rom.sha1))
+            .collect();
+        let new_roms: BTreeMap<&str, [u8; 20]> = game
+            .roms
+            .iter()
+            .map(|rom| (rom.name.as_str(), rom.sha1))
+            .collect();
+        let mut rom_names: Vec<&str> = old_roms.keys().chain(new_roms.keys()).copied().collect();
+        rom_names.sort();
+        rom_names.dedup();
+        rom_names
+            .into_iter()
+            .filter_map(|rom_name| {
+                let old_sha1 = old_roms.get(rom_name).copied();
+                let new_sha1 = new_roms.get(rom_name).copied();
+                (old_sha1 != new_sha1).then(|| ROMChange {
+                    game: self.name.clone(),
+                    rom: rom_name.to_string(),
+                    old_sha1,
+                    new_sha1,
+                })
+            })
+            .collect()
+    }
+    fn insert_categories(&self, batches: &mut GameRowBatches) {
         for category in &self.categories {
-            statement
-                .execute((self.gid.unwrap(), category))
-                .ndl("Failed to add game category to catalog DB")?;
+            batches.categories.push([
+                Box::new(self.gid.unwrap()) as Box<dyn ToSql>,
+                Box::new(*category),
+            ]);
         }
-        Ok(())
     }
-    fn insert_roms(&self, connection: &impl CanPrepare) -> Result<()> {
-        let mut statement = connection
-            .prepare_cached_common("INSERT INTO roms (gid, name, status, size, crc32, md5, sha1, sha256) VALUES (?, ?, ?, ?, ?, ?, ?, ?)")
-            .ndl("Failed to add ROMs to catalog DB")?;
+    fn insert_roms(&self, batches: &mut GameRowBatches) {
         for rom in &self.roms {
-            let name = compress_rom_name(&rom.name, &self.name);
-            statement
-                .execute((
-                    self.gid, &name, rom.status, rom.size, rom.crc32, rom.md5, rom.sha1, rom.sha256,
-                ))
-                .ndl("Failed to add ROMs to catalog DB")?;
+            batches.roms.push([
+                Box::new(self.gid) as Box<dyn ToSql>,
+                Box::new(compress_rom_name(&rom.name, &self.name)),
+                Box::new(rom.status),
+                Box::new(rom.size),
+                Box::new(rom.crc32),
+                Box::new(rom.md5),
+                Box::new(rom.sha1),
+                Box::new(rom.sha256),
+            ]);
         }
-        Ok(())
     }
-    fn insert(&mut self, connection: &impl CanPrepare) -> Result<()> {
-        let mut insert_game_stmt = connection
-            .prepare_cached_common("INSERT INTO games (dfid, name) VALUES (?, ?) RETURNING gid")
-            .ndl("Failed to add game to catalog DB")?;
-        let gid: i64 = insert_game_stmt
-            .query_one((self.dfid, &self.name), |row| Ok(row.get(0).unwrap()))
-            .ndl("Failed to add game to catalog DB")?;
+    fn insert(&mut self, batches: &mut GameRowBatches) {
+        let gid = batches.next_gid;
+        batches.next_gid += 1;
+        batches.games.push([
+            Box::new(gid) as Box<dyn ToSql>,
+            Box::new(self.dfid),
+            Box::new(self.name.clone()),
+            Box::new(self.serial.clone()),
+            Box::new(self.region.clone()),
+        ]);
         self.gid = Some(gid);
         self.revision = 0;
-        self.insert_categories(connection)?;
-        self.insert_roms(connection)?;
-        Ok(())
+        self.insert_categories(batches);
+        self.insert_roms(batches);
     }
     fn load(&mut self, connection: &impl CanPrepare) -> Result<()> {
         if self.loaded {
@@ -358,7 +538,12 @@ impl Game {
         self.loaded = true;
         Ok(())
     }
-    fn update(&mut self, connection: &impl CanPrepare, game: Game) -> Result<bool> {
+    fn update(
+        &mut self,
+        connection: &impl CanPrepare,
+        batches: &mut GameRowBatches,
+        game: Game,
+    ) -> Result<bool> {
         if !self.loaded {
             panic!("Attempted to update unloaded game");
         }
@@ -371,6 +556,16 @@ impl Game {
             }
         };
         let mut changed = false;
+        if self.serial != game.serial {
+            let mut statement = connection
+                .prepare_cached_common("UPDATE games SET serial = ? WHERE gid = ?")
+                .ndl("Failed to update games in catalog DB")?;
+            statement
+                .execute((&game.serial, gid))
+                .ndl("Failed to update games in catalog DB")?;
+            self.serial = game.serial;
+            changed = true;
+        }
         if self.categories != game.categories {
             if self.categories.len() != 0 {
                 let mut statement = connection
@@ -383,7 +578,7 @@ impl Game {
             }
             self.categories = game.categories;
             if self.categories.len() != 0 {
-                self.insert_categories(connection)?;
+                self.insert_categories(batches);
             }
         }
         if self.roms != game.roms {
@@ -428,7 +623,7 @@ impl Game {
             }
             self.roms = game.roms;
             if self.roms.len() != 0 {
-                self.insert_roms(connection)?;
+                self.insert_roms(batches);
             }
             changed = true;
         }
@@ -436,6 +631,54 @@ impl Game {
     }
 }
 
+/// Game, category, and ROM rows waiting to be inserted for the games being imported
+///
+/// New games are given their gids up front so that their rows can be batched too, which is only safe
+/// within a single transaction.
+struct GameRowBatches {
+    next_gid: i64,
+    games: InsertBatch,
+    categories: InsertBatch,
+    roms: InsertBatch,
+}
+
+impl GameRowBatches {
+    fn new(connection: &impl CanPrepare) -> Result<GameRowBatches> {
+        let mut statement = connection
+            .prepare_cached_common("SELECT COALESCE(MAX(gid), 0) + 1 FROM games")
+            .ndl("Failed to add games to catalog DB")?;
+        let next_gid = statement
+            .query_one((), |row| row.get(0))
+            .ndl("Failed to add games to catalog DB")?;
+        Ok(GameRowBatches {
+            next_gid,
+            games: InsertBatch::new("games", &["gid", "dfid", "name", "serial", "region"]),
+            categories: InsertBatch::new("game_categories", &["gid", "category"]),
+            roms: InsertBatch::new(
+                "roms",
+                &[
+                    "gid", "name", "status", "size", "crc32", "md5", "sha1", "sha256",
+                ],
+            ),
+        })
+    }
+
+    fn flush_if_full(&mut self, connection: &impl CanPrepare) -> Result<()> {
+        if self.games.is_full() || self.categories.is_full() || self.roms.is_full() {
+            self.flush(connection)?;
+        }
+        Ok(())
+    }
+
+    /// Inserts the buffered rows, games first so that their categories and ROMs can refer to them
+    ///
+    fn flush(&mut self, connection: &impl CanPrepare) -> Result<()> {
+        self.games.flush(connection)?;
+        self.categories.flush(connection)?;
+        self.roms.flush(connection)
+    }
+}
+
 #[allow(unused)]
 struct Datafile {
     pub dfid: i64,
@@ -443,6 +686,8 @@ struct Datafile {
     pub author: Author,
     pub version: String,
     pub last_updated: DateTime<Utc>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
 }
 impl Datafile {
     fn get(connection: &impl CanPrepare, name: &str, author: &Author) -> Result<Datafile> {
@@ -458,6 +703,8 @@ impl Datafile {
                     version: row.get("version").unwrap(),
                     last_updated: DateTime::from_timestamp_millis(row.get("last_updated").unwrap())
                         .unwrap(),
+                    etag: row.get("etag").unwrap(),
+                    last_modified: row.get("last_modified").unwrap(),
                 })
             })
             .optional()
@@ -480,13 +727,35 @@ impl Datafile {
             }
         }
     }
+    /// Uses this datafile as a source of games for a console
+    ///
+    /// When several datafiles for the same console contain a game with the same name,
+    /// the one with the lowest priority value wins (ties go to the oldest datafile).
+    fn assign_console(
+        &self,
+        connection: &impl CanPrepare,
+        console: GameConsole,
+        priority: i64,
+    ) -> Result<()> {
+        let mut statement = connection
+            .prepare_cached_common(
+                "INSERT OR REPLACE INTO console_datafiles (console, dfid, priority) VALUES (?, ?, ?)",
+            )
+            .ndl("Failed to assign datafile to console in catalog DB")?;
+        statement
+            .execute((console.formal_name(), self.dfid, priority))
+            .ndl("Failed to assign datafile to console in catalog DB")?;
+        Ok(())
+    }
     fn get_all_games_unloaded(
         &self,
         connection: &impl CanPrepare,
     ) -> Result<HashMap<String, Game>> {
         let mut games: HashMap<String, Game> = HashMap::new();
         let mut get_games_stmt = connection
-            .prepare_cached_common("SELECT gid, name, revision FROM games WHERE dfid = ?")
+            .prepare_cached_common(
+                "SELECT gid, name, revision, serial, region FROM games WHERE dfid = ?",
+            )
             .ndl("Failed to retrieve games from catalog DB")?;
         let game_rows = get_games_stmt
             .query_map((self.dfid,), |row| {
@@ -497,6 +766,8 @@ impl Datafile {
                     categories: HashSet::new(),
                     roms: HashSet::new(),
                     revision: row.get(2).unwrap(),
+                    serial: row.get(3).unwrap(),
+                    region: row.get(4).unwrap(),
                     loaded: false,
                 })
             })
@@ -510,13 +781,15 @@ impl Datafile {
     fn update(&self, connection: &impl CanPrepare) -> Result<()> {
         let mut statement = connection
             .prepare_cached_common(
-                "UPDATE datafiles SET version = ?, last_updated = ? WHERE dfid = ?",
+                "UPDATE datafiles SET version = ?, last_updated = ?, etag = ?, last_modified = ? WHERE dfid = ?",
             )
             .ndl("Failed to update datafile in catalog DB")?;
         let rows_changed = statement
             .execute((
                 &self.version,
                 self.last_updated.timestamp_millis(),
+                &self.etag,
+                &self.last_modified,
                 self.dfid,
             ))
             .ndl("Failed to update datafile in catalog DB")?;
@@ -531,175 +804,742 @@ impl Datafile {
     }
 }
 
+/// A datafile which is out of date and needs to be downloaded
+struct UpdateJob {
+    datafile: Datafile,
+    available: AvailableDatafile,
+    source: Arc<dyn DatafileSource>,
+}
+impl UpdateJob {
+    /// Downloads the datafile, returning `None` if it hasn't changed
+    ///
+    fn download(&self) -> Result<Option<FetchedDatafile>> {
+        timings::time(Stage::Download, || {
+            self.source.fetch_datafile(
+                &self.available,
+                self.datafile.etag.as_deref(),
+                self.datafile.last_modified.as_deref(),
+            )
+        })
+    }
+}
+
+/// A summary of a datafile stored in the catalog
+pub struct DatafileInfo {
+    pub name: String,
+    pub author: String,
+    pub version: String,
+    pub last_updated: DateTime<Utc>,
+    pub game_count: usize,
+    pub rom_count: usize,
+}
+
+/// A game which kept its gid when a datafile renamed it
+pub struct GameRename {
+    pub gid: i64,
+    pub old_name: String,
+    pub new_name: String,
+    pub renamed: DateTime<Utc>,
+}
+
+/// A rule excluding catalog entries from missing/wanted game lists
+#[derive(Clone, Debug)]
+pub enum BlockRule {
+    /// A case-insensitive wildcard pattern matched against game names (e.g. `*(Unl)*`)
+    Pattern(String),
+    /// A specific game, by its gid in the catalog
+    Gid(i64),
+}
+
+impl BlockRule {
+    pub(super) fn matches(&self, gid: i64, name: &str) -> bool {
+        match self {
+            Self::Pattern(pattern) => wildcard_match(pattern, name),
+            Self::Gid(blocked_gid) => *blocked_gid == gid,
+        }
+    }
+}
+
+/// Which games of a console to write in a datafile or list of a collection, for other ROM managers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CollectionPart {
+    /// The games which aren't collected, or aren't completely (a fixdat or miss list)
+    Missing,
+    /// The games which are collected, or partly (a have datafile or list)
+    Owned,
+}
+
+/// A datafile on disk which supplements (or overrides) the built-in datafiles for a console
+#[derive(Clone)]
+pub struct LocalDatafile {
+    pub console: GameConsole,
+    pub path: PathBuf,
+    /// Games in datafiles with lower priority values take precedence (Redump and No-Intro use 0)
+    pub priority: i64,
+}
+
+/// The author of the datafiles custom games are kept in
+const CUSTOM_AUTHOR: &str = "Custom";
+
+/// Custom games' datafiles come after every other datafile for their console
+const CUSTOM_PRIORITY: i64 = i64::MAX;
+
+/// The datafile a console's custom games are kept in
+fn custom_datafile_name(console: GameConsole) -> String {
+    format!("{CUSTOM_AUTHOR} - {}", console.formal_name())
+}
+
+/// A ROM of a [CustomGame]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CustomROM {
+    pub name: String,
+    pub size: usize,
+    /// The catalog stores every ROM's CRC32 and MD5, so unknown ones are stored as zeros
+    pub crc32: Option<u32>,
+    pub md5: Option<[u8; 16]>,
+    pub sha1: [u8; 20],
+    pub sha256: Option<[u8; 32]>,
+}
+
+impl CustomROM {
+    /// Hashes a dump the way it's hashed when it's verified, named after its file
+    ///
+    /// Cartridge ROMs are normalized first, like when they're verified.
+    pub fn of_file(path: &impl AsRef<Path>) -> Result<CustomROM> {
+        let path = path.as_ref();
+        let digests = match cartridge::read_normalized(path)? {
+            Some(rom) => {
+                let mut hasher = MultiHasher::all();
+                hasher.update(&rom);
+                hasher.finalize()
+            }
+            None => MultiHasher::all().hash_file(&path, true)?,
+        };
+        Ok(CustomROM {
+            name: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            size: digests.size as usize,
+            crc32: digests.crc32,
+            md5: digests.md5,
+            sha1: digests.sha1.unwrap(),
+            sha256: digests.sha256,
+        })
+    }
+}
+
+/// A game the user adds to the catalog, like a ROM hack, a translation, or homebrew
+///
+/// Each console's custom games are kept in a datafile of their own ("Custom - <console>"), which
+/// updates leave alone.
+#[derive(Clone, Debug)]
+pub struct CustomGame {
+    pub console: GameConsole,
+    pub name: String,
+    pub roms: Vec<CustomROM>,
+}
+
+impl CustomGame {
+    fn into_game(self, dfid: i64) -> Game {
+        Game {
+            dfid,
+            gid: None,
+            region: parse_region(&self.name),
+            name: self.name,
+            categories: HashSet::from([Category::Games]),
+            roms: self
+                .roms
+                .into_iter()
+                .map(|rom| ROM {
+                    name: rom.name,
+                    status: None,
+                    size: rom.size,
+                    crc32: rom.crc32.unwrap_or(0) as i32,
+                    md5: rom.md5.unwrap_or([0; 16]),
+                    sha1: rom.sha1,
+                    sha256: rom.sha256,
+                })
+                .collect(),
+            revision: 0,
+            serial: None,
+            loaded: true,
+        }
+    }
+}
+
 pub struct Catalog {
+    // dropped before the connection, so the write-ahead log is written back when it closes
+    reader: CatalogReader,
     connection: Connection,
     dat_update_delay: TimeDelta,
+    download_concurrency: usize,
+    sources: Vec<Arc<dyn DatafileSource>>,
+    local_datafiles: Vec<LocalDatafile>,
+    block_rules: Vec<BlockRule>,
+    forced_updates: Vec<UpdateTarget>,
+    /// How the write-ahead log is checkpointed, or `None` if the catalog was opened read-only
+    checkpoint: Option<CheckpointPolicy>,
+    /// Whether the catalog was opened read-only, so nothing may be recorded in it
+    read_only: bool,
 }
 
+/// The catalog DB's schema history, oldest first (see [run_migrations])
+const CATALOG_MIGRATIONS: [Migration; 13] = [
+    Migration {
+        description: "Create datafile, game, and ROM tables",
+        apply: create_catalog_tables,
+    },
+    Migration {
+        description: "Add cache validators to datafiles",
+        apply: add_datafile_cache_validators,
+    },
+    Migration {
+        description: "Add per-console datafile sources",
+        apply: add_console_datafiles,
+    },
+    Migration {
+        description: "Add foreign keys with cascading deletes",
+        apply: add_foreign_keys,
+    },
+    Migration {
+        description: "Add MD5 and size/CRC32 ROM indexes",
+        apply: add_rom_hash_indexes,
+    },
+    Migration {
+        description: "Add game serials and regions",
+        apply: add_game_serials_and_regions,
+    },
+    Migration {
+        description: "Add SHA-256 ROM index",
+        apply: add_rom_sha256_index,
+    },
+    Migration {
+        description: "Add maintenance table",
+        apply: create_maintenance_table,
+    },
+    Migration {
+        description: "Add game rename history",
+        apply: add_game_renames,
+    },
+    Migration {
+        description: "Replace rename history with change history",
+        apply: add_catalog_changes,
+    },
+    Migration {
+        description: "Add conversion size history",
+        apply: add_conversion_sizes,
+    },
+    Migration {
+        description: "Add CHD verification history",
+        apply: add_chd_verifications,
+    },
+    Migration {
+        description: "Add CHD track hashes",
+        apply: add_chd_tracks,
+    },
+];
+
+/// For each console, keeps only the highest precedence game of each name
+const RESOLVED_GAMES_VIEW: &str = r#"
+        CREATE VIEW IF NOT EXISTS "resolved_games" AS
+        SELECT "games"."gid", "games"."dfid", "games"."name", "games"."revision", "console_datafiles"."console"
+        FROM "games"
+        JOIN "console_datafiles" ON "games"."dfid" = "console_datafiles"."dfid"
+        WHERE NOT EXISTS (
+            SELECT 1 FROM "games" AS "other_games"
+            JOIN "console_datafiles" AS "other_sources" ON "other_games"."dfid" = "other_sources"."dfid"
+            WHERE "other_sources"."console" = "console_datafiles"."console"
+                AND "other_games"."name" = "games"."name"
+                AND (
+                    "other_sources"."priority" < "console_datafiles"."priority"
+                    OR ("other_sources"."priority" = "console_datafiles"."priority" AND "other_sources"."dfid" < "console_datafiles"."dfid")
+                )
+        );
+"#;
+
+fn create_catalog_tables(transaction: &Transaction) -> Result<()> {
+    // databases created before schema versioning may already have these
+    transaction
+        .execute_batch(
+            r#"
+                CREATE TABLE IF NOT EXISTS "datafiles" (
+                    "dfid"	INTEGER NOT NULL UNIQUE,
+                    "name"	TEXT NOT NULL UNIQUE,
+                    "author"    TEXT NOT NULL,
+                    "version"	TEXT NOT NULL,
+                    "last_updated"	INTEGER NOT NULL,
+                    PRIMARY KEY("dfid")
+                );
+                CREATE TABLE IF NOT EXISTS "games" (
+                    "dfid"	INTEGER NOT NULL,
+                    "gid"	INTEGER NOT NULL UNIQUE,
+                    "name"	TEXT NOT NULL,
+                    "revision"	INTEGER NOT NULL DEFAULT 0,
+                    PRIMARY KEY("gid")
+                );
+                CREATE TABLE IF NOT EXISTS "game_categories" (
+                    "gid"	INTEGER NOT NULL,
+                    "category"	INTEGER NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS "roms" (
+                    "gid"	INTEGER NOT NULL,
+                    "name"	TEXT NOT NULL,
+                    "status"	INTEGER,
+                    "size"	INTEGER NOT NULL,
+                    "crc32"	INTEGER NOT NULL,
+                    "md5"	BLOB NOT NULL,
+                    "sha1"	BLOB NOT NULL,
+                    "sha256"	BLOB
+                );
+                CREATE INDEX IF NOT EXISTS "game_category_index" ON "game_categories" (
+                    "gid"	DESC
+                );
+                CREATE INDEX IF NOT EXISTS "game_roms" ON "roms" (
+                    "gid"	DESC
+                );
+                CREATE INDEX IF NOT EXISTS "sha1_roms" ON "roms" (
+                    "sha1"	DESC
+                );
+            "#,
+        )
+        .ndl("Failed to create tables in catalog DB")
+}
+
+fn add_datafile_cache_validators(transaction: &Transaction) -> Result<()> {
+    let columns = get_table_columns(transaction, "datafiles")?;
+    for column in ["etag", "last_modified"] {
+        if !columns.contains(column) {
+            transaction
+                .execute(
+                    &format!(r#"ALTER TABLE "datafiles" ADD COLUMN "{column}" TEXT"#),
+                    (),
+                )
+                .ndl("Failed to update tables in catalog DB")?;
+        }
+    }
+    Ok(())
+}
+
+fn add_console_datafiles(transaction: &Transaction) -> Result<()> {
+    transaction
+        .execute_batch(
+            r#"
+                CREATE TABLE IF NOT EXISTS "console_datafiles" (
+                    "console"	TEXT NOT NULL,
+                    "dfid"	INTEGER NOT NULL,
+                    "priority"	INTEGER NOT NULL DEFAULT 0,
+                    PRIMARY KEY("console","dfid")
+                );
+                CREATE INDEX IF NOT EXISTS "game_names" ON "games" (
+                    "name"	DESC
+                );
+            "#,
+        )
+        .ndl("Failed to create tables in catalog DB")?;
+    transaction
+        .execute(RESOLVED_GAMES_VIEW, ())
+        .ndl("Failed to create tables in catalog DB")?;
+    Ok(())
+}
+
+fn add_foreign_keys(transaction: &Transaction) -> Result<()> {
+    // SQLite can't add constraints to existing tables, so the child tables are rebuilt without their orphans
+    transaction
+        .execute_batch(
+            r#"
+                DROP VIEW IF EXISTS "resolved_games";
+                CREATE TABLE "new_games" (
+                    "dfid"	INTEGER NOT NULL REFERENCES "datafiles"("dfid") ON DELETE CASCADE,
+                    "gid"	INTEGER NOT NULL UNIQUE,
+                    "name"	TEXT NOT NULL,
+                    "revision"	INTEGER NOT NULL DEFAULT 0,
+                    PRIMARY KEY("gid")
+                );
+                INSERT INTO "new_games" ("dfid", "gid", "name", "revision")
+                SELECT "dfid", "gid", "name", "revision" FROM "games"
+                WHERE "dfid" IN (SELECT "dfid" FROM "datafiles");
+                DROP TABLE "games";
+                ALTER TABLE "new_games" RENAME TO "games";
+                CREATE TABLE "new_game_categories" (
+                    "gid"	INTEGER NOT NULL REFERENCES "games"("gid") ON DELETE CASCADE,
+                    "category"	INTEGER NOT NULL
+                );
+                INSERT INTO "new_game_categories" ("gid", "category")
+                SELECT "gid", "category" FROM "game_categories"
+                WHERE "gid" IN (SELECT "gid" FROM "games");
+                DROP TABLE "game_categories";
+                ALTER TABLE "new_game_categories" RENAME TO "game_categories";
+                CREATE TABLE "new_roms" (
+                    "gid"	INTEGER NOT NULL REFERENCES "games"("gid") ON DELETE CASCADE,
+                    "name"	TEXT NOT NULL,
+                    "status"	INTEGER,
+                    "size"	INTEGER NOT NULL,
+                    "crc32"	INTEGER NOT NULL,
+                    "md5"	BLOB NOT NULL,
+                    "sha1"	BLOB NOT NULL,
+                    "sha256"	BLOB
+                );
+                INSERT INTO "new_roms" ("gid", "name", "status", "size", "crc32", "md5", "sha1", "sha256")
+                SELECT "gid", "name", "status", "size", "crc32", "md5", "sha1", "sha256" FROM "roms"
+                WHERE "gid" IN (SELECT "gid" FROM "games");
+                DROP TABLE "roms";
+                ALTER TABLE "new_roms" RENAME TO "roms";
+                CREATE TABLE "new_console_datafiles" (
+                    "console"	TEXT NOT NULL,
+                    "dfid"	INTEGER NOT NULL REFERENCES "datafiles"("dfid") ON DELETE CASCADE,
+                    "priority"	INTEGER NOT NULL DEFAULT 0,
+                    PRIMARY KEY("console","dfid")
+                );
+                INSERT INTO "new_console_datafiles" ("console", "dfid", "priority")
+                SELECT "console", "dfid", "priority" FROM "console_datafiles"
+                WHERE "dfid" IN (SELECT "dfid" FROM "datafiles");
+                DROP TABLE "console_datafiles";
+                ALTER TABLE "new_console_datafiles" RENAME TO "console_datafiles";
+                CREATE INDEX "game_category_index" ON "game_categories" (
+                    "gid"	DESC
+                );
+                CREATE INDEX "game_roms" ON "roms" (
+                    "gid"	DESC
+                );
+                CREATE INDEX "sha1_roms" ON "roms" (
+                    "sha1"	DESC
+                );
+                CREATE INDEX "game_names" ON "games" (
+                    "name"	DESC
+                );
+                CREATE INDEX "datafile_games" ON "games" (
+                    "dfid"	DESC
+                );
+            "#,
+        )
+        .ndl("Failed to add foreign keys to catalog DB")?;
+    transaction
+        .execute(RESOLVED_GAMES_VIEW, ())
+        .ndl("Failed to add foreign keys to catalog DB")?;
+    Ok(())
+}
+
+fn add_rom_hash_indexes(transaction: &Transaction) -> Result<()> {
+    transaction
+        .execute_batch(
+            r#"
+                CREATE INDEX "md5_roms" ON "roms" (
+                    "md5"	DESC
+                );
+                CREATE INDEX "size_crc_roms" ON "roms" (
+                    "size"	DESC,
+                    "crc32"	DESC
+                );
+            "#,
+        )
+        .ndl("Failed to add indexes to catalog DB")
+}
+
+fn add_rom_sha256_index(transaction: &Transaction) -> Result<()> {
+    transaction
+        .execute_batch(
+            r#"
+                CREATE INDEX "sha256_roms" ON "roms" (
+                    "sha256"	DESC
+                );
+            "#,
+        )
+        .ndl("Failed to add indexes to catalog DB")
+}
+
+fn add_game_serials_and_regions(transaction: &Transaction) -> Result<()> {
+    transaction
+        .execute_batch(
+            r#"
+                ALTER TABLE "games" ADD COLUMN "serial" TEXT;
+                ALTER TABLE "games" ADD COLUMN "region" TEXT;
+            "#,
+        )
+        .ndl("Failed to add serials and regions to catalog DB")?;
+    // serials only come with the next import, but regions can be read from the stored names
+    let mut select = transaction
+        .prepare(r#"SELECT "gid", "name" FROM "games""#)
+        .ndl("Failed to add serials and regions to catalog DB")?;
+    let mut update = transaction
+        .prepare(r#"UPDATE "games" SET "region" = ? WHERE "gid" = ?"#)
+        .ndl("Failed to add serials and regions to catalog DB")?;
+    let games = select
+        .query_map((), |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })
+        .ndl("Failed to add serials and regions to catalog DB")?;
+    for game in games {
+        let (gid, name) = game.ndl("Failed to add serials and regions to catalog DB")?;
+        if let Some(region) = parse_region(&name) {
+            update
+                .execute((region, gid))
+                .ndl("Failed to add serials and regions to catalog DB")?;
+        }
+    }
+    Ok(())
+}
+
+fn add_game_renames(transaction: &Transaction) -> Result<()> {
+    transaction
+        .execute_batch(
+            r#"
+                CREATE TABLE "game_renames" (
+                    "gid"	INTEGER NOT NULL REFERENCES "games"("gid") ON DELETE CASCADE,
+                    "old_name"	TEXT NOT NULL,
+                    "new_name"	TEXT NOT NULL,
+                    "renamed"	INTEGER NOT NULL
+                );
+                CREATE INDEX "game_renames_gid" ON "game_renames" (
+                    "gid"
+                );
+            "#,
+        )
+        .ndl("Failed to add rename history to catalog DB")
+}
+
+fn add_catalog_changes(transaction: &Transaction) -> Result<()> {
+    // renames are kept, as the changes of the datafiles they're in
+    transaction
+        .execute_batch(
+            r#"
+                CREATE TABLE "changes" (
+                    "id"	INTEGER NOT NULL,
+                    "dfid"	INTEGER NOT NULL REFERENCES "datafiles"("dfid") ON DELETE CASCADE,
+                    "version"	TEXT NOT NULL,
+                    "gid"	INTEGER NOT NULL,
+                    "kind"	TEXT NOT NULL,
+                    "game"	TEXT NOT NULL,
+                    "old_name"	TEXT,
+                    "rom"	TEXT,
+                    "old_sha1"	BLOB,
+                    "new_sha1"	BLOB,
+                    "changed"	INTEGER NOT NULL,
+                    PRIMARY KEY("id")
+                );
+                CREATE INDEX "changes_changed" ON "changes" (
+                    "changed"
+                );
+                INSERT INTO "changes" ("dfid", "version", "gid", "kind", "game", "old_name", "changed")
+                SELECT "games"."dfid", "datafiles"."version", "game_renames"."gid", 'renamed', "game_renames"."new_name", "game_renames"."old_name", "game_renames"."renamed"
+                FROM "game_renames"
+                JOIN "games" ON "games"."gid" = "game_renames"."gid"
+                JOIN "datafiles" ON "datafiles"."dfid" = "games"."dfid"
+                ORDER BY "game_renames"."renamed", "game_renames"."rowid";
+                DROP TABLE "game_renames";
+            "#,
+        )
+        .ndl("Failed to add change history to catalog DB")
+}
+
+fn add_conversion_sizes(transaction: &Transaction) -> Result<()> {
+    // dumps whose console isn't known are recorded under an empty console
+    transaction
+        .execute_batch(
+            r#"
+                CREATE TABLE "conversion_sizes" (
+                    "console"	TEXT NOT NULL,
+                    "format"	TEXT NOT NULL,
+                    "conversions"	INTEGER NOT NULL,
+                    "source_bytes"	INTEGER NOT NULL,
+                    "output_bytes"	INTEGER NOT NULL,
+                    PRIMARY KEY("console", "format")
+                );
+            "#,
+        )
+        .ndl("Failed to add conversion size history to catalog DB")
+}
+
+fn add_chd_verifications(transaction: &Transaction) -> Result<()> {
+    // CHDs are told apart by the SHA-1 of their data and metadata, which chdman writes in the header
+    transaction
+        .execute_batch(
+            r#"
+                CREATE TABLE "chd_verifications" (
+                    "sha1"	BLOB NOT NULL,
+                    "verified"	INTEGER NOT NULL,
+                    PRIMARY KEY("sha1")
+                );
+            "#,
+        )
+        .ndl("Failed to add CHD verification history to catalog DB")
+}
+
+fn add_chd_tracks(transaction: &Transaction) -> Result<()> {
+    // the tracks' SHA-1s are concatenated in order, keyed like "chd_verifications"
+    transaction
+        .execute_batch(
+            r#"
+                CREATE TABLE "chd_tracks" (
+                    "sha1"	BLOB NOT NULL,
+                    "tracks"	BLOB NOT NULL,
+                    PRIMARY KEY("sha1")
+                );
+            "#,
+        )
+        .ndl("Failed to add CHD track hashes to catalog DB")
+}
+
+/// Orphaned rows in the catalog DB, parents first so that repairs cascade
+const CATALOG_ORPHAN_CHECKS: [OrphanCheck; 5] = [
+    OrphanCheck {
+        table: "games",
+        condition: r#"NOT EXISTS (SELECT 1 FROM "datafiles" WHERE "datafiles"."dfid" = "games"."dfid")"#,
+    },
+    OrphanCheck {
+        table: "game_categories",
+        condition: r#"NOT EXISTS (SELECT 1 FROM "games" WHERE "games"."gid" = "game_categories"."gid")"#,
+    },
+    OrphanCheck {
+        table: "roms",
+        condition: r#"NOT EXISTS (SELECT 1 FROM "games" WHERE "games"."gid" = "roms"."gid")"#,
+    },
+    OrphanCheck {
+        table: "changes",
+        condition: r#"NOT EXISTS (SELECT 1 FROM "datafiles" WHERE "datafiles"."dfid" = "changes"."dfid")"#,
+    },
+    OrphanCheck {
+        table: "console_datafiles",
+        condition: r#"NOT EXISTS (SELECT 1 FROM "datafiles" WHERE "datafiles"."dfid" = "console_datafiles"."dfid")"#,
+    },
+];
+
 impl Drop for Catalog {
     fn drop(&mut self) {
-        self.connection.execute("VACUUM", ()).unwrap();
-        self.connection.execute("PRAGMA optimize;", ()).unwrap();
+        if let Some(checkpoint) = self.checkpoint {
+            close_database(&self.connection, "catalog", checkpoint);
+        }
     }
 }
 
 impl Catalog {
     pub fn init(path: &impl AsRef<Path>) -> Result<Catalog> {
-        let connection = Connection::open(path).ndl("Failed to open catalog DB")?;
+        let mut connection = Connection::open(path).ndl("Failed to open catalog DB")?;
         setup_database_default_config(&connection)?;
         debug!(
             r#"Opened Catalog database at "{}""#,
             path.as_ref().to_str().unwrap()
         );
-        // create missing tables and indexes
-        let tables = get_database_tables(&connection)?;
-        let indexes = get_database_indexes(&connection)?;
-        let mut changed = false;
-        if !tables.contains("datafiles") {
-            connection
-                .execute(
-                    r#"
-                        CREATE TABLE "datafiles" (
-                            "dfid"	INTEGER NOT NULL UNIQUE,
-                            "name"	TEXT NOT NULL UNIQUE,
-                            "author"    TEXT NOT NULL,
-                            "version"	TEXT NOT NULL,
-                            "last_updated"	INTEGER NOT NULL,
-                            PRIMARY KEY("dfid")
-                        )
-                    "#,
-                    (),
-                )
-                .ndl("Failed to create tables in catalog DB")?;
-            debug!("Created \"datafiles\" table");
-            changed = true;
-        }
-        if !tables.contains("games") {
-            connection
-                .execute(
-                    r#"
-                        CREATE TABLE "games" (
-                            "dfid"	INTEGER NOT NULL,
-                            "gid"	INTEGER NOT NULL UNIQUE,
-                            "name"	TEXT NOT NULL,
-                            "revision"	INTEGER NOT NULL DEFAULT 0,
-                            PRIMARY KEY("gid")
-                        )
-                    "#,
-                    (),
-                )
-                .ndl("Failed to create tables in catalog DB")?;
-            debug!("Created \"games\" table");
-            changed = true;
-        }
-        if !tables.contains("game_categories") {
-            connection
-                .execute(
-                    r#"
-                        CREATE TABLE "game_categories" (
-                            "gid"	INTEGER NOT NULL,
-                            "category"	INTEGER NOT NULL
-                        )
-                    "#,
-                    (),
-                )
-                .ndl("Failed to create tables in catalog DB")?;
-            debug!("Created \"game_categories\" table");
-            changed = true;
-        }
-        if !tables.contains("roms") {
-            connection
-                .execute(
-                    r#"
-                        CREATE TABLE "roms" (
-                            "gid"	INTEGER NOT NULL,
-                            "name"	TEXT NOT NULL,
-                            "status"	INTEGER,
-                            "size"	INTEGER NOT NULL,
-                            "crc32"	INTEGER NOT NULL,
-                            "md5"	BLOB NOT NULL,
-                            "sha1"	BLOB NOT NULL,
-                            "sha256"	BLOB
-                        )
-                    "#,
-                    (),
-                )
-                .ndl("Failed to create tables in catalog DB")?;
-            debug!("Created \"roms\" table");
-            changed = true;
-        }
-        if !indexes.contains_key("game_category_index") {
-            connection
-                .execute(
-                    r#"
-                        CREATE INDEX "game_category_index" ON "game_categories" (
-                            "gid"	DESC
-                        )
-                    "#,
-                    (),
-                )
-                .ndl("Failed to create tables in catalog DB")?;
-            debug!("Created \"game_category_index\" index");
-            changed = true;
-        }
-        if !indexes.contains_key("game_roms") {
-            connection
-                .execute(
-                    r#"
-                        CREATE INDEX "game_roms" ON "roms" (
-                            "gid"	DESC
-                        )
-                    "#,
-                    (),
-                )
-                .ndl("Failed to create tables in catalog DB")?;
-            debug!("Created \"game_roms\" index");
-            changed = true;
-        }
-        if !indexes.contains_key("sha1_roms") {
-            connection
-                .execute(
-                    r#"
-                        CREATE INDEX "sha1_roms" ON "roms" (
-                            "sha1"	DESC
-                        )
-                    "#,
-                    (),
-                )
-                .ndl("Failed to create tables in catalog DB")?;
-            debug!("Created \"sha1_roms\" index");
-            changed = true;
-        }
-        // optimize the database if the tables were changed
-        if changed {
+        // bring the schema up to date
+        if run_migrations(&mut connection, "catalog", &CATALOG_MIGRATIONS)? {
             connection
                 .execute("PRAGMA optimize;", ())
                 .ndl("Failed to optimize catalog DB")?;
             debug!("Optimized");
         }
+        // migrations rebuild tables, so this is only enabled once they're done
+        connection
+            .pragma_update(None, "foreign_keys", true)
+            .ndl("Failed to configure catalog DB")?;
         // return the database
+        Catalog::with_connection(connection, path, false)
+    }
+
+    /// Opens the catalog only to read it (see [crate::DumpManager::open_read_only])
+    ///
+    pub fn open_read_only(path: &impl AsRef<Path>) -> Result<Catalog> {
+        let connection = open_read_only_database(path.as_ref(), "catalog", &CATALOG_MIGRATIONS)?;
+        Catalog::with_connection(connection, path, true)
+    }
+
+    fn with_connection(
+        connection: Connection,
+        path: &impl AsRef<Path>,
+        read_only: bool,
+    ) -> Result<Catalog> {
         Ok(Catalog {
             connection,
+            reader: CatalogReader::open(path)?,
             dat_update_delay: TimeDelta::days(2),
+            download_concurrency: 3,
+            sources: vec![
+                Arc::new(NoIntroSource::new()),
+                Arc::new(RedumpSource::new()),
+            ],
+            local_datafiles: Vec::new(),
+            block_rules: Vec::new(),
+            forced_updates: Vec::new(),
+            checkpoint: (!read_only).then(CheckpointPolicy::default),
+            read_only,
         })
     }
 
-    pub fn is_rom(&self, sha1: [u8; 20]) -> Result<bool> {
-        let mut statement = self
-            .connection
-            .prepare_cached("SELECT EXISTS(SELECT 1 FROM roms WHERE sha1 = ? LIMIT 1)")
-            .ndl("Failed to check for ROM in catalog DB")?;
-        let result: i64 = statement
-            .query_one((sha1,), |f| Ok(f.get(0).unwrap()))
-            .ndl("Failed to check for ROM in catalog DB")?;
-        Ok(result == 1)
+    /// Sets how the write-ahead log is checkpointed (the catalog mustn't be read-only)
+    ///
+    pub fn with_checkpoint_policy(mut self, policy: CheckpointPolicy) -> Catalog {
+        apply_checkpoint_policy(&self.connection, "catalog", policy);
+        self.checkpoint = Some(policy);
+        self
+    }
+
+    pub fn with_update_delay(mut self, delay: TimeDelta) -> Catalog {
+        self.dat_update_delay = delay;
+        self
+    }
+
+    /// Replaces the sources with No-Intro and Redump sources which use these timeouts
+    ///
+    pub fn with_network_timeouts(mut self, timeouts: NetworkTimeouts) -> Catalog {
+        self.sources = vec![
+            Arc::new(NoIntroSource::with_timeouts(timeouts)),
+            Arc::new(RedumpSource::with_timeouts(timeouts)),
+        ];
+        self
+    }
+
+    /// Replaces the sources datafiles are downloaded from (No-Intro and Redump by default)
+    ///
+    pub fn with_datafile_sources(mut self, sources: Vec<Arc<dyn DatafileSource>>) -> Catalog {
+        self.sources = sources;
+        self
+    }
+
+    pub fn force_update(&mut self, target: UpdateTarget) {
+        self.forced_updates.push(target);
+    }
+
+    /// Checks whether data last updated at the given time should be updated again
+    ///
+    fn is_update_due(&self, last_updated: DateTime<Utc>, forced: bool) -> bool {
+        forced
+            || last_updated
+                .checked_add_signed(self.dat_update_delay)
+                .is_none_or(|due| Utc::now() >= due)
+    }
+
+    /// A read-only handle to the catalog, which can be shared with other threads
+    ///
+    pub fn reader(&self) -> CatalogReader {
+        self.reader
+            .clone()
+            .with_block_rules(self.block_rules.clone())
+    }
+
+    pub fn is_rom(&self, sha1: [u8; 20]) -> Result<Option<i64>> {
+        self.reader.is_rom(sha1)
+    }
+
+    pub fn is_rom_md5(&self, md5: [u8; 16]) -> Result<Option<i64>> {
+        self.reader.is_rom_md5(md5)
+    }
+
+    pub fn is_rom_size_crc(&self, size: usize, crc32: u32) -> Result<Option<i64>> {
+        self.reader.is_rom_size_crc(size, crc32)
+    }
+
+    pub fn is_rom_sha256(&self, sha256: [u8; 32]) -> Result<Option<i64>> {
+        self.reader.is_rom_sha256(sha256)
     }
 
+    /// Stores a datafile's games, returning whether any were added, changed, or removed
+    ///
     fn import_datafile_games<'a>(
         &mut self,
         datafile: &Datafile,
         xml: XMLDatafile<'a>,
-    ) -> Result<()> {
+    ) -> Result<bool> {
+        let start = Instant::now();
         let transaction = self
             .connection
             .transaction()
@@ -707,11 +1547,38 @@ impl Catalog {
         let mut stored_games: HashMap<String, Game> =
             datafile.get_all_games_unloaded(&transaction)?;
         debug!("Previously stored games: {}", stored_games.len());
+        // a first import only inserts, so it's faster to rebuild the indexes once at the end, unless
+        // the rest of the catalog is much bigger than the datafile
+        let deferred_indexes = if stored_games.is_empty()
+            && xml.approximate_rom_count() >= count_rows(&transaction, "roms")?
+        {
+            drop_indexes(&transaction, &["games", "game_categories", "roms"])?
+        } else {
+            Vec::new()
+        };
+        // once games are stored, new names wait until the rest are imported, as they may be renames
+        // of games missing from this version of the datafile
+        let first_import = stored_games.is_empty();
+        let mut added_games: Vec<Game> = Vec::new();
+        // a first import's games aren't changes, as there was nothing to change
+        let changes = ChangeLog::new(datafile.dfid, &datafile.name, &datafile.version);
+        let mut batches = GameRowBatches::new(&transaction)?;
         let mut unchanged_entries: usize = 0;
         let mut changed_entries: usize = 0;
         let mut new_entries: usize = 0;
+        let mut renamed_entries: usize = 0;
         let mut processed_games: HashSet<String> = HashSet::new();
-        for mut game_element in xml.parse_games::<Game>()? {
+        // games are parsed as they're imported, so the time spent parsing is split out
+        let mut parse_time = Duration::ZERO;
+        let mut game_elements = xml.parse_games::<Game>()?;
+        loop {
+            let parse_start = Instant::now();
+            let game_element = game_elements.next();
+            parse_time += parse_start.elapsed();
+            let Some(game_element) = game_element else {
+                break;
+            };
+            let mut game_element = game_element?;
             if processed_games.contains(&game_element.name) {
                 return Err(Error::new_original(format!(
                     "Failed to parse datafile\nDuplicate games were found: \"{}\"",
@@ -721,151 +1588,1226 @@ impl Catalog {
             let name = game_element.name.clone();
             if let Some(game) = stored_games.get_mut(&game_element.name) {
                 game.load(&transaction)?;
-                if game.update(&transaction, game_element)? {
+                let rom_changes = game.rom_changes(&game_element);
+                if game.update(&transaction, &mut batches, game_element)? {
                     changed_entries += 1;
                 } else {
                     unchanged_entries += 1;
                 }
+                for ROMChange {
+                    rom,
+                    old_sha1,
+                    new_sha1,
+                    ..
+                } in rom_changes
+                {
+                    changes.record(
+                        &transaction,
+                        &CatalogChange {
+                            rom: Some(rom),
+                            old_sha1,
+                            new_sha1,
+                            ..changes.change(game.gid.unwrap(), &name, ChangeKind::ROMChanged)
+                        },
+                    )?;
+                }
                 stored_games.remove(&name);
-            } else {
+            } else if first_import {
                 game_element.dfid = datafile.dfid;
-                game_element.insert(&transaction)?;
+                game_element.insert(&mut batches);
                 new_entries += 1;
+            } else {
+                game_element.dfid = datafile.dfid;
+                added_games.push(game_element);
             }
+            batches.flush_if_full(&transaction)?;
             processed_games.insert(name);
         }
         drop(processed_games);
+        // a missing game with exactly the same ROMs as a new one was renamed, unless several
+        // missing games have those ROMs
+        let mut missing_by_roms: HashMap<Vec<[u8; 20]>, Option<String>> = HashMap::new();
+        if !added_games.is_empty() {
+            for game in stored_games.values_mut() {
+                game.load(&transaction)?;
+                let rom_set = game.rom_set();
+                if !rom_set.is_empty() {
+                    missing_by_roms
+                        .entry(rom_set)
+                        .and_modify(|name| *name = None)
+                        .or_insert_with(|| Some(game.name.clone()));
+                }
+            }
+        }
+        // new games are handled in datafile order, so the first to claim a missing game renames it
+        for mut game_element in added_games {
+            let renamed = missing_by_roms
+                .get_mut(&game_element.rom_set())
+                .and_then(Option::take)
+                .and_then(|name| stored_games.remove(&name));
+            match renamed {
+                Some(mut game) => {
+                    debug!("Renamed \"{}\" to \"{}\"", game.name, game_element.name);
+                    let old_name = game.name.clone();
+                    game.rename(&transaction, &game_element.name)?;
+                    game.update(&transaction, &mut batches, game_element)?;
+                    changes.record(
+                        &transaction,
+                        &CatalogChange {
+                            old_name: Some(old_name),
+                            ..changes.change(game.gid.unwrap(), &game.name, ChangeKind::Renamed)
+                        },
+                    )?;
+                    renamed_entries += 1;
+                }
+                None => {
+                    game_element.insert(&mut batches);
+                    changes.record(
+                        &transaction,
+                        &changes.change(
+                            game_element.gid.unwrap(),
+                            &game_element.name,
+                            ChangeKind::Added,
+                        ),
+                    )?;
+                    new_entries += 1;
+                }
+            }
+            batches.flush_if_full(&transaction)?;
+        }
+        batches.flush(&transaction)?;
+        for sql in deferred_indexes {
+            transaction
+                .execute(&sql, ())
+                .ndl("Failed to rebuild indexes in catalog DB")?;
+        }
         // by this point, only games which exist in the database but not in this datafile will remain
         let removed_games = stored_games.len();
-        for (_, game) in stored_games {
+        let mut stored_games: Vec<Game> = stored_games.into_values().collect();
+        stored_games.sort_by_key(|game| game.gid);
+        for mut game in stored_games {
+            // the removed ROMs' hashes are kept, so dumps of them can be found
+            game.load(&transaction)?;
+            let removal = changes.change(game.gid.unwrap(), &game.name, ChangeKind::Removed);
+            let mut roms: Vec<&ROM> = game.roms.iter().collect();
+            roms.sort_by(|a, b| a.name.cmp(&b.name));
+            if roms.is_empty() {
+                changes.record(&transaction, &removal)?;
+            }
+            for rom in roms {
+                changes.record(
+                    &transaction,
+                    &CatalogChange {
+                        rom: Some(rom.name.clone()),
+                        old_sha1: Some(rom.sha1),
+                        ..removal.clone()
+                    },
+                )?;
+            }
             game.delete(&transaction)?;
         }
         transaction
             .commit()
             .ndl("Failed to commit changes to catalog DB")?;
+        timings::record(Stage::Parse, parse_time);
+        timings::record(Stage::Import, start.elapsed().saturating_sub(parse_time));
         debug!(
-            "Changed entries: {}\nUnchanged entries: {}\nAdded entries: {}\nRemoved entries: {}",
-            changed_entries, unchanged_entries, new_entries, removed_games
+            "Changed entries: {}\nUnchanged entries: {}\nAdded entries: {}\nRenamed entries: {}\nRemoved entries: {}",
+            changed_entries, unchanged_entries, new_entries, renamed_entries, removed_games
         );
-        Ok(())
+        Ok(changed_entries + new_entries + renamed_entries + removed_games > 0)
     }
 
-    fn oldest_nointro_datafile_time(&self) -> Result<DateTime<Utc>> {
+    /// The time the least recently updated datafile by `author` was updated
+    ///
+    fn oldest_datafile_time(&self, author: &Author) -> Result<DateTime<Utc>> {
         let mut statement = self
             .connection
             .prepare_cached("SELECT MIN(last_updated) FROM datafiles WHERE author = ?")
-            .ndl("Failed to optimize No-Intro requests")?;
+            .ndl("Failed to check datafiles for updates")?;
         match statement
-            .query_one(("No-Intro",), |row| Ok(row.get(0).unwrap()))
-            .ndl("Failed to optimize No-Intro requests")?
+            .query_one((author,), |row| Ok(row.get(0).unwrap()))
+            .ndl("Failed to check datafiles for updates")?
         {
             Some(timestamp) => Ok(DateTime::from_timestamp_millis(timestamp).unwrap()),
             None => Ok(DateTime::from_timestamp_millis(0).unwrap()),
         }
     }
 
-    fn update_nointro_console(
-        &mut self,
-        console: GameConsole,
-        agent: &Agent,
-        links: &HashMap<String, nointro::DatafileLink>,
-    ) -> Result<()> {
-        let datafile_name = console.nointro_datafile_name().unwrap();
-        let mut datafile = Datafile::get(&self.connection, datafile_name, &Author::NoIntro)?;
-        if Utc::now()
-            < datafile
-                .last_updated
-                .checked_add_signed(self.dat_update_delay)
-                .unwrap()
+    /// Checks whether a datafile offered by a source needs to be downloaded
+    ///
+    fn plan_update(
+        &self,
+        source: &Arc<dyn DatafileSource>,
+        available: AvailableDatafile,
+    ) -> Result<Option<UpdateJob>> {
+        let mut datafile = Datafile::get(
+            &self.connection,
+            &available.name,
+            &Author::from(source.author()),
+        )?;
+        datafile.assign_console(&self.connection, available.console, 0)?;
+        let forced = self
+            .forced_updates
+            .iter()
+            .any(|target| source.is_covered_by(target, available.console));
+        if !self.is_update_due(datafile.last_updated, forced) {
+            return Ok(None);
+        }
+        if available
+            .last_updated
+            .is_some_and(|last_updated| last_updated <= datafile.last_updated)
         {
-            return Ok(());
+            datafile.last_updated = Utc::now();
+            datafile.update(&self.connection)?;
+            debug!(
+                "Datafile \"{}\" is already up-to-date. Skipping...",
+                datafile.name
+            );
+            return Ok(None);
         }
-        let link = match links.get(datafile_name) {
-            Some(link) => link,
-            None => return Ok(()),
+        Ok(Some(UpdateJob {
+            datafile,
+            available,
+            source: source.clone(),
+        }))
+    }
+
+    /// Imports a datafile downloaded by an [UpdateJob], returning whether any of its games changed
+    ///
+    fn import_update(&mut self, job: UpdateJob, download: Option<FetchedDatafile>) -> Result<bool> {
+        let UpdateJob {
+            mut datafile,
+            available,
+            ..
+        } = job;
+        let download = match download {
+            Some(download) => download,
+            None => {
+                datafile.last_updated = Utc::now();
+                datafile.update(&self.connection)?;
+                debug!(
+                    "Datafile \"{}\" is already up-to-date. Skipping...",
+                    datafile.name
+                );
+                return Ok(false);
+            }
         };
-        if link.last_updated <= datafile.last_updated {
+        datafile.etag = download.etag;
+        datafile.last_modified = download.last_modified;
+        let (xml, header) = timings::time(Stage::Parse, || -> Result<_> {
+            let xml = logiqx::XMLDatafile::open(&download.content)?;
+            let header = xml.parse_header()?;
+            Ok((xml, header))
+        })?;
+        // without a time to compare, an unchanged version means an unchanged datafile
+        if available.last_updated.is_none() && datafile.version == header.version {
             datafile.last_updated = Utc::now();
             datafile.update(&self.connection)?;
-            debug!("Datafile \"{datafile_name}\" is already up-to-date. Skipping...");
-            return Ok(());
+            debug!(
+                "Datafile \"{}\" is already up-to-date. Skipping...",
+                datafile.name
+            );
+            return Ok(false);
         }
-        let url = match &link.link {
-            Some(url) => url,
-            None => return Ok(()),
-        };
-        let content = nointro::download_datafile(agent, url)?;
-        let xml = logiqx::XMLDatafile::open(&content)?;
-        let header = xml.parse_header()?;
-        datafile.version = header.version.to_string();
-        self.import_datafile_games(&datafile, xml)?;
+        datafile.version = header.version;
+        let changed = self.import_datafile_games(&datafile, xml)?;
         datafile.last_updated = Utc::now();
         datafile.update(&self.connection)?;
-        info!("Updated {} games", console.formal_name());
-        Ok(())
+        info!("Updated {} games", available.console.formal_name());
+        Ok(changed)
     }
 
-    fn update_redump_console(&mut self, console: GameConsole) -> Result<()> {
-        let datafile_name = console.redump_datafile_name().unwrap();
-        let mut datafile = Datafile::get(&self.connection, datafile_name, &Author::Redump)?;
-        if Utc::now()
-            < datafile
-                .last_updated
-                .checked_add_signed(self.dat_update_delay)
-                .unwrap()
-        {
+    /// Downloads the datafiles for each job concurrently, importing them as they arrive
+    ///
+    /// Returns the consoles whose games changed.
+    fn run_update_jobs(&mut self, jobs: VecDeque<UpdateJob>) -> Result<Vec<GameConsole>> {
+        let worker_count = self.download_concurrency.min(jobs.len());
+        let queue = Mutex::new(jobs);
+        let cancelled = AtomicBool::new(false);
+        let (sender, receiver) = mpsc::channel();
+        let warnings = WarningScope::current();
+        std::thread::scope(|scope| {
+            for _ in 0..worker_count {
+                let sender = sender.clone();
+                let (queue, cancelled) = (&queue, &cancelled);
+                let warnings = warnings.clone();
+                scope.spawn(move || {
+                    WarningScope::enter(warnings, || {
+                        while !cancelled.load(Ordering::Relaxed) {
+                            let job = match queue.lock().unwrap().pop_front() {
+                                Some(job) => job,
+                                None => break,
+                            };
+                            let download = job.download();
+                            if sender.send((job, download)).is_err() {
+                                break;
+                            }
+                        }
+                    })
+                });
+            }
+            drop(sender);
+            // import datafiles on this thread while the workers keep downloading
+            let mut changed_consoles = Vec::new();
+            for (job, download) in receiver {
+                let console = job.available.console;
+                match download.and_then(|download| self.import_update(job, download)) {
+                    Ok(true) => changed_consoles.push(console),
+                    Ok(false) => {}
+                    Err(err) => {
+                        cancelled.store(true, Ordering::Relaxed);
+                        return Err(err);
+                    }
+                }
+            }
+            Ok(changed_consoles)
+        })
+    }
+
+    /// Summarizes every datafile stored in the catalog
+    ///
+    pub fn datafile_info(&self) -> Result<Vec<DatafileInfo>> {
+        self.reader.datafile_info()
+    }
+
+    /// Lists the games datafiles have renamed since `since`, oldest first
+    ///
+    pub fn game_renames(&self, since: DateTime<Utc>) -> Result<Vec<GameRename>> {
+        Ok(self
+            .changes(since)?
+            .into_iter()
+            .filter(|change| change.kind == ChangeKind::Renamed)
+            .map(|change| GameRename {
+                gid: change.gid,
+                old_name: change.old_name.unwrap_or_default(),
+                new_name: change.game,
+                renamed: change.changed,
+            })
+            .collect())
+    }
+
+    /// Lists the changes datafile updates made since `since`, oldest first
+    ///
+    /// A datafile's first import isn't recorded, only its updates.
+    pub fn changes(&self, since: DateTime<Utc>) -> Result<Vec<CatalogChange>> {
+        changes_since(&self.connection, since)
+    }
+
+    /// Records how big a dump of `console` came out when it was converted to `format`, for
+    /// estimating later conversions (see [CatalogReader::conversion_ratio])
+    ///
+    /// Nothing is recorded if the catalog was opened read-only.
+    pub fn record_conversion(
+        &self,
+        console: Option<GameConsole>,
+        format: &str,
+        source_size: u64,
+        output_size: u64,
+    ) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        conversions::record_conversion(&self.connection, console, format, source_size, output_size)
+    }
+
+    /// Records that the CHD whose header has `sha1` passed `chdman verify` just now (see
+    /// [Catalog::last_chd_verification])
+    ///
+    /// Nothing is recorded if the catalog was opened read-only.
+    pub fn record_chd_verification(&self, sha1: [u8; 20]) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        verifications::record_chd_verification(&self.connection, sha1, Utc::now())
+    }
+
+    /// When the CHD whose header has `sha1` last passed `chdman verify`, or `None` if it never has
+    ///
+    pub fn last_chd_verification(&self, sha1: [u8; 20]) -> Result<Option<DateTime<Utc>>> {
+        verifications::last_chd_verification(&self.connection, sha1)
+    }
+
+    /// Records the SHA-1s of the tracks of the CHD whose header has `sha1`, so it needn't be
+    /// extracted again to identify it (see [Catalog::chd_tracks])
+    ///
+    /// Nothing is recorded if the catalog was opened read-only.
+    pub fn record_chd_tracks(&self, sha1: [u8; 20], tracks: &[[u8; 20]]) -> Result<()> {
+        if self.read_only {
             return Ok(());
         }
-        let content = redump::download_datafile(console.redump_slug().unwrap())?;
-        let xml = logiqx::XMLDatafile::open(&content)?;
-        let header = xml.parse_header()?;
-        if datafile.version == header.version {
+        verifications::record_chd_tracks(&self.connection, sha1, tracks)
+    }
+
+    /// The SHA-1s of the tracks of the CHD whose header has `sha1`, or `None` if they weren't
+    /// recorded
+    ///
+    pub fn chd_tracks(&self, sha1: [u8; 20]) -> Result<Option<Vec<[u8; 20]>>> {
+        verifications::chd_tracks(&self.connection, sha1)
+    }
+
+    pub fn add_local_datafile(&mut self, datafile: LocalDatafile) {
+        self.local_datafiles.push(datafile);
+    }
+
+    /// Re-imports any local datafiles which changed on disk since they were last imported
+    ///
+    /// Returns the consoles whose games changed.
+    fn update_local_datafiles(&mut self) -> Result<Vec<GameConsole>> {
+        // datafiles removed from the settings stop being sources, but their games are kept
+        self.connection
+            .execute(
+                "DELETE FROM console_datafiles WHERE dfid IN (SELECT dfid FROM datafiles WHERE author = ?)",
+                (Author::Local,),
+            )
+            .ndl("Failed to update local datafiles in catalog DB")?;
+        let mut changed_consoles = Vec::new();
+        for local in self.local_datafiles.clone() {
+            let path = local.path.to_str().unwrap();
+            let mut datafile = Datafile::get(&self.connection, path, &Author::Local)?;
+            datafile.assign_console(&self.connection, local.console, local.priority)?;
+            let modified: DateTime<Utc> = std::fs::metadata(&local.path)
+                .and_then(|metadata| metadata.modified())
+                .ndl(format!("Failed to read local datafile \"{path}\""))?
+                .into();
+            if modified <= datafile.last_updated {
+                continue;
+            }
+            let content = std::fs::read_to_string(&local.path)
+                .ndl(format!("Failed to read local datafile \"{path}\""))?;
+            let xml = timings::time(Stage::Parse, || -> Result<_> {
+                let xml = logiqx::XMLDatafile::open(&content)?;
+                datafile.version = xml.parse_header()?.version;
+                Ok(xml)
+            })?;
+            if self.import_datafile_games(&datafile, xml)? {
+                changed_consoles.push(local.console);
+            }
             datafile.last_updated = Utc::now();
             datafile.update(&self.connection)?;
-            debug!("Datafile \"{datafile_name}\" is already up-to-date. Skipping...");
-            return Ok(());
+            info!(
+                "Updated {} games from \"{path}\"",
+                local.console.formal_name()
+            );
+        }
+        Ok(changed_consoles)
+    }
+
+    /// Adds games of the user's own, replacing custom games with the same names
+    ///
+    /// A custom game can't share its name with one of the console's games from another datafile.
+    /// Returns the consoles whose games changed.
+    pub fn add_custom_games(&mut self, games: Vec<CustomGame>) -> Result<Vec<GameConsole>> {
+        let transaction = self
+            .connection
+            .transaction()
+            .ndl("Failed to start transaction in catalog DB")?;
+        let author = Author::Other(CUSTOM_AUTHOR.to_string());
+        let mut batches = GameRowBatches::new(&transaction)?;
+        let mut changed_consoles = Vec::new();
+        for custom in games {
+            if custom.name.trim().is_empty() || custom.roms.is_empty() {
+                return Err(Error::new_original(format!(
+                    "Failed to add custom game\n\"{}\" needs a name and at least one ROM",
+                    custom.name
+                ))
+                .with_category(ErrorCategory::InvalidInput));
+            }
+            let console = custom.console;
+            let mut datafile =
+                Datafile::get(&transaction, &custom_datafile_name(console), &author)?;
+            datafile.assign_console(&transaction, console, CUSTOM_PRIORITY)?;
+            let mut statement = transaction
+                .prepare_cached(
+                    "SELECT COUNT(*) FROM resolved_games WHERE console = ? AND name = ? AND dfid != ?",
+                )
+                .ndl("Failed to check for game in catalog DB")?;
+            let clashes: i64 = statement
+                .query_one(
+                    (console.formal_name(), &custom.name, datafile.dfid),
+                    |row| row.get(0),
+                )
+                .ndl("Failed to check for game in catalog DB")?;
+            drop(statement);
+            if clashes > 0 {
+                return Err(Error::new_original(format!(
+                    "Failed to add custom game\n{} already has a game named \"{}\"",
+                    console.formal_name(),
+                    custom.name
+                ))
+                .with_category(ErrorCategory::InvalidInput));
+            }
+            let game = custom.into_game(datafile.dfid);
+            let name = game.name.clone();
+            let changed = match datafile
+                .get_all_games_unloaded(&transaction)?
+                .remove(&game.name)
+            {
+                Some(mut stored) => {
+                    stored.load(&transaction)?;
+                    stored.update(&transaction, &mut batches, game)?
+                }
+                None => {
+                    let mut game = game;
+                    game.insert(&mut batches);
+                    true
+                }
+            };
+            // later games are looked up by name, so this one has to be stored by then
+            batches.flush(&transaction)?;
+            if changed {
+                info!("Added \"{name}\" to {}", datafile.name);
+                datafile.last_updated = Utc::now();
+                datafile.update(&transaction)?;
+                if !changed_consoles.contains(&console) {
+                    changed_consoles.push(console);
+                }
+            }
+        }
+        transaction
+            .commit()
+            .ndl("Failed to commit changes to catalog DB")?;
+        Ok(changed_consoles)
+    }
+
+    /// Removes a custom game, returning whether there was one by that name
+    ///
+    pub fn remove_custom_game(&mut self, console: GameConsole, name: &str) -> Result<bool> {
+        let removed = self
+            .connection
+            .execute(
+                "DELETE FROM games WHERE name = ? AND dfid = (SELECT dfid FROM datafiles WHERE name = ?)",
+                (name, custom_datafile_name(console)),
+            )
+            .ndl("Failed to remove custom game from catalog DB")?;
+        Ok(removed > 0)
+    }
+
+    pub fn add_block_rule(&mut self, rule: BlockRule) {
+        self.block_rules.push(rule);
+    }
+
+    /// Lists the names of a console's games, after resolving conflicts between its datafiles
+    ///
+    /// Blocked games are left out.
+    pub fn resolved_game_names(&self, console: GameConsole) -> Result<Vec<String>> {
+        self.reader().console_games(console)
+    }
+
+    /// Writes a datafile of a console's games, leaving out blocked games and the ROMs in `owned`
+    /// (by SHA-1)
+    ///
+    /// Games whose ROMs are all owned are left out entirely. Returns the number of games written.
+    pub fn write_fixdat(
+        &self,
+        console: GameConsole,
+        owned: &HashSet<[u8; 20]>,
+        output: impl Write,
+    ) -> Result<usize> {
+        self.write_collection_datafile(console, owned, CollectionPart::Missing, output)
+    }
+
+    /// Writes a datafile of a console's games with just their missing or owned ROMs (by SHA-1),
+    /// leaving out blocked games and games with none of those ROMs
+    ///
+    /// Returns the number of games written.
+    pub fn write_collection_datafile(
+        &self,
+        console: GameConsole,
+        owned: &HashSet<[u8; 20]>,
+        part: CollectionPart,
+        output: impl Write,
+    ) -> Result<usize> {
+        let (name, description) = match part {
+            CollectionPart::Missing => ("Fixdat", "missing from"),
+            CollectionPart::Owned => ("Have", "in"),
+        };
+        let header = Header {
+            name: format!("{} - {name}", console.formal_name()),
+            description: format!(
+                "{} games {description} the collection",
+                console.formal_name()
+            ),
+            version: Utc::now().format("%Y%m%d-%H%M%S").to_string(),
+            homepage: String::new(),
+        };
+        let mut writer = XMLDatafileWriter::new(output, &header)?;
+        let mut written_games = 0;
+        self.for_each_resolved_game(console, |mut game| {
+            game.roms
+                .retain(|rom| owned.contains(&rom.sha1) == (part == CollectionPart::Owned));
+            if !game.roms.is_empty() {
+                writer.write_game(&game)?;
+                written_games += 1;
+            }
+            Ok(())
+        })?;
+        writer.finish()?;
+        Ok(written_games)
+    }
+
+    /// Lists the names of a console's games which are missing any of their ROMs, or have all of
+    /// them, given the owned ROMs (by SHA-1)
+    ///
+    /// Blocked games are left out.
+    pub fn collection_game_names(
+        &self,
+        console: GameConsole,
+        owned: &HashSet<[u8; 20]>,
+        part: CollectionPart,
+    ) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        self.for_each_resolved_game(console, |game| {
+            let complete = game.roms.iter().all(|rom| owned.contains(&rom.sha1));
+            if complete == (part == CollectionPart::Owned) {
+                names.push(game.name);
+            }
+            Ok(())
+        })?;
+        Ok(names)
+    }
+
+    /// Calls `visit` with each of a console's unblocked games, with its ROMs, in order of name
+    ///
+    fn for_each_resolved_game(
+        &self,
+        console: GameConsole,
+        mut visit: impl FnMut(Game) -> Result<()>,
+    ) -> Result<()> {
+        let reader = self.reader();
+        let mut statement = self
+            .connection
+            .prepare_cached(
+                r#"
+                    SELECT "r"."dfid", "r"."gid", "r"."name", "r"."revision", "g"."serial", "g"."region"
+                    FROM "resolved_games" AS "r"
+                    JOIN "games" AS "g" ON "g"."gid" = "r"."gid"
+                    WHERE "r"."console" = ?
+                    ORDER BY "r"."name"
+                "#,
+            )
+            .ndl("Failed to retrieve games from catalog DB")?;
+        let games = statement
+            .query_map((console.formal_name(),), |row| {
+                Ok(Game {
+                    dfid: row.get(0)?,
+                    gid: Some(row.get(1)?),
+                    name: row.get(2)?,
+                    categories: HashSet::new(),
+                    roms: HashSet::new(),
+                    revision: row.get(3)?,
+                    serial: row.get(4)?,
+                    region: row.get(5)?,
+                    loaded: false,
+                })
+            })
+            .ndl("Failed to retrieve games from catalog DB")?;
+        for game in games {
+            let mut game = game.ndl("Failed to retrieve games from catalog DB")?;
+            if reader.is_blocked(game.gid.unwrap(), &game.name) {
+                continue;
+            }
+            game.load(&self.connection)?;
+            visit(game)?;
         }
-        datafile.version = header.version.to_string();
-        self.import_datafile_games(&datafile, xml)?;
-        datafile.last_updated = Utc::now();
-        datafile.update(&self.connection)?;
-        info!("Updated {} games", console.formal_name());
         Ok(())
     }
 
-    pub fn update_all_consoles(&mut self) -> Result<()> {
-        if Utc::now()
-            >= self
-                .oldest_nointro_datafile_time()?
-                .checked_add_signed(self.dat_update_delay)
-                .unwrap()
+    pub fn check(&mut self, repair: bool) -> Result<DatabaseCheck> {
+        check_database(
+            &mut self.connection,
+            "catalog",
+            &CATALOG_ORPHAN_CHECKS,
+            repair,
+        )
+    }
+
+    pub fn schema(&self) -> Result<DatabaseSchema> {
+        database_schema(&self.connection, "catalog")
+    }
+
+    pub fn backup(&self, to: &Path) -> Result<()> {
+        backup_database(&self.connection, "catalog", to)
+    }
+
+    pub fn maintain(&self) -> Result<DatabaseMaintenance> {
+        maintain_database(&self.connection, "catalog")
+    }
+
+    pub fn last_maintained(&self) -> Result<Option<DateTime<Utc>>> {
+        last_maintained(&self.connection, "catalog")
+    }
+
+    /// Updates every datafile which is due, returning the consoles whose games changed
+    ///
+    pub fn update_all_consoles(&mut self) -> Result<Vec<GameConsole>> {
+        let mut jobs = VecDeque::new();
+        for source in self.sources.clone() {
+            let consoles = source.consoles();
+            let forced = self.forced_updates.iter().any(|target| {
+                consoles
+                    .iter()
+                    .any(|console| source.is_covered_by(target, *console))
+            });
+            // listing datafiles can mean loading web pages, so skip it while they're all recent
+            let oldest = self.oldest_datafile_time(&Author::from(source.author()))?;
+            if !self.is_update_due(oldest, forced) {
+                continue;
+            }
+            for available in source.list_datafiles()? {
+                jobs.extend(self.plan_update(&source, available)?);
+            }
+        }
+        let mut changed_consoles = self.run_update_jobs(jobs)?;
+        changed_consoles.extend(self.update_local_datafiles()?);
+        let mut seen = HashSet::new();
+        changed_consoles.retain(|console| seen.insert(*console));
+        Ok(changed_consoles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_unversioned_catalog() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("catalog.db");
         {
-            let agent = agent();
-            let no_intro_links = nointro::load_datafile_links(&agent)?;
-            self.update_nointro_console(GameConsole::GB, &agent, &no_intro_links)?;
-            self.update_redump_console(GameConsole::Dreamcast)?;
-            self.update_redump_console(GameConsole::GameCube)?;
-            self.update_nointro_console(GameConsole::GBC, &agent, &no_intro_links)?;
-            self.update_redump_console(GameConsole::PSX)?;
-            self.update_redump_console(GameConsole::PS2)?;
-            self.update_nointro_console(GameConsole::GBA, &agent, &no_intro_links)?;
-            self.update_redump_console(GameConsole::PS3)?;
-            self.update_redump_console(GameConsole::PSP)?;
-            self.update_nointro_console(GameConsole::N64, &agent, &no_intro_links)?;
-            self.update_redump_console(GameConsole::Wii)?;
-            self.update_redump_console(GameConsole::Xbox)?;
-            self.update_redump_console(GameConsole::Xbox360)?;
-        } else {
-            self.update_redump_console(GameConsole::Dreamcast)?;
-            self.update_redump_console(GameConsole::GameCube)?;
-            self.update_redump_console(GameConsole::PSX)?;
-            self.update_redump_console(GameConsole::PS2)?;
-            self.update_redump_console(GameConsole::PS3)?;
-            self.update_redump_console(GameConsole::PSP)?;
-            self.update_redump_console(GameConsole::Wii)?;
-            self.update_redump_console(GameConsole::Xbox)?;
-            self.update_redump_console(GameConsole::Xbox360)?;
+            // the schema from before versioning, without cache validators
+            let connection = Connection::open(&path).unwrap();
+            connection
+                .execute_batch(
+                    r#"
+                        CREATE TABLE "datafiles" (
+                            "dfid"	INTEGER NOT NULL UNIQUE,
+                            "name"	TEXT NOT NULL UNIQUE,
+                            "author"    TEXT NOT NULL,
+                            "version"	TEXT NOT NULL,
+                            "last_updated"	INTEGER NOT NULL,
+                            PRIMARY KEY("dfid")
+                        );
+                        INSERT INTO "datafiles" VALUES (1, 'Sony - PlayStation', 'Redump', '2024', 0);
+                    "#,
+                )
+                .unwrap();
         }
-        Ok(())
+        let catalog = Catalog::init(&path).unwrap();
+        assert_eq!(
+            schema_version(&catalog.connection).unwrap(),
+            CATALOG_MIGRATIONS.len()
+        );
+        let columns = get_table_columns(&catalog.connection, "datafiles").unwrap();
+        assert!(columns.contains("etag") && columns.contains("last_modified"));
+        let name: String = catalog
+            .connection
+            .query_row(
+                r#"SELECT "name" FROM "datafiles" WHERE "dfid" = 1"#,
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(name, "Sony - PlayStation");
+    }
+
+    #[test]
+    fn estimates_conversions_from_history() {
+        let directory = tempfile::tempdir().unwrap();
+        let catalog = Catalog::init(&directory.path().join("catalog.db")).unwrap();
+        let ratio =
+            |console| conversions::conversion_ratio(&catalog.connection, console, "chd").unwrap();
+        assert_eq!(ratio(Some(GameConsole::PSX)), None);
+        conversions::record_conversion(&catalog.connection, None, "chd", 1000, 800).unwrap();
+        // until a console has enough conversions of its own, every console's are used
+        for _ in 0..2 {
+            conversions::record_conversion(
+                &catalog.connection,
+                Some(GameConsole::PSX),
+                "chd",
+                1000,
+                200,
+            )
+            .unwrap();
+        }
+        assert_eq!(ratio(Some(GameConsole::PSX)), Some(0.4));
+        conversions::record_conversion(
+            &catalog.connection,
+            Some(GameConsole::PSX),
+            "chd",
+            1000,
+            200,
+        )
+        .unwrap();
+        assert_eq!(ratio(Some(GameConsole::PSX)), Some(0.2));
+        assert_eq!(ratio(None), Some(0.35));
+    }
+
+    #[test]
+    fn records_chd_verifications() {
+        let directory = tempfile::tempdir().unwrap();
+        let catalog = Catalog::init(&directory.path().join("catalog.db")).unwrap();
+        assert_eq!(catalog.last_chd_verification([1; 20]).unwrap(), None);
+        let before = Utc::now() - TimeDelta::seconds(1);
+        catalog.record_chd_verification([1; 20]).unwrap();
+        let verified = catalog.last_chd_verification([1; 20]).unwrap().unwrap();
+        assert!(verified >= before && verified <= Utc::now());
+        assert_eq!(catalog.last_chd_verification([2; 20]).unwrap(), None);
+    }
+
+    #[test]
+    fn records_chd_tracks() {
+        let directory = tempfile::tempdir().unwrap();
+        let catalog = Catalog::init(&directory.path().join("catalog.db")).unwrap();
+        assert_eq!(catalog.chd_tracks([1; 20]).unwrap(), None);
+        catalog
+            .record_chd_tracks([1; 20], &[[2; 20], [3; 20]])
+            .unwrap();
+        assert_eq!(
+            catalog.chd_tracks([1; 20]).unwrap(),
+            Some(vec![[2; 20], [3; 20]])
+        );
+        // a CHD rewritten with the same data keeps its header's SHA-1, so it's simply replaced
+        catalog.record_chd_tracks([1; 20], &[[4; 20]]).unwrap();
+        assert_eq!(catalog.chd_tracks([1; 20]).unwrap(), Some(vec![[4; 20]]));
+        assert_eq!(catalog.chd_tracks([2; 20]).unwrap(), None);
+    }
+
+    #[test]
+    fn records_nothing_when_read_only() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("catalog.db");
+        drop(Catalog::init(&path).unwrap());
+        let catalog = Catalog::open_read_only(&path).unwrap();
+        assert!(catalog.read_only);
+        catalog.record_chd_verification([1; 20]).unwrap();
+        catalog.record_chd_tracks([1; 20], &[[2; 20]]).unwrap();
+        catalog.record_conversion(None, "chd", 1000, 800).unwrap();
+        assert_eq!(catalog.last_chd_verification([1; 20]).unwrap(), None);
+        assert_eq!(catalog.chd_tracks([1; 20]).unwrap(), None);
+    }
+
+    #[test]
+    fn deleting_datafile_cascades() {
+        let directory = tempfile::tempdir().unwrap();
+        let catalog = Catalog::init(&directory.path().join("catalog.db")).unwrap();
+        catalog
+            .connection
+            .execute_batch(
+                r#"
+                    INSERT INTO "datafiles" ("dfid", "name", "author", "version", "last_updated")
+                    VALUES (1, 'Sony - PlayStation', 'Redump', '2024', 0);
+                    INSERT INTO "games" ("dfid", "gid", "name", "revision") VALUES (1, 1, 'Game', 0);
+                    INSERT INTO "game_categories" VALUES (1, 0);
+                    INSERT INTO "roms" VALUES (1, 'Game.bin', NULL, 1, 0, x'00', x'00', NULL);
+                    INSERT INTO "console_datafiles" VALUES ('psx', 1, 0);
+                    DELETE FROM "datafiles" WHERE "dfid" = 1;
+                "#,
+            )
+            .unwrap();
+        for table in ["games", "game_categories", "roms", "console_datafiles"] {
+            let count: i64 = catalog
+                .connection
+                .query_row(&format!(r#"SELECT COUNT(*) FROM "{table}""#), (), |row| {
+                    row.get(0)
+                })
+                .unwrap();
+            assert_eq!(count, 0, "{table} wasn't cleared");
+        }
+    }
+
+    #[test]
+    fn adds_custom_games() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut catalog = Catalog::init(&directory.path().join("catalog.db")).unwrap();
+        catalog
+            .connection
+            .execute_batch(
+                r#"
+                    INSERT INTO "datafiles" ("dfid", "name", "author", "version", "last_updated")
+                    VALUES (1, 'Nintendo - Super Nintendo Entertainment System', 'No-Intro', '2024', 0);
+                    INSERT INTO "games" ("dfid", "gid", "name", "revision") VALUES (1, 1, 'Game (USA)', 0);
+                    INSERT INTO "console_datafiles" VALUES ('Super Nintendo Entertainment System', 1, 0);
+                "#,
+            )
+            .unwrap();
+        let rom_path = directory.path().join("Game (USA) (T-En).sfc");
+        std::fs::write(&rom_path, b"test").unwrap();
+        let rom = CustomROM::of_file(&rom_path).unwrap();
+        assert_eq!(rom.size, 4);
+        let game = |name: &str, rom: CustomROM| CustomGame {
+            console: GameConsole::SNES,
+            name: name.to_string(),
+            roms: vec![rom],
+        };
+        let changed = catalog
+            .add_custom_games(vec![game("Game (USA) (T-En)", rom.clone())])
+            .unwrap();
+        assert_eq!(changed, vec![GameConsole::SNES]);
+        let (entry, rom_name) = catalog.reader().find_rom_game(rom.sha1).unwrap().unwrap();
+        assert_eq!(entry.name, "Game (USA) (T-En)");
+        assert_eq!(
+            entry.datafile,
+            "Custom - Super Nintendo Entertainment System"
+        );
+        assert_eq!(rom_name, "Game (USA) (T-En).sfc");
+        // adding it again changes nothing, and official games' names are taken
+        let unchanged = catalog
+            .add_custom_games(vec![game("Game (USA) (T-En)", rom.clone())])
+            .unwrap();
+        assert!(unchanged.is_empty());
+        let err = catalog
+            .add_custom_games(vec![game("Game (USA)", rom.clone())])
+            .unwrap_err();
+        assert_eq!(err.category(), ErrorCategory::InvalidInput);
+
+        assert!(
+            catalog
+                .remove_custom_game(GameConsole::SNES, "Game (USA) (T-En)")
+                .unwrap()
+        );
+        assert_eq!(catalog.is_rom(rom.sha1).unwrap(), None);
+        assert!(
+            !catalog
+                .remove_custom_game(GameConsole::SNES, "Game (USA) (T-En)")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn updates_from_injected_source() {
+        let directory = tempfile::tempdir().unwrap();
+        let datafile_path = directory.path().join("psx.dat");
+        std::fs::write(
+            &datafile_path,
+            r#"<?xml version="1.0"?>
+<datafile>
+<header><name>Test</name><description>Test</description><version>1</version><date>2024</date><author>Test</author><homepage>Test</homepage><url>Test</url></header>
+<game name="Game (USA)"><category>Games</category><description>Game (USA)</description><serial>SLUS-00001</serial>
+<rom name="Game (USA).bin" size="4" crc="d87f7e0c" md5="098f6bcd4621d373cade4e832627b4f6" sha1="a94a8fe5ccb19ba61c4c0875a8b50a1b0c2f8a05"/>
+</game>
+</datafile>
+"#,
+        )
+        .unwrap();
+        let source = FileDatafileSource::new("Test").with_datafile(
+            GameConsole::PSX,
+            "Sony - PlayStation",
+            &datafile_path,
+        );
+        let mut catalog = Catalog::init(&directory.path().join("catalog.db"))
+            .unwrap()
+            .with_datafile_sources(vec![Arc::new(source)]);
+        assert_eq!(
+            catalog.update_all_consoles().unwrap(),
+            vec![GameConsole::PSX]
+        );
+        assert_eq!(
+            catalog.resolved_game_names(GameConsole::PSX).unwrap(),
+            vec!["Game (USA)".to_string()]
+        );
+        let games = catalog.reader().search_games("Game*").unwrap();
+        assert_eq!(games[0].serial.as_deref(), Some("SLUS-00001"));
+        assert_eq!(games[0].region.as_deref(), Some("USA"));
+        let sha1 = hex::decode("a94a8fe5ccb19ba61c4c0875a8b50a1b0c2f8a05").unwrap();
+        assert!(catalog.is_rom(sha1.try_into().unwrap()).unwrap().is_some());
+        let info = catalog.datafile_info().unwrap();
+        assert_eq!(info.len(), 1);
+        assert_eq!(
+            (info[0].author.as_str(), info[0].version.as_str()),
+            ("Test", "1")
+        );
+        // nothing is due again until the update delay passes
+        assert!(catalog.update_all_consoles().unwrap().is_empty());
+    }
+
+    #[test]
+    fn keeps_gids_of_renamed_games() {
+        let directory = tempfile::tempdir().unwrap();
+        let datafile_path = directory.path().join("psx.dat");
+        let write_datafile = |version: &str, games: &str| {
+            std::fs::write(
+                &datafile_path,
+                format!(
+                    r#"<?xml version="1.0"?>
+<datafile>
+<header><name>Test</name><description>Test</description><version>{version}</version><date>2024</date><author>Test</author><homepage>Test</homepage><url>Test</url></header>
+{games}
+</datafile>
+"#
+                ),
+            )
+            .unwrap();
+        };
+        write_datafile(
+            "1",
+            r#"<game name="Game (USA)"><description>Game (USA)</description>
+<rom name="Game (USA).bin" size="4" crc="d87f7e0c" md5="098f6bcd4621d373cade4e832627b4f6" sha1="a94a8fe5ccb19ba61c4c0875a8b50a1b0c2f8a05"/>
+</game>"#,
+        );
+        let source = FileDatafileSource::new("Test").with_datafile(
+            GameConsole::PSX,
+            "Sony - PlayStation",
+            &datafile_path,
+        );
+        let mut catalog = Catalog::init(&directory.path().join("catalog.db"))
+            .unwrap()
+            .with_update_delay(TimeDelta::zero())
+            .with_datafile_sources(vec![Arc::new(source)]);
+        let started = Utc::now();
+        catalog.update_all_consoles().unwrap();
+        let gid = catalog.reader().search_games("Game*").unwrap()[0].gid;
+        write_datafile(
+            "2",
+            r#"<game name="Game (USA) (Rev 1)"><description>Game (USA) (Rev 1)</description>
+<rom name="Game (USA) (Rev 1).bin" size="4" crc="d87f7e0c" md5="098f6bcd4621d373cade4e832627b4f6" sha1="a94a8fe5ccb19ba61c4c0875a8b50a1b0c2f8a05"/>
+</game>
+<game name="Other Game (USA)"><description>Other Game (USA)</description>
+<rom name="Other Game (USA).bin" size="3" crc="8c736521" md5="acbd18db4cc2f85cedef654fccc4a4d8" sha1="0beec7b5ea3f0fdbc95d0dd47f3c5bc275da8a33"/>
+</game>"#,
+        );
+        // the datafile's only imported again if it looks newer than the last import
+        std::fs::File::options()
+            .write(true)
+            .open(&datafile_path)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        catalog.update_all_consoles().unwrap();
+        assert_eq!(
+            catalog.resolved_game_names(GameConsole::PSX).unwrap(),
+            vec![
+                "Game (USA) (Rev 1)".to_string(),
+                "Other Game (USA)".to_string()
+            ]
+        );
+        let games = catalog.reader().search_games("Game*").unwrap();
+        assert_eq!(games[0].gid, gid);
+        assert_eq!(games[0].name, "Game (USA) (Rev 1)");
+        let renames = catalog.game_renames(started).unwrap();
+        assert_eq!(renames.len(), 1);
+        assert_eq!(renames[0].gid, gid);
+        assert_eq!(renames[0].old_name, "Game (USA)");
+        assert_eq!(renames[0].new_name, "Game (USA) (Rev 1)");
+        // the first import isn't a change, only the update
+        let changes: Vec<(ChangeKind, String)> = catalog
+            .changes(started)
+            .unwrap()
+            .into_iter()
+            .map(|change| (change.kind, change.game))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (ChangeKind::Renamed, "Game (USA) (Rev 1)".to_string()),
+                (ChangeKind::Added, "Other Game (USA)".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn looks_up_bad_dumps() {
+        let directory = tempfile::tempdir().unwrap();
+        let datafile_path = directory.path().join("psx.dat");
+        std::fs::write(
+            &datafile_path,
+            r#"<?xml version="1.0"?>
+<datafile>
+<header><name>Test</name><description>Test</description><version>1</version><date>2024</date><author>Test</author><homepage>Test</homepage><url>Test</url></header>
+<game name="Game (USA)"><description>Game (USA)</description>
+<rom name="Game (USA).bin" size="4" crc="d87f7e0c" md5="098f6bcd4621d373cade4e832627b4f6" sha1="a94a8fe5ccb19ba61c4c0875a8b50a1b0c2f8a05" status="baddump"/>
+</game>
+<game name="Other Game (USA)"><description>Other Game (USA)</description>
+<rom name="Other Game (USA).bin" size="3" crc="8c736521" md5="acbd18db4cc2f85cedef654fccc4a4d8" sha1="0beec7b5ea3f0fdbc95d0dd47f3c5bc275da8a33" status="verified"/>
+</game>
+</datafile>
+"#,
+        )
+        .unwrap();
+        let source = FileDatafileSource::new("Test").with_datafile(
+            GameConsole::PSX,
+            "Sony - PlayStation",
+            &datafile_path,
+        );
+        let mut catalog = Catalog::init(&directory.path().join("catalog.db"))
+            .unwrap()
+            .with_datafile_sources(vec![Arc::new(source)]);
+        catalog.update_all_consoles().unwrap();
+        let sha1 = |hex: &str| -> [u8; 20] { hex::decode(hex).unwrap().try_into().unwrap() };
+        let reader = catalog.reader();
+        assert_eq!(
+            reader
+                .rom_dump_status(sha1("a94a8fe5ccb19ba61c4c0875a8b50a1b0c2f8a05"))
+                .unwrap(),
+            Some("baddump")
+        );
+        assert_eq!(
+            reader
+                .rom_dump_status(sha1("0beec7b5ea3f0fdbc95d0dd47f3c5bc275da8a33"))
+                .unwrap(),
+            Some("verified")
+        );
+        assert_eq!(reader.rom_dump_status([0; 20]).unwrap(), None);
+    }
+
+    #[test]
+    fn finds_rom_names_by_datafile_priority() {
+        let directory = tempfile::tempdir().unwrap();
+        let catalog = Catalog::init(&directory.path().join("catalog.db")).unwrap();
+        // the custom datafile was added first, but the official one takes precedence
+        catalog
+            .connection
+            .execute_batch(
+                r#"
+                    INSERT INTO "datafiles" ("dfid", "name", "author", "version", "last_updated")
+                    VALUES (1, 'Custom - PlayStation', 'Custom', '1', 0),
+                        (2, 'Sony - PlayStation', 'Redump', '2024', 0);
+                    INSERT INTO "games" ("dfid", "gid", "name", "revision")
+                    VALUES (1, 1, 'Game (USA) (Custom)', 0), (2, 2, 'Game (USA)', 0);
+                    INSERT INTO "roms" ("gid", "name", "size", "crc32", "md5", "sha1")
+                    VALUES (1, '$b', 4, 0, x'00', x'a94a8fe5ccb19ba61c4c0875a8b50a1b0c2f8a05'),
+                        (2, '$b', 4, 0, x'00', x'a94a8fe5ccb19ba61c4c0875a8b50a1b0c2f8a05');
+                    INSERT INTO "console_datafiles" VALUES ('PlayStation', 1, 100), ('PlayStation', 2, 0);
+                "#,
+            )
+            .unwrap();
+        let sha1 = hex::decode("a94a8fe5ccb19ba61c4c0875a8b50a1b0c2f8a05").unwrap();
+        assert_eq!(
+            catalog
+                .reader()
+                .find_rom_name(sha1.try_into().unwrap())
+                .unwrap(),
+            Some(("Game (USA)".to_string(), "Game (USA).bin".to_string()))
+        );
+    }
+
+    #[test]
+    fn repairing_fixes_foreign_key_problems() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut catalog = Catalog::init(&directory.path().join("catalog.db")).unwrap();
+        catalog
+            .connection
+            .execute_batch(
+                r#"
+                    PRAGMA foreign_keys = OFF;
+                    INSERT INTO "roms" ("gid", "name", "size", "crc32", "md5", "sha1")
+                    VALUES (99, 'Game.bin', 4, 0, x'00', x'00');
+                    PRAGMA foreign_keys = ON;
+                "#,
+            )
+            .unwrap();
+        let check = catalog.check(false).unwrap();
+        assert_eq!(check.problems.len(), 1);
+        assert_eq!(check.remaining_problems, check.problems);
+        assert_eq!(check.orphaned_rows, vec![("roms".to_string(), 1)]);
+        let check = catalog.check(true).unwrap();
+        assert_eq!(check.problems.len(), 1);
+        assert!(check.remaining_problems.is_empty());
+        assert!(catalog.check(false).unwrap().is_healthy());
+    }
+
+    #[test]
+    fn parses_region_tags() {
+        assert_eq!(
+            parse_region("Crash Bandicoot (USA) (Rev 1)").as_deref(),
+            Some("USA")
+        );
+        assert_eq!(
+            parse_region("Tekken 3 (Japan, Asia) (Demo)").as_deref(),
+            Some("Japan, Asia")
+        );
+        // language and disc tags aren't regions
+        assert_eq!(
+            parse_region("Game (En,Fr,De) (Europe) (Disc 1)").as_deref(),
+            Some("Europe")
+        );
+        assert_eq!(parse_region("Game (Beta)"), None);
+    }
+
+    #[test]
+    fn writes_fixdat_of_missing_games() {
+        let directory = tempfile::tempdir().unwrap();
+        let datafile_path = directory.path().join("psx.dat");
+        std::fs::write(
+            &datafile_path,
+            r#"<?xml version="1.0"?>
+<datafile>
+<header><name>Test</name><description>Test</description><version>1</version><date>2024</date><author>Test</author><homepage>Test</homepage><url>Test</url></header>
+<game name="Game (USA)"><category>Games</category><description>Game (USA)</description>
+<rom name="Game (USA).bin" size="4" crc="d87f7e0c" md5="098f6bcd4621d373cade4e832627b4f6" sha1="a94a8fe5ccb19ba61c4c0875a8b50a1b0c2f8a05"/>
+</game>
+<game name="Other &amp; Game (Japan)"><category>Demos</category><description>Other &amp; Game (Japan)</description>
+<rom name="Other &amp; Game (Japan).bin" size="3" crc="352441c2" md5="900150983cd24fb0d6963f7d28e17f72" sha1="a9993e364706816aba3e25717850c26c9cd0d89d" status="verified"/>
+</game>
+</datafile>
+"#,
+        )
+        .unwrap();
+        let source = FileDatafileSource::new("Test").with_datafile(
+            GameConsole::PSX,
+            "Sony - PlayStation",
+            &datafile_path,
+        );
+        let mut catalog = Catalog::init(&directory.path().join("catalog.db"))
+            .unwrap()
+            .with_datafile_sources(vec![Arc::new(source)]);
+        catalog.update_all_consoles().unwrap();
+        let owned: [u8; 20] = hex::decode("a94a8fe5ccb19ba61c4c0875a8b50a1b0c2f8a05")
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let mut output = Vec::new();
+        assert_eq!(
+            catalog
+                .write_fixdat(GameConsole::PSX, &HashSet::from([owned]), &mut output)
+                .unwrap(),
+            1
+        );
+        // the fixdat can be read back like any other datafile
+        let content = String::from_utf8(output).unwrap();
+        let xml = XMLDatafile::open(&content).unwrap();
+        assert_eq!(xml.parse_header().unwrap().name, "PlayStation - Fixdat");
+        let games: Vec<Game> = xml
+            .parse_games::<Game>()
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].name, "Other & Game (Japan)");
+        assert!(games[0].categories.contains(&Category::Demos));
+        let rom = games[0].roms.iter().next().unwrap();
+        assert_eq!(rom.crc32 as u32, 0x352441c2);
+        assert!(rom.status == Some(Status::Verified));
+        // have datafiles and lists hold the other games
+        let mut output = Vec::new();
+        let owned = HashSet::from([owned]);
+        assert_eq!(
+            catalog
+                .write_collection_datafile(
+                    GameConsole::PSX,
+                    &owned,
+                    CollectionPart::Owned,
+                    &mut output
+                )
+                .unwrap(),
+            1
+        );
+        assert!(
+            String::from_utf8(output)
+                .unwrap()
+                .contains("Game (USA).bin")
+        );
+        assert_eq!(
+            catalog
+                .collection_game_names(GameConsole::PSX, &owned, CollectionPart::Owned)
+                .unwrap(),
+            ["Game (USA)"]
+        );
+        assert_eq!(
+            catalog
+                .collection_game_names(GameConsole::PSX, &owned, CollectionPart::Missing)
+                .unwrap(),
+            ["Other & Game (Japan)"]
+        );
     }
 }
@@ -0,0 +1,177 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{
+    ToSql,
+    types::{FromSql, FromSqlError, ToSqlOutput},
+};
+
+use crate::{Result, ResultUtils, utils::CanPrepare};
+
+/// What a datafile update changed about one of its games
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Renamed,
+    /// One of the game's ROMs was added, removed, or given a different hash
+    ROMChanged,
+}
+
+impl ChangeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Added => "added",
+            Self::Removed => "removed",
+            Self::Renamed => "renamed",
+            Self::ROMChanged => "rom changed",
+        }
+    }
+}
+
+impl FromSql for ChangeKind {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        match value.as_str()? {
+            "added" => Ok(Self::Added),
+            "removed" => Ok(Self::Removed),
+            "renamed" => Ok(Self::Renamed),
+            "rom" => Ok(Self::ROMChanged),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for ChangeKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(match self {
+            Self::Added => "added",
+            Self::Removed => "removed",
+            Self::Renamed => "renamed",
+            Self::ROMChanged => "rom",
+        }))
+    }
+}
+
+/// A change a datafile update made to the catalog (see [super::Catalog::changes])
+#[derive(Clone, Debug)]
+pub struct CatalogChange {
+    pub datafile: String,
+    /// The version of the datafile which made the change
+    pub version: String,
+    pub gid: i64,
+    /// The game's name after the change
+    pub game: String,
+    pub kind: ChangeKind,
+    /// The game's name before it was renamed
+    pub old_name: Option<String>,
+    /// The ROM which changed, or for a removed game, one of its ROMs (each gets a change)
+    pub rom: Option<String>,
+    pub old_sha1: Option<[u8; 20]>,
+    pub new_sha1: Option<[u8; 20]>,
+    pub changed: DateTime<Utc>,
+}
+
+impl CatalogChange {
+    /// The hash of a ROM the change took out of the catalog, whose dumps no longer verify unless
+    /// another game has the ROM
+    pub fn removed_sha1(&self) -> Option<[u8; 20]> {
+        match self.kind {
+            ChangeKind::Removed | ChangeKind::ROMChanged => self.old_sha1,
+            ChangeKind::Added | ChangeKind::Renamed => None,
+        }
+    }
+}
+
+/// Records the changes an update makes to one datafile's games, all as of when it started
+pub(super) struct ChangeLog {
+    dfid: i64,
+    datafile: String,
+    version: String,
+    changed: DateTime<Utc>,
+}
+
+impl ChangeLog {
+    pub(super) fn new(dfid: i64, datafile: &str, version: &str) -> ChangeLog {
+        ChangeLog {
+            dfid,
+            datafile: datafile.to_string(),
+            version: version.to_string(),
+            changed: Utc::now(),
+        }
+    }
+
+    /// A change to a game, with no ROM or old name
+    pub(super) fn change(&self, gid: i64, game: &str, kind: ChangeKind) -> CatalogChange {
+        CatalogChange {
+            datafile: self.datafile.clone(),
+            version: self.version.clone(),
+            gid,
+            game: game.to_string(),
+            kind,
+            old_name: None,
+            rom: None,
+            old_sha1: None,
+            new_sha1: None,
+            changed: self.changed,
+        }
+    }
+
+    pub(super) fn record(
+        &self,
+        connection: &impl CanPrepare,
+        change: &CatalogChange,
+    ) -> Result<()> {
+        let mut statement = connection
+            .prepare_cached_common(
+                "INSERT INTO changes (dfid, version, gid, kind, game, old_name, rom, old_sha1, new_sha1, changed) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .ndl("Failed to record changes in catalog DB")?;
+        statement
+            .execute((
+                self.dfid,
+                &self.version,
+                change.gid,
+                change.kind,
+                &change.game,
+                &change.old_name,
+                &change.rom,
+                change.old_sha1,
+                change.new_sha1,
+                self.changed.timestamp_millis(),
+            ))
+            .ndl("Failed to record changes in catalog DB")?;
+        Ok(())
+    }
+}
+
+/// Lists the changes datafile updates made since `since`, oldest first
+///
+pub(super) fn changes_since(
+    connection: &impl CanPrepare,
+    since: DateTime<Utc>,
+) -> Result<Vec<CatalogChange>> {
+    let mut statement = connection
+        .prepare_cached_common(
+            r#"SELECT "datafiles"."name", "changes"."version", "gid", "kind", "game", "old_name", "rom", "old_sha1", "new_sha1", "changed"
+            FROM "changes" JOIN "datafiles" ON "changes"."dfid" = "datafiles"."dfid"
+            WHERE "changed" >= ? ORDER BY "changed", "id""#,
+        )
+        .ndl("Failed to retrieve changes from catalog DB")?;
+    let changes = statement
+        .query_map((since.timestamp_millis(),), |row| {
+            Ok(CatalogChange {
+                datafile: row.get(0)?,
+                version: row.get(1)?,
+                gid: row.get(2)?,
+                kind: row.get(3)?,
+                game: row.get(4)?,
+                old_name: row.get(5)?,
+                rom: row.get(6)?,
+                old_sha1: row.get(7)?,
+                new_sha1: row.get(8)?,
+                changed: DateTime::from_timestamp_millis(row.get(9)?).unwrap(),
+            })
+        })
+        .ndl("Failed to retrieve changes from catalog DB")?;
+    changes
+        .collect::<rusqlite::Result<Vec<CatalogChange>>>()
+        .ndl("Failed to retrieve changes from catalog DB")
+}
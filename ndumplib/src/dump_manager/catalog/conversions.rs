@@ -0,0 +1,80 @@
+use rusqlite::OptionalExtension;
+
+use crate::{GameConsole, Result, ResultUtils, utils::CanPrepare};
+
+/// How many conversions to a format must be recorded for a console before its own ratio is used
+/// rather than every console's
+const MIN_CONVERSIONS: i64 = 3;
+
+/// Adds a finished conversion's sizes to the history of conversions to `format`, under the
+/// console of the dump if it's known
+///
+pub(super) fn record_conversion(
+    connection: &impl CanPrepare,
+    console: Option<GameConsole>,
+    format: &str,
+    source_size: u64,
+    output_size: u64,
+) -> Result<()> {
+    let mut statement = connection
+        .prepare_cached_common(
+            r#"INSERT INTO "conversion_sizes" ("console", "format", "conversions", "source_bytes", "output_bytes") VALUES (?, ?, 1, ?, ?)
+            ON CONFLICT ("console", "format") DO UPDATE SET
+                "conversions" = "conversions" + 1,
+                "source_bytes" = "source_bytes" + "excluded"."source_bytes",
+                "output_bytes" = "output_bytes" + "excluded"."output_bytes""#,
+        )
+        .ndl("Failed to record conversion in catalog DB")?;
+    statement
+        .execute((
+            console.as_ref().map_or("", GameConsole::formal_name),
+            format,
+            source_size as i64,
+            output_size as i64,
+        ))
+        .ndl("Failed to record conversion in catalog DB")?;
+    Ok(())
+}
+
+/// How big dumps converted to `format` came out, as a share of their sources' size
+///
+/// The console's own conversions are used once there are enough of them, and every console's
+/// otherwise. Returns `None` if nothing has been converted to the format yet.
+pub(super) fn conversion_ratio(
+    connection: &impl CanPrepare,
+    console: Option<GameConsole>,
+    format: &str,
+) -> Result<Option<f64>> {
+    if let Some(console) = console {
+        let mut statement = connection
+            .prepare_cached_common(
+                r#"SELECT "source_bytes", "output_bytes" FROM "conversion_sizes" WHERE "console" = ? AND "format" = ? AND "conversions" >= ?"#,
+            )
+            .ndl("Failed to retrieve conversions from catalog DB")?;
+        let sizes: Option<(i64, i64)> = statement
+            .query_row((console.formal_name(), format, MIN_CONVERSIONS), |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .optional()
+            .ndl("Failed to retrieve conversions from catalog DB")?;
+        if let Some((source_bytes, output_bytes)) = sizes
+            && source_bytes > 0
+        {
+            return Ok(Some(output_bytes as f64 / source_bytes as f64));
+        }
+    }
+    let mut statement = connection
+        .prepare_cached_common(
+            r#"SELECT SUM("source_bytes"), SUM("output_bytes") FROM "conversion_sizes" WHERE "format" = ?"#,
+        )
+        .ndl("Failed to retrieve conversions from catalog DB")?;
+    let (source_bytes, output_bytes): (Option<i64>, Option<i64>) = statement
+        .query_row((format,), |row| Ok((row.get(0)?, row.get(1)?)))
+        .ndl("Failed to retrieve conversions from catalog DB")?;
+    Ok(match (source_bytes, output_bytes) {
+        (Some(source_bytes), Some(output_bytes)) if source_bytes > 0 => {
+            Some(output_bytes as f64 / source_bytes as f64)
+        }
+        _ => None,
+    })
+}
@@ -0,0 +1,115 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use super::{Game, logiqx::XMLDatafile};
+use crate::{Result, ResultUtils};
+
+/// A ROM whose hash changed between two datafiles
+pub struct ROMChange {
+    pub game: String,
+    pub rom: String,
+    pub old_sha1: Option<[u8; 20]>,
+    pub new_sha1: Option<[u8; 20]>,
+}
+
+/// The differences between two versions of a datafile
+pub struct DatafileDiff {
+    pub old_version: String,
+    pub new_version: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub renamed: Vec<(String, String)>,
+    pub changed: Vec<ROMChange>,
+}
+
+fn load_games(path: &Path) -> Result<(String, BTreeMap<String, Game>)> {
+    let content = std::fs::read_to_string(path).ndl(format!(
+        "Failed to read datafile \"{}\"",
+        path.to_str().unwrap()
+    ))?;
+    let xml = XMLDatafile::open(&content)?;
+    let version = xml.parse_header()?.version;
+    let mut games = BTreeMap::new();
+    for game in xml.parse_games::<Game>()? {
+        let game = game?;
+        games.insert(game.name.clone(), game);
+    }
+    Ok((version, games))
+}
+
+/// Identifies a game by its ROM hashes, ignoring its name
+///
+/// Cuesheets are skipped, since they contain the track filenames and change whenever a game is renamed.
+fn rom_set_key(game: &Game) -> Vec<[u8; 20]> {
+    let mut key: Vec<[u8; 20]> = game
+        .roms
+        .iter()
+        .filter(|rom| !rom.name.ends_with(".cue"))
+        .map(|rom| rom.sha1)
+        .collect();
+    key.sort();
+    key
+}
+
+impl DatafileDiff {
+    /// Compares two logiqx datafiles on disk
+    ///
+    pub fn from_files(old: &impl AsRef<Path>, new: &impl AsRef<Path>) -> Result<DatafileDiff> {
+        let (old_version, old_games) = load_games(old.as_ref())?;
+        let (new_version, new_games) = load_games(new.as_ref())?;
+        let mut diff = DatafileDiff {
+            old_version,
+            new_version,
+            added: Vec::new(),
+            removed: Vec::new(),
+            renamed: Vec::new(),
+            changed: Vec::new(),
+        };
+        // compare the games present in both datafiles
+        for (name, old_game) in &old_games {
+            let Some(new_game) = new_games.get(name) else {
+                continue;
+            };
+            diff.changed.extend(old_game.rom_changes(new_game));
+        }
+        // games only in the new datafile are either renames or additions
+        let mut removed_by_roms: HashMap<Vec<[u8; 20]>, Vec<&str>> = HashMap::new();
+        for (name, game) in &old_games {
+            if !new_games.contains_key(name) {
+                removed_by_roms
+                    .entry(rom_set_key(game))
+                    .or_default()
+                    .push(name);
+            }
+        }
+        for (name, game) in &new_games {
+            if old_games.contains_key(name) {
+                continue;
+            }
+            let key = rom_set_key(game);
+            match removed_by_roms.get_mut(&key) {
+                Some(old_names) if !key.is_empty() && !old_names.is_empty() => {
+                    diff.renamed
+                        .push((old_names.remove(0).to_string(), name.clone()));
+                }
+                _ => diff.added.push(name.clone()),
+            }
+        }
+        diff.removed = removed_by_roms
+            .into_values()
+            .flatten()
+            .map(|name| name.to_string())
+            .collect();
+        diff.removed.sort();
+        Ok(diff)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.renamed.is_empty()
+            && self.changed.is_empty()
+    }
+}
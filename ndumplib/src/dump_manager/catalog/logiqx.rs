@@ -1,8 +1,17 @@
+use std::io::Write;
+
 use crate::Error;
 
-use super::ResultUtils;
+use super::{Game, ResultUtils};
+use quick_xml::{
+    Reader, Writer,
+    events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event},
+};
 use roxmltree::{Document, Node, ParsingOptions};
 
+/// The document type declared by Redump's and No-Intro's datafiles
+const LOGIQX_DOCTYPE: &str = r#"datafile PUBLIC "-//Logiqx//DTD ROM Management Datafile//EN" "http://www.logiqx.com/Dats/datafile.dtd""#;
+
 pub(crate) trait XMLQueries {
     fn get_tagged_child(&self, tag_name: &str) -> Option<Self>
     where
@@ -91,11 +100,11 @@ impl<'a, 'input, const N: usize> XMLHexAttribute<[u8; N]> for Node<'a, 'input> {
 }
 
 #[allow(unused)]
-pub(crate) struct Header<'a> {
-    pub name: &'a str,
-    pub description: &'a str,
-    pub version: &'a str,
-    pub homepage: &'a str,
+pub(crate) struct Header {
+    pub name: String,
+    pub description: String,
+    pub version: String,
+    pub homepage: String,
 }
 
 pub(crate) trait GameElement
@@ -109,36 +118,115 @@ where
     fn parse_game_rom(node: &Node) -> super::Result<Self::ROM>;
 }
 
+/// Parses a single element (and its children) cut out of a datafile
+///
+fn parse_chunk(chunk: &str) -> super::Result<Document<'_>> {
+    Document::parse_with_options(
+        chunk,
+        ParsingOptions {
+            allow_dtd: false,
+            nodes_limit: u32::MAX,
+        },
+    )
+    .ndl("Failed to parse logiqx datafile")
+}
+
+/// Walks the direct children of a datafile's root element without building a DOM for the whole file
+struct ChildElements<'a> {
+    content: &'a str,
+    reader: Reader<&'a [u8]>,
+    finished: bool,
+}
+
+impl<'a> ChildElements<'a> {
+    fn new(content: &'a str) -> super::Result<ChildElements<'a>> {
+        let mut reader = Reader::from_str(content);
+        // skip to the root <datafile> element
+        loop {
+            match reader.read_event().ndl("Failed to parse logiqx datafile")? {
+                Event::Start(element) if element.name().as_ref() == "datafile" => break,
+                Event::Start(_) | Event::Empty(_) | Event::Eof => {
+                    return Err(Error::new_original(
+                        "Failed to parse datafile\nMissing <datafile>",
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(ChildElements {
+            content,
+            reader,
+            finished: false,
+        })
+    }
+}
+
+impl<'a> Iterator for ChildElements<'a> {
+    /// The element's tag name, and the text of the whole element
+    type Item = super::Result<(String, &'a str)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.finished {
+            let start = self.reader.buffer_position() as usize;
+            let event = match self.reader.read_event() {
+                Ok(event) => event,
+                Err(err) => {
+                    self.finished = true;
+                    return Some(Err(Error::new("Failed to parse logiqx datafile", err)));
+                }
+            };
+            match event {
+                Event::Start(element) => {
+                    let name = element.name().as_ref().to_string();
+                    if let Err(err) = self.reader.read_to_end(element.name()) {
+                        self.finished = true;
+                        return Some(Err(Error::new("Failed to parse logiqx datafile", err)));
+                    }
+                    let end = self.reader.buffer_position() as usize;
+                    return Some(Ok((name, &self.content[start..end])));
+                }
+                Event::Empty(element) => {
+                    let name = element.name().as_ref().to_string();
+                    let end = self.reader.buffer_position() as usize;
+                    return Some(Ok((name, &self.content[start..end])));
+                }
+                Event::End(_) | Event::Eof => self.finished = true,
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
 pub(crate) struct XMLDatafile<'a> {
-    document: Document<'a>,
+    content: &'a str,
 }
 
 impl<'a> XMLDatafile<'a> {
     pub fn open(content: &'a str) -> super::Result<XMLDatafile<'a>> {
-        Ok(XMLDatafile {
-            document: Document::parse_with_options(
-                content,
-                ParsingOptions {
-                    allow_dtd: true,
-                    nodes_limit: u32::MAX,
-                },
-            )
-            .ndl("Failed to parse logiqx datafile")?,
-        })
+        ChildElements::new(content)?;
+        Ok(XMLDatafile { content })
     }
 
-    fn root(&'a self) -> super::Result<Node<'a, 'a>> {
-        self.document
-            .root()
-            .get_tagged_child("datafile")
-            .ndl("Failed to parse datafile\nMissing <datafile>")
+    /// Roughly how many ROMs the datafile has, without parsing it
+    pub fn approximate_rom_count(&self) -> usize {
+        self.content.matches("<rom ").count()
     }
 
-    pub fn parse_header(&'a self) -> super::Result<Header<'a>> {
-        let header = self
-            .root()?
-            .get_tagged_child("header")
-            .ndl("Failed to parse datafile\nMissing <header>")?;
+    pub fn parse_header(&self) -> super::Result<Header> {
+        let chunk = 'header: {
+            for element in ChildElements::new(self.content)? {
+                let (tag_name, chunk) = element?;
+                if tag_name == "header" {
+                    break 'header chunk;
+                }
+            }
+            return Err(Error::new_original(
+                "Failed to parse datafile\nMissing <header>",
+            ));
+        };
+        let document = parse_chunk(chunk)?;
+        let header = document.root_element();
         let name = header
             .get_tagged_child("name")
             .ndl("Failed to parse datafile\nMissing <name> in <header>")?
@@ -160,25 +248,129 @@ impl<'a> XMLDatafile<'a> {
             .text()
             .unwrap_or("");
         Ok(Header {
-            name,
-            description,
-            version,
-            homepage,
+            name: name.to_string(),
+            description: description.to_string(),
+            version: version.to_string(),
+            homepage: homepage.to_string(),
         })
     }
 
-    pub fn parse_games<T>(&self) -> super::Result<Vec<T>>
+    /// Parses the games in the datafile one at a time
+    ///
+    pub fn parse_games<T>(&self) -> super::Result<impl Iterator<Item = super::Result<T>> + 'a>
     where
         T: GameElement,
     {
-        let mut games = Vec::new();
-        for game_element in self.root()?.get_tagged_children("game") {
-            let mut game = T::parse_game(&game_element)?;
-            for rom in game_element.get_tagged_children("rom") {
-                game.add_rom(T::parse_game_rom(&rom)?)?;
-            }
-            games.push(game);
-        }
-        Ok(games)
+        Ok(ChildElements::new(self.content)?.filter_map(|element| {
+            let chunk = match element {
+                Ok((tag_name, chunk)) if tag_name == "game" => chunk,
+                Ok(_) => return None,
+                Err(err) => return Some(Err(err)),
+            };
+            Some(parse_chunk(chunk).and_then(|document| {
+                let game_element = document.root_element();
+                let mut game = T::parse_game(&game_element)?;
+                for rom in game_element.get_tagged_children("rom") {
+                    game.add_rom(T::parse_game_rom(&rom)?)?;
+                }
+                Ok(game)
+            }))
+        }))
+    }
+}
+
+/// Writes a datafile one game at a time, in the format read by [XMLDatafile]
+pub(crate) struct XMLDatafileWriter<W: Write> {
+    writer: Writer<W>,
+}
+
+impl<W: Write> XMLDatafileWriter<W> {
+    /// Starts a datafile, writing everything up to its first game
+    ///
+    pub fn new(output: W, header: &Header) -> super::Result<XMLDatafileWriter<W>> {
+        let mut writer = Writer::new_with_indent(output, b'\t', 1);
+        let mut write_start = || -> std::io::Result<()> {
+            writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+            writer.write_event(Event::DocType(BytesText::from_escaped(LOGIQX_DOCTYPE)))?;
+            writer.write_event(Event::Start(BytesStart::new("datafile")))?;
+            writer
+                .create_element("header")
+                .write_inner_content(|writer| {
+                    for (tag_name, text) in [
+                        ("name", &header.name),
+                        ("description", &header.description),
+                        ("version", &header.version),
+                        ("homepage", &header.homepage),
+                    ] {
+                        writer
+                            .create_element(tag_name)
+                            .write_text_content(BytesText::new(text))?;
+                    }
+                    Ok(())
+                })?;
+            Ok(())
+        };
+        write_start().ndl("Failed to write logiqx datafile")?;
+        Ok(XMLDatafileWriter { writer })
+    }
+
+    /// Writes a game with its categories and ROMs (sorted by name)
+    ///
+    pub fn write_game(&mut self, game: &Game) -> super::Result<()> {
+        let mut roms: Vec<_> = game.roms.iter().collect();
+        roms.sort_by(|a, b| a.name.cmp(&b.name));
+        self.writer
+            .create_element("game")
+            .with_attribute(("name", game.name.as_str()))
+            .write_inner_content(|writer| {
+                for category in &game.categories {
+                    if let Some(name) = category.name() {
+                        writer
+                            .create_element("category")
+                            .write_text_content(BytesText::new(name))?;
+                    }
+                }
+                writer
+                    .create_element("description")
+                    .write_text_content(BytesText::new(&game.name))?;
+                for rom in roms {
+                    let size = rom.size.to_string();
+                    let crc = format!("{:08x}", rom.crc32 as u32);
+                    let md5 = hex::encode(rom.md5);
+                    let sha1 = hex::encode(rom.sha1);
+                    let sha256 = rom.sha256.map(hex::encode);
+                    let mut element = writer.create_element("rom").with_attributes([
+                        ("name", rom.name.as_str()),
+                        ("size", &size),
+                        ("crc", &crc),
+                        ("md5", &md5),
+                        ("sha1", &sha1),
+                    ]);
+                    if let Some(sha256) = &sha256 {
+                        element = element.with_attribute(("sha256", sha256.as_str()));
+                    }
+                    if let Some(status) = rom.status.and_then(|status| status.name()) {
+                        element = element.with_attribute(("status", status));
+                    }
+                    element.write_empty()?;
+                }
+                Ok(())
+            })
+            .ndl("Failed to write logiqx datafile")?;
+        Ok(())
+    }
+
+    /// Closes the datafile, returning the output it was written to
+    ///
+    pub fn finish(mut self) -> super::Result<W> {
+        self.writer
+            .write_event(Event::End(BytesEnd::new("datafile")))
+            .ndl("Failed to write logiqx datafile")?;
+        let mut output = self.writer.into_inner();
+        output
+            .write_all(b"\n")
+            .and_then(|_| output.flush())
+            .ndl("Failed to write logiqx datafile")?;
+        Ok(output)
     }
 }
@@ -12,7 +12,11 @@ use tempfile::{NamedTempFile, TempDir};
 use ureq::{Agent, Body, ResponseExt, http::Response};
 use visdom::{Vis, types::Elements};
 
-use crate::{Error, GameConsole, Result, ResultUtils};
+use super::{AvailableDatafile, DatafileSource, FetchedDatafile};
+use crate::{
+    Error, GameConsole, Result, ResultUtils, UpdateTarget,
+    dump_manager::network::{Deadline, NetworkTimeouts},
+};
 
 trait ResponseUtils {
     fn content_type(&self) -> String;
@@ -42,7 +46,7 @@ impl ResponseUtils for Response<Body> {
 }
 
 #[allow(unused)]
-pub(super) struct DatafileLink {
+struct DatafileLink {
     pub name: String,
     pub link: Option<String>,
     pub last_updated: DateTime<Utc>,
@@ -50,20 +54,21 @@ pub(super) struct DatafileLink {
 
 fn load_html<'a>(
     agent: &Agent,
+    deadline: &Deadline,
     url: &str,
     form_body: Option<HashMap<String, String>>,
 ) -> Result<(Elements<'a>, String)> {
     let mut response = match form_body {
-        Some(body) => agent
-            .post(url)
+        Some(body) => deadline
+            .limit(agent.post(url))?
             .header(
                 "User-Agent",
                 "Mozilla/5.0 (X11; Linux x86_64; rv:140.0) Gecko/20100101 Firefox/140.0",
             )
             .send_form(body)
             .ndl("Failed to connect to No-Intro")?,
-        None => agent
-            .get(url)
+        None => deadline
+            .limit(agent.get(url))?
             .header(
                 "User-Agent",
                 "Mozilla/5.0 (X11; Linux x86_64; rv:140.0) Gecko/20100101 Firefox/140.0",
@@ -153,24 +158,24 @@ fn get_form_data(form: &Elements, submit_selector: &str) -> Result<HashMap<Strin
     Ok(form_data)
 }
 
-fn download_datafile_zip(agent: &Agent, link: &str) -> Result<NamedTempFile> {
+fn download_datafile_zip(agent: &Agent, deadline: &Deadline, link: &str) -> Result<NamedTempFile> {
     let mut file =
         NamedTempFile::with_suffix(".zip").ndl("Failed to download No-Intro datafile")?;
     // go to the datafile configuration settings
-    let (root, url) = load_html(agent, link, None)?;
+    let (root, url) = load_html(agent, deadline, link, None)?;
     // prepare the datafile
     let form_data = get_form_data(
         &root.find("form[name=\"main_form\"]"),
         "input[type=\"submit\"][value=\"Prepare\"]",
     )?;
-    let (root, url) = load_html(agent, url.as_ref(), Some(form_data))?;
+    let (root, url) = load_html(agent, deadline, url.as_ref(), Some(form_data))?;
     // download the file
     let form_data = get_form_data(
         &root.find(".standard form"),
         "input[type=\"submit\"][value=\"Download!!\"]",
     )?;
-    let mut response = agent
-        .post(url)
+    let mut response = deadline
+        .limit(agent.post(url))?
         .header(
             "User-Agent",
             "Mozilla/5.0 (X11; Linux x86_64; rv:140.0) Gecko/20100101 Firefox/140.0",
@@ -230,10 +235,14 @@ fn extract_datafile(file: &NamedTempFile) -> Result<String> {
     Ok(contents)
 }
 
-pub(super) fn load_datafile_links(agent: &Agent) -> Result<HashMap<String, DatafileLink>> {
+fn load_datafile_links(
+    agent: &Agent,
+    deadline: &Deadline,
+) -> Result<HashMap<String, DatafileLink>> {
     let time_regex = Regex::new(r"(?<time>\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2})").unwrap();
     let (page, _) = load_html(
         agent,
+        deadline,
         "https://datomatic.no-intro.org/index.php?page=download&s=64&op=select",
         None,
     )?;
@@ -285,8 +294,8 @@ pub(super) fn load_datafile_links(agent: &Agent) -> Result<HashMap<String, Dataf
     Ok(links)
 }
 
-pub(super) fn download_datafile(agent: &Agent, url: &str) -> Result<String> {
-    extract_datafile(&download_datafile_zip(agent, url)?)
+fn download_datafile(agent: &Agent, deadline: &Deadline, url: &str) -> Result<String> {
+    extract_datafile(&download_datafile_zip(agent, deadline, url)?)
 }
 
 impl GameConsole {
@@ -296,7 +305,90 @@ impl GameConsole {
             Self::GBA => Some("Nintendo - Game Boy Advance"),
             Self::GBC => Some("Nintendo - Game Boy Color"),
             Self::N64 => Some("Nintendo - Nintendo 64"),
+            // ROMs are hashed without their iNES header, so the headerless datafile matches them
+            Self::NES => Some("Nintendo - Nintendo Entertainment System (Headerless)"),
+            Self::SNES => Some("Nintendo - Super Nintendo Entertainment System"),
+            Self::Genesis => Some("Sega - Mega Drive - Genesis"),
             _ => None,
         }
     }
 }
+
+/// Downloads cartridge datafiles from No-Intro's DAT-o-MATIC
+pub struct NoIntroSource {
+    agent: Agent,
+    timeouts: NetworkTimeouts,
+}
+
+impl NoIntroSource {
+    pub fn new() -> NoIntroSource {
+        Self::with_timeouts(NetworkTimeouts::default())
+    }
+
+    pub fn with_timeouts(timeouts: NetworkTimeouts) -> NoIntroSource {
+        NoIntroSource {
+            agent: timeouts.agent(),
+            timeouts,
+        }
+    }
+}
+
+impl Default for NoIntroSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DatafileSource for NoIntroSource {
+    fn author(&self) -> &str {
+        "No-Intro"
+    }
+
+    fn consoles(&self) -> Vec<GameConsole> {
+        GameConsole::ALL
+            .into_iter()
+            .filter(|console| console.nointro_datafile_name().is_some())
+            .collect()
+    }
+
+    fn is_covered_by(&self, target: &UpdateTarget, console: GameConsole) -> bool {
+        target.covers_nointro(console)
+    }
+
+    fn list_datafiles(&self) -> Result<Vec<AvailableDatafile>> {
+        let links = load_datafile_links(&self.agent, &self.timeouts.start_deadline())?;
+        let mut datafiles = Vec::new();
+        for console in self.consoles() {
+            let name = console.nointro_datafile_name().unwrap();
+            let Some(link) = links.get(name) else {
+                continue;
+            };
+            if let Some(url) = &link.link {
+                datafiles.push(AvailableDatafile {
+                    console,
+                    name: name.to_string(),
+                    last_updated: Some(link.last_updated),
+                    location: url.clone(),
+                });
+            }
+        }
+        Ok(datafiles)
+    }
+
+    fn fetch_datafile(
+        &self,
+        datafile: &AvailableDatafile,
+        _etag: Option<&str>,
+        _last_modified: Option<&str>,
+    ) -> Result<Option<FetchedDatafile>> {
+        Ok(Some(FetchedDatafile {
+            content: download_datafile(
+                &self.agent,
+                &self.timeouts.start_deadline(),
+                &datafile.location,
+            )?,
+            etag: None,
+            last_modified: None,
+        }))
+    }
+}
@@ -0,0 +1,413 @@
+use std::{iter::Peekable, str::FromStr, vec::IntoIter};
+
+use rusqlite::types::Value;
+
+use super::Category;
+use crate::{Error, ErrorCategory, GameConsole, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Name,
+    Console,
+    Datafile,
+    Category,
+    Gid,
+    Revision,
+    Serial,
+    Region,
+}
+
+impl Field {
+    fn parse(name: &str) -> Result<Field> {
+        match name.to_lowercase().as_str() {
+            "name" => Ok(Self::Name),
+            "console" => Ok(Self::Console),
+            "datafile" => Ok(Self::Datafile),
+            "category" => Ok(Self::Category),
+            "gid" => Ok(Self::Gid),
+            "revision" => Ok(Self::Revision),
+            "serial" => Ok(Self::Serial),
+            "region" => Ok(Self::Region),
+            _ => Err(invalid_query(format!(
+                "Unknown field \"{name}\" (expected name, console, datafile, category, gid, revision, serial, or region)"
+            ))),
+        }
+    }
+
+    fn is_integer(&self) -> bool {
+        matches!(self, Self::Gid | Self::Revision)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Like,
+    NotLike,
+}
+
+impl Operator {
+    fn sql(&self) -> &str {
+        match self {
+            Self::Equal => "=",
+            Self::NotEqual => "!=",
+            Self::Less => "<",
+            Self::LessOrEqual => "<=",
+            Self::Greater => ">",
+            Self::GreaterOrEqual => ">=",
+            Self::Like => "LIKE",
+            Self::NotLike => "NOT LIKE",
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Expression {
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    Compare(Field, Operator, String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Operator(Operator),
+    Open,
+    Close,
+}
+
+fn invalid_query(message: impl AsRef<str>) -> Error {
+    Error::new_original(format!("Invalid query\n{}", message.as_ref()))
+        .with_category(ErrorCategory::InvalidInput)
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut characters = query.chars().peekable();
+    while let Some(&character) = characters.peek() {
+        match character {
+            _ if character.is_whitespace() => {
+                characters.next();
+            }
+            '(' | ')' => {
+                characters.next();
+                tokens.push(if character == '(' {
+                    Token::Open
+                } else {
+                    Token::Close
+                });
+            }
+            '\'' | '"' => {
+                // quotes are escaped by doubling them, as in SQL
+                characters.next();
+                let mut value = String::new();
+                loop {
+                    match characters.next() {
+                        Some(next) if next == character => {
+                            if characters.peek() == Some(&character) {
+                                characters.next();
+                                value.push(character);
+                            } else {
+                                break;
+                            }
+                        }
+                        Some(next) => value.push(next),
+                        None => return Err(invalid_query("Unterminated quoted value")),
+                    }
+                }
+                tokens.push(Token::Quoted(value));
+            }
+            '=' | '!' | '<' | '>' => {
+                characters.next();
+                let operator = match (character, characters.peek()) {
+                    ('=', _) => Operator::Equal,
+                    ('!', Some('=')) | ('<', Some('>')) => {
+                        characters.next();
+                        Operator::NotEqual
+                    }
+                    ('<', Some('=')) => {
+                        characters.next();
+                        Operator::LessOrEqual
+                    }
+                    ('>', Some('=')) => {
+                        characters.next();
+                        Operator::GreaterOrEqual
+                    }
+                    ('<', _) => Operator::Less,
+                    ('>', _) => Operator::Greater,
+                    _ => return Err(invalid_query("Expected \"!=\"")),
+                };
+                tokens.push(Token::Operator(operator));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&next) = characters.peek() {
+                    if next.is_whitespace() || "()'\"=!<>".contains(next) {
+                        break;
+                    }
+                    word.push(next);
+                    characters.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Peekable<IntoIter<Token>>,
+}
+
+impl Parser {
+    fn next_is_keyword(&mut self, keyword: &str) -> bool {
+        matches!(self.tokens.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn parse_or(&mut self) -> Result<Expression> {
+        let mut expression = self.parse_and()?;
+        while self.next_is_keyword("OR") {
+            self.tokens.next();
+            expression = Expression::Or(Box::new(expression), Box::new(self.parse_and()?));
+        }
+        Ok(expression)
+    }
+
+    fn parse_and(&mut self) -> Result<Expression> {
+        let mut expression = self.parse_condition()?;
+        while self.next_is_keyword("AND") {
+            self.tokens.next();
+            expression = Expression::And(Box::new(expression), Box::new(self.parse_condition()?));
+        }
+        Ok(expression)
+    }
+
+    fn parse_condition(&mut self) -> Result<Expression> {
+        if self.next_is_keyword("NOT") {
+            self.tokens.next();
+            return Ok(Expression::Not(Box::new(self.parse_condition()?)));
+        }
+        match self.tokens.next() {
+            Some(Token::Open) => {
+                let expression = self.parse_or()?;
+                match self.tokens.next() {
+                    Some(Token::Close) => Ok(expression),
+                    _ => Err(invalid_query("Expected \")\"")),
+                }
+            }
+            Some(Token::Word(field)) => {
+                let field = Field::parse(&field)?;
+                let operator = match self.tokens.next() {
+                    Some(Token::Operator(operator)) => operator,
+                    Some(Token::Word(word)) if word.eq_ignore_ascii_case("LIKE") => Operator::Like,
+                    Some(Token::Word(word)) if word.eq_ignore_ascii_case("NOT") => {
+                        match self.tokens.next() {
+                            Some(Token::Word(word)) if word.eq_ignore_ascii_case("LIKE") => {
+                                Operator::NotLike
+                            }
+                            _ => return Err(invalid_query("Expected \"LIKE\" after \"NOT\"")),
+                        }
+                    }
+                    _ => return Err(invalid_query("Expected a comparison after the field")),
+                };
+                let value = match self.tokens.next() {
+                    Some(Token::Word(value)) | Some(Token::Quoted(value)) => value,
+                    _ => return Err(invalid_query("Expected a value after the comparison")),
+                };
+                Ok(Expression::Compare(field, operator, value))
+            }
+            _ => Err(invalid_query("Expected a field, \"NOT\", or \"(\"")),
+        }
+    }
+}
+
+/// Parses a category by the name used in datafiles (case-insensitive)
+fn parse_category(value: &str) -> Result<Category> {
+    const NAMES: [&str; 11] = [
+        "Games",
+        "Demos",
+        "Coverdiscs",
+        "Applications",
+        "Preproduction",
+        "Educational",
+        "Bonus Discs",
+        "Multimedia",
+        "Add-Ons",
+        "Audio",
+        "Video",
+    ];
+    NAMES
+        .iter()
+        .find(|name| name.eq_ignore_ascii_case(value))
+        .map(|name| Category::from(*name))
+        .ok_or_else(|| invalid_query(format!("Unknown category \"{value}\"")))
+}
+
+impl Expression {
+    /// Writes the expression as an SQL condition over `resolved_games` (as `r`) joined with `games`
+    /// (as `g`) and `datafiles` (as `d`)
+    fn to_sql(&self, parameters: &mut Vec<Value>) -> Result<String> {
+        Ok(match self {
+            Self::And(left, right) => format!(
+                "({} AND {})",
+                left.to_sql(parameters)?,
+                right.to_sql(parameters)?
+            ),
+            Self::Or(left, right) => format!(
+                "({} OR {})",
+                left.to_sql(parameters)?,
+                right.to_sql(parameters)?
+            ),
+            Self::Not(expression) => format!("(NOT {})", expression.to_sql(parameters)?),
+            Self::Compare(Field::Category, operator, value) => {
+                parameters.push(Value::Integer(parse_category(value)?.id()));
+                let exists = r#"EXISTS (SELECT 1 FROM "game_categories" AS "c" WHERE "c"."gid" = "r"."gid" AND "c"."category" = ?)"#;
+                match operator {
+                    Operator::Equal => exists.to_string(),
+                    Operator::NotEqual => format!("(NOT {exists})"),
+                    _ => {
+                        return Err(invalid_query(
+                            "Categories can only be compared with \"=\" or \"!=\"",
+                        ));
+                    }
+                }
+            }
+            Self::Compare(field, operator, value) => {
+                let column = match field {
+                    Field::Name => r#""r"."name""#,
+                    Field::Console => r#""r"."console""#,
+                    Field::Datafile => r#""d"."name""#,
+                    Field::Gid => r#""r"."gid""#,
+                    Field::Revision => r#""r"."revision""#,
+                    Field::Serial => r#""g"."serial""#,
+                    Field::Region => r#""g"."region""#,
+                    Field::Category => unreachable!(),
+                };
+                let is_like = matches!(operator, Operator::Like | Operator::NotLike);
+                if field.is_integer() {
+                    if is_like {
+                        return Err(invalid_query(format!(
+                            "{field:?} can't be compared with \"LIKE\""
+                        )));
+                    }
+                    let value: i64 = value.parse().map_err(|_| {
+                        invalid_query(format!("Expected a number, got \"{value}\""))
+                    })?;
+                    parameters.push(Value::Integer(value));
+                } else if *field == Field::Console && !is_like {
+                    // consoles are stored by their formal names, but can be given by their short names
+                    let console: GameConsole = value.parse()?;
+                    parameters.push(Value::Text(console.formal_name().to_string()));
+                } else {
+                    parameters.push(Value::Text(value.clone()));
+                }
+                format!("{column} {} ?", operator.sql())
+            }
+        })
+    }
+}
+
+/// A filter over the games in the catalog, like
+/// `console=PSX AND category=Games AND name LIKE '%Final Fantasy%'`
+///
+/// Conditions compare a field (name, console, datafile, category, gid, revision, serial, or region)
+/// with `=`, `!=`, `<`, `<=`, `>`, `>=`, `LIKE`, or `NOT LIKE`, and can be combined with `AND`, `OR`,
+/// `NOT`, and parentheses. Values with spaces must be quoted.
+pub struct GameQuery {
+    expression: Expression,
+}
+
+impl FromStr for GameQuery {
+    type Err = Error;
+
+    fn from_str(query: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(query)?.into_iter().peekable(),
+        };
+        let expression = parser.parse_or()?;
+        if let Some(token) = parser.tokens.next() {
+            return Err(invalid_query(format!("Unexpected {token:?}")));
+        }
+        Ok(GameQuery { expression })
+    }
+}
+
+impl GameQuery {
+    /// The query's SQL condition, along with the parameters to bind to it
+    ///
+    pub(super) fn to_sql(&self) -> Result<(String, Vec<Value>)> {
+        let mut parameters = Vec::new();
+        let condition = self.expression.to_sql(&mut parameters)?;
+        Ok((condition, parameters))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_conditions_into_sql() {
+        let query: GameQuery = "console=psx AND (category = Games OR name LIKE '%Final Fantasy%')"
+            .parse()
+            .unwrap();
+        let (sql, parameters) = query.to_sql().unwrap();
+        assert_eq!(
+            sql,
+            r#"("r"."console" = ? AND (EXISTS (SELECT 1 FROM "game_categories" AS "c" WHERE "c"."gid" = "r"."gid" AND "c"."category" = ?) OR "r"."name" LIKE ?))"#
+        );
+        assert_eq!(
+            parameters,
+            vec![
+                Value::Text(String::from("PlayStation")),
+                Value::Integer(0),
+                Value::Text(String::from("%Final Fantasy%")),
+            ]
+        );
+    }
+
+    #[test]
+    fn handles_quotes_and_negation() {
+        let query: GameQuery = "NOT name NOT LIKE 'Tony Hawk''s%' AND gid >= 10"
+            .parse()
+            .unwrap();
+        let (sql, parameters) = query.to_sql().unwrap();
+        assert_eq!(sql, r#"((NOT "r"."name" NOT LIKE ?) AND "r"."gid" >= ?)"#);
+        assert_eq!(
+            parameters,
+            vec![
+                Value::Text(String::from("Tony Hawk's%")),
+                Value::Integer(10)
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_queries() {
+        for query in [
+            "",
+            "size = 1",
+            "name = ",
+            "gid = abc",
+            "name LIKE 'unterminated",
+            "(console = psx",
+            "console = psx extra",
+            "category LIKE Games",
+        ] {
+            assert!(
+                query
+                    .parse::<GameQuery>()
+                    .and_then(|query| query.to_sql())
+                    .is_err(),
+                "accepted \"{query}\""
+            );
+        }
+    }
+}
@@ -0,0 +1,529 @@
+use std::{path::Path, sync::Arc};
+
+use chrono::DateTime;
+use rusqlite::{OptionalExtension, params_from_iter, types::Value};
+
+use super::{
+    BlockRule, Category, DatafileInfo, GameQuery, Status, TrackIndex, decompress_rom_name,
+};
+use crate::{
+    GameConsole, Result, ResultUtils,
+    utils::{ReadOnlyPool, trigram_similarity},
+};
+
+/// How closely a game's name must resemble a failed lookup to be suggested (see
+/// [crate::utils::trigram_similarity])
+const MIN_SUGGESTION_SIMILARITY: f64 = 0.5;
+
+/// A game in the catalog, after resolving conflicts between its console's datafiles
+#[derive(Clone, Debug)]
+pub struct GameEntry {
+    pub gid: i64,
+    pub console: GameConsole,
+    pub name: String,
+    pub revision: i64,
+    /// The name of the datafile the game comes from
+    pub datafile: String,
+    /// Only known for games from datafiles listing serials
+    pub serial: Option<String>,
+    /// The region tag of the game's name, like "USA" or "Japan, Korea"
+    pub region: Option<String>,
+}
+
+/// A ROM of a game in the catalog
+pub struct CatalogROM {
+    pub name: String,
+    pub size: u64,
+    pub crc32: u32,
+    pub md5: [u8; 16],
+    pub sha1: [u8; 20],
+    pub sha256: Option<[u8; 32]>,
+    /// The dump status given by the datafile ("verified" or "baddump"), if any
+    pub status: Option<&'static str>,
+}
+
+/// A read-only view of the catalog that can be cloned and shared between threads
+///
+/// Each thread querying at the same time gets its own connection, so lookups don't wait on each other,
+/// and they can run while the catalog is being updated elsewhere (they see the last committed update).
+#[derive(Clone)]
+pub struct CatalogReader {
+    pool: Arc<ReadOnlyPool>,
+    block_rules: Arc<Vec<BlockRule>>,
+}
+
+/// Converts a wildcard pattern (see [BlockRule::Pattern]) to a LIKE pattern escaped with `\`
+fn wildcard_to_like(pattern: &str) -> String {
+    let mut like = String::with_capacity(pattern.len());
+    for character in pattern.chars() {
+        match character {
+            '*' => like.push('%'),
+            '?' => like.push('_'),
+            '%' | '_' | '\\' => {
+                like.push('\\');
+                like.push(character);
+            }
+            _ => like.push(character),
+        }
+    }
+    like
+}
+
+impl CatalogReader {
+    /// Opens the catalog DB at `path` (`catalog.sqlite` in the [crate::DumpManager]'s folder)
+    ///
+    /// The catalog must already have been created by a [crate::DumpManager].
+    pub fn open(path: &impl AsRef<Path>) -> Result<CatalogReader> {
+        let pool = ReadOnlyPool::new(path);
+        // fail now, rather than on the first query, if the catalog can't be opened
+        pool.with_connection(|_| Ok(()))?;
+        Ok(CatalogReader {
+            pool: Arc::new(pool),
+            block_rules: Arc::new(Vec::new()),
+        })
+    }
+
+    /// Excludes matching games from [CatalogReader::console_games] and [CatalogReader::search_games]
+    ///
+    pub fn with_block_rules(mut self, rules: Vec<BlockRule>) -> CatalogReader {
+        self.block_rules = Arc::new(rules);
+        self
+    }
+
+    pub(super) fn is_blocked(&self, gid: i64, name: &str) -> bool {
+        self.block_rules.iter().any(|rule| rule.matches(gid, name))
+    }
+
+    /// Finds the game with a ROM matching the given hash, returning its gid
+    ///
+    pub fn is_rom(&self, sha1: [u8; 20]) -> Result<Option<i64>> {
+        self.pool.with_connection(|connection| {
+            let mut statement = connection
+                .prepare_cached("SELECT gid FROM roms WHERE sha1 = ? LIMIT 1")
+                .ndl("Failed to check for ROM in catalog DB")?;
+            statement
+                .query_one((sha1,), |row| row.get(0))
+                .optional()
+                .ndl("Failed to check for ROM in catalog DB")
+        })
+    }
+
+    /// Finds the game with a ROM matching the given hash, returning its gid
+    ///
+    pub fn is_rom_md5(&self, md5: [u8; 16]) -> Result<Option<i64>> {
+        self.pool.with_connection(|connection| {
+            let mut statement = connection
+                .prepare_cached("SELECT gid FROM roms WHERE md5 = ? LIMIT 1")
+                .ndl("Failed to check for ROM in catalog DB")?;
+            statement
+                .query_one((md5,), |row| row.get(0))
+                .optional()
+                .ndl("Failed to check for ROM in catalog DB")
+        })
+    }
+
+    /// Finds the game with a ROM matching the given hash, returning its gid
+    ///
+    /// Only some datafiles list SHA-256s, so this finds fewer ROMs than the other hashes.
+    pub fn is_rom_sha256(&self, sha256: [u8; 32]) -> Result<Option<i64>> {
+        self.pool.with_connection(|connection| {
+            let mut statement = connection
+                .prepare_cached("SELECT gid FROM roms WHERE sha256 = ? LIMIT 1")
+                .ndl("Failed to check for ROM in catalog DB")?;
+            statement
+                .query_one((sha256,), |row| row.get(0))
+                .optional()
+                .ndl("Failed to check for ROM in catalog DB")
+        })
+    }
+
+    /// Finds the game with a ROM matching the given size and CRC32, returning its gid
+    ///
+    /// CRC32s collide far more often than the other hashes, so the size narrows the match down.
+    pub fn is_rom_size_crc(&self, size: usize, crc32: u32) -> Result<Option<i64>> {
+        self.pool.with_connection(|connection| {
+            let mut statement = connection
+                .prepare_cached("SELECT gid FROM roms WHERE size = ? AND crc32 = ? LIMIT 1")
+                .ndl("Failed to check for ROM in catalog DB")?;
+            // CRC32s are stored as signed integers (see parse_game_rom)
+            statement
+                .query_one((size, crc32 as i32), |row| row.get(0))
+                .optional()
+                .ndl("Failed to check for ROM in catalog DB")
+        })
+    }
+
+    /// The dump status the datafile gives the ROM with the given hash ("verified" or "baddump"),
+    /// if any
+    ///
+    /// Where several games have the ROM, it's only a bad dump if every one of them says so.
+    pub fn rom_dump_status(&self, sha1: [u8; 20]) -> Result<Option<&'static str>> {
+        self.pool.with_connection(|connection| {
+            let mut statement = connection
+                .prepare_cached(
+                    "SELECT status FROM roms WHERE sha1 = ? ORDER BY status IS ? LIMIT 1",
+                )
+                .ndl("Failed to look up ROM in catalog DB")?;
+            let status: Option<Option<Status>> = statement
+                .query_one((sha1, Status::BadDump), |row| row.get(0))
+                .optional()
+                .ndl("Failed to look up ROM in catalog DB")?;
+            Ok(status.flatten().and_then(|status| status.name()))
+        })
+    }
+
+    /// Finds the ROM with the given hash, returning its game's name and its own file name
+    ///
+    /// If several datafiles have the ROM, the one with the lowest priority value wins, like in
+    /// `resolved_games`.
+    pub fn find_rom_name(&self, sha1: [u8; 20]) -> Result<Option<(String, String)>> {
+        self.pool.with_connection(|connection| {
+            let mut statement = connection
+                .prepare_cached(
+                    r#"
+                        SELECT "games"."name", "roms"."name" FROM "roms"
+                        JOIN "games" ON "games"."gid" = "roms"."gid"
+                        LEFT JOIN "console_datafiles" ON "console_datafiles"."dfid" = "games"."dfid"
+                        WHERE "roms"."sha1" = ?
+                        ORDER BY "console_datafiles"."priority" IS NULL, "console_datafiles"."priority", "games"."dfid", "games"."gid"
+                        LIMIT 1
+                    "#,
+                )
+                .ndl("Failed to look up ROM in catalog DB")?;
+            let names = statement
+                .query_one((sha1,), |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+                })
+                .optional()
+                .ndl("Failed to look up ROM in catalog DB")?;
+            Ok(names.map(|(game_name, rom_name)| {
+                let rom_name = decompress_rom_name(&rom_name, &game_name);
+                (game_name, rom_name)
+            }))
+        })
+    }
+
+    /// Finds the game with a ROM matching the given hash, after resolving conflicts between
+    /// datafiles, along with the ROM's file name
+    ///
+    pub fn find_rom_game(&self, sha1: [u8; 20]) -> Result<Option<(GameEntry, String)>> {
+        let Some((_, rom_name)) = self.find_rom_name(sha1)? else {
+            return Ok(None);
+        };
+        let game = self
+            .find_games(
+                r#"EXISTS (SELECT 1 FROM "roms" WHERE "roms"."gid" = "r"."gid" AND "roms"."sha1" = ?)"#,
+                &[Value::Blob(sha1.to_vec())],
+                false,
+            )?
+            .into_iter()
+            .next();
+        Ok(game.map(|game| (game, rom_name)))
+    }
+
+    /// Finds a console's games with the given serial, including blocked games
+    ///
+    /// Datafiles may list several serials for a game, separated by commas, any of which matches.
+    pub fn find_games_by_serial(
+        &self,
+        console: GameConsole,
+        serial: &str,
+    ) -> Result<Vec<GameEntry>> {
+        let games = self.find_games(
+            r#""r"."console" = ? AND "g"."serial" LIKE ? ESCAPE '\'"#,
+            &[
+                Value::Text(console.formal_name().to_string()),
+                Value::Text(format!("%{}%", wildcard_to_like(serial))),
+            ],
+            false,
+        )?;
+        Ok(games
+            .into_iter()
+            .filter(|game| {
+                game.serial.as_deref().is_some_and(|serials| {
+                    serials
+                        .split(',')
+                        .any(|listed| listed.trim().eq_ignore_ascii_case(serial))
+                })
+            })
+            .collect())
+    }
+
+    /// Summarizes every datafile stored in the catalog
+    ///
+    pub fn datafile_info(&self) -> Result<Vec<DatafileInfo>> {
+        self.pool.with_connection(|connection| {
+            let mut statement = connection
+                .prepare_cached(
+                    r#"
+                        SELECT "name", "author", "version", "last_updated",
+                            (SELECT COUNT(*) FROM "games" WHERE "games"."dfid" = "datafiles"."dfid"),
+                            (SELECT COUNT(*) FROM "roms" JOIN "games" ON "roms"."gid" = "games"."gid" WHERE "games"."dfid" = "datafiles"."dfid")
+                        FROM "datafiles"
+                        ORDER BY "name"
+                    "#,
+                )
+                .ndl("Failed to retrieve datafile meta from catalog DB")?;
+            let rows = statement
+                .query_map((), |row| {
+                    Ok(DatafileInfo {
+                        name: row.get(0)?,
+                        author: row.get(1)?,
+                        version: row.get(2)?,
+                        last_updated: DateTime::from_timestamp_millis(row.get(3)?).unwrap(),
+                        game_count: row.get(4)?,
+                        rom_count: row.get(5)?,
+                    })
+                })
+                .ndl("Failed to retrieve datafile meta from catalog DB")?;
+            let mut datafiles = Vec::new();
+            for row in rows {
+                datafiles.push(row.ndl("Failed to retrieve datafile meta from catalog DB")?);
+            }
+            Ok(datafiles)
+        })
+    }
+
+    /// How big dumps of `console` converted to `format` have come out, as a share of their sources'
+    /// size, or `None` if nothing has been converted to it yet
+    ///
+    /// Until a few of the console's dumps have been converted, every console's conversions are used.
+    pub fn conversion_ratio(
+        &self,
+        console: Option<GameConsole>,
+        format: &str,
+    ) -> Result<Option<f64>> {
+        self.pool.with_connection(|connection| {
+            super::conversions::conversion_ratio(connection, console, format)
+        })
+    }
+
+    /// Lists the names of a console's games, excluding blocked games
+    ///
+    pub fn console_games(&self, console: GameConsole) -> Result<Vec<String>> {
+        self.pool.with_connection(|connection| {
+            let mut statement = connection
+                .prepare_cached(
+                    "SELECT gid, name FROM resolved_games WHERE console = ? ORDER BY name",
+                )
+                .ndl("Failed to retrieve games from catalog DB")?;
+            let games = statement
+                .query_map((console.formal_name(),), |row| {
+                    Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+                })
+                .ndl("Failed to retrieve games from catalog DB")?;
+            let mut result = Vec::new();
+            for game in games {
+                let (gid, name) = game.ndl("Failed to retrieve games from catalog DB")?;
+                if !self.is_blocked(gid, &name) {
+                    result.push(name);
+                }
+            }
+            Ok(result)
+        })
+    }
+
+    /// Lists the games matching an SQL condition over `resolved_games` (as `r`) joined with `games`
+    /// (as `g`) and `datafiles` (as `d`), excluding blocked games if `exclude_blocked` is set
+    fn find_games(
+        &self,
+        condition: &str,
+        parameters: &[Value],
+        exclude_blocked: bool,
+    ) -> Result<Vec<GameEntry>> {
+        self.pool.with_connection(|connection| {
+            let mut statement = connection
+                .prepare_cached(&format!(
+                    r#"
+                        SELECT "r"."gid", "r"."console", "r"."name", "r"."revision", "d"."name", "g"."serial", "g"."region"
+                        FROM "resolved_games" AS "r"
+                        JOIN "games" AS "g" ON "g"."gid" = "r"."gid"
+                        JOIN "datafiles" AS "d" ON "d"."dfid" = "r"."dfid"
+                        WHERE {condition}
+                        ORDER BY "r"."console", "r"."name"
+                    "#
+                ))
+                .ndl("Failed to search games in catalog DB")?;
+            let games = statement
+                .query_map(params_from_iter(parameters), |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, i64>(3)?,
+                        row.get::<_, String>(4)?,
+                        row.get::<_, Option<String>>(5)?,
+                        row.get::<_, Option<String>>(6)?,
+                    ))
+                })
+                .ndl("Failed to search games in catalog DB")?;
+            let mut result = Vec::new();
+            for game in games {
+                let (gid, console, name, revision, datafile, serial, region) =
+                    game.ndl("Failed to search games in catalog DB")?;
+                if exclude_blocked && self.is_blocked(gid, &name) {
+                    continue;
+                }
+                result.push(GameEntry {
+                    gid,
+                    console: console.parse()?,
+                    name,
+                    revision,
+                    datafile,
+                    serial,
+                    region,
+                });
+            }
+            Ok(result)
+        })
+    }
+
+    /// Finds the games named exactly `name` on any console, excluding blocked games
+    ///
+    pub fn find_games_by_name(&self, name: &str) -> Result<Vec<GameEntry>> {
+        self.find_games(r#""r"."name" = ?"#, &[Value::Text(name.to_string())], true)
+    }
+
+    /// Finds games on any console whose names match a case-insensitive wildcard pattern, excluding
+    /// blocked games
+    ///
+    pub fn search_games(&self, pattern: &str) -> Result<Vec<GameEntry>> {
+        self.find_games(
+            r#""r"."name" LIKE ? ESCAPE '\'"#,
+            &[Value::Text(wildcard_to_like(pattern))],
+            true,
+        )
+    }
+
+    /// Finds the games whose names most resemble `text`, excluding blocked games
+    ///
+    /// This is for suggesting corrections when a lookup finds nothing. Games resembling `text` too
+    /// little aren't suggested at all, so there may be fewer than `limit`.
+    pub fn suggest_games(&self, text: &str, limit: usize) -> Result<Vec<GameEntry>> {
+        let mut scored: Vec<(f64, GameEntry)> = self
+            .find_games("1", &[], true)?
+            .into_iter()
+            .map(|game| (trigram_similarity(text, &game.name), game))
+            .filter(|(score, _)| *score >= MIN_SUGGESTION_SIMILARITY)
+            .collect();
+        // prefer closer matches, then shorter names (which have less that wasn't asked for)
+        scored.sort_by(|(a_score, a), (b_score, b)| {
+            b_score
+                .total_cmp(a_score)
+                .then(a.name.len().cmp(&b.name.len()))
+        });
+        Ok(scored
+            .into_iter()
+            .take(limit)
+            .map(|(_, game)| game)
+            .collect())
+    }
+
+    /// Lists the games matching a query, including blocked games
+    ///
+    pub fn query_games(&self, query: &GameQuery) -> Result<Vec<GameEntry>> {
+        let (condition, parameters) = query.to_sql()?;
+        self.find_games(&condition, &parameters, false)
+    }
+
+    /// Lists every game in the catalog, including blocked games
+    ///
+    pub fn all_games(&self) -> Result<Vec<GameEntry>> {
+        self.find_games("1", &[], false)
+    }
+
+    /// Lists the names of a game's categories
+    ///
+    pub fn game_categories(&self, gid: i64) -> Result<Vec<&'static str>> {
+        self.pool.with_connection(|connection| {
+            let mut statement = connection
+                .prepare_cached("SELECT category FROM game_categories WHERE gid = ?")
+                .ndl("Failed to retrieve game categories from catalog DB")?;
+            let categories = statement
+                .query_map((gid,), |row| row.get::<_, Category>(0))
+                .ndl("Failed to retrieve game categories from catalog DB")?;
+            let mut result = Vec::new();
+            for category in categories {
+                let category =
+                    category.ndl("Failed to retrieve game categories from catalog DB")?;
+                result.extend(category.name());
+            }
+            Ok(result)
+        })
+    }
+
+    /// Reads every disc track (.bin ROM) in the catalog into an index, for matching many tracks by
+    /// their contents
+    ///
+    pub fn track_index(&self) -> Result<TrackIndex> {
+        self.pool.with_connection(|connection| {
+            let mut statement = connection
+                .prepare_cached(
+                    r#"
+                        SELECT "r"."gid", "r"."console", "r"."name", "roms"."name", "roms"."size", "roms"."crc32", "roms"."sha1"
+                        FROM "resolved_games" AS "r"
+                        JOIN "roms" ON "roms"."gid" = "r"."gid"
+                    "#,
+                )
+                .ndl("Failed to retrieve tracks from catalog DB")?;
+            let roms = statement
+                .query_map((), |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, u64>(4)?,
+                        row.get::<_, i32>(5)? as u32,
+                        row.get::<_, [u8; 20]>(6)?,
+                    ))
+                })
+                .ndl("Failed to retrieve tracks from catalog DB")?;
+            let mut index = TrackIndex::default();
+            for rom in roms {
+                let (gid, console, game_name, rom_name, size, crc32, sha1) =
+                    rom.ndl("Failed to retrieve tracks from catalog DB")?;
+                let rom_name = decompress_rom_name(&rom_name, &game_name);
+                if !rom_name.ends_with(".bin") {
+                    continue;
+                }
+                index.insert_game(gid, console.parse()?, game_name);
+                index.insert_track(gid, rom_name, size, crc32, sha1);
+            }
+            Ok(index)
+        })
+    }
+
+    /// Lists a game's ROMs, sorted by name
+    ///
+    pub fn game_roms(&self, game: &GameEntry) -> Result<Vec<CatalogROM>> {
+        self.pool.with_connection(|connection| {
+            let mut statement = connection
+                .prepare_cached(
+                    "SELECT name, status, size, crc32, md5, sha1, sha256 FROM roms WHERE gid = ?",
+                )
+                .ndl("Failed to retrieve ROMs from catalog DB")?;
+            let roms = statement
+                .query_map((game.gid,), |row| {
+                    Ok(CatalogROM {
+                        name: decompress_rom_name(&row.get::<_, String>(0)?, &game.name),
+                        status: row
+                            .get::<_, Option<Status>>(1)?
+                            .and_then(|status| status.name()),
+                        size: row.get(2)?,
+                        crc32: row.get::<_, i32>(3)? as u32,
+                        md5: row.get(4)?,
+                        sha1: row.get(5)?,
+                        sha256: row.get(6)?,
+                    })
+                })
+                .ndl("Failed to retrieve ROMs from catalog DB")?;
+            let mut result = Vec::new();
+            for rom in roms {
+                result.push(rom.ndl("Failed to retrieve ROMs from catalog DB")?);
+            }
+            result.sort_by(|a, b| a.name.cmp(&b.name));
+            Ok(result)
+        })
+    }
+}
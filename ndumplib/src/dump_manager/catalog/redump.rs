@@ -6,8 +6,13 @@ use std::{
 use compress_tools::{Ownership, uncompress_archive};
 use log::debug;
 use tempfile::{NamedTempFile, tempdir};
+use ureq::Agent;
 
-use crate::{Error, GameConsole, Result, ResultUtils};
+use super::{AvailableDatafile, DatafileSource, FetchedDatafile};
+use crate::{
+    Error, GameConsole, Result, ResultUtils, UpdateTarget,
+    dump_manager::network::{Deadline, NetworkTimeouts},
+};
 
 impl GameConsole {
     pub(super) fn redump_datafile_name(&self) -> Option<&str> {
@@ -40,13 +45,41 @@ impl GameConsole {
     }
 }
 
-pub(super) fn download_datafile(slug: &str) -> Result<String> {
-    let url: String = format!("http://redump.org/datfile/{slug}/");
+/// Downloads a Redump datafile
+///
+/// If `etag` or `last_modified` are given, the request is made conditional on them.
+/// Returns `None` if Redump reports that the datafile hasn't changed.
+fn download_datafile(
+    agent: &Agent,
+    deadline: &Deadline,
+    url: &str,
+    etag: Option<&str>,
+    last_modified: Option<&str>,
+) -> Result<Option<FetchedDatafile>> {
     let zip_file = NamedTempFile::with_suffix(".zip")
         .ndl("Failed to create temporary file to download datafile")?;
     let extracted_files = tempdir().ndl("Failed to create directory file to extract datafile")?;
-    {
-        let mut response = ureq::get(url).call().ndl("Failed to start download")?;
+    let (etag, last_modified) = {
+        let mut request = deadline.limit(agent.get(url))?;
+        if let Some(etag) = etag {
+            request = request.header("If-None-Match", etag);
+        }
+        if let Some(last_modified) = last_modified {
+            request = request.header("If-Modified-Since", last_modified);
+        }
+        let mut response = request.call().ndl("Failed to start download")?;
+        if response.status() == 304 {
+            debug!("Datafile \"{url}\" was not modified");
+            return Ok(None);
+        }
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+        let validators = (header("ETag"), header("Last-Modified"));
         let file = zip_file
             .as_file()
             .try_clone()
@@ -58,7 +91,8 @@ pub(super) fn download_datafile(slug: &str) -> Result<String> {
             "Downloaded zipped datafile to \"{}\"",
             zip_file.path().to_str().unwrap()
         );
-    }
+        validators
+    };
     uncompress_archive(
         BufReader::new(zip_file),
         extracted_files.path(),
@@ -89,5 +123,84 @@ pub(super) fn download_datafile(slug: &str) -> Result<String> {
     let mut contents = String::new();
     file.read_to_string(&mut contents)
         .ndl("Failed to read datafile")?;
-    Ok(contents)
+    Ok(Some(FetchedDatafile {
+        content: contents,
+        etag,
+        last_modified,
+    }))
+}
+
+/// Downloads disc datafiles from Redump
+pub struct RedumpSource {
+    agent: Agent,
+    timeouts: NetworkTimeouts,
+}
+
+impl RedumpSource {
+    pub fn new() -> RedumpSource {
+        Self::with_timeouts(NetworkTimeouts::default())
+    }
+
+    pub fn with_timeouts(timeouts: NetworkTimeouts) -> RedumpSource {
+        RedumpSource {
+            agent: timeouts.agent(),
+            timeouts,
+        }
+    }
+}
+
+impl Default for RedumpSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DatafileSource for RedumpSource {
+    fn author(&self) -> &str {
+        "Redump"
+    }
+
+    fn consoles(&self) -> Vec<GameConsole> {
+        GameConsole::ALL
+            .into_iter()
+            .filter(|console| console.redump_slug().is_some())
+            .collect()
+    }
+
+    fn is_covered_by(&self, target: &UpdateTarget, console: GameConsole) -> bool {
+        target.covers_redump(console)
+    }
+
+    /// Redump doesn't say when its datafiles change, so they're fetched conditionally instead
+    ///
+    fn list_datafiles(&self) -> Result<Vec<AvailableDatafile>> {
+        Ok(self
+            .consoles()
+            .into_iter()
+            .map(|console| AvailableDatafile {
+                console,
+                name: console.redump_datafile_name().unwrap().to_string(),
+                last_updated: None,
+                location: format!(
+                    "http://redump.org/datfile/{}/",
+                    console.redump_slug().unwrap()
+                ),
+            })
+            .collect())
+    }
+
+    fn fetch_datafile(
+        &self,
+        datafile: &AvailableDatafile,
+        etag: Option<&str>,
+        last_modified: Option<&str>,
+    ) -> Result<Option<FetchedDatafile>> {
+        download_datafile(
+            &self.agent,
+            &self.timeouts.start_deadline(),
+            &datafile.location,
+            etag,
+            last_modified,
+        )
+    }
 }
@@ -0,0 +1,140 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+
+use crate::{Error, ErrorCategory, GameConsole, Result, ResultUtils, UpdateTarget};
+
+/// A datafile offered by a [DatafileSource]
+#[derive(Clone)]
+pub struct AvailableDatafile {
+    pub console: GameConsole,
+    /// The name the datafile is stored under in the catalog
+    pub name: String,
+    /// When the datafile last changed, if the source can tell without fetching it
+    ///
+    /// Without this, a fetched datafile is only imported if its version changed.
+    pub last_updated: Option<DateTime<Utc>>,
+    /// Where the source fetches the datafile from (e.g. a URL)
+    pub location: String,
+}
+
+/// A datafile's content, along with the cache validators sent with it
+pub struct FetchedDatafile {
+    pub content: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// Somewhere the catalog gets its datafiles from, like Redump or No-Intro
+pub trait DatafileSource: Send + Sync {
+    /// The author stored with this source's datafiles (e.g. "Redump")
+    ///
+    /// This must differ between sources, and must not be "Local", which is used for
+    /// [crate::LocalDatafile]s.
+    fn author(&self) -> &str;
+
+    /// The consoles this source has datafiles for
+    ///
+    fn consoles(&self) -> Vec<GameConsole>;
+
+    /// Whether a forced update of `target` includes this source's datafile for `console`
+    ///
+    fn is_covered_by(&self, target: &UpdateTarget, console: GameConsole) -> bool {
+        matches!(target, UpdateTarget::All) || *target == UpdateTarget::Console(console)
+    }
+
+    /// Lists the datafiles which can currently be fetched
+    ///
+    fn list_datafiles(&self) -> Result<Vec<AvailableDatafile>>;
+
+    /// Fetches a datafile listed by [DatafileSource::list_datafiles]
+    ///
+    /// `etag` and `last_modified` are the cache validators sent with the last fetch, if any.
+    /// Returns `None` if the source reports that the datafile hasn't changed.
+    fn fetch_datafile(
+        &self,
+        datafile: &AvailableDatafile,
+        etag: Option<&str>,
+        last_modified: Option<&str>,
+    ) -> Result<Option<FetchedDatafile>>;
+}
+
+/// Serves datafiles from disk in place of a website, for testing or offline use
+pub struct FileDatafileSource {
+    author: String,
+    datafiles: Vec<(GameConsole, String, PathBuf)>,
+}
+
+impl FileDatafileSource {
+    pub fn new(author: &str) -> FileDatafileSource {
+        FileDatafileSource {
+            author: author.to_string(),
+            datafiles: Vec::new(),
+        }
+    }
+
+    /// Serves the datafile at `path` as `console`'s datafile called `name`
+    ///
+    pub fn with_datafile(
+        mut self,
+        console: GameConsole,
+        name: &str,
+        path: impl Into<PathBuf>,
+    ) -> FileDatafileSource {
+        self.datafiles
+            .push((console, name.to_string(), path.into()));
+        self
+    }
+}
+
+impl DatafileSource for FileDatafileSource {
+    fn author(&self) -> &str {
+        &self.author
+    }
+
+    fn consoles(&self) -> Vec<GameConsole> {
+        self.datafiles
+            .iter()
+            .map(|(console, _, _)| *console)
+            .collect()
+    }
+
+    fn list_datafiles(&self) -> Result<Vec<AvailableDatafile>> {
+        let mut datafiles = Vec::new();
+        for (console, name, path) in &self.datafiles {
+            // the location is handed back to fetch_datafile as a string
+            let Some(location) = path.to_str().map(str::to_string) else {
+                return Err(Error::new_original(format!(
+                    "Failed to read datafile \"{}\": its path isn't valid UTF-8",
+                    path.display()
+                ))
+                .with_category(ErrorCategory::InvalidInput));
+            };
+            let modified = std::fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ndl(format!("Failed to read datafile \"{location}\""))?;
+            datafiles.push(AvailableDatafile {
+                console: *console,
+                name: name.clone(),
+                last_updated: Some(modified.into()),
+                location,
+            });
+        }
+        Ok(datafiles)
+    }
+
+    fn fetch_datafile(
+        &self,
+        datafile: &AvailableDatafile,
+        _etag: Option<&str>,
+        _last_modified: Option<&str>,
+    ) -> Result<Option<FetchedDatafile>> {
+        let content = std::fs::read_to_string(&datafile.location)
+            .ndl(format!("Failed to read datafile \"{}\"", datafile.location))?;
+        Ok(Some(FetchedDatafile {
+            content,
+            etag: None,
+            last_modified: None,
+        }))
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use crate::{GameConsole, HashAlgorithm, MultiHasher, Result, ResultUtils};
+
+/// A game with tracks in a [TrackIndex]
+pub struct IndexedGame {
+    pub console: GameConsole,
+    pub name: String,
+    /// The names of the game's tracks, sorted
+    pub tracks: Vec<String>,
+}
+
+struct IndexedTrack {
+    gid: i64,
+    name: String,
+    sha1: [u8; 20],
+}
+
+/// The catalog's disc tracks (.bin ROMs) by size and CRC32, for matching tracks whatever they're
+/// named
+///
+/// It's read from the catalog once (see [crate::CatalogReader::track_index]), so matching many
+/// tracks doesn't query the catalog for each one. Files whose size no track has aren't hashed at
+/// all, and the rest are confirmed by SHA-1, since CRC32s collide too often to go by alone.
+#[derive(Default)]
+pub struct TrackIndex {
+    tracks: HashMap<(u64, u32), Vec<IndexedTrack>>,
+    sizes: HashSet<u64>,
+    games: HashMap<i64, IndexedGame>,
+}
+
+impl TrackIndex {
+    pub(super) fn insert_game(&mut self, gid: i64, console: GameConsole, name: String) {
+        self.games.entry(gid).or_insert(IndexedGame {
+            console,
+            name,
+            tracks: Vec::new(),
+        });
+    }
+
+    /// Adds a track of a game already added with [TrackIndex::insert_game]
+    pub(super) fn insert_track(
+        &mut self,
+        gid: i64,
+        name: String,
+        size: u64,
+        crc32: u32,
+        sha1: [u8; 20],
+    ) {
+        let game = self.games.get_mut(&gid).unwrap();
+        // a game listed under several consoles has its tracks read once for each
+        let Err(position) = game.tracks.binary_search(&name) else {
+            return;
+        };
+        game.tracks.insert(position, name.clone());
+        self.sizes.insert(size);
+        self.tracks
+            .entry((size, crc32))
+            .or_default()
+            .push(IndexedTrack { gid, name, sha1 });
+    }
+
+    /// Finds the track a file is, returning its game's gid and its name in the catalog
+    ///
+    pub fn find(&self, path: &impl AsRef<Path>) -> Result<Option<(i64, &str)>> {
+        let size = std::fs::metadata(path).ndl("Failed to match track")?.len();
+        if !self.sizes.contains(&size) {
+            return Ok(None);
+        }
+        let digests = MultiHasher::new(&[HashAlgorithm::CRC32, HashAlgorithm::SHA1])
+            .hash_file(path, false)?;
+        // both were asked for
+        let (crc32, sha1) = (digests.crc32.unwrap(), digests.sha1.unwrap());
+        Ok(self
+            .tracks
+            .get(&(size, crc32))
+            .and_then(|tracks| tracks.iter().find(|track| track.sha1 == sha1))
+            .map(|track| (track.gid, track.name.as_str())))
+    }
+
+    pub fn game(&self, gid: i64) -> Option<&IndexedGame> {
+        self.games.get(&gid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha1::{Digest, Sha1};
+
+    use super::*;
+
+    #[test]
+    fn finds_renamed_tracks() {
+        let directory = tempfile::tempdir().unwrap();
+        let track = directory.path().join("renamed.bin");
+        std::fs::write(&track, b"test").unwrap();
+        let mut index = TrackIndex::default();
+        index.insert_game(1, GameConsole::PSX, "Game".to_string());
+        let sha1: [u8; 20] = Sha1::digest(b"test").into();
+        index.insert_track(
+            1,
+            "Game (Track 1).bin".to_string(),
+            4,
+            crc32fast::hash(b"test"),
+            sha1,
+        );
+        // same size and CRC32, but another SHA-1
+        index.insert_track(
+            1,
+            "Game (Track 2).bin".to_string(),
+            4,
+            crc32fast::hash(b"test"),
+            [0; 20],
+        );
+        assert_eq!(index.find(&track).unwrap(), Some((1, "Game (Track 1).bin")));
+        std::fs::write(&track, b"other").unwrap();
+        assert_eq!(index.find(&track).unwrap(), None);
+        assert_eq!(index.game(1).unwrap().tracks.len(), 2);
+    }
+}
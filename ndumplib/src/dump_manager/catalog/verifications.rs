@@ -0,0 +1,79 @@
+use chrono::{DateTime, Utc};
+use rusqlite::OptionalExtension;
+
+use crate::{Result, ResultUtils, utils::CanPrepare};
+
+/// Records that the CHD whose header has `sha1` passed `chdman verify`
+///
+pub(super) fn record_chd_verification(
+    connection: &impl CanPrepare,
+    sha1: [u8; 20],
+    verified: DateTime<Utc>,
+) -> Result<()> {
+    let mut statement = connection
+        .prepare_cached_common(
+            r#"INSERT INTO "chd_verifications" ("sha1", "verified") VALUES (?, ?)
+            ON CONFLICT ("sha1") DO UPDATE SET "verified" = "excluded"."verified""#,
+        )
+        .ndl("Failed to record CHD verification in catalog DB")?;
+    statement
+        .execute((sha1, verified.timestamp_millis()))
+        .ndl("Failed to record CHD verification in catalog DB")?;
+    Ok(())
+}
+
+/// When the CHD whose header has `sha1` last passed `chdman verify`, or `None` if it never has
+///
+pub(super) fn last_chd_verification(
+    connection: &impl CanPrepare,
+    sha1: [u8; 20],
+) -> Result<Option<DateTime<Utc>>> {
+    let mut statement = connection
+        .prepare_cached_common(r#"SELECT "verified" FROM "chd_verifications" WHERE "sha1" = ?"#)
+        .ndl("Failed to retrieve CHD verification from catalog DB")?;
+    let millis: Option<i64> = statement
+        .query_row((sha1,), |row| row.get(0))
+        .optional()
+        .ndl("Failed to retrieve CHD verification from catalog DB")?;
+    Ok(millis.and_then(DateTime::from_timestamp_millis))
+}
+
+/// Records the SHA-1s of the tracks of the CHD whose header has `sha1`, in order
+///
+pub(super) fn record_chd_tracks(
+    connection: &impl CanPrepare,
+    sha1: [u8; 20],
+    tracks: &[[u8; 20]],
+) -> Result<()> {
+    let mut statement = connection
+        .prepare_cached_common(
+            r#"INSERT INTO "chd_tracks" ("sha1", "tracks") VALUES (?, ?)
+            ON CONFLICT ("sha1") DO UPDATE SET "tracks" = "excluded"."tracks""#,
+        )
+        .ndl("Failed to record CHD tracks in catalog DB")?;
+    statement
+        .execute((sha1, tracks.concat()))
+        .ndl("Failed to record CHD tracks in catalog DB")?;
+    Ok(())
+}
+
+/// The SHA-1s of the tracks of the CHD whose header has `sha1`, or `None` if they weren't recorded
+///
+pub(super) fn chd_tracks(
+    connection: &impl CanPrepare,
+    sha1: [u8; 20],
+) -> Result<Option<Vec<[u8; 20]>>> {
+    let mut statement = connection
+        .prepare_cached_common(r#"SELECT "tracks" FROM "chd_tracks" WHERE "sha1" = ?"#)
+        .ndl("Failed to retrieve CHD tracks from catalog DB")?;
+    let tracks: Option<Vec<u8>> = statement
+        .query_row((sha1,), |row| row.get(0))
+        .optional()
+        .ndl("Failed to retrieve CHD tracks from catalog DB")?;
+    Ok(tracks.map(|tracks| {
+        tracks
+            .chunks_exact(20)
+            .map(|sha1| sha1.try_into().unwrap())
+            .collect()
+    }))
+}
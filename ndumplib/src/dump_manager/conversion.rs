@@ -0,0 +1,386 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{Condvar, Mutex},
+};
+
+use log::{debug, info};
+
+use super::{
+    CatalogReader, SourceHandling, Stage, WarningKind, WarningScope, report_warning, timings,
+};
+use crate::{
+    Error, ErrorCategory, GameConsole, Result, ResultUtils,
+    transfer::write_atomically,
+    utils::{chdman, serial, work},
+};
+
+/// A dump for [crate::DumpManager::convert_files] to convert to a CHD
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConversionJob {
+    /// The cuesheet, GDI, or ISO to convert
+    pub path: PathBuf,
+    /// Where the CHD is created
+    pub output_directory: PathBuf,
+    pub sources: SourceHandling,
+}
+
+/// How many CHDs are created at once, and how much of the machine each one gets
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConversionLimits {
+    /// How many chdman processes run at once (by default, as many as there are cores for, given
+    /// [ConversionLimits::chdman_threads])
+    pub jobs: Option<usize>,
+    /// How many threads each chdman compresses with (by default, one per core)
+    pub chdman_threads: Option<usize>,
+    /// How many of the jobs may read from the same disk at once (by default, one, since reading a
+    /// disk from several places at once slows it down more than the extra compression makes up for)
+    pub jobs_per_device: Option<usize>,
+}
+
+impl ConversionLimits {
+    /// How many chdman processes run at once
+    ///
+    pub fn job_count(&self) -> usize {
+        let cores = std::thread::available_parallelism().map_or(1, |count| count.get());
+        self.jobs
+            .unwrap_or_else(|| cores / self.chdman_threads.unwrap_or(cores).max(1))
+            .max(1)
+    }
+}
+
+/// The device a file is stored on, which jobs are throttled by
+///
+/// Returns `None` if the file can't be read, in which case its job fails on its own.
+fn source_device(path: &Path) -> Option<u64> {
+    std::fs::metadata(path).ok().map(|metadata| metadata.dev())
+}
+
+/// The jobs waiting to run, and how many are running on each device
+struct QueueState {
+    pending: Vec<usize>,
+    running: HashMap<u64, usize>,
+}
+
+/// Frees a job's slot on its device once it finishes, even if it panics, so other workers don't
+/// wait on it forever
+struct DeviceSlot<'a> {
+    state: &'a Mutex<QueueState>,
+    finished: &'a Condvar,
+    device: Option<u64>,
+}
+
+impl Drop for DeviceSlot<'_> {
+    fn drop(&mut self) {
+        if let Some(device) = self.device {
+            let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+            if let Some(running) = state.running.get_mut(&device) {
+                *running -= 1;
+            }
+        }
+        self.finished.notify_all();
+    }
+}
+
+/// Runs `run` for each job on up to `workers` threads, with at most `per_device` of the jobs on
+/// each device running at once
+///
+/// Jobs start in order, except that ones whose device is busy are passed over for later ones. The
+/// results are in the same order as `devices`.
+fn run_queue<T: Send>(
+    devices: &[Option<u64>],
+    workers: usize,
+    per_device: usize,
+    run: impl Fn(usize) -> T + Sync,
+) -> Vec<T> {
+    let state = Mutex::new(QueueState {
+        pending: (0..devices.len()).collect(),
+        running: HashMap::new(),
+    });
+    let finished = Condvar::new();
+    let per_device = per_device.max(1);
+    let next_job = || {
+        let mut state = state.lock().unwrap();
+        loop {
+            if state.pending.is_empty() {
+                return None;
+            }
+            let ready = state
+                .pending
+                .iter()
+                .position(|&index| match devices[index] {
+                    Some(device) => state.running.get(&device).copied().unwrap_or(0) < per_device,
+                    None => true,
+                });
+            if let Some(position) = ready {
+                let index = state.pending.remove(position);
+                if let Some(device) = devices[index] {
+                    *state.running.entry(device).or_default() += 1;
+                }
+                return Some(index);
+            }
+            state = finished.wait(state).unwrap();
+        }
+    };
+    let mut results: Vec<Option<T>> = Vec::with_capacity(devices.len());
+    results.resize_with(devices.len(), || None);
+    let warnings = WarningScope::current();
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..workers.clamp(1, devices.len().max(1)))
+            .map(|_| {
+                let warnings = warnings.clone();
+                scope.spawn(|| {
+                    WarningScope::enter(warnings, || {
+                        let mut results = Vec::new();
+                        while let Some(index) = next_job() {
+                            let _slot = DeviceSlot {
+                                state: &state,
+                                finished: &finished,
+                                device: devices[index],
+                            };
+                            results.push((index, run(index)));
+                        }
+                        results
+                    })
+                })
+            })
+            .collect();
+        for worker in workers {
+            for (index, result) in worker.join().unwrap() {
+                results[index] = Some(result);
+            }
+        }
+    });
+    results.into_iter().map(|result| result.unwrap()).collect()
+}
+
+/// Converts several dumps to CHDs, running chdman on several of them at once (see
+/// [ConversionLimits])
+///
+pub(crate) fn convert_all(
+    reader: &CatalogReader,
+    jobs: &[ConversionJob],
+    limits: ConversionLimits,
+) -> Vec<Result<Option<PathBuf>>> {
+    let devices: Vec<Option<u64>> = jobs.iter().map(|job| source_device(&job.path)).collect();
+    // jobs creating the same CHD would clobber each other's output, so only the first one runs
+    let mut outputs = HashSet::new();
+    let duplicates: Vec<bool> = jobs
+        .iter()
+        .map(|job| !outputs.insert(chd_path(&job.path, &job.output_directory)))
+        .collect();
+    run_queue(
+        &devices,
+        limits.job_count(),
+        limits.jobs_per_device.unwrap_or(1),
+        |index| {
+            let job = &jobs[index];
+            if duplicates[index] {
+                let output = chd_path(&job.path, &job.output_directory);
+                return Err(already_exists(&job.path, &output));
+            }
+            convert_to_chd(
+                reader,
+                &job.path,
+                &job.output_directory,
+                job.sources,
+                limits.chdman_threads,
+            )
+        },
+    )
+}
+
+/// Where a dump's CHD is created
+///
+fn chd_path(path: &Path, output_directory: &Path) -> PathBuf {
+    let mut output = output_directory.join(path.file_name().unwrap());
+    output.set_extension("chd");
+    output
+}
+
+fn already_exists(path: &Path, output: &Path) -> Error {
+    Error::new_original(format!(
+        "Failed to convert \"{}\"\n\"{}\" already exists",
+        path.display(),
+        output.display()
+    ))
+    .with_category(ErrorCategory::IO)
+}
+
+/// How much bigger than the conversions recorded so far a conversion is allowed for, since dumps
+/// don't all compress alike
+const ESTIMATE_MARGIN: f64 = 1.25;
+
+/// A dump about to be converted, whose conversion is estimated from, and then added to, the
+/// catalog's history of conversions (see [CatalogReader::conversion_ratio])
+pub(crate) struct ConversionSource {
+    /// The dump's console, if it can be read from the disc without extracting it
+    pub(crate) console: Option<GameConsole>,
+    /// The size of the dump's files altogether
+    pub(crate) size: u64,
+}
+
+impl ConversionSource {
+    pub(crate) fn read(path: &Path) -> Result<ConversionSource> {
+        ConversionSource::of_files(&super::dump_files(path)?)
+    }
+
+    /// Reads a dump made of `files`, as listed by [super::dump_files]
+    ///
+    pub(crate) fn of_files(files: &[PathBuf]) -> Result<ConversionSource> {
+        let mut size = 0;
+        for file in files {
+            size += std::fs::metadata(file)
+                .ndl("Failed to read dump to convert")?
+                .len();
+        }
+        Ok(ConversionSource {
+            console: disc_console(files),
+            size,
+        })
+    }
+
+    /// Checks there's room next to `output` for the dump converted to `format`, so it fails now
+    /// rather than when the disk fills up
+    ///
+    /// The converted file is estimated from how big the console's dumps came out of earlier
+    /// conversions, with some margin, and taken to be as big as its sources until there are any.
+    pub(crate) fn ensure_room(
+        &self,
+        reader: &CatalogReader,
+        format: &str,
+        path: &Path,
+        output: &Path,
+    ) -> Result<()> {
+        let needed = match reader.conversion_ratio(self.console, format)? {
+            Some(ratio) => ((self.size as f64 * ratio * ESTIMATE_MARGIN) as u64).min(self.size),
+            None => self.size,
+        };
+        work::ensure_free_space(output.parent().unwrap(), needed).map_err(|err| {
+            Error::new_original(format!(
+                "Failed to convert \"{}\"\n{}",
+                path.display(),
+                err.message()
+            ))
+            .with_category(ErrorCategory::InsufficientSpace)
+        })
+    }
+}
+
+/// The console of a dump made of `files`, read from its disc's serial
+///
+/// Compressed images aren't read, since their serials can't be found without decompressing them.
+fn disc_console(files: &[PathBuf]) -> Option<GameConsole> {
+    let image = match files[0].extension()?.to_str()? {
+        "gdi" => return Some(GameConsole::Dreamcast),
+        "cue" => files.get(1)?,
+        "iso" => &files[0],
+        _ => return None,
+    };
+    let mut file = File::open(image).ok()?;
+    Some(serial::read_serial(&mut file).ok()??.console)
+}
+
+/// Converts a cuesheet, GDI, or ISO to a CHD (see [crate::DumpManager::convert_file])
+///
+pub(crate) fn convert_to_chd(
+    reader: &CatalogReader,
+    path: &Path,
+    output_directory: &Path,
+    sources: SourceHandling,
+    chdman_threads: Option<usize>,
+) -> Result<Option<PathBuf>> {
+    let output = chd_path(path, output_directory);
+    if output.exists() {
+        return Err(already_exists(path, &output));
+    }
+    // the sources are listed first, so a broken cuesheet stops the conversion
+    let files = super::dump_files(path)?;
+    ConversionSource::of_files(&files)?.ensure_room(reader, "chd", path, &output)?;
+    let options = chdman::CreateOptions {
+        compression: None,
+        force: false,
+        hunk_size: None,
+        processor_count: chdman_threads,
+    };
+    write_atomically(&output, |partial| {
+        chdman::create_cd(&path.to_str().unwrap(), &partial.to_str().unwrap(), options)
+    })?;
+    debug!(
+        "Converted \"{}\" to \"{}\"",
+        path.display(),
+        output.display()
+    );
+    if sources == SourceHandling::Keep {
+        return Ok(Some(output));
+    }
+    let verified = timings::time(Stage::Hashing, || chdman::verify(&output.to_str().unwrap()))?;
+    if !verified {
+        std::fs::remove_file(&output).ndl("Failed to remove broken CHD")?;
+        return Err(Error::new_original(format!(
+            "Failed to convert \"{}\"\nThe CHD failed verification, so the sources were kept",
+            path.display()
+        ))
+        .with_category(ErrorCategory::InvalidData));
+    }
+    if reader
+        .is_rom(chdman::read_header(&output)?.raw_sha1)?
+        .is_none()
+    {
+        report_warning(
+            WarningKind::SkippedFile,
+            format!(
+                "Keeping the sources of \"{}\": the CHD's data isn't in the catalog",
+                path.display()
+            ),
+        );
+        return Ok(Some(output));
+    }
+    for file in files {
+        std::fs::remove_file(&file).ndl("Failed to remove converted dump")?;
+        info!("Removed \"{}\" after converting it", file.display());
+    }
+    Ok(Some(output))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn throttles_jobs_per_device() {
+        let devices = [Some(1), Some(1), Some(2), Some(1), None, Some(2)];
+        let running = [0, 1, 2].map(|_| AtomicUsize::new(0));
+        let most_running = [0, 1, 2].map(|_| AtomicUsize::new(0));
+        let results = run_queue(&devices, 4, 1, |index| {
+            let slot = devices[index].unwrap_or(0) as usize;
+            let now = running[slot].fetch_add(1, Ordering::SeqCst) + 1;
+            most_running[slot].fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(20));
+            running[slot].fetch_sub(1, Ordering::SeqCst);
+            index * 10
+        });
+        assert_eq!(results, vec![0, 10, 20, 30, 40, 50]);
+        assert_eq!(most_running[1].load(Ordering::SeqCst), 1);
+        assert_eq!(most_running[2].load(Ordering::SeqCst), 1);
+
+        let limits = ConversionLimits {
+            jobs: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(limits.job_count(), 3);
+        let limits = ConversionLimits {
+            jobs: None,
+            chdman_threads: Some(usize::MAX),
+            jobs_per_device: None,
+        };
+        assert_eq!(limits.job_count(), 1);
+    }
+}
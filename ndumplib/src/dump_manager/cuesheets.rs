@@ -1,16 +1,28 @@
-use std::{collections::HashSet, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
 
 use chrono::{DateTime, TimeDelta, Utc};
 use log::{debug, info};
 use once_cell::sync::OnceCell;
-use rusqlite::{Connection, OptionalExtension};
+use rusqlite::{Connection, OptionalExtension, Transaction};
 use sha1::{Digest, Sha1};
 use tempfile::TempDir;
 
+use super::{
+    UpdateTarget,
+    network::NetworkTimeouts,
+    timings::{self, Stage},
+};
 use crate::{
-    Error, GameConsole, Result, ResultUtils,
+    CheckpointPolicy, DatabaseCheck, DatabaseMaintenance, DatabaseSchema, Error, GameConsole,
+    Result, ResultUtils,
     utils::{
-        CanPrepare, get_database_indexes, get_database_tables, regex, setup_database_default_config,
+        CanPrepare, apply_checkpoint_policy, backup_database, check_database, close_database,
+        database_schema, last_maintained, maintain_database,
+        migrations::{Migration, create_maintenance_table, run_migrations},
+        open_read_only_database, regex, setup_database_default_config,
     },
 };
 
@@ -76,6 +88,10 @@ impl Cuesheet {
 pub struct Cuesheets {
     connection: Connection,
     cue_update_delay: TimeDelta,
+    forced_updates: Vec<UpdateTarget>,
+    timeouts: NetworkTimeouts,
+    /// How the write-ahead log is checkpointed, or `None` if the DB was opened read-only
+    checkpoint: Option<CheckpointPolicy>,
 }
 
 static SUPPORTED_COMMANDS: OnceCell<HashSet<&'static str>> = OnceCell::new();
@@ -87,6 +103,21 @@ pub fn get_track_filenames(content: &impl AsRef<str>) -> Vec<String> {
         .collect()
 }
 
+/// Points a cuesheet's FILE commands at new track file names
+///
+/// `renames` maps old file names to new ones. Files missing from it are left alone.
+pub fn rename_tracks(content: &str, renames: &HashMap<String, String>) -> String {
+    regex!(r#"(?<=FILE ")[^"]+"#)
+        .replace_all(content, |captures: &fancy_regex::Captures| {
+            let filename = captures.get(0).unwrap().as_str();
+            renames
+                .get(filename)
+                .cloned()
+                .unwrap_or_else(|| filename.to_string())
+        })
+        .into_owned()
+}
+
 pub fn neutralize(content: &impl AsRef<str>, path: &impl AsRef<Path>) -> String {
     let supported_commands = SUPPORTED_COMMANDS.get_or_init(|| {
         let mut set = HashSet::new();
@@ -110,10 +141,46 @@ pub fn neutralize(content: &impl AsRef<str>, path: &impl AsRef<Path>) -> String
         .replace(path.as_ref().file_stem().unwrap().to_str().unwrap(), "$")
 }
 
+/// The cuesheet DB's schema history, oldest first (see [run_migrations])
+const CUESHEET_MIGRATIONS: [Migration; 2] = [
+    Migration {
+        description: "Create cuesheet tables",
+        apply: create_cuesheet_tables,
+    },
+    Migration {
+        description: "Add maintenance table",
+        apply: create_maintenance_table,
+    },
+];
+
+fn create_cuesheet_tables(transaction: &Transaction) -> Result<()> {
+    // databases created before schema versioning may already have these
+    transaction
+        .execute_batch(
+            r#"
+                CREATE TABLE IF NOT EXISTS "cuesheets" (
+                    "console"	TEXT NOT NULL UNIQUE,
+                    "last_updated"	INTEGER NOT NULL,
+                    PRIMARY KEY("console")
+                );
+                CREATE TABLE IF NOT EXISTS "cues" (
+                    "sha1"	BLOB NOT NULL UNIQUE,
+                    "content"	TEXT NOT NULL,
+                    PRIMARY KEY("sha1")
+                );
+                CREATE INDEX IF NOT EXISTS "content_to_cue" ON "cues" (
+                    "content"	DESC
+                );
+            "#,
+        )
+        .ndl("Failed to create tables in cuesheet DB")
+}
+
 impl Drop for Cuesheets {
     fn drop(&mut self) {
-        self.connection.execute("VACUUM", ()).unwrap();
-        self.connection.execute("PRAGMA optimize;", ()).unwrap();
+        if let Some(checkpoint) = self.checkpoint {
+            close_database(&self.connection, "cuesheet", checkpoint);
+        }
     }
 }
 
@@ -134,75 +201,78 @@ impl Cuesheets {
             .ndl("Failed to lookup cue in cuesheet DB")
     }
 
+    /// Finds a cuesheet by its hash in the catalog, returning its neutralized content (see
+    /// [neutralize])
+    ///
+    pub fn get_cue(&self, sha1: [u8; 20]) -> Result<Option<String>> {
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT content FROM cues WHERE sha1 = ?")
+            .ndl("Failed to lookup cue in cuesheet DB")?;
+        statement
+            .query_one((sha1,), |row| row.get(0))
+            .optional()
+            .ndl("Failed to lookup cue in cuesheet DB")
+    }
+
     pub fn init(path: &impl AsRef<Path>) -> Result<Cuesheets> {
-        let connection = Connection::open(path).ndl("Failed to open cuesheet DB")?;
+        let mut connection = Connection::open(path).ndl("Failed to open cuesheet DB")?;
         setup_database_default_config(&connection)?;
         debug!(
             r#"Opened cuesheet database at "{}""#,
             path.as_ref().to_str().unwrap()
         );
-        // create missing tables and indexes
-        let tables = get_database_tables(&connection)?;
-        let indexes = get_database_indexes(&connection)?;
-        let mut changed = false;
-        if !tables.contains("cuesheets") {
-            connection
-                .execute(
-                    r#"
-                        CREATE TABLE "cuesheets" (
-                            "console"	TEXT NOT NULL UNIQUE,
-                            "last_updated"	INTEGER NOT NULL,
-                            PRIMARY KEY("console")
-                        )
-                    "#,
-                    (),
-                )
-                .ndl("Failed to create tables in cuesheet DB")?;
-            debug!("Created \"cuesheets\" table");
-            changed = true;
-        }
-        if !tables.contains("cues") {
-            connection
-                .execute(
-                    r#"
-                        CREATE TABLE "cues" (
-                            "sha1"	BLOB NOT NULL UNIQUE,
-                            "content"	TEXT NOT NULL,
-                            PRIMARY KEY("sha1")
-                        )
-                    "#,
-                    (),
-                )
-                .ndl("Failed to create tables in cuesheet DB")?;
-            debug!("Created \"cues\" table");
-            changed = true;
-        }
-        if !indexes.contains_key("content_to_cue") {
-            connection
-                .execute(
-                    r#"
-                        CREATE INDEX "content_to_cue" ON "cues" (
-                            "content"	DESC
-                        )
-                    "#,
-                    (),
-                )
-                .ndl("Failed to create tables in cuesheet DB")?;
-            debug!("Created \"content_to_cue\" index");
-            changed = true;
-        }
-        // optimize the database if the tables were changed
-        if changed {
+        // bring the schema up to date
+        if run_migrations(&mut connection, "cuesheet", &CUESHEET_MIGRATIONS)? {
             connection
                 .execute("PRAGMA optimize;", ())
                 .ndl("Failed to optimize cuesheet DB")?;
             debug!("Optimized cuesheet database");
         }
         // return the database
-        Ok(Cuesheets {
+        Ok(Cuesheets::with_connection(
+            connection,
+            Some(CheckpointPolicy::default()),
+        ))
+    }
+
+    /// Opens the cuesheet DB only to read it (see [crate::DumpManager::open_read_only])
+    ///
+    pub fn open_read_only(path: &impl AsRef<Path>) -> Result<Cuesheets> {
+        let connection = open_read_only_database(path.as_ref(), "cuesheet", &CUESHEET_MIGRATIONS)?;
+        Ok(Cuesheets::with_connection(connection, None))
+    }
+
+    fn with_connection(connection: Connection, checkpoint: Option<CheckpointPolicy>) -> Cuesheets {
+        Cuesheets {
             connection,
             cue_update_delay: TimeDelta::days(7),
-        })
+            forced_updates: Vec::new(),
+            timeouts: NetworkTimeouts::default(),
+            checkpoint,
+        }
+    }
+
+    /// Sets how the write-ahead log is checkpointed (the DB mustn't be read-only)
+    ///
+    pub fn with_checkpoint_policy(mut self, policy: CheckpointPolicy) -> Cuesheets {
+        apply_checkpoint_policy(&self.connection, "cuesheet", policy);
+        self.checkpoint = Some(policy);
+        self
+    }
+
+    pub fn with_update_delay(mut self, delay: TimeDelta) -> Cuesheets {
+        self.cue_update_delay = delay;
+        self
+    }
+
+    pub fn with_network_timeouts(mut self, timeouts: NetworkTimeouts) -> Cuesheets {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn force_update(&mut self, target: UpdateTarget) {
+        self.forced_updates.push(target);
     }
 
     fn import_cues(&mut self, dir: TempDir) -> Result<()> {
@@ -240,24 +310,67 @@ impl Cuesheets {
 
     fn update_redump_cuesheets(&mut self, console: GameConsole) -> Result<()> {
         let mut cuesheet = Cuesheet::get(&self.connection, console)?;
-        if Utc::now()
-            < cuesheet
-                .last_updated
-                .checked_add_signed(self.cue_update_delay)
-                .unwrap()
-        {
+        let forced = self
+            .forced_updates
+            .iter()
+            .any(|target| target.covers_redump(console));
+        let due = cuesheet
+            .last_updated
+            .checked_add_signed(self.cue_update_delay)
+            .is_none_or(|due| Utc::now() >= due);
+        if !forced && !due {
             return Ok(());
         }
-        self.import_cues(redump::download_cuesheets(
-            console.redump_cue_slug().unwrap(),
-        )?)?;
+        let cues = timings::time(Stage::Download, || {
+            redump::download_cuesheets(console.redump_cue_slug().unwrap(), &self.timeouts)
+        })?;
+        timings::time(Stage::Import, || self.import_cues(cues))?;
         cuesheet.last_updated = Utc::now();
         cuesheet.update(&self.connection)?;
         info!("Updated {} cuesheet", console.formal_name());
         Ok(())
     }
 
+    pub fn check(&mut self, repair: bool) -> Result<DatabaseCheck> {
+        check_database(&mut self.connection, "cuesheet", &[], repair)
+    }
+
+    pub fn schema(&self) -> Result<DatabaseSchema> {
+        database_schema(&self.connection, "cuesheet")
+    }
+
+    pub fn backup(&self, to: &Path) -> Result<()> {
+        backup_database(&self.connection, "cuesheet", to)
+    }
+
+    pub fn maintain(&self) -> Result<DatabaseMaintenance> {
+        maintain_database(&self.connection, "cuesheet")
+    }
+
+    pub fn last_maintained(&self) -> Result<Option<DateTime<Utc>>> {
+        last_maintained(&self.connection, "cuesheet")
+    }
+
     pub fn update_all_consoles(&mut self) -> Result<()> {
         self.update_redump_cuesheets(GameConsole::PSX)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renames_only_listed_tracks() {
+        let content =
+            "FILE \"a (Track 1).bin\" BINARY\n  TRACK 01 MODE2/2352\nFILE \"b.bin\" BINARY\n";
+        let renames = HashMap::from([(
+            "a (Track 1).bin".to_string(),
+            "Game (USA) (Track 1).bin".to_string(),
+        )]);
+        assert_eq!(
+            rename_tracks(content, &renames),
+            "FILE \"Game (USA) (Track 1).bin\" BINARY\n  TRACK 01 MODE2/2352\nFILE \"b.bin\" BINARY\n"
+        );
+    }
+}
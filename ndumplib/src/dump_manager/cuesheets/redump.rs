@@ -4,7 +4,7 @@ use compress_tools::{Ownership, uncompress_archive};
 use log::debug;
 use tempfile::{NamedTempFile, TempDir, tempdir};
 
-use crate::{GameConsole, Result, ResultUtils};
+use crate::{GameConsole, Result, ResultUtils, dump_manager::network::NetworkTimeouts};
 
 impl GameConsole {
     pub(super) fn redump_cue_slug(&self) -> Option<&str> {
@@ -15,13 +15,17 @@ impl GameConsole {
     }
 }
 
-pub(super) fn download_cuesheets(slug: &str) -> Result<TempDir> {
+pub(super) fn download_cuesheets(slug: &str, timeouts: &NetworkTimeouts) -> Result<TempDir> {
     let url: String = format!("http://redump.org/cues/{slug}/");
     let zip_file = NamedTempFile::with_suffix(".zip")
         .ndl("Failed to create temporary file to download cuesheets")?;
     let extracted_files = tempdir().ndl("Failed to create directory file to extract cue files")?;
     {
-        let mut response = ureq::get(url).call().ndl("Failed to start download")?;
+        let mut response = timeouts
+            .start_deadline()
+            .limit(timeouts.agent().get(url))?
+            .call()
+            .ndl("Failed to start download")?;
         let file = zip_file
             .as_file()
             .try_clone()
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+/// Finds where a GDI track line's file name is, returning its byte range and whether it's quoted
+///
+/// Track lines are "number LBA type sector-size file-name offset". Names with spaces are quoted.
+fn track_filename_span(line: &str) -> Option<(usize, usize, bool)> {
+    let mut start = 0;
+    for _ in 0..4 {
+        let rest = &line[start..];
+        let field_start = start + rest.find(|c: char| !c.is_whitespace())?;
+        start = field_start + line[field_start..].find(char::is_whitespace)?;
+    }
+    start += line[start..].find(|c: char| !c.is_whitespace())?;
+    if line[start..].starts_with('"') {
+        let end = start + 1 + line[start + 1..].find('"')?;
+        Some((start + 1, end, true))
+    } else {
+        let end = start + line[start..].find(char::is_whitespace)?;
+        Some((start, end, false))
+    }
+}
+
+/// Lists the track files of a GDI, in order
+///
+/// The first line is the track count, so it's skipped.
+pub fn get_track_filenames(content: &impl AsRef<str>) -> Vec<String> {
+    content
+        .as_ref()
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            track_filename_span(line).map(|(start, end, _)| line[start..end].to_string())
+        })
+        .collect()
+}
+
+/// Points a GDI's tracks at new file names, like [super::cuesheets::rename_tracks]
+///
+/// Names are quoted if they need to be.
+pub fn rename_tracks(content: &str, renames: &HashMap<String, String>) -> String {
+    content
+        .split_inclusive('\n')
+        .enumerate()
+        .map(|(index, line)| {
+            let Some((start, end, quoted)) = track_filename_span(line).filter(|_| index > 0) else {
+                return line.to_string();
+            };
+            let Some(new_name) = renames.get(&line[start..end]) else {
+                return line.to_string();
+            };
+            if quoted || !new_name.contains(' ') {
+                format!("{}{new_name}{}", &line[..start], &line[end..])
+            } else {
+                format!("{}\"{new_name}\"{}", &line[..start], &line[end..])
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renames_tracks() {
+        let content = "3\r\n1 0 4 2352 \"Game (Track 1).bin\" 0\r\n2 756 0 2352 track02.raw 0\r\n3 45000 4 2352 \"Game (Track 3).bin\" 0\r\n";
+        assert_eq!(
+            get_track_filenames(&content),
+            ["Game (Track 1).bin", "track02.raw", "Game (Track 3).bin"]
+        );
+        let renames = HashMap::from([
+            ("track02.raw".to_string(), "Game (Track 2).bin".to_string()),
+            (
+                "Game (Track 3).bin".to_string(),
+                "Other (Track 3).bin".to_string(),
+            ),
+        ]);
+        assert_eq!(
+            rename_tracks(content, &renames),
+            "3\r\n1 0 4 2352 \"Game (Track 1).bin\" 0\r\n2 756 0 2352 \"Game (Track 2).bin\" 0\r\n3 45000 4 2352 \"Other (Track 3).bin\" 0\r\n"
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::{Duration, Instant};
+
+use ureq::{Agent, RequestBuilder};
+
+use crate::{Error, ErrorKind, Result};
+
+/// How long downloads of datafiles and cuesheets may take before they're given up on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NetworkTimeouts {
+    /// How long connecting to a server may take
+    pub connect: Duration,
+    /// How long a server may take to respond to a request once it's sent
+    pub read: Duration,
+    /// How long downloading a single datafile (or cuesheet archive) may take altogether, including
+    /// every page loaded along the way
+    pub deadline: Duration,
+}
+
+impl Default for NetworkTimeouts {
+    fn default() -> Self {
+        NetworkTimeouts {
+            connect: Duration::from_secs(30),
+            read: Duration::from_secs(60),
+            deadline: Duration::from_secs(30 * 60),
+        }
+    }
+}
+
+impl NetworkTimeouts {
+    /// Creates an agent which gives up on connections and responses after these timeouts
+    ///
+    pub(crate) fn agent(&self) -> Agent {
+        Agent::config_builder()
+            .timeout_connect(Some(self.connect))
+            .timeout_recv_response(Some(self.read))
+            .build()
+            .into()
+    }
+
+    /// Starts the deadline of a download, which the requests making it up share
+    ///
+    pub(crate) fn start_deadline(&self) -> Deadline {
+        Deadline {
+            end: Instant::now() + self.deadline,
+        }
+    }
+}
+
+/// When a download has to be finished by (see [NetworkTimeouts::deadline])
+pub(crate) struct Deadline {
+    end: Instant,
+}
+
+impl Deadline {
+    /// Limits a request to the time left, failing if none is
+    ///
+    pub(crate) fn limit<B>(&self, request: RequestBuilder<B>) -> Result<RequestBuilder<B>> {
+        let remaining = self.end.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(Error::new(
+                "Download took too long",
+                ErrorKind::Timeout(ureq::Timeout::Global),
+            ));
+        }
+        Ok(request.config().timeout_global(Some(remaining)).build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::ResultUtils;
+
+    #[test]
+    fn times_out_silent_servers() {
+        // accepts connections, but never responds
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let timeouts = NetworkTimeouts {
+            read: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let deadline = timeouts.start_deadline();
+        let err = deadline
+            .limit(timeouts.agent().get(&url))
+            .unwrap()
+            .call()
+            .ndl("Failed to start download")
+            .unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            Some(ErrorKind::Timeout(ureq::Timeout::RecvResponse))
+        ));
+        let expired = NetworkTimeouts {
+            deadline: Duration::ZERO,
+            ..Default::default()
+        };
+        let err = expired
+            .start_deadline()
+            .limit(expired.agent().get(&url))
+            .unwrap_err();
+        assert!(matches!(err.kind(), Some(ErrorKind::Timeout(_))));
+    }
+}
@@ -0,0 +1,344 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+
+use super::{WarningKind, report_warning};
+use crate::{
+    Error, ErrorCategory, Result, ResultUtils,
+    transfer::write_atomically,
+    utils::{regex, sevenzip, work},
+};
+
+/// How a dump was split into parts
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SplitKind {
+    /// "Game.iso.1", "Game.iso.2"... (or .001, .002...), as cut by file splitters
+    Numbered,
+    /// "Game.wbfs", "Game.wbf1", "Game.wbf2"..., as cut by Wii backup managers to fit FAT32
+    Wbfs,
+    /// "Game.part1.rar", "Game.part2.rar"..., an archive in volumes, which has to be extracted
+    RarVolumes,
+}
+
+/// A dump stored as several files, which only make up the dump together
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SplitDump {
+    pub kind: SplitKind,
+    /// The parts, in order
+    pub parts: Vec<PathBuf>,
+}
+
+/// A part's place in its dump: the dump's (joined) name, and the part's number
+fn part_number(kind: SplitKind, name: &str) -> Option<(String, u32)> {
+    let pattern = match kind {
+        SplitKind::Numbered => regex!(r"(?i)^(.+\.(?:iso|bin|img|gcm))\.(\d{1,3})$"),
+        SplitKind::Wbfs => regex!(r"(?i)^(.+)\.wb(fs|f\d)$"),
+        SplitKind::RarVolumes => regex!(r"(?i)^(.+)\.part(\d+)\.rar$"),
+    };
+    let captures = pattern.captures(name).ok()??;
+    let base = captures.get(1)?.as_str();
+    let number = captures.get(2)?.as_str();
+    Some(match kind {
+        SplitKind::Wbfs if number.eq_ignore_ascii_case("fs") => (format!("{base}.wbfs"), 0),
+        SplitKind::Wbfs => (format!("{base}.wbfs"), number[1..].parse().ok()?),
+        SplitKind::RarVolumes => (base.to_string(), number.parse().ok()?),
+        SplitKind::Numbered => (base.to_string(), number.parse().ok()?),
+    })
+}
+
+/// Groups the split dumps among `files`, returning them and the files which aren't parts of one
+///
+/// A set of parts with a gap in its numbering is reported as a warning, and its parts are left
+/// as they are. So is a lone ".wbfs", which isn't split at all.
+pub fn find_split_dumps(files: &[PathBuf]) -> (Vec<SplitDump>, Vec<PathBuf>) {
+    let mut sets: BTreeMap<(SplitKind, PathBuf), Vec<(u32, PathBuf)>> = BTreeMap::new();
+    let mut others = Vec::new();
+    for file in files {
+        let name = file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        let part = [SplitKind::Numbered, SplitKind::Wbfs, SplitKind::RarVolumes]
+            .into_iter()
+            .find_map(|kind| part_number(kind, name).map(|(base, number)| (kind, base, number)));
+        match part {
+            Some((kind, base, number)) => sets
+                .entry((kind, file.with_file_name(base)))
+                .or_default()
+                .push((number, file.clone())),
+            None => others.push(file.clone()),
+        }
+    }
+    let mut dumps = Vec::new();
+    for ((kind, base), mut parts) in sets {
+        parts.sort();
+        let first = parts[0].0;
+        // numbered parts count from 0 or 1, volumes from 1, and WBFS parts follow the .wbfs
+        let complete = parts.len() > 1
+            && first <= 1
+            && parts
+                .iter()
+                .enumerate()
+                .all(|(index, (number, _))| *number == first + index as u32);
+        if complete {
+            dumps.push(SplitDump {
+                kind,
+                parts: parts.into_iter().map(|(_, path)| path).collect(),
+            });
+            continue;
+        }
+        if parts.len() > 1 || first > 1 {
+            report_warning(
+                WarningKind::SkippedFile,
+                format!(
+                    "\"{}\" is split into parts, but some are missing, so the parts are handled on their own",
+                    base.display()
+                ),
+            );
+        }
+        others.extend(parts.into_iter().map(|(_, path)| path));
+    }
+    (dumps, others)
+}
+
+impl SplitDump {
+    /// The name of the file the parts make up when joined, like "Game.iso" for "Game.iso.1"
+    ///
+    /// Returns `None` for archive volumes, which hold files of their own.
+    pub fn joined_name(&self) -> Option<String> {
+        if self.kind == SplitKind::RarVolumes {
+            return None;
+        }
+        let name = self.parts[0].file_name()?.to_str()?;
+        part_number(self.kind, name).map(|(base, _)| base)
+    }
+
+    /// The total size of the parts
+    ///
+    pub fn size(&self) -> Result<u64> {
+        let mut size = 0;
+        for part in &self.parts {
+            size += std::fs::metadata(part)
+                .ndl("Failed to read split dump")?
+                .len();
+        }
+        Ok(size)
+    }
+
+    /// Names a part after a new name for the joined file, keeping its own suffix: part 2 of
+    /// "Game.iso" becomes "Game.iso.2", and the first WBFS part after "Game.wbfs" is "Game.wbf1"
+    ///
+    pub fn part_name(&self, index: usize, joined_name: &str) -> String {
+        let name = self.parts[index].file_name().unwrap().to_string_lossy();
+        match self.kind {
+            SplitKind::Wbfs => {
+                let extension = Path::new(name.as_ref()).extension().unwrap();
+                Path::new(joined_name)
+                    .with_extension(extension)
+                    .to_string_lossy()
+                    .into_owned()
+            }
+            _ => {
+                let suffix = name.rsplit('.').next().unwrap();
+                format!("{joined_name}.{suffix}")
+            }
+        }
+    }
+
+    /// Reads the parts one after another, as if they were the joined file
+    ///
+    pub(crate) fn open(&self) -> Result<SplitReader> {
+        let mut parts = Vec::with_capacity(self.parts.len());
+        let mut size = 0;
+        for path in &self.parts {
+            let file = File::open(path).ndl("Failed to open split dump")?;
+            let length = file.metadata().ndl("Failed to open split dump")?.len();
+            parts.push((file, size));
+            size += length;
+        }
+        Ok(SplitReader {
+            parts,
+            size,
+            position: 0,
+        })
+    }
+
+    /// Writes the parts one after another to `output`, which mustn't exist yet
+    ///
+    /// Archive volumes can't be joined; see [SplitDump::extract] instead. The parts are written under
+    /// a temporary name, which is only renamed to `output` once they all are.
+    pub fn join(&self, output: &impl AsRef<Path>) -> Result<()> {
+        let output = output.as_ref();
+        if self.kind == SplitKind::RarVolumes {
+            return Err(Error::new_original(format!(
+                "Failed to join \"{}\"\nArchive volumes are extracted, not joined",
+                self.parts[0].display()
+            ))
+            .with_category(ErrorCategory::InvalidInput));
+        }
+        if output.exists() {
+            return Err(Error::new_original(format!(
+                "Failed to join \"{}\"\n\"{}\" already exists",
+                self.parts[0].display(),
+                output.display()
+            ))
+            .with_category(ErrorCategory::IO));
+        }
+        write_atomically(output, |partial| {
+            let mut joined = File::create_new(partial)
+                .ndl(format!("Failed to join \"{}\"", self.parts[0].display()))?;
+            for part in &self.parts {
+                File::open(part)
+                    .and_then(|mut part| io::copy(&mut part, &mut joined))
+                    .ndl(format!("Failed to join \"{}\"", self.parts[0].display()))?;
+            }
+            Ok(())
+        })?;
+        debug!(
+            "Joined {} part(s) into \"{}\"",
+            self.parts.len(),
+            output.display()
+        );
+        Ok(())
+    }
+
+    /// Extracts archive volumes into `directory` with 7-Zip, returning the extracted files
+    ///
+    pub fn extract(&self, directory: &impl AsRef<Path>) -> Result<Vec<PathBuf>> {
+        let directory = directory.as_ref();
+        if self.kind != SplitKind::RarVolumes {
+            return Err(Error::new_original(format!(
+                "Failed to extract \"{}\"\nOnly archive volumes are extracted",
+                self.parts[0].display()
+            ))
+            .with_category(ErrorCategory::InvalidInput));
+        }
+        extract_archive(&self.parts[0], &directory)
+    }
+}
+
+/// Extracts an archive (like a ZIP, 7z, or RAR) into `directory` with 7-Zip, returning the
+/// extracted files
+///
+/// Fails before extracting anything if `directory` hasn't room for the files.
+pub fn extract_archive(
+    archive: &impl AsRef<Path>,
+    directory: &impl AsRef<Path>,
+) -> Result<Vec<PathBuf>> {
+    let directory = directory.as_ref();
+    work::ensure_free_space(directory, sevenzip::uncompressed_size(archive.as_ref())?)?;
+    sevenzip::extract(archive.as_ref(), directory)?;
+    let mut files = Vec::new();
+    let mut folders = vec![directory.to_path_buf()];
+    while let Some(folder) = folders.pop() {
+        for entry in std::fs::read_dir(&folder).ndl("Failed to list extracted files")? {
+            let path = entry.ndl("Failed to list extracted files")?.path();
+            if path.is_dir() {
+                folders.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Reads a [SplitDump]'s parts as one file
+pub(crate) struct SplitReader {
+    /// Each part, with where it starts in the joined file
+    parts: Vec<(File, u64)>,
+    size: u64,
+    position: u64,
+}
+
+impl Read for SplitReader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.size || buffer.is_empty() {
+            return Ok(0);
+        }
+        let index = self
+            .parts
+            .partition_point(|(_, start)| *start <= self.position)
+            - 1;
+        let end = self
+            .parts
+            .get(index + 1)
+            .map_or(self.size, |(_, start)| *start);
+        let (file, start) = &mut self.parts[index];
+        file.seek(SeekFrom::Start(self.position - *start))?;
+        let length = buffer.len().min((end - self.position) as usize);
+        let read = file.read(&mut buffer[..length])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for SplitReader {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Seeked before the start")
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_split_dumps() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = |name: &str| directory.path().join(name);
+        std::fs::write(path("Game.iso.1"), b"0123").unwrap();
+        std::fs::write(path("Game.iso.2"), b"45").unwrap();
+        std::fs::write(path("Game.iso.3"), b"6789").unwrap();
+        let files = [
+            path("Game.iso.3"),
+            path("Other.wbfs"),
+            path("Game.iso.1"),
+            path("Game.iso.2"),
+            path("Other.wbf1"),
+            path("Lone.iso"),
+        ];
+        let (dumps, others) = find_split_dumps(&files);
+        assert_eq!(others, vec![path("Lone.iso")]);
+        assert_eq!(dumps.len(), 2);
+        let dump = &dumps[0];
+        assert_eq!(dump.kind, SplitKind::Numbered);
+        assert_eq!(dump.joined_name().as_deref(), Some("Game.iso"));
+        assert_eq!(dump.part_name(1, "Game (USA).iso"), "Game (USA).iso.2");
+        assert_eq!(dumps[1].kind, SplitKind::Wbfs);
+        assert_eq!(dumps[1].part_name(1, "Game (USA).wbfs"), "Game (USA).wbf1");
+
+        let mut reader = dump.open().unwrap();
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "0123456789");
+        reader.seek(SeekFrom::Start(3)).unwrap();
+        let mut bytes = [0u8; 4];
+        reader.read_exact(&mut bytes).unwrap();
+        assert_eq!(&bytes, b"3456");
+
+        dump.join(&path("Game.iso")).unwrap();
+        assert_eq!(std::fs::read(path("Game.iso")).unwrap(), b"0123456789");
+        assert!(dump.join(&path("Game.iso")).is_err());
+        assert_eq!(std::fs::read(path("Game.iso")).unwrap(), b"0123456789");
+
+        // a gap in the numbering leaves the parts alone
+        let (dumps, others) = find_split_dumps(&[path("Game.iso.1"), path("Game.iso.3")]);
+        assert!(dumps.is_empty());
+        assert_eq!(others.len(), 2);
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// A part of the dump manager's work whose time is measured (see [run_timings])
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Fetching datafiles and cuesheets
+    Download,
+    /// Reading datafiles' XML
+    Parse,
+    /// Storing datafiles' games and cuesheets in the databases
+    Import,
+    /// Hashing dumps, including verifying CHDs with chdman and disc images with dolphin-tool
+    Hashing,
+    /// Creating and extracting CHDs, and converting and decompressing other compressed ISOs
+    Conversion,
+}
+
+impl Stage {
+    const ALL: [Stage; 5] = [
+        Stage::Download,
+        Stage::Parse,
+        Stage::Import,
+        Stage::Hashing,
+        Stage::Conversion,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Download => "download",
+            Stage::Parse => "parse",
+            Stage::Import => "import",
+            Stage::Hashing => "hashing",
+            Stage::Conversion => "conversion",
+        }
+    }
+}
+
+/// The time spent in each stage so far, and the most memory used at once
+pub struct RunTimings {
+    /// Stages done on several threads at once (like downloads) add up each thread's time
+    pub stages: Vec<(Stage, Duration)>,
+    /// The peak resident memory in bytes, where the OS reports it (currently only Linux)
+    pub peak_memory: Option<u64>,
+}
+
+static STAGE_TOTALS: Mutex<[Duration; 5]> = Mutex::new([Duration::ZERO; 5]);
+
+/// Adds time spent in a stage to the run's totals
+///
+pub(crate) fn record(stage: Stage, duration: Duration) {
+    // StageTimer records while unwinding too, where a poisoned lock mustn't panic again
+    STAGE_TOTALS.lock().unwrap_or_else(PoisonError::into_inner)[stage as usize] += duration;
+}
+
+/// Runs `work`, adding the time it takes to `stage`'s total
+///
+pub(crate) fn time<T>(stage: Stage, work: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = work();
+    record(stage, start.elapsed());
+    result
+}
+
+/// Adds the time until it's dropped to a stage's total, for work with several early returns
+///
+pub(crate) struct StageTimer {
+    stage: Stage,
+    start: Instant,
+}
+
+impl StageTimer {
+    pub(crate) fn start(stage: Stage) -> StageTimer {
+        StageTimer {
+            stage,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for StageTimer {
+    fn drop(&mut self) {
+        record(self.stage, self.start.elapsed());
+    }
+}
+
+/// Reads the peak resident memory of this process from /proc
+///
+fn peak_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes: u64 = line
+        .trim_start_matches("VmHWM:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// The time spent in each stage since the process started
+///
+pub fn run_timings() -> RunTimings {
+    let totals = *STAGE_TOTALS.lock().unwrap();
+    RunTimings {
+        stages: Stage::ALL
+            .iter()
+            .map(|stage| (*stage, totals[*stage as usize]))
+            .collect(),
+        peak_memory: peak_memory(),
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    cell::RefCell,
+    sync::{Arc, Mutex},
+};
+
+use log::warn;
+
+/// What kind of problem a [Warning] is about
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WarningKind {
+    /// A file was left out, like a missing path or a name that's already taken
+    SkippedFile,
+    /// A dump was identified by a guess rather than its hash, like a cuesheet named after its
+    /// first track
+    HeuristicMatch,
+    /// Something was repaired after an unclean shutdown
+    Recovery,
+    /// A dump in the library stopped matching the catalog when a datafile was updated, like when
+    /// Redump replaces a bad dump
+    ObsoleteDump,
+}
+
+impl WarningKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WarningKind::SkippedFile => "skipped-file",
+            WarningKind::HeuristicMatch => "heuristic-match",
+            WarningKind::Recovery => "recovery",
+            WarningKind::ObsoleteDump => "obsolete-dump",
+        }
+    }
+}
+
+/// A problem which didn't stop the run, but which the user should know about
+#[derive(Clone, Debug)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub message: String,
+}
+
+static WARNINGS: Mutex<Vec<Warning>> = Mutex::new(Vec::new());
+
+thread_local! {
+    /// The warnings of the work [collect_warnings] is running on this thread, if any
+    static COLLECTED: RefCell<Option<WarningScope>> = const { RefCell::new(None) };
+}
+
+/// Where a thread's warnings are kept, which threads spawned for the same work share
+///
+/// Threads started for a piece of work enter their parent's scope (see [WarningScope::current]),
+/// so that the warnings of jobs running at once in one process aren't mixed up.
+#[derive(Clone)]
+pub(crate) struct WarningScope(Arc<Mutex<Vec<Warning>>>);
+
+impl WarningScope {
+    /// The scope this thread's warnings go to, or `None` for the process's
+    ///
+    pub(crate) fn current() -> Option<WarningScope> {
+        COLLECTED.with(|collected| collected.borrow().clone())
+    }
+
+    /// Runs `work` with this thread's warnings going to `scope` (or to the process's for `None`)
+    ///
+    pub(crate) fn enter<T>(scope: Option<WarningScope>, work: impl FnOnce() -> T) -> T {
+        let previous = COLLECTED.with(|collected| collected.replace(scope));
+        // the previous scope is restored while unwinding too
+        struct Restore(Option<WarningScope>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                COLLECTED.with(|collected| *collected.borrow_mut() = self.0.take());
+            }
+        }
+        let _restore = Restore(previous);
+        work()
+    }
+}
+
+/// Logs a warning, and keeps it for [take_warnings] or the [collect_warnings] call it's reported
+/// under
+///
+pub fn report_warning(kind: WarningKind, message: impl Into<String>) {
+    let message = message.into();
+    warn!("{message}");
+    let warning = Warning { kind, message };
+    match WarningScope::current() {
+        Some(scope) => scope.0.lock().unwrap().push(warning),
+        None => WARNINGS.lock().unwrap().push(warning),
+    }
+}
+
+/// The warnings reported since the process started (or since this was last called), oldest first
+///
+/// Warnings reported under [collect_warnings] aren't included.
+pub fn take_warnings() -> Vec<Warning> {
+    std::mem::take(&mut *WARNINGS.lock().unwrap())
+}
+
+/// Runs `work`, returning the warnings it reported (oldest first) along with its result
+///
+/// The warnings are kept apart from the process's, and from other work collecting its own, so
+/// jobs run at once (like by a server) each get theirs.
+pub fn collect_warnings<T>(work: impl FnOnce() -> T) -> (T, Vec<Warning>) {
+    let scope = WarningScope(Arc::new(Mutex::new(Vec::new())));
+    let result = WarningScope::enter(Some(scope.clone()), work);
+    let warnings = std::mem::take(&mut *scope.0.lock().unwrap());
+    (result, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_collected_warnings_apart() {
+        let collect = |message: &'static str| {
+            std::thread::spawn(move || {
+                collect_warnings(|| {
+                    let scope = WarningScope::current();
+                    // like the worker threads started while updating and converting
+                    std::thread::spawn(move || {
+                        WarningScope::enter(scope, || {
+                            report_warning(WarningKind::SkippedFile, message)
+                        })
+                    })
+                    .join()
+                    .unwrap();
+                })
+                .1
+            })
+        };
+        let (first, second) = (collect("first"), collect("second"));
+        for (job, message) in [(first, "first"), (second, "second")] {
+            let warnings = job.join().unwrap();
+            assert_eq!(warnings.len(), 1);
+            assert_eq!(warnings[0].message, message);
+        }
+        assert!(
+            take_warnings()
+                .iter()
+                .all(|warning| warning.message != "first" && warning.message != "second")
+        );
+    }
+}
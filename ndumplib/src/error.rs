@@ -1,89 +1,196 @@
-#[derive(Debug)]
-pub(crate) enum InnerError {
-    IOError(std::io::Error),
-    NetError(ureq::Error),
-    ArchiveError(compress_tools::Error),
-    XMLError(roxmltree::Error),
-    SQLiteError(rusqlite::Error),
-    UnknownError(visdom::types::BoxDynError),
+/// What went wrong underneath an [Error], for callers which need more than its message
+///
+/// Errors from the libraries ndumplib uses are kept as they are, so they can be inspected too.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    #[error("I/O Error: {0}")]
+    IO(std::io::Error),
+    #[error("Network Error: {0}")]
+    Network(ureq::Error),
+    /// A download took too long (see [crate::NetworkTimeouts]), saying which timeout it hit
+    #[error("Network Error: Timed out ({0})")]
+    Timeout(ureq::Timeout),
+    #[error("Archive Error: {0}")]
+    Archive(#[from] compress_tools::Error),
+    #[error("XML Error: {0}")]
+    XML(#[from] roxmltree::Error),
+    #[error("XML Error: {0}")]
+    XMLRead(#[from] quick_xml::Error),
+    #[error("SQLite Error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("SSH Error: {0}")]
+    SSH(#[from] ssh2::Error),
+    /// An [crate::ExternalTool] isn't installed, or isn't on the PATH
+    #[error("{} isn't installed, or isn't on the PATH ({source})", tool.name())]
+    ToolMissing {
+        tool: crate::ExternalTool,
+        source: std::io::Error,
+    },
+    /// A file's hash didn't match the one it should have, like a copy's hash not matching the
+    /// original's
+    #[error("Expected {algorithm} {expected}, got {actual}")]
+    VerificationFailed {
+        algorithm: &'static str,
+        expected: String,
+        actual: String,
+    },
+    #[error("{0}")]
+    Other(#[from] visdom::types::BoxDynError),
 }
 
-impl std::fmt::Display for InnerError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl ErrorKind {
+    fn category(&self) -> ErrorCategory {
         match self {
-            Self::IOError(e) => write!(f, "I/O Error: {e}"),
-            Self::NetError(e) => write!(f, "Network Error: {e}"),
-            Self::ArchiveError(e) => write!(f, "Archive Error: {e}"),
-            Self::XMLError(e) => write!(f, "XML Error: {e}"),
-            Self::SQLiteError(e) => write!(f, "SQLite Error: {e}"),
-            Self::UnknownError(e) => write!(f, "{e}"),
+            Self::IO(_) => ErrorCategory::IO,
+            Self::Network(_) | Self::Timeout(_) | Self::SSH(_) => ErrorCategory::Network,
+            Self::Archive(_) | Self::XML(_) | Self::XMLRead(_) => ErrorCategory::InvalidData,
+            Self::Database(_) => ErrorCategory::Database,
+            Self::ToolMissing { tool, .. } => tool.missing_category(),
+            Self::VerificationFailed { .. } => ErrorCategory::VerificationFailed,
+            Self::Other(_) => ErrorCategory::Other,
         }
     }
 }
 
-impl From<std::io::Error> for InnerError {
-    fn from(error: std::io::Error) -> Self {
-        Self::IOError(error)
-    }
-}
-impl From<ureq::Error> for InnerError {
+impl From<ureq::Error> for ErrorKind {
     fn from(error: ureq::Error) -> Self {
-        Self::NetError(error)
-    }
-}
-impl From<compress_tools::Error> for InnerError {
-    fn from(error: compress_tools::Error) -> Self {
-        Self::ArchiveError(error)
-    }
-}
-impl From<roxmltree::Error> for InnerError {
-    fn from(error: roxmltree::Error) -> Self {
-        Self::XMLError(error)
+        match error {
+            ureq::Error::Timeout(timeout) => Self::Timeout(timeout),
+            error => Self::Network(error),
+        }
     }
 }
-impl From<rusqlite::Error> for InnerError {
-    fn from(error: rusqlite::Error) -> Self {
-        Self::SQLiteError(error)
+impl From<std::io::Error> for ErrorKind {
+    /// Network errors while reading a response body arrive as I/O errors, so they're unwrapped
+    fn from(error: std::io::Error) -> Self {
+        if error
+            .get_ref()
+            .is_some_and(|inner| inner.is::<ureq::Error>())
+        {
+            let inner = error
+                .into_inner()
+                .unwrap()
+                .downcast::<ureq::Error>()
+                .unwrap();
+            Self::from(*inner)
+        } else {
+            Self::IO(error)
+        }
     }
 }
-impl From<visdom::types::BoxDynError> for InnerError {
-    fn from(value: visdom::types::BoxDynError) -> Self {
-        Self::UnknownError(value)
-    }
+
+/// What kind of problem caused an [Error], so callers can react to it (e.g. with an exit code)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCategory {
+    /// A file couldn't be read or written
+    IO,
+    /// A website couldn't be reached, or sent something unexpected
+    Network,
+    /// The catalog or cuesheet DB couldn't be read or updated
+    Database,
+    /// A datafile, cuesheet, or archive couldn't be parsed
+    InvalidData,
+    /// A value given by the user (e.g. a console or query) couldn't be parsed
+    InvalidInput,
+    /// chdman isn't installed, isn't on the PATH, or is too old
+    ChdmanMissing,
+    /// dolphin-tool isn't installed, isn't on the PATH, or is too old
+    DolphinToolMissing,
+    /// maxcso isn't installed, isn't on the PATH, or is too old
+    MaxcsoMissing,
+    /// 7-Zip isn't installed, or isn't on the PATH
+    SevenZipMissing,
+    /// xdelta3 isn't installed, or isn't on the PATH
+    XdeltaMissing,
+    /// Another process is using the data folder
+    Busy,
+    /// A hash didn't match (see [ErrorKind::VerificationFailed])
+    VerificationFailed,
+    /// There isn't enough free space for a conversion or extraction
+    InsufficientSpace,
+    Other,
 }
 
+/// An error with a message saying what failed, and the [ErrorKind] behind it if there is one
+///
+/// Its [ErrorCategory] is usually taken from the kind, but can be replaced where the library knows
+/// better (e.g. a missing file given by the user is [ErrorCategory::InvalidInput]).
 #[derive(Debug)]
-pub struct Error(String, Option<InnerError>);
+pub struct Error {
+    message: String,
+    kind: Option<ErrorKind>,
+    category: ErrorCategory,
+}
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Error(str, Some(err)) => write!(f, "{str}\n{err}"),
-            Error(str, None) => write!(f, "{str}"),
+        match &self.kind {
+            Some(kind) => write!(f, "{}\n{kind}", self.message),
+            None => write!(f, "{}", self.message),
         }
     }
 }
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.kind
+            .as_ref()
+            .map(|kind| kind as &(dyn std::error::Error + 'static))
+    }
+}
 impl Error {
-    /// Creates a new [Error] with the given message and internal error
+    /// Creates a new [Error] with the given message and the error behind it
     ///
-    pub(crate) fn new<S: AsRef<str>, E: Into<InnerError>>(message: S, error: E) -> Error {
-        Error(message.as_ref().to_string(), Some(error.into()))
+    /// The category is taken from the error's [ErrorKind].
+    pub fn new<S: AsRef<str>, E: Into<ErrorKind>>(message: S, error: E) -> Error {
+        let kind = error.into();
+        Error {
+            message: message.as_ref().to_string(),
+            category: kind.category(),
+            kind: Some(kind),
+        }
     }
     /// Creates a new [Error] without a separate internal error
     ///
-    pub(crate) fn new_original<S: AsRef<str>>(message: S) -> Error {
-        Error(message.as_ref().to_string(), None)
+    /// Its category is [ErrorCategory::Other] until it's replaced with [Error::with_category].
+    /// This is how implementations outside the library (e.g. of [crate::DatafileSource]) report
+    /// errors.
+    pub fn new_original<S: AsRef<str>>(message: S) -> Error {
+        Error {
+            message: message.as_ref().to_string(),
+            kind: None,
+            category: ErrorCategory::Other,
+        }
+    }
+    /// Replaces the error's category
+    ///
+    pub fn with_category(mut self, category: ErrorCategory) -> Error {
+        self.category = category;
+        self
+    }
+    pub fn category(&self) -> ErrorCategory {
+        self.category
+    }
+    /// What went wrong underneath, if anything more is known than the message
+    ///
+    pub fn kind(&self) -> Option<&ErrorKind> {
+        self.kind.as_ref()
+    }
+    /// What failed, without the [ErrorKind]'s message
+    ///
+    pub fn message(&self) -> &str {
+        &self.message
     }
 }
 
-pub(crate) type Result<T> = std::result::Result<T, Error>;
+/// The result of anything in the library which can fail
+pub type Result<T> = std::result::Result<T, Error>;
 
 #[doc(hidden)]
 pub(crate) trait ResultUtils<T> {
     fn ndl<S: AsRef<str>>(self, message: S) -> Result<T>;
 }
-impl<T, E: Into<InnerError>> ResultUtils<T> for std::result::Result<T, E> {
+impl<T, E: Into<ErrorKind>> ResultUtils<T> for std::result::Result<T, E> {
     fn ndl<S: AsRef<str>>(self, message: S) -> Result<T> {
         match self {
             Ok(v) => Ok(v),
@@ -0,0 +1,48 @@
+use std::path::{Path, PathBuf};
+
+use crate::{GameEntry, Result, ResultUtils, transfer::write_atomically};
+
+mod esde;
+mod launchbox;
+mod pegasus;
+mod xml_list;
+
+pub use self::esde::{GAMELIST_FILE_NAME, update_gamelist};
+pub use self::launchbox::{LAUNCHBOX_FILE_NAME, launchbox_platform, update_launchbox_list};
+pub use self::pegasus::{PEGASUS_FILE_NAME, update_pegasus_metadata};
+
+/// A game in a frontend's list of a console's games
+#[derive(Clone, Debug)]
+pub struct FrontendGame {
+    /// The game's file (for games of several files, the one to launch, like the cuesheet),
+    /// relative to the console's folder
+    pub path: PathBuf,
+    pub game: GameEntry,
+}
+
+impl FrontendGame {
+    /// The game's path with forward slashes, like "Game (USA)/Game (USA).cue"
+    ///
+    pub(crate) fn relative_path(&self) -> String {
+        let components: Vec<_> = self
+            .path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect();
+        components.join("/")
+    }
+}
+
+/// Replaces a file with new content by way of a temporary file next to it, so that frontends never
+/// read it half written
+///
+/// Returns `false` without writing if the file already has that content.
+pub(crate) fn replace_file(path: &Path, content: &str) -> Result<bool> {
+    if std::fs::read_to_string(path).is_ok_and(|existing| existing == content) {
+        return Ok(false);
+    }
+    write_atomically(path, |partial| {
+        std::fs::write(partial, content).ndl(format!("Failed to write \"{}\"", path.display()))
+    })?;
+    Ok(true)
+}
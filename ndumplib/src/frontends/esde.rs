@@ -0,0 +1,116 @@
+use std::path::Path;
+
+use super::{
+    FrontendGame,
+    xml_list::{XMLListFormat, update_xml_list},
+};
+use crate::Result;
+
+/// The name of the files EmulationStation (and ES-DE) read a console's games from
+pub const GAMELIST_FILE_NAME: &str = "gamelist.xml";
+
+const GAMELIST_FORMAT: XMLListFormat = XMLListFormat {
+    root: "gameList",
+    game: "game",
+    path: "path",
+    name: "name",
+    catalog_elements: &["path", "name", "region"],
+};
+
+/// Writes or updates the gamelist in a console's folder, listing its games with their names and
+/// regions from the catalog, for EmulationStation-based frontends like ES-DE and Batocera
+///
+/// What's already listed about the games (like scraped metadata) is kept, following games which
+/// moved by their names. Games whose files are gone are dropped, and anything else in the gamelist
+/// is kept as it is. Returns whether the gamelist changed.
+pub fn update_gamelist(folder: &Path, games: &[FrontendGame]) -> Result<bool> {
+    update_xml_list(
+        &folder.join(GAMELIST_FILE_NAME),
+        folder,
+        &GAMELIST_FORMAT,
+        games,
+        |game| {
+            let mut fields = vec![
+                ("path", format!("./{}", game.relative_path())),
+                ("name", game.game.name.clone()),
+            ];
+            if let Some(region) = &game.game.region {
+                fields.push(("region", region.clone()));
+            }
+            fields
+        },
+        |_| Vec::new(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GameConsole, GameEntry};
+
+    fn game(path: &str, name: &str) -> FrontendGame {
+        FrontendGame {
+            path: path.into(),
+            game: GameEntry {
+                gid: 1,
+                console: GameConsole::PSX,
+                name: name.to_string(),
+                revision: 0,
+                datafile: String::from("Sony - PlayStation"),
+                serial: None,
+                region: Some(String::from("USA")),
+            },
+        }
+    }
+
+    #[test]
+    fn updates_gamelists() {
+        let directory = tempfile::tempdir().unwrap();
+        let folder = directory.path();
+        std::fs::write(folder.join("Homebrew.bin"), b"").unwrap();
+        std::fs::write(
+            folder.join(GAMELIST_FILE_NAME),
+            r#"<?xml version="1.0"?>
+<gameList>
+	<folder><path>./Empty</path></folder>
+	<game><path>./old/Game (USA).cue</path><name>Game (USA)</name><desc>Scraped &amp; kept</desc><playcount>3</playcount></game>
+	<game><path>./Homebrew.bin</path><name>Homebrew</name></game>
+	<game><path>./Gone.bin</path><name>Gone</name></game>
+</gameList>
+"#,
+        )
+        .unwrap();
+        let games = [
+            game("Game (USA).cue", "Game (USA)"),
+            game("Other & Game (USA).chd", "Other & Game (USA)"),
+        ];
+        assert!(update_gamelist(folder, &games).unwrap());
+        let content = std::fs::read_to_string(folder.join(GAMELIST_FILE_NAME)).unwrap();
+        // the moved game keeps its metadata, and what the catalog doesn't know is left alone
+        assert_eq!(
+            content,
+            r#"<?xml version="1.0"?>
+<gameList>
+	<folder><path>./Empty</path></folder>
+	<game>
+		<path>./Game (USA).cue</path>
+		<name>Game (USA)</name>
+		<region>USA</region>
+		<desc>Scraped &amp; kept</desc>
+		<playcount>3</playcount>
+	</game>
+	<game>
+		<path>./Other &amp; Game (USA).chd</path>
+		<name>Other &amp; Game (USA)</name>
+		<region>USA</region>
+	</game>
+	<game>
+		<path>./Homebrew.bin</path>
+		<name>Homebrew</name>
+	</game>
+</gameList>
+"#
+        );
+        assert!(!update_gamelist(folder, &games).unwrap());
+    }
+}
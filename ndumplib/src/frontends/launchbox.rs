@@ -0,0 +1,93 @@
+use std::path::Path;
+
+use sha1::{Digest, Sha1};
+
+use super::{
+    FrontendGame,
+    xml_list::{XMLListFormat, update_xml_list},
+};
+use crate::{GameConsole, Result};
+
+/// The name of the files listing a console's games for LaunchBox
+pub const LAUNCHBOX_FILE_NAME: &str = "launchbox.xml";
+
+const LAUNCHBOX_FORMAT: XMLListFormat = XMLListFormat {
+    root: "LaunchBox",
+    game: "Game",
+    path: "ApplicationPath",
+    name: "Title",
+    catalog_elements: &["ApplicationPath", "Title", "Platform", "Region"],
+};
+
+/// The name LaunchBox gives a console's platform
+pub fn launchbox_platform(console: GameConsole) -> &'static str {
+    match console {
+        GameConsole::Dreamcast => "Sega Dreamcast",
+        GameConsole::GB => "Nintendo Game Boy",
+        GameConsole::GBC => "Nintendo Game Boy Color",
+        GameConsole::GBA => "Nintendo Game Boy Advance",
+        GameConsole::GameCube => "Nintendo GameCube",
+        GameConsole::Genesis => "Sega Genesis",
+        GameConsole::N64 => "Nintendo 64",
+        GameConsole::NES => "Nintendo Entertainment System",
+        GameConsole::SNES => "Super Nintendo Entertainment System",
+        GameConsole::PSX => "Sony Playstation",
+        GameConsole::PS2 => "Sony Playstation 2",
+        GameConsole::PS3 => "Sony Playstation 3",
+        GameConsole::PSP => "Sony PSP",
+        GameConsole::Wii => "Nintendo Wii",
+        GameConsole::WiiU => "Nintendo Wii U",
+        GameConsole::Xbox => "Microsoft Xbox",
+        GameConsole::Xbox360 => "Microsoft Xbox 360",
+    }
+}
+
+/// A LaunchBox ID for a game, which is the same every time the game is listed
+fn game_id(game: &FrontendGame) -> String {
+    let digest = Sha1::new()
+        .chain_update(game.game.console.short_name())
+        .chain_update("/")
+        .chain_update(&game.game.name)
+        .finalize();
+    let hex = hex::encode(&digest[..16]);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Writes or updates the LaunchBox list in a console's folder, in the format of the platform files
+/// in LaunchBox's "Data/Platforms" folder, with the games' names and regions from the catalog
+///
+/// The games' files are listed by their full paths. What's already listed about the games is
+/// kept, like [super::update_gamelist]. Returns whether the list changed.
+pub fn update_launchbox_list(folder: &Path, games: &[FrontendGame]) -> Result<bool> {
+    update_xml_list(
+        &folder.join(LAUNCHBOX_FILE_NAME),
+        folder,
+        &LAUNCHBOX_FORMAT,
+        games,
+        |game| {
+            let mut fields = vec![
+                (
+                    "ApplicationPath",
+                    folder.join(&game.path).to_string_lossy().into_owned(),
+                ),
+                ("Title", game.game.name.clone()),
+                (
+                    "Platform",
+                    launchbox_platform(game.game.console).to_string(),
+                ),
+            ];
+            if let Some(region) = &game.game.region {
+                fields.push(("Region", region.clone()));
+            }
+            fields
+        },
+        |game| vec![("ID", game_id(game))],
+    )
+}
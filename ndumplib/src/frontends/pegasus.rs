@@ -0,0 +1,248 @@
+use std::{collections::HashSet, path::Path};
+
+use super::{FrontendGame, replace_file};
+use crate::{GameConsole, Result, ResultUtils};
+
+/// The name of the files Pegasus reads a collection's games from
+pub const PEGASUS_FILE_NAME: &str = "metadata.pegasus.txt";
+
+/// The keys written from the catalog, which replace those already in the file
+const CATALOG_KEYS: [&str; 6] = [
+    "collection",
+    "shortname",
+    "launch",
+    "game",
+    "file",
+    "x-region",
+];
+
+/// A collection or game in a metadata file, as the lines it was written with
+struct Block<'a> {
+    lines: Vec<&'a str>,
+}
+
+impl<'a> Block<'a> {
+    /// The first value of a key, like the game's name for "game"
+    fn value(&self, key: &str) -> Option<&'a str> {
+        self.lines.iter().find_map(|line| {
+            let (line_key, value) = line.split_once(':')?;
+            (line_key.trim() == key && !line.starts_with([' ', '\t'])).then(|| value.trim())
+        })
+    }
+
+    /// The block's lines, leaving out the keys written from the catalog (with the lines continuing
+    /// their values)
+    fn extra(&self) -> Vec<&'a str> {
+        let mut extra = Vec::new();
+        let mut skipping = false;
+        for line in &self.lines {
+            if line.starts_with([' ', '\t']) {
+                if !skipping {
+                    extra.push(*line);
+                }
+                continue;
+            }
+            skipping = line
+                .split_once(':')
+                .is_some_and(|(key, _)| CATALOG_KEYS.contains(&key.trim()));
+            if !skipping {
+                extra.push(*line);
+            }
+        }
+        extra
+    }
+}
+
+/// Splits a metadata file into what comes before its first collection or game, and its
+/// collections and games
+fn parse(input: &str) -> (Vec<&str>, Vec<Block<'_>>, Vec<Block<'_>>) {
+    let mut preamble = Vec::new();
+    let mut collections: Vec<Block> = Vec::new();
+    let mut games: Vec<Block> = Vec::new();
+    // whether the lines go to the last collection (true) or game (false)
+    let mut current = None;
+    for line in input.lines() {
+        let key = line
+            .split_once(':')
+            .filter(|_| !line.starts_with([' ', '\t', '#']))
+            .map(|(key, _)| key.trim());
+        match key {
+            Some("collection") => {
+                collections.push(Block { lines: Vec::new() });
+                current = Some(true);
+            }
+            Some("game") => {
+                games.push(Block { lines: Vec::new() });
+                current = Some(false);
+            }
+            _ => (),
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        match current {
+            None => preamble.push(line),
+            Some(true) => collections.last_mut().unwrap().lines.push(line),
+            Some(false) => games.last_mut().unwrap().lines.push(line),
+        }
+    }
+    (preamble, collections, games)
+}
+
+fn write_block(content: &mut String, fields: &[(&str, String)], extra: &[&str]) {
+    for (key, value) in fields {
+        content.push_str(&format!("{key}: {value}\n"));
+    }
+    for line in extra {
+        content.push_str(line);
+        content.push('\n');
+    }
+    content.push('\n');
+}
+
+/// Writes or updates the Pegasus metadata file in a console's folder, listing its games as a
+/// collection with their names and regions from the catalog
+///
+/// `launch` is the command Pegasus runs the games with, like "retroarch -L core.so {file.path}".
+/// What's already in the file about the collection and its games (like descriptions) is kept,
+/// following games which moved by their names. Games whose files are gone are dropped, and others
+/// are kept as they are. Returns whether the file changed.
+pub fn update_pegasus_metadata(
+    folder: &Path,
+    console: GameConsole,
+    games: &[FrontendGame],
+    launch: Option<&str>,
+) -> Result<bool> {
+    let path = folder.join(PEGASUS_FILE_NAME);
+    let existing = match std::fs::read_to_string(&path) {
+        Ok(existing) => existing,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound && games.is_empty() => {
+            return Ok(false);
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err).ndl(format!("Failed to read \"{}\"", path.display())),
+    };
+    let (preamble, collections, listed) = parse(&existing);
+    let mut content = String::new();
+    if !preamble.is_empty() {
+        write_block(&mut content, &[], &preamble);
+    }
+    let mut collection = vec![
+        ("collection", console.formal_name().to_string()),
+        ("shortname", console.short_name().to_string()),
+    ];
+    if let Some(launch) = launch {
+        collection.push(("launch", launch.to_string()));
+    }
+    let mut collections = collections.iter();
+    let extra = collections
+        .next()
+        .map(|collection| collection.extra())
+        .unwrap_or_default();
+    write_block(&mut content, &collection, &extra);
+    for other in collections {
+        write_block(&mut content, &[], &other.lines);
+    }
+    let is_missing = |block: &Block| {
+        block
+            .value("file")
+            .is_some_and(|file| !folder.join(file).exists())
+    };
+    let mut games: Vec<&FrontendGame> = games.iter().collect();
+    games.sort_by(|a, b| a.path.cmp(&b.path));
+    let files: Vec<String> = games.iter().map(|game| game.relative_path()).collect();
+    let file_set: HashSet<&str> = files.iter().map(String::as_str).collect();
+    let mut claimed = vec![false; listed.len()];
+    for (game, file) in games.iter().zip(&files) {
+        let position = listed
+            .iter()
+            .position(|block| block.value("file") == Some(file.as_str()))
+            .or_else(|| {
+                listed.iter().enumerate().position(|(index, block)| {
+                    !claimed[index]
+                        && block
+                            .value("file")
+                            .is_some_and(|file| !file_set.contains(file))
+                        && block.value("game") == Some(game.game.name.as_str())
+                        && is_missing(block)
+                })
+            });
+        let extra = match position {
+            Some(index) => {
+                claimed[index] = true;
+                listed[index].extra()
+            }
+            None => Vec::new(),
+        };
+        let mut fields = vec![("game", game.game.name.clone()), ("file", file.clone())];
+        if let Some(region) = &game.game.region {
+            fields.push(("x-region", region.clone()));
+        }
+        write_block(&mut content, &fields, &extra);
+    }
+    for (index, block) in listed.iter().enumerate() {
+        let known = block
+            .value("file")
+            .is_some_and(|file| file_set.contains(file));
+        if claimed[index] || known || is_missing(block) {
+            continue;
+        }
+        // games the catalog doesn't know, like homebrew, are the user's to list
+        write_block(&mut content, &[], &block.lines);
+    }
+    // a single line break at the end
+    content.truncate(content.trim_end().len());
+    content.push('\n');
+    replace_file(&path, &content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GameEntry;
+
+    #[test]
+    fn updates_pegasus_metadata() {
+        let directory = tempfile::tempdir().unwrap();
+        let folder = directory.path();
+        std::fs::write(
+            folder.join(PEGASUS_FILE_NAME),
+            "# my games\ncollection: Old\nlaunch: old-emulator {file.path}\nsort-by: 1\n\ngame: Game (USA)\nfile: old/Game (USA).chd\ndescription: Kept,\n  over two lines\nx-region: Nowhere\n\ngame: Gone\nfile: Gone.bin\n",
+        )
+        .unwrap();
+        let games = [FrontendGame {
+            path: "Game (USA).chd".into(),
+            game: GameEntry {
+                gid: 1,
+                console: GameConsole::PSX,
+                name: String::from("Game (USA)"),
+                revision: 0,
+                datafile: String::from("Sony - PlayStation"),
+                serial: None,
+                region: Some(String::from("USA")),
+            },
+        }];
+        assert!(
+            update_pegasus_metadata(
+                folder,
+                GameConsole::PSX,
+                &games,
+                Some("emulator {file.path}")
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            std::fs::read_to_string(folder.join(PEGASUS_FILE_NAME)).unwrap(),
+            "# my games\n\ncollection: PlayStation\nshortname: psx\nlaunch: emulator {file.path}\nsort-by: 1\n\ngame: Game (USA)\nfile: Game (USA).chd\nx-region: USA\ndescription: Kept,\n  over two lines\n"
+        );
+        assert!(
+            !update_pegasus_metadata(
+                folder,
+                GameConsole::PSX,
+                &games,
+                Some("emulator {file.path}")
+            )
+            .unwrap()
+        );
+    }
+}
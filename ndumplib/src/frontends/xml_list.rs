@@ -0,0 +1,173 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use quick_xml::escape::escape;
+use roxmltree::{Document, Node};
+
+use super::{FrontendGame, replace_file};
+use crate::{Result, ResultUtils};
+
+/// How a frontend's XML list of games is laid out
+pub(super) struct XMLListFormat {
+    /// The root element, like "gameList"
+    pub root: &'static str,
+    /// The element of each game, like "game"
+    pub game: &'static str,
+    /// The elements holding a game's file and name
+    pub path: &'static str,
+    pub name: &'static str,
+    /// The elements written from the catalog, which replace those already in the list
+    pub catalog_elements: &'static [&'static str],
+}
+
+/// An element of a game written from the catalog, with its text
+pub(super) type Field = (&'static str, String);
+
+/// A game already in a list
+struct ListedGame<'a> {
+    path: &'a str,
+    name: Option<&'a str>,
+    /// The game's other elements (like scraped descriptions and play counts), as they were written
+    extra: Vec<&'a str>,
+}
+
+impl<'a> ListedGame<'a> {
+    fn parse(node: Node<'a, 'a>, input: &'a str, format: &XMLListFormat) -> Option<ListedGame<'a>> {
+        let text = |name: &str| {
+            node.children()
+                .find(|child| child.has_tag_name(name))
+                .and_then(|child| child.text())
+                .map(str::trim)
+        };
+        Some(ListedGame {
+            path: text(format.path)?,
+            name: text(format.name),
+            extra: node
+                .children()
+                .filter(|child| {
+                    child.is_element()
+                        && !format.catalog_elements.contains(&child.tag_name().name())
+                })
+                .map(|child| &input[child.range()])
+                .collect(),
+        })
+    }
+
+    /// Whether the game's file is gone, like after sorting moved it
+    fn is_missing(&self, folder: &Path) -> bool {
+        let file = match self.path.strip_prefix("./") {
+            Some(path) => folder.join(path),
+            None if Path::new(self.path).is_absolute() => PathBuf::from(self.path),
+            // paths relative to something else (like "%ROMPATH%") are left alone
+            None => return false,
+        };
+        !file.exists()
+    }
+}
+
+fn write_game(content: &mut String, format: &XMLListFormat, fields: &[Field], extra: &[&str]) {
+    content.push_str(&format!("\t<{}>\n", format.game));
+    for (element, text) in fields {
+        content.push_str(&format!("\t\t<{element}>{}</{element}>\n", escape(text)));
+    }
+    for element in extra {
+        content.push_str(&format!("\t\t{element}\n"));
+    }
+    content.push_str(&format!("\t</{}>\n", format.game));
+}
+
+/// Writes or updates an XML list of a folder's games
+///
+/// `fields` gives the elements written for each game from the catalog, starting with its path, and
+/// `new_fields` the ones only written for games which weren't listed yet (like IDs). What's
+/// already listed about the games (like scraped metadata) is kept, following games which moved by
+/// their names. Games whose files are gone are dropped, and anything else in the list is kept as
+/// it is. Returns whether the list changed.
+pub(super) fn update_xml_list(
+    file: &Path,
+    folder: &Path,
+    format: &XMLListFormat,
+    games: &[FrontendGame],
+    fields: impl Fn(&FrontendGame) -> Vec<Field>,
+    new_fields: impl Fn(&FrontendGame) -> Vec<Field>,
+) -> Result<bool> {
+    let existing = match std::fs::read_to_string(file) {
+        Ok(existing) => Some(existing),
+        // folders of nothing the catalog knows don't need lists
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound && games.is_empty() => {
+            return Ok(false);
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err).ndl(format!("Failed to read \"{}\"", file.display())),
+    };
+    let document = existing
+        .as_deref()
+        .map(Document::parse)
+        .transpose()
+        .ndl(format!("Failed to parse \"{}\"", file.display()))?;
+    let mut listed = Vec::new();
+    let mut others = Vec::new();
+    if let (Some(input), Some(document)) = (&existing, &document) {
+        for node in document.root_element().children().filter(Node::is_element) {
+            match node
+                .has_tag_name(format.game)
+                .then(|| ListedGame::parse(node, input, format))
+            {
+                Some(Some(game)) => listed.push(game),
+                // folders, alternative emulators, and malformed games
+                _ => others.push(&input[node.range()]),
+            }
+        }
+    }
+    let mut games: Vec<(&FrontendGame, Vec<Field>)> =
+        games.iter().map(|game| (game, fields(game))).collect();
+    games.sort_by(|(a, _), (b, _)| a.path.cmp(&b.path));
+    let paths: HashSet<&str> = games
+        .iter()
+        .map(|(_, fields)| fields[0].1.as_str())
+        .collect();
+    let mut claimed = vec![false; listed.len()];
+    let mut content = format!("<?xml version=\"1.0\"?>\n<{}>\n", format.root);
+    for other in others {
+        content.push_str(&format!("\t{other}\n"));
+    }
+    for (game, mut fields) in games.iter().map(|(game, fields)| (*game, fields.clone())) {
+        let position = listed
+            .iter()
+            .position(|listed| listed.path == fields[0].1)
+            .or_else(|| {
+                listed.iter().enumerate().position(|(index, listed)| {
+                    !claimed[index]
+                        && !paths.contains(listed.path)
+                        && listed.name == Some(game.game.name.as_str())
+                        && listed.is_missing(folder)
+                })
+            });
+        let extra = match position {
+            Some(index) => {
+                claimed[index] = true;
+                listed[index].extra.as_slice()
+            }
+            None => {
+                fields.extend(new_fields(game));
+                &[]
+            }
+        };
+        write_game(&mut content, format, &fields, extra);
+    }
+    for (index, game) in listed.iter().enumerate() {
+        if claimed[index] || paths.contains(game.path) || game.is_missing(folder) {
+            continue;
+        }
+        // games the catalog doesn't know, like homebrew, are the user's to list
+        let mut fields = vec![(format.path, game.path.to_string())];
+        if let Some(name) = game.name {
+            fields.push((format.name, name.to_string()));
+        }
+        write_game(&mut content, format, &fields, &game.extra);
+    }
+    content.push_str(&format!("</{}>\n", format.root));
+    replace_file(file, &content)
+}
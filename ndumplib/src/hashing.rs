@@ -0,0 +1,330 @@
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{ErrorKind, Read},
+    path::Path,
+    sync::{Condvar, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use md5::Md5;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use crate::{
+    Error, ErrorCategory, Result, ResultUtils,
+    dump_manager::timings::{Stage, StageTimer},
+};
+
+/// How files are read while they're hashed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HashingOptions {
+    /// How much of a file is read at a time, in bytes
+    pub buffer_size: usize,
+    /// Whether files are mapped into memory instead of read, which saves copying them into a buffer
+    ///
+    /// This is off by default: it's slower on some filesystems (like network shares), and a file
+    /// truncated by another program while it's being hashed crashes the process.
+    pub mmap: bool,
+    /// The most bytes read per second, across every file being hashed, so hashing a library on a
+    /// network share doesn't take up the whole link (by default, there's no limit)
+    pub max_read_rate: Option<u64>,
+    /// The most files read at once, however many threads are hashing (by default, there's no
+    /// limit)
+    pub max_concurrent_reads: Option<usize>,
+}
+
+impl HashingOptions {
+    /// The buffer size files are hashed with by default, the same as copies are made with
+    ///
+    /// This is far fewer reads than `std::io::copy`'s 8 KiB buffer makes. The `hash_benchmark`
+    /// example compares buffer sizes (and mmap) on a given disk.
+    pub const DEFAULT_BUFFER_SIZE: usize = 1 << 20;
+}
+
+impl Default for HashingOptions {
+    fn default() -> Self {
+        HashingOptions {
+            buffer_size: Self::DEFAULT_BUFFER_SIZE,
+            mmap: false,
+            max_read_rate: None,
+            max_concurrent_reads: None,
+        }
+    }
+}
+
+static OPTIONS: Mutex<HashingOptions> = Mutex::new(HashingOptions {
+    buffer_size: HashingOptions::DEFAULT_BUFFER_SIZE,
+    mmap: false,
+    max_read_rate: None,
+    max_concurrent_reads: None,
+});
+
+/// When the next read may start under the max read rate, shared by every thread hashing
+static NEXT_READ: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// How many files are being read, to hold the max concurrent reads
+static OPEN_READS: Mutex<usize> = Mutex::new(0);
+static READ_FINISHED: Condvar = Condvar::new();
+
+/// Buffers start on a page boundary, so the kernel can copy whole pages into them
+const BUFFER_ALIGNMENT: usize = 4096;
+
+/// Sets how files are read while they're hashed, from now on
+///
+pub fn set_hashing_options(options: HashingOptions) {
+    *OPTIONS.lock().unwrap() = options;
+}
+
+pub(crate) fn hashing_options() -> HashingOptions {
+    *OPTIONS.lock().unwrap()
+}
+
+/// Waits long enough after reading `length` bytes to stay under the max read rate
+///
+/// Reads are paced one after another, so the rate holds however many threads are reading.
+fn throttle(length: usize) {
+    let Some(rate) = hashing_options().max_read_rate else {
+        return;
+    };
+    let wait = {
+        let mut next_read = NEXT_READ.lock().unwrap();
+        let now = Instant::now();
+        let start = next_read.map_or(now, |next_read| next_read.max(now));
+        *next_read = Some(start + Duration::from_secs_f64(length as f64 / rate.max(1) as f64));
+        start - now
+    };
+    if !wait.is_zero() {
+        std::thread::sleep(wait);
+    }
+}
+
+/// A file being read under the max concurrent reads, which frees its place once it's dropped
+struct ReadSlot {
+    limited: bool,
+}
+
+impl ReadSlot {
+    /// Waits for a place to read a file in, if the number of reads at once is limited
+    fn acquire() -> ReadSlot {
+        let Some(max) = hashing_options().max_concurrent_reads else {
+            return ReadSlot { limited: false };
+        };
+        let mut open_reads = OPEN_READS.lock().unwrap();
+        while *open_reads >= max.max(1) {
+            open_reads = READ_FINISHED.wait(open_reads).unwrap();
+        }
+        *open_reads += 1;
+        ReadSlot { limited: true }
+    }
+}
+
+impl Drop for ReadSlot {
+    fn drop(&mut self) {
+        if self.limited {
+            // this runs while unwinding too, where a poisoned lock mustn't panic again
+            *OPEN_READS.lock().unwrap_or_else(PoisonError::into_inner) -= 1;
+            READ_FINISHED.notify_one();
+        }
+    }
+}
+
+/// Feeds what's left of a reader to `update` a buffer at a time, returning how many bytes it read
+///
+pub(crate) fn hash_reader(
+    reader: &mut impl Read,
+    mut update: impl FnMut(&[u8]),
+) -> std::io::Result<u64> {
+    let buffer_size = hashing_options().buffer_size.max(BUFFER_ALIGNMENT);
+    let mut storage = vec![0u8; buffer_size + BUFFER_ALIGNMENT];
+    let offset = storage.as_ptr().align_offset(BUFFER_ALIGNMENT);
+    let buffer = &mut storage[offset..offset + buffer_size];
+    let mut total = 0;
+    loop {
+        let length = match reader.read(buffer) {
+            Ok(0) => return Ok(total),
+            Ok(length) => length,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        update(&buffer[..length]);
+        total += length as u64;
+        throttle(length);
+    }
+}
+
+/// Feeds a file's contents to `update`, mapping it into memory if that's enabled (see
+/// [HashingOptions]), and returns its size
+///
+pub(crate) fn hash_file(path: &Path, mut update: impl FnMut(&[u8])) -> std::io::Result<u64> {
+    let options = hashing_options();
+    let _slot = ReadSlot::acquire();
+    let mut file = File::open(path)?;
+    // empty files can't be mapped
+    if options.mmap && file.metadata()?.len() > 0 {
+        // SAFETY: the map is only read from, and lives no longer than this call. Another program
+        // truncating the file meanwhile would crash the process, which is why mmap is opt-in.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        let _ = map.advise(memmap2::Advice::Sequential);
+        for chunk in map.chunks(options.buffer_size.max(BUFFER_ALIGNMENT)) {
+            update(chunk);
+            throttle(chunk.len());
+        }
+        return Ok(map.len() as u64);
+    }
+    hash_reader(&mut file, update)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HashAlgorithm {
+    CRC32,
+    MD5,
+    SHA1,
+    SHA256,
+}
+
+impl HashAlgorithm {
+    pub const ALL: [HashAlgorithm; 4] = [Self::CRC32, Self::MD5, Self::SHA1, Self::SHA256];
+
+    pub fn formal_name(&self) -> &'static str {
+        match self {
+            Self::CRC32 => "CRC32",
+            Self::MD5 => "MD5",
+            Self::SHA1 => "SHA-1",
+            Self::SHA256 => "SHA-256",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FileHash {
+    CRC32(u32),
+    MD5([u8; 16]),
+    SHA1([u8; 20]),
+    SHA256([u8; 32]),
+}
+
+impl Display for FileHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CRC32(crc) => write!(f, "{crc:08x}"),
+            Self::MD5(md5) => write!(f, "{}", hex::encode(md5)),
+            Self::SHA1(sha1) => write!(f, "{}", hex::encode(sha1)),
+            Self::SHA256(sha256) => write!(f, "{}", hex::encode(sha256)),
+        }
+    }
+}
+
+impl FileHash {
+    /// Parses a hex string as a hash of the given algorithm
+    ///
+    pub fn from_hex(algorithm: HashAlgorithm, value: &str) -> Result<FileHash> {
+        let value = value.trim();
+        let invalid = || {
+            Error::new_original(format!(
+                "Invalid {} hash: \"{value}\"",
+                algorithm.formal_name()
+            ))
+            .with_category(ErrorCategory::InvalidInput)
+        };
+        match algorithm {
+            HashAlgorithm::CRC32 => {
+                let mut bytes = [0u8; 4];
+                hex::decode_to_slice(value, &mut bytes).map_err(|_| invalid())?;
+                Ok(Self::CRC32(u32::from_be_bytes(bytes)))
+            }
+            HashAlgorithm::MD5 => {
+                let mut bytes = [0u8; 16];
+                hex::decode_to_slice(value, &mut bytes).map_err(|_| invalid())?;
+                Ok(Self::MD5(bytes))
+            }
+            HashAlgorithm::SHA1 => {
+                let mut bytes = [0u8; 20];
+                hex::decode_to_slice(value, &mut bytes).map_err(|_| invalid())?;
+                Ok(Self::SHA1(bytes))
+            }
+            HashAlgorithm::SHA256 => {
+                let mut bytes = [0u8; 32];
+                hex::decode_to_slice(value, &mut bytes).map_err(|_| invalid())?;
+                Ok(Self::SHA256(bytes))
+            }
+        }
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        match self {
+            Self::CRC32(_) => HashAlgorithm::CRC32,
+            Self::MD5(_) => HashAlgorithm::MD5,
+            Self::SHA1(_) => HashAlgorithm::SHA1,
+            Self::SHA256(_) => HashAlgorithm::SHA256,
+        }
+    }
+
+    /// Hashes the contents of a file with the given algorithm
+    ///
+    pub fn of_file(algorithm: HashAlgorithm, path: &impl AsRef<Path>) -> Result<FileHash> {
+        let _timer = StageTimer::start(Stage::Hashing);
+        let path = path.as_ref();
+        match algorithm {
+            HashAlgorithm::CRC32 => {
+                let mut hasher = crc32fast::Hasher::new();
+                hash_file(path, |chunk| hasher.update(chunk)).ndl("Failed to hash file")?;
+                Ok(Self::CRC32(hasher.finalize()))
+            }
+            HashAlgorithm::MD5 => {
+                let mut hasher = Md5::new();
+                hash_file(path, |chunk| hasher.update(chunk)).ndl("Failed to hash file")?;
+                Ok(Self::MD5(hasher.finalize().into()))
+            }
+            HashAlgorithm::SHA1 => {
+                let mut hasher = Sha1::new();
+                hash_file(path, |chunk| hasher.update(chunk)).ndl("Failed to hash file")?;
+                Ok(Self::SHA1(hasher.finalize().into()))
+            }
+            HashAlgorithm::SHA256 => {
+                let mut hasher = Sha256::new();
+                hash_file(path, |chunk| hasher.update(chunk)).ndl("Failed to hash file")?;
+                Ok(Self::SHA256(hasher.finalize().into()))
+            }
+        }
+    }
+
+    /// Checks whether a file's contents match this hash
+    ///
+    pub fn matches_file(&self, path: &impl AsRef<Path>) -> Result<bool> {
+        Ok(FileHash::of_file(self.algorithm(), path)? == *self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_the_same_however_files_are_read() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("dump.bin");
+        let contents: Vec<u8> = (0..100_000u32).map(|byte| byte as u8).collect();
+        std::fs::write(&path, &contents).unwrap();
+        let expected = FileHash::SHA1(Sha1::digest(&contents).into());
+        for mmap in [false, true] {
+            // smaller than the file, and than the alignment, so it's read in several pieces
+            set_hashing_options(HashingOptions {
+                buffer_size: 1000,
+                mmap,
+                ..HashingOptions::default()
+            });
+            assert_eq!(
+                FileHash::of_file(HashAlgorithm::SHA1, &path).unwrap(),
+                expected
+            );
+            let mut chunks = 0;
+            assert_eq!(hash_file(&path, |_| chunks += 1).unwrap(), 100_000);
+            assert_eq!(chunks, 100_000usize.div_ceil(BUFFER_ALIGNMENT));
+        }
+        set_hashing_options(HashingOptions::default());
+        let empty = directory.path().join("empty.bin");
+        std::fs::write(&empty, b"").unwrap();
+        assert_eq!(hash_file(&empty, |_| ()).unwrap(), 0);
+    }
+}
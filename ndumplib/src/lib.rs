@@ -1,9 +1,86 @@
+//! Identifies, verifies, and organizes game dumps using the No-Intro and Redump catalogs
+//!
+//! [DumpManager] is the entry point. It keeps a catalog of known dumps (built from the No-Intro and
+//! Redump datafiles) and a database of Redump's cuesheets in a data folder, and answers questions
+//! about dump files against them: which game a file is ([DumpManager::get_rom_info]), whether it's
+//! intact ([DumpManager::verify_file]), and what it should be named. [CatalogReader] gives
+//! read-only access to the catalog itself, for listing and searching games.
+//!
+//! ```no_run
+//! use ndumplib::{DumpManager, ROMStatus, WhenLocked};
+//!
+//! let mut manager = DumpManager::init(&"/path/to/data", WhenLocked::Wait)?;
+//! // downloads the datafiles if the catalog is empty or out of date
+//! manager.update()?;
+//! if let Some(info) = manager.get_rom_info("/path/to/game.iso")? {
+//!     println!("{} ({})", info.game_name, info.console.formal_name());
+//! }
+//! if manager.verify_file(&"/path/to/game.iso")? == ROMStatus::Verified {
+//!     println!("The dump is intact");
+//! }
+//! # Ok::<(), ndumplib::Error>(())
+//! ```
+//!
+//! Failures are reported as [Error]s, whose [ErrorCategory] tells what kind of problem it was.
+//! Problems which don't stop an operation are reported as [Warning]s instead (see
+//! [take_warnings] and [collect_warnings]), and the time spent in each [Stage] is tracked by [run_timings].
+//! [chd_info] reads the layout of a CHD (its tracks and compression) without needing chdman.
+//! [retroachievements_hash] hashes dumps the way RetroAchievements recognizes them.
+//!
+//! # Stability
+//!
+//! Everything re-exported here is the public API, versioned with semver: while the version is
+//! 0.x, breaking changes to it raise the minor version. Anything else is internal.
+//! See the `examples` folder for complete programs.
+
 pub(crate) mod utils;
 
 mod dump_manager;
 mod error;
+mod frontends;
+mod hashing;
+mod patch;
+mod retroachievements;
+mod storage;
+mod transfer;
 mod types;
 
-pub use dump_manager::*;
-pub use error::*;
-pub use types::*;
+pub use dump_manager::{
+    ArchiveEntry, AvailableDatafile, BlockRule, CatalogChange, CatalogROM, CatalogReader,
+    ChangeKind, CheckpointPolicy, CollectionPart, ConversionJob, ConversionLimits, CustomGame,
+    CustomROM, DatabaseCheck, DatabaseMaintenance, DatabaseSchema, DatafileDiff, DatafileInfo,
+    DatafileSource, DiscSerial, DumpManager, FetchedDatafile, FileDatafileSource, GameEntry,
+    GameQuery, GameRename, GameTracks, ImageModification, IndexedGame, LocalDatafile,
+    NetworkTimeouts, NoIntroSource, PatchedDump, ROMChange, ROMInfo, ROMStatus, Recovery,
+    RedumpSource, Rename, RenamePlan, RunTimings, SourceHandling, SplitDump, SplitKind, Stage,
+    TrackIndex, TrackMatches, UpdateTarget, Warning, WarningKind, WhenLocked, collect_warnings,
+    extract_archive, find_split_dumps, report_warning, run_timings, take_warnings,
+};
+pub use error::{Error, ErrorCategory, ErrorKind, Result};
+pub use frontends::{
+    FrontendGame, GAMELIST_FILE_NAME, LAUNCHBOX_FILE_NAME, PEGASUS_FILE_NAME, launchbox_platform,
+    update_gamelist, update_launchbox_list, update_pegasus_metadata,
+};
+pub use hashing::{FileHash, HashAlgorithm, HashingOptions, set_hashing_options};
+pub use patch::{PatchFormat, apply_patch};
+pub use retroachievements::{
+    fetch_retroachievements_hashes, retroachievements_console_id, retroachievements_hash,
+};
+pub use storage::{
+    LocalStorage, SftpStorage, Storage, StorageEntry, StorageLocation, remove_temporary_files,
+};
+pub use transfer::{
+    LinkMethod, TransferMode, duplicate_file, is_temporary_file, temporary_path, transfer_file,
+    transfer_file_over,
+};
+pub use types::GameConsole;
+pub use utils::chdman::{
+    ChdInfo, ChdMedia, ChdMetadata, ChdTrack, Codec, TrackType, info as chd_info,
+};
+pub use utils::dolphin::DiscFormat;
+pub use utils::maxcso::{CsoFormat, CsoOptions};
+pub use utils::multi_hasher::{Digests, MultiHasher};
+pub use utils::tool::{ExternalTool, ToolVersion};
+pub use utils::work::{set_work_directory, work_directory};
+
+pub(crate) use error::ResultUtils;
@@ -0,0 +1,414 @@
+use std::{fs, path::Path};
+
+use log::debug;
+
+use crate::{
+    Digests, Error, ErrorCategory, ErrorKind, FileHash, MultiHasher, Result, ResultUtils,
+    transfer::write_atomically, utils::xdelta,
+};
+
+/// A format of patches made to turn one ROM into another, like a translation or a hack
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PatchFormat {
+    /// International Patching System, the oldest and most common, which only replaces bytes
+    IPS,
+    /// Beat's format, which checks the CRC32s of the ROM it's applied to and the ROM it makes
+    BPS,
+    /// xdelta (VCDIFF), common for disc images, applied with xdelta3
+    Xdelta,
+}
+
+impl PatchFormat {
+    /// The format of a patch file, going by its extension
+    ///
+    pub fn of(path: &impl AsRef<Path>) -> Option<PatchFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ips" => Some(Self::IPS),
+            "bps" => Some(Self::BPS),
+            "xdelta" | "vcdiff" => Some(Self::Xdelta),
+            _ => None,
+        }
+    }
+}
+
+/// The most a BPS patch's output is allocated up front, however big the patch says it is (it grows
+/// past this as it's written)
+const MAX_BPS_PREALLOCATION: u64 = 64 << 20;
+
+fn malformed(format: &str, reason: &str) -> Error {
+    Error::new_original(format!(
+        "Failed to apply patch\nMalformed {format} patch: {reason}"
+    ))
+    .with_category(ErrorCategory::InvalidData)
+}
+
+fn mismatch(message: &str, expected: FileHash, actual: FileHash) -> Error {
+    Error::new(
+        message,
+        ErrorKind::VerificationFailed {
+            algorithm: expected.algorithm().formal_name(),
+            expected: expected.to_string(),
+            actual: actual.to_string(),
+        },
+    )
+}
+
+/// Applies an IPS patch: records of bytes to write at an offset (or one byte repeated), growing
+/// the ROM if they're past its end, and maybe a size to truncate it to at the end
+fn apply_ips(base: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    let truncated = || malformed("IPS", "ends early");
+    if !patch.starts_with(b"PATCH") {
+        return Err(malformed("IPS", "no \"PATCH\" header"));
+    }
+    let mut output = base.to_vec();
+    let mut position = 5;
+    let mut read = |length: usize| -> Result<&[u8]> {
+        let bytes = patch
+            .get(position..position + length)
+            .ok_or_else(truncated)?;
+        position += length;
+        Ok(bytes)
+    };
+    let number = |bytes: &[u8]| bytes.iter().fold(0usize, |n, byte| n << 8 | *byte as usize);
+    loop {
+        let offset = read(3)?;
+        if offset == b"EOF" {
+            break;
+        }
+        let offset = number(offset);
+        let size = number(read(2)?);
+        let (size, data) = if size == 0 {
+            // run-length encoded: a size, then the byte to repeat
+            let size = number(read(2)?);
+            (size, vec![read(1)?[0]; size])
+        } else {
+            (size, read(size)?.to_vec())
+        };
+        if output.len() < offset + size {
+            output.resize(offset + size, 0);
+        }
+        output[offset..offset + size].copy_from_slice(&data);
+    }
+    if let Ok(size) = read(3) {
+        output.truncate(number(size));
+    }
+    Ok(output)
+}
+
+/// Reads one of BPS's variable-length numbers, 7 bits per byte
+fn read_bps_number(patch: &[u8], position: &mut usize) -> Result<u64> {
+    let mut number = 0u64;
+    let mut shift = 1u64;
+    loop {
+        let byte = *patch
+            .get(*position)
+            .ok_or_else(|| malformed("BPS", "ends early"))?;
+        *position += 1;
+        number = shift
+            .checked_mul((byte & 0x7F) as u64)
+            .and_then(|value| number.checked_add(value))
+            .ok_or_else(|| malformed("BPS", "number too large"))?;
+        if byte & 0x80 != 0 {
+            return Ok(number);
+        }
+        shift = shift
+            .checked_mul(1 << 7)
+            .ok_or_else(|| malformed("BPS", "number too large"))?;
+        number = number
+            .checked_add(shift)
+            .ok_or_else(|| malformed("BPS", "number too large"))?;
+    }
+}
+
+/// Applies a BPS patch, checking the CRC32s it carries of the patch itself, the ROM it's made for,
+/// and the ROM it makes
+fn apply_bps(base: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    if !patch.starts_with(b"BPS1") || patch.len() < 4 + 12 {
+        return Err(malformed("BPS", "no \"BPS1\" header"));
+    }
+    let footer = patch.len() - 12;
+    let crc = |offset: usize| {
+        FileHash::CRC32(u32::from_le_bytes(
+            patch[offset..offset + 4].try_into().unwrap(),
+        ))
+    };
+    let (source_crc, target_crc, patch_crc) = (crc(footer), crc(footer + 4), crc(footer + 8));
+    let actual = FileHash::CRC32(crc32fast::hash(&patch[..footer + 8]));
+    if actual != patch_crc {
+        return Err(mismatch(
+            "Failed to apply patch\nThe BPS patch is damaged",
+            patch_crc,
+            actual,
+        ));
+    }
+    let actual = FileHash::CRC32(crc32fast::hash(base));
+    if actual != source_crc {
+        return Err(mismatch(
+            "Failed to apply patch\nThe BPS patch is for another ROM",
+            source_crc,
+            actual,
+        ));
+    }
+    let out_of_range = || malformed("BPS", "copies from outside the ROMs");
+    let mut position = 4;
+    let source_size = read_bps_number(patch, &mut position)?;
+    let target_size = read_bps_number(patch, &mut position)?;
+    let metadata_size = read_bps_number(patch, &mut position)?;
+    if source_size != base.len() as u64 {
+        return Err(malformed("BPS", "made for a ROM of another size"));
+    }
+    position = usize::try_from(metadata_size)
+        .ok()
+        .and_then(|metadata_size| position.checked_add(metadata_size))
+        .filter(|&position| position <= footer)
+        .ok_or_else(|| malformed("BPS", "ends early"))?;
+    // the size comes from the patch, so a huge one mustn't be allocated before anything is written
+    let mut output: Vec<u8> = Vec::with_capacity(target_size.min(MAX_BPS_PREALLOCATION) as usize);
+    let (mut source_offset, mut target_offset) = (0i64, 0i64);
+    while position < footer {
+        let action = read_bps_number(patch, &mut position)?;
+        let length = usize::try_from(action >> 2)
+            .ok()
+            .and_then(|length| length.checked_add(1))
+            .filter(|&length| {
+                (output.len() as u64)
+                    .checked_add(length as u64)
+                    .is_some_and(|end| end <= target_size)
+            })
+            .ok_or_else(|| malformed("BPS", "writes past the end of the patched ROM"))?;
+        // the end of a range of `length` bytes starting at `start`, if it doesn't overflow
+        let end = |start: usize| start.checked_add(length);
+        match action & 3 {
+            // the base's bytes at the same offset
+            0 => {
+                let start = output.len();
+                let bytes = end(start)
+                    .and_then(|end| base.get(start..end))
+                    .ok_or_else(out_of_range)?;
+                output.extend_from_slice(bytes);
+            }
+            // bytes from the patch
+            1 => {
+                let bytes = end(position)
+                    .filter(|&end| end <= footer)
+                    .and_then(|end| patch.get(position..end))
+                    .ok_or_else(|| malformed("BPS", "ends early"))?;
+                output.extend_from_slice(bytes);
+                position += length;
+            }
+            // bytes from elsewhere in the base, or earlier in the output (which may overlap)
+            command => {
+                let relative = read_bps_number(patch, &mut position)?;
+                let distance = (relative >> 1) as i64 * if relative & 1 != 0 { -1 } else { 1 };
+                let offset = if command == 2 {
+                    &mut source_offset
+                } else {
+                    &mut target_offset
+                };
+                *offset = offset.checked_add(distance).ok_or_else(out_of_range)?;
+                let start = usize::try_from(*offset).map_err(|_| out_of_range())?;
+                let end = end(start).ok_or_else(out_of_range)?;
+                if command == 2 {
+                    let bytes = base.get(start..end).ok_or_else(out_of_range)?;
+                    output.extend_from_slice(bytes);
+                } else {
+                    if start >= output.len() {
+                        return Err(out_of_range());
+                    }
+                    for index in start..end {
+                        output.push(output[index]);
+                    }
+                }
+                *offset = i64::try_from(end).map_err(|_| out_of_range())?;
+            }
+        }
+    }
+    if output.len() as u64 != target_size {
+        return Err(malformed("BPS", "makes a ROM of the wrong size"));
+    }
+    let actual = FileHash::CRC32(crc32fast::hash(&output));
+    if actual != target_crc {
+        return Err(mismatch(
+            "Failed to apply patch\nThe patched ROM doesn't match the BPS patch",
+            target_crc,
+            actual,
+        ));
+    }
+    Ok(output)
+}
+
+/// Applies a patch to `base`, writing the patched file to a new file at `output`
+///
+/// BPS and xdelta patches check that they're applied to the file they were made for, and BPS
+/// patches check the file they make too. If `expected` is given (like a hash published with the
+/// patch), the output must match it. Returns the output's hashes. Nothing is left at `output` if
+/// the patch fails, or its output doesn't match.
+pub fn apply_patch(
+    base: &impl AsRef<Path>,
+    patch: &impl AsRef<Path>,
+    output: &impl AsRef<Path>,
+    expected: Option<FileHash>,
+) -> Result<Digests> {
+    let (base, patch, output) = (base.as_ref(), patch.as_ref(), output.as_ref());
+    let format = PatchFormat::of(&patch).ok_or_else(|| {
+        Error::new_original(format!(
+            "Failed to apply patch\n\"{}\" isn't an IPS, BPS, or xdelta patch",
+            patch.display()
+        ))
+        .with_category(ErrorCategory::InvalidInput)
+    })?;
+    if output.exists() {
+        return Err(Error::new_original(format!(
+            "Failed to apply patch\n\"{}\" already exists",
+            output.display()
+        ))
+        .with_category(ErrorCategory::InvalidInput));
+    }
+    let result = write_atomically(output, |partial| {
+        match format {
+            PatchFormat::Xdelta => xdelta::apply(base, patch, partial)?,
+            PatchFormat::IPS | PatchFormat::BPS => {
+                let base = fs::read(base).ndl("Failed to read ROM to patch")?;
+                let patch = fs::read(patch).ndl("Failed to read patch")?;
+                let patched = if format == PatchFormat::IPS {
+                    apply_ips(&base, &patch)?
+                } else {
+                    apply_bps(&base, &patch)?
+                };
+                fs::File::create_new(partial)
+                    .and_then(|mut file| {
+                        std::io::Write::write_all(&mut file, &patched)?;
+                        file.sync_all()
+                    })
+                    .ndl("Failed to write patched ROM")?;
+            }
+        }
+        let digests = MultiHasher::all().hash_file(&partial, false)?;
+        if let Some(expected) = expected {
+            let actual = digests.get(expected.algorithm()).unwrap();
+            if actual != expected {
+                return Err(mismatch(
+                    "Failed to apply patch\nThe patched ROM doesn't match the expected hash",
+                    expected,
+                    actual,
+                ));
+            }
+        }
+        Ok(digests)
+    });
+    if result.is_ok() {
+        debug!(
+            "Applied \"{}\" to \"{}\", writing \"{}\"",
+            patch.display(),
+            base.display(),
+            output.display()
+        );
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_ips_and_bps_patches() {
+        let base = b"Hello, world!".to_vec();
+        // "world" becomes "there", then 3 "!"s are written past the end
+        let mut ips = b"PATCH".to_vec();
+        ips.extend_from_slice(&[0, 0, 7, 0, 5]);
+        ips.extend_from_slice(b"there");
+        ips.extend_from_slice(&[0, 0, 13, 0, 0, 0, 3, b'!']);
+        ips.extend_from_slice(b"EOF");
+        assert_eq!(apply_ips(&base, &ips).unwrap(), b"Hello, there!!!!");
+        assert!(apply_ips(&base, &ips[..ips.len() - 3]).is_err());
+
+        // "Hello, " from the base, "Hi" from the patch, then "!" copied from the base's end
+        let target = b"Hello, Hi!".to_vec();
+        let mut bps = b"BPS1".to_vec();
+        for number in [base.len() as u8, target.len() as u8, 0] {
+            bps.push(number | 0x80);
+        }
+        bps.push((6 << 2) | 0x80);
+        bps.push((1 << 2 | 1) | 0x80);
+        bps.extend_from_slice(b"Hi");
+        bps.push(2 | 0x80);
+        bps.push((12 << 1) | 0x80);
+        bps.extend_from_slice(&crc32fast::hash(&base).to_le_bytes());
+        bps.extend_from_slice(&crc32fast::hash(&target).to_le_bytes());
+        bps.extend_from_slice(&crc32fast::hash(&bps).to_le_bytes());
+        assert_eq!(apply_bps(&base, &bps).unwrap(), target);
+        let err = apply_bps(b"Hello, World!", &bps).unwrap_err();
+        assert_eq!(err.category(), ErrorCategory::VerificationFailed);
+
+        // malformed patches with valid CRC32s are refused rather than panicking or allocating
+        // whatever they claim
+        let malformed_bps = |numbers: &[u8], commands: &[u8]| {
+            let mut bps = b"BPS1".to_vec();
+            bps.push(base.len() as u8 | 0x80);
+            bps.extend_from_slice(numbers);
+            bps.extend_from_slice(commands);
+            bps.extend_from_slice(&crc32fast::hash(&base).to_le_bytes());
+            bps.extend_from_slice(&crc32fast::hash(&target).to_le_bytes());
+            bps.extend_from_slice(&crc32fast::hash(&bps).to_le_bytes());
+            apply_bps(&base, &bps).unwrap_err().category()
+        };
+        // a number longer than 64 bits
+        let huge = [[0x7F; 10].as_slice(), &[0xFF]].concat();
+        assert_eq!(malformed_bps(&huge, &[]), ErrorCategory::InvalidData);
+        // a huge target size, then a huge metadata size
+        let near_max = [[0x7E; 8].as_slice(), &[0x80]].concat();
+        let numbers = [near_max.as_slice(), &[0x80]].concat();
+        assert_eq!(malformed_bps(&numbers, &[]), ErrorCategory::InvalidData);
+        let numbers = [[target.len() as u8 | 0x80].as_slice(), &near_max].concat();
+        assert_eq!(malformed_bps(&numbers, &[]), ErrorCategory::InvalidData);
+        // a huge target size, then a copy running past the base
+        let numbers = [near_max.as_slice(), &[0x80]].concat();
+        assert_eq!(
+            malformed_bps(&numbers, &[0x7C, 0x7F, 0x7F, 0x7F, 0x80]),
+            ErrorCategory::InvalidData
+        );
+        // a copy longer than the target
+        let numbers = [target.len() as u8 | 0x80, 0x80];
+        assert_eq!(
+            malformed_bps(&numbers, &[(20 << 2) | 0x80]),
+            ErrorCategory::InvalidData
+        );
+        // relative offsets moving before the start of the base, and far past its end
+        assert_eq!(
+            malformed_bps(&numbers, &[2 | 0x80, 3 | 0x80]),
+            ErrorCategory::InvalidData
+        );
+        let commands = [[2 | 0x80].as_slice(), &near_max].concat();
+        assert_eq!(
+            malformed_bps(&numbers, &commands),
+            ErrorCategory::InvalidData
+        );
+
+        let directory = tempfile::tempdir().unwrap();
+        let path = |name: &str| directory.path().join(name);
+        fs::write(path("Game.sfc"), &base).unwrap();
+        fs::write(path("Hack.ips"), &ips).unwrap();
+        let expected = FileHash::CRC32(crc32fast::hash(b"Hello, there!!!!"));
+        let digests = apply_patch(
+            &path("Game.sfc"),
+            &path("Hack.ips"),
+            &path("Hack.sfc"),
+            Some(expected),
+        )
+        .unwrap();
+        assert_eq!(digests.size, 16);
+        let wrong = FileHash::CRC32(0);
+        assert!(
+            apply_patch(
+                &path("Game.sfc"),
+                &path("Hack.ips"),
+                &path("Wrong.sfc"),
+                Some(wrong)
+            )
+            .is_err()
+        );
+        assert!(!path("Wrong.sfc").exists());
+    }
+}
@@ -0,0 +1,304 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read, Seek},
+    path::{Path, PathBuf},
+};
+
+use md5::{Digest, Md5};
+
+use crate::{
+    Error, ErrorCategory, GameConsole, NetworkTimeouts, Result, ResultUtils,
+    dump_manager::cuesheets::get_track_filenames,
+    utils::{
+        cartridge::{N64ByteOrder, Normalizer},
+        regex,
+        serial::{SectorReader, find_iso_file},
+    },
+};
+
+/// RetroAchievements only hashes this much of a cartridge ROM
+const MAX_HASHED_SIZE: u64 = 64 << 20;
+/// How much of a disc is hashed at most for a single file of it
+const MAX_DISC_FILE_SIZE: u32 = 64 << 20;
+const SECTOR_SIZE: u32 = 2048;
+
+/// The ID RetroAchievements gives a console, or `None` for consoles without achievements
+pub fn retroachievements_console_id(console: GameConsole) -> Option<u32> {
+    Some(match console {
+        GameConsole::Genesis => 1,
+        GameConsole::N64 => 2,
+        GameConsole::SNES => 3,
+        GameConsole::GB => 4,
+        GameConsole::GBA => 5,
+        GameConsole::GBC => 6,
+        GameConsole::NES => 7,
+        GameConsole::PSX => 12,
+        GameConsole::GameCube => 16,
+        GameConsole::PS2 => 21,
+        GameConsole::Dreamcast => 40,
+        GameConsole::PSP => 41,
+        GameConsole::PS3
+        | GameConsole::Wii
+        | GameConsole::WiiU
+        | GameConsole::Xbox
+        | GameConsole::Xbox360 => return None,
+    })
+}
+
+/// Reads a cartridge ROM the way RetroAchievements hashes it: headers emulators need are
+/// skipped, N64 ROMs are made big-endian, and only the first 64 MiB count
+fn cartridge_content(path: &Path, console: GameConsole) -> Result<Vec<u8>> {
+    let mut rom = Vec::new();
+    File::open(path)
+        .and_then(|file| file.take(MAX_HASHED_SIZE).read_to_end(&mut rom))
+        .ndl(format!("Failed to read \"{}\"", path.display()))?;
+    let skipped = match console {
+        GameConsole::NES if rom.starts_with(b"NES\x1A") || rom.starts_with(b"FDS\x1A") => 16,
+        // RetroAchievements goes by 8 KiB banks, unlike the 1 KiB No-Intro goes by
+        GameConsole::SNES if rom.len() % 0x2000 == 512 => 512,
+        GameConsole::N64 => return Ok(N64ByteOrder.normalize(&rom).unwrap_or(rom)),
+        _ => 0,
+    };
+    rom.drain(..skipped);
+    Ok(rom)
+}
+
+/// Hashes a file of a disc, starting with its path when `name` is given
+fn hash_disc_file<R: Read + Seek>(
+    reader: &mut SectorReader<R>,
+    md5: &mut Md5,
+    name: Option<&str>,
+    sector: u32,
+    size: u32,
+) -> Result<bool> {
+    if let Some(name) = name {
+        md5.update(name.as_bytes());
+    }
+    let size = size.min(MAX_DISC_FILE_SIZE);
+    let mut done = 0;
+    while done < size {
+        let chunk = (size - done).min(64 * SECTOR_SIZE);
+        let Some(data) = reader.read(sector + done / SECTOR_SIZE, chunk as usize)? else {
+            return Ok(false);
+        };
+        md5.update(&data);
+        done += chunk;
+    }
+    Ok(true)
+}
+
+/// Finds a PS1 or PS2 disc's executable from the boot line of its SYSTEM.CNF (BOOT for PS1 discs,
+/// BOOT2 for PS2 ones), as the path RetroAchievements hashes it with, like "SLUS_005.94"
+fn boot_executable(system_cnf: &str, key: &str, device: &str) -> Option<String> {
+    system_cnf.lines().find_map(|line| {
+        let value = line.trim_start().strip_prefix(key)?.trim_start();
+        let value = value.strip_prefix('=')?.trim_start();
+        let value = value.strip_prefix(device).unwrap_or(value);
+        let value = value.strip_prefix('\\').unwrap_or(value);
+        let end = value
+            .find(|c: char| c.is_whitespace() || c == ';')
+            .unwrap_or(value.len());
+        Some(value[..end].to_string())
+    })
+}
+
+/// Hashes a PS1 or PS2 disc: the path of its executable, then the executable
+fn hash_playstation<R: Read + Seek>(
+    reader: &mut SectorReader<R>,
+    console: GameConsole,
+) -> Result<Option<String>> {
+    let (key, device) = match console {
+        GameConsole::PSX => ("BOOT", "cdrom:"),
+        _ => ("BOOT2", "cdrom0:"),
+    };
+    let executable = match find_iso_file(reader, "SYSTEM.CNF")? {
+        Some((sector, size)) => {
+            let Some(content) = reader.read(sector, size.min(SECTOR_SIZE) as usize)? else {
+                return Ok(None);
+            };
+            boot_executable(&String::from_utf8_lossy(&content), key, device)
+        }
+        // some early PS1 discs boot without a SYSTEM.CNF
+        None if console == GameConsole::PSX => Some(String::from("PSX.EXE")),
+        None => None,
+    };
+    let Some(executable) = executable else {
+        return Ok(None);
+    };
+    let Some((sector, mut size)) = find_iso_file(reader, &executable)? else {
+        return Ok(None);
+    };
+    if console == GameConsole::PSX {
+        // PS-X EXE headers give the size of the code after them, which is hashed with the header
+        let Some(header) = reader.read(sector, 32)? else {
+            return Ok(None);
+        };
+        if header.starts_with(b"PS-X EXE") {
+            size =
+                u32::from_le_bytes(header[28..32].try_into().unwrap()).saturating_add(SECTOR_SIZE);
+        }
+    }
+    let mut md5 = Md5::new();
+    if !hash_disc_file(reader, &mut md5, Some(&executable), sector, size)? {
+        return Ok(None);
+    }
+    Ok(Some(hex::encode(md5.finalize())))
+}
+
+/// Hashes a PSP disc: its PARAM.SFO, then its EBOOT.BIN
+fn hash_psp<R: Read + Seek>(reader: &mut SectorReader<R>) -> Result<Option<String>> {
+    let mut md5 = Md5::new();
+    for path in ["PSP_GAME\\PARAM.SFO", "PSP_GAME\\SYSDIR\\EBOOT.BIN"] {
+        let Some((sector, size)) = find_iso_file(reader, path)? else {
+            return Ok(None);
+        };
+        if !hash_disc_file(reader, &mut md5, None, sector, size)? {
+            return Ok(None);
+        }
+    }
+    Ok(Some(hex::encode(md5.finalize())))
+}
+
+/// The track of a disc image holding its filesystem: a cuesheet's first file, or the image itself
+fn data_track(path: &Path) -> Result<PathBuf> {
+    let is_cuesheet = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("cue"));
+    if !is_cuesheet {
+        return Ok(path.to_path_buf());
+    }
+    let content = std::fs::read_to_string(path)
+        .ndl(format!("Failed to read cuesheet \"{}\"", path.display()))?;
+    let Some(track) = get_track_filenames(&content).into_iter().next() else {
+        return Err(
+            Error::new_original(format!("Cuesheet \"{}\" has no tracks", path.display()))
+                .with_category(ErrorCategory::InvalidData),
+        );
+    };
+    Ok(path.with_file_name(track))
+}
+
+/// Computes the hash RetroAchievements recognizes a dump by, following its rules for the console
+///
+/// Cartridge ROMs are hashed whole, skipping headers like iNES's and copiers' ones. PS1 and PS2
+/// discs are hashed by their executable, and PSP discs by their PARAM.SFO and EBOOT.BIN, read from
+/// ISOs, raw tracks, or cuesheets (whose first track is read). Returns `None` for dumps which
+/// can't be hashed like RetroAchievements does: consoles without achievements, discs of other
+/// consoles, and compressed images like CHDs and CSOs, which have to be extracted first.
+pub fn retroachievements_hash(path: &Path, console: GameConsole) -> Result<Option<String>> {
+    match console {
+        GameConsole::Genesis
+        | GameConsole::N64
+        | GameConsole::SNES
+        | GameConsole::GB
+        | GameConsole::GBA
+        | GameConsole::GBC
+        | GameConsole::NES => {
+            let content = cartridge_content(path, console)?;
+            Ok(Some(hex::encode(Md5::digest(&content))))
+        }
+        GameConsole::PSX | GameConsole::PS2 | GameConsole::PSP => {
+            let track = data_track(path)?;
+            let file = File::open(&track).ndl(format!("Failed to open \"{}\"", track.display()))?;
+            let mut image = BufReader::new(file);
+            let mut reader = SectorReader::new(&mut image)?;
+            match console {
+                GameConsole::PSP => hash_psp(&mut reader),
+                _ => hash_playstation(&mut reader, console),
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Downloads the hashes RetroAchievements recognizes a console's games by, mapped to the IDs of
+/// the games they're for
+///
+/// Fails for consoles without achievements (see [retroachievements_console_id]).
+pub fn fetch_retroachievements_hashes(
+    console: GameConsole,
+    timeouts: &NetworkTimeouts,
+) -> Result<HashMap<String, u32>> {
+    let Some(id) = retroachievements_console_id(console) else {
+        return Err(Error::new_original(format!(
+            "{} has no RetroAchievements",
+            console.formal_name()
+        ))
+        .with_category(ErrorCategory::InvalidInput));
+    };
+    let url = format!("https://retroachievements.org/dorequest.php?r=hashlibrary&c={id}");
+    let content = timeouts
+        .start_deadline()
+        .limit(timeouts.agent().get(&url))?
+        .header(
+            "User-Agent",
+            concat!("ndumplib/", env!("CARGO_PKG_VERSION")),
+        )
+        .call()
+        .ndl("Failed to connect to RetroAchievements")?
+        .body_mut()
+        .read_to_string()
+        .ndl("Failed to download RetroAchievements hashes")?;
+    if !regex!(r#""Success"\s*:\s*true"#)
+        .is_match(&content)
+        .unwrap()
+    {
+        return Err(Error::new_original(format!(
+            "RetroAchievements didn't send the hashes of {} games",
+            console.formal_name()
+        ))
+        .with_category(ErrorCategory::Network));
+    }
+    Ok(regex!(r#""([0-9a-fA-F]{32})"\s*:\s*"?(\d+)"#)
+        .captures_iter(&content)
+        .filter_map(|captures| {
+            let captures = captures.ok()?;
+            Some((captures[1].to_ascii_lowercase(), captures[2].parse().ok()?))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::serial::tests::playstation_image;
+
+    #[test]
+    fn hashes_like_retroachievements() {
+        let directory = tempfile::tempdir().unwrap();
+        let nes = directory.path().join("Game.nes");
+        let mut rom = b"NES\x1A".to_vec();
+        rom.resize(16, 0);
+        rom.extend_from_slice(b"ROM");
+        std::fs::write(&nes, &rom).unwrap();
+        assert_eq!(
+            retroachievements_hash(&nes, GameConsole::NES).unwrap(),
+            Some(hex::encode(Md5::digest(b"ROM")))
+        );
+
+        // the executable (SYSTEM.CNF itself here) is hashed after its path
+        let system_cnf = "BOOT = cdrom:\\SYSTEM.CNF;1\r\n";
+        let bin = directory.path().join("Game (Track 1).bin");
+        std::fs::write(&bin, playstation_image(system_cnf)).unwrap();
+        let cue = directory.path().join("Game.cue");
+        std::fs::write(
+            &cue,
+            "FILE \"Game (Track 1).bin\" BINARY\n  TRACK 01 MODE2/2352\n",
+        )
+        .unwrap();
+        let expected = Md5::new()
+            .chain_update("SYSTEM.CNF")
+            .chain_update(system_cnf)
+            .finalize();
+        assert_eq!(
+            retroachievements_hash(&cue, GameConsole::PSX).unwrap(),
+            Some(hex::encode(expected))
+        );
+        assert_eq!(
+            retroachievements_hash(&bin, GameConsole::PSP).unwrap(),
+            None
+        );
+        assert_eq!(retroachievements_console_id(GameConsole::Xbox360), None);
+    }
+}
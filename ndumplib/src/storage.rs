@@ -0,0 +1,344 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    Error, ErrorCategory, Result, ResultUtils,
+    transfer::{is_temporary_file, temporary_path},
+};
+
+mod sftp;
+
+pub use self::sftp::SftpStorage;
+
+/// A file or folder in a [Storage]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StorageEntry {
+    pub path: PathBuf,
+    pub is_folder: bool,
+    /// The file's size in bytes (0 for folders)
+    pub size: u64,
+}
+
+/// Where a library's files are kept, like a local folder or a folder on a NAS or seedbox reached
+/// over SFTP
+///
+/// Paths are absolute paths within the storage. Files are only ever written whole: uploads go to a
+/// temporary file next to their target, which is renamed once it's complete.
+pub trait Storage {
+    /// The storage as it's written in messages, like "sftp://user@nas:22"
+    fn name(&self) -> String;
+
+    /// Lists a folder's files and subfolders (but not what's in the subfolders)
+    ///
+    fn list(&self, folder: &Path) -> Result<Vec<StorageEntry>>;
+
+    /// Looks up a file or folder, returning `None` if there's nothing at `path`
+    ///
+    fn metadata(&self, path: &Path) -> Result<Option<StorageEntry>>;
+
+    /// Opens a file to read it from the start, like to hash it
+    ///
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + '_>>;
+
+    /// Copies a local file to `to`, creating its folder
+    ///
+    /// `progress` is given the bytes copied so far and the file's size. What's already at `to` is
+    /// replaced.
+    fn upload(&self, from: &Path, to: &Path, progress: &mut dyn FnMut(u64, u64)) -> Result<()>;
+
+    /// Moves a file within the storage, creating the folder it goes to
+    ///
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+    fn remove_file(&self, path: &Path) -> Result<()>;
+}
+
+/// Removes the files under `root` left half-written by runs which never finished, like when
+/// ndumpmgr crashed or the power went out (see [crate::is_temporary_file])
+///
+/// Returns the files removed. Files being written are temporary files too, so this must only run
+/// while nothing else writes under `root`.
+pub fn remove_temporary_files(storage: &dyn Storage, root: &Path) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    if storage.metadata(root)?.is_none() {
+        return Ok(removed);
+    }
+    let mut folders = vec![root.to_path_buf()];
+    while let Some(folder) = folders.pop() {
+        for entry in storage.list(&folder)? {
+            if entry.is_folder {
+                folders.push(entry.path);
+            } else if is_temporary_file(&entry.path) {
+                storage.remove_file(&entry.path)?;
+                removed.push(entry.path);
+            }
+        }
+    }
+    removed.sort();
+    Ok(removed)
+}
+
+/// Copies a local file to a writer in chunks, reporting its progress
+fn copy_with_progress(
+    from: &Path,
+    to: &mut dyn io::Write,
+    progress: &mut dyn FnMut(u64, u64),
+) -> io::Result<u64> {
+    let mut source = File::open(from)?;
+    let size = source.metadata()?.len();
+    let mut buffer = vec![0u8; 1 << 20];
+    let mut copied = 0;
+    loop {
+        let length = source.read(&mut buffer)?;
+        if length == 0 {
+            break;
+        }
+        to.write_all(&buffer[..length])?;
+        copied += length as u64;
+        progress(copied, size);
+    }
+    to.flush()?;
+    Ok(copied)
+}
+
+/// Files on this computer, including network shares mounted on it
+pub struct LocalStorage;
+
+impl Storage for LocalStorage {
+    fn name(&self) -> String {
+        String::from("file://")
+    }
+
+    fn list(&self, folder: &Path) -> Result<Vec<StorageEntry>> {
+        let failed = || format!("Failed to read folder \"{}\"", folder.display());
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(folder).ndl(failed())? {
+            let entry = entry.ndl(failed())?;
+            let metadata = entry.metadata().ndl(failed())?;
+            entries.push(StorageEntry {
+                path: entry.path(),
+                is_folder: metadata.is_dir(),
+                size: if metadata.is_dir() { 0 } else { metadata.len() },
+            });
+        }
+        Ok(entries)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Option<StorageEntry>> {
+        match fs::metadata(path) {
+            Ok(metadata) => Ok(Some(StorageEntry {
+                path: path.to_path_buf(),
+                is_folder: metadata.is_dir(),
+                size: if metadata.is_dir() { 0 } else { metadata.len() },
+            })),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).ndl(format!("Failed to read \"{}\"", path.display())),
+        }
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + '_>> {
+        let file = File::open(path).ndl(format!("Failed to open \"{}\"", path.display()))?;
+        Ok(Box::new(io::BufReader::new(file)))
+    }
+
+    fn upload(&self, from: &Path, to: &Path, progress: &mut dyn FnMut(u64, u64)) -> Result<()> {
+        let partial = temporary_path(to);
+        let result = fs::create_dir_all(to.parent().unwrap())
+            .and_then(|_| File::create(&partial))
+            .and_then(|mut file| {
+                copy_with_progress(from, &mut file, progress)?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&partial, to));
+        if result.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+        result.ndl(format!("Failed to upload \"{}\"", from.display()))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        fs::create_dir_all(to.parent().unwrap())
+            .and_then(|_| fs::rename(from, to))
+            .ndl(format!(
+                "Failed to move \"{}\" to \"{}\"",
+                from.display(),
+                to.display()
+            ))
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        fs::remove_file(path).ndl(format!("Failed to remove \"{}\"", path.display()))
+    }
+}
+
+/// Where a library is kept, parsed from a URL like "sftp://user@nas:22/srv/games" or
+/// "file:///mnt/games"
+///
+/// SFTP URLs without a user are for the current one, and without a port for port 22.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StorageLocation {
+    Local {
+        root: PathBuf,
+    },
+    Sftp {
+        user: String,
+        host: String,
+        port: u16,
+        root: PathBuf,
+    },
+}
+
+impl FromStr for StorageLocation {
+    type Err = Error;
+
+    fn from_str(url: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            Error::new_original(format!("Invalid storage URL \"{url}\": {reason}"))
+                .with_category(ErrorCategory::InvalidInput)
+        };
+        let Some((scheme, rest)) = url.split_once("://") else {
+            return Err(invalid(
+                "expected one like \"sftp://user@host/path\" or \"file:///path\"",
+            ));
+        };
+        match scheme {
+            "file" => {
+                if !rest.starts_with('/') {
+                    return Err(invalid("the path must be absolute"));
+                }
+                Ok(StorageLocation::Local {
+                    root: PathBuf::from(rest),
+                })
+            }
+            "sftp" => {
+                let (authority, root) = match rest.find('/') {
+                    Some(index) => rest.split_at(index),
+                    None => return Err(invalid("missing the folder's path")),
+                };
+                let (user, address) = match authority.rsplit_once('@') {
+                    Some((user, address)) => (user.to_string(), address),
+                    None => (
+                        std::env::var("USER")
+                            .or_else(|_| std::env::var("USERNAME"))
+                            .map_err(|_| invalid("missing the user"))?,
+                        authority,
+                    ),
+                };
+                let (host, port) = match address.rsplit_once(':') {
+                    Some((host, port)) => (
+                        host,
+                        port.parse()
+                            .map_err(|_| invalid(&format!("invalid port \"{port}\"")))?,
+                    ),
+                    None => (address, 22),
+                };
+                if host.is_empty() || user.is_empty() {
+                    return Err(invalid("missing the host"));
+                }
+                Ok(StorageLocation::Sftp {
+                    user,
+                    host: host.to_string(),
+                    port,
+                    root: PathBuf::from(root),
+                })
+            }
+            // SMB and NFS shares are mounted by the OS, which does it better than a client here could
+            "smb" | "cifs" | "nfs" => Err(invalid(&format!(
+                "\"{scheme}\" shares aren't supported, mount the share and use a \"file\" URL \
+                for its folder instead (supported schemes: \"sftp\", \"file\")"
+            ))),
+            _ => Err(invalid(&format!(
+                "unsupported scheme \"{scheme}\" (supported schemes: \"sftp\", \"file\")"
+            ))),
+        }
+    }
+}
+
+impl StorageLocation {
+    /// The library's folder within the storage
+    ///
+    pub fn root(&self) -> &Path {
+        match self {
+            StorageLocation::Local { root } | StorageLocation::Sftp { root, .. } => root,
+        }
+    }
+
+    /// Connects to the storage
+    ///
+    /// SFTP servers must be in the user's known_hosts file already, and are logged in to with the
+    /// SSH agent or, failing that, the given private key.
+    pub fn connect(&self, identity_file: Option<&Path>) -> Result<Box<dyn Storage>> {
+        match self {
+            StorageLocation::Local { .. } => Ok(Box::new(LocalStorage)),
+            StorageLocation::Sftp {
+                user, host, port, ..
+            } => Ok(Box::new(SftpStorage::connect(
+                user,
+                host,
+                *port,
+                identity_file,
+            )?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_storage_urls() {
+        assert_eq!(
+            "sftp://me@nas:2222/srv/games"
+                .parse::<StorageLocation>()
+                .unwrap(),
+            StorageLocation::Sftp {
+                user: String::from("me"),
+                host: String::from("nas"),
+                port: 2222,
+                root: PathBuf::from("/srv/games"),
+            }
+        );
+        assert_eq!(
+            "file:///mnt/games"
+                .parse::<StorageLocation>()
+                .unwrap()
+                .root(),
+            Path::new("/mnt/games")
+        );
+        assert!("sftp://me@nas".parse::<StorageLocation>().is_err());
+        let smb = "smb://nas/games".parse::<StorageLocation>().unwrap_err();
+        assert!(smb.to_string().contains("mount the share"), "{smb}");
+        let unknown = "s3://bucket/games".parse::<StorageLocation>().unwrap_err();
+        assert!(
+            unknown.to_string().contains("\"sftp\", \"file\""),
+            "{unknown}"
+        );
+
+        let directory = tempfile::tempdir().unwrap();
+        let source = directory.path().join("source.bin");
+        fs::write(&source, b"dump").unwrap();
+        let target = directory.path().join("library/psx/Game.bin");
+        LocalStorage
+            .upload(&source, &target, &mut |_, _| {})
+            .unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"dump");
+        assert!(!temporary_path(&target).exists());
+        let entries = LocalStorage.list(target.parent().unwrap()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].size, 4);
+
+        // an upload which never finished is cleaned up, without touching the dumps around it
+        let leftover = temporary_path(&directory.path().join("library/ps2/Other.iso"));
+        fs::create_dir_all(leftover.parent().unwrap()).unwrap();
+        fs::write(&leftover, b"du").unwrap();
+        let removed =
+            remove_temporary_files(&LocalStorage, &directory.path().join("library")).unwrap();
+        assert_eq!(removed, vec![leftover.clone()]);
+        assert!(!leftover.exists() && target.exists());
+    }
+}
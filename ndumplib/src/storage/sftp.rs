@@ -0,0 +1,205 @@
+use std::{io::Read, net::TcpStream, path::Path};
+
+use log::debug;
+use ssh2::{CheckResult, ErrorCode, KnownHostFileKind, Session, Sftp};
+
+use super::{Storage, StorageEntry, copy_with_progress, temporary_path};
+use crate::{Error, ErrorCategory, Result, ResultUtils};
+
+/// The SFTP status code of a missing file
+const NO_SUCH_FILE: i32 = 2;
+
+/// A folder on another computer reached over SFTP, like a NAS or a seedbox
+pub struct SftpStorage {
+    name: String,
+    // the session has to outlive the SFTP channel
+    _session: Session,
+    sftp: Sftp,
+}
+
+/// Checks the server's host key against the user's known_hosts file, so files are never sent to
+/// an impostor
+fn check_host_key(session: &Session, host: &str, port: u16) -> Result<()> {
+    let mut known_hosts = session.known_hosts().ndl("Failed to check the host key")?;
+    #[allow(deprecated)] // home_dir is deprecated
+    let file = std::env::home_dir()
+        .map(|home| home.join(".ssh").join("known_hosts"))
+        .filter(|file| file.is_file());
+    if let Some(file) = &file {
+        known_hosts
+            .read_file(file, KnownHostFileKind::OpenSSH)
+            .ndl(format!("Failed to read \"{}\"", file.display()))?;
+    }
+    let (key, _) = session
+        .host_key()
+        .ndl(format!("{host} didn't send its host key"))?;
+    let problem = match known_hosts.check_port(host, port, key) {
+        CheckResult::Match => return Ok(()),
+        CheckResult::NotFound => {
+            "isn't a known host yet. Connect to it once with ssh to check and add its key"
+        }
+        CheckResult::Mismatch => {
+            "sent a different host key than the known one, so it may not be the server it claims to be"
+        }
+        CheckResult::Failure => "sent a host key which couldn't be checked",
+    };
+    Err(Error::new_original(format!("{host} {problem}")).with_category(ErrorCategory::Network))
+}
+
+impl SftpStorage {
+    /// Connects to an SFTP server and logs in, with the SSH agent or, failing that, a private key
+    ///
+    pub fn connect(
+        user: &str,
+        host: &str,
+        port: u16,
+        identity_file: Option<&Path>,
+    ) -> Result<SftpStorage> {
+        let failed = || format!("Failed to connect to {host}:{port}");
+        let stream = TcpStream::connect((host, port)).ndl(failed())?;
+        let mut session = Session::new().ndl(failed())?;
+        session.set_tcp_stream(stream);
+        session.handshake().ndl(failed())?;
+        check_host_key(&session, host, port)?;
+        if let Err(err) = session.userauth_agent(user) {
+            debug!("Couldn't log in to {host} with the SSH agent: {err}");
+        }
+        if !session.authenticated()
+            && let Some(identity_file) = identity_file
+        {
+            session
+                .userauth_pubkey_file(user, None, identity_file, None)
+                .ndl(format!(
+                    "Failed to log in to {host} with \"{}\"",
+                    identity_file.display()
+                ))?;
+        }
+        if !session.authenticated() {
+            return Err(Error::new_original(format!(
+                "Failed to log in to {host} as {user}: add a key to the SSH agent, or set the storage's identity file"
+            ))
+            .with_category(ErrorCategory::Network));
+        }
+        let sftp = session.sftp().ndl(format!("{host} doesn't serve SFTP"))?;
+        debug!("Connected to {host}:{port} over SFTP");
+        Ok(SftpStorage {
+            name: format!("sftp://{user}@{host}:{port}"),
+            _session: session,
+            sftp,
+        })
+    }
+
+    fn create_dir_all(&self, folder: &Path) -> Result<()> {
+        let mut missing = Vec::new();
+        for ancestor in folder.ancestors() {
+            if ancestor.as_os_str().is_empty() || self.metadata(ancestor)?.is_some() {
+                break;
+            }
+            missing.push(ancestor);
+        }
+        for folder in missing.into_iter().rev() {
+            self.sftp
+                .mkdir(folder, 0o755)
+                .ndl(format!("Failed to create \"{}\"", folder.display()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Storage for SftpStorage {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn list(&self, folder: &Path) -> Result<Vec<StorageEntry>> {
+        let entries = self
+            .sftp
+            .readdir(folder)
+            .ndl(format!("Failed to read folder \"{}\"", folder.display()))?;
+        Ok(entries
+            .into_iter()
+            .filter(|(path, _)| {
+                path.file_name()
+                    .is_some_and(|name| name != "." && name != "..")
+            })
+            .map(|(path, stat)| StorageEntry {
+                is_folder: stat.is_dir(),
+                size: if stat.is_dir() {
+                    0
+                } else {
+                    stat.size.unwrap_or(0)
+                },
+                path,
+            })
+            .collect())
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Option<StorageEntry>> {
+        match self.sftp.stat(path) {
+            Ok(stat) => Ok(Some(StorageEntry {
+                path: path.to_path_buf(),
+                is_folder: stat.is_dir(),
+                size: if stat.is_dir() {
+                    0
+                } else {
+                    stat.size.unwrap_or(0)
+                },
+            })),
+            Err(err) if err.code() == ErrorCode::SFTP(NO_SUCH_FILE) => Ok(None),
+            Err(err) => Err(err).ndl(format!("Failed to read \"{}\"", path.display())),
+        }
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + '_>> {
+        let file = self
+            .sftp
+            .open(path)
+            .ndl(format!("Failed to open \"{}\"", path.display()))?;
+        Ok(Box::new(std::io::BufReader::with_capacity(1 << 16, file)))
+    }
+
+    fn upload(&self, from: &Path, to: &Path, progress: &mut dyn FnMut(u64, u64)) -> Result<()> {
+        let failed = || format!("Failed to upload \"{}\"", from.display());
+        self.create_dir_all(to.parent().unwrap())?;
+        let partial = temporary_path(to);
+        let result = (|| -> Result<()> {
+            let mut file = self.sftp.create(&partial).ndl(failed())?;
+            let copied = copy_with_progress(from, &mut file, progress).ndl(failed())?;
+            file.fsync().ndl(failed())?;
+            drop(file);
+            // a dropped connection can cut a file short without an error
+            let uploaded = self.metadata(&partial)?.map_or(0, |entry| entry.size);
+            if uploaded != copied {
+                return Err(Error::new_original(format!(
+                    "{}\nOnly {uploaded} of {copied} bytes arrived",
+                    failed()
+                )));
+            }
+            // servers of SFTP version 3 (like OpenSSH's) don't rename over files, so what's there
+            // is removed first
+            if self.metadata(to)?.is_some() {
+                self.remove_file(to)?;
+            }
+            self.sftp.rename(&partial, to, None).ndl(failed())
+        })();
+        if result.is_err() {
+            let _ = self.sftp.unlink(&partial);
+        }
+        result
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.create_dir_all(to.parent().unwrap())?;
+        self.sftp.rename(from, to, None).ndl(format!(
+            "Failed to move \"{}\" to \"{}\"",
+            from.display(),
+            to.display()
+        ))
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        self.sftp
+            .unlink(path)
+            .ndl(format!("Failed to remove \"{}\"", path.display()))
+    }
+}
@@ -0,0 +1,338 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+use sha1::{Digest, Sha1};
+
+use crate::{
+    Error, ErrorCategory, ErrorKind, Result, ResultUtils,
+    dump_manager::timings::{Stage, StageTimer},
+    hashing,
+    utils::work,
+};
+
+/// How much of a file is copied between progress reports
+const COPY_CHUNK_SIZE: usize = 1 << 20;
+
+/// The suffix of files being written into the library, which only get their real names once
+/// they're complete
+const TEMPORARY_SUFFIX: &str = ".ndumptmp";
+
+/// The hidden file a file bound for `path` is written to until it's complete
+pub fn temporary_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(TEMPORARY_SUFFIX);
+    path.with_file_name(name)
+}
+
+/// Whether a file is being written, or was left half-written by a run which never finished, so it
+/// isn't a dump
+pub fn is_temporary_file(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.to_string_lossy())
+        .is_some_and(|name| name.starts_with('.') && name.ends_with(TEMPORARY_SUFFIX))
+}
+
+/// Has `write` write a file to a temporary path next to `target` (see [temporary_path]), then
+/// renames it to `target` once it's synced to disk
+///
+/// A file `write` fails to finish is removed, so whatever has `target`'s name is always whole.
+pub(crate) fn write_atomically<T>(
+    target: &Path,
+    write: impl FnOnce(&Path) -> Result<T>,
+) -> Result<T> {
+    let partial = temporary_path(target);
+    // one left over from a run which never finished would stop it being created
+    let _ = fs::remove_file(&partial);
+    let result = write(&partial).and_then(|value| {
+        File::open(&partial)
+            .and_then(|file| file.sync_all())
+            .and_then(|_| fs::rename(&partial, target))
+            .ndl(format!("Failed to write \"{}\"", target.display()))?;
+        Ok(value)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result
+}
+
+/// Whether a transferred dump's original is kept
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferMode {
+    /// Renames the file, or copies it and deletes the original if it's on another filesystem
+    Move,
+    /// Copies the file, keeping the original
+    Copy,
+}
+
+/// Copies `from` to a new file at `to`, hashing it as it goes, then reads the copy back to check
+/// it matches
+///
+/// `progress` is given the bytes copied so far and the file's size. Nothing is copied without room
+/// for the whole file, and the copy only gets `to`'s name once it matches.
+fn copy_verified(from: &Path, to: &Path, progress: &mut dyn FnMut(u64, u64)) -> Result<()> {
+    let mut source = File::open(from).ndl("Failed to copy file")?;
+    let size = source.metadata().ndl("Failed to copy file")?.len();
+    // fail before the copy starts, rather than leave part of it behind when the disk fills up
+    work::ensure_free_space(to.parent().unwrap(), size).map_err(|err| {
+        Error::new_original(format!(
+            "Failed to copy \"{}\"\n{}",
+            from.display(),
+            err.message()
+        ))
+        .with_category(ErrorCategory::InsufficientSpace)
+    })?;
+    write_atomically(to, |partial| {
+        let mut destination = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(partial)
+            .ndl("Failed to copy file")?;
+        let mut hasher = Sha1::new();
+        let mut buffer = vec![0u8; COPY_CHUNK_SIZE];
+        let mut copied = 0;
+        loop {
+            let length = source.read(&mut buffer).ndl("Failed to copy file")?;
+            if length == 0 {
+                break;
+            }
+            hasher.update(&buffer[..length]);
+            destination
+                .write_all(&buffer[..length])
+                .ndl("Failed to copy file")?;
+            copied += length as u64;
+            progress(copied, size);
+        }
+        destination.sync_all().ndl("Failed to copy file")?;
+        let expected: [u8; 20] = hasher.finalize().into();
+        let _timer = StageTimer::start(Stage::Hashing);
+        let mut hasher = Sha1::new();
+        hashing::hash_file(partial, |chunk| hasher.update(chunk)).ndl("Failed to verify copy")?;
+        let actual: [u8; 20] = hasher.finalize().into();
+        if actual != expected {
+            return Err(Error::new(
+                format!(
+                    "Failed to copy file\n\"{}\" doesn't match the original",
+                    to.display()
+                ),
+                ErrorKind::VerificationFailed {
+                    algorithm: "SHA-1",
+                    expected: hex::encode(expected),
+                    actual: hex::encode(actual),
+                },
+            ));
+        }
+        Ok(())
+    })
+}
+
+/// Moves or copies a file to `to`, which must not exist yet
+///
+/// Moves across filesystems are done by copying, checking the copy's hash, and only then deleting
+/// the original. `progress` is only called while copying.
+pub fn transfer_file(
+    from: &Path,
+    to: &Path,
+    mode: TransferMode,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<()> {
+    if to.exists() {
+        return Err(Error::new_original(format!(
+            "Failed to transfer file\n\"{}\" already exists",
+            to.display()
+        ))
+        .with_category(ErrorCategory::IO));
+    }
+    match mode {
+        TransferMode::Copy => copy_verified(from, to, progress),
+        TransferMode::Move => match fs::rename(from, to) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+                debug!(
+                    "\"{}\" is on another filesystem, so it's copied",
+                    from.display()
+                );
+                copy_verified(from, to, progress)?;
+                fs::remove_file(from).ndl("Failed to remove original after copying")
+            }
+            Err(err) => Err(err).ndl("Failed to move file"),
+        },
+    }
+}
+
+/// Moves or copies a file to `to`, replacing what's there only once the new file is complete
+///
+/// The file goes to a temporary path next to `to` first (see [transfer_file]), and is then renamed
+/// over it. A moved file is moved back if that fails.
+pub fn transfer_file_over(
+    from: &Path,
+    to: &Path,
+    mode: TransferMode,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<()> {
+    let partial = temporary_path(to);
+    // one left over from a run which never finished would stop it being created
+    let _ = fs::remove_file(&partial);
+    transfer_file(from, &partial, mode, progress)?;
+    let Err(err) = fs::rename(&partial, to) else {
+        return Ok(());
+    };
+    let message = format!("Failed to replace \"{}\"", to.display());
+    if mode == TransferMode::Copy {
+        let _ = fs::remove_file(&partial);
+    } else if fs::rename(&partial, from).is_err() {
+        return Err(err).ndl(format!(
+            "{message}\n\"{}\" was left at \"{}\"",
+            from.display(),
+            partial.display()
+        ));
+    }
+    Err(err).ndl(message)
+}
+
+/// How a file is duplicated by [duplicate_file]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkMethod {
+    /// Another name for the same file, which only works within a filesystem
+    HardLink,
+    /// A new file sharing the original's data on disk until either is changed, which needs a
+    /// filesystem supporting it (like btrfs or XFS)
+    Reflink,
+    /// A full copy
+    Copy,
+}
+
+/// Clones `from`'s data into a new file at `to`, with the FICLONE ioctl
+#[cfg(target_os = "linux")]
+fn reflink(from: &Path, to: &Path) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // _IOW(0x94, 9, int), from linux/fs.h
+    const FICLONE: u32 = 0x40049409;
+    let source = File::open(from)?;
+    let destination = OpenOptions::new().write(true).create_new(true).open(to)?;
+    if unsafe { libc::ioctl(destination.as_raw_fd(), FICLONE as _, source.as_raw_fd()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_from: &Path, _to: &Path) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// Duplicates a file at `to`, which must not exist yet, taking up no more space if the
+/// filesystem allows it
+///
+/// Hard links fall back to reflinks, and reflinks to copies (checked like [transfer_file]'s), so
+/// the file is always duplicated one way or another. Returns how it was. `progress` is only called
+/// while copying.
+pub fn duplicate_file(
+    from: &Path,
+    to: &Path,
+    method: LinkMethod,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<LinkMethod> {
+    if to.exists() {
+        return Err(Error::new_original(format!(
+            "Failed to duplicate file\n\"{}\" already exists",
+            to.display()
+        ))
+        .with_category(ErrorCategory::IO));
+    }
+    if method == LinkMethod::HardLink {
+        match fs::hard_link(from, to) {
+            Ok(()) => return Ok(LinkMethod::HardLink),
+            Err(err) => debug!("Couldn't hard-link \"{}\" ({err})", from.display()),
+        }
+    }
+    if method != LinkMethod::Copy {
+        match write_atomically(to, |partial| {
+            reflink(from, partial).ndl("Failed to reflink file")
+        }) {
+            Ok(()) => return Ok(LinkMethod::Reflink),
+            Err(err) => debug!("Couldn't reflink \"{}\" ({err})", from.display()),
+        }
+    }
+    copy_verified(from, to, progress)?;
+    Ok(LinkMethod::Copy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_and_moves_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let original = directory.path().join("original.bin");
+        fs::write(&original, vec![7u8; COPY_CHUNK_SIZE + 1]).unwrap();
+        let copy = directory.path().join("copy.bin");
+        let mut reports = Vec::new();
+        transfer_file(&original, &copy, TransferMode::Copy, &mut |copied, size| {
+            reports.push((copied, size))
+        })
+        .unwrap();
+        assert!(original.exists());
+        assert_eq!(fs::read(&copy).unwrap(), fs::read(&original).unwrap());
+        let size = COPY_CHUNK_SIZE as u64 + 1;
+        assert_eq!(reports, vec![(size - 1, size), (size, size)]);
+        assert!(!temporary_path(&copy).exists());
+        assert!(is_temporary_file(&temporary_path(&copy)) && !is_temporary_file(&copy));
+        // existing files are never overwritten
+        assert!(transfer_file(&original, &copy, TransferMode::Move, &mut |_, _| {}).is_err());
+        let moved = directory.path().join("moved.bin");
+        transfer_file(&original, &moved, TransferMode::Move, &mut |_, _| {}).unwrap();
+        assert!(!original.exists() && moved.exists());
+    }
+
+    #[test]
+    fn replaces_files_once_transferred() {
+        let directory = tempfile::tempdir().unwrap();
+        let original = directory.path().join("original.bin");
+        fs::write(&original, b"new dump").unwrap();
+        let target = directory.path().join("target.bin");
+        fs::write(&target, b"old dump").unwrap();
+        transfer_file_over(&original, &target, TransferMode::Copy, &mut |_, _| {}).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"new dump");
+        assert!(original.exists() && !temporary_path(&target).exists());
+        // what's there is kept when the new file can't be transferred
+        let missing = directory.path().join("missing.bin");
+        assert!(transfer_file_over(&missing, &target, TransferMode::Move, &mut |_, _| {}).is_err());
+        assert_eq!(fs::read(&target).unwrap(), b"new dump");
+        transfer_file_over(&original, &target, TransferMode::Move, &mut |_, _| {}).unwrap();
+        assert!(!original.exists() && target.exists());
+    }
+
+    #[test]
+    fn duplicates_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let original = directory.path().join("original.bin");
+        fs::write(&original, b"dump").unwrap();
+        let linked = directory.path().join("linked.bin");
+        // a temporary folder is all on one filesystem
+        assert_eq!(
+            duplicate_file(&original, &linked, LinkMethod::HardLink, &mut |_, _| {}).unwrap(),
+            LinkMethod::HardLink
+        );
+        assert_eq!(fs::read(&linked).unwrap(), b"dump");
+        let copy = directory.path().join("copy.bin");
+        assert_eq!(
+            duplicate_file(&original, &copy, LinkMethod::Copy, &mut |_, _| {}).unwrap(),
+            LinkMethod::Copy
+        );
+        // reflinks fall back to copies where the filesystem can't make them
+        let clone = directory.path().join("clone.bin");
+        duplicate_file(&original, &clone, LinkMethod::Reflink, &mut |_, _| {}).unwrap();
+        assert_eq!(fs::read(&clone).unwrap(), b"dump");
+        assert!(!temporary_path(&clone).exists());
+        assert!(duplicate_file(&original, &copy, LinkMethod::Copy, &mut |_, _| {}).is_err());
+    }
+}
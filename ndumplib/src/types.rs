@@ -1,11 +1,18 @@
-#[derive(Clone, Copy)]
+use std::str::FromStr;
+
+use crate::{Error, ErrorCategory};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum GameConsole {
     Dreamcast,
     GB,
     GBC,
     GBA,
     GameCube,
+    Genesis,
     N64,
+    NES,
+    SNES,
     PSX,
     PS2,
     PS3,
@@ -17,6 +24,26 @@ pub enum GameConsole {
 }
 
 impl GameConsole {
+    pub const ALL: [GameConsole; 17] = [
+        Self::Dreamcast,
+        Self::GB,
+        Self::GBC,
+        Self::GBA,
+        Self::GameCube,
+        Self::Genesis,
+        Self::N64,
+        Self::NES,
+        Self::SNES,
+        Self::PSX,
+        Self::PS2,
+        Self::PS3,
+        Self::PSP,
+        Self::Wii,
+        Self::WiiU,
+        Self::Xbox,
+        Self::Xbox360,
+    ];
+
     pub fn formal_name(&self) -> &str {
         match self {
             Self::Dreamcast => "Dreamcast",
@@ -24,7 +51,10 @@ impl GameConsole {
             Self::GBC => "Game Boy Color",
             Self::GBA => "Game Boy Advance",
             Self::GameCube => "GameCube",
+            Self::Genesis => "Genesis",
             Self::N64 => "Nintendo 64",
+            Self::NES => "Nintendo Entertainment System",
+            Self::SNES => "Super Nintendo Entertainment System",
             Self::PSX => "PlayStation",
             Self::PS2 => "PlayStation 2",
             Self::PS3 => "PlayStation 3",
@@ -35,4 +65,54 @@ impl GameConsole {
             Self::Xbox360 => "Xbox 360",
         }
     }
+
+    /// Whether the console's games are cartridges, whose ROMs are small enough to read whole
+    pub fn is_cartridge(&self) -> bool {
+        matches!(
+            self,
+            Self::GB | Self::GBC | Self::GBA | Self::Genesis | Self::N64 | Self::NES | Self::SNES
+        )
+    }
+
+    /// The short name used to refer to the console in settings and on the command line
+    pub fn short_name(&self) -> &str {
+        match self {
+            Self::Dreamcast => "dc",
+            Self::GB => "gb",
+            Self::GBC => "gbc",
+            Self::GBA => "gba",
+            Self::GameCube => "gc",
+            Self::Genesis => "genesis",
+            Self::N64 => "n64",
+            Self::NES => "nes",
+            Self::SNES => "snes",
+            Self::PSX => "psx",
+            Self::PS2 => "ps2",
+            Self::PS3 => "ps3",
+            Self::PSP => "psp",
+            Self::Wii => "wii",
+            Self::WiiU => "wiiu",
+            Self::Xbox => "xbox",
+            Self::Xbox360 => "xbox360",
+        }
+    }
+}
+
+impl FromStr for GameConsole {
+    type Err = Error;
+
+    /// Parses a console from its short or formal name (case-insensitive)
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        GameConsole::ALL
+            .into_iter()
+            .find(|console| {
+                console.short_name().eq_ignore_ascii_case(value)
+                    || console.formal_name().eq_ignore_ascii_case(value)
+            })
+            .ok_or_else(|| {
+                Error::new_original(format!("Unknown console: \"{value}\""))
+                    .with_category(ErrorCategory::InvalidInput)
+            })
+    }
 }
@@ -1,11 +1,35 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
 
-use fancy_regex::Regex;
-use rusqlite::{CachedStatement, Connection, Transaction};
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use rusqlite::{
+    CachedStatement, Connection, OpenFlags, OptionalExtension, ToSql, Transaction, params_from_iter,
+};
 
-use crate::{Result, ResultUtils};
+use crate::{
+    CheckpointPolicy, DatabaseCheck, DatabaseMaintenance, DatabaseSchema, Error, ErrorCategory,
+    Result, ResultUtils,
+};
 
+pub(crate) mod cartridge;
 pub(crate) mod chdman;
+pub(crate) mod dolphin;
+pub(crate) mod maxcso;
+pub(crate) mod migrations;
+pub(crate) mod multi_hasher;
+pub(crate) mod nintendo;
+pub(crate) mod serial;
+pub(crate) mod sevenzip;
+pub(crate) mod tool;
+pub(crate) mod wii;
+pub(crate) mod work;
+pub(crate) mod xbox360;
+pub(crate) mod xdelta;
+pub(crate) mod zip;
 
 pub(crate) trait CanPrepare {
     fn prepare_cached_common(&self, sql: &str) -> rusqlite::Result<CachedStatement>;
@@ -25,48 +49,451 @@ impl<'a> CanPrepare for Transaction<'a> {
     }
 }
 
-pub(crate) fn get_database_tables(connection: &impl CanPrepare) -> Result<HashSet<String>> {
+pub(crate) fn get_table_columns(
+    connection: &impl CanPrepare,
+    table: &str,
+) -> Result<HashSet<String>> {
     let mut statement = connection
-        .prepare_cached_common("SELECT * FROM sqlite_master WHERE type = 'table'")
-        .ndl("Failed to retrieve created tables from catalog DB")?;
-    let mut tables = HashSet::new();
+        .prepare_cached_common("SELECT name FROM pragma_table_info(?)")
+        .ndl("Failed to retrieve table columns from DB")?;
+    let mut columns = HashSet::new();
     let mut rows = statement
-        .query(())
-        .ndl("Failed to retrieve created tables from catalog DB")?;
+        .query((table,))
+        .ndl("Failed to retrieve table columns from DB")?;
     while let Some(row) = rows
         .next()
-        .ndl("Failed to retrieve created tables from catalog DB")?
+        .ndl("Failed to retrieve table columns from DB")?
     {
-        tables.insert(
+        columns.insert(
             row.get("name")
-                .ndl("Failed to retrieve created tables from catalog DB")?,
+                .ndl("Failed to retrieve table columns from DB")?,
         );
     }
-    Ok(tables)
+    Ok(columns)
 }
 
-pub(crate) fn get_database_indexes(
-    connection: &impl CanPrepare,
-) -> Result<HashMap<String, String>> {
+/// Buffers rows for a table and inserts them with multi-row INSERTs
+///
+/// Rows are only written when [InsertBatch::flush] is called, so it must be flushed before the rows are
+/// read back.
+pub(crate) struct InsertBatch {
+    table: &'static str,
+    columns: &'static [&'static str],
+    values: Vec<Box<dyn ToSql>>,
+    /// How many rows each INSERT writes
+    max_rows: usize,
+}
+
+impl InsertBatch {
+    /// How many variables a statement can have in SQLite before 3.32, which later versions default
+    /// to allowing more of
+    const MAX_VARIABLES: usize = 999;
+
+    pub fn new(table: &'static str, columns: &'static [&'static str]) -> InsertBatch {
+        let max_rows = Self::MAX_VARIABLES / columns.len();
+        InsertBatch {
+            table,
+            columns,
+            values: Vec::with_capacity(max_rows * columns.len()),
+            max_rows,
+        }
+    }
+
+    pub fn push(&mut self, row: impl IntoIterator<Item = Box<dyn ToSql>>) {
+        self.values.extend(row);
+        debug_assert_eq!(self.values.len() % self.columns.len(), 0);
+    }
+
+    /// Whether enough rows are buffered to fill a whole INSERT
+    pub fn is_full(&self) -> bool {
+        self.values.len() >= self.max_rows * self.columns.len()
+    }
+
+    pub fn flush(&mut self, connection: &impl CanPrepare) -> Result<()> {
+        while !self.values.is_empty() {
+            let row_count = (self.values.len() / self.columns.len()).min(self.max_rows);
+            let row = format!("({})", vec!["?"; self.columns.len()].join(", "));
+            let sql = format!(
+                r#"INSERT INTO "{}" ({}) VALUES {}"#,
+                self.table,
+                self.columns.join(", "),
+                vec![row; row_count].join(", ")
+            );
+            let mut statement = connection
+                .prepare_cached_common(&sql)
+                .ndl(format!("Failed to add {} to DB", self.table))?;
+            statement
+                .execute(params_from_iter(
+                    self.values.drain(..row_count * self.columns.len()),
+                ))
+                .ndl(format!("Failed to add {} to DB", self.table))?;
+        }
+        Ok(())
+    }
+}
+
+/// How many rows `table` has
+///
+pub(crate) fn count_rows(connection: &impl CanPrepare, table: &str) -> Result<usize> {
     let mut statement = connection
-        .prepare_cached_common("SELECT * FROM sqlite_master WHERE type = 'index'")
-        .ndl("Failed to retrieve created tables from catalog DB")?;
-    let mut indexes: HashMap<String, String> = HashMap::new();
-    let mut rows = statement
-        .query(())
-        .ndl("Failed to retrieve created tables from catalog DB")?;
-    while let Some(row) = rows
-        .next()
-        .ndl("Failed to retrieve created tables from catalog DB")?
+        .prepare_cached_common(&format!(r#"SELECT COUNT(*) FROM "{table}""#))
+        .ndl("Failed to count rows in DB")?;
+    let count: i64 = statement
+        .query_row((), |row| row.get(0))
+        .ndl("Failed to count rows in DB")?;
+    Ok(count as usize)
+}
+
+/// Drops the indexes on the given tables, returning the SQL to recreate them
+///
+pub(crate) fn drop_indexes(connection: &impl CanPrepare, tables: &[&str]) -> Result<Vec<String>> {
+    let mut indexes: Vec<(String, String)> = Vec::new();
     {
-        indexes.insert(
-            row.get("name")
-                .ndl("Failed to retrieve created tables from catalog DB")?,
-            row.get("tbl_name")
-                .ndl("Failed to retrieve created tables from catalog DB")?,
-        );
+        let mut statement = connection
+            .prepare_cached_common(
+                "SELECT name, sql FROM sqlite_master WHERE type = 'index' AND tbl_name = ? AND sql IS NOT NULL",
+            )
+            .ndl("Failed to retrieve indexes from DB")?;
+        for table in tables {
+            let rows = statement
+                .query_map((table,), |row| Ok((row.get(0)?, row.get(1)?)))
+                .ndl("Failed to retrieve indexes from DB")?;
+            for row in rows {
+                indexes.push(row.ndl("Failed to retrieve indexes from DB")?);
+            }
+        }
+    }
+    let mut recreate = Vec::with_capacity(indexes.len());
+    for (name, sql) in indexes {
+        connection
+            .prepare_cached_common(&format!(r#"DROP INDEX "{name}""#))
+            .and_then(|mut statement| statement.execute(()))
+            .ndl("Failed to drop index from DB")?;
+        recreate.push(sql);
+    }
+    Ok(recreate)
+}
+
+/// Rows in `table` matching `condition` have lost the row they belong to
+pub(crate) struct OrphanCheck {
+    pub table: &'static str,
+    pub condition: &'static str,
+}
+
+/// Runs SQLite's integrity and foreign key checks on a database and counts its orphaned rows
+///
+/// When `repair` is set, the orphaned rows are deleted (in the order of `orphan_checks`) and the indexes
+/// are rebuilt.
+pub(crate) fn check_database(
+    connection: &mut Connection,
+    database_name: &str,
+    orphan_checks: &[OrphanCheck],
+    repair: bool,
+) -> Result<DatabaseCheck> {
+    let error_message = format!("Failed to check {database_name} DB");
+    let problems = find_database_problems(connection, &error_message)?;
+    let mut orphaned_rows = Vec::new();
+    for check in orphan_checks {
+        let count: usize = connection
+            .query_row(
+                &format!(
+                    r#"SELECT COUNT(*) FROM "{}" WHERE {}"#,
+                    check.table, check.condition
+                ),
+                (),
+                |row| row.get(0),
+            )
+            .ndl(&error_message)?;
+        if count > 0 {
+            orphaned_rows.push((check.table.to_string(), count));
+        }
+    }
+    if repair {
+        let error_message = format!("Failed to repair {database_name} DB");
+        let transaction = connection.transaction().ndl(&error_message)?;
+        for check in orphan_checks {
+            let deleted = transaction
+                .execute(
+                    &format!(r#"DELETE FROM "{}" WHERE {}"#, check.table, check.condition),
+                    (),
+                )
+                .ndl(&error_message)?;
+            if deleted > 0 {
+                debug!(
+                    "Deleted {deleted} orphaned rows from \"{}\" in {database_name} DB",
+                    check.table
+                );
+            }
+        }
+        transaction.execute("REINDEX", ()).ndl(&error_message)?;
+        transaction.commit().ndl(&error_message)?;
+        debug!("Rebuilt indexes in {database_name} DB");
+    }
+    // deleting orphaned rows fixes the foreign key problems they caused
+    let remaining_problems = if repair && !problems.is_empty() {
+        find_database_problems(connection, &error_message)?
+    } else {
+        problems.clone()
+    };
+    Ok(DatabaseCheck {
+        database: database_name.to_string(),
+        problems,
+        remaining_problems,
+        orphaned_rows,
+        repaired: repair,
+    })
+}
+
+/// Runs SQLite's integrity and foreign key checks, returning the problems they report
+fn find_database_problems(connection: &Connection, error_message: &str) -> Result<Vec<String>> {
+    let mut problems = Vec::new();
+    let mut statement = connection
+        .prepare("PRAGMA integrity_check")
+        .ndl(error_message)?;
+    let mut rows = statement.query(()).ndl(error_message)?;
+    while let Some(row) = rows.next().ndl(error_message)? {
+        let message: String = row.get(0).ndl(error_message)?;
+        if message != "ok" {
+            problems.push(message);
+        }
+    }
+    let mut statement = connection
+        .prepare("PRAGMA foreign_key_check")
+        .ndl(error_message)?;
+    let mut rows = statement.query(()).ndl(error_message)?;
+    while let Some(row) = rows.next().ndl(error_message)? {
+        let table: String = row.get(0).ndl(error_message)?;
+        let rowid: Option<i64> = row.get(1).ndl(error_message)?;
+        let parent: String = row.get(2).ndl(error_message)?;
+        problems.push(format!(
+            "Row {} in table \"{table}\" refers to a missing row in table \"{parent}\"",
+            rowid.map_or(String::from("?"), |rowid| rowid.to_string())
+        ));
+    }
+    Ok(problems)
+}
+
+/// Reads the statements creating a database's tables, indexes, views, and triggers
+///
+/// Tables come first, then indexes, views, and triggers, each sorted by name.
+pub(crate) fn database_schema(
+    connection: &Connection,
+    database_name: &str,
+) -> Result<DatabaseSchema> {
+    let error_message = format!("Failed to read {database_name} DB schema");
+    let mut statement = connection
+        .prepare(
+            r#"
+                SELECT "sql" FROM "sqlite_master"
+                WHERE "sql" IS NOT NULL AND "name" NOT LIKE 'sqlite_%'
+                ORDER BY CASE "type" WHEN 'table' THEN 0 WHEN 'index' THEN 1 WHEN 'view' THEN 2 ELSE 3 END, "name"
+            "#,
+        )
+        .ndl(&error_message)?;
+    let rows = statement
+        .query_map((), |row| row.get::<_, String>(0))
+        .ndl(&error_message)?;
+    let mut statements = Vec::new();
+    for row in rows {
+        statements.push(row.ndl(&error_message)?);
+    }
+    Ok(DatabaseSchema {
+        database: database_name.to_string(),
+        version: migrations::schema_version(connection)?,
+        statements,
+    })
+}
+
+/// Writes a consistent copy of a database to `to` with SQLite's backup API, replacing what's there
+///
+/// Other connections can keep reading and writing meanwhile: the copy is of the database as it
+/// was when the backup finished.
+pub(crate) fn backup_database(
+    connection: &Connection,
+    database_name: &str,
+    to: &Path,
+) -> Result<()> {
+    if to.exists() {
+        std::fs::remove_file(to).ndl(format!("Failed to back up {database_name} DB"))?;
+    }
+    connection
+        .backup(rusqlite::MAIN_DB, to, None)
+        .ndl(format!("Failed to back up {database_name} DB"))?;
+    debug!(
+        "Backed up {database_name} DB to \"{}\"",
+        to.to_str().unwrap()
+    );
+    Ok(())
+}
+
+/// Runs `PRAGMA optimize` as a database is closed, as SQLite recommends, then checkpoints its
+/// write-ahead log as the policy says, without ever panicking
+///
+/// It's only best-effort: failures are logged, and it's skipped while unwinding from a panic,
+/// which may have left the database in the middle of a write. Anything heavier is left to
+/// [maintain_database].
+pub(crate) fn close_database(
+    connection: &Connection,
+    database_name: &str,
+    checkpoint: CheckpointPolicy,
+) {
+    if std::thread::panicking() {
+        debug!("Not optimizing {database_name} DB while unwinding from a panic");
+        return;
+    }
+    if let Err(err) = connection.execute_batch("PRAGMA optimize;") {
+        debug!("Failed to optimize {database_name} DB: {err}");
+    }
+    if checkpoint.on_close {
+        let mode = if checkpoint.truncate {
+            "TRUNCATE"
+        } else {
+            "PASSIVE"
+        };
+        if let Err(err) = connection.execute_batch(&format!("PRAGMA wal_checkpoint({mode});")) {
+            debug!("Failed to checkpoint {database_name} DB: {err}");
+        }
+    }
+}
+
+/// Has SQLite checkpoint a database's write-ahead log as the policy says while it's written
+///
+/// This is best-effort like [close_database], since the database works either way.
+pub(crate) fn apply_checkpoint_policy(
+    connection: &Connection,
+    database_name: &str,
+    checkpoint: CheckpointPolicy,
+) {
+    if let Some(pages) = checkpoint.auto_checkpoint_pages
+        && let Err(err) = connection.pragma_update(None, "wal_autocheckpoint", pages)
+    {
+        warn!("Failed to configure {database_name} DB's checkpoints: {err}");
+    }
+}
+
+/// Opens a database only to read it, as [crate::DumpManager::open_read_only] does
+///
+/// Its schema isn't brought up to date, so opening fails if it's out of date.
+pub(crate) fn open_read_only_database(
+    path: &Path,
+    database_name: &str,
+    migrations: &[migrations::Migration],
+) -> Result<Connection> {
+    let connection = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+    .ndl(format!("Failed to open {database_name} DB"))?;
+    connection.set_prepared_statement_cache_capacity(32);
+    let version = migrations::schema_version(&connection)?;
+    if version != migrations.len() {
+        return Err(Error::new_original(format!(
+            "Failed to open {database_name} DB read-only\nIts schema version ({version}) isn't this version of ndumpmgr's ({}). Open it once without read-only mode to bring it up to date",
+            migrations.len()
+        ))
+        .with_category(ErrorCategory::Database));
+    }
+    debug!(
+        r#"Opened {database_name} database at "{}" read-only"#,
+        path.to_str().unwrap()
+    );
+    Ok(connection)
+}
+
+/// The database's size in bytes, going by its pages
+fn database_size(connection: &Connection, error_message: &str) -> Result<u64> {
+    let page_count: i64 = connection
+        .pragma_query_value(None, "page_count", |row| row.get(0))
+        .ndl(error_message)?;
+    let page_size: i64 = connection
+        .pragma_query_value(None, "page_size", |row| row.get(0))
+        .ndl(error_message)?;
+    Ok((page_count * page_size) as u64)
+}
+
+/// Vacuums a database, updates the query planner's statistics, and writes its write-ahead log back,
+/// recording when it was done
+///
+/// Vacuuming rewrites the whole file, which takes a while for big databases, so it's only done
+/// when asked (or every so often, see [crate::DumpManager::maintain_databases_if_due]).
+pub(crate) fn maintain_database(
+    connection: &Connection,
+    database_name: &str,
+) -> Result<DatabaseMaintenance> {
+    let error_message = format!("Failed to maintain {database_name} DB");
+    let size_before = database_size(connection, &error_message)?;
+    connection
+        .execute(
+            r#"INSERT OR REPLACE INTO "maintenance" ("id", "last_maintained") VALUES (0, ?)"#,
+            (Utc::now().timestamp_millis(),),
+        )
+        .ndl(&error_message)?;
+    connection
+        .execute_batch("VACUUM; PRAGMA optimize; PRAGMA wal_checkpoint(TRUNCATE);")
+        .ndl(&error_message)?;
+    let size_after = database_size(connection, &error_message)?;
+    debug!("Maintained {database_name} DB ({size_before} bytes to {size_after})");
+    Ok(DatabaseMaintenance {
+        database: database_name.to_string(),
+        size_before,
+        size_after,
+    })
+}
+
+/// When a database was last maintained, or `None` if it never has been
+///
+pub(crate) fn last_maintained(
+    connection: &Connection,
+    database_name: &str,
+) -> Result<Option<DateTime<Utc>>> {
+    let millis: Option<i64> = connection
+        .query_row(
+            r#"SELECT "last_maintained" FROM "maintenance" WHERE "id" = 0"#,
+            (),
+            |row| row.get(0),
+        )
+        .optional()
+        .ndl(format!("Failed to read {database_name} DB"))?;
+    Ok(millis.and_then(DateTime::from_timestamp_millis))
+}
+
+/// Read-only connections to a database, shared between threads
+///
+/// Connections are opened as they're needed and kept for reuse, so each thread gets its own
+/// connection without opening one per query. WAL mode lets them read while another connection writes.
+pub(crate) struct ReadOnlyPool {
+    path: PathBuf,
+    idle: Mutex<Vec<Connection>>,
+}
+
+impl ReadOnlyPool {
+    pub fn new(path: &impl AsRef<Path>) -> ReadOnlyPool {
+        ReadOnlyPool {
+            path: path.as_ref().to_path_buf(),
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Runs a query on an idle connection, opening a new one if they're all in use
+    ///
+    pub fn with_connection<T>(&self, query: impl FnOnce(&Connection) -> Result<T>) -> Result<T> {
+        let idle = self.idle.lock().unwrap().pop();
+        let connection = match idle {
+            Some(connection) => connection,
+            None => {
+                let connection = Connection::open_with_flags(
+                    &self.path,
+                    OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+                )
+                .ndl("Failed to open read-only DB connection")?;
+                connection.set_prepared_statement_cache_capacity(32);
+                connection
+            }
+        };
+        let result = query(&connection);
+        self.idle.lock().unwrap().push(connection);
+        result
     }
-    Ok(indexes)
 }
 
 pub(crate) fn setup_database_default_config(connection: &Connection) -> Result<()> {
@@ -85,6 +512,66 @@ pub(crate) fn setup_database_default_config(connection: &Connection) -> Result<(
         .ndl("Failed to configure catalog DB")
 }
 
+/// The lowercase character trigrams of the words in some text, padded so that short words and word
+/// boundaries count too
+fn trigrams(text: &str) -> HashSet<[char; 3]> {
+    let mut trigrams = HashSet::new();
+    for word in text
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        let padded: Vec<char> = "  "
+            .chars()
+            .chain(word.chars())
+            .chain(" ".chars())
+            .collect();
+        for window in padded.windows(3) {
+            trigrams.insert([window[0], window[1], window[2]]);
+        }
+    }
+    trigrams
+}
+
+/// Scores how closely `text` resembles what was typed in `query`, from 0 to 1
+///
+/// This is the share of the query's trigrams found in the text, so a short query can fully match a
+/// longer title (e.g. "metal gear solid" in "Metal Gear Solid (USA) (Disc 1)"). Typos only lose the
+/// few trigrams around them.
+pub(crate) fn trigram_similarity(query: &str, text: &str) -> f64 {
+    let query = trigrams(query);
+    if query.is_empty() {
+        return 0.0;
+    }
+    let text = trigrams(text);
+    query.intersection(&text).count() as f64 / query.len() as f64
+}
+
+/// Matches text against a case-insensitive wildcard pattern (`*` matches any run of characters, `?` one character)
+pub(crate) fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // the position of the last '*' seen, and the text position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, star_t)) = backtrack {
+            p = star + 1;
+            t = star_t + 1;
+            backtrack = Some((star, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
 macro_rules! regex {
     ($re:literal $(,)?) => {{
         static RE: once_cell::sync::OnceCell<fancy_regex::Regex> = once_cell::sync::OnceCell::new();
@@ -92,12 +579,73 @@ macro_rules! regex {
     }};
 }
 
-#[inline(always)]
-pub fn first_match(regex: &Regex, input: &str) -> Option<String> {
-    regex
-        .find(input)
-        .unwrap()
-        .map(|result| result.as_str().to_owned())
-}
-
 pub(crate) use regex;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores_typos_below_exact_matches() {
+        let title = "Metal Gear Solid (USA) (Disc 1)";
+        assert_eq!(trigram_similarity("metal gear solid", title), 1.0);
+        let typo = trigram_similarity("metl gear sold", title);
+        assert!(typo > 0.5 && typo < 1.0, "{typo}");
+        assert!(trigram_similarity("final fantasy", title) < 0.2);
+    }
+
+    #[test]
+    fn batches_inserts_within_variable_limit() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(r#"CREATE TABLE "wide" (a, b, c, d, e, f, g, h);"#)
+            .unwrap();
+        let mut batch = InsertBatch::new("wide", &["a", "b", "c", "d", "e", "f", "g", "h"]);
+        let mut rows = 0;
+        while !batch.is_full() {
+            batch.push((0..8).map(|value| Box::new(value) as Box<dyn ToSql>));
+            rows += 1;
+        }
+        assert!(rows * 8 <= InsertBatch::MAX_VARIABLES);
+        // a partial INSERT follows the full ones
+        for _ in 0..rows + 1 {
+            batch.push((0..8).map(|value| Box::new(value) as Box<dyn ToSql>));
+        }
+        batch.flush(&connection).unwrap();
+        assert_eq!(count_rows(&connection, "wide").unwrap(), rows * 2 + 1);
+    }
+
+    #[test]
+    fn records_when_databases_were_maintained() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        let transaction = connection.transaction().unwrap();
+        migrations::create_maintenance_table(&transaction).unwrap();
+        transaction.commit().unwrap();
+        assert_eq!(last_maintained(&connection, "test").unwrap(), None);
+        let maintenance = maintain_database(&connection, "test").unwrap();
+        assert_eq!(maintenance.database, "test");
+        assert!(maintenance.size_after > 0);
+        let last = last_maintained(&connection, "test").unwrap().unwrap();
+        assert!(Utc::now() - last < chrono::TimeDelta::minutes(1));
+    }
+
+    #[test]
+    fn opens_only_up_to_date_databases_read_only() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("test.sqlite");
+        let migration = || migrations::Migration {
+            description: "Create the maintenance table",
+            apply: migrations::create_maintenance_table,
+        };
+        let migrations = [migration()];
+        let mut connection = Connection::open(&path).unwrap();
+        migrations::run_migrations(&mut connection, "test", &migrations).unwrap();
+        drop(connection);
+        let connection = open_read_only_database(&path, "test", &migrations).unwrap();
+        assert_eq!(last_maintained(&connection, "test").unwrap(), None);
+        assert!(maintain_database(&connection, "test").is_err());
+        drop(connection);
+        let newer = [migration(), migration()];
+        assert!(open_read_only_database(&path, "test", &newer).is_err());
+    }
+}
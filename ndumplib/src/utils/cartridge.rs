@@ -0,0 +1,194 @@
+use std::path::Path;
+
+use crate::{GameConsole, Result, ResultUtils};
+
+/// The largest ROM read whole to normalize it, well past any cartridge; bigger files are hashed as
+/// they are
+pub(crate) const MAX_ROM_SIZE: u64 = 128 << 20;
+
+/// Undoes one way a console's cartridge ROMs are commonly stored differently from how No-Intro
+/// lists them, like a copier's header or a swapped byte order
+///
+pub(crate) trait Normalizer: Sync {
+    fn console(&self) -> GameConsole;
+
+    /// Returns the ROM as No-Intro lists it, or `None` if it already is
+    ///
+    fn normalize(&self, rom: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// N64 ROMs are listed big-endian (.z64), but are also found with their bytes swapped in pairs
+/// (.v64) or in fours (.n64), which the first word of every ROM tells apart
+pub(crate) struct N64ByteOrder;
+
+impl Normalizer for N64ByteOrder {
+    fn console(&self) -> GameConsole {
+        GameConsole::N64
+    }
+
+    fn normalize(&self, rom: &[u8]) -> Option<Vec<u8>> {
+        let swap = |size: usize| {
+            rom.chunks(size)
+                .flat_map(|word| word.iter().rev())
+                .copied()
+                .collect()
+        };
+        match rom.get(..4)? {
+            [0x37, 0x80, 0x40, 0x12] => Some(swap(2)),
+            [0x40, 0x12, 0x37, 0x80] => Some(swap(4)),
+            _ => None,
+        }
+    }
+}
+
+/// NES ROMs are listed without the 16-byte iNES header emulators need (nor the trainer it may be
+/// followed by)
+struct INesHeader;
+
+impl INesHeader {
+    const MAGIC: &[u8; 4] = b"NES\x1A";
+    const SIZE: usize = 16;
+    const TRAINER_SIZE: usize = 512;
+}
+
+impl Normalizer for INesHeader {
+    fn console(&self) -> GameConsole {
+        GameConsole::NES
+    }
+
+    fn normalize(&self, rom: &[u8]) -> Option<Vec<u8>> {
+        if !rom.starts_with(Self::MAGIC) {
+            return None;
+        }
+        let has_trainer = rom.get(6)? & 0x04 != 0;
+        let start = Self::SIZE + if has_trainer { Self::TRAINER_SIZE } else { 0 };
+        Some(rom.get(start..)?.to_vec())
+    }
+}
+
+/// SNES ROMs come in multiples of 1 KiB, so one with 512 bytes over has a copier's header
+struct SnesCopierHeader;
+
+impl Normalizer for SnesCopierHeader {
+    fn console(&self) -> GameConsole {
+        GameConsole::SNES
+    }
+
+    fn normalize(&self, rom: &[u8]) -> Option<Vec<u8>> {
+        (rom.len() % 1024 == 512).then(|| rom[512..].to_vec())
+    }
+}
+
+/// Super Magic Drive dumps of Genesis ROMs have a 512-byte header, then the ROM in 16 KiB blocks
+/// holding its odd bytes followed by its even ones
+struct GenesisInterleaving;
+
+impl GenesisInterleaving {
+    const HEADER_SIZE: usize = 512;
+    const BLOCK_SIZE: usize = 0x4000;
+}
+
+impl Normalizer for GenesisInterleaving {
+    fn console(&self) -> GameConsole {
+        GameConsole::Genesis
+    }
+
+    fn normalize(&self, rom: &[u8]) -> Option<Vec<u8>> {
+        // SMD headers are signed with these at offsets 8 and 9
+        if rom.len() % Self::BLOCK_SIZE != Self::HEADER_SIZE || rom.get(8..10)? != [0xAA, 0xBB] {
+            return None;
+        }
+        let mut normalized = vec![0u8; rom.len() - Self::HEADER_SIZE];
+        for (block, output) in rom[Self::HEADER_SIZE..]
+            .chunks_exact(Self::BLOCK_SIZE)
+            .zip(normalized.chunks_exact_mut(Self::BLOCK_SIZE))
+        {
+            let (odd, even) = block.split_at(Self::BLOCK_SIZE / 2);
+            for (index, pair) in output.chunks_exact_mut(2).enumerate() {
+                pair[0] = even[index];
+                pair[1] = odd[index];
+            }
+        }
+        Some(normalized)
+    }
+}
+
+/// The normalizer for a cartridge ROM, going by its extension
+///
+/// Returns `None` for files which aren't cartridge ROMs that may need normalizing.
+pub(crate) fn normalizer_for(path: &Path) -> Option<&'static dyn Normalizer> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    Some(match extension.as_str() {
+        "z64" | "v64" | "n64" => &N64ByteOrder,
+        "nes" => &INesHeader,
+        "sfc" | "smc" | "swc" | "fig" => &SnesCopierHeader,
+        "md" | "gen" | "smd" => &GenesisInterleaving,
+        _ => return None,
+    })
+}
+
+/// Reads a cartridge ROM as No-Intro lists it, if it's stored differently
+///
+/// Returns `None` for ROMs which are hashed as they are: those already as listed, files of other
+/// kinds, and files too big to be cartridge ROMs.
+pub(crate) fn read_normalized(path: &Path) -> Result<Option<Vec<u8>>> {
+    let Some(normalizer) = normalizer_for(path) else {
+        return Ok(None);
+    };
+    let size = std::fs::metadata(path).ndl("Failed to read ROM")?.len();
+    if size > MAX_ROM_SIZE {
+        return Ok(None);
+    }
+    let rom = std::fs::read(path).ndl("Failed to read ROM")?;
+    let normalized = normalizer.normalize(&rom);
+    if normalized.is_some() {
+        log::debug!(
+            "Normalized {} ROM \"{}\" before hashing it",
+            normalizer.console().formal_name(),
+            path.display()
+        );
+    }
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_cartridge_roms() {
+        let z64 = [0x80, 0x37, 0x12, 0x40, 1, 2, 3, 4];
+        assert_eq!(N64ByteOrder.normalize(&z64), None);
+        let v64 = [0x37, 0x80, 0x40, 0x12, 2, 1, 4, 3];
+        assert_eq!(N64ByteOrder.normalize(&v64).unwrap(), z64);
+        let n64 = [0x40, 0x12, 0x37, 0x80, 4, 3, 2, 1];
+        assert_eq!(N64ByteOrder.normalize(&n64).unwrap(), z64);
+
+        let mut nes = b"NES\x1A".to_vec();
+        nes.resize(16, 0);
+        nes.extend_from_slice(b"PRG");
+        assert_eq!(INesHeader.normalize(&nes).unwrap(), b"PRG");
+        nes[6] = 0x04;
+        nes.splice(16..16, vec![0xEE; 512]);
+        assert_eq!(INesHeader.normalize(&nes).unwrap(), b"PRG");
+        assert_eq!(INesHeader.normalize(b"PRG"), None);
+
+        let mut snes = vec![0xFF; 512];
+        snes.extend(vec![0x42; 2048]);
+        assert_eq!(SnesCopierHeader.normalize(&snes).unwrap(), vec![0x42; 2048]);
+        assert_eq!(SnesCopierHeader.normalize(&snes[512..]), None);
+
+        let rom: Vec<u8> = (0..0x8000u32).map(|byte| (byte % 251) as u8).collect();
+        let mut smd = vec![0u8; 512];
+        smd[8..10].copy_from_slice(&[0xAA, 0xBB]);
+        for block in rom.chunks(0x4000) {
+            smd.extend(block.iter().skip(1).step_by(2));
+            smd.extend(block.iter().step_by(2));
+        }
+        assert_eq!(GenesisInterleaving.normalize(&smd).unwrap(), rom);
+        assert_eq!(GenesisInterleaving.normalize(&rom), None);
+
+        assert!(normalizer_for(Path::new("Game (USA).V64")).is_some());
+        assert!(normalizer_for(Path::new("Game (USA).gba")).is_none());
+    }
+}
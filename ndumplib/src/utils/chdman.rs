@@ -1,12 +1,24 @@
-use std::process::Command;
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
 
-use fancy_regex::Regex;
+use super::tool::{ExternalTool, ToolVersion};
+use crate::{
+    Error, Result, ResultUtils,
+    dump_manager::timings::{self, Stage},
+};
 
-use super::{first_match, regex};
-use crate::{Error, Result, ResultUtils};
+/// The first chdman which can extract each track of a CD to its own bin (`-sb`)
+const SPLIT_TRACKS_VERSION: ToolVersion = ToolVersion {
+    major: 0,
+    minor: 262,
+    patch: 0,
+};
 
-#[derive(Clone, Copy, Debug)]
-#[allow(unused)]
+/// A codec chdman can compress a CHD's hunks with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Codec {
     ZLIB,
     ZSTD,
@@ -21,22 +33,25 @@ pub enum Codec {
 }
 
 impl Codec {
-    fn from_string(str: &str) -> Self {
+    /// Parses a codec's name, returning `None` for ones this doesn't know (or no codec)
+    fn from_string(str: &str) -> Option<Self> {
         match str {
-            "zlib" => Self::ZLIB,
-            "zstd" => Self::ZSTD,
-            "lzma" => Self::LZMA,
-            "huff" => Self::HUFF,
-            "flac" => Self::FLAC,
-            "cdzl" => Self::CDZL,
-            "cdzs" => Self::CDZS,
-            "cdlz" => Self::CDLZ,
-            "cdfl" => Self::CDFL,
-            "avhu" => Self::AVHU,
-            _ => panic!(),
+            "zlib" => Some(Self::ZLIB),
+            "zstd" => Some(Self::ZSTD),
+            "lzma" => Some(Self::LZMA),
+            "huff" => Some(Self::HUFF),
+            "flac" => Some(Self::FLAC),
+            "cdzl" => Some(Self::CDZL),
+            "cdzs" => Some(Self::CDZS),
+            "cdlz" => Some(Self::CDLZ),
+            "cdfl" => Some(Self::CDFL),
+            "avhu" => Some(Self::AVHU),
+            _ => None,
         }
     }
-    fn to_string(self) -> &'static str {
+    /// The codec's name, as chdman takes and reports it
+    ///
+    pub fn name(self) -> &'static str {
         match self {
             Self::ZLIB => "zlib",
             Self::ZSTD => "zstd",
@@ -64,7 +79,7 @@ pub fn create_cd(
     output: &impl AsRef<str>,
     options: CreateOptions,
 ) -> Result<()> {
-    let mut command = Command::new("chdman");
+    let mut command = ExternalTool::Chdman.command();
     command
         .arg("createcd")
         .arg("-i")
@@ -75,7 +90,7 @@ pub fn create_cd(
         command.arg("-c").arg(
             compression
                 .iter()
-                .map(|f| f.to_string())
+                .map(|f| f.name())
                 .collect::<Vec<&str>>()
                 .join(","),
         );
@@ -89,7 +104,9 @@ pub fn create_cd(
     if let Some(processor_count) = options.processor_count {
         command.arg("-np").arg(processor_count.to_string());
     }
-    let output = command.output().ndl("Failed to create CHD")?;
+    let output = timings::time(Stage::Conversion, || {
+        ExternalTool::Chdman.run(&mut command, "Failed to create CHD")
+    })?;
     let stderr = std::str::from_utf8(&output.stderr).unwrap();
     if stderr.contains("Compression complete") {
         Ok(())
@@ -101,9 +118,11 @@ pub fn create_cd(
     }
 }
 
+#[derive(Default)]
 pub struct ExtractOptions {
-    force: bool,
-    split_tracks: bool,
+    pub force: bool,
+    /// Writes a .bin for each track, rather than one for the whole disc
+    pub split_tracks: bool,
 }
 
 pub fn extract_cd(
@@ -111,7 +130,7 @@ pub fn extract_cd(
     output: &impl AsRef<str>,
     options: ExtractOptions,
 ) -> Result<()> {
-    let mut command = Command::new("chdman");
+    let mut command = ExternalTool::Chdman.command();
     command
         .arg("extractcd")
         .arg("-i")
@@ -122,9 +141,12 @@ pub fn extract_cd(
         command.arg("-f");
     }
     if options.split_tracks {
+        ExternalTool::Chdman.require_version(SPLIT_TRACKS_VERSION, "Extracting split tracks")?;
         command.arg("-sb");
     }
-    let output = command.output().ndl("Failed to extract CHD")?;
+    let output = timings::time(Stage::Conversion, || {
+        ExternalTool::Chdman.run(&mut command, "Failed to extract CHD")
+    })?;
     let stderr = std::str::from_utf8(&output.stderr).unwrap();
     if stderr.contains("Extraction complete") {
         Ok(())
@@ -137,20 +159,83 @@ pub fn extract_cd(
 }
 
 pub fn verify(input: &impl AsRef<str>) -> Result<bool> {
-    let output = Command::new("chdman")
-        .arg("verify")
-        .arg("-i")
-        .arg(input.as_ref())
-        .output()
-        .ndl("Failed to verify CHD")?;
+    let output = ExternalTool::Chdman.run(
+        ExternalTool::Chdman
+            .command()
+            .arg("verify")
+            .arg("-i")
+            .arg(input.as_ref()),
+        "Failed to verify CHD",
+    )?;
     Ok(std::str::from_utf8(&output.stdout)
         .unwrap()
         .contains("verification successful"))
 }
 
+/// The hashes chdman records in a V5 CHD's header when it's created
+#[allow(unused)]
 #[derive(Debug)]
+pub struct HeaderV5 {
+    pub logical_size: u64,
+    /// The codecs hunks may be compressed with
+    pub compression: Vec<Codec>,
+    /// Where the first metadata entry is, or 0 if there's none
+    pub metadata_offset: u64,
+    /// SHA-1 of the uncompressed data
+    pub raw_sha1: [u8; 20],
+    /// SHA-1 of the uncompressed data and metadata
+    pub sha1: [u8; 20],
+    pub parent_sha1: Option<[u8; 20]>,
+}
+
+/// Reads a V5 CHD's header directly, without decompressing anything
+///
+pub fn read_header(input: &impl AsRef<Path>) -> Result<HeaderV5> {
+    let mut header = [0u8; 124];
+    File::open(input)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .ndl("Failed to read CHD header")?;
+    if &header[0..8] != b"MComprHD" {
+        return Err(Error::new_original(
+            "Failed to read CHD header
+Not a CHD file",
+        ));
+    }
+    let version = u32::from_be_bytes(header[12..16].try_into().unwrap());
+    if version != 5 {
+        return Err(Error::new_original(format!(
+            "Failed to read CHD header
+Unsupported CHD version: {version}"
+        )));
+    }
+    let sha1_at = |offset: usize| -> [u8; 20] { header[offset..offset + 20].try_into().unwrap() };
+    let parent_sha1 = sha1_at(104);
+    Ok(HeaderV5 {
+        logical_size: u64::from_be_bytes(header[32..40].try_into().unwrap()),
+        compression: header[16..32]
+            .chunks(4)
+            .filter_map(|tag| Codec::from_string(std::str::from_utf8(tag).ok()?))
+            .collect(),
+        metadata_offset: u64::from_be_bytes(header[48..56].try_into().unwrap()),
+        raw_sha1: sha1_at(64),
+        sha1: sha1_at(84),
+        parent_sha1: if parent_sha1 == [0; 20] {
+            None
+        } else {
+            Some(parent_sha1)
+        },
+    })
+}
+
+/// The type of a CD track, as chdman names it in the track's metadata
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TrackType {
     Mode1,
+    Mode1Raw,
+    Mode2,
+    Mode2Form1,
+    Mode2Form2,
+    Mode2FormMix,
     Mode2Raw,
     Audio,
 }
@@ -159,100 +244,300 @@ impl TrackType {
     fn from_str(str: &str) -> Option<TrackType> {
         match str {
             "MODE1" => Some(Self::Mode1),
+            "MODE1_RAW" => Some(Self::Mode1Raw),
+            "MODE2" => Some(Self::Mode2),
+            "MODE2_FORM1" => Some(Self::Mode2Form1),
+            "MODE2_FORM2" => Some(Self::Mode2Form2),
+            "MODE2_FORM_MIX" => Some(Self::Mode2FormMix),
             "MODE2_RAW" => Some(Self::Mode2Raw),
             "AUDIO" => Some(Self::Audio),
             _ => None,
         }
     }
+    /// How many bytes of each frame are stored, without subcode
+    ///
+    pub fn sector_size(self) -> u64 {
+        match self {
+            Self::Mode1 | Self::Mode2Form1 => 2048,
+            Self::Mode2Form2 => 2324,
+            Self::Mode2 | Self::Mode2FormMix => 2336,
+            Self::Mode1Raw | Self::Mode2Raw | Self::Audio => 2352,
+        }
+    }
 }
 
-#[allow(unused)]
-#[derive(Debug)]
-pub enum Tag {
-    CHT2 { track: u8, track_type: TrackType },
-    Other(String),
+/// A CD or GD-ROM track, from a CHD's CHTR, CHT2, or CHGD metadata
+#[derive(Clone, Debug)]
+pub struct ChdTrack {
+    number: u8,
+    track_type: TrackType,
+    frames: u64,
 }
 
-#[allow(unused)]
-#[derive(Debug)]
-pub struct InfoV5 {
-    logical_size: usize,
-    chd_size: usize,
+impl ChdTrack {
+    /// The track's number on the disc, starting at 1
+    ///
+    pub fn number(&self) -> u8 {
+        self.number
+    }
+    pub fn track_type(&self) -> TrackType {
+        self.track_type
+    }
+    /// How many frames the track has, excluding its pregap
+    ///
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+    /// How big the track is once extracted, in bytes
+    ///
+    pub fn size(&self) -> u64 {
+        self.frames * self.track_type.sector_size()
+    }
+}
+
+/// A metadata entry of a CHD
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum ChdMetadata {
+    /// A CD track (CHTR or CHT2 metadata) or GD-ROM track (CHGD metadata)
+    Track(ChdTrack),
+    /// The CHD is a DVD (`DVD ` metadata)
+    DVD,
+    /// Any other metadata, with its tag and (first line of) data
+    Other { tag: String, data: String },
+}
+
+/// What kind of disc (or disk) a CHD holds, going by its metadata
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChdMedia {
+    CD,
+    GDROM,
+    DVD,
+    /// A hard disk, laserdisc, or anything else which isn't a disc of tracks
+    Other,
+}
+
+/// What a (version 5) CHD's header and metadata say about it, as `chdman info` reports
+#[derive(Clone, Debug)]
+pub struct ChdInfo {
+    logical_size: u64,
+    chd_size: u64,
     compression: Vec<Codec>,
     sha1: [u8; 20],
     data_sha1: [u8; 20],
-    metadata: Vec<Tag>,
+    metadata: Vec<ChdMetadata>,
+    gdrom: bool,
 }
 
-fn parse_usize(regex: &Regex, input: &str) -> Option<usize> {
-    first_match(regex, input)
-        .map(|v| usize::from_str_radix(&v.trim().replace(",", ""), 10).unwrap())
+impl ChdInfo {
+    /// The size of the CHD's contents once decompressed, in bytes
+    ///
+    pub fn logical_size(&self) -> u64 {
+        self.logical_size
+    }
+    /// The size of the CHD file, in bytes
+    ///
+    pub fn chd_size(&self) -> u64 {
+        self.chd_size
+    }
+    /// The codecs the CHD's hunks may be compressed with
+    ///
+    pub fn compression(&self) -> &[Codec] {
+        &self.compression
+    }
+    /// The SHA-1 of the CHD's contents and metadata
+    ///
+    pub fn sha1(&self) -> [u8; 20] {
+        self.sha1
+    }
+    /// The SHA-1 of the CHD's contents, which is the ISO's for DVDs
+    ///
+    pub fn data_sha1(&self) -> [u8; 20] {
+        self.data_sha1
+    }
+    pub fn metadata(&self) -> &[ChdMetadata] {
+        &self.metadata
+    }
+    /// The CD or GD-ROM tracks, in the order they're on the disc
+    ///
+    pub fn tracks(&self) -> impl Iterator<Item = &ChdTrack> {
+        self.metadata.iter().filter_map(|tag| match tag {
+            ChdMetadata::Track(track) => Some(track),
+            _ => None,
+        })
+    }
+    /// How many CD or GD-ROM tracks the CHD has (0 for DVDs and hard disks)
+    ///
+    pub fn track_count(&self) -> usize {
+        self.tracks().count()
+    }
+    pub fn media(&self) -> ChdMedia {
+        if self.gdrom {
+            ChdMedia::GDROM
+        } else if self.track_count() > 0 {
+            ChdMedia::CD
+        } else if self
+            .metadata
+            .iter()
+            .any(|tag| matches!(tag, ChdMetadata::DVD))
+        {
+            ChdMedia::DVD
+        } else {
+            ChdMedia::Other
+        }
+    }
 }
 
-fn parse_sha1(regex: &Regex, input: &str) -> Option<[u8; 20]> {
-    first_match(regex, input).map(|v| {
-        let mut sha1 = [0u8; 20];
-        hex::decode_to_slice(&v.trim(), &mut sha1).unwrap();
-        sha1
+/// How many metadata entries are read before a CHD is taken to be corrupt (its entries are a linked
+/// list, which could loop)
+const MAX_METADATA_ENTRIES: usize = 1024;
+
+/// Reads a V5 CHD's layout (its tracks, compression, and hashes) directly, like `chdman info`
+///
+/// Only the header and metadata are read, so this works without chdman installed.
+pub fn info(input: &impl AsRef<Path>) -> Result<ChdInfo> {
+    let header = read_header(input)?;
+    let mut file = File::open(input).ndl("Failed to read CHD metadata")?;
+    let chd_size = file.metadata().ndl("Failed to read CHD metadata")?.len();
+    let mut metadata = Vec::new();
+    let mut gdrom = false;
+    let mut offset = header.metadata_offset;
+    while offset != 0 {
+        if metadata.len() == MAX_METADATA_ENTRIES {
+            return Err(Error::new_original(
+                "Failed to read CHD metadata\nToo many metadata entries",
+            ));
+        }
+        // tag, flags, 24-bit length, and the offset of the next entry
+        let mut entry = [0u8; 16];
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(&mut entry))
+            .ndl("Failed to read CHD metadata")?;
+        let tag = String::from_utf8_lossy(&entry[0..4]).into_owned();
+        let length = u32::from_be_bytes(entry[4..8].try_into().unwrap()) & 0xFF_FFFF;
+        offset = u64::from_be_bytes(entry[8..16].try_into().unwrap());
+        let mut data = vec![0u8; length as usize];
+        file.read_exact(&mut data)
+            .ndl("Failed to read CHD metadata")?;
+        let data = String::from_utf8_lossy(&data)
+            .trim_end_matches('\0')
+            .to_string();
+        match tag.as_str() {
+            "CHTR" | "CHT2" | "CHGD" => {
+                gdrom |= tag == "CHGD";
+                metadata.push(ChdMetadata::Track(parse_track(&data)?));
+            }
+            "DVD " => metadata.push(ChdMetadata::DVD),
+            _ => metadata.push(ChdMetadata::Other { tag, data }),
+        }
+    }
+    Ok(ChdInfo {
+        logical_size: header.logical_size,
+        chd_size,
+        compression: header.compression,
+        sha1: header.sha1,
+        data_sha1: header.raw_sha1,
+        metadata,
+        gdrom,
     })
 }
 
-pub fn info(input: &impl AsRef<str>) -> Result<InfoV5> {
-    let output = Command::new("chdman")
-        .arg("info")
-        .arg("-i")
-        .arg(input.as_ref())
-        .output()
-        .ndl("Failed to get info on CHD")?;
-    let content = std::str::from_utf8(&output.stdout).unwrap();
-    let compression: Vec<Codec> = {
-        let comp_str: String = first_match(regex!(r"(?<=Compression:)\s+\w[^\n]+"), content)
-            .ndl("Failed to parse V5 CHD info")?;
-        comp_str
-            .trim()
-            .split(", ")
-            .map(|v| first_match(regex!(r"^\w+"), v).unwrap())
-            .map(|v| Codec::from_string(&v))
-            .collect()
+/// Parses a track's metadata, like "TRACK:1 TYPE:MODE2_RAW SUBTYPE:NONE FRAMES:1000 ..."
+///
+fn parse_track(data: &str) -> Result<ChdTrack> {
+    let field = |name: &str| {
+        data.split_whitespace()
+            .find_map(|pair| pair.strip_prefix(name)?.strip_prefix(':'))
     };
-    let metadata: Vec<Tag> = {
-        let total_metadata: String = first_match(regex!(r"(?<=Metadata:)[\s\S]+"), content)
-            .ndl("Failed to parse V5 CHD info")?;
-        let total_meta_lines: Vec<&str> = total_metadata
-            .trim()
-            .split("\n")
-            .map(|v| v.trim())
-            .collect();
-        let mut metadata: Vec<Tag> = Vec::with_capacity(total_meta_lines.len() / 2);
-        for i in (0..total_meta_lines.len()).step_by(2) {
-            let line = total_meta_lines.get(i + 1).unwrap();
-            if total_meta_lines.get(i).unwrap().contains("Tag='CHT2'") {
-                metadata.push(Tag::CHT2 {
-                    track: u8::from_str_radix(
-                        &first_match(regex!(r"(?<=TRACK:)\d+"), line)
-                            .ndl("Failed to parse V5 CHD info")?,
-                        10,
-                    )
-                    .unwrap(),
-                    track_type: TrackType::from_str(
-                        &first_match(regex!(r"(?<= TYPE:)\w+"), line)
-                            .ndl("Failed to parse V5 CHD info")?,
-                    )
-                    .unwrap(),
-                })
+    let invalid = format!("Failed to read CHD metadata\nInvalid track: {data}");
+    Ok(ChdTrack {
+        number: field("TRACK")
+            .and_then(|number| number.parse().ok())
+            .ndl(&invalid)?,
+        track_type: field("TYPE").and_then(TrackType::from_str).ndl(&invalid)?,
+        frames: field("FRAMES")
+            .and_then(|frames| frames.parse().ok())
+            .ndl(&invalid)?,
+    })
+}
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// Writes a CHD with the given metadata entries, but no hunks
+    fn write_chd(metadata: &[(&[u8; 4], &str)]) -> tempfile::NamedTempFile {
+        let mut chd = vec![0u8; 124];
+        chd[0..8].copy_from_slice(b"MComprHD");
+        chd[8..12].copy_from_slice(&124u32.to_be_bytes());
+        chd[12..16].copy_from_slice(&5u32.to_be_bytes());
+        chd[16..20].copy_from_slice(b"cdlz");
+        chd[20..24].copy_from_slice(b"cdfl");
+        chd[32..40].copy_from_slice(&1_234_567u64.to_be_bytes());
+        chd[64..84].fill(0xAB);
+        if !metadata.is_empty() {
+            chd[48..56].copy_from_slice(&124u64.to_be_bytes());
+        }
+        for (i, (tag, data)) in metadata.iter().enumerate() {
+            let data = format!("{data}\0");
+            let next = if i + 1 == metadata.len() {
+                0
             } else {
-                metadata.push(Tag::Other(line.to_string()));
-            }
+                (chd.len() + 16 + data.len()) as u64
+            };
+            chd.extend_from_slice(*tag);
+            chd.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            chd.extend_from_slice(&next.to_be_bytes());
+            chd.extend_from_slice(data.as_bytes());
         }
-        metadata
-    };
-    Ok(InfoV5 {
-        logical_size: parse_usize(regex!(r"(?<=Logical size:)\s+[\d,]+(?= bytes)"), content)
-            .unwrap(),
-        chd_size: parse_usize(regex!(r"(?<=CHD size:)\s+[\d,]+(?= bytes)"), content).unwrap(),
-        sha1: parse_sha1(regex!(r"(?<=\nSHA1:)\s+[\da-fA-F]{40}"), content).unwrap(),
-        data_sha1: parse_sha1(regex!(r"(?<=Data SHA1:)\s+[\da-fA-F]{40}"), content).unwrap(),
-        compression,
-        metadata,
-    })
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&chd).unwrap();
+        file
+    }
+
+    #[test]
+    fn reads_track_layout() {
+        let cd = write_chd(&[
+            (
+                b"CHT2",
+                "TRACK:1 TYPE:MODE2_RAW SUBTYPE:NONE FRAMES:1000 PREGAP:0 PGTYPE:MODE1 PGSUB:RW POSTGAP:0",
+            ),
+            (
+                b"CHT2",
+                "TRACK:2 TYPE:AUDIO SUBTYPE:NONE FRAMES:500 PREGAP:150 PGTYPE:MODE1 PGSUB:RW POSTGAP:0",
+            ),
+        ]);
+        let info = info_of(&cd);
+        assert_eq!(info.media(), ChdMedia::CD);
+        assert_eq!(info.logical_size(), 1_234_567);
+        assert_eq!(info.data_sha1(), [0xAB; 20]);
+        assert_eq!(info.compression(), [Codec::CDLZ, Codec::CDFL]);
+        let tracks: Vec<_> = info
+            .tracks()
+            .map(|track| (track.number(), track.track_type(), track.size()))
+            .collect();
+        assert_eq!(
+            tracks,
+            [
+                (1, TrackType::Mode2Raw, 2_352_000),
+                (2, TrackType::Audio, 1_176_000)
+            ]
+        );
+
+        let gdrom = write_chd(&[(
+            b"CHGD",
+            "TRACK:1 TYPE:MODE1 SUBTYPE:NONE FRAMES:300 PAD:0 PREGAP:0 PGTYPE:MODE1 PGSUB:NONE POSTGAP:0",
+        )]);
+        assert_eq!(info_of(&gdrom).media(), ChdMedia::GDROM);
+
+        let dvd = info_of(&write_chd(&[(b"DVD ", "")]));
+        assert_eq!(dvd.media(), ChdMedia::DVD);
+        assert_eq!(dvd.track_count(), 0);
+        assert_eq!(info_of(&write_chd(&[])).media(), ChdMedia::Other);
+    }
+
+    fn info_of(chd: &tempfile::NamedTempFile) -> ChdInfo {
+        info(&chd.path()).unwrap()
+    }
 }
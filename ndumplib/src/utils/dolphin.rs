@@ -0,0 +1,136 @@
+use super::tool::ExternalTool;
+use crate::{
+    Error, Result,
+    dump_manager::timings::{self, Stage},
+};
+
+/// A GameCube or Wii disc image format dolphin-tool can convert to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiscFormat {
+    ISO,
+    GCZ,
+    WIA,
+    RVZ,
+}
+
+impl DiscFormat {
+    /// The format's name, as dolphin-tool takes it (which is also its extension)
+    ///
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ISO => "iso",
+            Self::GCZ => "gcz",
+            Self::WIA => "wia",
+            Self::RVZ => "rvz",
+        }
+    }
+    /// The options Dolphin itself converts to the format with
+    ///
+    pub fn default_options(self) -> ConvertOptions {
+        let (block_size, compression) = match self {
+            Self::ISO => (None, None),
+            Self::GCZ => (Some(32 * 1024), None),
+            Self::WIA => (Some(2 * 1024 * 1024), Some(DiscCompression::Lzma2)),
+            Self::RVZ => (Some(128 * 1024), Some(DiscCompression::Zstd)),
+        };
+        ConvertOptions {
+            format: self,
+            block_size,
+            compression,
+            compression_level: compression.map(|_| 5),
+        }
+    }
+}
+
+/// How WIA and RVZ images are compressed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiscCompression {
+    None,
+    Zstd,
+    Bzip2,
+    Lzma,
+    Lzma2,
+}
+
+impl DiscCompression {
+    fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Zstd => "zstd",
+            Self::Bzip2 => "bzip",
+            Self::Lzma => "lzma",
+            Self::Lzma2 => "lzma2",
+        }
+    }
+}
+
+pub struct ConvertOptions {
+    pub format: DiscFormat,
+    pub block_size: Option<usize>,
+    /// Only for WIA and RVZ
+    pub compression: Option<DiscCompression>,
+    pub compression_level: Option<u32>,
+}
+
+pub fn convert(
+    input: &impl AsRef<str>,
+    output: &impl AsRef<str>,
+    options: ConvertOptions,
+) -> Result<()> {
+    let mut command = ExternalTool::DolphinTool.command();
+    command
+        .arg("convert")
+        .arg("-i")
+        .arg(input.as_ref())
+        .arg("-o")
+        .arg(output.as_ref())
+        .arg("-f")
+        .arg(options.format.name());
+    if let Some(block_size) = options.block_size {
+        command.arg("-b").arg(block_size.to_string());
+    }
+    if let Some(compression) = options.compression {
+        command.arg("-c").arg(compression.name());
+    }
+    if let Some(compression_level) = options.compression_level {
+        command.arg("-l").arg(compression_level.to_string());
+    }
+    let output = timings::time(Stage::Conversion, || {
+        ExternalTool::DolphinTool.run(&mut command, "Failed to convert disc image")
+    })?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(ExternalTool::DolphinTool.failure("Failed to convert disc image", &output))
+    }
+}
+
+/// Hashes the disc a GameCube or Wii image holds, which is what datafiles list, whatever format
+/// it's stored in
+///
+pub fn verify(input: &impl AsRef<str>) -> Result<[u8; 20]> {
+    let output = timings::time(Stage::Hashing, || {
+        ExternalTool::DolphinTool.run(
+            ExternalTool::DolphinTool
+                .command()
+                .arg("verify")
+                .arg("-i")
+                .arg(input.as_ref())
+                .arg("-a")
+                .arg("sha1"),
+            "Failed to verify disc image",
+        )
+    })?;
+    if !output.status.success() {
+        return Err(ExternalTool::DolphinTool.failure("Failed to verify disc image", &output));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut sha1 = [0u8; 20];
+    match hex::decode_to_slice(stdout.trim(), &mut sha1) {
+        Ok(()) => Ok(sha1),
+        Err(_) => Err(Error::new_original(format!(
+            "Failed to verify disc image\nUnexpected output: {}",
+            stdout.trim()
+        ))),
+    }
+}
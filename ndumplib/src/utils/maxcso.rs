@@ -0,0 +1,108 @@
+use std::{fs::File, io::Read, path::Path, process::Command};
+
+use super::tool::ExternalTool;
+use crate::{
+    Result, ResultUtils,
+    dump_manager::timings::{self, Stage},
+};
+
+/// A compressed ISO format maxcso can create, for PSP and PS2 discs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsoFormat {
+    /// CSO v1, which every emulator reads
+    CSO1,
+    /// CSO v2, which only newer emulators read
+    CSO2,
+    /// LZ4-compressed, which decompresses faster
+    ZSO,
+}
+
+impl CsoFormat {
+    /// The format's name, as maxcso takes it
+    ///
+    fn name(self) -> &'static str {
+        match self {
+            Self::CSO1 => "cso1",
+            Self::CSO2 => "cso2",
+            Self::ZSO => "zso",
+        }
+    }
+    /// The extension of files in the format
+    ///
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::CSO1 | Self::CSO2 => "cso",
+            Self::ZSO => "zso",
+        }
+    }
+}
+
+/// How maxcso compresses and decompresses, where the defaults aren't wanted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CsoOptions {
+    /// The size of each compressed block, in bytes (a power of 2; maxcso picks one by default)
+    pub block_size: Option<usize>,
+    /// How many threads maxcso uses (by default, one per core)
+    pub threads: Option<usize>,
+}
+
+impl CsoOptions {
+    fn apply(&self, command: &mut Command) {
+        if let Some(block_size) = self.block_size {
+            command.arg(format!("--block={block_size}"));
+        }
+        if let Some(threads) = self.threads {
+            command.arg(format!("--threads={threads}"));
+        }
+    }
+}
+
+pub fn compress(
+    input: &impl AsRef<str>,
+    output: &impl AsRef<str>,
+    format: CsoFormat,
+    options: CsoOptions,
+) -> Result<()> {
+    let mut command = ExternalTool::Maxcso.command();
+    command.arg(format!("--format={}", format.name()));
+    options.apply(&mut command);
+    command.arg(input.as_ref()).arg("-o").arg(output.as_ref());
+    let output = timings::time(Stage::Conversion, || {
+        ExternalTool::Maxcso.run(&mut command, "Failed to compress ISO")
+    })?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(ExternalTool::Maxcso.failure("Failed to compress ISO", &output))
+    }
+}
+
+/// Reads the size of the ISO a CSO or ZSO holds from its header
+pub fn uncompressed_size(input: &impl AsRef<Path>) -> Result<u64> {
+    let mut header = [0u8; 16];
+    File::open(input.as_ref())
+        .and_then(|mut file| file.read_exact(&mut header))
+        .ndl("Failed to read CSO header")?;
+    Ok(u64::from_le_bytes(header[8..16].try_into().unwrap()))
+}
+
+pub fn decompress(
+    input: &impl AsRef<str>,
+    output: &impl AsRef<str>,
+    options: CsoOptions,
+) -> Result<()> {
+    let mut command = ExternalTool::Maxcso.command();
+    command.arg("--decompress");
+    if let Some(threads) = options.threads {
+        command.arg(format!("--threads={threads}"));
+    }
+    command.arg(input.as_ref()).arg("-o").arg(output.as_ref());
+    let output = timings::time(Stage::Conversion, || {
+        ExternalTool::Maxcso.run(&mut command, "Failed to decompress ISO")
+    })?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(ExternalTool::Maxcso.failure("Failed to decompress ISO", &output))
+    }
+}
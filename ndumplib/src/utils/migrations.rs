@@ -0,0 +1,158 @@
+use log::debug;
+use rusqlite::{Connection, Transaction};
+
+use crate::{Error, Result, ResultUtils};
+
+/// A single step in upgrading a database's schema
+pub(crate) struct Migration {
+    pub description: &'static str,
+    pub apply: fn(&Transaction) -> Result<()>,
+}
+
+/// Reads a database's schema version
+///
+pub(crate) fn schema_version(connection: &Connection) -> Result<usize> {
+    let version: i64 = connection
+        .pragma_query_value(None, "user_version", |row| row.get(0))
+        .ndl("Failed to read schema version")?;
+    Ok(version as usize)
+}
+
+/// Adds the table recording when a database was last maintained (see
+/// [crate::utils::maintain_database]), which every database has
+pub(crate) fn create_maintenance_table(transaction: &Transaction) -> Result<()> {
+    transaction
+        .execute_batch(
+            r#"
+                CREATE TABLE "maintenance" (
+                    "id"	INTEGER NOT NULL CHECK ("id" = 0),
+                    "last_maintained"	INTEGER NOT NULL,
+                    PRIMARY KEY("id")
+                );
+            "#,
+        )
+        .ndl("Failed to add maintenance table")
+}
+
+/// Brings a database's schema up to date, returning whether any migrations were applied
+///
+/// The schema version is kept in SQLite's `user_version` pragma. `migrations[i]` upgrades a database
+/// from version `i` to `i + 1`, and each one runs in its own transaction.
+pub(crate) fn run_migrations(
+    connection: &mut Connection,
+    database_name: &str,
+    migrations: &[Migration],
+) -> Result<bool> {
+    let version = schema_version(connection)?;
+    if version > migrations.len() {
+        return Err(Error::new_original(format!(
+            "Failed to open {database_name} DB\nSchema version {version} is newer than this version of ndumpmgr supports ({})",
+            migrations.len()
+        )));
+    }
+    for (index, migration) in migrations.iter().enumerate().skip(version) {
+        let transaction = connection
+            .transaction()
+            .ndl(format!("Failed to migrate {database_name} DB"))?;
+        (migration.apply)(&transaction)?;
+        transaction
+            .pragma_update(None, "user_version", (index + 1) as i64)
+            .ndl(format!("Failed to migrate {database_name} DB"))?;
+        transaction
+            .commit()
+            .ndl(format!("Failed to migrate {database_name} DB"))?;
+        debug!(
+            "Migrated {database_name} DB to schema version {}: {}",
+            index + 1,
+            migration.description
+        );
+    }
+    Ok(version < migrations.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_numbers(transaction: &Transaction) -> Result<()> {
+        transaction
+            .execute("CREATE TABLE numbers (value INTEGER NOT NULL)", ())
+            .ndl("create")?;
+        Ok(())
+    }
+
+    fn add_label(transaction: &Transaction) -> Result<()> {
+        transaction
+            .execute("ALTER TABLE numbers ADD COLUMN label TEXT", ())
+            .ndl("alter")?;
+        Ok(())
+    }
+
+    fn fail(_: &Transaction) -> Result<()> {
+        Err(Error::new_original("fail"))
+    }
+
+    const MIGRATIONS: [Migration; 2] = [
+        Migration {
+            description: "create numbers",
+            apply: create_numbers,
+        },
+        Migration {
+            description: "add label",
+            apply: add_label,
+        },
+    ];
+
+    #[test]
+    fn migrates_fresh_database_to_latest() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        assert!(run_migrations(&mut connection, "test", &MIGRATIONS).unwrap());
+        assert_eq!(schema_version(&connection).unwrap(), 2);
+        connection
+            .execute("INSERT INTO numbers (value, label) VALUES (1, 'one')", ())
+            .unwrap();
+        // running again is a no-op
+        assert!(!run_migrations(&mut connection, "test", &MIGRATIONS).unwrap());
+    }
+
+    #[test]
+    fn migrates_forward_from_older_version() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        run_migrations(&mut connection, "test", &MIGRATIONS[..1]).unwrap();
+        connection
+            .execute("INSERT INTO numbers (value) VALUES (7)", ())
+            .unwrap();
+        assert!(run_migrations(&mut connection, "test", &MIGRATIONS).unwrap());
+        let (value, label): (i64, Option<String>) = connection
+            .query_row("SELECT value, label FROM numbers", (), |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(value, 7);
+        assert_eq!(label, None);
+    }
+
+    #[test]
+    fn failed_migration_rolls_back() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        let migrations = [
+            Migration {
+                description: "create numbers",
+                apply: create_numbers,
+            },
+            Migration {
+                description: "fail",
+                apply: fail,
+            },
+        ];
+        assert!(run_migrations(&mut connection, "test", &migrations).is_err());
+        assert_eq!(schema_version(&connection).unwrap(), 1);
+    }
+
+    #[test]
+    fn rejects_newer_schema() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        run_migrations(&mut connection, "test", &MIGRATIONS).unwrap();
+        assert!(run_migrations(&mut connection, "test", &MIGRATIONS[..1]).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    fs::File,
+    io::{ErrorKind, Read},
+    path::Path,
+    sync::{
+        Arc,
+        mpsc::{self, SyncSender},
+    },
+};
+
+use md5::Md5;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use crate::{
+    FileHash, HashAlgorithm, Result, ResultUtils,
+    dump_manager::timings::{Stage, StageTimer},
+    hashing,
+};
+
+/// How many buffers a threaded [MultiHasher] reads ahead into while the hashers catch up
+const RING_SIZE: usize = 4;
+
+/// One of a [MultiHasher]'s algorithms, partway through the data
+enum Hasher {
+    CRC32(crc32fast::Hasher),
+    MD5(Md5),
+    SHA1(Sha1),
+    SHA256(Sha256),
+}
+
+impl Hasher {
+    fn new(algorithm: HashAlgorithm) -> Hasher {
+        match algorithm {
+            HashAlgorithm::CRC32 => Self::CRC32(crc32fast::Hasher::new()),
+            HashAlgorithm::MD5 => Self::MD5(Md5::new()),
+            HashAlgorithm::SHA1 => Self::SHA1(Sha1::new()),
+            HashAlgorithm::SHA256 => Self::SHA256(Sha256::new()),
+        }
+    }
+
+    fn algorithm(&self) -> HashAlgorithm {
+        match self {
+            Self::CRC32(_) => HashAlgorithm::CRC32,
+            Self::MD5(_) => HashAlgorithm::MD5,
+            Self::SHA1(_) => HashAlgorithm::SHA1,
+            Self::SHA256(_) => HashAlgorithm::SHA256,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::CRC32(hasher) => hasher.update(data),
+            Self::MD5(hasher) => hasher.update(data),
+            Self::SHA1(hasher) => hasher.update(data),
+            Self::SHA256(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finalize(self) -> FileHash {
+        match self {
+            Self::CRC32(hasher) => FileHash::CRC32(hasher.finalize()),
+            Self::MD5(hasher) => FileHash::MD5(hasher.finalize().into()),
+            Self::SHA1(hasher) => FileHash::SHA1(hasher.finalize().into()),
+            Self::SHA256(hasher) => FileHash::SHA256(hasher.finalize().into()),
+        }
+    }
+}
+
+/// The hashes a [MultiHasher] computed, with the size of the data
+///
+/// Hashes which weren't asked for are `None`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Digests {
+    pub size: u64,
+    pub crc32: Option<u32>,
+    pub md5: Option<[u8; 16]>,
+    pub sha1: Option<[u8; 20]>,
+    pub sha256: Option<[u8; 32]>,
+}
+
+impl Digests {
+    /// The hash computed with the given algorithm, if it was
+    ///
+    pub fn get(&self, algorithm: HashAlgorithm) -> Option<FileHash> {
+        match algorithm {
+            HashAlgorithm::CRC32 => self.crc32.map(FileHash::CRC32),
+            HashAlgorithm::MD5 => self.md5.map(FileHash::MD5),
+            HashAlgorithm::SHA1 => self.sha1.map(FileHash::SHA1),
+            HashAlgorithm::SHA256 => self.sha256.map(FileHash::SHA256),
+        }
+    }
+}
+
+/// Computes several hashes of the same data in one pass, so each extra hash costs CPU time, not
+/// another read of the file
+///
+/// ```
+/// use ndumplib::{HashAlgorithm, MultiHasher};
+///
+/// let mut hasher = MultiHasher::new(&[HashAlgorithm::CRC32, HashAlgorithm::SHA1]);
+/// hasher.update(b"test");
+/// let digests = hasher.finalize();
+/// assert_eq!(digests.size, 4);
+/// assert_eq!(digests.crc32, Some(0xd87f7e0c));
+/// assert_eq!(digests.md5, None);
+/// ```
+pub struct MultiHasher {
+    hashers: Vec<Hasher>,
+    size: u64,
+}
+
+/// A buffer a threaded [MultiHasher] shares between its hashers, which goes back to the ring once
+/// they've all hashed it
+struct Chunk {
+    data: Vec<u8>,
+    length: usize,
+    ring: SyncSender<Vec<u8>>,
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        // the ring has room for every buffer, so this never blocks
+        let _ = self.ring.send(std::mem::take(&mut self.data));
+    }
+}
+
+impl MultiHasher {
+    /// Starts hashing with the given algorithms
+    ///
+    pub fn new(algorithms: &[HashAlgorithm]) -> MultiHasher {
+        let mut hashers: Vec<Hasher> = Vec::with_capacity(algorithms.len());
+        for &algorithm in algorithms {
+            if !hashers.iter().any(|hasher| hasher.algorithm() == algorithm) {
+                hashers.push(Hasher::new(algorithm));
+            }
+        }
+        MultiHasher { hashers, size: 0 }
+    }
+
+    /// Starts hashing with every algorithm (see [HashAlgorithm::ALL])
+    ///
+    pub fn all() -> MultiHasher {
+        MultiHasher::new(&HashAlgorithm::ALL)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for hasher in &mut self.hashers {
+            hasher.update(data);
+        }
+        self.size += data.len() as u64;
+    }
+
+    pub fn finalize(self) -> Digests {
+        let mut digests = Digests {
+            size: self.size,
+            ..Default::default()
+        };
+        for hasher in self.hashers {
+            match hasher.finalize() {
+                FileHash::CRC32(crc32) => digests.crc32 = Some(crc32),
+                FileHash::MD5(md5) => digests.md5 = Some(md5),
+                FileHash::SHA1(sha1) => digests.sha1 = Some(sha1),
+                FileHash::SHA256(sha256) => digests.sha256 = Some(sha256),
+            }
+        }
+        digests
+    }
+
+    /// Hashes the rest of a file's contents, then finishes
+    ///
+    /// With `threaded`, each algorithm runs on its own thread, fed from a ring of buffers the file
+    /// is read into (which is only worth it for large files and slow hashes like SHA-256).
+    /// Otherwise, the file is read as set by [crate::set_hashing_options].
+    pub fn hash_file(mut self, path: &impl AsRef<Path>, threaded: bool) -> Result<Digests> {
+        let _timer = StageTimer::start(Stage::Hashing);
+        let path = path.as_ref();
+        if !threaded || self.hashers.len() < 2 {
+            hashing::hash_file(path, |chunk| self.update(chunk)).ndl("Failed to hash file")?;
+            return Ok(self.finalize());
+        }
+        let mut file = File::open(path).ndl("Failed to hash file")?;
+        let buffer_size = hashing::hashing_options().buffer_size.max(4096);
+        let (ring, free_buffers) = mpsc::sync_channel(RING_SIZE);
+        for _ in 0..RING_SIZE {
+            ring.send(vec![0u8; buffer_size]).unwrap();
+        }
+        let hashers = std::mem::take(&mut self.hashers);
+        let (read, hashers) = std::thread::scope(|scope| {
+            let (senders, workers): (Vec<_>, Vec<_>) = hashers
+                .into_iter()
+                .map(|mut hasher| {
+                    let (sender, chunks) = mpsc::sync_channel::<Arc<Chunk>>(RING_SIZE);
+                    let worker = scope.spawn(move || {
+                        for chunk in chunks {
+                            hasher.update(&chunk.data[..chunk.length]);
+                        }
+                        hasher
+                    });
+                    (sender, worker)
+                })
+                .unzip();
+            let read = (|| {
+                let mut size = 0;
+                loop {
+                    let mut data = free_buffers.recv().unwrap();
+                    let length = match file.read(&mut data) {
+                        Ok(0) => return Ok(size),
+                        Ok(length) => length,
+                        Err(err) if err.kind() == ErrorKind::Interrupted => {
+                            ring.send(data).unwrap();
+                            continue;
+                        }
+                        Err(err) => return Err(err),
+                    };
+                    size += length as u64;
+                    let chunk = Arc::new(Chunk {
+                        data,
+                        length,
+                        ring: ring.clone(),
+                    });
+                    for sender in &senders {
+                        sender.send(chunk.clone()).unwrap();
+                    }
+                }
+            })();
+            // the hashers stop once there's nothing more to receive
+            drop(senders);
+            let hashers: Vec<Hasher> = workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .collect();
+            (read, hashers)
+        });
+        self.size += read.ndl("Failed to hash file")?;
+        self.hashers = hashers;
+        Ok(self.finalize())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_on_threads_like_in_one() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("dump.bin");
+        // several buffers' worth, so the ring goes round
+        let contents: Vec<u8> = (0..5_000_000u32).map(|byte| (byte % 251) as u8).collect();
+        std::fs::write(&path, &contents).unwrap();
+        let mut hasher = MultiHasher::all();
+        hasher.update(&contents);
+        let expected = hasher.finalize();
+        assert_eq!(expected.size, 5_000_000);
+        assert_eq!(expected.sha1, Some(Sha1::digest(&contents).into()));
+        assert_eq!(expected.sha256, Some(Sha256::digest(&contents).into()));
+        assert_eq!(expected.crc32, Some(crc32fast::hash(&contents)));
+        for threaded in [false, true] {
+            let digests = MultiHasher::all().hash_file(&path, threaded).unwrap();
+            assert_eq!(digests, expected);
+        }
+        let digests = MultiHasher::new(&[HashAlgorithm::MD5, HashAlgorithm::MD5])
+            .hash_file(&path, true)
+            .unwrap();
+        assert_eq!(
+            digests.get(HashAlgorithm::MD5),
+            expected.get(HashAlgorithm::MD5)
+        );
+        assert_eq!(digests.get(HashAlgorithm::SHA1), None);
+    }
+}
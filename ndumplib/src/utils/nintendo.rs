@@ -0,0 +1,134 @@
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::{
+    ImageModification, Result, ResultUtils,
+    utils::{serial::GAMECUBE_MAGIC, wii},
+};
+
+/// NKit writes its own header at 0x200 of the disc, after the game's
+const NKIT_MAGIC: &[u8; 4] = b"NKIT";
+const NKIT_OFFSET: u64 = 0x200;
+/// The size of every full GameCube disc image
+const GAMECUBE_DISC_SIZE: u64 = 1_459_978_240;
+/// The sizes of full single and dual layer Wii disc images
+const WII_DISC_SIZES: [u64; 2] = [4_699_979_776, 8_511_160_320];
+/// Discs are read (and scrubbed) in clusters of this size
+const CLUSTER_SIZE: usize = 0x8000;
+
+/// Whether an image's name marks it as NKit, like "Game.nkit.iso" or "Game.nkit.gcz"
+///
+pub(crate) fn is_nkit_name(path: &Path) -> bool {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .is_some_and(|stem| stem.to_ascii_lowercase().ends_with(".nkit"))
+}
+
+fn read_at(image: &mut (impl Read + Seek), offset: u64, length: usize) -> Result<Option<Vec<u8>>> {
+    let mut bytes = vec![0u8; length];
+    match image
+        .seek(SeekFrom::Start(offset))
+        .and_then(|_| image.read_exact(&mut bytes))
+    {
+        Ok(()) => Ok(Some(bytes)),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err).ndl("Failed to read disc image"),
+    }
+}
+
+/// Whether a cluster holds nothing but one byte repeated, which is how scrubbers blank out data
+/// the game doesn't read
+fn is_blank(cluster: &[u8]) -> bool {
+    matches!(cluster.first(), Some(&(0x00 | 0xFF)))
+        && cluster.iter().all(|byte| *byte == cluster[0])
+}
+
+/// Tells whether a GameCube or Wii ISO was changed on purpose, so it can never match the catalog
+///
+/// NKit images are found by their header (or name). Scrubbed images are found by being trimmed
+/// short of a full disc, or by their last cluster being blanked: on real discs it's padding the
+/// mastering filled with junk on GameCube, and encrypted data on Wii, neither of which is ever
+/// uniform. Returns `None` for other images, including those of other consoles.
+pub(crate) fn detect_modification(
+    image: &mut (impl Read + Seek),
+    path: &Path,
+) -> Result<Option<ImageModification>> {
+    let Some(header) = read_at(image, 0, 0x20)? else {
+        return Ok(None);
+    };
+    let magic_at =
+        |offset: usize| u32::from_be_bytes(header[offset..offset + 4].try_into().unwrap());
+    let is_wii = magic_at(0x18) == wii::WII_MAGIC;
+    if !is_wii && magic_at(0x1C) != GAMECUBE_MAGIC {
+        return Ok(None);
+    }
+    if is_nkit_name(path)
+        || read_at(image, NKIT_OFFSET, 4)?.is_some_and(|magic| magic == NKIT_MAGIC)
+    {
+        return Ok(Some(ImageModification::NKit));
+    }
+    let size = image
+        .seek(SeekFrom::End(0))
+        .ndl("Failed to read disc image")?;
+    let full_size = if is_wii {
+        WII_DISC_SIZES.contains(&size)
+    } else {
+        size == GAMECUBE_DISC_SIZE
+    };
+    if !full_size {
+        return Ok(Some(ImageModification::Scrubbed));
+    }
+    let last_cluster_end = if is_wii {
+        match wii::data_partition_range(image)? {
+            Some((_, end)) => end.min(size),
+            None => return Ok(None),
+        }
+    } else {
+        size
+    };
+    let Some(offset) = last_cluster_end.checked_sub(CLUSTER_SIZE as u64) else {
+        return Ok(None);
+    };
+    Ok(read_at(image, offset, CLUSTER_SIZE)?
+        .filter(|cluster| is_blank(cluster))
+        .map(|_| ImageModification::Scrubbed))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn detects_nkit_and_scrubbed_images() {
+        let mut header = vec![0u8; 0x400];
+        header[0x1C..0x20].copy_from_slice(&GAMECUBE_MAGIC.to_be_bytes());
+        let path = Path::new("Game (USA).iso");
+        // anything short of a full disc was trimmed
+        assert_eq!(
+            detect_modification(&mut Cursor::new(&header), path).unwrap(),
+            Some(ImageModification::Scrubbed)
+        );
+        assert_eq!(
+            detect_modification(&mut Cursor::new(&header), Path::new("Game.nkit.iso")).unwrap(),
+            Some(ImageModification::NKit)
+        );
+        header[0x200..0x204].copy_from_slice(NKIT_MAGIC);
+        assert_eq!(
+            detect_modification(&mut Cursor::new(&header), path).unwrap(),
+            Some(ImageModification::NKit)
+        );
+        header[0x1C..0x20].fill(0);
+        assert_eq!(
+            detect_modification(&mut Cursor::new(&header), path).unwrap(),
+            None
+        );
+
+        assert!(is_blank(&[0xFF; 16]));
+        assert!(!is_blank(&[0x00, 0x00, 0x01]));
+        assert!(!is_blank(&[0x42; 16]));
+    }
+}
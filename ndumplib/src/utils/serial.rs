@@ -0,0 +1,386 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::{
+    DiscSerial, GameConsole, Result, ResultUtils,
+    utils::{wii::WII_MAGIC, xbox360},
+};
+
+/// Identifies GameCube discs, at offset 0x1C of the disc header
+pub(crate) const GAMECUBE_MAGIC: u32 = 0xC2339F3D;
+/// Identifies an Xbox DVD filesystem, in its 32nd sector
+const XBOX_MEDIA_MAGIC: &[u8; 20] = b"MICROSOFT*XBOX*MEDIA";
+/// Where the game partition starts: at 0 in extracted images, after the video partition in full
+/// (Redump) ones, which is further in for original Xbox discs (XGD1) than for Xbox 360 ones (XGD2
+/// and XGD3)
+const XBOX_PARTITION_OFFSETS: [u64; 4] = [0, 0x18300000, 0xFD90000, 0x2080000];
+/// The sync pattern starting every raw (2352 byte) CD sector
+const RAW_SECTOR_SYNC: [u8; 12] = [
+    0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00,
+];
+const SECTOR_SIZE: usize = 2048;
+const RAW_SECTOR_SIZE: u64 = 2352;
+/// SYSTEM.CNF is a few lines, so anything bigger is probably something else
+const MAX_SYSTEM_CNF_SIZE: usize = 0x1000;
+
+/// Reads `length` bytes at `offset`, or returns `None` if the image is too short
+pub(crate) fn read_at(
+    image: &mut (impl Read + Seek),
+    offset: u64,
+    length: usize,
+) -> Result<Option<Vec<u8>>> {
+    let mut bytes = vec![0u8; length];
+    match image
+        .seek(SeekFrom::Start(offset))
+        .and_then(|_| image.read_exact(&mut bytes))
+    {
+        Ok(()) => Ok(Some(bytes)),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err).ndl("Failed to read disc image"),
+    }
+}
+
+fn u32_le(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Reads an image's 2048 byte sectors, whether it's an ISO or a raw (MODE1/MODE2) CD track
+pub(crate) struct SectorReader<'a, R: Read + Seek> {
+    image: &'a mut R,
+    sector_size: u64,
+    /// Where the user data starts within each sector
+    data_offset: u64,
+}
+
+impl<'a, R: Read + Seek> SectorReader<'a, R> {
+    pub(crate) fn new(image: &'a mut R) -> Result<Self> {
+        let (sector_size, data_offset) = match read_at(image, 0, 16)? {
+            Some(header) if header[..12] == RAW_SECTOR_SYNC => {
+                // MODE2 sectors have an 8 byte subheader after the 16 byte sync and header
+                (RAW_SECTOR_SIZE, if header[15] == 2 { 24 } else { 16 })
+            }
+            _ => (SECTOR_SIZE as u64, 0),
+        };
+        Ok(SectorReader {
+            image,
+            sector_size,
+            data_offset,
+        })
+    }
+
+    /// Reads `length` bytes of user data starting at sector `lba`
+    pub(crate) fn read(&mut self, lba: u32, length: usize) -> Result<Option<Vec<u8>>> {
+        let mut data = Vec::with_capacity(length);
+        let mut sector = lba as u64;
+        while data.len() < length {
+            let chunk = (length - data.len()).min(SECTOR_SIZE);
+            let offset = sector * self.sector_size + self.data_offset;
+            let Some(bytes) = read_at(self.image, offset, chunk)? else {
+                return Ok(None);
+            };
+            data.extend_from_slice(&bytes);
+            sector += 1;
+        }
+        Ok(Some(data))
+    }
+}
+
+/// Reads the serial of a GameCube or Wii disc, the 6 character game ID starting its header
+fn nintendo_serial(image: &mut (impl Read + Seek)) -> Result<Option<DiscSerial>> {
+    let Some(header) = read_at(image, 0, 0x20)? else {
+        return Ok(None);
+    };
+    let magic_at =
+        |offset: usize| u32::from_be_bytes(header[offset..offset + 4].try_into().unwrap());
+    let console = if magic_at(0x18) == WII_MAGIC {
+        GameConsole::Wii
+    } else if magic_at(0x1C) == GAMECUBE_MAGIC {
+        GameConsole::GameCube
+    } else {
+        return Ok(None);
+    };
+    let id = &header[..6];
+    if !id.iter().all(u8::is_ascii_alphanumeric) {
+        return Ok(None);
+    }
+    Ok(Some(DiscSerial {
+        console,
+        serial: String::from_utf8(id.to_vec()).unwrap(),
+    }))
+}
+
+/// Formats an XBE or XEX title ID like its disc's label: 0x4D530004 becomes "MS-004"
+pub(crate) fn format_xbox_title_id(title_id: u32) -> String {
+    let publisher = [(title_id >> 24) as u8, (title_id >> 16) as u8];
+    if publisher.iter().all(u8::is_ascii_alphanumeric) {
+        format!(
+            "{}{}-{:03}",
+            publisher[0] as char,
+            publisher[1] as char,
+            title_id & 0xFFFF
+        )
+    } else {
+        format!("{title_id:08X}")
+    }
+}
+
+/// Reads the title ID of an Xbox or Xbox 360 disc, from the default.xbe (or default.xex) in its
+/// root
+fn xbox_serial(image: &mut (impl Read + Seek)) -> Result<Option<DiscSerial>> {
+    for partition in XBOX_PARTITION_OFFSETS {
+        let Some(volume) = read_at(image, partition + 32 * SECTOR_SIZE as u64, 28)? else {
+            continue;
+        };
+        if &volume[..20] != XBOX_MEDIA_MAGIC {
+            continue;
+        }
+        let root_offset = partition + u32_le(&volume, 20) as u64 * SECTOR_SIZE as u64;
+        let Some(root) = read_at(image, root_offset, u32_le(&volume, 24) as usize)? else {
+            return Ok(None);
+        };
+        // the entries form a binary tree, but they're also laid out one after another, 4 byte
+        // aligned and padded with 0xFF up to the end of each sector
+        let mut position = 0;
+        let mut executable = None;
+        while position + 14 <= root.len() {
+            if root[position..position + 2] == [0xFF, 0xFF] {
+                position = (position / SECTOR_SIZE + 1) * SECTOR_SIZE;
+                continue;
+            }
+            let name_length = root[position + 13] as usize;
+            let Some(name) = root.get(position + 14..position + 14 + name_length) else {
+                break;
+            };
+            if name.eq_ignore_ascii_case(b"default.xbe")
+                || name.eq_ignore_ascii_case(b"default.xex")
+            {
+                executable = Some((u32_le(&root, position + 4), name[8].to_ascii_lowercase()));
+                break;
+            }
+            position = (position + 14 + name_length).next_multiple_of(4);
+        }
+        let Some((sector, kind)) = executable else {
+            return Ok(None);
+        };
+        let xbe_offset = partition + sector as u64 * SECTOR_SIZE as u64;
+        if kind == b'x' {
+            return Ok(
+                xbox360::xex_title_id(image, xbe_offset)?.map(|title_id| DiscSerial {
+                    console: GameConsole::Xbox360,
+                    serial: format_xbox_title_id(title_id),
+                }),
+            );
+        }
+        let Some(header) = read_at(image, xbe_offset, 0x11C)? else {
+            return Ok(None);
+        };
+        if &header[..4] != b"XBEH" {
+            return Ok(None);
+        }
+        let base_address = u32_le(&header, 0x104);
+        let Some(certificate_offset) = u32_le(&header, 0x118).checked_sub(base_address) else {
+            return Ok(None);
+        };
+        let Some(title_id) = read_at(image, xbe_offset + certificate_offset as u64 + 8, 4)? else {
+            return Ok(None);
+        };
+        return Ok(Some(DiscSerial {
+            console: GameConsole::Xbox,
+            serial: format_xbox_title_id(u32_le(&title_id, 0)),
+        }));
+    }
+    Ok(None)
+}
+
+/// Parses a SYSTEM.CNF's boot line, like "BOOT2 = cdrom0:\SLUS_200.62;1"
+///
+/// PS2 discs boot with BOOT2, PS1 discs with BOOT. The executable's name is the serial, written
+/// "SLUS_200.62" on the disc but "SLUS-20062" on its label.
+fn parse_system_cnf(content: &str) -> Option<DiscSerial> {
+    content.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        let console = match key.trim().to_uppercase().as_str() {
+            "BOOT2" => GameConsole::PS2,
+            "BOOT" => GameConsole::PSX,
+            _ => return None,
+        };
+        let executable = value.trim().rsplit(['\\', '/', ':']).next()?;
+        let executable = executable.split(';').next()?;
+        let (prefix, number) = executable.split_once(['_', '-'])?;
+        let number = number.replace('.', "");
+        if prefix.is_empty() || number.is_empty() {
+            return None;
+        }
+        Some(DiscSerial {
+            console,
+            serial: format!("{}-{}", prefix.to_uppercase(), number),
+        })
+    })
+}
+
+/// Finds a file in an image's ISO 9660 filesystem, by its path from the root with backslashes
+/// (like `PSP_GAME\SYSDIR\EBOOT.BIN`), ignoring case and version numbers
+///
+/// Returns the sector the file starts at and its size, or `None` if there's no such file (or no
+/// ISO 9660 filesystem).
+pub(crate) fn find_iso_file<R: Read + Seek>(
+    reader: &mut SectorReader<R>,
+    path: &str,
+) -> Result<Option<(u32, u32)>> {
+    let Some(volume) = reader.read(16, SECTOR_SIZE)? else {
+        return Ok(None);
+    };
+    if volume[0] != 1 || &volume[1..6] != b"CD001" {
+        return Ok(None);
+    }
+    // the root directory's record is embedded in the primary volume descriptor
+    let root = &volume[156..190];
+    let mut extent = (u32_le(root, 2), u32_le(root, 10));
+    for component in path.split('\\').filter(|component| !component.is_empty()) {
+        let Some(directory) = reader.read(extent.0, extent.1 as usize)? else {
+            return Ok(None);
+        };
+        let mut found = None;
+        let mut position = 0;
+        while position + 33 <= directory.len() {
+            let length = directory[position] as usize;
+            if length == 0 {
+                // records don't cross sectors, so the rest of this one is padding
+                position = (position / SECTOR_SIZE + 1) * SECTOR_SIZE;
+                continue;
+            }
+            let name_length = directory[position + 32] as usize;
+            let Some(name) = directory.get(position + 33..position + 33 + name_length) else {
+                break;
+            };
+            let name = name.split(|byte| *byte == b';').next().unwrap();
+            if name.eq_ignore_ascii_case(component.as_bytes()) {
+                let record = &directory[position..];
+                found = Some((u32_le(record, 2), u32_le(record, 10)));
+                break;
+            }
+            position += length;
+        }
+        let Some(found) = found else {
+            return Ok(None);
+        };
+        extent = found;
+    }
+    Ok(Some(extent))
+}
+
+/// Reads the serial of a PS1 or PS2 disc, from the SYSTEM.CNF in the root of its ISO 9660
+/// filesystem
+fn playstation_serial(image: &mut (impl Read + Seek)) -> Result<Option<DiscSerial>> {
+    let mut reader = SectorReader::new(image)?;
+    let Some((sector, size)) = find_iso_file(&mut reader, "SYSTEM.CNF")? else {
+        return Ok(None);
+    };
+    let Some(content) = reader.read(sector, (size as usize).min(MAX_SYSTEM_CNF_SIZE))? else {
+        return Ok(None);
+    };
+    Ok(parse_system_cnf(&String::from_utf8_lossy(&content)))
+}
+
+/// Reads the game serial of a disc image, if it's one of the supported consoles'
+///
+/// PS1/PS2 serials are read from SYSTEM.CNF (in ISOs or raw CD tracks), GameCube/Wii ones are
+/// the game ID in the disc header, and Xbox (360) ones come from the default.xbe's (or
+/// default.xex's) title ID.
+pub(crate) fn read_serial(image: &mut (impl Read + Seek)) -> Result<Option<DiscSerial>> {
+    if let Some(serial) = nintendo_serial(image)? {
+        return Ok(Some(serial));
+    }
+    if let Some(serial) = xbox_serial(image)? {
+        return Ok(Some(serial));
+    }
+    playstation_serial(image)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Builds a raw MODE2 image whose root directory only holds SYSTEM.CNF
+    pub(crate) fn playstation_image(system_cnf: &str) -> Vec<u8> {
+        let mut sectors = vec![[0u8; SECTOR_SIZE]; 20];
+        let volume = &mut sectors[16];
+        volume[0] = 1;
+        volume[1..6].copy_from_slice(b"CD001");
+        volume[156 + 2..156 + 6].copy_from_slice(&18u32.to_le_bytes());
+        volume[156 + 10..156 + 14].copy_from_slice(&(SECTOR_SIZE as u32).to_le_bytes());
+        let directory = &mut sectors[18];
+        let name = b"SYSTEM.CNF;1";
+        directory[0] = 33 + name.len() as u8 + 1;
+        directory[2..6].copy_from_slice(&19u32.to_le_bytes());
+        directory[10..14].copy_from_slice(&(system_cnf.len() as u32).to_le_bytes());
+        directory[32] = name.len() as u8;
+        directory[33..33 + name.len()].copy_from_slice(name);
+        sectors[19][..system_cnf.len()].copy_from_slice(system_cnf.as_bytes());
+        let mut image = Vec::new();
+        for sector in sectors {
+            image.extend_from_slice(&RAW_SECTOR_SYNC);
+            image.extend_from_slice(&[0, 2, 0, 2]);
+            image.extend_from_slice(&[0; 8]);
+            image.extend_from_slice(&sector);
+            image.extend_from_slice(&[0; 280]);
+        }
+        image
+    }
+
+    #[test]
+    fn reads_serials() {
+        let image = playstation_image("BOOT = cdrom:\\SLUS_005.94;1\r\nTCB = 4\r\n");
+        let serial = read_serial(&mut Cursor::new(&image)).unwrap().unwrap();
+        assert_eq!(serial.console, GameConsole::PSX);
+        assert_eq!(serial.serial, "SLUS-00594");
+
+        let mut image = vec![0u8; 0x20];
+        image[..6].copy_from_slice(b"GALE01");
+        image[0x1C..0x20].copy_from_slice(&GAMECUBE_MAGIC.to_be_bytes());
+        let serial = read_serial(&mut Cursor::new(&image)).unwrap().unwrap();
+        assert_eq!(serial.console, GameConsole::GameCube);
+        assert_eq!(serial.serial, "GALE01");
+
+        assert!(
+            read_serial(&mut Cursor::new(vec![0u8; 0x100]))
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(format_xbox_title_id(0x4D530004), "MS-004");
+    }
+
+    #[test]
+    fn reads_xbox_360_title_ids() {
+        // an extracted image: the volume descriptor, then the root directory, then default.xex
+        let mut image = vec![0u8; 35 * SECTOR_SIZE];
+        let volume = 32 * SECTOR_SIZE;
+        image[volume..volume + 20].copy_from_slice(XBOX_MEDIA_MAGIC);
+        image[volume + 20..volume + 24].copy_from_slice(&33u32.to_le_bytes());
+        image[volume + 24..volume + 28].copy_from_slice(&(SECTOR_SIZE as u32).to_le_bytes());
+        let root = 33 * SECTOR_SIZE;
+        image[root..root + SECTOR_SIZE].fill(0xFF);
+        image[root..root + 4].fill(0);
+        image[root + 4..root + 8].copy_from_slice(&34u32.to_le_bytes());
+        image[root + 13] = 11;
+        image[root + 14..root + 25].copy_from_slice(b"default.xex");
+        let xex = 34 * SECTOR_SIZE;
+        image[xex..xex + 4].copy_from_slice(b"XEX2");
+        image[xex + 0x14..xex + 0x18].copy_from_slice(&1u32.to_be_bytes());
+        image[xex + 0x18..xex + 0x1C].copy_from_slice(&0x00040006u32.to_be_bytes());
+        image[xex + 0x1C..xex + 0x20].copy_from_slice(&0x100u32.to_be_bytes());
+        image[xex + 0x10C..xex + 0x110].copy_from_slice(&0x4D5307E6u32.to_be_bytes());
+        let serial = read_serial(&mut Cursor::new(&image)).unwrap().unwrap();
+        assert_eq!(serial.console, GameConsole::Xbox360);
+        assert_eq!(serial.serial, "MS-2022");
+    }
+
+    #[test]
+    fn parses_ps2_boot_lines() {
+        let serial = parse_system_cnf("BOOT2 = cdrom0:\\SCES_500.51;1\nVER = 1.00\n").unwrap();
+        assert_eq!(serial.console, GameConsole::PS2);
+        assert_eq!(serial.serial, "SCES-50051");
+        assert!(parse_system_cnf("VMODE = PAL\n").is_none());
+    }
+}
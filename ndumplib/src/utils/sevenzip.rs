@@ -0,0 +1,44 @@
+use std::path::Path;
+
+use super::tool::ExternalTool;
+use crate::{
+    Result,
+    dump_manager::timings::{self, Stage},
+};
+
+/// Adds up the sizes of the files in an archive from 7-Zip's listing of it, finding its other
+/// volumes next to it
+pub fn uncompressed_size(archive: &Path) -> Result<u64> {
+    let mut command = ExternalTool::SevenZip.command();
+    command.arg("l").arg("-slt").arg(archive);
+    let output = ExternalTool::SevenZip.run(&mut command, "Failed to list archive")?;
+    if !output.status.success() {
+        return Err(ExternalTool::SevenZip.failure("Failed to list archive", &output));
+    }
+    // the archive's own details come before the separator, and its files' after
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip_while(|line| !line.starts_with("----------"))
+        .filter_map(|line| line.strip_prefix("Size = ")?.trim().parse::<u64>().ok())
+        .sum())
+}
+
+/// Extracts an archive into `directory`, finding its other volumes next to it
+///
+/// For volume sets, `archive` must be the first volume.
+pub fn extract(archive: &Path, directory: &Path) -> Result<()> {
+    let mut command = ExternalTool::SevenZip.command();
+    command
+        .arg("x")
+        .arg("-y")
+        .arg(format!("-o{}", directory.display()))
+        .arg(archive);
+    let output = timings::time(Stage::Conversion, || {
+        ExternalTool::SevenZip.run(&mut command, "Failed to extract archive")
+    })?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(ExternalTool::SevenZip.failure("Failed to extract archive", &output))
+    }
+}
@@ -0,0 +1,320 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read},
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+    str::FromStr,
+    sync::{LazyLock, Mutex},
+};
+
+use fancy_regex::Regex;
+use log::trace;
+
+use crate::{Error, ErrorCategory, Result};
+
+/// An external program ndumplib runs for what it can't do itself
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ExternalTool {
+    /// MAME's CHD manager, for creating and extracting CHDs
+    Chdman,
+    /// Dolphin's command line tool, for GameCube and Wii images
+    DolphinTool,
+    /// For compressing ISOs to CSOs and ZSOs
+    Maxcso,
+    /// 7-Zip, for extracting archives split into volumes
+    SevenZip,
+    /// For applying xdelta (VCDIFF) patches
+    Xdelta,
+}
+
+/// A tool's version, like 0.264 for chdman
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ToolVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl std::fmt::Display for ToolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.patch == 0 {
+            write!(f, "{}.{}", self.major, self.minor)
+        } else {
+            write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+        }
+    }
+}
+
+/// The binaries given with [ExternalTool::set_path], instead of the ones on the PATH
+static PATHS: LazyLock<Mutex<HashMap<ExternalTool, PathBuf>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+/// The versions probed so far, so each tool is only asked once
+static VERSIONS: LazyLock<Mutex<HashMap<ExternalTool, Option<ToolVersion>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+impl ExternalTool {
+    pub const ALL: [ExternalTool; 5] = [
+        Self::Chdman,
+        Self::DolphinTool,
+        Self::Maxcso,
+        Self::SevenZip,
+        Self::Xdelta,
+    ];
+
+    /// The tool's binary's name, which it's looked for on the PATH by
+    ///
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Chdman => "chdman",
+            Self::DolphinTool => "dolphin-tool",
+            Self::Maxcso => "maxcso",
+            Self::SevenZip => "7z",
+            Self::Xdelta => "xdelta3",
+        }
+    }
+
+    /// Runs the given binary for the tool from now on, instead of the one on the PATH
+    ///
+    pub fn set_path(self, path: impl Into<PathBuf>) {
+        PATHS.lock().unwrap().insert(self, path.into());
+        VERSIONS.lock().unwrap().remove(&self);
+    }
+
+    /// Finds the binary that's run for the tool: the one given with [ExternalTool::set_path], or
+    /// else the first on the PATH
+    ///
+    /// Returns `None` if there's no such file.
+    pub fn locate(self) -> Option<PathBuf> {
+        if let Some(path) = PATHS.lock().unwrap().get(&self) {
+            return path.is_file().then(|| path.clone());
+        }
+        std::env::split_paths(&std::env::var_os("PATH")?)
+            .map(|directory| directory.join(self.name()))
+            .find(|path| path.is_file())
+    }
+
+    /// Asks the tool for its version, which is only done once per run
+    ///
+    /// Returns `None` if the tool runs, but its version can't be told from what it prints.
+    pub fn version(self) -> Result<Option<ToolVersion>> {
+        if let Some(version) = VERSIONS.lock().unwrap().get(&self) {
+            return Ok(*version);
+        }
+        let (arguments, pattern): (&[&str], &Regex) = match self {
+            // chdman prints its version above its usage when it's run without a command
+            Self::Chdman => (&[], super::regex!(r"manager (\d+)\.(\d+)")),
+            Self::DolphinTool => (&["--version"], super::regex!(r"(\d+)\.(\d+)(?:-(\d+))?")),
+            Self::Maxcso => (&["--version"], super::regex!(r"v(\d+)\.(\d+)(?:\.(\d+))?")),
+            // 7-Zip prints its version in the banner above everything it does
+            Self::SevenZip => (&["i"], super::regex!(r"7-Zip[^0-9]*(\d+)\.(\d+)")),
+            Self::Xdelta => (
+                &["-V"],
+                super::regex!(r"Xdelta version (\d+)\.(\d+)(?:\.(\d+))?"),
+            ),
+        };
+        let output = self.run(
+            self.command().args(arguments),
+            &format!("Failed to get the version of {}", self.name()),
+        )?;
+        let text =
+            String::from_utf8_lossy(&output.stdout) + String::from_utf8_lossy(&output.stderr);
+        let version = pattern.captures(&text).unwrap().map(|captures| {
+            let part = |index: usize| {
+                captures
+                    .get(index)
+                    .and_then(|part| part.as_str().parse().ok())
+                    .unwrap_or(0)
+            };
+            ToolVersion {
+                major: part(1),
+                minor: part(2),
+                patch: part(3),
+            }
+        });
+        trace!("{} version: {version:?}", self.name());
+        VERSIONS.lock().unwrap().insert(self, version);
+        Ok(version)
+    }
+
+    /// Fails unless the tool is at least `minimum`, naming the feature which needs it
+    ///
+    /// Tools whose version can't be told are given the benefit of the doubt.
+    pub(crate) fn require_version(self, minimum: ToolVersion, feature: &str) -> Result<()> {
+        match self.version()? {
+            Some(version) if version < minimum => Err(Error::new_original(format!(
+                "{feature} needs {} {minimum} or later, but {version} is installed",
+                self.name()
+            ))
+            .with_category(self.missing_category())),
+            _ => Ok(()),
+        }
+    }
+
+    /// The category of errors about the tool being missing, or too old to use
+    ///
+    pub(crate) fn missing_category(self) -> ErrorCategory {
+        match self {
+            Self::Chdman => ErrorCategory::ChdmanMissing,
+            Self::DolphinTool => ErrorCategory::DolphinToolMissing,
+            Self::Maxcso => ErrorCategory::MaxcsoMissing,
+            Self::SevenZip => ErrorCategory::SevenZipMissing,
+            Self::Xdelta => ErrorCategory::XdeltaMissing,
+        }
+    }
+
+    /// Starts a command running the tool, whose arguments are added by the caller
+    ///
+    pub(crate) fn command(self) -> Command {
+        let program = PATHS
+            .lock()
+            .unwrap()
+            .get(&self)
+            .cloned()
+            .unwrap_or_else(|| PathBuf::from(self.name()));
+        Command::new(program)
+    }
+
+    /// Runs a command made by [ExternalTool::command], capturing its output
+    ///
+    /// What the tool writes to stderr (like its progress) is logged as it runs, at trace level. A
+    /// missing tool is reported as [crate::ErrorKind::ToolMissing], but a tool exiting with an error
+    /// isn't an error here, since tools differ in how they report failures.
+    pub(crate) fn run(self, command: &mut Command, error_message: &str) -> Result<Output> {
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| {
+                if err.kind() == std::io::ErrorKind::NotFound {
+                    Error::new(
+                        error_message,
+                        crate::ErrorKind::ToolMissing {
+                            tool: self,
+                            source: err,
+                        },
+                    )
+                } else {
+                    Error::new(error_message, err)
+                }
+            })?;
+        // stdout is read alongside stderr, so neither pipe fills up and stalls the tool
+        let mut stdout = child.stdout.take().unwrap();
+        let stdout_reader = std::thread::spawn(move || {
+            let mut buffer = Vec::new();
+            stdout.read_to_end(&mut buffer).map(|_| buffer)
+        });
+        let mut stderr = Vec::new();
+        let mut reader = BufReader::new(child.stderr.take().unwrap());
+        let mut line = Vec::new();
+        loop {
+            let chunk = reader
+                .fill_buf()
+                .map_err(|err| Error::new(error_message, err))?;
+            if chunk.is_empty() {
+                break;
+            }
+            // progress is redrawn with carriage returns, so each redraw counts as a line
+            for &byte in chunk {
+                if byte == b'\r' || byte == b'\n' {
+                    if !line.is_empty() {
+                        trace!("{}: {}", self.name(), String::from_utf8_lossy(&line));
+                        line.clear();
+                    }
+                } else {
+                    line.push(byte);
+                }
+            }
+            stderr.extend_from_slice(chunk);
+            let length = chunk.len();
+            reader.consume(length);
+        }
+        if !line.is_empty() {
+            trace!("{}: {}", self.name(), String::from_utf8_lossy(&line));
+        }
+        let status = child.wait().map_err(|err| Error::new(error_message, err))?;
+        let stdout = stdout_reader
+            .join()
+            .unwrap()
+            .map_err(|err| Error::new(error_message, err))?;
+        Ok(Output {
+            status,
+            stdout,
+            stderr,
+        })
+    }
+
+    /// An error saying why the tool failed, from the last line it wrote to stderr
+    ///
+    pub(crate) fn failure(self, message: &str, output: &Output) -> Error {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        match stderr.lines().rev().find(|line| !line.trim().is_empty()) {
+            Some(line) => Error::new_original(format!("{message}\n{}", line.trim())),
+            None => Error::new_original(format!("{message}\nUnknown")),
+        }
+    }
+}
+
+impl FromStr for ExternalTool {
+    type Err = Error;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        ExternalTool::ALL
+            .into_iter()
+            .find(|tool| tool.name().eq_ignore_ascii_case(value.trim()))
+            .ok_or_else(|| {
+                Error::new_original(format!("Unknown tool: \"{value}\""))
+                    .with_category(ErrorCategory::InvalidInput)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn gates_features_by_version() {
+        let directory = tempfile::tempdir().unwrap();
+        let script = directory.path().join("maxcso");
+        // progress goes to stderr, with carriage returns, as it does for the real tools
+        std::fs::write(
+            &script,
+            "#!/bin/sh\nprintf '10%%\\r50%%\\r' >&2\necho 'maxcso v1.13.0'\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        ExternalTool::Maxcso.set_path(&script);
+        assert_eq!(ExternalTool::Maxcso.locate(), Some(script));
+        let output = ExternalTool::Maxcso
+            .run(&mut ExternalTool::Maxcso.command(), "Failed to run maxcso")
+            .unwrap();
+        assert_eq!(output.stderr, b"10%\r50%\r");
+        let version = ToolVersion {
+            major: 1,
+            minor: 13,
+            patch: 0,
+        };
+        assert_eq!(ExternalTool::Maxcso.version().unwrap(), Some(version));
+        assert!(
+            ExternalTool::Maxcso
+                .require_version(version, "Compressing")
+                .is_ok()
+        );
+        let newer = ToolVersion {
+            minor: 14,
+            ..version
+        };
+        assert!(
+            ExternalTool::Maxcso
+                .require_version(newer, "Compressing")
+                .is_err()
+        );
+
+        ExternalTool::Maxcso.set_path(directory.path().join("missing"));
+        let err = ExternalTool::Maxcso.version().unwrap_err();
+        assert_eq!(err.category(), ErrorCategory::MaxcsoMissing);
+    }
+}
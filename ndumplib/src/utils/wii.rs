@@ -0,0 +1,88 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::{Error, Result, ResultUtils};
+
+/// Identifies Wii discs, at offset 0x18 of the disc header
+pub(crate) const WII_MAGIC: u32 = 0x5D1C9EA3;
+/// Where the 4 partition groups are listed on a Wii disc
+const PARTITION_TABLE_OFFSET: u64 = 0x40000;
+/// The type of the partition holding the game itself (1 is the update partition, 2 the channel)
+const DATA_PARTITION_TYPE: u32 = 0;
+
+fn read_u32(file: &mut (impl Read + Seek), offset: u64) -> Result<u32> {
+    let mut bytes = [0u8; 4];
+    file.seek(SeekFrom::Start(offset))
+        .and_then(|_| file.read_exact(&mut bytes))
+        .ndl("Failed to read Wii partition table")?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+/// Finds the byte range of a Wii disc's data partition, including its header and encrypted data
+///
+/// Returns `None` if the image isn't a Wii disc. GameCube discs aren't partitioned, so they're
+/// `None` too.
+pub(crate) fn data_partition_range(file: &mut (impl Read + Seek)) -> Result<Option<(u64, u64)>> {
+    let mut magic = [0u8; 4];
+    if file
+        .seek(SeekFrom::Start(0x18))
+        .and_then(|_| file.read_exact(&mut magic))
+        .is_err()
+        || u32::from_be_bytes(magic) != WII_MAGIC
+    {
+        return Ok(None);
+    }
+    for group in 0..4 {
+        let group_offset = PARTITION_TABLE_OFFSET + group * 8;
+        let count = read_u32(file, group_offset)? as u64;
+        let table_offset = (read_u32(file, group_offset + 4)? as u64) << 2;
+        for entry in 0..count {
+            let entry_offset = table_offset + entry * 8;
+            if read_u32(file, entry_offset + 4)? != DATA_PARTITION_TYPE {
+                continue;
+            }
+            let start = (read_u32(file, entry_offset)? as u64) << 2;
+            // the partition header ends with the offset and size of the encrypted data (both shifted)
+            let data_offset = (read_u32(file, start + 0x2B8)? as u64) << 2;
+            let data_size = (read_u32(file, start + 0x2BC)? as u64) << 2;
+            return Ok(Some((start, start + data_offset + data_size)));
+        }
+    }
+    Err(Error::new_original(
+        "Failed to read Wii partition table\nNo data partition",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn finds_data_partition() {
+        let mut image = vec![0u8; 0x60000];
+        let mut put = |offset: usize, value: u32| {
+            image[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+        };
+        put(0x18, WII_MAGIC);
+        // one group with an update partition followed by the data partition
+        put(0x40000, 2);
+        put(0x40004, 0x40020 >> 2);
+        put(0x40020, 0x50000 >> 2);
+        put(0x40024, 1);
+        put(0x40028, 0x58000 >> 2);
+        put(0x4002C, DATA_PARTITION_TYPE);
+        put(0x58000 + 0x2B8, 0x1000 >> 2);
+        put(0x58000 + 0x2BC, 0x2000 >> 2);
+        assert_eq!(
+            data_partition_range(&mut Cursor::new(&image)).unwrap(),
+            Some((0x58000, 0x5B000))
+        );
+        // GameCube discs have their magic elsewhere
+        image[0x18..0x1C].fill(0);
+        assert_eq!(
+            data_partition_range(&mut Cursor::new(&image)).unwrap(),
+            None
+        );
+    }
+}
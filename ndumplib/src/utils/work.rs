@@ -0,0 +1,97 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use tempfile::TempDir;
+
+use crate::{Error, ErrorCategory, Result, ResultUtils};
+
+/// Where large temporary files are made, if not in the system's temporary folder
+static WORK_DIRECTORY: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Has large temporary files, like extracted CHDs and decompressed ISOs, made in `directory`
+/// instead of the system's temporary folder, which is often too small a tmpfs to hold a disc
+///
+/// `None` goes back to the system's temporary folder.
+pub fn set_work_directory(directory: Option<PathBuf>) {
+    *WORK_DIRECTORY.lock().unwrap() = directory;
+}
+
+/// The folder large temporary files are made in (see [set_work_directory])
+pub fn work_directory() -> PathBuf {
+    WORK_DIRECTORY
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+/// The free space at `path`, or at the closest folder above it which exists
+///
+/// Returns `None` where it can't be told, in which case nothing is checked.
+#[cfg(unix)]
+fn free_space(path: &Path) -> Option<u64> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let existing = path.ancestors().find(|ancestor| ancestor.exists())?;
+    let path = CString::new(existing.as_os_str().as_bytes()).ok()?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+    #[allow(clippy::unnecessary_cast)] // the fields' types differ between platforms
+    Some(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+fn gigabytes(bytes: u64) -> f64 {
+    bytes as f64 / 1e9
+}
+
+/// Fails with an [ErrorCategory::InsufficientSpace] error if fewer than `needed` bytes are free
+/// at `path`, so a conversion or extraction fails before it starts rather than when the disk
+/// fills up
+///
+pub(crate) fn ensure_free_space(path: &Path, needed: u64) -> Result<()> {
+    match free_space(path) {
+        Some(free) if free < needed => Err(Error::new_original(format!(
+            "Need {:.1} GB free at \"{}\", but only {:.1} GB is",
+            gigabytes(needed),
+            path.display(),
+            gigabytes(free)
+        ))
+        .with_category(ErrorCategory::InsufficientSpace)),
+        _ => Ok(()),
+    }
+}
+
+/// Makes a temporary folder in the work directory, checking `needed` bytes are free there first
+///
+pub(crate) fn temp_dir(needed: u64) -> Result<TempDir> {
+    let directory = work_directory();
+    ensure_free_space(&directory, needed)?;
+    tempfile::tempdir_in(&directory).ndl(format!(
+        "Failed to create temporary folder in \"{}\"",
+        directory.display()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_free_space() {
+        let directory = tempfile::tempdir().unwrap();
+        assert!(ensure_free_space(directory.path(), 0).is_ok());
+        // folders which don't exist yet are checked on the disk they'd be made on
+        assert!(ensure_free_space(&directory.path().join("new"), 0).is_ok());
+        let err = ensure_free_space(directory.path(), u64::MAX).unwrap_err();
+        assert_eq!(err.category(), ErrorCategory::InsufficientSpace);
+    }
+}
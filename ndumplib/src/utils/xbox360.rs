@@ -0,0 +1,157 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use crate::{Result, ResultUtils};
+
+/// Starts every Xbox 360 executable
+const XEX_MAGIC: &[u8; 4] = b"XEX2";
+/// The key of a XEX's optional header holding its execution info (media ID, version, title ID...)
+const XEX_EXECUTION_INFO: u32 = 0x00040006;
+/// XEXs have a handful of optional headers, so a count past this is something else
+const MAX_XEX_HEADERS: u32 = 64;
+/// The content type of Games on Demand packages, at offset 0x344 of their STFS header
+const GAMES_ON_DEMAND: u32 = 0x7000;
+/// The STFS header is followed by the package's data, so this covers everything read from it
+const STFS_HEADER_SIZE: usize = 0x1000;
+/// Where the package's English display name starts (UTF-16BE, padded with zeroes)
+const DISPLAY_NAME_OFFSET: usize = 0x411;
+const DISPLAY_NAME_LENGTH: usize = 0x80;
+
+fn u32_be(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        bytes.get(offset..offset + 4)?.try_into().unwrap(),
+    ))
+}
+
+/// Reads the title ID of a XEX starting at `offset`, from its execution info
+///
+/// Returns `None` if there's no XEX there, or it has no execution info.
+pub(crate) fn xex_title_id(image: &mut (impl Read + Seek), offset: u64) -> Result<Option<u32>> {
+    let mut read = |position: u64, length: usize| -> Result<Option<Vec<u8>>> {
+        let mut bytes = vec![0u8; length];
+        match image
+            .seek(SeekFrom::Start(offset + position))
+            .and_then(|_| image.read_exact(&mut bytes))
+        {
+            Ok(()) => Ok(Some(bytes)),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(err) => Err(err).ndl("Failed to read XEX"),
+        }
+    };
+    let Some(header) = read(0, 0x18)? else {
+        return Ok(None);
+    };
+    if &header[..4] != XEX_MAGIC {
+        return Ok(None);
+    }
+    let count = u32_be(&header, 0x14).unwrap();
+    if count > MAX_XEX_HEADERS {
+        return Ok(None);
+    }
+    let Some(headers) = read(0x18, count as usize * 8)? else {
+        return Ok(None);
+    };
+    let execution_info = headers
+        .chunks_exact(8)
+        .find(|entry| u32_be(entry, 0) == Some(XEX_EXECUTION_INFO))
+        .and_then(|entry| u32_be(entry, 4));
+    let Some(execution_info) = execution_info else {
+        return Ok(None);
+    };
+    Ok(read(execution_info as u64 + 0x0C, 4)?.and_then(|title_id| u32_be(&title_id, 0)))
+}
+
+/// The header of a Games on Demand package, the extensionless file named after its content ID
+///
+/// The game's data is in numbered parts (Data0000, Data0001...) in a folder named like the header
+/// with ".data" added.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct GodHeader {
+    pub(crate) title_id: u32,
+    /// The game's name, as the dashboard shows it
+    pub(crate) display_name: String,
+}
+
+impl GodHeader {
+    /// Reads a package's STFS header, returning `None` if it isn't a Games on Demand package
+    ///
+    pub(crate) fn read(path: &Path) -> Result<Option<GodHeader>> {
+        let mut file = File::open(path).ndl("Failed to open package")?;
+        let mut header = Vec::with_capacity(STFS_HEADER_SIZE);
+        (&mut file)
+            .take(STFS_HEADER_SIZE as u64)
+            .read_to_end(&mut header)
+            .ndl("Failed to read package")?;
+        Ok(Self::parse(&header))
+    }
+
+    fn parse(header: &[u8]) -> Option<GodHeader> {
+        if !matches!(header.get(..4)?, b"CON " | b"LIVE" | b"PIRS") {
+            return None;
+        }
+        if u32_be(header, 0x344)? != GAMES_ON_DEMAND {
+            return None;
+        }
+        let name = header.get(DISPLAY_NAME_OFFSET..DISPLAY_NAME_OFFSET + DISPLAY_NAME_LENGTH)?;
+        let name: Vec<u16> = name
+            .chunks_exact(2)
+            .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+            .take_while(|unit| *unit != 0)
+            .collect();
+        Some(GodHeader {
+            title_id: u32_be(header, 0x360)?,
+            display_name: String::from_utf16_lossy(&name).trim().to_string(),
+        })
+    }
+}
+
+/// If `path` is one of a Games on Demand package's data parts, returns the package's header
+///
+pub(crate) fn god_header_of_part(path: &Path) -> Option<PathBuf> {
+    let data = path.parent()?;
+    let content_id = data.file_name()?.to_str()?.strip_suffix(".data")?;
+    let header = data.with_file_name(content_id);
+    header.is_file().then_some(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn reads_title_ids() {
+        let mut xex = vec![0u8; 0x40];
+        xex[..4].copy_from_slice(XEX_MAGIC);
+        xex[0x14..0x18].copy_from_slice(&2u32.to_be_bytes());
+        xex[0x18..0x1C].copy_from_slice(&0x000105FFu32.to_be_bytes());
+        xex[0x20..0x24].copy_from_slice(&XEX_EXECUTION_INFO.to_be_bytes());
+        xex[0x24..0x28].copy_from_slice(&0x28u32.to_be_bytes());
+        xex[0x34..0x38].copy_from_slice(&0x4D5307E6u32.to_be_bytes());
+        let mut image = vec![0u8; 0x100];
+        image.extend_from_slice(&xex);
+        assert_eq!(
+            xex_title_id(&mut Cursor::new(&image), 0x100).unwrap(),
+            Some(0x4D5307E6)
+        );
+        assert_eq!(xex_title_id(&mut Cursor::new(&image), 0).unwrap(), None);
+
+        let mut header = vec![0u8; STFS_HEADER_SIZE];
+        header[..4].copy_from_slice(b"LIVE");
+        header[0x344..0x348].copy_from_slice(&GAMES_ON_DEMAND.to_be_bytes());
+        header[0x360..0x364].copy_from_slice(&0x4D5307E6u32.to_be_bytes());
+        for (index, unit) in "Halo 3".encode_utf16().enumerate() {
+            let offset = DISPLAY_NAME_OFFSET + index * 2;
+            header[offset..offset + 2].copy_from_slice(&unit.to_be_bytes());
+        }
+        let god = GodHeader::parse(&header).unwrap();
+        assert_eq!(god.title_id, 0x4D5307E6);
+        assert_eq!(god.display_name, "Halo 3");
+        header[0x344..0x348].copy_from_slice(&0xD0000u32.to_be_bytes());
+        assert_eq!(GodHeader::parse(&header), None);
+    }
+}
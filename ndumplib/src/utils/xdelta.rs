@@ -0,0 +1,23 @@
+use std::path::Path;
+
+use super::tool::ExternalTool;
+use crate::{
+    Result,
+    dump_manager::timings::{self, Stage},
+};
+
+/// Applies an xdelta patch to `base`, writing the patched file to `output`
+///
+/// xdelta checks the data it copies from `base` itself, so a patch for another file fails.
+pub fn apply(base: &Path, patch: &Path, output: &Path) -> Result<()> {
+    let mut command = ExternalTool::Xdelta.command();
+    command.arg("-d").arg("-s").arg(base).arg(patch).arg(output);
+    let output = timings::time(Stage::Conversion, || {
+        ExternalTool::Xdelta.run(&mut command, "Failed to apply xdelta patch")
+    })?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(ExternalTool::Xdelta.failure("Failed to apply xdelta patch", &output))
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use flate2::{Compression, write::DeflateEncoder};
+
+use crate::{Error, ErrorCategory, Result, ResultUtils, transfer::write_atomically};
+
+/// The DOS time TorrentZip gives every file, 23:32
+const DOS_TIME: u16 = 0xBC00;
+
+/// The DOS date TorrentZip gives every file, 1996-12-24
+const DOS_DATE: u16 = 0x2198;
+
+/// The general purpose flags of files deflated at the highest level
+const MAXIMUM_COMPRESSION: u16 = 0x0002;
+
+/// The version of the format needed to extract deflated files
+const VERSION_NEEDED: u16 = 20;
+
+const DEFLATED: u16 = 8;
+
+/// The start of the comment TorrentZip stamps archives with, which ends with the CRC-32 of the
+/// central directory
+const COMMENT_PREFIX: &str = "TORRENTZIPPED-";
+
+/// Appends the fields local file headers and central directory headers share
+fn put_file_fields(output: &mut Vec<u8>, crc: u32, compressed_size: u32, size: u32, name: &str) {
+    for field in [
+        VERSION_NEEDED,
+        MAXIMUM_COMPRESSION,
+        DEFLATED,
+        DOS_TIME,
+        DOS_DATE,
+    ] {
+        output.extend(field.to_le_bytes());
+    }
+    for field in [crc, compressed_size, size] {
+        output.extend(field.to_le_bytes());
+    }
+    output.extend((name.len() as u16).to_le_bytes());
+    // no extra field
+    output.extend(0u16.to_le_bytes());
+}
+
+/// Writes files to a ZIP at `output` the way TorrentZip does, so the same files always make the
+/// same archive, byte for byte
+///
+/// `files` are each the name to store a file under, and the file. They're stored in order of their
+/// lowercased names, deflated at the highest level, with fixed timestamps and no extra fields, and
+/// the archive's comment holds the CRC-32 of its central directory. Each file is read whole, so
+/// this is for cartridge ROMs rather than disc images, and archives over 4 GiB (which need ZIP64)
+/// aren't written.
+pub(crate) fn write_torrentzip(files: &[(String, PathBuf)], output: &Path) -> Result<()> {
+    let too_big = || {
+        Error::new_original(format!(
+            "Failed to write \"{}\"\nIt would be too big for a ZIP without ZIP64",
+            output.display()
+        ))
+        .with_category(ErrorCategory::InvalidInput)
+    };
+    let mut files: Vec<(String, &Path)> = files
+        .iter()
+        .map(|(name, path)| (name.replace('\\', "/"), path.as_path()))
+        .collect();
+    files.sort_by_cached_key(|(name, _)| name.to_lowercase());
+    let mut archive = Vec::new();
+    let mut directory = Vec::new();
+    for (name, path) in &files {
+        let data = std::fs::read(path).ndl("Failed to read file to zip")?;
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&data).ndl("Failed to compress file")?;
+        let compressed = encoder.finish().ndl("Failed to compress file")?;
+        let (Ok(size), Ok(compressed_size), Ok(offset), Ok(_)) = (
+            u32::try_from(data.len()),
+            u32::try_from(compressed.len()),
+            u32::try_from(archive.len()),
+            u16::try_from(name.len()),
+        ) else {
+            return Err(too_big());
+        };
+        let crc = crc32fast::hash(&data);
+        archive.extend(0x04034b50u32.to_le_bytes());
+        put_file_fields(&mut archive, crc, compressed_size, size, name);
+        archive.extend(name.as_bytes());
+        archive.extend(&compressed);
+        directory.extend(0x02014b50u32.to_le_bytes());
+        // made by MS-DOS, version 0
+        directory.extend(0u16.to_le_bytes());
+        put_file_fields(&mut directory, crc, compressed_size, size, name);
+        // no comment, on the first disk, with no internal or external attributes
+        for field in [0u16, 0, 0] {
+            directory.extend(field.to_le_bytes());
+        }
+        directory.extend(0u32.to_le_bytes());
+        directory.extend(offset.to_le_bytes());
+        directory.extend(name.as_bytes());
+    }
+    let (Ok(count), Ok(directory_size), Ok(directory_offset)) = (
+        u16::try_from(files.len()),
+        u32::try_from(directory.len()),
+        u32::try_from(archive.len()),
+    ) else {
+        return Err(too_big());
+    };
+    let comment = format!("{COMMENT_PREFIX}{:08X}", crc32fast::hash(&directory));
+    archive.extend(&directory);
+    archive.extend(0x06054b50u32.to_le_bytes());
+    for field in [0u16, 0, count, count] {
+        archive.extend(field.to_le_bytes());
+    }
+    archive.extend(directory_size.to_le_bytes());
+    archive.extend(directory_offset.to_le_bytes());
+    archive.extend((comment.len() as u16).to_le_bytes());
+    archive.extend(comment.as_bytes());
+    write_atomically(output, |partial| {
+        std::fs::write(partial, &archive).ndl("Failed to write zip")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_the_same_zip_for_the_same_files() {
+        let folder = tempfile::tempdir().unwrap();
+        let rom = folder.path().join("b.gb");
+        let other = folder.path().join("A.gb");
+        std::fs::write(&rom, [1u8; 4096]).unwrap();
+        std::fs::write(&other, [2u8; 1024]).unwrap();
+        let first = folder.path().join("first.zip");
+        let second = folder.path().join("second.zip");
+        write_torrentzip(
+            &[
+                ("b.gb".to_string(), rom.clone()),
+                ("A.gb".to_string(), other.clone()),
+            ],
+            &first,
+        )
+        .unwrap();
+        write_torrentzip(
+            &[("A.gb".to_string(), other), ("b.gb".to_string(), rom)],
+            &second,
+        )
+        .unwrap();
+        let written = std::fs::read(&first).unwrap();
+        assert_eq!(written, std::fs::read(&second).unwrap());
+        // files are stored in order of their lowercased names
+        assert_eq!(&written[30..34], b"A.gb");
+        let comment = String::from_utf8_lossy(&written[written.len() - 22..]).into_owned();
+        assert!(comment.starts_with(COMMENT_PREFIX));
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+};
+
+use clap::Subcommand;
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use ndumplib::{DumpManager, WhenLocked};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{CliError, ExitCode, Result},
+    open_manager,
+    prompt::Prompter,
+    settings::{Settings, StorageLocations},
+};
+
+/// The version of the backup archives' layout, raised whenever it changes
+const BACKUP_VERSION: u32 = 1;
+
+/// The file in a backup archive describing it
+const MANIFEST_NAME: &str = "backup.json";
+
+/// The name the config file is kept under in a backup archive, whatever it's called
+const CONFIG_NAME: &str = "ndumpmgr.yml";
+
+#[derive(Subcommand)]
+pub enum BackupCommand {
+    /// Writes the catalog and cuesheet databases, and the config file, to a compressed archive,
+    /// for moving to another machine or recovering from a corrupted database
+    Create {
+        /// The archive to write, like "ndumpmgr-backup.tar.gz"
+        output: String,
+    },
+    /// Replaces the databases and config file with the ones in an archive from `backup create`
+    Restore {
+        /// The archive to restore
+        archive: String,
+        /// Keeps the current config file, only restoring the databases
+        #[arg(long)]
+        no_config: bool,
+    },
+}
+
+/// Describes a backup archive, so it can be checked before it's restored
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    version: u32,
+    /// When the backup was made, in RFC 3339 format
+    created: String,
+    /// Whether the archive holds the config file
+    config: bool,
+}
+
+fn io_error(action: &str, path: &Path, err: impl std::fmt::Display) -> CliError {
+    CliError::new(
+        ExitCode::IO,
+        format!("Failed to {action} \"{}\": {err}", path.display()),
+    )
+}
+
+/// Snapshots the databases, then archives them with the config file and a manifest
+fn create(settings: Settings, locations: &StorageLocations, output: &Path) -> Result<()> {
+    let snapshots =
+        tempfile::tempdir().map_err(|err| io_error("create", &std::env::temp_dir(), err))?;
+    let databases = {
+        let manager = open_manager(&settings, locations)?;
+        manager.backup_databases(snapshots.path())?
+    };
+    let config = locations.config_path.is_file();
+    let manifest = Manifest {
+        version: BACKUP_VERSION,
+        created: chrono::Utc::now().to_rfc3339(),
+        config,
+    };
+    let manifest_path = snapshots.path().join(MANIFEST_NAME);
+    fs::write(
+        &manifest_path,
+        serde_json::to_vec_pretty(&manifest).unwrap(),
+    )
+    .map_err(|err| io_error("write", &manifest_path, err))?;
+    let file = File::create(output).map_err(|err| io_error("create", output, err))?;
+    let mut builder =
+        tar::Builder::new(GzEncoder::new(BufWriter::new(file), Compression::default()));
+    let mut entries = vec![(manifest_path, MANIFEST_NAME.to_string())];
+    entries.extend(databases.into_iter().map(|database| {
+        let name = database.file_name().unwrap().to_string_lossy().into_owned();
+        (database, name)
+    }));
+    if config {
+        entries.push((locations.config_path.clone(), CONFIG_NAME.to_string()));
+    }
+    let result = entries
+        .iter()
+        .try_for_each(|(path, name)| builder.append_path_with_name(path, name))
+        .and_then(|_| builder.into_inner())
+        .and_then(|encoder| encoder.finish())
+        .and_then(|mut writer| std::io::Write::flush(&mut writer));
+    if let Err(err) = result {
+        let _ = fs::remove_file(output);
+        return Err(io_error("write", output, err));
+    }
+    log::info!(
+        "Backed up the databases{} to \"{}\"",
+        if config { " and config file" } else { "" },
+        output.display()
+    );
+    Ok(())
+}
+
+/// Unpacks a backup archive, then puts its databases (and config file) in place
+fn restore(
+    settings: Settings,
+    locations: &StorageLocations,
+    prompter: &Prompter,
+    archive: &Path,
+    no_config: bool,
+) -> Result<()> {
+    let unpacked =
+        tempfile::tempdir().map_err(|err| io_error("create", &std::env::temp_dir(), err))?;
+    let file = File::open(archive).map_err(|err| io_error("open", archive, err))?;
+    tar::Archive::new(GzDecoder::new(file))
+        .unpack(unpacked.path())
+        .map_err(|err| {
+            CliError::new(
+                ExitCode::InvalidData,
+                format!("Failed to unpack \"{}\": {err}", archive.display()),
+            )
+        })?;
+    let not_a_backup = || {
+        CliError::new(
+            ExitCode::InvalidData,
+            format!("\"{}\" isn't an ndumpmgr backup", archive.display()),
+        )
+    };
+    let manifest: Manifest = fs::read(unpacked.path().join(MANIFEST_NAME))
+        .ok()
+        .and_then(|content| serde_json::from_slice(&content).ok())
+        .ok_or_else(not_a_backup)?;
+    if manifest.version > BACKUP_VERSION {
+        return Err(CliError::new(
+            ExitCode::InvalidData,
+            format!(
+                "\"{}\" was made by a newer version of ndumpmgr. Update ndumpmgr to restore it",
+                archive.display()
+            ),
+        ));
+    }
+    if !prompter.confirm(&format!(
+        "Replace the databases{} with the backup from {}?",
+        if manifest.config && !no_config {
+            " and config file"
+        } else {
+            ""
+        },
+        manifest.created
+    ))? {
+        log::info!("Nothing was restored");
+        return Ok(());
+    }
+    let when_locked = if settings.wait_for_lock {
+        WhenLocked::Wait
+    } else {
+        WhenLocked::Fail
+    };
+    let restored =
+        DumpManager::restore_databases(&locations.data_path, unpacked.path(), when_locked)?;
+    if restored.is_empty() {
+        return Err(not_a_backup());
+    }
+    if manifest.config && !no_config {
+        let config = unpacked.path().join(CONFIG_NAME);
+        let mut partial = locations.config_path.clone().into_os_string();
+        partial.push(".restoring");
+        fs::copy(&config, &partial)
+            .and_then(|_| fs::rename(&partial, &locations.config_path))
+            .map_err(|err| io_error("restore", &locations.config_path, err))?;
+        log::info!("Restored the config file");
+    }
+    // opening the restored databases brings them up to date, and checks they're usable
+    open_manager(&settings, locations)?;
+    log::info!("Restored the {} DB(s)", restored.join(" and "));
+    Ok(())
+}
+
+pub fn run(
+    command: BackupCommand,
+    settings: Settings,
+    locations: &StorageLocations,
+    prompter: &Prompter,
+) -> Result<()> {
+    match command {
+        BackupCommand::Create { output } => create(settings, locations, Path::new(&output)),
+        BackupCommand::Restore { archive, no_config } => restore(
+            settings,
+            locations,
+            prompter,
+            Path::new(&archive),
+            no_config,
+        ),
+    }
+}
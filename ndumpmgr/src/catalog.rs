@@ -0,0 +1,534 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::Subcommand;
+use ndumplib::{
+    CatalogChange, ChangeKind, CustomGame, CustomROM, FileHash, GameConsole, HashAlgorithm,
+};
+use serde::Deserialize;
+
+use crate::{
+    error::{CliError, ExitCode, Result},
+    open_manager,
+    settings::{Settings, StorageLocations},
+};
+
+/// How many similar names to suggest when a search finds nothing
+const MAX_SUGGESTIONS: usize = 5;
+
+#[derive(Subcommand)]
+pub enum CatalogCommand {
+    /// Shows how up-to-date each datafile in the catalog is
+    Status {},
+    /// Finds games by name, suggesting the closest names if none match
+    Search {
+        /// A case-insensitive name, or a wildcard pattern (`*` matches anything, `?` one character)
+        pattern: String,
+    },
+    /// Adds a game of your own (like a ROM hack, translation, or homebrew), so its dumps verify
+    #[command(group = clap::ArgGroup::new("source").required(true).args(["files", "sha1", "manifest"]))]
+    Add {
+        /// Dumps to add as the game's ROMs, hashed the way they're verified
+        files: Vec<PathBuf>,
+        /// The game's console, like "snes"
+        #[arg(long, required_unless_present = "manifest")]
+        console: Option<String>,
+        /// The game's name (defaults to the first dump's, without its extension)
+        #[arg(long, required_unless_present_any = ["files", "manifest"])]
+        name: Option<String>,
+        /// The ROM's SHA-1 hash (hex), to add a game without its dump
+        #[arg(long, requires = "size")]
+        sha1: Option<String>,
+        /// The ROM's size in bytes
+        #[arg(long, requires = "sha1")]
+        size: Option<usize>,
+        /// The ROM's CRC32 hash (hex)
+        #[arg(long, requires = "sha1")]
+        crc32: Option<String>,
+        /// The ROM's MD5 hash (hex)
+        #[arg(long, requires = "sha1")]
+        md5: Option<String>,
+        /// The ROM's file name (defaults to the game's name)
+        #[arg(long, requires = "sha1")]
+        rom_name: Option<String>,
+        /// A YAML manifest listing games to add, instead of one game
+        #[arg(long, conflicts_with_all = ["files", "console", "name", "sha1"])]
+        manifest: Option<PathBuf>,
+    },
+    /// Lists what datafile updates changed in the catalog (games added, removed, or renamed, and
+    /// ROMs given different hashes)
+    Changelog {
+        /// Only lists changes since this date, like "2025-01-31" (UTC) or "2025-01-31T04:00:00Z"
+        #[arg(long, value_parser = parse_since)]
+        since: DateTime<Utc>,
+        /// Dumps, or folders of dumps, to check for ones the changes outdated
+        #[arg(long, value_name = "PATH", num_args = 1..)]
+        have: Vec<String>,
+    },
+    /// Removes a game added with `catalog add`
+    Remove {
+        /// The game's console, like "snes"
+        #[arg(long)]
+        console: String,
+        /// The game's name
+        name: String,
+    },
+}
+
+/// A game in a manifest of custom games, like:
+///
+/// ```yaml
+/// - console: snes
+///   name: Game (USA) (T-En)
+///   roms:
+///     - file: Game (USA) (T-En).sfc
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestGame {
+    console: String,
+    name: String,
+    roms: Vec<ManifestROM>,
+}
+
+/// A ROM in a manifest of custom games: either a dump to hash, or its name, size, and hashes
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestROM {
+    /// Relative to the manifest
+    file: Option<PathBuf>,
+    name: Option<String>,
+    size: Option<usize>,
+    sha1: Option<String>,
+    crc32: Option<String>,
+    md5: Option<String>,
+}
+
+impl ManifestROM {
+    fn into_rom(self, folder: &Path, game_name: &str) -> Result<CustomROM> {
+        match (self.file, self.sha1, self.size) {
+            (Some(file), None, None) => {
+                let mut rom = CustomROM::of_file(&folder.join(file))?;
+                if let Some(name) = self.name {
+                    rom.name = name;
+                }
+                Ok(rom)
+            }
+            (None, Some(sha1), Some(size)) => custom_rom(
+                self.name.unwrap_or_else(|| game_name.to_string()),
+                size,
+                &sha1,
+                self.crc32.as_deref(),
+                self.md5.as_deref(),
+            ),
+            _ => Err(CliError::new(
+                ExitCode::InvalidInput,
+                format!(
+                    "Each of \"{game_name}\"'s ROMs needs either a file, or a size and a SHA-1 hash"
+                ),
+            )),
+        }
+    }
+}
+
+/// A custom ROM from its hashes, given in hex
+fn custom_rom(
+    name: String,
+    size: usize,
+    sha1: &str,
+    crc32: Option<&str>,
+    md5: Option<&str>,
+) -> Result<CustomROM> {
+    let FileHash::SHA1(sha1) = FileHash::from_hex(HashAlgorithm::SHA1, sha1)? else {
+        unreachable!()
+    };
+    let crc32 = match crc32 {
+        Some(crc32) => match FileHash::from_hex(HashAlgorithm::CRC32, crc32)? {
+            FileHash::CRC32(crc32) => Some(crc32),
+            _ => unreachable!(),
+        },
+        None => None,
+    };
+    let md5 = match md5 {
+        Some(md5) => match FileHash::from_hex(HashAlgorithm::MD5, md5)? {
+            FileHash::MD5(md5) => Some(md5),
+            _ => unreachable!(),
+        },
+        None => None,
+    };
+    Ok(CustomROM {
+        name,
+        size,
+        crc32,
+        md5,
+        sha1,
+        sha256: None,
+    })
+}
+
+/// Reads the games listed in a manifest of custom games
+fn read_manifest(path: &Path) -> Result<Vec<CustomGame>> {
+    let content = std::fs::read_to_string(path).map_err(|err| {
+        CliError::new(
+            ExitCode::IO,
+            format!("Failed to read \"{}\": {err}", path.display()),
+        )
+    })?;
+    let games: Vec<ManifestGame> = serde_yaml::from_str(&content).map_err(|err| {
+        CliError::new(
+            ExitCode::InvalidInput,
+            format!("Failed to parse \"{}\": {err}", path.display()),
+        )
+    })?;
+    let folder = path.parent().unwrap_or(Path::new(""));
+    games
+        .into_iter()
+        .map(|game| {
+            let roms = game
+                .roms
+                .into_iter()
+                .map(|rom| rom.into_rom(folder, &game.name))
+                .collect::<Result<Vec<CustomROM>>>()?;
+            Ok(CustomGame {
+                console: game.console.parse()?,
+                name: game.name,
+                roms,
+            })
+        })
+        .collect()
+}
+
+/// The arguments of `catalog add` for a single game
+struct AddArgs {
+    files: Vec<PathBuf>,
+    console: Option<String>,
+    name: Option<String>,
+    sha1: Option<String>,
+    size: Option<usize>,
+    crc32: Option<String>,
+    md5: Option<String>,
+    rom_name: Option<String>,
+}
+
+/// Adds games of the user's own to the catalog, from dumps, hashes, or a manifest
+fn add(
+    settings: Settings,
+    locations: &StorageLocations,
+    args: AddArgs,
+    manifest: Option<PathBuf>,
+) -> Result<()> {
+    let games = match manifest {
+        Some(manifest) => read_manifest(&manifest)?,
+        None => {
+            // clap requires these for a single game
+            let console: GameConsole = args.console.unwrap().parse()?;
+            let roms = match (args.sha1, args.size) {
+                (Some(sha1), Some(size)) => {
+                    let name = args.name.clone().unwrap();
+                    vec![custom_rom(
+                        args.rom_name.unwrap_or(name),
+                        size,
+                        &sha1,
+                        args.crc32.as_deref(),
+                        args.md5.as_deref(),
+                    )?]
+                }
+                _ => args
+                    .files
+                    .iter()
+                    .map(|file| Ok(CustomROM::of_file(file)?))
+                    .collect::<Result<Vec<CustomROM>>>()?,
+            };
+            let name = match args.name {
+                Some(name) => name,
+                None => args.files[0]
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            vec![CustomGame {
+                console,
+                name,
+                roms,
+            }]
+        }
+    };
+    let count = games.len();
+    let mut manager = open_manager(&settings, locations)?;
+    let changed = manager.add_custom_games(games)?;
+    if changed.is_empty() {
+        println!("The catalog already had these games");
+    } else {
+        println!("Added {count} custom game(s) to the catalog");
+    }
+    Ok(())
+}
+
+/// Removes a game added with `catalog add`
+fn remove(
+    settings: Settings,
+    locations: &StorageLocations,
+    console: String,
+    name: String,
+) -> Result<()> {
+    let console: GameConsole = console.parse()?;
+    let mut manager = open_manager(&settings, locations)?;
+    if manager.remove_custom_game(console, &name)? {
+        println!("Removed \"{name}\" from the catalog");
+        Ok(())
+    } else {
+        Err(CliError::new(
+            ExitCode::InvalidInput,
+            format!(
+                "No custom {} game is named \"{name}\"",
+                console.formal_name()
+            ),
+        ))
+    }
+}
+
+/// Shows how up-to-date each datafile in the catalog is
+fn status(settings: Settings, locations: &StorageLocations) -> Result<()> {
+    let manager = open_manager(&settings, locations)?;
+    let datafiles = manager.datafile_info()?;
+    if datafiles.is_empty() {
+        println!("The catalog is empty. Run `ndumpmgr sort` to download datafiles");
+        return Ok(());
+    }
+    let rows: Vec<[String; 6]> = datafiles
+        .into_iter()
+        .map(|datafile| {
+            [
+                datafile.name,
+                datafile.author,
+                datafile.version,
+                if datafile.last_updated.timestamp_millis() == 0 {
+                    String::from("never")
+                } else {
+                    datafile.last_updated.format("%Y-%m-%d %H:%M").to_string()
+                },
+                datafile.game_count.to_string(),
+                datafile.rom_count.to_string(),
+            ]
+        })
+        .collect();
+    print_table(
+        &[
+            "Datafile",
+            "Author",
+            "Version",
+            "Last Updated",
+            "Games",
+            "ROMs",
+        ],
+        &rows,
+    );
+    Ok(())
+}
+
+/// Reads a `--since` date, either a day or an RFC 3339 time
+fn parse_since(value: &str) -> std::result::Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| format!("\"{value}\" isn't a date like \"2025-01-31\""))
+}
+
+/// Describes a change for the changelog, past its game's name
+fn change_details(change: &CatalogChange, removed_roms: usize) -> String {
+    match change.kind {
+        ChangeKind::Added => String::new(),
+        ChangeKind::Removed => format!("{removed_roms} ROM(s)"),
+        ChangeKind::Renamed => format!("was \"{}\"", change.old_name.as_deref().unwrap_or("")),
+        ChangeKind::ROMChanged => {
+            let hash = |sha1: Option<[u8; 20]>| {
+                sha1.map_or(String::from("none"), |sha1| {
+                    FileHash::SHA1(sha1).to_string()
+                })
+            };
+            format!(
+                "{}: {} -> {}",
+                change.rom.as_deref().unwrap_or(""),
+                hash(change.old_sha1),
+                hash(change.new_sha1)
+            )
+        }
+    }
+}
+
+/// Lists what datafile updates changed since a date, and which dumps the changes outdated
+fn changelog(
+    settings: Settings,
+    locations: &StorageLocations,
+    since: DateTime<Utc>,
+    have: Vec<String>,
+) -> Result<()> {
+    let manager = open_manager(&settings, locations)?;
+    let changes = manager.catalog_changes(since)?;
+    if changes.is_empty() {
+        println!(
+            "Nothing has changed since {}",
+            since.format("%Y-%m-%d %H:%M")
+        );
+        return Ok(());
+    }
+    // a removed game has a change for each of its ROMs, which are listed as one
+    let mut rows: Vec<[String; 6]> = Vec::new();
+    let mut index = 0;
+    while index < changes.len() {
+        let change = &changes[index];
+        let mut removed_roms = 0;
+        while change.kind == ChangeKind::Removed
+            && changes.get(index).is_some_and(|other| {
+                other.kind == ChangeKind::Removed
+                    && other.gid == change.gid
+                    && other.datafile == change.datafile
+                    && other.changed == change.changed
+            })
+        {
+            removed_roms += usize::from(changes[index].rom.is_some());
+            index += 1;
+        }
+        if change.kind != ChangeKind::Removed {
+            index += 1;
+        }
+        rows.push([
+            change.changed.format("%Y-%m-%d %H:%M").to_string(),
+            change.datafile.clone(),
+            change.version.clone(),
+            change.kind.name().to_string(),
+            change.game.clone(),
+            change_details(change, removed_roms),
+        ]);
+    }
+    print_table(
+        &[
+            "Changed", "Datafile", "Version", "Change", "Game", "Details",
+        ],
+        &rows,
+    );
+    if !have.is_empty() {
+        let dumps = crate::expand_paths(&have, &settings.ignore)?;
+        let outdated = manager.outdated_dumps(&changes, &dumps)?;
+        if outdated.is_empty() {
+            println!("None of the dumps were outdated");
+        } else {
+            println!("Outdated dumps:");
+            for (path, change) in outdated {
+                println!(
+                    "  {} ({} {} in {})",
+                    path.display(),
+                    change.game,
+                    change.kind.name(),
+                    change.version
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Finds games by name, suggesting the closest names if none match
+fn search(settings: Settings, locations: &StorageLocations, pattern: String) -> Result<()> {
+    let reader = open_manager(&settings, locations)?.catalog_reader();
+    // without wildcards, the name can appear anywhere
+    let wildcard_pattern = if pattern.contains(['*', '?']) {
+        pattern.clone()
+    } else {
+        format!("*{pattern}*")
+    };
+    let games = reader.search_games(&wildcard_pattern)?;
+    if games.is_empty() {
+        println!("No matching games");
+        let text = pattern.replace(['*', '?'], " ");
+        let suggestions = reader.suggest_games(&text, MAX_SUGGESTIONS)?;
+        if !suggestions.is_empty() {
+            println!("Did you mean:");
+            for game in suggestions {
+                println!("  {} ({})", game.name, game.console.short_name());
+            }
+        }
+        return Ok(());
+    }
+    let rows: Vec<[String; 6]> = games
+        .into_iter()
+        .map(|game| {
+            [
+                game.gid.to_string(),
+                game.console.short_name().to_string(),
+                game.name,
+                game.region.unwrap_or_default(),
+                game.serial.unwrap_or_default(),
+                game.datafile,
+            ]
+        })
+        .collect();
+    print_table(
+        &["GID", "Console", "Name", "Region", "Serial", "Datafile"],
+        &rows,
+    );
+    Ok(())
+}
+
+/// Prints rows of values in columns, padded to line up under the header
+pub(crate) fn print_table<const N: usize>(header: &[&str; N], rows: &[[String; N]]) {
+    let mut widths = header.map(|column| column.len());
+    for row in rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+    let print_row = |row: &[&str]| {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(value, width)| format!("{value:<width$}"))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    };
+    print_row(header);
+    for row in rows {
+        print_row(&row.each_ref().map(|value| value.as_str()));
+    }
+}
+
+pub fn run(
+    command: CatalogCommand,
+    settings: Settings,
+    locations: &StorageLocations,
+) -> Result<()> {
+    match command {
+        CatalogCommand::Status {} => status(settings, locations),
+        CatalogCommand::Search { pattern } => search(settings, locations, pattern),
+        CatalogCommand::Add {
+            files,
+            console,
+            name,
+            sha1,
+            size,
+            crc32,
+            md5,
+            rom_name,
+            manifest,
+        } => add(
+            settings,
+            locations,
+            AddArgs {
+                files,
+                console,
+                name,
+                sha1,
+                size,
+                crc32,
+                md5,
+                rom_name,
+            },
+            manifest,
+        ),
+        CatalogCommand::Changelog { since, have } => changelog(settings, locations, since, have),
+        CatalogCommand::Remove { console, name } => remove(settings, locations, console, name),
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use ndumplib::SplitDump;
+
+/// The extensions of companion files by default: save files, patches, and cover art
+pub const DEFAULT_COMPANION_EXTENSIONS: [&str; 7] =
+    ["sav", "srm", "eep", "ips", "bps", "png", "jpg"];
+
+/// A dump's path without its extension, which its companions are named after
+pub fn dump_stem(file: &Path) -> PathBuf {
+    file.with_extension("")
+}
+
+/// A split dump's path without its extension, like "Game" for "Game.iso.1"
+///
+/// Returns `None` for archive volumes, whose files are only known once they're extracted.
+pub fn split_dump_stem(dump: &SplitDump) -> Option<PathBuf> {
+    let joined_name = dump.joined_name()?;
+    Some(dump_stem(&dump.parts[0].with_file_name(joined_name)))
+}
+
+/// Files kept next to dumps which belong with them, like save files, patches, and cover art
+///
+/// A companion has one of the companion extensions, and is named after a dump in the same folder:
+/// "Game.sav" and "Game.cover.png" both belong with "Game.iso".
+pub struct Companions {
+    /// The companions, by the stem of the dump they belong with (see [dump_stem])
+    by_stem: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl Companions {
+    /// Sets apart the companions among `files` (which don't include `split_dumps`' parts),
+    /// returning them and the other files
+    ///
+    /// Files with a companion extension are never dumps themselves, so those which don't belong
+    /// with any dump are left where they are.
+    pub fn find(
+        files: Vec<PathBuf>,
+        split_dumps: &[SplitDump],
+        extensions: &[String],
+    ) -> (Companions, Vec<PathBuf>) {
+        let is_companion = |file: &PathBuf| {
+            file.extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| {
+                    extensions
+                        .iter()
+                        .any(|companion| companion.eq_ignore_ascii_case(extension))
+                })
+        };
+        let (companions, others): (Vec<PathBuf>, Vec<PathBuf>) =
+            files.into_iter().partition(is_companion);
+        let mut by_stem: HashMap<PathBuf, Vec<PathBuf>> = others
+            .iter()
+            .map(|file| dump_stem(file))
+            .chain(split_dumps.iter().filter_map(split_dump_stem))
+            .map(|stem| (stem, Vec::new()))
+            .collect();
+        for companion in companions {
+            // "Game.cover.png" is tried as a companion of "Game.cover", then of "Game"
+            let mut stem = dump_stem(&companion);
+            loop {
+                if let Some(group) = by_stem.get_mut(&stem) {
+                    group.push(companion);
+                    break;
+                }
+                if stem.extension().is_none() {
+                    log::debug!(
+                        "Leaving \"{}\" where it is: it isn't named after a dump",
+                        companion.display()
+                    );
+                    break;
+                }
+                stem = dump_stem(&stem);
+            }
+        }
+        by_stem.retain(|_, group| !group.is_empty());
+        (Companions { by_stem }, others)
+    }
+
+    /// Takes the companions of the dump with the given stem
+    ///
+    pub fn take(&mut self, stem: &Path) -> Vec<PathBuf> {
+        self.by_stem.remove(stem).unwrap_or_default()
+    }
+}
+
+/// Where a companion goes once its dump is placed at `target`, keeping what follows the dump's
+/// name: "Game.cover.png" follows "Game.iso" to "Game (USA).cover.png"
+pub fn companion_target(companion: &Path, stem: &Path, target: &Path) -> PathBuf {
+    let name = companion.file_name().unwrap().to_string_lossy();
+    let stem_name = stem.file_name().unwrap().to_string_lossy();
+    let suffix = name.strip_prefix(stem_name.as_ref()).unwrap_or(&name);
+    let mut target_name = dump_stem(target).file_name().unwrap().to_os_string();
+    target_name.push(suffix);
+    target.with_file_name(target_name)
+}
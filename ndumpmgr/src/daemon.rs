@@ -0,0 +1,275 @@
+use std::{collections::BTreeMap, path::PathBuf, time::Duration};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    catalog::print_table,
+    error::{CliError, ExitCode, Result},
+    notify, open_manager,
+    prompt::Prompter,
+    schedule::Schedule,
+    settings::{DaemonSettings, SettingOverride, Settings, StorageLocations},
+    sort, summary,
+};
+
+/// The file in the data directory where `daemon` records how its jobs went
+pub const STATUS_FILE_NAME: &str = "daemon-status.json";
+
+/// The longest the daemon sleeps at a time, so that it notices the clock jumping (like after the
+/// machine was suspended)
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Something `daemon` does on a schedule
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Job {
+    /// Updates the catalog's datafiles and cuesheets
+    Update,
+    /// Imports the dumps in the watch folders
+    Import,
+}
+
+impl Job {
+    const ALL: [Job; 2] = [Job::Update, Job::Import];
+
+    fn name(self) -> &'static str {
+        match self {
+            Job::Update => "update",
+            Job::Import => "import",
+        }
+    }
+
+    fn schedule(self, settings: &DaemonSettings) -> &str {
+        match self {
+            Job::Update => &settings.update_schedule,
+            Job::Import => &settings.import_schedule,
+        }
+    }
+}
+
+/// How a job last went, and when it runs next (times are RFC 3339)
+#[derive(Serialize, Deserialize, Default)]
+struct JobStatus {
+    last_run: Option<String>,
+    /// The error which stopped the last run, if it failed
+    last_error: Option<String>,
+    next_run: Option<String>,
+}
+
+/// What the daemon writes to [STATUS_FILE_NAME] whenever a job starts or finishes
+#[derive(Serialize, Deserialize)]
+struct DaemonStatus {
+    pid: u32,
+    started: String,
+    jobs: BTreeMap<String, JobStatus>,
+}
+
+impl DaemonStatus {
+    fn path(locations: &StorageLocations) -> PathBuf {
+        locations.data_path.join(STATUS_FILE_NAME)
+    }
+
+    /// Writes the status, logging a failure rather than stopping the daemon
+    fn write(&self, locations: &StorageLocations) {
+        let path = Self::path(locations);
+        let content = serde_json::to_string_pretty(self).unwrap();
+        if let Err(err) = std::fs::write(&path, content) {
+            log::warn!("Failed to write \"{}\": {err}", path.display());
+        }
+    }
+}
+
+/// Formats a time in the status for people, like "2025-01-31 04:00"
+fn format_time(time: &Option<String>) -> String {
+    match time.as_deref().map(DateTime::parse_from_rfc3339) {
+        Some(Ok(time)) => time.format("%Y-%m-%d %H:%M").to_string(),
+        Some(Err(_)) => String::from("?"),
+        None => String::from("never"),
+    }
+}
+
+/// Shows how the daemon's jobs last went, and when they run next
+fn print_status(locations: &StorageLocations) -> Result<()> {
+    let path = DaemonStatus::path(locations);
+    if !path.exists() {
+        println!("The daemon hasn't run yet");
+        return Ok(());
+    }
+    let status: DaemonStatus = std::fs::read_to_string(&path)
+        .map_err(|err| err.to_string())
+        .and_then(|content| serde_json::from_str(&content).map_err(|err| err.to_string()))
+        .map_err(|err| {
+            CliError::new(
+                ExitCode::IO,
+                format!("Failed to read \"{}\": {err}", path.display()),
+            )
+        })?;
+    println!(
+        "Started {} (process {})",
+        format_time(&Some(status.started)),
+        status.pid
+    );
+    let rows: Vec<[String; 4]> = status
+        .jobs
+        .into_iter()
+        .map(|(name, job)| {
+            [
+                name,
+                format_time(&job.last_run),
+                match (&job.last_run, job.last_error) {
+                    (None, _) => String::new(),
+                    (Some(_), None) => String::from("succeeded"),
+                    (Some(_), Some(error)) => format!("failed: {error}"),
+                },
+                format_time(&job.next_run),
+            ]
+        })
+        .collect();
+    print_table(&["Job", "Last Run", "Outcome", "Next Run"], &rows);
+    Ok(())
+}
+
+/// Updates the catalog's datafiles and cuesheets
+fn update(settings: &Settings, locations: &StorageLocations) -> Result<()> {
+    let mut manager = open_manager(settings, locations)?;
+    let changed_consoles = manager.update()?;
+    summary::record_count("consoles_updated", changed_consoles.len());
+    if !changed_consoles.is_empty() {
+        let names: Vec<&str> = changed_consoles
+            .iter()
+            .map(|console| console.formal_name())
+            .collect();
+        log::info!("The catalog changed for {}", names.join(", "));
+    }
+    Ok(())
+}
+
+/// Imports the dumps in the watch folders which exist
+fn import(settings: Settings, locations: &StorageLocations, prompter: &Prompter) -> Result<()> {
+    let folders: Vec<PathBuf> = settings
+        .daemon
+        .watch_folders
+        .iter()
+        .filter(|folder| {
+            let exists = folder.is_dir();
+            if !exists {
+                // like a network share which isn't mounted yet
+                log::warn!(
+                    "Skipping watch folder \"{}\": it isn't there",
+                    folder.display()
+                );
+            }
+            exists
+        })
+        .cloned()
+        .collect();
+    // with no paths, import would fall back on the downloads folder
+    if folders.is_empty() {
+        log::debug!("No watch folders to import");
+        return Ok(());
+    }
+    sort::import(folders, settings, locations, prompter)
+}
+
+/// Runs a job with the current settings, logging and reporting how it went like a command would
+fn run_job(
+    job: Job,
+    locations: &StorageLocations,
+    overrides: &[SettingOverride],
+    prompter: &Prompter,
+) -> Result<()> {
+    log::info!("Starting scheduled {}", job.name());
+    // the scheduled job's warnings are kept apart from anything else this process runs meanwhile
+    let (outcome, warnings) = ndumplib::collect_warnings(|| {
+        Settings::load(locations, overrides).and_then(|mut settings| {
+            // commands run by hand meanwhile hold the data directory for a while at most
+            settings.wait_for_lock = true;
+            settings.apply_tool_paths();
+            settings.apply_hashing_options();
+            settings.apply_work_directory();
+            notify::arm(&settings, job.name());
+            match job {
+                Job::Update => update(&settings, locations),
+                Job::Import => import(settings, locations, prompter),
+            }
+        })
+    });
+    if let Err(err) = &outcome {
+        log::error!("{}", err.message);
+    }
+    summary::log_warnings(&warnings);
+    notify::send(&outcome, &warnings);
+    summary::reset_counts();
+    outcome
+}
+
+/// Stays running, updating the catalog and importing the watch folders on their schedules
+///
+/// The settings are read again before each job, so changes to them apply without a restart. With
+/// `show_status`, shows how the jobs last went instead.
+pub fn run(
+    locations: &StorageLocations,
+    overrides: &[SettingOverride],
+    prompter: &Prompter,
+    show_status: bool,
+) -> Result<()> {
+    if show_status {
+        return print_status(locations);
+    }
+    let started = Local::now();
+    let mut daemon_settings = Settings::load(locations, overrides)?.daemon;
+    let mut status = DaemonStatus {
+        pid: std::process::id(),
+        started: started.to_rfc3339(),
+        jobs: BTreeMap::new(),
+    };
+    // jobs are scheduled from when they last ran, so one which overran doesn't run twice
+    let mut last_runs: BTreeMap<Job, DateTime<Local>> =
+        Job::ALL.into_iter().map(|job| (job, started)).collect();
+    log::info!("Running as a daemon (process {})", status.pid);
+    loop {
+        let mut next_job = None;
+        for job in Job::ALL {
+            let schedule: Schedule = job.schedule(&daemon_settings).parse()?;
+            let next = schedule.next_after(last_runs[&job]);
+            status
+                .jobs
+                .entry(job.name().to_string())
+                .or_default()
+                .next_run = next.map(|next| next.to_rfc3339());
+            if let Some(next) = next
+                && next_job.is_none_or(|(_, time)| next < time)
+            {
+                next_job = Some((job, next));
+            }
+        }
+        status.write(locations);
+        let Some((job, time)) = next_job else {
+            return Err(CliError::new(
+                ExitCode::Config,
+                "None of the daemon's schedules ever come around",
+            ));
+        };
+        log::debug!("Next job: {} at {time}", job.name());
+        loop {
+            let remaining = (time - Local::now()).to_std().unwrap_or_default();
+            if remaining.is_zero() {
+                break;
+            }
+            std::thread::sleep(remaining.min(MAX_SLEEP));
+        }
+        let now = Local::now();
+        last_runs.insert(job, now);
+        let job_status = status.jobs.entry(job.name().to_string()).or_default();
+        job_status.last_run = Some(now.to_rfc3339());
+        job_status.next_run = None;
+        status.write(locations);
+        let outcome = run_job(job, locations, overrides, prompter);
+        status.jobs.get_mut(job.name()).unwrap().last_error = outcome.err().map(|err| err.message);
+        match Settings::load(locations, overrides) {
+            Ok(settings) => daemon_settings = settings.daemon,
+            // the last good schedules are kept until the settings are fixed
+            Err(err) => log::error!("{}", err.message),
+        }
+    }
+}
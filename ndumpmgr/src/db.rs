@@ -0,0 +1,118 @@
+use clap::Subcommand;
+use ndumplib::{Recovery, WarningKind, report_warning};
+
+use crate::{
+    error::{CliError, ExitCode, Result},
+    open_manager,
+    settings::{Settings, StorageLocations},
+};
+
+#[derive(Subcommand)]
+pub enum DbCommand {
+    /// Checks the databases for corruption and orphaned rows
+    Check {
+        /// Deletes orphaned rows and rebuilds the indexes
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Compacts the databases and updates their statistics, which speeds up lookups after big
+    /// catalog updates (this also runs every db_maintenance_interval_days, after an update)
+    Maintain,
+}
+
+/// Checks the databases for corruption and orphaned rows
+fn check(settings: Settings, locations: &StorageLocations, repair: bool) -> Result<()> {
+    let mut manager = open_manager(&settings, locations)?;
+    let checks = manager.check_databases(repair)?;
+    let mut unhealthy = false;
+    for check in checks {
+        if check.is_healthy() {
+            println!("{} DB: OK", check.database);
+            continue;
+        }
+        println!("{} DB:", check.database);
+        for problem in &check.problems {
+            if check.remaining_problems.contains(problem) {
+                println!("  {problem}");
+            } else {
+                println!("  {problem} (repaired)");
+            }
+        }
+        for (table, count) in &check.orphaned_rows {
+            if check.repaired {
+                println!("  Deleted {count} orphaned rows from \"{table}\"");
+            } else {
+                println!("  {count} orphaned rows in \"{table}\"");
+            }
+        }
+        // repairs can't fix corruption that SQLite itself reports, but deleting orphaned rows
+        // fixes the foreign key problems they caused
+        unhealthy |= !check.remaining_problems.is_empty() || !check.repaired;
+    }
+    if !unhealthy {
+        Ok(())
+    } else if repair {
+        Err(CliError::new(
+            ExitCode::CheckFailed,
+            "Some problems couldn't be repaired",
+        ))
+    } else {
+        Err(CliError::new(
+            ExitCode::CheckFailed,
+            "Problems found. Run `ndumpmgr db check --repair` to fix them",
+        ))
+    }
+}
+
+/// Compacts the databases, printing how much smaller they got
+fn maintain(settings: Settings, locations: &StorageLocations) -> Result<()> {
+    let mut manager = open_manager(&settings, locations)?;
+    for maintenance in manager.maintain_databases()? {
+        println!(
+            "{} DB: {:.1} MB -> {:.1} MB",
+            maintenance.database,
+            maintenance.size_before as f64 / 1e6,
+            maintenance.size_after as f64 / 1e6
+        );
+    }
+    Ok(())
+}
+
+/// Reports what was recovered after an unclean shutdown, failing if the databases are still unusable
+pub fn report_recovery(recovery: &Recovery) -> Result<()> {
+    for database in &recovery.replayed_logs {
+        report_warning(
+            WarningKind::Recovery,
+            format!("Restored unsaved changes to the {database} DB"),
+        );
+    }
+    for check in &recovery.checks {
+        for (table, count) in &check.orphaned_rows {
+            report_warning(
+                WarningKind::Recovery,
+                format!(
+                    "Deleted {count} orphaned rows from \"{table}\" in the {} DB",
+                    check.database
+                ),
+            );
+        }
+    }
+    if !recovery.is_complete() {
+        return Err(CliError::new(
+            ExitCode::Database,
+            format!(
+                "The databases couldn't be recovered:\n{}\nRestore them from a backup, or delete them to start over",
+                recovery.remaining_problems.join("\n")
+            ),
+        ));
+    }
+    log::info!("Recovered from the unclean shutdown");
+    Ok(())
+}
+
+pub fn run(command: DbCommand, settings: Settings, locations: &StorageLocations) -> Result<()> {
+    match command {
+        DbCommand::Check { repair } => check(settings, locations, repair),
+        DbCommand::Maintain => maintain(settings, locations),
+    }
+}
@@ -0,0 +1,81 @@
+use ndumplib::ErrorCategory;
+
+/// The exit codes ndumpmgr fails with, so scripts can tell what went wrong
+///
+/// Clap also exits with 2 when the arguments themselves are malformed.
+#[derive(Clone, Copy, Debug)]
+pub enum ExitCode {
+    /// Anything not covered below
+    Failure = 1,
+    /// An argument's value couldn't be parsed, like an unknown console or a malformed query
+    InvalidInput = 2,
+    /// The configuration file or data directory couldn't be used
+    Config = 3,
+    Network = 4,
+    Database = 5,
+    IO = 6,
+    /// A datafile, cuesheet, or archive couldn't be parsed
+    InvalidData = 7,
+    /// chdman isn't installed, isn't on the PATH, or is too old
+    ChdmanMissing = 8,
+    /// A check ran, but found problems (like a hash mismatch)
+    CheckFailed = 9,
+    /// Another instance is using the data directory (see --wait)
+    Busy = 10,
+    /// dolphin-tool isn't installed, isn't on the PATH, or is too old
+    DolphinToolMissing = 11,
+    /// maxcso isn't installed, isn't on the PATH, or is too old
+    MaxcsoMissing = 12,
+    /// 7-Zip isn't installed, or isn't on the PATH
+    SevenZipMissing = 13,
+    /// xdelta3 isn't installed, or isn't on the PATH
+    XdeltaMissing = 14,
+    /// There isn't enough free space for a conversion, extraction, or copy (see the work_directory
+    /// setting)
+    InsufficientSpace = 15,
+}
+
+impl From<ErrorCategory> for ExitCode {
+    fn from(category: ErrorCategory) -> Self {
+        match category {
+            ErrorCategory::IO => Self::IO,
+            ErrorCategory::Network => Self::Network,
+            ErrorCategory::Database => Self::Database,
+            ErrorCategory::InvalidData => Self::InvalidData,
+            ErrorCategory::InvalidInput => Self::InvalidInput,
+            ErrorCategory::ChdmanMissing => Self::ChdmanMissing,
+            ErrorCategory::DolphinToolMissing => Self::DolphinToolMissing,
+            ErrorCategory::MaxcsoMissing => Self::MaxcsoMissing,
+            ErrorCategory::SevenZipMissing => Self::SevenZipMissing,
+            ErrorCategory::XdeltaMissing => Self::XdeltaMissing,
+            ErrorCategory::Busy => Self::Busy,
+            ErrorCategory::VerificationFailed => Self::CheckFailed,
+            ErrorCategory::InsufficientSpace => Self::InsufficientSpace,
+            ErrorCategory::Other => Self::Failure,
+        }
+    }
+}
+
+/// A failure ending the command, which main() logs before exiting with its code
+#[derive(Debug)]
+pub struct CliError {
+    pub code: ExitCode,
+    pub message: String,
+}
+
+impl CliError {
+    pub fn new(code: ExitCode, message: impl Into<String>) -> CliError {
+        CliError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<ndumplib::Error> for CliError {
+    fn from(error: ndumplib::Error) -> Self {
+        CliError::new(error.category().into(), error.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, CliError>;
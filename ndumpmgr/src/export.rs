@@ -0,0 +1,641 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::PathBuf,
+    sync::Mutex,
+    time::Instant,
+};
+
+use clap::{Args, ValueEnum};
+use ndumplib::{
+    CollectionPart, DumpManager, GameConsole, GameQuery, HashAlgorithm, HashingOptions,
+    ImageModification, MultiHasher, ROMStatus, find_split_dumps,
+};
+use serde::{Serialize, Serializer, ser::SerializeStruct};
+use serde_json::{Value, json};
+
+use crate::{
+    error::{CliError, ExitCode, Result},
+    expand_paths, open_manager,
+    settings::{Settings, StorageLocations},
+    storage,
+};
+
+/// The version of `ndumpmgr export --format json`'s output, raised whenever its format changes
+pub const EXPORT_OUTPUT_VERSION: u32 = 4;
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    /// A Logiqx XML datafile, which clrmamepro and RomVault read, for `--what have` and `missing`
+    Dat,
+    /// One game name per line, like clrmamepro's have and miss lists, for `--what have` and
+    /// `missing`
+    Text,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExportTarget {
+    /// The catalog's games, with their categories and ROM hashes
+    Catalog,
+    /// The verification states of the given dumps
+    Library,
+    /// A console's games among the given dumps: as a datafile, the ROMs collected of every game
+    /// with any, and as text, the games collected completely
+    Have,
+    /// A console's games missing from the given dumps: as a datafile, the ROMs missing from every
+    /// game missing any (a fixdat), and as text, the games missing any ROMs
+    Missing,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    #[arg(long, value_enum)]
+    format: ExportFormat,
+    #[arg(long, value_enum, default_value = "catalog")]
+    what: ExportTarget,
+    /// Only exports the catalog's games matching a filter (see `ndumpmgr query`)
+    #[arg(long)]
+    filter: Option<String>,
+    /// The console to export with `--what have` or `missing`, like "psx"
+    #[arg(long)]
+    console: Option<String>,
+    /// The dumps, or folders of dumps, to export with `--what library` (by default, the remote
+    /// library in the storage settings), or to compare with the catalog with `--what have` or
+    /// `missing`
+    paths: Vec<String>,
+    /// Also records each dump's size, CRC32, MD5, SHA-1, and SHA-256 with `--what library`
+    #[arg(long)]
+    hashes: bool,
+    /// Writes the export to a file instead of the standard output
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ExportedROM {
+    name: String,
+    size: u64,
+    crc32: String,
+    md5: String,
+    sha1: String,
+    sha256: Option<String>,
+    status: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+struct ExportedGame {
+    gid: i64,
+    console: String,
+    name: String,
+    revision: i64,
+    datafile: String,
+    categories: Vec<&'static str>,
+    roms: Vec<ExportedROM>,
+}
+
+struct ExportedDump {
+    path: String,
+    status: &'static str,
+    /// Why the dump couldn't be verified (or hashed), if it couldn't
+    error: Option<String>,
+    /// Only with `--hashes`, and `None` inside if the dump couldn't be hashed
+    hashes: Option<Option<DumpHashes>>,
+}
+
+impl Serialize for ExportedDump {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let length = if self.hashes.is_some() { 8 } else { 3 };
+        let mut dump = serializer.serialize_struct("ExportedDump", length)?;
+        dump.serialize_field("path", &self.path)?;
+        dump.serialize_field("status", self.status)?;
+        dump.serialize_field("error", &self.error)?;
+        // every dump gets the same fields, so CSV rows line up even where hashing failed
+        if let Some(hashes) = &self.hashes {
+            let hashes = hashes.as_ref();
+            dump.serialize_field("size", &hashes.map(|hashes| hashes.size))?;
+            dump.serialize_field("crc32", &hashes.map(|hashes| &hashes.crc32))?;
+            dump.serialize_field("md5", &hashes.map(|hashes| &hashes.md5))?;
+            dump.serialize_field("sha1", &hashes.map(|hashes| &hashes.sha1))?;
+            dump.serialize_field("sha256", &hashes.map(|hashes| &hashes.sha256))?;
+        }
+        dump.end()
+    }
+}
+
+struct DumpHashes {
+    size: u64,
+    crc32: String,
+    md5: String,
+    sha1: String,
+    sha256: String,
+}
+
+impl DumpHashes {
+    /// Hashes a dump's file, or a split dump's parts as if they were joined
+    fn of_files(paths: &[PathBuf]) -> ndumplib::Result<DumpHashes> {
+        let digests = match paths {
+            [path] => MultiHasher::all().hash_file(path, true)?,
+            parts => {
+                let failed = |err| ndumplib::Error::new("Failed to hash split dump", err);
+                let mut hasher = MultiHasher::all();
+                let mut buffer = vec![0u8; HashingOptions::DEFAULT_BUFFER_SIZE];
+                for part in parts {
+                    let mut file = File::open(part).map_err(failed)?;
+                    loop {
+                        match file.read(&mut buffer).map_err(failed)? {
+                            0 => break,
+                            read => hasher.update(&buffer[..read]),
+                        }
+                    }
+                }
+                hasher.finalize()
+            }
+        };
+        // every hash was asked for
+        let hash = |algorithm| digests.get(algorithm).unwrap().to_string();
+        Ok(DumpHashes {
+            size: digests.size,
+            crc32: hash(HashAlgorithm::CRC32),
+            md5: hash(HashAlgorithm::MD5),
+            sha1: hash(HashAlgorithm::SHA1),
+            sha256: hash(HashAlgorithm::SHA256),
+        })
+    }
+}
+
+/// A JSON Schema describing the games written by `ndumpmgr export --what catalog --format json`
+pub fn catalog_output_schema() -> Value {
+    let hex = |description: &str| json!({ "type": "string", "description": description });
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "type": "array",
+        "items": {
+            "type": "object",
+            "properties": {
+                "gid": { "type": "integer" },
+                "console": { "type": "string", "description": "The console's short name, like \"psx\"" },
+                "name": { "type": "string" },
+                "revision": { "type": "integer" },
+                "datafile": { "type": "string" },
+                "categories": { "type": "array", "items": { "type": "string" } },
+                "roms": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "name": { "type": "string" },
+                            "size": { "type": "integer" },
+                            "crc32": hex("Lowercase hex"),
+                            "md5": hex("Lowercase hex"),
+                            "sha1": hex("Lowercase hex"),
+                            "sha256": { "type": ["string", "null"] },
+                            "status": { "enum": ["verified", "baddump", null] }
+                        },
+                        "required": ["name", "size", "crc32", "md5", "sha1", "sha256", "status"],
+                        "additionalProperties": false
+                    }
+                }
+            },
+            "required": ["gid", "console", "name", "revision", "datafile", "categories", "roms"],
+            "additionalProperties": false
+        }
+    })
+}
+
+/// A JSON Schema describing the dumps written by `ndumpmgr export --what library --format json`
+pub fn library_output_schema() -> Value {
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "type": "array",
+        "items": {
+            "type": "object",
+            "properties": {
+                "path": { "type": "string", "description": "For dumps split into parts, the first part" },
+                "status": {
+                    "enum": ["verified", "data-partition-verified", "unverified", "nkit", "scrubbed", "broken", "known-bad-dump", "error"],
+                    "description": "nkit and scrubbed dumps were changed from the disc on purpose, so they can't be verified, and known-bad-dump dumps match a ROM the datafile marks as a bad dump"
+                },
+                "error": { "type": ["string", "null"], "description": "Why the dump couldn't be verified" },
+                "size": { "type": ["integer", "null"], "description": "Only with --hashes, like the hashes (null if the dump couldn't be hashed)" },
+                "crc32": { "type": ["string", "null"] },
+                "md5": { "type": ["string", "null"] },
+                "sha1": { "type": ["string", "null"] },
+                "sha256": { "type": ["string", "null"] }
+            },
+            "required": ["path", "status", "error"],
+            "additionalProperties": false
+        }
+    })
+}
+
+fn write_failed(err: impl std::fmt::Display) -> CliError {
+    CliError::new(ExitCode::IO, format!("Failed to write export: {err}"))
+}
+
+pub(crate) fn status_name(status: ROMStatus) -> &'static str {
+    match status {
+        ROMStatus::Verified => "verified",
+        ROMStatus::DataPartitionVerified => "data-partition-verified",
+        ROMStatus::Unverified => "unverified",
+        ROMStatus::Modified(ImageModification::NKit) => "nkit",
+        ROMStatus::Modified(ImageModification::Scrubbed) => "scrubbed",
+        ROMStatus::Broken => "broken",
+        ROMStatus::KnownBadDump => "known-bad-dump",
+    }
+}
+
+/// Writes the catalog's games (or those matching `filter`), one CSV row per ROM
+fn export_catalog(
+    settings: &Settings,
+    locations: &StorageLocations,
+    format: ExportFormat,
+    filter: Option<String>,
+    output: &mut impl Write,
+) -> Result<()> {
+    let reader = open_manager(settings, locations)?.catalog_reader();
+    let games = match filter {
+        Some(filter) => reader.query_games(&filter.parse::<GameQuery>()?)?,
+        None => reader.all_games()?,
+    };
+    let mut exported_games = Vec::with_capacity(games.len());
+    for game in games {
+        let categories = reader.game_categories(game.gid)?;
+        let roms = reader
+            .game_roms(&game)?
+            .into_iter()
+            .map(|rom| ExportedROM {
+                name: rom.name,
+                size: rom.size,
+                crc32: format!("{:08x}", rom.crc32),
+                md5: hex_string(&rom.md5),
+                sha1: hex_string(&rom.sha1),
+                sha256: rom.sha256.map(|sha256| hex_string(&sha256)),
+                status: rom.status,
+            })
+            .collect();
+        exported_games.push(ExportedGame {
+            gid: game.gid,
+            console: game.console.short_name().to_string(),
+            name: game.name,
+            revision: game.revision,
+            datafile: game.datafile,
+            categories,
+            roms,
+        });
+    }
+    match format {
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut *output, &exported_games).map_err(write_failed)?
+        }
+        // CSV, as the other formats are only for collections
+        _ => {
+            let mut writer = csv::Writer::from_writer(&mut *output);
+            writer
+                .write_record([
+                    "gid",
+                    "console",
+                    "game",
+                    "revision",
+                    "datafile",
+                    "categories",
+                    "rom",
+                    "size",
+                    "crc32",
+                    "md5",
+                    "sha1",
+                    "sha256",
+                    "status",
+                ])
+                .map_err(write_failed)?;
+            for game in &exported_games {
+                let categories = game.categories.join(";");
+                for rom in &game.roms {
+                    writer
+                        .write_record([
+                            &game.gid.to_string(),
+                            &game.console,
+                            &game.name,
+                            &game.revision.to_string(),
+                            &game.datafile,
+                            &categories,
+                            &rom.name,
+                            &rom.size.to_string(),
+                            &rom.crc32,
+                            &rom.md5,
+                            &rom.sha1,
+                            rom.sha256.as_deref().unwrap_or(""),
+                            rom.status.unwrap_or(""),
+                        ])
+                        .map_err(write_failed)?;
+                }
+            }
+            writer.flush().map_err(write_failed)?;
+        }
+    }
+    Ok(())
+}
+
+/// Verifying fewer bytes than this finishes too quickly for its progress to be worth logging
+const MIN_PROGRESS_SIZE: u64 = 1 << 30;
+
+/// How far verifying several dumps has got, going by the size of their files, with an estimate of
+/// the time left (which holds up under a max read rate too, since it's measured as it goes)
+pub(crate) struct VerifyProgress {
+    sizes: Vec<u64>,
+    total: u64,
+    start: Instant,
+    /// The dumps verified so far, their bytes, and the tenths of the bytes last logged
+    state: Mutex<(usize, u64, u64)>,
+}
+
+impl VerifyProgress {
+    /// Tracks verifying the dumps made up of each list of files, in order
+    pub fn new<'a>(dumps: impl Iterator<Item = &'a [PathBuf]>) -> VerifyProgress {
+        let sizes: Vec<u64> = dumps
+            .map(|files| {
+                files
+                    .iter()
+                    .filter_map(|file| std::fs::metadata(file).ok())
+                    .map(|metadata| metadata.len())
+                    .sum()
+            })
+            .collect();
+        VerifyProgress {
+            total: sizes.iter().sum(),
+            sizes,
+            start: Instant::now(),
+            state: Mutex::new((0, 0, 0)),
+        }
+    }
+
+    /// Counts a dump as verified, logging the progress every 10%
+    pub fn verified(&self, index: usize) {
+        let mut state = self.state.lock().unwrap();
+        let (dumps, bytes, reported_tenths) = &mut *state;
+        *dumps += 1;
+        *bytes += self.sizes[index];
+        let tenths = *bytes * 10 / self.total.max(1);
+        if self.total < MIN_PROGRESS_SIZE || tenths <= *reported_tenths || *bytes == self.total {
+            return;
+        }
+        *reported_tenths = tenths;
+        let left = match self.seconds_left(*bytes) {
+            Some(seconds) if seconds >= 90 => format!(", about {} minute(s) left", seconds / 60),
+            Some(seconds) => format!(", about {seconds} second(s) left"),
+            None => String::new(),
+        };
+        log::info!(
+            "Verified {dumps} of {} dump(s): {}%{left}",
+            self.sizes.len(),
+            tenths * 10
+        );
+    }
+
+    /// Estimates how long verifying the rest takes, at the pace it's gone so far
+    fn seconds_left(&self, bytes: u64) -> Option<u64> {
+        if bytes == 0 {
+            return None;
+        }
+        let elapsed = self.start.elapsed().as_secs_f64();
+        Some((elapsed / bytes as f64 * (self.total - bytes) as f64).round() as u64)
+    }
+
+    /// Estimates how long verifying the rest takes, from the dumps verified so far
+    pub fn estimate(&self) -> Option<u64> {
+        self.seconds_left(self.state.lock().unwrap().1)
+    }
+}
+
+/// Describes a dump's verification state for exporting
+fn exported_dump(
+    path: String,
+    result: ndumplib::Result<ROMStatus>,
+    hashes: Option<Option<DumpHashes>>,
+) -> ExportedDump {
+    let (status, error) = match result {
+        Ok(status) => (status_name(status), None),
+        Err(err) => ("error", Some(err.to_string())),
+    };
+    ExportedDump {
+        path,
+        status,
+        error,
+        hashes,
+    }
+}
+
+/// Verifies local dumps, hashing them too with `hashes`
+fn verify_local_dumps(
+    manager: &DumpManager,
+    paths: &[String],
+    ignore: &[String],
+    hashes: bool,
+) -> Result<Vec<ExportedDump>> {
+    let files = expand_paths(paths, ignore)?;
+    // split dumps are listed once, under their first part
+    let (split_dumps, files) = find_split_dumps(&files);
+    let progress = VerifyProgress::new(
+        files
+            .iter()
+            .map(std::slice::from_ref)
+            .chain(split_dumps.iter().map(|dump| dump.parts.as_slice())),
+    );
+    let mut results: Vec<(Vec<PathBuf>, ndumplib::Result<ROMStatus>)> = files
+        .iter()
+        .zip(manager.verify_files_with_progress(&files, |index| progress.verified(index)))
+        .map(|(path, result)| (vec![path.clone()], result))
+        .collect();
+    for (index, dump) in split_dumps.into_iter().enumerate() {
+        let result = manager.verify_split(&dump);
+        progress.verified(files.len() + index);
+        results.push((dump.parts, result));
+    }
+    Ok(results
+        .into_iter()
+        .map(|(paths, result)| {
+            let mut hash_error = None;
+            let hashes = hashes.then(|| match DumpHashes::of_files(&paths) {
+                Ok(hashes) => Some(hashes),
+                Err(err) => {
+                    hash_error = Some(err.to_string());
+                    None
+                }
+            });
+            let mut dump = exported_dump(paths[0].to_str().unwrap().to_string(), result, hashes);
+            if dump.error.is_none() {
+                dump.error = hash_error;
+            }
+            dump
+        })
+        .collect())
+}
+
+/// Verifies dumps and writes their verification states
+fn export_library(
+    settings: &Settings,
+    locations: &StorageLocations,
+    format: ExportFormat,
+    paths: &[String],
+    hashes: bool,
+    output: &mut impl Write,
+) -> Result<()> {
+    let manager = open_manager(settings, locations)?;
+    // without any paths, the remote library is exported
+    let remote = match paths.is_empty() {
+        true => storage::open_library(settings)?,
+        false => None,
+    };
+    let dumps = match remote {
+        Some(remote) => {
+            if hashes {
+                return Err(CliError::new(
+                    ExitCode::InvalidInput,
+                    "--hashes can't be used with a remote library, as its dumps would have to be downloaded",
+                ));
+            }
+            remote
+                .verify(&manager, &settings.ignore)?
+                .into_iter()
+                .map(|(path, result)| exported_dump(path, result, None))
+                .collect()
+        }
+        None => verify_local_dumps(&manager, paths, &settings.ignore, hashes)?,
+    };
+    let modified = dumps
+        .iter()
+        .filter(|dump| matches!(dump.status, "nkit" | "scrubbed"))
+        .count();
+    if modified > 0 {
+        log::info!(
+            "{modified} dump(s) are NKit or scrubbed images, which can't match the catalog (NKit can restore NKit images to ISOs)"
+        );
+    }
+    match format {
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut *output, &dumps).map_err(write_failed)?
+        }
+        // CSV, as the other formats are only for collections
+        _ => {
+            let mut writer = csv::Writer::from_writer(&mut *output);
+            for dump in &dumps {
+                writer.serialize(dump).map_err(write_failed)?;
+            }
+            writer.flush().map_err(write_failed)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes a console's games which are collected or missing among the given dumps, for other ROM
+/// managers
+fn export_collection(
+    settings: &Settings,
+    locations: &StorageLocations,
+    format: ExportFormat,
+    console: GameConsole,
+    paths: &[String],
+    part: CollectionPart,
+    output: &mut impl Write,
+) -> Result<()> {
+    let dumps = expand_paths(paths, &settings.ignore)?;
+    let manager = open_manager(settings, locations)?;
+    let games = match format {
+        ExportFormat::Dat => {
+            manager.write_collection_datafile(console, &dumps, part, &mut *output)?
+        }
+        _ => {
+            let names = manager.collection_game_names(console, &dumps, part)?;
+            for name in &names {
+                writeln!(output, "{name}").map_err(write_failed)?;
+            }
+            names.len()
+        }
+    };
+    log::info!(
+        "Exported {games} {} {} game(s)",
+        match part {
+            CollectionPart::Missing => "missing",
+            CollectionPart::Owned => "collected",
+        },
+        console.formal_name()
+    );
+    Ok(())
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Exports the catalog or the given dumps to a file, or the standard output
+pub fn run(settings: Settings, locations: &StorageLocations, args: ExportArgs) -> Result<()> {
+    let ExportArgs {
+        format,
+        what,
+        filter,
+        console,
+        paths,
+        hashes,
+        output,
+    } = args;
+    let invalid = |message: &str| Err(CliError::new(ExitCode::InvalidInput, message));
+    let collection = matches!(what, ExportTarget::Have | ExportTarget::Missing);
+    match (collection, format) {
+        (true, ExportFormat::Csv | ExportFormat::Json) => {
+            return invalid(
+                "`--what have` and `missing` are exported with `--format dat` or `text`",
+            );
+        }
+        (false, ExportFormat::Dat | ExportFormat::Text) => {
+            return invalid("Only `--what have` and `missing` are exported as datafiles or text");
+        }
+        _ => (),
+    }
+    let remote_library =
+        matches!(what, ExportTarget::Library) && settings.storage.library.is_some();
+    if matches!(what, ExportTarget::Library | ExportTarget::Have)
+        && paths.is_empty()
+        && !remote_library
+    {
+        return invalid("There's no library database yet, so give the dumps to export");
+    }
+    let console: Option<GameConsole> = console.map(|console| console.parse()).transpose()?;
+    if collection && console.is_none() {
+        return invalid("Give the console to export with --console");
+    }
+    let mut output: Box<dyn Write> = match &output {
+        Some(path) => Box::new(std::io::BufWriter::new(
+            std::fs::File::create(path).map_err(|err| {
+                CliError::new(ExitCode::IO, format!("Failed to create \"{path}\": {err}"))
+            })?,
+        )),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    match what {
+        ExportTarget::Catalog => export_catalog(&settings, locations, format, filter, &mut output)?,
+        ExportTarget::Library => {
+            export_library(&settings, locations, format, &paths, hashes, &mut output)?
+        }
+        ExportTarget::Have | ExportTarget::Missing => {
+            let part = match what {
+                ExportTarget::Have => CollectionPart::Owned,
+                _ => CollectionPart::Missing,
+            };
+            let console = console.unwrap();
+            export_collection(
+                &settings,
+                locations,
+                format,
+                console,
+                &paths,
+                part,
+                &mut output,
+            )?
+        }
+    }
+    if matches!(format, ExportFormat::Json) {
+        writeln!(output).map_err(write_failed)?;
+    }
+    output.flush().map_err(write_failed)
+}
@@ -0,0 +1,182 @@
+use std::{collections::BTreeMap, path::Path};
+
+use ndumplib::{
+    CatalogReader, FrontendGame, GAMELIST_FILE_NAME, GameConsole, LAUNCHBOX_FILE_NAME,
+    PEGASUS_FILE_NAME, WarningKind, report_warning, update_gamelist, update_launchbox_list,
+    update_pegasus_metadata,
+};
+
+use crate::{
+    collect_files,
+    error::{CliError, ExitCode, Result},
+    open_manager,
+    settings::{Frontend, Settings, StorageLocations},
+    sort::LayoutTemplate,
+};
+
+/// The extensions of files which other files of a game are launched through, and so are listed
+/// in their place (like a cuesheet rather than its tracks)
+pub(crate) const SHEET_EXTENSIONS: [&str; 4] = ["m3u", "cue", "gdi", "ccd"];
+
+impl Frontend {
+    fn name(self) -> &'static str {
+        match self {
+            Frontend::EmulationStation => "EmulationStation",
+            Frontend::Pegasus => "Pegasus",
+            Frontend::LaunchBox => "LaunchBox",
+        }
+    }
+
+    fn file_name(self) -> &'static str {
+        match self {
+            Frontend::EmulationStation => GAMELIST_FILE_NAME,
+            Frontend::Pegasus => PEGASUS_FILE_NAME,
+            Frontend::LaunchBox => LAUNCHBOX_FILE_NAME,
+        }
+    }
+}
+
+/// Finds the games in a console's folder, by their file names
+///
+/// Sorted dumps are named after their games, so a file is taken to be the game it's named after
+/// (like "Game (USA).chd"), without hashing it again. Tracks, companion files, and anything else
+/// not named after a game are left out.
+fn console_games(
+    settings: &Settings,
+    reader: &CatalogReader,
+    console: GameConsole,
+    folder: &Path,
+) -> Result<Vec<FrontendGame>> {
+    let mut files = Vec::new();
+    collect_files(folder, &settings.ignore, &mut files)?;
+    files.sort();
+    let mut games: BTreeMap<i64, FrontendGame> = BTreeMap::new();
+    for file in files {
+        let extension = file
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if settings
+            .companion_extensions
+            .iter()
+            .any(|companion| companion.eq_ignore_ascii_case(&extension))
+        {
+            continue;
+        }
+        let Some(stem) = file.file_stem().map(|stem| stem.to_string_lossy()) else {
+            continue;
+        };
+        let Some(game) = reader
+            .find_games_by_name(&stem)?
+            .into_iter()
+            .find(|game| game.console == console)
+        else {
+            continue;
+        };
+        let is_sheet = SHEET_EXTENSIONS.contains(&extension.as_str());
+        let listed = games.get(&game.gid).is_some_and(|listed| {
+            let listed_extension = listed
+                .path
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            SHEET_EXTENSIONS.contains(&listed_extension.as_str()) || !is_sheet
+        });
+        if !listed {
+            games.insert(
+                game.gid,
+                FrontendGame {
+                    path: file.strip_prefix(folder).unwrap().to_path_buf(),
+                    game,
+                },
+            );
+        }
+    }
+    Ok(games.into_values().collect())
+}
+
+/// Writes the frontends' game lists in each console's folder in the game location
+///
+/// Returns the number of lists which changed.
+pub fn write_lists(
+    settings: &Settings,
+    reader: &CatalogReader,
+    frontends: &[Frontend],
+) -> Result<usize> {
+    let layout = LayoutTemplate::parse(&settings.layout)?;
+    let root = settings.game_location();
+    // whether there's a folder per console depends on the layout alone
+    if layout.console_folder(GameConsole::PSX).is_none() {
+        return Err(CliError::new(
+            ExitCode::Config,
+            "The layout has no folder per console, which frontends need (like \"{console}/{file}\")",
+        ));
+    }
+    let mut changed = 0;
+    for console in GameConsole::ALL {
+        let folder = root.join(layout.console_folder(console).unwrap());
+        if !folder.is_dir() {
+            continue;
+        }
+        let games = console_games(settings, reader, console, &folder)?;
+        for frontend in frontends {
+            let written = match frontend {
+                Frontend::EmulationStation => update_gamelist(&folder, &games)?,
+                Frontend::Pegasus => update_pegasus_metadata(
+                    &folder,
+                    console,
+                    &games,
+                    settings.frontend.pegasus_launch(console),
+                )?,
+                Frontend::LaunchBox => update_launchbox_list(&folder, &games)?,
+            };
+            if written {
+                log::info!(
+                    "Wrote the {} list of {} game(s) in \"{}\"",
+                    frontend.name(),
+                    games.len(),
+                    folder.join(frontend.file_name()).display()
+                );
+                changed += 1;
+            }
+        }
+    }
+    Ok(changed)
+}
+
+/// Writes the game lists of the frontends in the settings after dumps were placed, reporting a
+/// failure as a warning since the dumps themselves were placed
+pub fn after_placing(settings: &Settings, reader: &CatalogReader) {
+    if settings.frontend.lists.is_empty() {
+        return;
+    }
+    if let Err(err) = write_lists(settings, reader, &settings.frontend.lists) {
+        report_warning(
+            WarningKind::SkippedFile,
+            format!("Couldn't write the frontends' game lists: {}", err.message),
+        );
+    }
+}
+
+/// Writes the game lists of the given frontends, or those in the settings
+pub fn run(
+    settings: Settings,
+    locations: &StorageLocations,
+    frontends: Vec<Frontend>,
+) -> Result<()> {
+    let frontends = if frontends.is_empty() {
+        settings.frontend.lists.clone()
+    } else {
+        frontends
+    };
+    if frontends.is_empty() {
+        return Err(CliError::new(
+            ExitCode::InvalidInput,
+            "Give the frontends to write game lists for, or list them in the \"frontend.lists\" setting",
+        ));
+    }
+    let reader = open_manager(&settings, locations)?.catalog_reader();
+    let changed = write_lists(&settings, &reader, &frontends)?;
+    log::info!("Updated {changed} game list(s)");
+    Ok(())
+}
@@ -0,0 +1,168 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::{CliError, ExitCode, Result};
+
+/// The name of the files listing patterns of files to ignore in their folder and its subfolders
+pub const IGNORE_FILE_NAME: &str = ".ndumpignore";
+
+/// A .gitignore-style pattern
+#[derive(Clone)]
+struct Pattern {
+    /// The folder the pattern applies from
+    base: PathBuf,
+    glob: Vec<char>,
+    /// Whether the pattern re-includes what an earlier pattern ignored ("!pattern")
+    negated: bool,
+    /// Whether the pattern only matches folders ("pattern/")
+    folders_only: bool,
+    /// Whether the pattern matches paths from `base` ("saves/*.state"), instead of names at any
+    /// depth ("*.state")
+    anchored: bool,
+}
+
+impl Pattern {
+    /// Parses a line of an ignore file, returning `None` for blank lines and comments
+    ///
+    fn parse(line: &str, base: &Path) -> Option<Pattern> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let (folders_only, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if line.is_empty() {
+            return None;
+        }
+        Some(Pattern {
+            base: base.to_path_buf(),
+            glob: line.chars().collect(),
+            negated,
+            folders_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, path: &Path, is_folder: bool) -> bool {
+        if self.folders_only && !is_folder {
+            return false;
+        }
+        let Ok(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        let text: Vec<char> = if self.anchored {
+            let components: Vec<_> = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect();
+            components.join("/").chars().collect()
+        } else {
+            match relative.file_name() {
+                Some(name) => name.to_string_lossy().chars().collect(),
+                None => return false,
+            }
+        };
+        glob_match(&self.glob, &text)
+    }
+}
+
+/// Matches a glob case-insensitively, where `*` and `?` don't match across folders but `**` does
+fn glob_match(glob: &[char], text: &[char]) -> bool {
+    match glob {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => match rest.strip_prefix(&['/']) {
+            // "**/" matches any number of whole folders, including none
+            Some(rest) => (0..=text.len())
+                .filter(|&skip| skip == 0 || text[skip - 1] == '/')
+                .any(|skip| glob_match(rest, &text[skip..])),
+            None => (0..=text.len()).any(|skip| glob_match(rest, &text[skip..])),
+        },
+        ['*', rest @ ..] => (0..=text.len())
+            .take_while(|&skip| skip == 0 || text[skip - 1] != '/')
+            .any(|skip| glob_match(rest, &text[skip..])),
+        ['?', rest @ ..] => text.first().is_some_and(|c| *c != '/') && glob_match(rest, &text[1..]),
+        [c, rest @ ..] => {
+            text.first()
+                .is_some_and(|t| t.to_lowercase().eq(c.to_lowercase()))
+                && glob_match(rest, &text[1..])
+        }
+    }
+}
+
+/// Patterns of files to leave out when listing a folder, like save states or work in progress
+///
+/// They come from the `ignore` setting, which applies from each folder listed, and from
+/// [IGNORE_FILE_NAME] files, which apply from the folder they're in. As in .gitignore files, the
+/// last matching pattern decides, and nothing in an ignored folder is listed.
+#[derive(Clone, Default)]
+pub struct IgnoreRules {
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreRules {
+    /// Parses patterns which apply from `base`
+    ///
+    pub fn new(patterns: &[String], base: &Path) -> IgnoreRules {
+        IgnoreRules {
+            patterns: patterns
+                .iter()
+                .filter_map(|pattern| Pattern::parse(pattern, base))
+                .collect(),
+        }
+    }
+
+    /// Adds the patterns in a folder's ignore file, if it has one
+    ///
+    pub fn with_folder(&self, folder: &Path) -> Result<IgnoreRules> {
+        let path = folder.join(IGNORE_FILE_NAME);
+        if !path.is_file() {
+            return Ok(self.clone());
+        }
+        let content = std::fs::read_to_string(&path).map_err(|err| {
+            CliError::new(
+                ExitCode::IO,
+                format!("Failed to read \"{}\": {err}", path.display()),
+            )
+        })?;
+        let mut rules = self.clone();
+        rules.patterns.extend(
+            content
+                .lines()
+                .filter_map(|line| Pattern::parse(line, folder)),
+        );
+        Ok(rules)
+    }
+
+    /// Whether a file or folder is left out
+    ///
+    pub fn is_ignored(&self, path: &Path, is_folder: bool) -> bool {
+        if path
+            .file_name()
+            .is_some_and(|name| name == IGNORE_FILE_NAME)
+        {
+            return true;
+        }
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(path, is_folder))
+            .is_some_and(|pattern| !pattern.negated)
+    }
+}
+
+/// Checks a pattern from the settings, returning why it's invalid
+///
+pub fn pattern_problem(pattern: &str) -> Option<&'static str> {
+    if Pattern::parse(pattern, Path::new("")).is_none() {
+        Some("matches nothing")
+    } else {
+        None
+    }
+}
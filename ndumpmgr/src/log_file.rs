@@ -0,0 +1,63 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+};
+
+use log::LevelFilter;
+use simplelog::{ConfigBuilder, SharedLogger, WriteLogger};
+
+/// The log file's name in the data directory
+pub const LOG_FILE_NAME: &str = "ndumpmgr.log";
+
+/// How big the log file gets before it's rotated
+const MAX_LOG_SIZE: u64 = 5 << 20;
+
+/// How many rotated log files are kept (as "ndumpmgr.log.1", "ndumpmgr.log.2", ...)
+const ROTATED_LOGS: u32 = 3;
+
+fn rotated_path(path: &Path, index: u32) -> PathBuf {
+    let mut rotated = OsString::from(path.as_os_str());
+    rotated.push(format!(".{index}"));
+    PathBuf::from(rotated)
+}
+
+/// Moves the log file aside if it's grown too big, dropping the oldest rotated one
+///
+/// It's only checked when a run starts, so a single run's log is never split.
+fn rotate(path: &Path) -> io::Result<()> {
+    if fs::metadata(path).map_or(true, |metadata| metadata.len() < MAX_LOG_SIZE) {
+        return Ok(());
+    }
+    for index in (1..ROTATED_LOGS).rev() {
+        let from = rotated_path(path, index);
+        if from.exists() {
+            fs::rename(&from, rotated_path(path, index + 1))?;
+        }
+    }
+    fs::rename(path, rotated_path(path, 1))
+}
+
+/// Opens the log file for appending, after rotating it if needed
+///
+pub fn open(path: &Path) -> io::Result<File> {
+    rotate(path)?;
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// A logger writing to the log file, for auditing what ndumpmgr did to the collection
+///
+/// Everything down to debug messages is written, with UTC timestamps, whatever is shown on the
+/// terminal. Only ndumpmgr's own messages are written, not those of the libraries it uses.
+pub fn logger(file: File) -> Box<dyn SharedLogger> {
+    let mut config = ConfigBuilder::new();
+    config
+        .set_time_level(LevelFilter::Error)
+        .set_time_format_rfc3339()
+        .set_target_level(LevelFilter::Off)
+        .set_thread_level(LevelFilter::Off)
+        .add_filter_allow_str("ndumpmgr")
+        .add_filter_allow_str("ndumplib");
+    WriteLogger::new(LevelFilter::Debug, config.build(), file)
+}
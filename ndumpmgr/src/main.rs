@@ -1,19 +1,49 @@
-use clap::{Parser, Subcommand};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::{CommandFactory, Parser, Subcommand};
 use log::LevelFilter;
-use ndumplib::DumpManager;
-use simplelog::{ConfigBuilder, TermLogger};
+use ndumplib::{
+    BlockRule, CheckpointPolicy, ConversionJob, ConversionLimits, CsoFormat, CsoOptions,
+    DatafileDiff, DiscFormat, DumpManager, ErrorCategory, FileHash, GameConsole, GameEntry,
+    GameQuery, HashAlgorithm, LocalDatafile, MultiHasher, NetworkTimeouts, ROMStatus,
+    SourceHandling, WarningKind, WhenLocked, is_temporary_file, report_warning,
+};
+use serde::Serialize;
+use simplelog::{CombinedLogger, ConfigBuilder, SharedLogger, TermLogger};
 
+mod backup;
+mod catalog;
+mod companions;
+mod daemon;
+mod db;
+mod error;
+mod export;
+mod frontends;
+mod ignore;
+mod log_file;
+mod mirror;
+mod notify;
+mod prompt;
+mod quarantine;
+mod retroachievements;
+mod schedule;
+mod schema;
+mod serve;
 mod settings;
+mod sort;
+mod storage;
+mod summary;
 
-macro_rules! error_exit {
-    ($($values:expr),*) => {{
-        log::error!($($values),*);
-        std::process::exit(0);
-    }};
-}
-pub(crate) use error_exit;
-
-use crate::settings::StorageLocations;
+use crate::{
+    error::{CliError, ExitCode, Result},
+    ignore::IgnoreRules,
+    prompt::Prompter,
+    settings::{BlocklistEntry, ConversionFormat, StorageLocations},
+};
 
 #[derive(Parser)]
 #[command(
@@ -27,6 +57,40 @@ struct Cli {
     /// Enables verbose logging - detailed info useful for debugging ndumpmgr
     #[arg(short, long)]
     verbose: bool,
+    /// Plain output for screen readers and dumb terminals: no colors or other terminal effects
+    /// (also enabled by NO_COLOR or TERM=dumb)
+    #[arg(long, global = true)]
+    ascii: bool,
+    /// Writes the outcome of the run, its warnings, the time spent in each stage, and the peak memory
+    /// use to a JSON file (also printed with --verbose)
+    #[arg(long, global = true, value_name = "FILE")]
+    summary_json: Option<String>,
+    /// Overrides a setting from the configuration file for this run, like "--set keep_originals=true"
+    /// (settings can also be overridden by NDUMPMGR_* environment variables, like
+    /// NDUMPMGR_KEEP_ORIGINALS, which these take precedence over)
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    set: Vec<String>,
+    /// Uses this configuration file instead of the one in the home directory
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Stores the catalog and other data in this folder instead of the one in the home directory
+    /// (e.g. to work with a library on a network drive)
+    #[arg(long, global = true, value_name = "DIR")]
+    data_dir: Option<PathBuf>,
+    /// Waits for other instances using the data directory to finish, instead of failing
+    #[arg(long, global = true)]
+    wait: bool,
+    /// Writes the log to this file instead of "ndumpmgr.log" in the data directory (the log file
+    /// records every message, with timestamps, so what was done to the collection can be audited)
+    #[arg(long, global = true, value_name = "FILE")]
+    log_file: Option<PathBuf>,
+    /// Answers yes to every confirmation, like deleting sources or replacing files in the library
+    #[arg(short, long, global = true)]
+    yes: bool,
+    /// Never asks for confirmation, skipping whatever would need it (the default when the output
+    /// isn't a terminal)
+    #[arg(long, global = true, conflicts_with = "yes")]
+    no_input: bool,
 }
 
 #[derive(Subcommand)]
@@ -36,31 +100,1026 @@ enum Command {
         /// The path to the dump or folder of dumps
         /// (defaults to the user's download folder)
         path: Option<String>,
+        /// Imports the paths listed in a file instead: one per line, or a JSON array of paths
+        /// (or of objects with a "path", like a previous report's entries)
+        #[arg(long, value_name = "FILE", conflicts_with = "path")]
+        from_list: Option<String>,
+    },
+    /// Repairs the library from folders of dumps (or archives of them), copying in the files of
+    /// games which are missing or don't verify
+    ///
+    /// The sources are left as they are, so they can be other collections or downloads.
+    Rebuild {
+        /// The folders, dumps, or archives to take dumps from
+        #[arg(long, required = true, num_args = 1..)]
+        from: Vec<String>,
+    },
+    /// Updates the catalog, then moves the stored game dumps into the folders given by the layout
+    /// setting (by default, one per console), and links them into any mirrors (see the mirrors
+    /// setting)
+    Sort {
+        /// Updates datafiles and cuesheets even if they were updated recently
+        /// ("all", "redump", "no-intro", or a console like "psx")
+        #[arg(long, value_name = "TARGET", num_args = 0..=1, default_missing_value = "all")]
+        force_update: Vec<String>,
+    },
+    /// Renames dumps to their names in the catalog, including region and revision tags
+    ///
+    /// Cuesheets next to renamed tracks are updated to match.
+    Rename {
+        /// The dumps, or folders of dumps, to rename
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// Shows what would be renamed without renaming anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Converts cuesheets, GDIs, and ISOs to CHDs, or the format their console is given in the
+    /// conversion_formats setting (like CSOs for PSP ISOs, or ZIPs for cartridge ROMs)
+    ///
+    /// Sources are deleted once the converted file checks out and its data is in the catalog.
+    Convert {
+        /// The dumps, or folders of dumps, to convert
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// The folder to write the converted files to (defaults to each dump's own folder)
+        #[arg(short, long, value_name = "DIR")]
+        output: Option<String>,
+        /// Keeps the sources, even once their converted file checks out
+        #[arg(long)]
+        keep_sources: bool,
+    },
+    /// Verifies CHDs by extracting and hashing their tracks
+    ///
+    /// For CHDs whose header doesn't match the catalog, like ones created from differently split
+    /// tracks. CHDs whose header matches aren't extracted, and neither are DVD CHDs, which have no
+    /// tracks.
+    ReverifyChd {
+        /// The CHDs, or folders of CHDs, to verify
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// Also decompresses each CHD with `chdman verify`, even if it isn't due yet (see
+        /// deep_chd_verification_interval_days)
+        #[arg(long)]
+        deep: bool,
+    },
+    /// Matches disc tracks by their contents, whatever they're named, and lists which of each game's
+    /// tracks were found
+    Tracks {
+        /// The tracks, or folders of tracks, to match
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Writes a Logiqx datafile of a console's games which are missing from a collection, for other
+    /// ROM managers or to track what's left to collect
+    Fixdat {
+        /// The console, like "psx"
+        #[arg(long)]
+        console: String,
+        /// The path to write the datafile to
+        #[arg(short, long, value_name = "FILE")]
+        output: String,
+        /// Dumps, or folders of dumps, which are already collected and so left out
+        #[arg(long, value_name = "PATH", num_args = 1..)]
+        have: Vec<String>,
+    },
+    /// Checks a file against an expected hash, independent of the catalog
+    #[command(group(clap::ArgGroup::new("expected").required(true).multiple(true)))]
+    Check {
+        /// The path to the file to check
+        file: String,
+        /// The expected SHA-1 hash (hex)
+        #[arg(long, group = "expected")]
+        sha1: Option<String>,
+        /// The expected MD5 hash (hex)
+        #[arg(long, group = "expected")]
+        md5: Option<String>,
+        /// The expected CRC32 hash (hex)
+        #[arg(long, group = "expected")]
+        crc32: Option<String>,
+        /// The expected SHA-256 hash (hex)
+        #[arg(long, group = "expected")]
+        sha256: Option<String>,
+    },
+    /// Applies an IPS, BPS, or xdelta patch (like a translation or a hack) to a dump in the catalog,
+    /// storing the patched dump in the library's patched_folder
+    #[command(group(clap::ArgGroup::new("expected").multiple(false)))]
+    Patch {
+        /// The dump to patch
+        dump: String,
+        /// The patch to apply
+        patch: String,
+        /// The patched dump's name, without its extension (defaults to the patch's)
+        #[arg(long)]
+        name: Option<String>,
+        /// The patched dump's expected SHA-1 hash (hex), like one published with the patch
+        #[arg(long, group = "expected")]
+        sha1: Option<String>,
+        /// The patched dump's expected MD5 hash (hex)
+        #[arg(long, group = "expected")]
+        md5: Option<String>,
+        /// The patched dump's expected CRC32 hash (hex)
+        #[arg(long, group = "expected")]
+        crc32: Option<String>,
+    },
+    /// Inspects the catalog of known games, or adds games of your own
+    Catalog {
+        #[command(subcommand)]
+        command: catalog::CatalogCommand,
+    },
+    /// Lists or restores the files import quarantined
+    Quarantine {
+        #[command(subcommand)]
+        command: quarantine::QuarantineCommand,
+    },
+    /// Checks the configuration file
+    Config {
+        #[command(subcommand)]
+        command: settings::ConfigCommand,
     },
-    /// Sorts the currently stored game dumps by console
-    Sort {},
+    /// Maintains the catalog and cuesheet databases
+    Db {
+        #[command(subcommand)]
+        command: db::DbCommand,
+    },
+    /// Backs up the databases and config file to an archive, or restores them from one
+    Backup {
+        #[command(subcommand)]
+        command: backup::BackupCommand,
+    },
+    /// Stays running, updating the catalog and importing the watch folders on the schedules in
+    /// the daemon settings (for running as a service)
+    Daemon {
+        /// Shows how the daemon's jobs last went, and when they run next, instead
+        #[arg(long)]
+        status: bool,
+    },
+    /// Writes game lists for frontends in each console's folder, so they show the library with the
+    /// catalog's names (ES-DE's gamelist.xml, Pegasus's metadata.pegasus.txt, or a LaunchBox XML)
+    Frontends {
+        /// The frontends to write lists for (by default, those in the settings)
+        #[arg(value_enum)]
+        frontends: Vec<settings::Frontend>,
+    },
+    /// Serves a REST API on a local port, for web dashboards and other tools: the library, catalog
+    /// searches, verify and import jobs, and their progress as server-sent events
+    Serve(serve::ServeArgs),
+    /// Checks which dumps RetroAchievements will recognize, by computing the hashes it identifies
+    /// games by (which skip headers and such, following its rules for each console) and looking
+    /// them up in its list of known hashes
+    RaCheck(retroachievements::RaCheckArgs),
+    /// Lists the catalog's games matching a filter, like
+    /// "console=psx AND category=Games AND name LIKE '%Final Fantasy%'"
+    ///
+    /// Filters compare name, console, datafile, category, gid, revision, serial, or region using =,
+    /// !=, <, <=, >, >=, LIKE, or NOT LIKE, combined with AND, OR, NOT, and parentheses.
+    Query {
+        /// The filter to match games against
+        filter: String,
+        /// Prints the games as JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Exports the catalog's games with their categories and ROM hashes, or the verification states
+    /// of dumps, for spreadsheets or scripts
+    Export(export::ExportArgs),
+    /// Prints the databases' schemas and the formats of JSON outputs, for building other tools on them
+    Schema {
+        /// Prints the schemas as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Shows the differences between two versions of a datafile
+    DatDiff {
+        /// The path to the older datafile
+        old: String,
+        /// The path to the newer datafile
+        new: String,
+    },
+}
+
+/// Reads the paths in a list file for `import --from-list`
+///
+/// Paths which no longer exist are skipped with a warning.
+fn read_path_list(list_path: &str) -> Result<Vec<PathBuf>> {
+    let content = std::fs::read_to_string(list_path).map_err(|err| {
+        CliError::new(
+            ExitCode::IO,
+            format!("Failed to read list \"{list_path}\": {err}"),
+        )
+    })?;
+    let paths: Vec<PathBuf> = if content.trim_start().starts_with('[') {
+        let entries: Vec<serde_json::Value> = serde_json::from_str(&content).map_err(|err| {
+            CliError::new(
+                ExitCode::InvalidInput,
+                format!("Malformed list \"{list_path}\": {err}"),
+            )
+        })?;
+        entries
+            .iter()
+            .map(|entry| match entry {
+                serde_json::Value::String(path) => Ok(PathBuf::from(path)),
+                _ => match entry.get("path").and_then(|path| path.as_str()) {
+                    Some(path) => Ok(PathBuf::from(path)),
+                    None => Err(CliError::new(
+                        ExitCode::InvalidInput,
+                        format!(
+                            "Malformed list \"{list_path}\": expected paths or objects with a \"path\", got {entry}"
+                        ),
+                    )),
+                },
+            })
+            .collect::<Result<_>>()?
+    } else {
+        content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .collect()
+    };
+    Ok(paths
+        .into_iter()
+        .filter(|path| {
+            let exists = path.exists();
+            if !exists {
+                report_warning(
+                    WarningKind::SkippedFile,
+                    format!("Skipping \"{}\", which no longer exists", path.display()),
+                );
+            }
+            exists
+        })
+        .collect())
+}
+
+/// The timeouts of downloads, from the settings
+fn network_timeouts(settings: &settings::Settings) -> NetworkTimeouts {
+    NetworkTimeouts {
+        connect: Duration::from_secs(settings.connect_timeout_seconds),
+        read: Duration::from_secs(settings.read_timeout_seconds),
+        deadline: Duration::from_secs(settings.download_deadline_minutes * 60),
+    }
+}
+
+/// Opens the dump manager's databases, registering the datafile sources from the settings
+fn open_manager(
+    settings: &settings::Settings,
+    locations: &StorageLocations,
+) -> Result<DumpManager> {
+    let when_locked = if settings.wait_for_lock {
+        WhenLocked::Wait
+    } else {
+        WhenLocked::Fail
+    };
+    let manager = if settings.read_only_data {
+        DumpManager::open_read_only(&locations.data_path)?
+    } else {
+        DumpManager::init(
+            &locations.data_path.as_path().to_str().unwrap(),
+            when_locked,
+        )?
+        .with_checkpoint_policy(CheckpointPolicy {
+            on_close: settings.wal_checkpoint_on_close,
+            truncate: settings.truncate_wal,
+            auto_checkpoint_pages: settings.wal_autocheckpoint_pages,
+        })
+    };
+    let mut manager = manager
+        .with_datafile_update_delay(Duration::from_secs(
+            settings.datafile_update_delay_hours.saturating_mul(60 * 60),
+        ))
+        .with_cuesheet_update_delay(Duration::from_secs(
+            settings.cuesheet_update_delay_hours.saturating_mul(60 * 60),
+        ))
+        .with_network_timeouts(network_timeouts(settings))
+        .with_cso_options(CsoOptions {
+            block_size: settings.maxcso_block_size,
+            threads: settings.maxcso_threads,
+        })
+        .with_conversion_limits(ConversionLimits {
+            jobs: settings.conversion_jobs,
+            chdman_threads: settings.chdman_threads,
+            jobs_per_device: settings.conversion_jobs_per_disk,
+        })
+        .with_maintenance_interval((settings.db_maintenance_interval_days > 0).then(|| {
+            Duration::from_secs(
+                settings
+                    .db_maintenance_interval_days
+                    .saturating_mul(24 * 60 * 60),
+            )
+        }))
+        .with_deep_chd_verification_interval(
+            (settings.deep_chd_verification_interval_days > 0).then(|| {
+                Duration::from_secs(
+                    settings
+                        .deep_chd_verification_interval_days
+                        .saturating_mul(24 * 60 * 60),
+                )
+            }),
+        )
+        .with_partition_hashing(settings.wii_partition_hashing);
+    if let Some(recovery) = manager.recovery() {
+        db::report_recovery(recovery)?;
+    }
+    for source in &settings.datafiles {
+        // a bad console here is a mistake in the configuration, not the arguments
+        let console: GameConsole = source
+            .console
+            .parse()
+            .map_err(|err: ndumplib::Error| CliError::new(ExitCode::Config, err.to_string()))?;
+        manager.add_local_datafile(LocalDatafile {
+            console,
+            path: source.path.clone(),
+            priority: source.priority,
+        });
+    }
+    for entry in &settings.blocklist {
+        manager.add_block_rule(match entry {
+            BlocklistEntry::Gid(gid) => BlockRule::Gid(*gid),
+            BlocklistEntry::Pattern(pattern) => BlockRule::Pattern(pattern.clone()),
+        });
+    }
+    Ok(manager)
+}
+
+/// Lists the files in a folder and its subfolders, leaving out those matching the `ignore` patterns
+/// (see [IgnoreRules])
+fn collect_files(directory: &Path, ignore: &[String], files: &mut Vec<PathBuf>) -> Result<()> {
+    collect_folder_files(directory, &IgnoreRules::new(ignore, directory), files)
+}
+
+fn collect_folder_files(
+    directory: &Path,
+    rules: &IgnoreRules,
+    files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let rules = rules.with_folder(directory)?;
+    let entries = std::fs::read_dir(directory).map_err(|err| {
+        CliError::new(
+            ExitCode::IO,
+            format!("Failed to read folder \"{}\": {err}", directory.display()),
+        )
+    })?;
+    // subfolders come first, so the data parts of Xbox 360 Games on Demand packages are handled
+    // while their header, which identifies them, is still next to them
+    let mut folder_files = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|err| CliError::new(ExitCode::IO, err.to_string()))?
+            .path();
+        let is_folder = path.is_dir();
+        // files still being written (or left half-written) are never dumps
+        if rules.is_ignored(&path, is_folder) || (!is_folder && is_temporary_file(&path)) {
+            log::debug!("Ignoring \"{}\"", path.display());
+        } else if is_folder {
+            collect_folder_files(&path, &rules, files)?;
+        } else {
+            folder_files.push(path);
+        }
+    }
+    files.append(&mut folder_files);
+    Ok(())
+}
+
+/// Lists the given files, along with the files in the given folders and their subfolders (except
+/// ignored ones)
+fn expand_paths(paths: &[String], ignore: &[String]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths.iter().map(PathBuf::from) {
+        if path.is_dir() {
+            collect_files(&path, ignore, &mut files)?;
+        } else if path.exists() {
+            files.push(path);
+        } else {
+            return Err(CliError::new(
+                ExitCode::IO,
+                format!("\"{}\" doesn't exist", path.display()),
+            ));
+        }
+    }
+    Ok(files)
+}
+
+/// Renames dumps to their names in the catalog
+fn rename(
+    settings: settings::Settings,
+    locations: &StorageLocations,
+    paths: Vec<String>,
+    dry_run: bool,
+) -> Result<()> {
+    let files = expand_paths(&paths, &settings.ignore)?;
+    let manager = open_manager(&settings, locations)?;
+    let plan = manager.plan_renames(&files)?;
+    for rename in &plan.renames {
+        log::info!(
+            "{} \"{}\" to \"{}\"",
+            if dry_run { "Would rename" } else { "Renaming" },
+            rename.from.display(),
+            rename.to.file_name().unwrap().to_str().unwrap()
+        );
+    }
+    for collision in &plan.collisions {
+        report_warning(
+            WarningKind::SkippedFile,
+            format!(
+                "Not renaming \"{}\": \"{}\" is already taken",
+                collision.from.display(),
+                collision.to.display()
+            ),
+        );
+    }
+    if !plan.unidentified.is_empty() {
+        log::info!(
+            "{} file(s) aren't in the catalog, so were left alone",
+            plan.unidentified.len()
+        );
+    }
+    if !dry_run {
+        manager.apply_renames(&plan.renames)?;
+    }
+    Ok(())
+}
+
+/// Converts dumps to CHDs, deleting their sources unless `keep_sources` (or the prompter declines)
+fn convert(
+    settings: settings::Settings,
+    locations: &StorageLocations,
+    prompter: &Prompter,
+    paths: Vec<String>,
+    output: Option<String>,
+    keep_sources: bool,
+) -> Result<()> {
+    let files = expand_paths(&paths, &settings.ignore)?;
+    let manager = open_manager(&settings, locations)?;
+    let sources = if keep_sources {
+        SourceHandling::Keep
+    } else {
+        SourceHandling::RemoveVerified
+    };
+    let mut converted = 0;
+    let mut failures = Vec::new();
+    // CHDs are created together at the end, since several chdmans can run at once
+    let mut chd_jobs = Vec::new();
+    // cartridge ROMs are only converted if some console's are zipped
+    let zips = settings
+        .conversion_formats
+        .values()
+        .any(|format| *format == ConversionFormat::Zip);
+    for file in files
+        .iter()
+        .filter(|file| manager.can_convert(file) || (zips && manager.can_zip(file)))
+    {
+        let output_directory = match &output {
+            Some(output) => PathBuf::from(output),
+            None => file.parent().unwrap().to_path_buf(),
+        };
+        let sources = if sources == SourceHandling::RemoveVerified
+            && !prompter.confirm(&format!(
+                "Delete \"{}\" once it's converted and verified?",
+                file.display()
+            ))? {
+            SourceHandling::Keep
+        } else {
+            sources
+        };
+        // only dumps of consoles with another format need identifying
+        let format = if settings.conversion_formats.is_empty() {
+            ConversionFormat::Chd
+        } else {
+            manager
+                .get_rom_info(file.to_str().unwrap())?
+                .map_or(ConversionFormat::Chd, |info| {
+                    settings.conversion_format(info.console)
+                })
+        };
+        if format != ConversionFormat::Zip && !manager.can_convert(file) {
+            continue;
+        }
+        log::info!("Converting \"{}\" to {}", file.display(), format.name());
+        let (input, output_directory) =
+            (file.to_str().unwrap(), output_directory.to_str().unwrap());
+        let result = match format {
+            ConversionFormat::Chd => {
+                chd_jobs.push(ConversionJob {
+                    path: file.clone(),
+                    output_directory: PathBuf::from(output_directory),
+                    sources,
+                });
+                continue;
+            }
+            ConversionFormat::Cso => {
+                manager.convert_to_cso(input, output_directory, CsoFormat::CSO1, sources)
+            }
+            ConversionFormat::Zso => {
+                manager.convert_to_cso(input, output_directory, CsoFormat::ZSO, sources)
+            }
+            ConversionFormat::Rvz => {
+                manager.convert_disc_image(input, output_directory, DiscFormat::RVZ, sources)
+            }
+            ConversionFormat::Zip => manager.convert_to_zip(input, output_directory, sources),
+        };
+        let result = match result {
+            Ok(result) => result,
+            // a dump too big for the room left is passed over, since smaller ones may still fit
+            Err(err) if err.category() == ErrorCategory::InsufficientSpace => {
+                log::error!("{err}");
+                failures.push(err.category());
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        match result {
+            Some(_) => converted += 1,
+            None if format == ConversionFormat::Zip => log::warn!(
+                "\"{}\" can't be converted to a ZIP, only cartridge ROMs in the catalog can",
+                file.display()
+            ),
+            None => log::warn!(
+                "\"{}\" can't be converted to {}, only ISOs can",
+                file.display(),
+                format.name()
+            ),
+        }
+    }
+    // the rest of the queue has already run, so each failure is logged rather than stopping
+    for result in manager.convert_files(&chd_jobs) {
+        match result {
+            Ok(_) => converted += 1,
+            Err(err) => {
+                log::error!("{err}");
+                failures.push(err.category());
+            }
+        }
+    }
+    log::info!("Converted {converted} dump(s)");
+    match failures.first() {
+        Some(&category) => Err(CliError::new(
+            category.into(),
+            format!("Failed to convert {} dump(s)", failures.len()),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Verifies CHDs by their tracks, logging each one's verification state
+///
+/// With `deep`, each CHD is decompressed to check its data against its header too.
+fn reverify_chd(
+    settings: settings::Settings,
+    locations: &StorageLocations,
+    paths: Vec<String>,
+    deep: bool,
+) -> Result<()> {
+    let files = expand_paths(&paths, &settings.ignore)?;
+    let manager = open_manager(&settings, locations)?.with_deep_chd_verification(deep);
+    let chds: Vec<&PathBuf> = files
+        .iter()
+        .filter(|file| file.extension().is_some_and(|extension| extension == "chd"))
+        .collect();
+    let mut verified = 0;
+    for chd in &chds {
+        let status = manager.reverify_chd(chd)?;
+        if matches!(status, ROMStatus::Verified) {
+            verified += 1;
+        }
+        log::info!("\"{}\": {}", chd.display(), export::status_name(status));
+    }
+    log::info!("Verified {verified} of {} CHD(s)", chds.len());
+    Ok(())
 }
 
-/// Imports a game dump or folder of game dumps
-fn import(_path: Option<String>, _settings: settings::Settings) {}
+/// Lists the games with tracks among the given files, with how many of their tracks were found
+fn tracks(
+    settings: settings::Settings,
+    locations: &StorageLocations,
+    paths: Vec<String>,
+) -> Result<()> {
+    let files = expand_paths(&paths, &settings.ignore)?;
+    let manager = open_manager(&settings, locations)?;
+    let matches = manager.match_tracks(&files)?;
+    if matches.games.is_empty() {
+        println!("No tracks found");
+    } else {
+        let rows: Vec<[String; 4]> = matches
+            .games
+            .iter()
+            .map(|game| {
+                [
+                    game.name.clone(),
+                    game.console.formal_name().to_string(),
+                    format!(
+                        "{}/{}",
+                        game.found.len(),
+                        game.found.len() + game.missing.len()
+                    ),
+                    game.missing.join(", "),
+                ]
+            })
+            .collect();
+        catalog::print_table(&["Game", "Console", "Tracks", "Missing"], &rows);
+    }
+    if !matches.unmatched.is_empty() {
+        log::info!(
+            "{} file(s) aren't tracks in the catalog",
+            matches.unmatched.len()
+        );
+    }
+    Ok(())
+}
 
-/// Sorts the currently stored game dumps by console
-fn sort(_settings: settings::Settings, locations: &StorageLocations) {
-    // setup databases
-    let mut manager = DumpManager::init(&locations.default_data_path.as_path().to_str().unwrap())
-        .unwrap_or_else(|err| error_exit!("{}", err));
-    manager
-        .update()
-        .unwrap_or_else(|err| error_exit!("{}", err));
+/// Writes a datafile of a console's games which are missing from a collection
+fn fixdat(
+    settings: settings::Settings,
+    locations: &StorageLocations,
+    console: String,
+    output: String,
+    have: Vec<String>,
+) -> Result<()> {
+    let console: GameConsole = console.parse()?;
+    let dumps = expand_paths(&have, &settings.ignore)?;
+    let manager = open_manager(&settings, locations)?;
+    let file = std::fs::File::create(&output).map_err(|err| {
+        CliError::new(
+            ExitCode::IO,
+            format!("Failed to create \"{output}\": {err}"),
+        )
+    })?;
+    let games = manager.write_fixdat(console, &dumps, std::io::BufWriter::new(file))?;
+    log::info!(
+        "Wrote {games} missing {} game(s) to \"{output}\"",
+        console.formal_name()
+    );
+    Ok(())
+}
+
+/// Checks a file against an expected hash, independent of the catalog
+fn check(
+    file: String,
+    sha1: Option<String>,
+    md5: Option<String>,
+    crc32: Option<String>,
+    sha256: Option<String>,
+) -> Result<()> {
+    let expected_hashes = [
+        (HashAlgorithm::SHA1, sha1),
+        (HashAlgorithm::MD5, md5),
+        (HashAlgorithm::CRC32, crc32),
+        (HashAlgorithm::SHA256, sha256),
+    ]
+    .into_iter()
+    .filter_map(|(algorithm, value)| Some(FileHash::from_hex(algorithm, &value?)))
+    .collect::<ndumplib::Result<Vec<FileHash>>>()?;
+    // every hash is computed in one read of the file
+    let algorithms: Vec<HashAlgorithm> = expected_hashes
+        .iter()
+        .map(|expected| expected.algorithm())
+        .collect();
+    let digests = MultiHasher::new(&algorithms).hash_file(&file, true)?;
+    let mut mismatched = false;
+    for expected in expected_hashes {
+        let algorithm = expected.algorithm();
+        let actual = digests.get(algorithm).unwrap();
+        if actual == expected {
+            log::info!("{}: OK ({})", algorithm.formal_name(), actual);
+        } else {
+            log::error!(
+                "{}: MISMATCH (expected {}, got {})",
+                algorithm.formal_name(),
+                expected,
+                actual
+            );
+            mismatched = true;
+        }
+    }
+    if mismatched {
+        return Err(CliError::new(
+            ExitCode::CheckFailed,
+            format!("\"{file}\" does not match the expected hash"),
+        ));
+    }
+    Ok(())
+}
+
+/// Patches a dump in the catalog, storing the result in the patched folder
+fn patch_dump(
+    settings: settings::Settings,
+    locations: &StorageLocations,
+    dump: String,
+    patch: String,
+    name: Option<String>,
+    expected: Option<FileHash>,
+) -> Result<()> {
+    let manager = open_manager(&settings, locations)?;
+    let Some(info) = manager.get_rom_info(&dump)? else {
+        return Err(CliError::new(
+            ExitCode::InvalidInput,
+            format!("\"{dump}\" isn't in the catalog, so there's no telling what the patch is for"),
+        ));
+    };
+    let name = match name {
+        Some(name) => name,
+        None => Path::new(&patch)
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+    };
+    // the patched dump is the same kind of file as the dump
+    let file_name = match Path::new(&dump).extension() {
+        Some(extension) => format!("{name}.{}", extension.to_string_lossy()),
+        None => name,
+    };
+    let output = settings
+        .game_location()
+        .join(&settings.patched_folder)
+        .join(info.console.formal_name())
+        .join(file_name);
+    std::fs::create_dir_all(output.parent().unwrap()).map_err(|err| {
+        CliError::new(
+            ExitCode::IO,
+            format!(
+                "Failed to create \"{}\": {err}",
+                output.parent().unwrap().display()
+            ),
+        )
+    })?;
+    let patched = manager.patch_dump(&dump, &patch, &output, expected)?;
+    log::info!(
+        "Patched {} into \"{}\" (SHA-1 {})",
+        patched.base.game_name,
+        output.display(),
+        FileHash::SHA1(patched.digests.sha1.unwrap())
+    );
+    if expected.is_none() {
+        log::info!(
+            "The patched dump wasn't checked against a hash, since none was given (see --sha1)"
+        );
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct QueryResult {
+    gid: i64,
+    console: String,
+    name: String,
+    revision: i64,
+    datafile: String,
+    serial: Option<String>,
+    region: Option<String>,
+}
+
+impl From<GameEntry> for QueryResult {
+    fn from(game: GameEntry) -> Self {
+        QueryResult {
+            gid: game.gid,
+            console: game.console.short_name().to_string(),
+            name: game.name,
+            revision: game.revision,
+            datafile: game.datafile,
+            serial: game.serial,
+            region: game.region,
+        }
+    }
+}
+
+/// Lists the catalog's games matching a filter
+fn query(
+    settings: settings::Settings,
+    locations: &StorageLocations,
+    filter: String,
+    json: bool,
+) -> Result<()> {
+    let query: GameQuery = filter.parse()?;
+    let games = open_manager(&settings, locations)?
+        .catalog_reader()
+        .query_games(&query)?;
+    let results: Vec<QueryResult> = games.into_iter().map(QueryResult::from).collect();
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&results)
+                .map_err(|err| CliError::new(ExitCode::Failure, err.to_string()))?
+        );
+        return Ok(());
+    }
+    if results.is_empty() {
+        println!("No matching games");
+        return Ok(());
+    }
+    let rows: Vec<[String; 6]> = results
+        .into_iter()
+        .map(|result| {
+            [
+                result.gid.to_string(),
+                result.console,
+                result.name,
+                result.region.unwrap_or_default(),
+                result.revision.to_string(),
+                result.datafile,
+            ]
+        })
+        .collect();
+    catalog::print_table(
+        &["GID", "Console", "Name", "Region", "Revision", "Datafile"],
+        &rows,
+    );
+    Ok(())
+}
+
+/// Shows the differences between two versions of a datafile
+fn dat_diff(old: String, new: String) -> Result<()> {
+    let diff = DatafileDiff::from_files(&old, &new)?;
+    println!("Comparing {} -> {}", diff.old_version, diff.new_version);
+    if diff.is_empty() {
+        println!("No differences");
+        return Ok(());
+    }
+    let sha1_or_none = |sha1: Option<[u8; 20]>| match sha1 {
+        Some(sha1) => FileHash::SHA1(sha1).to_string(),
+        None => String::from("(none)"),
+    };
+    if !diff.added.is_empty() {
+        println!("\nAdded ({}):", diff.added.len());
+        for name in &diff.added {
+            println!("  + {name}");
+        }
+    }
+    if !diff.removed.is_empty() {
+        println!("\nRemoved ({}):", diff.removed.len());
+        for name in &diff.removed {
+            println!("  - {name}");
+        }
+    }
+    if !diff.renamed.is_empty() {
+        println!("\nRenamed ({}):", diff.renamed.len());
+        for (old_name, new_name) in &diff.renamed {
+            println!("  {old_name}\n    -> {new_name}");
+        }
+    }
+    if !diff.changed.is_empty() {
+        println!("\nChanged hashes ({}):", diff.changed.len());
+        for change in &diff.changed {
+            println!(
+                "  {} / {}\n    {} -> {}",
+                change.game,
+                change.rom,
+                sha1_or_none(change.old_sha1),
+                sha1_or_none(change.new_sha1)
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Adds the default options from the settings to the command line
+///
+/// Each option (with the values following it) is only added if the command being run accepts it,
+/// and it wasn't given already. They go right after the subcommand, so they can't end up after a
+/// "--" and be taken as positional arguments.
+fn with_default_args(mut args: Vec<OsString>, defaults: &[String]) -> Vec<OsString> {
+    let mut cli_command = Cli::command();
+    cli_command.build();
+    // everything after "--" is positional
+    let mut end = args
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(args.len());
+    // find the (sub)command being run
+    let mut command = &cli_command;
+    let mut insert_at = 1.min(end);
+    for (index, arg) in args.iter().enumerate().take(end).skip(1) {
+        if let Some(subcommand) = arg.to_str().and_then(|arg| command.find_subcommand(arg)) {
+            command = subcommand;
+            insert_at = index + 1;
+        }
+    }
+    let mut options: Vec<Vec<&String>> = Vec::new();
+    for default in defaults {
+        match options.last_mut() {
+            Some(option) if !default.starts_with('-') => option.push(default),
+            _ => options.push(vec![default]),
+        }
+    }
+    for option in options {
+        // the default may be spelled differently than on the command line, like "--yes" and "-y"
+        let Some(arg) = find_option(command, option[0]) else {
+            continue;
+        };
+        if !option_given(command, arg, &args[1..end]) {
+            let count = option.len();
+            args.splice(insert_at..insert_at, option.into_iter().map(OsString::from));
+            insert_at += count;
+            end += count;
+        }
+    }
+    args
+}
+
+/// Finds the argument of `command` an option like "--yes", "--output=FILE" or "-y" names, by its
+/// long or short name or one of their aliases
+fn find_option<'a>(command: &'a clap::Command, option: &str) -> Option<&'a clap::Arg> {
+    let name = option.split('=').next().unwrap();
+    match (name.strip_prefix("--"), name.strip_prefix('-')) {
+        (Some(long), _) => command.get_arguments().find(|arg| {
+            arg.get_long() == Some(long)
+                || arg
+                    .get_all_aliases()
+                    .is_some_and(|aliases| aliases.contains(&long))
+        }),
+        (None, Some(short)) => {
+            let mut chars = short.chars();
+            match (chars.next(), chars.next()) {
+                (Some(short), None) => find_short_option(command, short),
+                _ => None,
+            }
+        }
+        (None, None) => None,
+    }
+}
+
+fn find_short_option(command: &clap::Command, short: char) -> Option<&clap::Arg> {
+    command.get_arguments().find(|arg| {
+        arg.get_short() == Some(short)
+            || arg
+                .get_all_short_aliases()
+                .is_some_and(|aliases| aliases.contains(&short))
+    })
+}
+
+/// Whether `option` is among `args`, under any of its names, including within a cluster of short
+/// flags like "-vy"
+fn option_given(command: &clap::Command, option: &clap::Arg, args: &[OsString]) -> bool {
+    args.iter().filter_map(|arg| arg.to_str()).any(|arg| {
+        if arg.starts_with("--") {
+            return find_option(command, arg).is_some_and(|arg| arg.get_id() == option.get_id());
+        }
+        let Some(shorts) = arg.strip_prefix('-') else {
+            return false;
+        };
+        for short in shorts.chars() {
+            let Some(arg) = find_short_option(command, short) else {
+                return false;
+            };
+            if arg.get_id() == option.get_id() {
+                return true;
+            }
+            // the rest of the cluster is this option's value, like "-oFILE"
+            if arg.get_action().takes_values() {
+                return false;
+            }
+        }
+        false
+    })
+}
+
+/// Finds an option's value on the command line before it's parsed, like "--config FILE" or
+/// "--config=FILE"
+fn raw_option(args: &[OsString], name: &str) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        let arg = arg.to_str()?;
+        if arg == name {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(value) = arg
+            .strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            return Some(PathBuf::from(value));
+        }
+    }
+    None
 }
 
 fn main() {
-    // parse cli arguments
-    let cli = Cli::parse();
+    // parse cli arguments, with the defaults from the settings (run() reports any problem loading
+    // them, once logging is set up)
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let locations = settings::StorageLocations::find(
+        raw_option(&args, "--config"),
+        raw_option(&args, "--data-dir"),
+    )
+    .ok();
+    let default_args = locations
+        .as_ref()
+        .and_then(|locations| {
+            settings::Settings::load(locations, &settings::SettingOverride::from_env()).ok()
+        })
+        .map(|settings| settings.args)
+        .unwrap_or_default();
+    let args = with_default_args(args, &default_args);
+    let command_line: Vec<_> = args.iter().map(|arg| arg.to_string_lossy()).collect();
+    let command_line = command_line.join(" ");
+    let cli = Cli::parse_from(args);
     // initialize logger
+    let plain_output = cli.ascii
+        || std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+        || std::env::var("TERM").is_ok_and(|term| term == "dumb");
     let mut logger_config = ConfigBuilder::new();
     logger_config.set_time_level(LevelFilter::Off);
-    TermLogger::init(
+    let mut loggers: Vec<Box<dyn SharedLogger>> = vec![TermLogger::new(
         if cli.verbose {
             simplelog::LevelFilter::Debug
         } else {
@@ -68,16 +1127,156 @@ fn main() {
         },
         logger_config.build(),
         simplelog::TerminalMode::Mixed,
-        simplelog::ColorChoice::Auto,
-    )
-    .unwrap();
+        if plain_output {
+            simplelog::ColorChoice::Never
+        } else {
+            simplelog::ColorChoice::Auto
+        },
+    )];
+    let log_path = cli
+        .log_file
+        .clone()
+        .or_else(|| locations.map(|locations| locations.data_path.join(log_file::LOG_FILE_NAME)));
+    let log_file_error = log_path.and_then(|path| match log_file::open(&path) {
+        Ok(file) => {
+            loggers.push(log_file::logger(file));
+            None
+        }
+        Err(err) => Some((path, err)),
+    });
+    // the API streams what its jobs log to its clients
+    if matches!(cli.command, Some(Command::Serve(_))) {
+        loggers.push(serve::event_logger());
+    }
+    CombinedLogger::init(loggers).unwrap();
+    if let Some((path, err)) = log_file_error {
+        log::warn!("Couldn't open the log file \"{}\": {err}", path.display());
+    }
+    log::debug!("Running \"{command_line}\"");
+    // run command, logging the error that stopped it if there is one
+    let prompter = Prompter::new(cli.yes, cli.no_input);
+    let outcome = run(
+        cli.command,
+        &cli.set,
+        cli.config,
+        cli.data_dir,
+        cli.wait,
+        &prompter,
+    );
+    if let Err(err) = &outcome {
+        log::error!("{}", err.message);
+    }
+    let warnings = ndumplib::take_warnings();
+    summary::log_warnings(&warnings);
+    let timings = ndumplib::run_timings();
+    summary::log_timings(&timings);
+    if let Some(path) = cli.summary_json
+        && let Err(err) = summary::write(&path, &outcome, &warnings, &timings)
+    {
+        log::error!("{}", err.message);
+    }
+    notify::send(&outcome, &warnings);
+    if let Err(err) = outcome {
+        log::debug!("Exiting with code {}", err.code as i32);
+        std::process::exit(err.code as i32);
+    }
+    log::debug!("Finished");
+}
+
+/// Runs a command with the user's settings, and the settings given by `--set` flags
+fn run(
+    command: Option<Command>,
+    set: &[String],
+    config: Option<PathBuf>,
+    data_dir: Option<PathBuf>,
+    wait: bool,
+    prompter: &Prompter,
+) -> Result<()> {
     // load settings
-    let locations = settings::StorageLocations::default();
-    let settings = settings::Settings::load(&locations);
-    // run command
-    match cli.command {
-        Some(Command::Import { path }) => import(path, settings),
-        Some(Command::Sort {}) => sort(settings, &locations),
-        None => {}
+    let locations = settings::StorageLocations::find(config, data_dir)?;
+    let mut overrides = settings::SettingOverride::from_env();
+    for flag in set {
+        overrides.push(settings::SettingOverride::parse_flag(flag)?);
+    }
+    let mut settings = settings::Settings::load(&locations, &overrides)?;
+    settings.wait_for_lock |= wait;
+    settings.apply_tool_paths();
+    settings.apply_hashing_options();
+    settings.apply_work_directory();
+    // these can run for a long time unattended, so they say how they went
+    match &command {
+        Some(Command::Import { .. }) => notify::arm(&settings, "import"),
+        Some(Command::Rebuild { .. }) => notify::arm(&settings, "rebuild"),
+        Some(Command::Sort { .. }) => notify::arm(&settings, "sort"),
+        Some(Command::ReverifyChd { .. }) => notify::arm(&settings, "reverify-chd"),
+        _ => (),
+    }
+    match command {
+        Some(Command::Import { path, from_list }) => {
+            let paths = match (path, from_list) {
+                (_, Some(list_path)) => read_path_list(&list_path)?,
+                (Some(path), None) => vec![PathBuf::from(path)],
+                (None, None) => Vec::new(),
+            };
+            sort::import(paths, settings, &locations, prompter)
+        }
+        Some(Command::Rebuild { from }) => sort::rebuild(from, settings, &locations, prompter),
+        Some(Command::Sort { force_update }) => {
+            sort::run(settings, &locations, prompter, force_update)
+        }
+        Some(Command::Rename { paths, dry_run }) => rename(settings, &locations, paths, dry_run),
+        Some(Command::Convert {
+            paths,
+            output,
+            keep_sources,
+        }) => convert(settings, &locations, prompter, paths, output, keep_sources),
+        Some(Command::ReverifyChd { paths, deep }) => {
+            reverify_chd(settings, &locations, paths, deep)
+        }
+        Some(Command::Tracks { paths }) => tracks(settings, &locations, paths),
+        Some(Command::Fixdat {
+            console,
+            output,
+            have,
+        }) => fixdat(settings, &locations, console, output, have),
+        Some(Command::Check {
+            file,
+            sha1,
+            md5,
+            crc32,
+            sha256,
+        }) => check(file, sha1, md5, crc32, sha256),
+        Some(Command::Patch {
+            dump,
+            patch,
+            name,
+            sha1,
+            md5,
+            crc32,
+        }) => {
+            let expected = [
+                (HashAlgorithm::SHA1, sha1),
+                (HashAlgorithm::MD5, md5),
+                (HashAlgorithm::CRC32, crc32),
+            ]
+            .into_iter()
+            .find_map(|(algorithm, value)| Some(FileHash::from_hex(algorithm, &value?)))
+            .transpose()?;
+            patch_dump(settings, &locations, dump, patch, name, expected)
+        }
+        Some(Command::Catalog { command }) => catalog::run(command, settings, &locations),
+        Some(Command::Db { command }) => db::run(command, settings, &locations),
+        Some(Command::Backup { command }) => backup::run(command, settings, &locations, prompter),
+        Some(Command::Daemon { status }) => daemon::run(&locations, &overrides, prompter, status),
+        Some(Command::Frontends { frontends }) => frontends::run(settings, &locations, frontends),
+        Some(Command::Serve(args)) => serve::run(args, settings, &locations, &overrides, prompter),
+        Some(Command::Config { command }) => settings::run(command, &locations),
+        Some(Command::Quarantine { command }) => quarantine::run(command, settings, &locations),
+        Some(Command::RaCheck(args)) => retroachievements::run(settings, &locations, args),
+        Some(Command::Query { filter, json }) => query(settings, &locations, filter, json),
+        Some(Command::Export(args)) => export::run(settings, &locations, args),
+        Some(Command::Schema { json }) => schema::run(settings, &locations, json),
+        Some(Command::DatDiff { old, new }) => dat_diff(old, new),
+        None => Ok(()),
     }
 }
@@ -0,0 +1,204 @@
+use std::{
+    fs::Metadata,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use ndumplib::{
+    DumpManager, LinkMethod, ROMInfo, SplitDump, WarningKind, duplicate_file, report_warning,
+};
+
+use crate::{
+    error::Result,
+    settings::MirrorSettings,
+    sort::{LayoutTemplate, copy_progress},
+    summary,
+};
+
+/// A folder `sort` fills with the library's dumps in a layout of its own, duplicating them without
+/// taking up more space where the filesystem allows it (see [MirrorSettings])
+///
+/// Companions (like saves) aren't mirrored, since they change after they're placed.
+pub struct Mirror {
+    root: PathBuf,
+    layout: LayoutTemplate,
+    method: LinkMethod,
+    hard_linked: usize,
+    reflinked: usize,
+    copied: usize,
+    /// Files which were already in the mirror
+    unchanged: usize,
+    /// Files in the mirror which were replaced, since the library's had changed
+    refreshed: usize,
+}
+
+impl Mirror {
+    pub fn new(settings: &MirrorSettings) -> Result<Mirror> {
+        Ok(Mirror {
+            root: settings.location.clone(),
+            layout: LayoutTemplate::parse(&settings.layout)?,
+            method: settings.link.method(),
+            hard_linked: 0,
+            reflinked: 0,
+            copied: 0,
+            unchanged: 0,
+            refreshed: 0,
+        })
+    }
+
+    /// Duplicates a dump placed at `file` in the library to where the mirror's layout puts it
+    pub fn add(&mut self, info: &ROMInfo, file: &Path) {
+        let target = self.root.join(self.layout.render(info));
+        self.duplicate(file, &target);
+    }
+
+    /// Duplicates the parts of a split dump placed at `target` in the library (named like
+    /// [SplitDump::part_name]) to where the mirror's layout puts them
+    pub fn add_split(&mut self, info: &ROMInfo, dump: &SplitDump, target: &Path) {
+        let library_name = target.file_name().unwrap().to_string_lossy().into_owned();
+        let mirror_target = self.root.join(self.layout.render(info));
+        let mirror_name = mirror_target
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        for index in 0..dump.parts.len() {
+            self.duplicate(
+                &target.with_file_name(dump.part_name(index, &library_name)),
+                &mirror_target.with_file_name(dump.part_name(index, &mirror_name)),
+            );
+        }
+    }
+
+    /// Duplicates a cuesheet or GDI placed at `sheet` in the library, and the tracks next to it,
+    /// to where the mirror's layout puts the sheet
+    ///
+    /// The tracks keep their names, which the sheet refers to them by.
+    pub fn add_disc(&mut self, manager: &DumpManager, info: &ROMInfo, sheet: &Path) {
+        let tracks = match manager.disc_tracks(&sheet) {
+            Ok(tracks) => tracks,
+            Err(err) => {
+                report_warning(
+                    WarningKind::SkippedFile,
+                    format!(
+                        "Not mirroring \"{}\": {}",
+                        sheet.display(),
+                        err.to_string().replace('\n', ": ")
+                    ),
+                );
+                return;
+            }
+        };
+        let target = self.root.join(self.layout.render(info));
+        for track in &tracks {
+            self.duplicate(track, &target.with_file_name(track.file_name().unwrap()));
+        }
+        self.duplicate(sheet, &target);
+    }
+
+    /// Duplicates one file into the mirror, reporting a warning if it can't be
+    ///
+    /// A file already at `target` is kept if it's still a duplicate of `file` (see [is_current]),
+    /// and replaced otherwise, as the library's file was replaced since it was mirrored.
+    fn duplicate(&mut self, file: &Path, target: &Path) {
+        if let Ok(existing) = std::fs::symlink_metadata(target) {
+            if !existing.is_file() {
+                report_warning(
+                    WarningKind::SkippedFile,
+                    format!(
+                        "Not mirroring \"{}\": \"{}\" is already taken",
+                        file.display(),
+                        target.display()
+                    ),
+                );
+                return;
+            }
+            if std::fs::metadata(file).is_ok_and(|metadata| is_current(&metadata, &existing)) {
+                self.unchanged += 1;
+                return;
+            }
+            if let Err(err) = std::fs::remove_file(target) {
+                report_warning(
+                    WarningKind::SkippedFile,
+                    format!(
+                        "Not mirroring \"{}\": couldn't remove the outdated \"{}\": {err}",
+                        file.display(),
+                        target.display()
+                    ),
+                );
+                return;
+            }
+            log::debug!("Removed outdated \"{}\"", target.display());
+            self.refreshed += 1;
+        }
+        let result = std::fs::create_dir_all(target.parent().unwrap())
+            .map_err(|err| err.to_string())
+            .and_then(|_| {
+                duplicate_file(file, target, self.method, &mut copy_progress(file))
+                    .map_err(|err| err.to_string().replace('\n', ": "))
+            });
+        match result {
+            Ok(method) => {
+                log::debug!(
+                    "{} \"{}\" to \"{}\"",
+                    match method {
+                        LinkMethod::HardLink => "Hard-linked",
+                        LinkMethod::Reflink => "Reflinked",
+                        LinkMethod::Copy => "Copied",
+                    },
+                    file.display(),
+                    target.display()
+                );
+                match method {
+                    LinkMethod::HardLink => self.hard_linked += 1,
+                    LinkMethod::Reflink => self.reflinked += 1,
+                    LinkMethod::Copy => self.copied += 1,
+                }
+            }
+            Err(err) => report_warning(
+                WarningKind::SkippedFile,
+                format!("Not mirroring \"{}\": {err}", file.display()),
+            ),
+        }
+    }
+
+    /// Logs how the mirror's new files were duplicated, and adds them to the summary
+    pub fn report(&self) {
+        summary::record_count("mirror_hard_linked", self.hard_linked);
+        summary::record_count("mirror_reflinked", self.reflinked);
+        summary::record_count("mirror_copied", self.copied);
+        summary::record_count("mirror_refreshed", self.refreshed);
+        log::info!(
+            "Mirrored {} file(s) to \"{}\": {} hard-linked, {} reflinked, {} copied ({} already there, {} replacing outdated ones)",
+            self.hard_linked + self.reflinked + self.copied,
+            self.root.display(),
+            self.hard_linked,
+            self.reflinked,
+            self.copied,
+            self.unchanged,
+            self.refreshed
+        );
+        if self.copied > 0 {
+            log::warn!(
+                "{} file(s) were copied to \"{}\", since they couldn't be linked. Hard links only work within a filesystem, and reflinks need one like btrfs or XFS",
+                self.copied,
+                self.root.display()
+            );
+        }
+    }
+}
+
+/// Whether a file in a mirror is still a duplicate of the library's file
+///
+/// A hard link is the library's file itself. Reflinks and copies are made after the library's file
+/// is placed, so one which is older (or a different size) was made from a file since replaced.
+fn is_current(library: &Metadata, mirrored: &Metadata) -> bool {
+    if library.dev() == mirrored.dev() && library.ino() == mirrored.ino() {
+        return true;
+    }
+    library.len() == mirrored.len()
+        && matches!(
+            (library.modified(), mirrored.modified()),
+            (Ok(library), Ok(mirrored)) if mirrored >= library
+        )
+}
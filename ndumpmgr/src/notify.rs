@@ -0,0 +1,146 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    process::{Command, Stdio},
+    sync::Mutex,
+    time::Duration,
+};
+
+use ndumplib::Warning;
+use serde_json::{Value, json};
+
+use crate::{
+    error::Result,
+    settings::{NotificationSettings, Settings, WebhookFormat},
+    summary,
+};
+
+/// Sends a summary of a command's run where the settings say to, once it finishes
+pub struct Notifier {
+    settings: NotificationSettings,
+    /// The command being run, like "sort"
+    command: &'static str,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+}
+
+static PENDING: Mutex<Option<Notifier>> = Mutex::new(None);
+
+/// Sends a summary of `command` when the run finishes (see [send]), if notifications are set up
+pub fn arm(settings: &Settings, command: &'static str) {
+    let notifications = &settings.notifications;
+    if notifications.webhook_url.is_none() && notifications.command.is_empty() {
+        return;
+    }
+    *PENDING.lock().unwrap() = Some(Notifier {
+        settings: notifications.clone(),
+        command,
+        connect_timeout: Duration::from_secs(settings.connect_timeout_seconds),
+        read_timeout: Duration::from_secs(settings.read_timeout_seconds),
+    });
+}
+
+/// Sends the summary of the run's command, if one was armed
+///
+/// Notifications which can't be sent are logged, but don't fail the run.
+pub fn send(outcome: &Result<()>, warnings: &[Warning]) {
+    let Some(notifier) = PENDING.lock().unwrap().take() else {
+        return;
+    };
+    if notifier.settings.only_problems && outcome.is_ok() && warnings.is_empty() {
+        return;
+    }
+    let counts = summary::counts();
+    let text = notifier.text(outcome, warnings, &counts);
+    if let Some(url) = &notifier.settings.webhook_url {
+        let body = match notifier.settings.webhook_format {
+            WebhookFormat::Json => notifier.json(outcome, warnings, &counts),
+            WebhookFormat::Discord => json!({ "content": text }),
+            WebhookFormat::Slack => json!({ "text": text }),
+        };
+        if let Err(err) = notifier.post(url, &body) {
+            log::warn!("Couldn't send the summary to the webhook: {err}");
+        }
+    }
+    if let Some((program, args)) = notifier.settings.command.split_first()
+        && let Err(err) = run_command(program, args, &text)
+    {
+        log::warn!("Couldn't send the summary to \"{program}\": {err}");
+    }
+}
+
+impl Notifier {
+    /// The summary as a short message, like "ndumpmgr sort finished" followed by its counts
+    fn text(
+        &self,
+        outcome: &Result<()>,
+        warnings: &[Warning],
+        counts: &BTreeMap<&'static str, usize>,
+    ) -> String {
+        let mut lines = vec![match outcome {
+            Ok(()) => format!("ndumpmgr {} finished", self.command),
+            Err(err) => format!("ndumpmgr {} failed: {}", self.command, err.message),
+        }];
+        for (name, count) in counts {
+            lines.push(format!("{name}: {count}"));
+        }
+        if !warnings.is_empty() {
+            lines.push(format!("warnings: {}", warnings.len()));
+            for warning in warnings {
+                lines.push(format!("- {}", warning.message));
+            }
+        }
+        lines.join("\n")
+    }
+
+    fn json(
+        &self,
+        outcome: &Result<()>,
+        warnings: &[Warning],
+        counts: &BTreeMap<&'static str, usize>,
+    ) -> Value {
+        json!({
+            "command": self.command,
+            "exit_code": match outcome {
+                Ok(()) => 0,
+                Err(err) => err.code as i32,
+            },
+            "error": outcome.as_ref().err().map(|err| err.message.as_str()),
+            "counts": counts,
+            "warnings": warnings
+                .iter()
+                .map(|warning| json!({ "kind": warning.kind.name(), "message": warning.message }))
+                .collect::<Vec<Value>>(),
+        })
+    }
+
+    fn post(&self, url: &str, body: &Value) -> std::result::Result<(), ureq::Error> {
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_connect(Some(self.connect_timeout))
+            .timeout_recv_response(Some(self.read_timeout))
+            .build()
+            .into();
+        agent
+            .post(url)
+            .header("Content-Type", "application/json")
+            .send(body.to_string())?;
+        log::debug!("Sent the summary to \"{url}\"");
+        Ok(())
+    }
+}
+
+/// Runs a program with `text` on its standard input, failing if it exits unsuccessfully
+fn run_command(program: &str, args: &[String], text: &str) -> std::io::Result<()> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    // the program may not read its input, which is fine
+    let _ = child.stdin.take().unwrap().write_all(text.as_bytes());
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!("it exited with {status}")))
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    cell::Cell,
+    io::{BufRead, IsTerminal, Write},
+};
+
+use crate::error::{CliError, ExitCode, Result};
+
+/// How questions about destructive operations get answered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PromptMode {
+    /// Asks the user on the terminal
+    Interactive,
+    /// Answers yes to everything (`--yes`)
+    AssumeYes,
+    /// Never asks, declining anything destructive (`--no-input`, or when there's no terminal)
+    AssumeNo,
+}
+
+/// Asks before deleting sources, replacing files in the library, or quarantining files
+///
+/// Commands consult it before each destructive step, and skip the step if it's declined, as they
+/// would have without a prompt.
+pub struct Prompter {
+    mode: PromptMode,
+    /// Set once the user answers "all", so they aren't asked again this run
+    accept_all: Cell<bool>,
+}
+
+impl Prompter {
+    /// Picks the mode from the `--yes` and `--no-input` flags, asking only if stdout is a terminal
+    ///
+    pub fn new(yes: bool, no_input: bool) -> Prompter {
+        let mode = if yes {
+            PromptMode::AssumeYes
+        } else if no_input || !std::io::stdout().is_terminal() {
+            PromptMode::AssumeNo
+        } else {
+            PromptMode::Interactive
+        };
+        Prompter {
+            mode,
+            accept_all: Cell::new(false),
+        }
+    }
+
+    /// A prompter for work done away from the terminal, like jobs `serve` runs, which says yes to
+    /// everything only if this one does
+    ///
+    pub fn non_interactive(&self) -> Prompter {
+        Prompter {
+            mode: match self.mode {
+                PromptMode::AssumeYes => PromptMode::AssumeYes,
+                PromptMode::Interactive | PromptMode::AssumeNo => PromptMode::AssumeNo,
+            },
+            accept_all: Cell::new(false),
+        }
+    }
+
+    /// Asks a yes/no question, returning whether the step should go ahead
+    ///
+    /// Anything but "y", "yes", "a", or "all" is a no, including the end of the input.
+    pub fn confirm(&self, question: &str) -> Result<bool> {
+        let accepted = match self.mode {
+            PromptMode::AssumeYes => true,
+            PromptMode::AssumeNo => false,
+            PromptMode::Interactive if self.accept_all.get() => true,
+            PromptMode::Interactive => {
+                let failed = |err: std::io::Error| {
+                    CliError::new(ExitCode::IO, format!("Failed to read the answer: {err}"))
+                };
+                let mut stdout = std::io::stdout().lock();
+                write!(stdout, "{question} [y/N/a(ll)] ").map_err(failed)?;
+                stdout.flush().map_err(failed)?;
+                let mut answer = String::new();
+                std::io::stdin()
+                    .lock()
+                    .read_line(&mut answer)
+                    .map_err(failed)?;
+                match answer.trim().to_lowercase().as_str() {
+                    "y" | "yes" => true,
+                    "a" | "all" => {
+                        self.accept_all.set(true);
+                        true
+                    }
+                    _ => false,
+                }
+            }
+        };
+        log::debug!("{question} {}", if accepted { "Yes" } else { "No" });
+        Ok(accepted)
+    }
+}
@@ -0,0 +1,213 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::Subcommand;
+use ndumplib::{TransferMode, WarningKind, report_warning, transfer_file};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    catalog::print_table,
+    error::{CliError, ExitCode, Result},
+    settings::{Settings, StorageLocations},
+};
+
+/// Added to a quarantined file's name for the sidecar explaining why it's there
+const SIDECAR_EXTENSION: &str = "quarantine.json";
+
+#[derive(Subcommand)]
+pub enum QuarantineCommand {
+    /// Lists the quarantined files, and why they were quarantined
+    List,
+    /// Moves quarantined files back to where they were imported from
+    Restore {
+        /// The names of the files to restore, as listed
+        #[arg(required_unless_present = "all")]
+        names: Vec<String>,
+        /// Restores every quarantined file
+        #[arg(long, conflicts_with = "names")]
+        all: bool,
+    },
+}
+
+/// Why a file was quarantined, stored next to it
+#[derive(Serialize, Deserialize)]
+struct QuarantineRecord {
+    original_path: PathBuf,
+    reason: String,
+    /// Seconds since the Unix epoch
+    quarantined_at: u64,
+}
+
+fn sidecar_path(file: &Path) -> PathBuf {
+    let mut name = file.file_name().unwrap().to_os_string();
+    name.push(".");
+    name.push(SIDECAR_EXTENSION);
+    file.with_file_name(name)
+}
+
+fn io_error(action: &str, path: &Path, err: impl std::fmt::Display) -> CliError {
+    CliError::new(
+        ExitCode::IO,
+        format!("Failed to {action} \"{}\": {err}", path.display()),
+    )
+}
+
+/// Moves (or copies) a file into the quarantine folder, with a sidecar giving `reason`
+///
+/// Files are renamed like "name (2).bin" if the name is already taken there.
+pub fn quarantine(directory: &Path, file: &Path, reason: &str, mode: TransferMode) -> Result<()> {
+    fs::create_dir_all(directory)
+        .map_err(|err| io_error("create quarantine folder", directory, err))?;
+    let name = Path::new(file.file_name().unwrap());
+    let mut target = directory.join(name);
+    let mut copy = 1;
+    while target.exists() || sidecar_path(&target).exists() {
+        copy += 1;
+        let stem = name.file_stem().unwrap().to_string_lossy();
+        target = directory.join(match name.extension() {
+            Some(extension) => format!("{stem} ({copy}).{}", extension.to_string_lossy()),
+            None => format!("{stem} ({copy})"),
+        });
+    }
+    let record = QuarantineRecord {
+        original_path: std::path::absolute(file)
+            .map_err(|err| io_error("quarantine", file, err))?,
+        reason: reason.to_string(),
+        quarantined_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0),
+    };
+    transfer_file(file, &target, mode, &mut |_, _| {})
+        .map_err(|err| io_error("quarantine", file, err))?;
+    fs::write(
+        sidecar_path(&target),
+        serde_json::to_string_pretty(&record).unwrap(),
+    )
+    .map_err(|err| io_error("write sidecar for", &target, err))?;
+    log::info!("Quarantined \"{}\": {reason}", file.display());
+    Ok(())
+}
+
+/// Lists the quarantined files along with their records, by name
+fn quarantined_files(directory: &Path) -> Result<Vec<(PathBuf, QuarantineRecord)>> {
+    if !directory.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(directory).map_err(|err| io_error("read", directory, err))? {
+        let path = entry
+            .map_err(|err| io_error("read", directory, err))?
+            .path();
+        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let Some(name) = file_name.strip_suffix(&format!(".{SIDECAR_EXTENSION}")) else {
+            continue;
+        };
+        let content = fs::read_to_string(&path).map_err(|err| io_error("read", &path, err))?;
+        let record: QuarantineRecord = serde_json::from_str(&content).map_err(|err| {
+            CliError::new(
+                ExitCode::InvalidData,
+                format!("Malformed sidecar \"{}\": {err}", path.display()),
+            )
+        })?;
+        files.push((directory.join(name), record));
+    }
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(files)
+}
+
+/// Lists the quarantined files, and why they were quarantined
+fn list(directory: &Path) -> Result<()> {
+    let files = quarantined_files(directory)?;
+    if files.is_empty() {
+        println!("Nothing is quarantined");
+        return Ok(());
+    }
+    let rows: Vec<[String; 3]> = files
+        .into_iter()
+        .map(|(path, record)| {
+            [
+                path.file_name().unwrap().to_string_lossy().to_string(),
+                record.reason,
+                record.original_path.display().to_string(),
+            ]
+        })
+        .collect();
+    print_table(&["Name", "Reason", "Original path"], &rows);
+    Ok(())
+}
+
+/// Moves quarantined files back to where they were imported from
+fn restore(directory: &Path, names: Vec<String>, all: bool) -> Result<()> {
+    let files = quarantined_files(directory)?;
+    for name in &names {
+        if !files.iter().any(|(path, _)| {
+            path.file_name()
+                .is_some_and(|file_name| file_name == name.as_str())
+        }) {
+            return Err(CliError::new(
+                ExitCode::InvalidInput,
+                format!("\"{name}\" isn't quarantined"),
+            ));
+        }
+    }
+    let mut restored = 0;
+    for (path, record) in files {
+        if !all
+            && !names.iter().any(|name| {
+                path.file_name()
+                    .is_some_and(|file_name| file_name == name.as_str())
+            })
+        {
+            continue;
+        }
+        if record.original_path.exists() {
+            report_warning(
+                WarningKind::SkippedFile,
+                format!(
+                    "Not restoring \"{}\": \"{}\" is already taken",
+                    path.display(),
+                    record.original_path.display()
+                ),
+            );
+            continue;
+        }
+        if let Some(parent) = record.original_path.parent() {
+            fs::create_dir_all(parent).map_err(|err| io_error("create", parent, err))?;
+        }
+        transfer_file(
+            &path,
+            &record.original_path,
+            TransferMode::Move,
+            &mut |_, _| {},
+        )
+        .map_err(|err| io_error("restore", &path, err))?;
+        let sidecar = sidecar_path(&path);
+        fs::remove_file(&sidecar).map_err(|err| io_error("remove", &sidecar, err))?;
+        log::debug!(
+            "Restored \"{}\" to \"{}\"",
+            path.display(),
+            record.original_path.display()
+        );
+        restored += 1;
+    }
+    log::info!("Restored {restored} file(s)");
+    Ok(())
+}
+
+pub fn run(
+    command: QuarantineCommand,
+    settings: Settings,
+    locations: &StorageLocations,
+) -> Result<()> {
+    let directory = settings.quarantine_location(locations);
+    match command {
+        QuarantineCommand::List => list(&directory),
+        QuarantineCommand::Restore { names, all } => restore(&directory, names, all),
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    collections::{BTreeMap, HashMap, hash_map::Entry},
+    path::PathBuf,
+};
+
+use clap::Args;
+use ndumplib::{
+    GameConsole, fetch_retroachievements_hashes, retroachievements_console_id,
+    retroachievements_hash,
+};
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use crate::{
+    catalog,
+    error::{CliError, ExitCode, Result},
+    expand_paths,
+    frontends::SHEET_EXTENSIONS,
+    network_timeouts, open_manager,
+    settings::{Settings, StorageLocations},
+};
+
+/// The version of `ndumpmgr ra-check --json`'s output, raised whenever its format changes
+pub const RA_CHECK_OUTPUT_VERSION: u32 = 1;
+
+#[derive(Args)]
+pub struct RaCheckArgs {
+    /// The dumps, or folders of dumps, to check (by default, the game location)
+    paths: Vec<String>,
+    /// Prints the dumps as JSON instead of a table
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum RaStatus {
+    /// RetroAchievements knows the dump's hash
+    Recognized,
+    /// RetroAchievements doesn't know the dump's hash, so it's probably another revision or
+    /// region than the one achievements were made for
+    Unrecognized,
+    /// The dump's console has no achievements
+    UnsupportedConsole,
+    /// The dump can't be hashed like RetroAchievements does, like a CHD or a Dreamcast disc
+    Unhashable,
+}
+
+impl RaStatus {
+    fn description(self) -> &'static str {
+        match self {
+            RaStatus::Recognized => "Recognized",
+            RaStatus::Unrecognized => "Not recognized",
+            RaStatus::UnsupportedConsole => "No achievements",
+            RaStatus::Unhashable => "Can't hash",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RaCheckResult {
+    path: String,
+    console: String,
+    game: String,
+    hash: Option<String>,
+    status: RaStatus,
+    /// The game's ID on RetroAchievements, for recognized dumps
+    ra_game_id: Option<u32>,
+}
+
+/// A JSON Schema describing the dumps printed by `ndumpmgr ra-check --json`
+pub fn ra_check_output_schema() -> Value {
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "type": "array",
+        "items": {
+            "type": "object",
+            "properties": {
+                "path": { "type": "string" },
+                "console": { "type": "string", "description": "The console's short name, like \"psx\"" },
+                "game": { "type": "string", "description": "The game's name in the catalog" },
+                "hash": { "type": ["string", "null"], "description": "The MD5 hash RetroAchievements identifies the dump by, if it can be computed" },
+                "status": { "enum": ["recognized", "unrecognized", "unsupported-console", "unhashable"] },
+                "ra_game_id": { "type": ["integer", "null"], "description": "The game's ID on RetroAchievements, for recognized dumps" }
+            },
+            "required": ["path", "console", "game", "hash", "status", "ra_game_id"],
+            "additionalProperties": false
+        }
+    })
+}
+
+/// A dump in the catalog, with its console and the name of its game
+struct IdentifiedDump {
+    path: PathBuf,
+    console: GameConsole,
+    game: String,
+}
+
+/// Identifies the given dumps, leaving out the tracks of dumps whose cuesheets are there too
+///
+/// Returns the dumps, and how many files aren't in the catalog.
+fn identify_dumps(
+    settings: &Settings,
+    locations: &StorageLocations,
+    files: &[PathBuf],
+) -> Result<(Vec<IdentifiedDump>, usize)> {
+    let manager = open_manager(settings, locations)?;
+    // by folder, console, and game, sorted for the output
+    let mut dumps: BTreeMap<(PathBuf, String, String), (PathBuf, GameConsole)> = BTreeMap::new();
+    let mut unidentified = 0;
+    for file in files {
+        let Some(info) = manager.get_rom_info(file.to_str().unwrap())? else {
+            unidentified += 1;
+            continue;
+        };
+        let is_sheet = |path: &PathBuf| {
+            path.extension().is_some_and(|extension| {
+                SHEET_EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str())
+            })
+        };
+        let folder = file.parent().unwrap_or(file).to_path_buf();
+        let (listed, _) = dumps
+            .entry((
+                folder,
+                info.console.short_name().to_string(),
+                info.game_name,
+            ))
+            .or_insert_with(|| (file.clone(), info.console));
+        if is_sheet(file) && !is_sheet(listed) {
+            *listed = file.clone();
+        }
+    }
+    let dumps = dumps
+        .into_iter()
+        .map(|((_, _, game), (path, console))| IdentifiedDump {
+            path,
+            console,
+            game,
+        })
+        .collect();
+    Ok((dumps, unidentified))
+}
+
+/// Checks which dumps RetroAchievements will recognize, by computing the hashes it identifies
+/// games by and looking them up in its list of each console's hashes
+pub fn run(settings: Settings, locations: &StorageLocations, args: RaCheckArgs) -> Result<()> {
+    let RaCheckArgs { mut paths, json } = args;
+    if paths.is_empty() {
+        paths.push(settings.game_location().to_string_lossy().into_owned());
+    }
+    let files = expand_paths(&paths, &settings.ignore)?;
+    let (dumps, unidentified) = identify_dumps(&settings, locations, &files)?;
+    let timeouts = network_timeouts(&settings);
+    let mut known_hashes: HashMap<GameConsole, HashMap<String, u32>> = HashMap::new();
+    let mut results = Vec::new();
+    for IdentifiedDump {
+        path,
+        console,
+        game,
+    } in dumps
+    {
+        let (hash, status, ra_game_id) = if retroachievements_console_id(console).is_none() {
+            (None, RaStatus::UnsupportedConsole, None)
+        } else {
+            match retroachievements_hash(&path, console)? {
+                None => (None, RaStatus::Unhashable, None),
+                Some(hash) => {
+                    let known = match known_hashes.entry(console) {
+                        Entry::Occupied(entry) => entry.into_mut(),
+                        Entry::Vacant(entry) => {
+                            log::info!(
+                                "Downloading the {} hashes RetroAchievements recognizes",
+                                console.formal_name()
+                            );
+                            entry.insert(fetch_retroachievements_hashes(console, &timeouts)?)
+                        }
+                    };
+                    match known.get(&hash) {
+                        Some(id) => (Some(hash), RaStatus::Recognized, Some(*id)),
+                        None => (Some(hash), RaStatus::Unrecognized, None),
+                    }
+                }
+            }
+        };
+        results.push(RaCheckResult {
+            path: path.to_string_lossy().into_owned(),
+            console: console.short_name().to_string(),
+            game,
+            hash,
+            status,
+            ra_game_id,
+        });
+    }
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&results)
+                .map_err(|err| CliError::new(ExitCode::Failure, err.to_string()))?
+        );
+    } else if results.is_empty() {
+        println!("No dumps in the catalog found");
+    } else {
+        let rows: Vec<[String; 4]> = results
+            .iter()
+            .map(|result| {
+                [
+                    result.path.clone(),
+                    result.console.clone(),
+                    result.hash.clone().unwrap_or_default(),
+                    match result.ra_game_id {
+                        Some(id) => format!("{} (game {id})", result.status.description()),
+                        None => result.status.description().to_string(),
+                    },
+                ]
+            })
+            .collect();
+        catalog::print_table(&["Dump", "Console", "RA hash", "Status"], &rows);
+    }
+    let count = |status: RaStatus| {
+        results
+            .iter()
+            .filter(|result| result.status == status)
+            .count()
+    };
+    log::info!(
+        "{} of {} dump(s) will be recognized by RetroAchievements",
+        count(RaStatus::Recognized),
+        results.len()
+    );
+    if count(RaStatus::Unrecognized) > 0 {
+        log::info!(
+            "Dumps which aren't recognized are probably of other revisions or regions than the ones with achievements"
+        );
+    }
+    if count(RaStatus::Unhashable) > 0 {
+        log::info!(
+            "Compressed images (like CHDs) have to be extracted to be checked, and Dreamcast and GameCube discs can't be yet"
+        );
+    }
+    if unidentified > 0 {
+        log::info!("{unidentified} file(s) aren't in the catalog");
+    }
+    Ok(())
+}
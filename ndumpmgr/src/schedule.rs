@@ -0,0 +1,133 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, TimeDelta, Timelike};
+
+use crate::error::{CliError, ExitCode};
+
+/// How far ahead to look for a schedule's next time, past which it's taken to never come (like
+/// "0 0 31 2 *")
+const MAX_LOOKAHEAD_DAYS: i64 = 5 * 366;
+
+/// A cron-style schedule: a minute, hour, day of the month, month, and day of the week (0 or 7 for
+/// Sunday), like "30 4 * * 1-5"
+///
+/// Each field is `*`, a number, a range ("1-5"), a step ("*/15" or "0-30/10"), or a list of those
+/// ("1,15"). "@hourly", "@daily", "@weekly", and "@monthly" are short for the usual schedules.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// As in cron, when both days are restricted, a time matches if either does
+    any_day: bool,
+    any_weekday: bool,
+}
+
+/// Parses a field into a bit set of the values it matches, returning whether it starts with `*`
+fn parse_field(field: &str, min: u32, max: u32) -> Option<(u64, bool)> {
+    let mut set = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|step| *step > 0)?),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (start.parse().ok()?, end.parse().ok()?)
+        } else {
+            let value = range.parse().ok()?;
+            // "5/10" means from 5 on
+            (value, if step > 1 { max } else { value })
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        for value in (start..=end).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Some((set, field.starts_with('*')))
+}
+
+impl FromStr for Schedule {
+    type Err = CliError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let expanded = match value {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            value => value,
+        };
+        let invalid = || {
+            CliError::new(
+                ExitCode::InvalidInput,
+                format!("\"{value}\" isn't a schedule like \"30 4 * * *\""),
+            )
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(invalid());
+        };
+        let (minutes, _) = parse_field(minutes, 0, 59).ok_or_else(invalid)?;
+        let (hours, _) = parse_field(hours, 0, 23).ok_or_else(invalid)?;
+        let (days, any_day) = parse_field(days, 1, 31).ok_or_else(invalid)?;
+        let (months, _) = parse_field(months, 1, 12).ok_or_else(invalid)?;
+        let (mut weekdays, any_weekday) = parse_field(weekdays, 0, 7).ok_or_else(invalid)?;
+        // 7 is Sunday too
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Schedule {
+            minutes,
+            hours,
+            days,
+            months,
+            weekdays,
+            any_day,
+            any_weekday,
+        })
+    }
+}
+
+impl Schedule {
+    fn matches_day(&self, time: &NaiveDateTime) -> bool {
+        let day = self.days & (1 << time.day()) != 0;
+        let weekday = self.weekdays & (1 << time.weekday().num_days_from_sunday()) != 0;
+        if self.months & (1 << time.month()) == 0 {
+            false
+        } else if self.any_day || self.any_weekday {
+            day && weekday
+        } else {
+            day || weekday
+        }
+    }
+
+    /// The first time the schedule comes around after `time`, in local time
+    ///
+    /// Times which don't exist locally (skipped when clocks go forward) are skipped too.
+    pub fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = time.naive_local().with_second(0)?.with_nanosecond(0)?;
+        let end = start + TimeDelta::days(MAX_LOOKAHEAD_DAYS);
+        let mut next = start + TimeDelta::minutes(1);
+        while next < end {
+            if !self.matches_day(&next) {
+                next = next.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if self.hours & (1 << next.hour()) == 0 {
+                next = next.with_minute(0)? + TimeDelta::hours(1);
+            } else if self.minutes & (1 << next.minute()) == 0 {
+                next += TimeDelta::minutes(1);
+            } else if let Some(local) = next.and_local_timezone(Local).earliest() {
+                return Some(local);
+            } else {
+                next += TimeDelta::minutes(1);
+            }
+        }
+        None
+    }
+}
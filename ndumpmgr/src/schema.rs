@@ -0,0 +1,128 @@
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use crate::{
+    error::{CliError, ExitCode, Result},
+    export::{EXPORT_OUTPUT_VERSION, catalog_output_schema, library_output_schema},
+    open_manager,
+    retroachievements::{RA_CHECK_OUTPUT_VERSION, ra_check_output_schema},
+    settings::{Settings, StorageLocations},
+    summary::{SUMMARY_OUTPUT_VERSION, summary_output_schema},
+};
+
+/// The version of `ndumpmgr query --json`'s output, raised whenever its format changes
+const QUERY_OUTPUT_VERSION: u32 = 2;
+
+#[derive(Serialize)]
+struct DatabaseDocumentation {
+    name: String,
+    version: usize,
+    statements: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct OutputDocumentation {
+    command: &'static str,
+    version: u32,
+    schema: Value,
+}
+
+#[derive(Serialize)]
+struct SchemaDocumentation {
+    databases: Vec<DatabaseDocumentation>,
+    outputs: Vec<OutputDocumentation>,
+}
+
+/// A JSON Schema describing the games printed by `ndumpmgr query --json`
+fn query_output_schema() -> Value {
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "type": "array",
+        "items": {
+            "type": "object",
+            "properties": {
+                "gid": { "type": "integer", "description": "The game's ID in the catalog" },
+                "console": { "type": "string", "description": "The console's short name, like \"psx\"" },
+                "name": { "type": "string" },
+                "revision": { "type": "integer" },
+                "datafile": { "type": "string", "description": "The name of the datafile the game comes from" },
+                "serial": { "type": ["string", "null"], "description": "Only known for datafiles listing serials" },
+                "region": { "type": ["string", "null"], "description": "The region tag of the game's name, like \"USA\" or \"Japan, Korea\"" }
+            },
+            "required": ["gid", "console", "name", "revision", "datafile", "serial", "region"],
+            "additionalProperties": false
+        }
+    })
+}
+
+/// Prints the databases' schemas and the formats of the JSON outputs
+pub fn run(settings: Settings, locations: &StorageLocations, json: bool) -> Result<()> {
+    let schemas = open_manager(&settings, locations)?.database_schemas()?;
+    let documentation = SchemaDocumentation {
+        databases: schemas
+            .into_iter()
+            .map(|schema| DatabaseDocumentation {
+                name: schema.database,
+                version: schema.version,
+                statements: schema.statements,
+            })
+            .collect(),
+        outputs: vec![
+            OutputDocumentation {
+                command: "query --json",
+                version: QUERY_OUTPUT_VERSION,
+                schema: query_output_schema(),
+            },
+            OutputDocumentation {
+                command: "export --what catalog --format json",
+                version: EXPORT_OUTPUT_VERSION,
+                schema: catalog_output_schema(),
+            },
+            OutputDocumentation {
+                command: "export --what library --format json",
+                version: EXPORT_OUTPUT_VERSION,
+                schema: library_output_schema(),
+            },
+            OutputDocumentation {
+                command: "ra-check --json",
+                version: RA_CHECK_OUTPUT_VERSION,
+                schema: ra_check_output_schema(),
+            },
+            OutputDocumentation {
+                command: "--summary-json",
+                version: SUMMARY_OUTPUT_VERSION,
+                schema: summary_output_schema(),
+            },
+        ],
+    };
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&documentation)
+                .map_err(|err| CliError::new(ExitCode::Failure, err.to_string()))?
+        );
+        return Ok(());
+    }
+    for database in &documentation.databases {
+        println!(
+            "-- {} DB (schema version {})",
+            database.name, database.version
+        );
+        for statement in &database.statements {
+            println!("{};", statement.trim());
+        }
+        println!();
+    }
+    for output in &documentation.outputs {
+        println!(
+            "-- `ndumpmgr {}` output (format version {})",
+            output.command, output.version
+        );
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&output.schema)
+                .map_err(|err| CliError::new(ExitCode::Failure, err.to_string()))?
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,678 @@
+use std::{
+    io::{Read, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{Mutex, mpsc},
+    time::Duration,
+};
+
+use clap::Args;
+use log::{LevelFilter, Log, Metadata, Record};
+use ndumplib::{CatalogReader, find_split_dumps};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use simplelog::{Config, SharedLogger};
+
+use crate::{
+    QueryResult, collect_files,
+    error::{CliError, ExitCode, Result},
+    expand_paths,
+    export::{VerifyProgress, status_name},
+    open_manager,
+    prompt::Prompter,
+    settings::{SettingOverride, Settings, StorageLocations},
+    sort,
+};
+
+/// The port `serve` listens on by default
+const DEFAULT_PORT: u16 = 8477;
+
+/// The largest request accepted, headers and body together
+const MAX_REQUEST_SIZE: usize = 1 << 20;
+
+/// How often an idle event stream is sent a comment, so that proxies don't close it
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long a client may take to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Args)]
+pub struct ServeArgs {
+    /// The port to listen on
+    #[arg(long, default_value_t = DEFAULT_PORT)]
+    port: u16,
+    /// The address to listen on, which must be a loopback address (like 127.0.0.1 or ::1), since
+    /// anything that reaches the API can control ndumpmgr
+    #[arg(long, default_value = "127.0.0.1")]
+    address: IpAddr,
+}
+
+/// Something which happened while serving, sent to every client of `/api/events`
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum Event<'a> {
+    JobQueued {
+        job: usize,
+        kind: JobKind,
+    },
+    JobStarted {
+        job: usize,
+    },
+    DumpVerified {
+        job: usize,
+        #[serde(flatten)]
+        dump: &'a VerifiedDump,
+    },
+    JobFinished {
+        job: usize,
+        state: JobState,
+        error: Option<&'a str>,
+        warnings: &'a [JobWarning],
+    },
+    /// A message logged by a job, like the progress of an import
+    Log {
+        level: String,
+        message: String,
+    },
+}
+
+/// The event streams' channels, which are dropped once their client disconnects
+static SUBSCRIBERS: Mutex<Vec<mpsc::Sender<String>>> = Mutex::new(Vec::new());
+
+fn broadcast(event: &Event) {
+    let data = serde_json::to_string(event).unwrap();
+    SUBSCRIBERS
+        .lock()
+        .unwrap()
+        .retain(|subscriber| subscriber.send(data.clone()).is_ok());
+}
+
+/// Sends ndumpmgr's log messages (down to info) to the event streams
+struct EventLogger;
+
+impl Log for EventLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= LevelFilter::Info && metadata.target().starts_with("ndump")
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            broadcast(&Event::Log {
+                level: record.level().as_str().to_lowercase(),
+                message: record.args().to_string(),
+            });
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+impl SharedLogger for EventLogger {
+    fn level(&self) -> LevelFilter {
+        LevelFilter::Info
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
+
+/// A logger sending log messages to the clients of `/api/events`
+pub fn event_logger() -> Box<dyn SharedLogger> {
+    Box::new(EventLogger)
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum JobKind {
+    /// Verifies dumps, or the whole game location
+    Verify,
+    /// Imports dumps, like the `import` command
+    Import,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// A problem which didn't stop a job (see [ndumplib::Warning])
+#[derive(Serialize, Clone)]
+struct JobWarning {
+    kind: &'static str,
+    message: String,
+}
+
+#[derive(Serialize, Clone)]
+struct VerifiedDump {
+    path: String,
+    status: &'static str,
+    error: Option<String>,
+}
+
+/// Work started through the API, which runs in the background one job at a time
+#[derive(Serialize, Clone)]
+struct Job {
+    id: usize,
+    kind: JobKind,
+    paths: Vec<String>,
+    state: JobState,
+    error: Option<String>,
+    /// The dumps verified so far, for verify jobs
+    dumps: Vec<VerifiedDump>,
+    /// About how many seconds a running verify job has left
+    seconds_left: Option<u64>,
+    /// The warnings reported by a finished job
+    warnings: Vec<JobWarning>,
+}
+
+/// The body of `POST /api/jobs`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobRequest {
+    kind: JobKind,
+    /// The dumps or folders to work on (verify jobs default to the game location)
+    #[serde(default)]
+    paths: Vec<String>,
+}
+
+struct Server<'a> {
+    locations: &'a StorageLocations,
+    overrides: &'a [SettingOverride],
+    reader: CatalogReader,
+    jobs: Mutex<Vec<Job>>,
+    queue: Mutex<mpsc::Sender<usize>>,
+}
+
+struct Response {
+    status: u16,
+    body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Response {
+        Response { status: 200, body }
+    }
+
+    fn error(status: u16, message: impl Into<String>) -> Response {
+        Response {
+            status,
+            body: json!({ "error": message.into() }),
+        }
+    }
+}
+
+impl From<CliError> for Response {
+    fn from(err: CliError) -> Self {
+        let status = match err.code {
+            ExitCode::InvalidInput => 400,
+            ExitCode::Busy => 503,
+            _ => 500,
+        };
+        Response::error(status, err.message)
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Content Too Large",
+        415 => "Unsupported Media Type",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Decodes a query string's `%XX` escapes, and `+` for spaces
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'+' => decoded.push(b' '),
+            b'%' if index + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[index + 1..index + 3]).unwrap_or("");
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => {
+                        decoded.push(byte);
+                        index += 2;
+                    }
+                    Err(_) => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// A request's method, path, query parameters, and body
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    is_json: bool,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn parameter(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Reads a request, or returns the response refusing it
+///
+/// Requests must be addressed to localhost, which stops web pages from reaching the API through
+/// DNS rebinding.
+fn read_request(stream: &mut TcpStream) -> std::result::Result<Request, Response> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 8192];
+    loop {
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut parsed = httparse::Request::new(&mut headers);
+        let status = parsed
+            .parse(&buffer)
+            .map_err(|err| Response::error(400, err.to_string()))?;
+        if let httparse::Status::Complete(header_length) = status {
+            let header = |name: &str| {
+                parsed
+                    .headers
+                    .iter()
+                    .find(|header| header.name.eq_ignore_ascii_case(name))
+                    .map(|header| String::from_utf8_lossy(header.value).into_owned())
+            };
+            let host = header("Host").unwrap_or_default();
+            let hostname = match host.rsplit_once(':') {
+                Some((hostname, port)) if !port.contains(']') => hostname.to_string(),
+                _ => host.clone(),
+            };
+            if !matches!(hostname.as_str(), "localhost" | "127.0.0.1" | "[::1]") {
+                return Err(Response::error(403, format!("Unexpected host \"{host}\"")));
+            }
+            let length: usize = header("Content-Length")
+                .map(|length| length.trim().parse())
+                .transpose()
+                .map_err(|_| Response::error(400, "Invalid Content-Length"))?
+                .unwrap_or(0);
+            let request_length = header_length
+                .checked_add(length)
+                .filter(|&request_length| request_length <= MAX_REQUEST_SIZE)
+                .ok_or_else(|| Response::error(413, "The request is too large"))?;
+            let is_json = header("Content-Type")
+                .is_some_and(|content_type| content_type.starts_with("application/json"));
+            let target = parsed.path.unwrap_or("/").to_string();
+            let method = parsed.method.unwrap_or("GET").to_string();
+            while buffer.len() < request_length {
+                let read = stream
+                    .read(&mut chunk)
+                    .map_err(|err| Response::error(400, err.to_string()))?;
+                if read == 0 {
+                    return Err(Response::error(400, "The request ended early"));
+                }
+                buffer.extend_from_slice(&chunk[..read]);
+            }
+            let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+            return Ok(Request {
+                method,
+                path: percent_decode(path),
+                query: query
+                    .split('&')
+                    .filter(|pair| !pair.is_empty())
+                    .map(|pair| {
+                        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                        (percent_decode(key), percent_decode(value))
+                    })
+                    .collect(),
+                is_json,
+                body: buffer[header_length..request_length].to_vec(),
+            });
+        }
+        if buffer.len() > MAX_REQUEST_SIZE {
+            return Err(Response::error(413, "The request is too large"));
+        }
+        let read = stream
+            .read(&mut chunk)
+            .map_err(|err| Response::error(400, err.to_string()))?;
+        if read == 0 {
+            return Err(Response::error(400, "The request ended early"));
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+}
+
+fn write_response(stream: &mut TcpStream, response: Response) {
+    let body = serde_json::to_string_pretty(&response.body).unwrap();
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        response.status,
+        reason_phrase(response.status),
+        body.len()
+    );
+}
+
+/// Streams events to a client until it disconnects
+fn stream_events(mut stream: TcpStream) {
+    let (sender, receiver) = mpsc::channel();
+    SUBSCRIBERS.lock().unwrap().push(sender);
+    let headers = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n";
+    if stream.write_all(headers.as_bytes()).is_err() {
+        return;
+    }
+    loop {
+        let message = match receiver.recv_timeout(KEEP_ALIVE_INTERVAL) {
+            Ok(data) => format!("data: {data}\n\n"),
+            Err(mpsc::RecvTimeoutError::Timeout) => String::from(": keep-alive\n\n"),
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        };
+        if stream.write_all(message.as_bytes()).is_err() {
+            // the channel is dropped with the receiver, so the next event removes it
+            return;
+        }
+    }
+}
+
+impl Server<'_> {
+    fn settings(&self) -> Result<Settings> {
+        Settings::load(self.locations, self.overrides)
+    }
+
+    /// The files in the game location, relative to it, with their sizes
+    fn library(&self) -> Result<Value> {
+        let settings = self.settings()?;
+        let root = settings.game_location();
+        let mut files = Vec::new();
+        if root.is_dir() {
+            collect_files(root, &settings.ignore, &mut files)?;
+        }
+        let files: Vec<Value> = files
+            .iter()
+            .map(|file| {
+                json!({
+                    "path": file.strip_prefix(root).unwrap_or(file).to_string_lossy(),
+                    "size": file.metadata().map(|metadata| metadata.len()).ok(),
+                })
+            })
+            .collect();
+        Ok(json!(files))
+    }
+
+    /// Finds games by name, like `catalog search`
+    fn search(&self, pattern: &str) -> Result<Value> {
+        let pattern = if pattern.contains(['*', '?']) {
+            pattern.to_string()
+        } else {
+            format!("*{pattern}*")
+        };
+        let games: Vec<QueryResult> = self
+            .reader
+            .search_games(&pattern)?
+            .into_iter()
+            .map(QueryResult::from)
+            .collect();
+        Ok(json!(games))
+    }
+
+    fn start_job(&self, request: &Request) -> Response {
+        if !request.is_json {
+            // browsers can't send JSON to another site without asking first, which is refused
+            return Response::error(415, "Jobs are started with a JSON body");
+        }
+        let job_request: JobRequest = match serde_json::from_slice(&request.body) {
+            Ok(job_request) => job_request,
+            Err(err) => return Response::error(400, err.to_string()),
+        };
+        if job_request.kind == JobKind::Import && job_request.paths.is_empty() {
+            return Response::error(400, "Import jobs need paths");
+        }
+        let mut jobs = self.jobs.lock().unwrap();
+        let id = jobs.len() + 1;
+        jobs.push(Job {
+            id,
+            kind: job_request.kind,
+            paths: job_request.paths,
+            state: JobState::Queued,
+            error: None,
+            dumps: Vec::new(),
+            seconds_left: None,
+            warnings: Vec::new(),
+        });
+        drop(jobs);
+        broadcast(&Event::JobQueued {
+            job: id,
+            kind: job_request.kind,
+        });
+        let _ = self.queue.lock().unwrap().send(id);
+        Response {
+            status: 202,
+            body: json!({ "job": id }),
+        }
+    }
+
+    fn route(&self, request: &Request) -> Response {
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        let result = match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["api", "library"]) => self.library(),
+            ("GET", ["api", "catalog", "search"]) => match request.parameter("q") {
+                Some(pattern) => self.search(pattern),
+                None => return Response::error(400, "Missing the \"q\" parameter"),
+            },
+            ("GET", ["api", "jobs"]) => Ok(json!(*self.jobs.lock().unwrap())),
+            ("GET", ["api", "jobs", id]) => {
+                let jobs = self.jobs.lock().unwrap();
+                match id
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|id| jobs.get(id.wrapping_sub(1)))
+                {
+                    Some(job) => Ok(json!(job)),
+                    None => return Response::error(404, format!("No job {id}")),
+                }
+            }
+            ("POST", ["api", "jobs"]) => return self.start_job(request),
+            (_, ["api", "library" | "jobs"] | ["api", "catalog", "search"] | ["api", "events"]) => {
+                return Response::error(405, "Method not allowed");
+            }
+            _ => return Response::error(404, "Not found"),
+        };
+        match result {
+            Ok(body) => Response::ok(body),
+            Err(err) => err.into(),
+        }
+    }
+
+    fn handle(&self, mut stream: TcpStream) {
+        let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+        let request = match read_request(&mut stream) {
+            Ok(request) => request,
+            Err(response) => return write_response(&mut stream, response),
+        };
+        log::debug!("{} {}", request.method, request.path);
+        if request.method == "GET" && request.path.trim_end_matches('/') == "/api/events" {
+            return stream_events(stream);
+        }
+        let response = self.route(&request);
+        write_response(&mut stream, response);
+    }
+
+    fn update_job(&self, id: usize, update: impl FnOnce(&mut Job)) {
+        update(&mut self.jobs.lock().unwrap()[id - 1]);
+    }
+
+    /// Verifies the job's dumps one at a time, so their results can be streamed
+    fn verify(&self, id: usize, settings: Settings, paths: Vec<String>) -> Result<()> {
+        let paths = if paths.is_empty() {
+            vec![settings.game_location().to_string_lossy().into_owned()]
+        } else {
+            paths
+        };
+        let files = expand_paths(&paths, &settings.ignore)?;
+        let manager = open_manager(&settings, self.locations)?;
+        let (split_dumps, files) = find_split_dumps(&files);
+        let progress = VerifyProgress::new(
+            files
+                .iter()
+                .map(std::slice::from_ref)
+                .chain(split_dumps.iter().map(|dump| dump.parts.as_slice())),
+        );
+        let results = files
+            .into_iter()
+            .map(|file| (file.clone(), manager.verify_file(&file)))
+            .chain(split_dumps.into_iter().map(|dump| {
+                let result = manager.verify_split(&dump);
+                (dump.parts[0].clone(), result)
+            }));
+        for (index, (path, result)) in results.enumerate() {
+            progress.verified(index);
+            let (status, error) = match result {
+                Ok(status) => (status_name(status), None),
+                Err(err) => ("error", Some(err.to_string())),
+            };
+            let dump = VerifiedDump {
+                path: path.to_string_lossy().into_owned(),
+                status,
+                error,
+            };
+            broadcast(&Event::DumpVerified {
+                job: id,
+                dump: &dump,
+            });
+            self.update_job(id, |job| {
+                job.dumps.push(dump);
+                job.seconds_left = progress.estimate();
+            });
+        }
+        Ok(())
+    }
+
+    fn run_job(&self, id: usize, prompter: &Prompter) {
+        let (kind, paths) = {
+            let jobs = self.jobs.lock().unwrap();
+            (jobs[id - 1].kind, jobs[id - 1].paths.clone())
+        };
+        self.update_job(id, |job| job.state = JobState::Running);
+        broadcast(&Event::JobStarted { job: id });
+        let (outcome, warnings) = ndumplib::collect_warnings(|| {
+            self.settings().and_then(|mut settings| {
+                // jobs wait for commands run meanwhile rather than failing
+                settings.wait_for_lock = true;
+                settings.apply_tool_paths();
+                settings.apply_hashing_options();
+                settings.apply_work_directory();
+                match kind {
+                    JobKind::Verify => self.verify(id, settings, paths),
+                    JobKind::Import => sort::import(
+                        paths.into_iter().map(PathBuf::from).collect(),
+                        settings,
+                        self.locations,
+                        prompter,
+                    ),
+                }
+            })
+        });
+        let (state, error) = match outcome {
+            Ok(()) => (JobState::Succeeded, None),
+            Err(err) => {
+                log::error!("{}", err.message);
+                (JobState::Failed, Some(err.message))
+            }
+        };
+        let warnings: Vec<JobWarning> = warnings
+            .into_iter()
+            .map(|warning| JobWarning {
+                kind: warning.kind.name(),
+                message: warning.message,
+            })
+            .collect();
+        broadcast(&Event::JobFinished {
+            job: id,
+            state,
+            error: error.as_deref(),
+            warnings: &warnings,
+        });
+        self.update_job(id, |job| {
+            job.state = state;
+            job.error = error;
+            job.warnings = warnings;
+        });
+    }
+}
+
+/// Serves a REST API on a local port, so that other programs can use ndumpmgr without running it
+///
+/// - `GET /api/library` lists the files in the game location
+/// - `GET /api/catalog/search?q=...` finds games, like `catalog search`
+/// - `POST /api/jobs` with `{"kind": "verify" | "import", "paths": [...]}` starts a job
+/// - `GET /api/jobs` and `GET /api/jobs/{id}` show how jobs are going
+/// - `GET /api/events` streams jobs' progress as server-sent events
+pub fn run(
+    args: ServeArgs,
+    settings: Settings,
+    locations: &StorageLocations,
+    overrides: &[SettingOverride],
+    prompter: &Prompter,
+) -> Result<()> {
+    if !args.address.is_loopback() {
+        return Err(CliError::new(
+            ExitCode::InvalidInput,
+            format!(
+                "Can't listen on {}: only loopback addresses are allowed, since anything that reaches the API can control ndumpmgr",
+                args.address
+            ),
+        ));
+    }
+    let reader = open_manager(&settings, locations)?.catalog_reader();
+    let address = SocketAddr::new(args.address, args.port);
+    let listener = TcpListener::bind(address).map_err(|err| {
+        CliError::new(
+            ExitCode::IO,
+            format!("Failed to listen on {address}: {err}"),
+        )
+    })?;
+    let (queue, jobs) = mpsc::channel::<usize>();
+    let server = Server {
+        locations,
+        overrides,
+        reader,
+        jobs: Mutex::new(Vec::new()),
+        queue: Mutex::new(queue),
+    };
+    let prompter = prompter.non_interactive();
+    log::info!("Serving the API on http://{address}/api/");
+    std::thread::scope(|scope| {
+        let server = &server;
+        scope.spawn(move || {
+            for id in jobs {
+                server.run_job(id, &prompter);
+            }
+        });
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    scope.spawn(move || server.handle(stream));
+                }
+                Err(err) => log::debug!("Failed to accept a connection: {err}"),
+            }
+        }
+    });
+    Ok(())
+}
@@ -1,23 +1,96 @@
+use clap::{Subcommand, ValueEnum};
+use ndumplib::{ExternalTool, GameConsole, HashingOptions, LinkMethod, StorageLocation};
 use serde::{Deserialize, Serialize};
-use std::{env, fs, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
 
 use log::debug;
 
-use crate::error_exit;
+use crate::{
+    error::{CliError, ExitCode, Result},
+    sort::LayoutTemplate,
+};
 
-macro_rules! no_home_directory {
-    () => {
-        error_exit!("Could not find home directory.");
-    };
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Checks the configuration file, reporting every problem found
+    Validate,
+    /// Lists the external tools ndumpmgr runs (like chdman), where they were found, and their
+    /// versions
+    Tools,
+}
+
+fn no_home_directory() -> CliError {
+    CliError::new(ExitCode::Config, "Could not find home directory.")
+}
+
+/// Creates a directory if it doesn't exist yet
+///
+/// `description` names it in error messages (e.g. "data directory").
+fn ensure_directory(path: &Path, description: &str) -> Result<()> {
+    if !path.exists() {
+        fs::create_dir(path).map_err(|_| {
+            CliError::new(
+                ExitCode::Config,
+                format!(
+                    "Failed to create {description} \"{}\". Please grant ndumpmgr the needed permissions",
+                    path.to_str().unwrap()
+                ),
+            )
+        })
+    // if there's something other than a directory there, ask the user to remove it
+    } else if !path.is_dir() {
+        Err(CliError::new(
+            ExitCode::Config,
+            format!(
+                "\"{}\" is not a directory. Please move whatever is there",
+                path.to_str().unwrap()
+            ),
+        ))
+    } else {
+        Ok(())
+    }
 }
 
 pub struct StorageLocations {
     pub config_path: PathBuf,
-    pub default_data_path: PathBuf,
+    pub data_path: PathBuf,
 }
 
-impl Default for StorageLocations {
-    fn default() -> Self {
+impl StorageLocations {
+    /// Finds (and creates, if needed) where the configuration file and data are stored
+    ///
+    /// The given paths (from `--config` and `--data-dir`) replace the ones derived from the home
+    /// directory. A given data directory is created if it doesn't exist, but not its parents, so an
+    /// unmounted drive isn't mistaken for an empty one.
+    pub fn find(
+        config_path: Option<PathBuf>,
+        data_path: Option<PathBuf>,
+    ) -> Result<StorageLocations> {
+        let locations = match (config_path, data_path) {
+            (Some(config_path), Some(data_path)) => StorageLocations {
+                config_path,
+                data_path,
+            },
+            (config_path, data_path) => {
+                let defaults = StorageLocations::find_default()?;
+                StorageLocations {
+                    config_path: config_path.unwrap_or(defaults.config_path),
+                    data_path: data_path.unwrap_or(defaults.data_path),
+                }
+            }
+        };
+        ensure_directory(&locations.data_path, "data directory")?;
+        debug!("Config path: {}", locations.config_path.display());
+        debug!("Data path: {}", locations.data_path.display());
+        Ok(locations)
+    }
+    /// Finds where the configuration file and data are stored by default, based on the home
+    /// directory
+    fn find_default() -> Result<StorageLocations> {
         #[allow(deprecated)] // home_dir is deprecated
         match (env::consts::OS, env::home_dir()) {
             // OS is linux, and the home directory is defined
@@ -28,141 +101,949 @@ impl Default for StorageLocations {
                 // if .config and .local/share exist, store our files in these places
                 if config_dir.is_dir() && share_dir.is_dir() {
                     share_dir.push("ndumpmgr");
-                    // try to create data directory
-                    if !share_dir.exists() {
-                        match fs::create_dir(&share_dir) {
-                            Ok(_) => {}
-                            Err(_) => error_exit!(
-                                "Failed to create data directory \"{}\". Please grant ndumpmgr the needed permissions",
-                                share_dir.to_str().unwrap()
-                            ),
-                        }
-                    // if there's something other than a directory there, ask the user to remove it
-                    } else if !share_dir.is_dir() {
-                        error_exit!(
-                            "\"{}\" is not a directory. Please move whatever is there",
-                            share_dir.to_str().unwrap()
-                        );
-                    }
+                    ensure_directory(&share_dir, "data directory")?;
                     // return the storage locations
                     let config_path = config_dir.join("ndumpmgr.yml");
-                    debug!("Config path: {}", config_path.to_str().unwrap());
-                    debug!("Default data path: {}", share_dir.to_str().unwrap());
-                    return StorageLocations {
+                    Ok(StorageLocations {
                         config_path,
-                        default_data_path: share_dir,
-                    };
+                        data_path: share_dir,
+                    })
                 // otherwise, store them together in a .ndumpmgr folder in home
                 } else {
                     let base_dir = home_dir.join(".ndumpmgr");
                     let config_path = base_dir.join("config.yml");
-                    let default_data_path = base_dir.join("data");
-                    // try to create ndumpmgr directory
-                    if !base_dir.exists() {
-                        match fs::create_dir(&base_dir) {
-                            Ok(_) => {}
-                            Err(_) => error_exit!(
-                                "Failed to create directory \"{}\". Please grant ndumpmgr the needed permissions",
-                                base_dir.to_str().unwrap()
-                            ),
-                        }
-                    // if there's something other than a directory there, ask the user to remove it
-                    } else if !base_dir.is_dir() {
-                        error_exit!(
-                            "\"{}\" is not a directory. Please move whatever is there",
-                            base_dir.to_str().unwrap()
-                        );
-                    }
-                    // try to create data directory
-                    if !default_data_path.exists() {
-                        match fs::create_dir(&default_data_path) {
-                            Ok(_) => {}
-                            Err(_) => error_exit!(
-                                "Failed to create data directory \"{}\". Please grant ndumpmgr the needed permissions",
-                                default_data_path.to_str().unwrap()
-                            ),
-                        }
-                    // if there's something other than a directory there, ask the user to remove it
-                    } else if !default_data_path.is_dir() {
-                        error_exit!(
-                            "\"{}\" is not a directory. Please move whatever is there",
-                            default_data_path.to_str().unwrap()
-                        );
-                    }
+                    let data_path = base_dir.join("data");
+                    ensure_directory(&base_dir, "directory")?;
+                    ensure_directory(&data_path, "data directory")?;
                     // return the storage locations
-                    debug!("Config path: {}", config_path.to_str().unwrap());
-                    debug!("Default data path: {}", default_data_path.to_str().unwrap());
-                    return StorageLocations {
+                    Ok(StorageLocations {
                         config_path,
-                        default_data_path,
-                    };
+                        data_path,
+                    })
                 }
             }
             // OS is linux, but there's no home directory
-            ("linux", None) => {
-                no_home_directory!();
-            }
+            ("linux", None) => Err(no_home_directory()),
             // any other OS
-            _ => error_exit!("Unsupported OS: {}", env::consts::OS),
-        };
+            _ => Err(CliError::new(
+                ExitCode::Config,
+                format!("Unsupported OS: {}", env::consts::OS),
+            )),
+        }
     }
 }
 
+/// An extra datafile to use as a source of games for a console
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DatafileSource {
+    /// The console the datafile's games belong to (e.g. "psx")
+    pub console: String,
+    /// The path to the logiqx datafile
+    pub path: PathBuf,
+    /// Games from sources with lower priorities win conflicts (Redump and No-Intro are 0)
+    #[serde(default)]
+    pub priority: i64,
+}
+
+/// A catalog entry the user never intends to collect
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum BlocklistEntry {
+    /// A game's gid in the catalog
+    Gid(i64),
+    /// A wildcard pattern matched against game names (e.g. "*(Unl)*")
+    Pattern(String),
+}
+
+/// What `convert` compresses a console's dumps to
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConversionFormat {
+    /// CHDs, made by chdman
+    Chd,
+    /// CSOs (v1), made from ISOs by maxcso
+    Cso,
+    /// ZSOs, made from ISOs by maxcso
+    Zso,
+    /// RVZs, made from GameCube and Wii ISOs by dolphin-tool
+    Rvz,
+    /// ZIPs of one cartridge ROM each, written like TorrentZip writes them so the same ROM always
+    /// makes the same ZIP
+    Zip,
+}
+
+impl ConversionFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Chd => "CHD",
+            Self::Cso => "CSO",
+            Self::Zso => "ZSO",
+            Self::Rvz => "RVZ",
+            Self::Zip => "ZIP",
+        }
+    }
+}
+
+/// What import does with dumps split into parts, like FAT32-split ISOs and WBFS images
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SplitHandling {
+    /// Keeps the parts, renamed after the game
+    Keep,
+    /// Joins the parts into one file
+    Join,
+    /// Joins the parts, then converts ISOs to CHDs (other dumps are only joined)
+    Chd,
+}
+
+/// How `sort` duplicates the library's dumps into a mirror
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MirrorLink {
+    /// Hard links, falling back to reflinks, then copies
+    #[default]
+    Hardlink,
+    /// Reflinks, which don't share changes made to either file, falling back to copies
+    Reflink,
+}
+
+impl MirrorLink {
+    pub fn method(self) -> LinkMethod {
+        match self {
+            Self::Hardlink => LinkMethod::HardLink,
+            Self::Reflink => LinkMethod::Reflink,
+        }
+    }
+}
+
+/// Another folder `sort` fills with the library's dumps in a layout of its own, like an archival
+/// layout next to an emulator-friendly one (see [crate::mirror::Mirror])
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MirrorSettings {
+    /// The folder the mirror is made in, which must be outside the game location
+    pub location: PathBuf,
+    /// Where dumps go within the mirror, like the layout setting
+    pub layout: String,
+    /// How dumps are duplicated: "hardlink" or "reflink" (on btrfs or XFS)
+    #[serde(default)]
+    pub link: MirrorLink,
+}
+
+/// A frontend whose game lists `sort` and `import` keep up to date (see [crate::frontends])
+#[derive(Serialize, Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Frontend {
+    /// A gamelist.xml in each console's folder, for ES-DE, Batocera, and other EmulationStation
+    /// forks
+    #[value(name = "emulationstation")]
+    EmulationStation,
+    /// A metadata.pegasus.txt in each console's folder
+    Pegasus,
+    /// A launchbox.xml in each console's folder, in the format of LaunchBox's platform files
+    #[value(name = "launchbox")]
+    LaunchBox,
+}
+
+/// The frontends whose game lists are kept up to date, and how
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FrontendSettings {
+    /// The frontends whose game lists are written once `sort` or `import` finishes, like
+    /// ["emulationstation", "pegasus"]
+    #[serde(default)]
+    pub lists: Vec<Frontend>,
+    /// The commands Pegasus launches each console's games with, like
+    /// {"psx": "duckstation-qt {file.path}"}
+    #[serde(default)]
+    pub pegasus_launch: BTreeMap<String, String>,
+}
+
+impl FrontendSettings {
+    /// The command Pegasus launches a console's games with, if there's one
+    pub fn pegasus_launch(&self, console: GameConsole) -> Option<&str> {
+        self.pegasus_launch
+            .iter()
+            .find(|(name, _)| name.parse::<GameConsole>().is_ok_and(|key| key == console))
+            .map(|(_, command)| command.as_str())
+    }
+}
+
+/// Where the library is kept, when it isn't the local game location
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct StorageSettings {
+    /// A remote folder `import`, `sort`, and `export --what library` work on instead of the game
+    /// location, like "sftp://user@nas/srv/games" (or "file:///mnt/games" for a mounted share)
+    #[serde(default)]
+    pub library: Option<String>,
+    /// The private key logging in to SFTP servers, when the SSH agent doesn't have one, like
+    /// "/home/me/.ssh/id_ed25519"
+    #[serde(default)]
+    pub identity_file: Option<PathBuf>,
+}
+
+/// How a webhook expects summaries to be posted
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// The summary as JSON, like the `--summary-json` file
+    #[default]
+    Json,
+    /// A Discord message
+    Discord,
+    /// A Slack message
+    Slack,
+}
+
+/// Where `import` and `sort` send a summary once they finish, for unattended runs (like on a NAS)
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NotificationSettings {
+    /// A URL the summary is posted to
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    #[serde(default)]
+    pub webhook_format: WebhookFormat,
+    /// A program and its arguments, run with the summary as text on its standard input (like a
+    /// script which emails it)
+    #[serde(default)]
+    pub command: Vec<String>,
+    /// Only sends summaries of runs which failed or had warnings
+    #[serde(default)]
+    pub only_problems: bool,
+}
+
+/// What `daemon` does, and when (see [crate::schedule::Schedule] for the schedules' format)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DaemonSettings {
+    /// When to update the catalog, like "0 4 * * *" for 4 AM every day
+    #[serde(default = "default_update_schedule")]
+    pub update_schedule: String,
+    /// When to import the dumps in the watch folders
+    #[serde(default = "default_import_schedule")]
+    pub import_schedule: String,
+    /// Folders whose dumps are imported on the import schedule, like a downloads folder
+    #[serde(default)]
+    pub watch_folders: Vec<PathBuf>,
+}
+
+impl Default for DaemonSettings {
+    fn default() -> Self {
+        DaemonSettings {
+            update_schedule: default_update_schedule(),
+            import_schedule: default_import_schedule(),
+            watch_folders: Vec::new(),
+        }
+    }
+}
+
+fn default_update_schedule() -> String {
+    String::from("0 4 * * *")
+}
+
+fn default_import_schedule() -> String {
+    String::from("*/15 * * * *")
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct Settings {
     game_location: PathBuf,
+    #[serde(default)]
+    pub datafiles: Vec<DatafileSource>,
+    #[serde(default)]
+    pub blocklist: Vec<BlocklistEntry>,
+    /// How long to wait before checking for datafile updates again
+    #[serde(default = "default_datafile_update_delay_hours")]
+    pub datafile_update_delay_hours: u64,
+    /// How long to wait before checking for cuesheet updates again
+    #[serde(default = "default_cuesheet_update_delay_hours")]
+    pub cuesheet_update_delay_hours: u64,
+    /// Options added to every command which accepts them, unless they're given on the command line
+    /// (e.g. ["--json", "--ascii"])
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Where `sort` puts identified dumps within the game location, like
+    /// "{console}/{letter}/{game} ({region})/{file}" (see [crate::sort::LayoutTemplate])
+    #[serde(default = "default_layout")]
+    pub layout: String,
+    /// Folders `sort` fills with links to the library's dumps, each in a layout of its own
+    #[serde(default)]
+    pub mirrors: Vec<MirrorSettings>,
+    /// Copies imported dumps instead of moving them, keeping the originals
+    #[serde(default)]
+    pub keep_originals: bool,
+    /// What import does with dumps split into parts: "keep" them, "join" them, or join them and
+    /// convert them to a "chd" (RAR volumes are always extracted)
+    #[serde(default = "default_split_dumps")]
+    pub split_dumps: SplitHandling,
+    /// The extensions of files which move with the dump they're named after, like "Game.sav" with
+    /// "Game.iso" (see [crate::companions::Companions])
+    #[serde(default = "default_companion_extensions")]
+    pub companion_extensions: Vec<String>,
+    /// .gitignore-style patterns of files and folders to leave alone when listing folders, like
+    /// save states or texture packs; ".ndumpignore" files add patterns for their own folder
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// The folder within the game location where `patch` stores patched dumps (translations and
+    /// hacks), in a folder per console
+    #[serde(default = "default_patched_folder")]
+    pub patched_folder: PathBuf,
+    /// Where import moves files it couldn't identify (defaults to "quarantine" in the data
+    /// directory)
+    #[serde(default)]
+    pub quarantine_location: Option<PathBuf>,
+    /// Where large temporary files go, like CHDs extracted to verify them and archives extracted
+    /// to import them (defaults to the system's temporary folder, which is often too small a tmpfs
+    /// to hold a disc)
+    #[serde(default)]
+    pub work_directory: Option<PathBuf>,
+    /// Waits for other instances using the data directory to finish, instead of failing (also
+    /// enabled by --wait)
+    #[serde(default)]
+    pub wait_for_lock: bool,
+    /// How long connecting to No-Intro or Redump may take before giving up
+    #[serde(default = "default_connect_timeout_seconds")]
+    pub connect_timeout_seconds: u64,
+    /// How long No-Intro or Redump may take to respond to a request before giving up
+    #[serde(default = "default_read_timeout_seconds")]
+    pub read_timeout_seconds: u64,
+    /// How long downloading a single datafile or set of cuesheets may take altogether
+    #[serde(default = "default_download_deadline_minutes")]
+    pub download_deadline_minutes: u64,
+    /// What `convert` compresses each console's dumps to, like {"psp": "cso", "wii": "rvz", "gba":
+    /// "zip"} (consoles which aren't listed, and unidentified dumps, get CHDs)
+    #[serde(default)]
+    pub conversion_formats: BTreeMap<String, ConversionFormat>,
+    /// The block size maxcso compresses with, in bytes (by default, maxcso picks one)
+    #[serde(default)]
+    pub maxcso_block_size: Option<usize>,
+    /// How many threads maxcso uses (by default, one per core)
+    #[serde(default)]
+    pub maxcso_threads: Option<usize>,
+    /// How many CHDs `convert` creates at once (by default, one per `chdman_threads` cores)
+    #[serde(default)]
+    pub conversion_jobs: Option<usize>,
+    /// How many threads each chdman compresses with (by default, one per core)
+    #[serde(default)]
+    pub chdman_threads: Option<usize>,
+    /// How many of the CHDs being created at once may be read from the same disk (by default, one)
+    #[serde(default)]
+    pub conversion_jobs_per_disk: Option<usize>,
+    /// How many days pass between compacting the databases, which `sort` does after updating the
+    /// catalog when it's due (0 leaves it to `ndumpmgr db maintain`)
+    #[serde(default = "default_db_maintenance_interval_days")]
+    pub db_maintenance_interval_days: u64,
+    /// Opens the databases without writing to them, for a data directory on a read-only mount or
+    /// a snapshot (commands that change the databases, like `sort` and `import`, fail)
+    #[serde(default)]
+    pub read_only_data: bool,
+    /// Whether the databases' write-ahead logs are written back into them when ndumpmgr exits
+    #[serde(default = "default_wal_checkpoint_on_close")]
+    pub wal_checkpoint_on_close: bool,
+    /// Whether the write-ahead logs are emptied when they're checkpointed on exit, rather than
+    /// left at their size to be reused
+    #[serde(default = "default_truncate_wal")]
+    pub truncate_wal: bool,
+    /// How many pages a write-ahead log grows to before it's checkpointed while ndumpmgr runs (by
+    /// default, SQLite's 1000)
+    #[serde(default)]
+    pub wal_autocheckpoint_pages: Option<u32>,
+    /// How much of a file is read at a time while it's hashed, in bytes
+    #[serde(default = "default_hash_buffer_size")]
+    pub hash_buffer_size: usize,
+    /// Whether files are mapped into memory to hash them, rather than read (faster on some disks,
+    /// but a file changed by another program meanwhile crashes ndumpmgr)
+    #[serde(default)]
+    pub hash_with_mmap: bool,
+    /// The most megabytes read per second while hashing, like 20 to leave room on a slow network
+    /// share (by default, there's no limit)
+    #[serde(default)]
+    pub max_read_mb_per_second: Option<f64>,
+    /// The most files read at once while hashing, like 1 for a share which slows down with more
+    /// (by default, as many as there are threads hashing)
+    #[serde(default)]
+    pub max_concurrent_reads: Option<usize>,
+    /// How many days pass before a CHD is verified again by decompressing it with `chdman verify`,
+    /// rather than only checking its header (0 only checks headers, unless `reverify-chd --deep`
+    /// asks for it)
+    #[serde(default)]
+    pub deep_chd_verification_interval_days: u64,
+    /// Experimental: checks Wii images which don't match the catalog again by only their data
+    /// partition, for images with their update partition stripped (they're reported as
+    /// "data-partition-verified", since the rest of the disc isn't checked, and only match
+    /// datafiles listing the data partition's SHA-1, like a local datafile)
+    #[serde(default)]
+    pub wii_partition_hashing: bool,
+    /// The binaries to run for external tools instead of the ones on the PATH, like
+    /// {"chdman": "/opt/mame/chdman"}
+    #[serde(default)]
+    pub tool_paths: BTreeMap<String, PathBuf>,
+    /// Where to send a summary once `import` or `sort` finishes
+    #[serde(default)]
+    pub notifications: NotificationSettings,
+    #[serde(default)]
+    pub daemon: DaemonSettings,
+    #[serde(default)]
+    pub frontend: FrontendSettings,
+    #[serde(default)]
+    pub storage: StorageSettings,
 }
 
-impl Default for Settings {
-    fn default() -> Self {
+fn default_layout() -> String {
+    crate::sort::DEFAULT_LAYOUT.to_string()
+}
+
+fn default_split_dumps() -> SplitHandling {
+    SplitHandling::Join
+}
+
+fn default_companion_extensions() -> Vec<String> {
+    crate::companions::DEFAULT_COMPANION_EXTENSIONS
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+fn default_patched_folder() -> PathBuf {
+    PathBuf::from("Patched")
+}
+
+fn default_datafile_update_delay_hours() -> u64 {
+    48
+}
+
+fn default_cuesheet_update_delay_hours() -> u64 {
+    168
+}
+
+fn default_connect_timeout_seconds() -> u64 {
+    30
+}
+
+fn default_read_timeout_seconds() -> u64 {
+    60
+}
+
+fn default_download_deadline_minutes() -> u64 {
+    30
+}
+
+fn default_db_maintenance_interval_days() -> u64 {
+    30
+}
+
+fn default_wal_checkpoint_on_close() -> bool {
+    true
+}
+
+fn default_truncate_wal() -> bool {
+    true
+}
+
+fn default_hash_buffer_size() -> usize {
+    HashingOptions::DEFAULT_BUFFER_SIZE
+}
+
+impl Settings {
+    /// The settings used when there's no config file
+    fn defaults() -> Result<Settings> {
         #[allow(deprecated)] // home_dir is deprecated
         // get the default game location
-        let game_location = match env::home_dir() {
-            Some(mut home_dir) => {
-                home_dir.push("games");
-                home_dir
+        let mut game_location = env::home_dir().ok_or_else(no_home_directory)?;
+        game_location.push("games");
+        // return defaults
+        Ok(Settings {
+            game_location,
+            datafiles: Vec::new(),
+            blocklist: Vec::new(),
+            datafile_update_delay_hours: default_datafile_update_delay_hours(),
+            cuesheet_update_delay_hours: default_cuesheet_update_delay_hours(),
+            args: Vec::new(),
+            layout: default_layout(),
+            mirrors: Vec::new(),
+            keep_originals: false,
+            split_dumps: default_split_dumps(),
+            companion_extensions: default_companion_extensions(),
+            ignore: Vec::new(),
+            patched_folder: default_patched_folder(),
+            quarantine_location: None,
+            work_directory: None,
+            wait_for_lock: false,
+            connect_timeout_seconds: default_connect_timeout_seconds(),
+            read_timeout_seconds: default_read_timeout_seconds(),
+            download_deadline_minutes: default_download_deadline_minutes(),
+            conversion_formats: BTreeMap::new(),
+            maxcso_block_size: None,
+            maxcso_threads: None,
+            conversion_jobs: None,
+            chdman_threads: None,
+            conversion_jobs_per_disk: None,
+            db_maintenance_interval_days: default_db_maintenance_interval_days(),
+            read_only_data: false,
+            wal_checkpoint_on_close: default_wal_checkpoint_on_close(),
+            truncate_wal: default_truncate_wal(),
+            wal_autocheckpoint_pages: None,
+            hash_buffer_size: default_hash_buffer_size(),
+            hash_with_mmap: false,
+            max_read_mb_per_second: None,
+            max_concurrent_reads: None,
+            deep_chd_verification_interval_days: 0,
+            wii_partition_hashing: false,
+            tool_paths: BTreeMap::new(),
+            notifications: NotificationSettings::default(),
+            daemon: DaemonSettings::default(),
+            frontend: FrontendSettings::default(),
+            storage: StorageSettings::default(),
+        })
+    }
+    /// The folder sorted dumps are kept in
+    pub fn game_location(&self) -> &Path {
+        &self.game_location
+    }
+    /// The format `convert` compresses a console's dumps to
+    pub fn conversion_format(&self, console: GameConsole) -> ConversionFormat {
+        self.conversion_formats
+            .iter()
+            .find(|(name, _)| name.parse::<GameConsole>().is_ok_and(|key| key == console))
+            .map_or(ConversionFormat::Chd, |(_, format)| *format)
+    }
+    /// Has the external tools run the binaries given in tool_paths
+    pub fn apply_tool_paths(&self) {
+        for (name, path) in &self.tool_paths {
+            // the names were checked when the settings were loaded
+            if let Ok(tool) = name.parse::<ExternalTool>() {
+                tool.set_path(path);
             }
-            None => {
-                no_home_directory!();
+        }
+    }
+    /// Has files hashed as hash_buffer_size, hash_with_mmap, and the read limits say
+    pub fn apply_hashing_options(&self) {
+        ndumplib::set_hashing_options(HashingOptions {
+            buffer_size: self.hash_buffer_size,
+            mmap: self.hash_with_mmap,
+            max_read_rate: self
+                .max_read_mb_per_second
+                .map(|rate| (rate * 1_000_000.0) as u64),
+            max_concurrent_reads: self.max_concurrent_reads,
+        });
+    }
+    /// Has large temporary files made in work_directory
+    pub fn apply_work_directory(&self) {
+        ndumplib::set_work_directory(self.work_directory.clone());
+    }
+    /// The folder import moves unidentified files to
+    pub fn quarantine_location(&self, locations: &StorageLocations) -> PathBuf {
+        self.quarantine_location
+            .clone()
+            .unwrap_or_else(|| locations.data_path.join("quarantine"))
+    }
+    /// Loads a config file from the given storage location, then applies the overrides in order
+    ///
+    /// Later overrides win, so environment variables should come before `--set` flags.
+    pub fn load(locations: &StorageLocations, overrides: &[SettingOverride]) -> Result<Settings> {
+        let mut settings = Settings::load_file(locations)?;
+        if overrides.is_empty() {
+            return Ok(settings);
+        }
+        let mut value = serde_yaml::to_value(&settings).unwrap();
+        for setting in overrides {
+            let mapping = value.as_mapping_mut().unwrap();
+            let key = serde_yaml::Value::String(setting.key.clone());
+            // every setting is serialized, so the keys list the settings there are
+            if !mapping.contains_key(&key) {
+                return Err(CliError::new(
+                    ExitCode::Config,
+                    format!("Unknown setting \"{}\" ({})", setting.key, setting.source),
+                ));
             }
-        };
-        // return defaults
-        return Settings { game_location };
+            mapping.insert(key, setting.value());
+            // each override is read on its own, so errors say which one is wrong
+            settings = serde_yaml::from_value(value.clone()).map_err(|err| {
+                CliError::new(
+                    ExitCode::Config,
+                    format!(
+                        "Invalid setting \"{}\" ({}): {err}",
+                        setting.key, setting.source
+                    ),
+                )
+            })?;
+        }
+        let problems = settings.problems();
+        if problems.is_empty() {
+            Ok(settings)
+        } else {
+            Err(CliError::new(
+                ExitCode::Config,
+                format!("Invalid settings after overrides:\n{}", problems.join("\n")),
+            ))
+        }
     }
-}
-
-impl Settings {
-    /// Loads a config file from the given storage location
-    pub fn load(locations: &StorageLocations) -> Settings {
+    /// Loads the config file alone, or the defaults if there isn't one
+    fn load_file(locations: &StorageLocations) -> Result<Settings> {
         // if the config file doesn't exist, return the default
         if !locations.config_path.exists() {
             debug!("Config file not found. Using defaults...");
-            Default::default()
+            Settings::defaults()
         // if the config file isn't a file, tell the user to fix it
         } else if !locations.config_path.is_file() {
-            error_exit!(
-                "Configuration file \"{}\" is not a file. Please move whatever is there",
-                locations.config_path.to_str().unwrap()
-            );
+            Err(CliError::new(
+                ExitCode::Config,
+                format!(
+                    "Configuration file \"{}\" is not a file. Please move whatever is there",
+                    locations.config_path.to_str().unwrap()
+                ),
+            ))
         // if the config file does exist, read it
         } else {
             debug!("Using config file");
-            let file_contents = match fs::read_to_string(&locations.config_path) {
-                Ok(content) => content,
-                Err(err) => error_exit!("Failed to read configuration file: {}", err),
-            };
-            match serde_yaml::from_str(file_contents.as_str()) {
-                Ok(settings) => settings,
-                Err(_) => error_exit!("Malformed configuration."),
+            let file_contents = fs::read_to_string(&locations.config_path).map_err(|err| {
+                CliError::new(
+                    ExitCode::Config,
+                    format!("Failed to read configuration file: {err}"),
+                )
+            })?;
+            Settings::parse(&file_contents).map_err(|problems| {
+                let mut message = format!(
+                    "Malformed configuration file \"{}\":\n{}",
+                    locations.config_path.display(),
+                    problems.join("\n")
+                );
+                let backup = backup_path(&locations.config_path);
+                match fs::copy(&locations.config_path, &backup) {
+                    Ok(_) => message += &format!("\nA copy was saved to \"{}\"", backup.display()),
+                    Err(err) => debug!("Failed to back up configuration file: {err}"),
+                }
+                CliError::new(ExitCode::Config, message)
+            })
+        }
+    }
+    /// Reads settings from YAML, then checks the values make sense
+    ///
+    /// Returns each problem found, like "line 3, column 5: unknown field `layuot`".
+    fn parse(content: &str) -> std::result::Result<Settings, Vec<String>> {
+        let settings: Settings = serde_yaml::from_str(content).map_err(|err| {
+            let message = err.to_string();
+            vec![match err.location() {
+                Some(location) => {
+                    // the message ends with the location, which goes first instead
+                    let suffix =
+                        format!(" at line {} column {}", location.line(), location.column());
+                    format!(
+                        "line {}, column {}: {}",
+                        location.line(),
+                        location.column(),
+                        message.strip_suffix(&suffix).unwrap_or(&message)
+                    )
+                }
+                None => message,
+            }]
+        })?;
+        let problems = settings.problems();
+        if problems.is_empty() {
+            Ok(settings)
+        } else {
+            Err(problems)
+        }
+    }
+    /// Checks the values which are well-formed YAML, but can't be used
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (index, datafile) in self.datafiles.iter().enumerate() {
+            if datafile.console.parse::<GameConsole>().is_err() {
+                problems.push(format!(
+                    "datafiles[{index}].console: unknown console \"{}\"",
+                    datafile.console
+                ));
+            }
+            if !datafile.path.is_file() {
+                problems.push(format!(
+                    "datafiles[{index}].path: \"{}\" doesn't exist",
+                    datafile.path.display()
+                ));
+            }
+        }
+        match LayoutTemplate::parse(&self.layout) {
+            Err(err) => problems.push(format!("layout: {}", err.message)),
+            Ok(layout)
+                if !self.frontend.lists.is_empty()
+                    && layout.console_folder(GameConsole::PSX).is_none() =>
+            {
+                problems.push(
+                    "frontend.lists: the layout has no folder per console to write game lists in"
+                        .to_string(),
+                );
+            }
+            Ok(_) => (),
+        }
+        for (index, mirror) in self.mirrors.iter().enumerate() {
+            if let Err(err) = LayoutTemplate::parse(&mirror.layout) {
+                problems.push(format!("mirrors[{index}].layout: {}", err.message));
+            }
+            // either one inside the other would have sort take the mirror's files for dumps
+            if mirror.location.starts_with(&self.game_location)
+                || self.game_location.starts_with(&mirror.location)
+            {
+                problems.push(format!(
+                    "mirrors[{index}].location: must be outside the game location"
+                ));
+            } else if mirror.location.exists() && !mirror.location.is_dir() {
+                problems.push(format!(
+                    "mirrors[{index}].location: \"{}\" isn't a folder",
+                    mirror.location.display()
+                ));
+            }
+        }
+        if !self.mirrors.is_empty() && self.storage.library.is_some() {
+            problems.push("mirrors: only a local library can be mirrored".to_string());
+        }
+        for extension in &self.companion_extensions {
+            if extension.is_empty() || extension.contains(['.', '/', '\\']) {
+                problems.push(format!(
+                    "companion_extensions: \"{extension}\" isn't an extension (without its dot)"
+                ));
+            }
+        }
+        if let Some(url) = &self.notifications.webhook_url
+            && !(url.starts_with("http://") || url.starts_with("https://"))
+        {
+            problems.push(format!(
+                "notifications.webhook_url: \"{url}\" isn't an HTTP(S) URL"
+            ));
+        }
+        if self
+            .notifications
+            .command
+            .first()
+            .is_some_and(|program| program.is_empty())
+        {
+            problems.push("notifications.command: the program can't be empty".to_string());
+        }
+        for (name, schedule) in [
+            ("update_schedule", &self.daemon.update_schedule),
+            ("import_schedule", &self.daemon.import_schedule),
+        ] {
+            if let Err(err) = schedule.parse::<crate::schedule::Schedule>() {
+                problems.push(format!("daemon.{name}: {}", err.message));
             }
         }
+        for pattern in &self.ignore {
+            if let Some(problem) = crate::ignore::pattern_problem(pattern) {
+                problems.push(format!("ignore: \"{pattern}\" {problem}"));
+            }
+        }
+        if self.patched_folder.as_os_str().is_empty() || self.patched_folder.is_absolute() {
+            problems.push("patched_folder: must be a folder within the game location".to_string());
+        }
+        for (name, path) in [
+            ("game_location", Some(&self.game_location)),
+            ("quarantine_location", self.quarantine_location.as_ref()),
+        ] {
+            if let Some(path) = path
+                && path.exists()
+                && !path.is_dir()
+            {
+                problems.push(format!("{name}: \"{}\" isn't a folder", path.display()));
+            }
+        }
+        // temporary folders are made in it, rather than it being made
+        if let Some(path) = &self.work_directory
+            && !path.is_dir()
+        {
+            problems.push(format!(
+                "work_directory: \"{}\" isn't a folder",
+                path.display()
+            ));
+        }
+        for (name, value) in [
+            ("connect_timeout_seconds", self.connect_timeout_seconds),
+            ("read_timeout_seconds", self.read_timeout_seconds),
+            ("download_deadline_minutes", self.download_deadline_minutes),
+        ] {
+            if value == 0 {
+                problems.push(format!("{name}: must be more than 0"));
+            }
+        }
+        for name in self.conversion_formats.keys() {
+            if name.parse::<GameConsole>().is_err() {
+                problems.push(format!("conversion_formats: unknown console \"{name}\""));
+            }
+        }
+        if let Some(url) = &self.storage.library {
+            if let Err(err) = url.parse::<StorageLocation>() {
+                problems.push(format!(
+                    "storage.library: {}",
+                    err.to_string().replace('\n', ": ")
+                ));
+            } else if !self.frontend.lists.is_empty() {
+                problems.push(
+                    "frontend.lists: game lists can't be written in a remote library".to_string(),
+                );
+            }
+        }
+        if let Some(file) = &self.storage.identity_file
+            && !file.is_file()
+        {
+            problems.push(format!(
+                "storage.identity_file: \"{}\" doesn't exist",
+                file.display()
+            ));
+        }
+        for name in self.frontend.pegasus_launch.keys() {
+            if name.parse::<GameConsole>().is_err() {
+                problems.push(format!(
+                    "frontend.pegasus_launch: unknown console \"{name}\""
+                ));
+            }
+        }
+        // maxcso's blocks hold whole sectors
+        if let Some(block_size) = self.maxcso_block_size
+            && (block_size < 2048 || !block_size.is_power_of_two())
+        {
+            problems.push("maxcso_block_size: must be a power of 2, and at least 2048".to_string());
+        }
+        if self.maxcso_threads == Some(0) {
+            problems.push("maxcso_threads: must be more than 0".to_string());
+        }
+        for (name, value) in [
+            ("conversion_jobs", self.conversion_jobs),
+            ("chdman_threads", self.chdman_threads),
+            ("conversion_jobs_per_disk", self.conversion_jobs_per_disk),
+            ("max_concurrent_reads", self.max_concurrent_reads),
+        ] {
+            if value == Some(0) {
+                problems.push(format!("{name}: must be more than 0"));
+            }
+        }
+        if self.wal_autocheckpoint_pages == Some(0) {
+            problems.push("wal_autocheckpoint_pages: must be more than 0".to_string());
+        }
+        if self.hash_buffer_size < 4096 {
+            problems.push("hash_buffer_size: must be at least 4096".to_string());
+        }
+        if let Some(rate) = self.max_read_mb_per_second
+            && (rate.is_nan() || rate <= 0.0)
+        {
+            problems.push("max_read_mb_per_second: must be more than 0".to_string());
+        }
+        for (name, path) in &self.tool_paths {
+            if name.parse::<ExternalTool>().is_err() {
+                problems.push(format!("tool_paths: unknown tool \"{name}\""));
+            } else if !path.is_file() {
+                problems.push(format!(
+                    "tool_paths.{name}: \"{}\" doesn't exist",
+                    path.display()
+                ));
+            }
+        }
+        problems
     }
     /// Saves a config file to the given storage location
     #[allow(unused)]
-    pub fn save(&self, locations: &StorageLocations) {
-        match fs::write(&locations.config_path, serde_yaml::to_string(self).unwrap()) {
-            Ok(()) => debug!("Saved config file"),
-            Err(err) => error_exit!("Failed to write configuration file: {}", err),
+    pub fn save(&self, locations: &StorageLocations) -> Result<()> {
+        fs::write(&locations.config_path, serde_yaml::to_string(self).unwrap()).map_err(|err| {
+            CliError::new(
+                ExitCode::Config,
+                format!("Failed to write configuration file: {err}"),
+            )
+        })?;
+        debug!("Saved config file");
+        Ok(())
+    }
+}
+
+/// Where a configuration file is copied when it can't be loaded, like "config.yml.bak"
+fn backup_path(config_path: &Path) -> PathBuf {
+    let mut name = config_path.file_name().unwrap().to_os_string();
+    name.push(".bak");
+    config_path.with_file_name(name)
+}
+
+pub fn run(command: ConfigCommand, locations: &StorageLocations) -> Result<()> {
+    match command {
+        // the settings were already loaded (and so checked) to run the command
+        ConfigCommand::Validate => {
+            if locations.config_path.exists() {
+                println!("\"{}\" is valid", locations.config_path.to_str().unwrap());
+            } else {
+                println!(
+                    "There's no configuration file at \"{}\", so the defaults are used",
+                    locations.config_path.to_str().unwrap()
+                );
+            }
+            Ok(())
         }
+        ConfigCommand::Tools => {
+            for tool in ExternalTool::ALL {
+                let Some(path) = tool.locate() else {
+                    println!("{}: not found", tool.name());
+                    continue;
+                };
+                let version = match tool.version() {
+                    Ok(Some(version)) => version.to_string(),
+                    Ok(None) => "unknown version".to_string(),
+                    Err(err) => {
+                        debug!("{err}");
+                        "unknown version".to_string()
+                    }
+                };
+                println!("{}: \"{}\" ({version})", tool.name(), path.display());
+            }
+            Ok(())
+        }
+    }
+}
+
+/// A setting given outside the configuration file, which takes precedence over it
+pub struct SettingOverride {
+    key: String,
+    value: String,
+    /// Where the override came from, for error messages (e.g. "--set layout=...")
+    source: String,
+}
+
+/// The prefix of environment variables overriding settings
+const ENV_PREFIX: &str = "NDUMPMGR_";
+
+impl SettingOverride {
+    /// Reads the settings given by environment variables, like `NDUMPMGR_KEEP_ORIGINALS=true`
+    pub fn from_env() -> Vec<SettingOverride> {
+        let mut overrides: Vec<SettingOverride> = env::vars()
+            .filter_map(|(name, value)| {
+                let key = name.strip_prefix(ENV_PREFIX)?.to_lowercase();
+                Some(SettingOverride {
+                    key,
+                    value,
+                    source: name,
+                })
+            })
+            .collect();
+        // the environment's order isn't meaningful, but the result should be the same every run
+        overrides.sort_by(|a, b| a.source.cmp(&b.source));
+        overrides
+    }
+    /// Parses a `--set key=value` flag
+    pub fn parse_flag(flag: &str) -> Result<SettingOverride> {
+        let Some((key, value)) = flag.split_once('=') else {
+            return Err(CliError::new(
+                ExitCode::InvalidInput,
+                format!("Expected \"--set key=value\", found \"--set {flag}\""),
+            ));
+        };
+        Ok(SettingOverride {
+            key: key.trim().to_string(),
+            value: value.to_string(),
+            source: format!("--set {flag}"),
+        })
+    }
+    /// The value as YAML, so "true", "48", and "[a, b]" aren't taken as text
+    ///
+    /// Anything which isn't valid YAML (like "{console") is text.
+    fn value(&self) -> serde_yaml::Value {
+        serde_yaml::from_str(&self.value)
+            .unwrap_or_else(|_| serde_yaml::Value::String(self.value.clone()))
     }
 }
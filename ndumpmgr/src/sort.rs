@@ -0,0 +1,1339 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use ndumplib::{
+    ChangeKind, DumpManager, ErrorCategory, FileHash, GameConsole, ROMInfo, ROMStatus,
+    SourceHandling, SplitDump, SplitKind, TransferMode, UpdateTarget, WarningKind, extract_archive,
+    find_split_dumps, report_warning, temporary_path, transfer_file, transfer_file_over,
+    work_directory,
+};
+
+use crate::{
+    collect_files,
+    companions::{Companions, companion_target, dump_stem, split_dump_stem},
+    error::{CliError, ExitCode, Result},
+    expand_paths, frontends,
+    mirror::Mirror,
+    open_manager,
+    prompt::Prompter,
+    quarantine::quarantine,
+    settings::{Settings, SplitHandling, StorageLocations},
+    storage, summary,
+};
+
+/// The folder layout used when the settings don't give one
+pub const DEFAULT_LAYOUT: &str = "{console}/{file}";
+
+/// A value filled in from a dump's game
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    /// The console's formal name, like "PlayStation"
+    Console,
+    /// The first letter of the game's name, or "#" if it doesn't start with one
+    Letter,
+    Game,
+    Region,
+    Serial,
+    /// The dump's file name in the catalog
+    File,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Field> {
+        match name {
+            "console" => Some(Self::Console),
+            "letter" => Some(Self::Letter),
+            "game" => Some(Self::Game),
+            "region" => Some(Self::Region),
+            "serial" => Some(Self::Serial),
+            "file" => Some(Self::File),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+/// Where sorted dumps go, relative to the game location, like
+/// `{console}/{letter}/{game} ({region})/{file}`
+///
+/// Each folder is literal text mixed with fields in braces: console, letter, game, region, serial,
+/// or file. The last part must be `{file}` on its own, so that every file of a game lands in the
+/// same folder, where a cuesheet or GDI can find its tracks (see [place_disc]).
+pub struct LayoutTemplate {
+    folders: Vec<Vec<Part>>,
+}
+
+fn invalid_layout(template: &str, reason: impl std::fmt::Display) -> CliError {
+    CliError::new(
+        ExitCode::Config,
+        format!("Invalid layout \"{template}\": {reason}"),
+    )
+}
+
+impl LayoutTemplate {
+    pub fn parse(template: &str) -> Result<LayoutTemplate> {
+        let mut folders: Vec<&str> = template.split('/').collect();
+        if folders.pop() != Some("{file}") {
+            return Err(invalid_layout(template, "it must end with \"{file}\""));
+        }
+        let folders = folders
+            .into_iter()
+            .map(|folder| {
+                if folder.is_empty() || folder == "." || folder == ".." {
+                    return Err(invalid_layout(
+                        template,
+                        format!("\"{folder}\" isn't a folder name"),
+                    ));
+                }
+                let mut parts = Vec::new();
+                let mut rest = folder;
+                while let Some(start) = rest.find('{') {
+                    if start > 0 {
+                        parts.push(Part::Text(rest[..start].to_string()));
+                    }
+                    let Some(length) = rest[start..].find('}') else {
+                        return Err(invalid_layout(template, "a \"{\" isn't closed"));
+                    };
+                    let name = &rest[start + 1..start + length];
+                    let field = Field::parse(name).ok_or_else(|| {
+                        invalid_layout(
+                            template,
+                            format!("unknown field \"{name}\" (expected console, letter, game, region, serial, or file)"),
+                        )
+                    })?;
+                    if field == Field::File {
+                        return Err(invalid_layout(template, "\"{file}\" must come last"));
+                    }
+                    parts.push(Part::Field(field));
+                    rest = &rest[start + length + 1..];
+                }
+                if rest.contains('}') {
+                    return Err(invalid_layout(template, "a \"}\" isn't opened"));
+                }
+                if !rest.is_empty() {
+                    parts.push(Part::Text(rest.to_string()));
+                }
+                Ok(parts)
+            })
+            .collect::<Result<_>>()?;
+        Ok(LayoutTemplate { folders })
+    }
+
+    /// The folder holding all of a console's dumps, relative to the game location, if the layout
+    /// has one (like "PlayStation" for `{console}/{letter}/{file}`)
+    ///
+    pub fn console_folder(&self, console: GameConsole) -> Option<PathBuf> {
+        let leading: Vec<&Vec<Part>> = self
+            .folders
+            .iter()
+            .take_while(|parts| {
+                parts
+                    .iter()
+                    .all(|part| matches!(part, Part::Text(_) | Part::Field(Field::Console)))
+            })
+            .collect();
+        let has_console = leading
+            .iter()
+            .any(|parts| parts.contains(&Part::Field(Field::Console)));
+        has_console.then(|| {
+            leading
+                .into_iter()
+                .map(|parts| {
+                    parts
+                        .iter()
+                        .map(|part| match part {
+                            Part::Text(text) => text.clone(),
+                            Part::Field(_) => console.formal_name().replace(['/', '\\'], "_"),
+                        })
+                        .collect::<String>()
+                })
+                .collect()
+        })
+    }
+
+    /// The path a dump belongs at, relative to the game location
+    ///
+    /// Missing regions and serials are written as "Unknown".
+    pub fn render(&self, info: &ROMInfo) -> PathBuf {
+        let value = |field: Field| -> String {
+            let value = match field {
+                Field::Console => info.console.formal_name().to_string(),
+                Field::Letter => match info.game_name.chars().next() {
+                    Some(letter) if letter.is_alphabetic() => letter.to_uppercase().to_string(),
+                    _ => "#".to_string(),
+                },
+                Field::Game => info.game_name.clone(),
+                Field::Region => info.region.clone().unwrap_or("Unknown".to_string()),
+                Field::Serial => info.serial.clone().unwrap_or("Unknown".to_string()),
+                Field::File => info.preferred_file_name.clone(),
+            };
+            // values can't add folders of their own
+            value.replace(['/', '\\'], "_")
+        };
+        let mut path: PathBuf = self
+            .folders
+            .iter()
+            .map(|parts| {
+                parts
+                    .iter()
+                    .map(|part| match part {
+                        Part::Text(text) => text.clone(),
+                        Part::Field(field) => value(*field),
+                    })
+                    .collect::<String>()
+            })
+            .collect();
+        if let Some(subfolder) = &info.subfolder {
+            path.push(subfolder.replace(['/', '\\'], "_"));
+        }
+        path.push(value(Field::File));
+        path
+    }
+}
+
+/// What happened to a dump given to [place_dump]
+pub enum Placement {
+    /// It was moved or copied to the given path, where the layout puts its game
+    Placed(PathBuf, ROMInfo),
+    /// It was already at the given path, where the layout puts its game
+    AlreadyPlaced(PathBuf, ROMInfo),
+    /// It isn't in the catalog, so it was left alone
+    Unidentified,
+    /// It couldn't be read to identify it, for the given reason
+    Failed(String),
+    /// Its place was taken, which was reported as a warning
+    Skipped,
+}
+
+/// Copies of smaller files finish too quickly for their progress to be worth logging
+const MIN_PROGRESS_SIZE: u64 = 64 << 20;
+
+/// Logs a copy's progress every 10%, since copies across filesystems can take a while
+pub fn copy_progress(file: &Path) -> impl FnMut(u64, u64) + '_ {
+    let mut reported_tenths = 0;
+    move |copied, size| {
+        if size < MIN_PROGRESS_SIZE {
+            return;
+        }
+        let tenths = copied * 10 / size.max(1);
+        if tenths > reported_tenths {
+            reported_tenths = tenths;
+            log::info!("Copying \"{}\": {}%", file.display(), tenths * 10);
+        }
+    }
+}
+
+/// Identifies a dump, then moves or copies it to where the layout puts it within `root`
+///
+/// If something is already there, it's only replaced if the prompter allows it.
+pub fn place_dump(
+    manager: &DumpManager,
+    prompter: &Prompter,
+    layout: &LayoutTemplate,
+    root: &Path,
+    file: &Path,
+    mode: TransferMode,
+) -> Result<Placement> {
+    let info = match manager.get_rom_info(file.to_str().unwrap()) {
+        Ok(Some(info)) => info,
+        Ok(None) => return Ok(Placement::Unidentified),
+        Err(err) => return Ok(Placement::Failed(err.to_string())),
+    };
+    let target = root.join(layout.render(&info));
+    if target == file {
+        return Ok(Placement::AlreadyPlaced(target, info));
+    }
+    if !make_room(prompter, &target, file)? {
+        return Ok(Placement::Skipped);
+    }
+    match transfer(file, &target, mode) {
+        Ok(()) => {}
+        // other dumps may still fit, so only this one is given up on
+        Err(err) if matches!(err.code, ExitCode::InsufficientSpace) => {
+            return Ok(Placement::Failed(err.message));
+        }
+        Err(err) => return Err(err),
+    }
+    if info.status == Some("baddump") {
+        log::warn!(
+            "\"{}\" is a known bad dump of {}, so it should be dumped again",
+            target.display(),
+            info.game_name
+        );
+    }
+    Ok(Placement::Placed(target, info))
+}
+
+/// Checks whether `file` can be placed at `target`, asking the prompter whether to replace what's
+/// there
+///
+/// Returns `false` if `target` is taken, which is reported as a warning. What's there is only
+/// replaced once the new file is complete (see [transfer]).
+fn make_room(prompter: &Prompter, target: &Path, file: &Path) -> Result<bool> {
+    if !target.exists() {
+        return Ok(true);
+    }
+    if prompter.confirm(&format!(
+        "\"{}\" is already in the library. Replace it with \"{}\"?",
+        target.display(),
+        file.display()
+    ))? {
+        log::info!("Replacing \"{}\"", target.display());
+        return Ok(true);
+    }
+    report_warning(
+        WarningKind::SkippedFile,
+        format!(
+            "Not moving \"{}\": \"{}\" is already taken",
+            file.display(),
+            target.display()
+        ),
+    );
+    Ok(false)
+}
+
+/// Moves or copies a file to `target`, creating its folder
+///
+/// What's already at `target` is replaced once the file is there in full, so it has to be checked
+/// with [make_room] first. Fails with [ExitCode::InsufficientSpace] if a copy wouldn't fit, before
+/// anything is copied.
+fn transfer(file: &Path, target: &Path, mode: TransferMode) -> Result<()> {
+    let failed = |code: ExitCode, err: String| {
+        CliError::new(
+            code,
+            format!(
+                "Failed to move \"{}\" to \"{}\": {err}",
+                file.display(),
+                target.display()
+            ),
+        )
+    };
+    std::fs::create_dir_all(target.parent().unwrap())
+        .map_err(|err| failed(ExitCode::IO, err.to_string()))?;
+    let transfer = if target.exists() {
+        transfer_file_over
+    } else {
+        transfer_file
+    };
+    transfer(file, target, mode, &mut copy_progress(file)).map_err(|err| {
+        let code = match err.category() {
+            ErrorCategory::InsufficientSpace => ExitCode::InsufficientSpace,
+            _ => ExitCode::IO,
+        };
+        failed(code, err.to_string())
+    })?;
+    log::debug!(
+        "{} \"{}\" to \"{}\"",
+        match mode {
+            TransferMode::Move => "Moved",
+            TransferMode::Copy => "Copied",
+        },
+        file.display(),
+        target.display()
+    );
+    Ok(())
+}
+
+/// Identifies a dump split into parts, then places it where the layout puts it within `root`,
+/// either as its parts (named after the game) or joined into one file
+///
+/// With [SplitHandling::Chd], joined ISOs are converted to CHDs next to where they'd go. Parts
+/// are only removed once they're joined, and only if they're moved rather than copied.
+pub fn place_split_dump(
+    manager: &DumpManager,
+    prompter: &Prompter,
+    layout: &LayoutTemplate,
+    root: &Path,
+    dump: &SplitDump,
+    mode: TransferMode,
+    handling: SplitHandling,
+) -> Result<Placement> {
+    let info = match manager.get_split_rom_info(dump) {
+        Ok(Some(info)) => info,
+        Ok(None) => return Ok(Placement::Unidentified),
+        Err(err) => return Ok(Placement::Failed(err.to_string())),
+    };
+    let target = root.join(layout.render(&info));
+    let first = &dump.parts[0];
+    if handling == SplitHandling::Keep {
+        let joined_name = target.file_name().unwrap().to_string_lossy().into_owned();
+        let targets: Vec<PathBuf> = (0..dump.parts.len())
+            .map(|index| target.with_file_name(dump.part_name(index, &joined_name)))
+            .collect();
+        if targets == dump.parts {
+            return Ok(Placement::AlreadyPlaced(target, info));
+        }
+        for (part, part_target) in dump.parts.iter().zip(&targets) {
+            if part != part_target && !make_room(prompter, part_target, part)? {
+                return Ok(Placement::Skipped);
+            }
+        }
+        for (part, part_target) in dump.parts.iter().zip(&targets) {
+            if part != part_target {
+                transfer(part, part_target, mode)?;
+            }
+        }
+        return Ok(Placement::Placed(target, info));
+    }
+    if !make_room(prompter, &target, first)? {
+        return Ok(Placement::Skipped);
+    }
+    std::fs::create_dir_all(target.parent().unwrap()).map_err(|err| {
+        CliError::new(
+            ExitCode::IO,
+            format!("Failed to create \"{}\": {err}", target.display()),
+        )
+    })?;
+    if target.exists() {
+        // what's there is only replaced once the parts are joined
+        let joined = temporary_path(&target);
+        let _ = std::fs::remove_file(&joined);
+        dump.join(&joined)?;
+        std::fs::rename(&joined, &target).map_err(|err| {
+            let _ = std::fs::remove_file(&joined);
+            CliError::new(
+                ExitCode::IO,
+                format!("Failed to replace \"{}\": {err}", target.display()),
+            )
+        })?;
+    } else {
+        dump.join(&target)?;
+    }
+    log::info!(
+        "Joined {} part(s) of \"{}\" into \"{}\"",
+        dump.parts.len(),
+        first.display(),
+        target.display()
+    );
+    if mode == TransferMode::Move {
+        for part in &dump.parts {
+            std::fs::remove_file(part).map_err(|err| {
+                CliError::new(
+                    ExitCode::IO,
+                    format!("Failed to remove \"{}\": {err}", part.display()),
+                )
+            })?;
+        }
+    }
+    if handling == SplitHandling::Chd && manager.can_convert(&target) {
+        let target_str = target.to_str().unwrap();
+        let directory = target.parent().unwrap().to_str().unwrap();
+        match manager.convert_file(target_str, directory, SourceHandling::RemoveVerified) {
+            Ok(Some(chd)) => log::info!(
+                "Converted \"{}\" to \"{}\"",
+                target.display(),
+                chd.display()
+            ),
+            Ok(None) => {}
+            // the joined dump is still there, so only the conversion is lost
+            Err(err) => report_warning(
+                WarningKind::SkippedFile,
+                format!(
+                    "Not converting \"{}\": {}",
+                    target.display(),
+                    err.to_string().replace('\n', ": ")
+                ),
+            ),
+        }
+    }
+    Ok(Placement::Placed(target, info))
+}
+
+/// A cuesheet or GDI and the tracks it lists, which are placed together (see [place_disc])
+pub struct DiscSet {
+    pub sheet: PathBuf,
+    pub tracks: Vec<PathBuf>,
+}
+
+impl DiscSet {
+    /// The sheet, then its tracks
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut files = vec![self.sheet.clone()];
+        files.extend(self.tracks.iter().cloned());
+        files
+    }
+}
+
+/// Separates the cuesheets and GDIs in `files`, along with the tracks they list, from the other
+/// files
+///
+/// Sheets which can't be read are left with the other files, to be reported when they're placed.
+pub fn find_disc_sets(manager: &DumpManager, files: Vec<PathBuf>) -> (Vec<DiscSet>, Vec<PathBuf>) {
+    let mut discs = Vec::new();
+    let mut grouped = HashSet::new();
+    for file in &files {
+        if !file
+            .extension()
+            .is_some_and(|extension| extension == "cue" || extension == "gdi")
+        {
+            continue;
+        }
+        match manager.disc_tracks(file) {
+            Ok(tracks) if !tracks.is_empty() => {
+                grouped.insert(file.clone());
+                grouped.extend(tracks.iter().cloned());
+                discs.push(DiscSet {
+                    sheet: file.clone(),
+                    tracks,
+                });
+            }
+            _ => {}
+        }
+    }
+    let files = files
+        .into_iter()
+        .filter(|file| !grouped.contains(file))
+        .collect();
+    (discs, files)
+}
+
+/// Identifies a cuesheet or GDI's tracks, then moves or copies them and the sheet to where the
+/// layout puts them within `root`, pointing the sheet at the tracks' new names
+///
+/// The set goes where the layout puts the sheet's game: the sheet's entry in the catalog or, if it
+/// has none, its first track's game. Tracks shared with other games keep their names. The set is
+/// left alone unless every track is identified, and its files are only replaced if the prompter
+/// allows it.
+pub fn place_disc(
+    manager: &DumpManager,
+    prompter: &Prompter,
+    layout: &LayoutTemplate,
+    root: &Path,
+    disc: &DiscSet,
+    mode: TransferMode,
+) -> Result<Placement> {
+    let mut track_infos = Vec::new();
+    for track in &disc.tracks {
+        if !track.is_file() {
+            return Ok(Placement::Failed(format!(
+                "its track \"{}\" is missing",
+                track.display()
+            )));
+        }
+        match manager.get_rom_info(track.to_str().unwrap()) {
+            Ok(Some(info)) => track_infos.push(info),
+            Ok(None) => return Ok(Placement::Unidentified),
+            Err(err) => return Ok(Placement::Failed(err.to_string())),
+        }
+    }
+    let (sheet_info, is_named_after_track) =
+        match manager.get_rom_info(disc.sheet.to_str().unwrap()) {
+            Ok(Some(info)) => (info, false),
+            Ok(None) => {
+                let extension = disc.sheet.extension().unwrap().to_string_lossy();
+                let info = ROMInfo {
+                    preferred_file_name: format!("{}.{extension}", track_infos[0].game_name),
+                    ..track_infos[0].clone()
+                };
+                (info, true)
+            }
+            Err(err) => return Ok(Placement::Failed(err.to_string())),
+        };
+    let sheet_target = root.join(layout.render(&sheet_info));
+    let folder = sheet_target.parent().unwrap();
+    // tracks shared with other games (like identical audio tracks) keep their names
+    let track_targets: Vec<PathBuf> = disc
+        .tracks
+        .iter()
+        .zip(&track_infos)
+        .map(|(track, info)| {
+            if info.game_name == sheet_info.game_name {
+                folder.join(layout.render(info).file_name().unwrap())
+            } else {
+                folder.join(track.file_name().unwrap())
+            }
+        })
+        .collect();
+    if sheet_target == disc.sheet && track_targets == disc.tracks {
+        return Ok(Placement::AlreadyPlaced(sheet_target, sheet_info));
+    }
+    if sheet_target != disc.sheet && !make_room(prompter, &sheet_target, &disc.sheet)? {
+        return Ok(Placement::Skipped);
+    }
+    for (track, target) in disc.tracks.iter().zip(&track_targets) {
+        if track != target && !make_room(prompter, target, track)? {
+            return Ok(Placement::Skipped);
+        }
+    }
+    if is_named_after_track {
+        report_warning(
+            WarningKind::HeuristicMatch,
+            format!(
+                "\"{}\" isn't in the catalog, so it's named \"{}\" after its first track",
+                disc.sheet.display(),
+                sheet_info.preferred_file_name
+            ),
+        );
+    }
+    // the sheet names its tracks relative to its own folder
+    let sheet_folder = disc.sheet.parent().unwrap();
+    let mut renames = HashMap::new();
+    for (track, target) in disc.tracks.iter().zip(&track_targets) {
+        if track == target {
+            continue;
+        }
+        match transfer(track, target, mode) {
+            Ok(()) => {}
+            Err(err) if matches!(err.code, ExitCode::InsufficientSpace) => {
+                return Ok(Placement::Failed(err.message));
+            }
+            Err(err) => return Err(err),
+        }
+        renames.insert(
+            track
+                .strip_prefix(sheet_folder)
+                .unwrap_or(track)
+                .to_string_lossy()
+                .into_owned(),
+            target.file_name().unwrap().to_string_lossy().into_owned(),
+        );
+    }
+    std::fs::create_dir_all(folder).map_err(|err| {
+        CliError::new(
+            ExitCode::IO,
+            format!("Failed to create \"{}\": {err}", folder.display()),
+        )
+    })?;
+    manager.write_renamed_sheet(&disc.sheet, &sheet_target, &renames)?;
+    if mode == TransferMode::Move && sheet_target != disc.sheet {
+        std::fs::remove_file(&disc.sheet).map_err(|err| {
+            CliError::new(
+                ExitCode::IO,
+                format!("Failed to remove \"{}\": {err}", disc.sheet.display()),
+            )
+        })?;
+    }
+    log::debug!(
+        "Placed \"{}\" and its {} track(s) at \"{}\"",
+        disc.sheet.display(),
+        disc.tracks.len(),
+        sheet_target.display()
+    );
+    Ok(Placement::Placed(sheet_target, sheet_info))
+}
+
+/// Moves or copies the companions of the dump with the given stem next to where it was placed at
+/// `target`, renamed after it
+///
+/// Companions whose place is taken are left where they are, unless the prompter allows replacing
+/// what's there.
+fn place_companions(
+    prompter: &Prompter,
+    companions: &mut Companions,
+    stem: &Path,
+    target: &Path,
+    mode: TransferMode,
+) -> Result<()> {
+    for companion in companions.take(stem) {
+        let companion_target = companion_target(&companion, stem, target);
+        if companion_target != companion && make_room(prompter, &companion_target, &companion)? {
+            transfer(&companion, &companion_target, mode)?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves the identified dumps in the game location to where the layout puts them
+///
+/// Dumps which aren't in the catalog are left alone. Returns the number of dumps moved.
+fn move_dumps(
+    settings: &Settings,
+    manager: &DumpManager,
+    prompter: &Prompter,
+    layout: &LayoutTemplate,
+    mirrors: &mut [Mirror],
+) -> Result<usize> {
+    let root = settings.game_location();
+    if !root.is_dir() {
+        log::info!(
+            "\"{}\" doesn't exist yet, so there's nothing to sort",
+            root.display()
+        );
+        return Ok(0);
+    }
+    let mut files = Vec::new();
+    collect_files(root, &settings.ignore, &mut files)?;
+    let (split_dumps, files) = find_split_dumps(&files);
+    let (mut companions, files) =
+        Companions::find(files, &split_dumps, &settings.companion_extensions);
+    let (discs, files) = find_disc_sets(manager, files);
+    let mut moved = 0;
+    for dump in &split_dumps {
+        // sorting never joins or extracts what's already in the library
+        let Some(stem) = split_dump_stem(dump) else {
+            continue;
+        };
+        let mode = TransferMode::Move;
+        match place_split_dump(
+            manager,
+            prompter,
+            layout,
+            root,
+            dump,
+            mode,
+            SplitHandling::Keep,
+        )? {
+            Placement::Placed(target, info) => {
+                place_companions(prompter, &mut companions, &stem, &target, mode)?;
+                moved += 1;
+                for mirror in mirrors.iter_mut() {
+                    mirror.add_split(&info, dump, &target);
+                }
+            }
+            Placement::AlreadyPlaced(target, info) => {
+                for mirror in mirrors.iter_mut() {
+                    mirror.add_split(&info, dump, &target);
+                }
+            }
+            Placement::Failed(reason) => report_warning(
+                WarningKind::SkippedFile,
+                format!("Not sorting \"{}\": {reason}", dump.parts[0].display()),
+            ),
+            _ => {}
+        }
+    }
+    for disc in &discs {
+        match place_disc(manager, prompter, layout, root, disc, TransferMode::Move)? {
+            Placement::Placed(target, info) => {
+                let stem = dump_stem(&disc.sheet);
+                place_companions(
+                    prompter,
+                    &mut companions,
+                    &stem,
+                    &target,
+                    TransferMode::Move,
+                )?;
+                moved += 1;
+                for mirror in mirrors.iter_mut() {
+                    mirror.add_disc(manager, &info, &target);
+                }
+            }
+            Placement::AlreadyPlaced(target, info) => {
+                for mirror in mirrors.iter_mut() {
+                    mirror.add_disc(manager, &info, &target);
+                }
+            }
+            Placement::Failed(reason) => report_warning(
+                WarningKind::SkippedFile,
+                format!("Not sorting \"{}\": {reason}", disc.sheet.display()),
+            ),
+            _ => {}
+        }
+    }
+    for file in files {
+        // the game location is the library, so its dumps are never copied
+        match place_dump(manager, prompter, layout, root, &file, TransferMode::Move)? {
+            Placement::Placed(target, info) => {
+                let stem = dump_stem(&file);
+                place_companions(
+                    prompter,
+                    &mut companions,
+                    &stem,
+                    &target,
+                    TransferMode::Move,
+                )?;
+                moved += 1;
+                for mirror in mirrors.iter_mut() {
+                    mirror.add(&info, &target);
+                }
+            }
+            Placement::AlreadyPlaced(target, info) => {
+                for mirror in mirrors.iter_mut() {
+                    mirror.add(&info, &target);
+                }
+            }
+            Placement::Failed(reason) => report_warning(
+                WarningKind::SkippedFile,
+                format!("Not sorting \"{}\": {reason}", file.display()),
+            ),
+            _ => {}
+        }
+    }
+    Ok(moved)
+}
+
+/// Imports dumps into the game location, where the layout puts them
+///
+/// Folders are imported with their subfolders. Without any paths, the user's Downloads folder is
+/// imported. Dumps are moved unless the settings keep originals. Files which can't be identified
+/// are quarantined (see [crate::quarantine]), rather than left mixed in with the downloads, unless
+/// the prompter declines it. For a remote library, dumps are placed in a staging folder, then
+/// uploaded (see [crate::storage]).
+pub fn import(
+    paths: Vec<PathBuf>,
+    settings: Settings,
+    locations: &StorageLocations,
+    prompter: &Prompter,
+) -> Result<()> {
+    let layout = LayoutTemplate::parse(&settings.layout)?;
+    let paths = if paths.is_empty() {
+        #[allow(deprecated)] // home_dir is deprecated
+        let downloads = std::env::home_dir()
+            .map(|home| home.join("Downloads"))
+            .ok_or_else(|| CliError::new(ExitCode::Config, "Could not find home directory."))?;
+        vec![downloads]
+    } else {
+        paths
+    };
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            collect_files(&path, &settings.ignore, &mut files)?;
+        } else if path.exists() {
+            files.push(path);
+        } else {
+            return Err(CliError::new(
+                ExitCode::IO,
+                format!("\"{}\" doesn't exist", path.display()),
+            ));
+        }
+    }
+    let manager = open_manager(&settings, locations)?;
+    let mode = if settings.keep_originals {
+        TransferMode::Copy
+    } else {
+        TransferMode::Move
+    };
+    // connecting first means a down server is found before anything is moved
+    let remote = storage::open_library(&settings)?;
+    let staging = match remote {
+        Some(_) => Some(storage::staging_folder(locations)?),
+        None => None,
+    };
+    storage::remove_leftovers(&settings, remote.as_ref(), staging.as_deref())?;
+    let root = staging.as_deref().unwrap_or(settings.game_location());
+    let quarantine_location = settings.quarantine_location(locations);
+    let mut imported = 0;
+    let mut quarantined = 0;
+    let mut settle =
+        |files: &[PathBuf], placement: Placement, mode: TransferMode| -> Result<bool> {
+            let reason = match placement {
+                Placement::Placed(..) | Placement::AlreadyPlaced(..) => {
+                    imported += 1;
+                    return Ok(true);
+                }
+                Placement::Skipped => return Ok(false),
+                Placement::Unidentified => "Not in the catalog".to_string(),
+                // library errors put their cause on a line of its own
+                Placement::Failed(reason) => {
+                    format!("Couldn't be identified: {}", reason.replace('\n', ": "))
+                }
+            };
+            for file in files {
+                if !prompter.confirm(&format!("Quarantine \"{}\"? ({reason})", file.display()))? {
+                    log::info!("Leaving \"{}\" where it is: {reason}", file.display());
+                    continue;
+                }
+                quarantine(&quarantine_location, file, &reason, mode)?;
+                quarantined += 1;
+            }
+            Ok(false)
+        };
+    let (split_dumps, files) = find_split_dumps(&files);
+    let (mut companions, files) =
+        Companions::find(files, &split_dumps, &settings.companion_extensions);
+    for dump in &split_dumps {
+        // quarantined files stay put if the quarantine folder is imported
+        if dump.parts[0].starts_with(&quarantine_location) {
+            continue;
+        }
+        if dump.kind == SplitKind::RarVolumes {
+            import_volumes(dump, mode, |files| {
+                // the extracted files are temporary, so they're moved whatever the mode
+                let (discs, files) = find_disc_sets(&manager, files.to_vec());
+                let mut all_placed = true;
+                for disc in &discs {
+                    let placement =
+                        place_disc(&manager, prompter, &layout, root, disc, TransferMode::Move)?;
+                    all_placed &= settle(&disc.files(), placement, TransferMode::Move)?;
+                }
+                for file in &files {
+                    let placement =
+                        place_dump(&manager, prompter, &layout, root, file, TransferMode::Move)?;
+                    all_placed &=
+                        settle(std::slice::from_ref(file), placement, TransferMode::Move)?;
+                }
+                Ok(all_placed)
+            })?;
+            continue;
+        }
+        let placement = place_split_dump(
+            &manager,
+            prompter,
+            &layout,
+            root,
+            dump,
+            mode,
+            settings.split_dumps,
+        )?;
+        if let (Placement::Placed(target, _), Some(stem)) = (&placement, split_dump_stem(dump)) {
+            place_companions(prompter, &mut companions, &stem, target, mode)?;
+        }
+        settle(&dump.parts, placement, mode)?;
+    }
+    let (discs, files) = find_disc_sets(&manager, files);
+    for disc in &discs {
+        if disc.sheet.starts_with(&quarantine_location) {
+            continue;
+        }
+        let placement = place_disc(&manager, prompter, &layout, root, disc, mode)?;
+        if let Placement::Placed(target, _) = &placement {
+            place_companions(
+                prompter,
+                &mut companions,
+                &dump_stem(&disc.sheet),
+                target,
+                mode,
+            )?;
+        }
+        settle(&disc.files(), placement, mode)?;
+    }
+    for file in &files {
+        if file.starts_with(&quarantine_location) {
+            continue;
+        }
+        let placement = place_dump(&manager, prompter, &layout, root, file, mode)?;
+        if let Placement::Placed(target, _) = &placement {
+            place_companions(prompter, &mut companions, &dump_stem(file), target, mode)?;
+        }
+        settle(std::slice::from_ref(file), placement, mode)?;
+    }
+    if let (Some(remote), Some(staging)) = (&remote, &staging) {
+        let uploaded = remote.upload_staged(staging, prompter)?;
+        log::info!("Uploaded {uploaded} file(s) to the library");
+    }
+    summary::record_count("imported", imported);
+    summary::record_count("quarantined", quarantined);
+    log::info!("Imported {imported} dump(s)");
+    if quarantined > 0 {
+        log::info!(
+            "Quarantined {quarantined} file(s) in \"{}\". Run `ndumpmgr quarantine list` to see why",
+            quarantine_location.display()
+        );
+    }
+    frontends::after_placing(&settings, &manager.catalog_reader());
+    Ok(())
+}
+
+/// Extracts archive volumes next to them, then has `import` place the extracted files
+///
+/// `import` returns whether every file was placed. The volumes are removed once every file they held
+/// was placed, unless originals are kept. The extracted files left over are removed either way.
+fn import_volumes(
+    dump: &SplitDump,
+    mode: TransferMode,
+    mut import: impl FnMut(&[PathBuf]) -> Result<bool>,
+) -> Result<()> {
+    let first = &dump.parts[0];
+    let mut name = std::ffi::OsString::from(".");
+    name.push(first.file_name().unwrap());
+    name.push(".extracting");
+    let directory = first.with_file_name(name);
+    let extracted = match dump.extract(&directory) {
+        Ok(extracted) => extracted,
+        Err(err) => {
+            let _ = std::fs::remove_dir_all(&directory);
+            if err.category() == ErrorCategory::SevenZipMissing {
+                return Err(err.into());
+            }
+            report_warning(
+                WarningKind::SkippedFile,
+                format!(
+                    "Not importing \"{}\": {}",
+                    first.display(),
+                    err.to_string().replace('\n', ": ")
+                ),
+            );
+            return Ok(());
+        }
+    };
+    log::info!(
+        "Extracted {} file(s) from \"{}\"",
+        extracted.len(),
+        first.display()
+    );
+    let all_placed = import(&extracted)?;
+    let _ = std::fs::remove_dir_all(&directory);
+    if all_placed && mode == TransferMode::Move {
+        for part in &dump.parts {
+            std::fs::remove_file(part).map_err(|err| {
+                CliError::new(
+                    ExitCode::IO,
+                    format!("Failed to remove \"{}\": {err}", part.display()),
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// The archives whose files `rebuild` looks through, when the archives aren't dumps themselves
+const ARCHIVE_EXTENSIONS: [&str; 3] = ["zip", "7z", "rar"];
+
+/// Finds where the layout puts a source's dump within `root`, if the library doesn't have an intact
+/// dump there yet, or has a broken one the prompter allows replacing
+///
+/// A broken dump is only replaced once the new one is complete.
+/// `source` describes where the dump comes from, for messages. `checked` holds the targets already
+/// looked at, so each is only verified (or filled) once.
+fn rebuild_target(
+    manager: &DumpManager,
+    prompter: &Prompter,
+    layout: &LayoutTemplate,
+    root: &Path,
+    info: &ROMInfo,
+    source: &str,
+    checked: &mut HashSet<PathBuf>,
+) -> Result<Option<PathBuf>> {
+    let target = root.join(layout.render(info));
+    if !checked.insert(target.clone()) {
+        return Ok(None);
+    }
+    if target.exists() {
+        let problem = match manager.verify_file(&target) {
+            Ok(ROMStatus::Verified | ROMStatus::KnownBadDump) => return Ok(None),
+            Ok(_) => "doesn't verify".to_string(),
+            Err(err) => format!(
+                "couldn't be verified ({})",
+                err.to_string().replace('\n', ": ")
+            ),
+        };
+        if !prompter.confirm(&format!(
+            "\"{}\" {problem}. Replace it with {source}?",
+            target.display()
+        ))? {
+            report_warning(
+                WarningKind::SkippedFile,
+                format!("Not repairing \"{}\": it {problem}", target.display()),
+            );
+            return Ok(None);
+        }
+    }
+    Ok(Some(target))
+}
+
+/// Copies a source file to where the layout puts it within `root`, if the library doesn't have an
+/// intact dump there yet (see [rebuild_target])
+///
+/// Returns whether the file was copied.
+fn rebuild_from(
+    manager: &DumpManager,
+    prompter: &Prompter,
+    layout: &LayoutTemplate,
+    root: &Path,
+    file: &Path,
+    checked: &mut HashSet<PathBuf>,
+) -> Result<bool> {
+    let info = match manager.get_rom_info(file.to_str().unwrap()) {
+        Ok(Some(info)) => info,
+        Ok(None) => return Ok(false),
+        Err(err) => {
+            log::debug!("Not rebuilding from \"{}\": {err}", file.display());
+            return Ok(false);
+        }
+    };
+    let source = format!("\"{}\"", file.display());
+    let Some(target) = rebuild_target(manager, prompter, layout, root, &info, &source, checked)?
+    else {
+        return Ok(false);
+    };
+    transfer(file, &target, TransferMode::Copy)?;
+    log::info!("Rebuilt \"{}\" from {source}", target.display());
+    Ok(true)
+}
+
+/// Extracts the files of an archive which the library needs straight to where the layout puts
+/// them, hashing the archive as it's decompressed rather than extracting it first
+///
+/// Returns the number of files extracted, or `None` if the archive has to be extracted to be
+/// identified: if it can't be read as it's decompressed, or holds dumps hashed as whole files
+/// (like cuesheets).
+fn rebuild_from_archive(
+    manager: &DumpManager,
+    prompter: &Prompter,
+    layout: &LayoutTemplate,
+    root: &Path,
+    archive: &Path,
+    checked: &mut HashSet<PathBuf>,
+) -> Result<Option<usize>> {
+    let entries = match manager.scan_archive(&archive) {
+        Ok(entries) => entries,
+        Err(err) => {
+            log::debug!("Extracting \"{}\" instead: {err}", archive.display());
+            return Ok(None);
+        }
+    };
+    if entries.iter().any(|entry| entry.sha1.is_none()) {
+        return Ok(None);
+    }
+    let mut rebuilt = 0;
+    for entry in &entries {
+        let Some(info) =
+            manager.get_rom_info_by_sha1(entry.sha1.unwrap(), Path::new(&entry.name))?
+        else {
+            continue;
+        };
+        let source = format!("\"{}\" in \"{}\"", entry.name, archive.display());
+        let Some(target) =
+            rebuild_target(manager, prompter, layout, root, &info, &source, checked)?
+        else {
+            continue;
+        };
+        // what's there is only replaced once the file is extracted
+        let extracted = if target.exists() {
+            temporary_path(&target)
+        } else {
+            target.clone()
+        };
+        let _ = std::fs::remove_file(temporary_path(&target));
+        std::fs::create_dir_all(target.parent().unwrap())
+            .map_err(|err| err.to_string())
+            .and_then(|_| {
+                manager
+                    .extract_archive_entry(&archive, entry, &extracted)
+                    .map_err(|err| err.to_string().replace('\n', ": "))
+            })
+            .and_then(|_| {
+                if extracted == target {
+                    return Ok(());
+                }
+                std::fs::rename(&extracted, &target).map_err(|err| {
+                    let _ = std::fs::remove_file(&extracted);
+                    err.to_string()
+                })
+            })
+            .map_err(|err| {
+                CliError::new(
+                    ExitCode::IO,
+                    format!(
+                        "Failed to extract {source} to \"{}\": {err}",
+                        target.display()
+                    ),
+                )
+            })?;
+        log::info!("Rebuilt \"{}\" from {source}", target.display());
+        rebuilt += 1;
+    }
+    Ok(Some(rebuilt))
+}
+
+/// Completes the library's games from dumps in source folders, like other ROM managers'
+/// rebuilders
+///
+/// Each source file the catalog knows, including the files in archives, is copied to where the
+/// layout puts it, unless an intact dump is already there. Dumps there which don't verify are
+/// replaced if the prompter allows it. Archives are identified as they're decompressed, so only
+/// the files the library needs are extracted, unless they have to be extracted with 7-Zip to be
+/// identified. The sources are left as they are.
+pub fn rebuild(
+    from: Vec<String>,
+    settings: Settings,
+    locations: &StorageLocations,
+    prompter: &Prompter,
+) -> Result<()> {
+    if settings.storage.library.is_some() {
+        return Err(CliError::new(
+            ExitCode::Config,
+            "Rebuilding works on a local library only. Import the dumps instead",
+        ));
+    }
+    let layout = LayoutTemplate::parse(&settings.layout)?;
+    let files = expand_paths(&from, &settings.ignore)?;
+    let manager = open_manager(&settings, locations)?;
+    storage::remove_leftovers(&settings, None, None)?;
+    let root = settings.game_location();
+    let mut checked = HashSet::new();
+    let mut rebuilt = 0;
+    let (split_dumps, files) = find_split_dumps(&files);
+    let mut archives = Vec::new();
+    for dump in &split_dumps {
+        if dump.kind == SplitKind::RarVolumes {
+            archives.push(dump.parts[0].clone());
+        } else {
+            report_warning(
+                WarningKind::SkippedFile,
+                format!(
+                    "Not rebuilding from \"{}\": split dumps have to be imported",
+                    dump.parts[0].display()
+                ),
+            );
+        }
+    }
+    for file in &files {
+        if rebuild_from(&manager, prompter, &layout, root, file, &mut checked)? {
+            rebuilt += 1;
+        } else if file
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                ARCHIVE_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+            })
+        {
+            archives.push(file.clone());
+        }
+    }
+    for archive in &archives {
+        // volumes aren't read together, so they're always extracted
+        if !split_dumps.iter().any(|dump| dump.parts[0] == *archive)
+            && let Some(extracted) =
+                rebuild_from_archive(&manager, prompter, &layout, root, archive, &mut checked)?
+        {
+            rebuilt += extracted;
+            continue;
+        }
+        let directory = tempfile::tempdir_in(work_directory()).map_err(|err| {
+            CliError::new(
+                ExitCode::IO,
+                format!("Failed to create a folder to extract into: {err}"),
+            )
+        })?;
+        let extracted = match extract_archive(archive, &directory.path()) {
+            Ok(extracted) => extracted,
+            Err(err) if err.category() == ErrorCategory::SevenZipMissing => {
+                return Err(err.into());
+            }
+            Err(err) => {
+                report_warning(
+                    WarningKind::SkippedFile,
+                    format!(
+                        "Not rebuilding from \"{}\": {}",
+                        archive.display(),
+                        err.to_string().replace('\n', ": ")
+                    ),
+                );
+                continue;
+            }
+        };
+        for file in &extracted {
+            if rebuild_from(&manager, prompter, &layout, root, file, &mut checked)? {
+                rebuilt += 1;
+            }
+        }
+    }
+    summary::record_count("rebuilt", rebuilt);
+    log::info!("Rebuilt {rebuilt} dump(s)");
+    frontends::after_placing(&settings, &manager.catalog_reader());
+    Ok(())
+}
+
+/// Reports the dumps in the game location which the catalog's changes since `since` made
+/// obsolete, with the hashes their ROMs are expected to have now
+///
+/// Only dumps named after a changed ROM or game are hashed, so this takes a moment rather than
+/// hashing the whole library. Returns the number of obsolete dumps.
+fn report_obsolete_dumps(
+    settings: &Settings,
+    manager: &DumpManager,
+    since: DateTime<Utc>,
+) -> Result<usize> {
+    let changes: Vec<_> = manager
+        .catalog_changes(since)?
+        .into_iter()
+        .filter(|change| change.removed_sha1().is_some())
+        .collect();
+    let root = settings.game_location();
+    if changes.is_empty() || !root.is_dir() {
+        return Ok(0);
+    }
+    // converted dumps are named after their games rather than their ROMs
+    let names: HashSet<&str> = changes
+        .iter()
+        .flat_map(|change| [change.rom.as_deref(), Some(change.game.as_str())])
+        .flatten()
+        .collect();
+    let mut files = Vec::new();
+    collect_files(root, &settings.ignore, &mut files)?;
+    files.retain(|file| {
+        [file.file_name(), file.file_stem()]
+            .into_iter()
+            .flatten()
+            .any(|name| name.to_str().is_some_and(|name| names.contains(name)))
+    });
+    let obsolete = manager.outdated_dumps(&changes, &files)?;
+    for (path, change) in &obsolete {
+        let reason = match (change.kind, change.new_sha1) {
+            (ChangeKind::ROMChanged, Some(sha1)) => format!(
+                "now expects \"{}\" to have SHA-1 {}",
+                change.rom.as_deref().unwrap_or(&change.game),
+                FileHash::SHA1(sha1)
+            ),
+            (ChangeKind::ROMChanged, None) => format!(
+                "no longer lists \"{}\"",
+                change.rom.as_deref().unwrap_or(&change.game)
+            ),
+            _ => format!("no longer lists \"{}\"", change.game),
+        };
+        report_warning(
+            WarningKind::ObsoleteDump,
+            format!(
+                "\"{}\" is obsolete, as {} ({}) {reason}",
+                path.display(),
+                change.datafile,
+                change.version
+            ),
+        );
+    }
+    Ok(obsolete.len())
+}
+
+/// Updates the catalog, then sorts the stored game dumps into the folders given by the layout,
+/// duplicating them into each mirror (see [Mirror])
+pub fn run(
+    settings: Settings,
+    locations: &StorageLocations,
+    prompter: &Prompter,
+    force_update: Vec<String>,
+) -> Result<()> {
+    // a broken layout is caught before spending time on updates
+    let layout = LayoutTemplate::parse(&settings.layout)?;
+    let mut mirrors = settings
+        .mirrors
+        .iter()
+        .map(Mirror::new)
+        .collect::<Result<Vec<Mirror>>>()?;
+    // setup databases
+    let mut manager = open_manager(&settings, locations)?;
+    for target in force_update {
+        let target: UpdateTarget = target.parse()?;
+        manager.force_update(target);
+    }
+    let updated = Utc::now();
+    let changed_consoles = manager.update()?;
+    if !changed_consoles.is_empty() {
+        let names: Vec<&str> = changed_consoles
+            .iter()
+            .map(|console| console.formal_name())
+            .collect();
+        log::info!(
+            "The catalog changed for {}. Dumps for these consoles may need verifying again",
+            names.join(", ")
+        );
+        // a remote library's dumps would have to be downloaded to hash them
+        if settings.storage.library.is_none() {
+            let obsolete = report_obsolete_dumps(&settings, &manager, updated)?;
+            summary::record_count("obsolete", obsolete);
+        }
+    }
+    summary::record_count("consoles_updated", changed_consoles.len());
+    let remote = storage::open_library(&settings)?;
+    storage::remove_leftovers(&settings, remote.as_ref(), None)?;
+    let moved = match remote {
+        Some(remote) => remote.sort(&settings, &manager, prompter, &layout)?,
+        None => move_dumps(&settings, &manager, prompter, &layout, &mut mirrors)?,
+    };
+    summary::record_count("moved", moved);
+    log::info!("Moved {moved} dump(s)");
+    for mirror in &mirrors {
+        mirror.report();
+    }
+    frontends::after_placing(&settings, &manager.catalog_reader());
+    Ok(())
+}
@@ -0,0 +1,321 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use ndumplib::{
+    DumpManager, LocalStorage, ROMStatus, Storage, StorageEntry, StorageLocation, WarningKind,
+    is_temporary_file, remove_temporary_files, report_warning, temporary_path,
+};
+
+use crate::{
+    companions::{Companions, companion_target, dump_stem},
+    error::{CliError, ExitCode, Result},
+    ignore::IgnoreRules,
+    prompt::Prompter,
+    settings::{Settings, StorageLocations},
+    sort::LayoutTemplate,
+};
+
+/// A library kept somewhere other than the game location, like on a NAS or seedbox (see the
+/// storage settings)
+///
+/// Dumps are imported by placing them in a local staging folder, then uploading them. Dumps
+/// already in the library are identified and verified by hashing them as they're read, without
+/// downloading them.
+pub struct RemoteLibrary {
+    storage: Box<dyn Storage>,
+    root: PathBuf,
+}
+
+/// Connects to the library's storage, if it's kept somewhere other than the game location
+///
+pub fn open_library(settings: &Settings) -> Result<Option<RemoteLibrary>> {
+    let Some(url) = &settings.storage.library else {
+        return Ok(None);
+    };
+    let location: StorageLocation = url.parse()?;
+    let storage = location.connect(settings.storage.identity_file.as_deref())?;
+    Ok(Some(RemoteLibrary {
+        storage,
+        root: location.root().to_path_buf(),
+    }))
+}
+
+/// The local folder dumps are placed in before they're uploaded to a remote library
+///
+/// Dumps which couldn't be uploaded are left there, so they're never lost.
+pub fn staging_folder(locations: &StorageLocations) -> Result<PathBuf> {
+    let folder = locations.data_path.join("staging");
+    std::fs::create_dir_all(&folder).map_err(|err| {
+        CliError::new(
+            ExitCode::IO,
+            format!("Failed to create \"{}\": {err}", folder.display()),
+        )
+    })?;
+    Ok(folder)
+}
+
+/// Removes the files a run which never finished (like one killed by a crash or power cut) left
+/// half-written in the library, its mirrors, or the staging folder, so they're never taken for
+/// dumps
+///
+/// Files still being written look the same, so this must only run with the data folder locked
+/// (see [crate::open_manager]).
+pub fn remove_leftovers(
+    settings: &Settings,
+    library: Option<&RemoteLibrary>,
+    staging: Option<&Path>,
+) -> Result<()> {
+    let mut removed = Vec::new();
+    match library {
+        Some(library) => removed.extend(
+            remove_temporary_files(library.storage.as_ref(), &library.root)?
+                .iter()
+                .map(|path| library.describe(path)),
+        ),
+        None => removed.extend(
+            remove_temporary_files(&LocalStorage, settings.game_location())?
+                .iter()
+                .map(|path| path.display().to_string()),
+        ),
+    }
+    // copies into mirrors are written the same way
+    for mirror in &settings.mirrors {
+        removed.extend(
+            remove_temporary_files(&LocalStorage, &mirror.location)?
+                .iter()
+                .map(|path| path.display().to_string()),
+        );
+    }
+    if let Some(staging) = staging {
+        removed.extend(
+            remove_temporary_files(&LocalStorage, staging)?
+                .iter()
+                .map(|path| path.display().to_string()),
+        );
+    }
+    for file in removed {
+        log::info!("Removed \"{file}\", left half-written by a run which didn't finish");
+    }
+    Ok(())
+}
+
+impl RemoteLibrary {
+    /// Where a path within the library is, for messages
+    fn describe(&self, path: &Path) -> String {
+        format!("{}{}", self.storage.name(), path.display())
+    }
+
+    /// Lists the library's files, in its subfolders too, except those matching the `ignore`
+    /// patterns
+    pub fn files(&self, ignore: &[String]) -> Result<Vec<StorageEntry>> {
+        let rules = IgnoreRules::new(ignore, &self.root);
+        let mut files = Vec::new();
+        if self.storage.metadata(&self.root)?.is_none() {
+            return Ok(files);
+        }
+        let mut folders = vec![self.root.clone()];
+        while let Some(folder) = folders.pop() {
+            let mut entries = self.storage.list(&folder)?;
+            entries.sort_by(|a, b| a.path.cmp(&b.path));
+            for entry in entries {
+                if rules.is_ignored(&entry.path, entry.is_folder) || is_temporary_file(&entry.path)
+                {
+                    log::debug!("Ignoring \"{}\"", self.describe(&entry.path));
+                } else if entry.is_folder {
+                    folders.push(entry.path);
+                } else {
+                    files.push(entry);
+                }
+            }
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(files)
+    }
+
+    /// Hashes a dump in the library as it's read, returning `None` for dumps which can only be
+    /// hashed as local files (see [DumpManager::stream_sha1])
+    fn sha1(&self, manager: &DumpManager, path: &Path) -> Result<Option<[u8; 20]>> {
+        let mut reader = self.storage.open(path)?;
+        Ok(manager.stream_sha1(path, &mut reader)?)
+    }
+
+    /// Checks whether a file can go to `target` in the library, asking the prompter whether to
+    /// replace what's there
+    ///
+    /// Returns `false` if `target` is taken, which is reported as a warning. What's there is only
+    /// replaced once the new file is complete, as uploads are (see [Self::move_file]).
+    fn make_room(&self, prompter: &Prompter, target: &Path, file: &Path) -> Result<bool> {
+        if self.storage.metadata(target)?.is_none() {
+            return Ok(true);
+        }
+        if prompter.confirm(&format!(
+            "\"{}\" is already in the library. Replace it with \"{}\"?",
+            self.describe(target),
+            file.display()
+        ))? {
+            log::info!("Replacing \"{}\"", self.describe(target));
+            return Ok(true);
+        }
+        report_warning(
+            WarningKind::SkippedFile,
+            format!(
+                "Not moving \"{}\": \"{}\" is already taken",
+                file.display(),
+                self.describe(target)
+            ),
+        );
+        Ok(false)
+    }
+
+    /// Moves a file within the library to `target`, replacing what's there
+    ///
+    /// What's there is set aside under a temporary name until the file is moved, and put back if
+    /// that fails.
+    fn move_file(&self, file: &Path, target: &Path) -> Result<()> {
+        if self.storage.metadata(target)?.is_none() {
+            return Ok(self.storage.rename(file, target)?);
+        }
+        let replaced = temporary_path(target);
+        if self.storage.metadata(&replaced)?.is_some() {
+            self.storage.remove_file(&replaced)?;
+        }
+        self.storage.rename(target, &replaced)?;
+        if let Err(err) = self.storage.rename(file, target) {
+            let _ = self.storage.rename(&replaced, target);
+            return Err(err.into());
+        }
+        Ok(self.storage.remove_file(&replaced)?)
+    }
+
+    /// Uploads what's in the staging folder to the same places in the library, removing each
+    /// file once it's uploaded
+    ///
+    /// Returns the number of files uploaded. Files whose place is taken are left in the staging
+    /// folder, unless the prompter allows replacing what's there.
+    pub fn upload_staged(&self, staging: &Path, prompter: &Prompter) -> Result<usize> {
+        let mut files = Vec::new();
+        crate::collect_files(staging, &[], &mut files)?;
+        let mut uploaded = 0;
+        for file in &files {
+            let target = self.root.join(file.strip_prefix(staging).unwrap());
+            if !self.make_room(prompter, &target, file)? {
+                continue;
+            }
+            self.storage
+                .upload(file, &target, &mut crate::sort::copy_progress(file))?;
+            log::debug!(
+                "Uploaded \"{}\" to \"{}\"",
+                file.display(),
+                self.describe(&target)
+            );
+            std::fs::remove_file(file).map_err(|err| {
+                CliError::new(
+                    ExitCode::IO,
+                    format!("Failed to remove \"{}\": {err}", file.display()),
+                )
+            })?;
+            uploaded += 1;
+        }
+        if uploaded < files.len() {
+            log::info!(
+                "{} file(s) weren't uploaded, and are still in \"{}\"",
+                files.len() - uploaded,
+                staging.display()
+            );
+        }
+        Ok(uploaded)
+    }
+
+    /// Moves the identified dumps in the library to where the layout puts them, like sorting the
+    /// game location
+    ///
+    /// Dumps which aren't in the catalog, and those which can only be identified as local files,
+    /// are left alone. Returns the number of dumps moved.
+    pub fn sort(
+        &self,
+        settings: &Settings,
+        manager: &DumpManager,
+        prompter: &Prompter,
+        layout: &LayoutTemplate,
+    ) -> Result<usize> {
+        let files: Vec<PathBuf> = self
+            .files(&settings.ignore)?
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+        let (mut companions, files) = Companions::find(files, &[], &settings.companion_extensions);
+        // cuesheets and GDIs can only be identified as local files, and their tracks stay with them
+        let mut sheet_tracks = HashSet::new();
+        for file in &files {
+            if file
+                .extension()
+                .is_some_and(|extension| extension == "cue" || extension == "gdi")
+            {
+                let mut reader = self.storage.open(file)?;
+                sheet_tracks.extend(manager.read_disc_tracks(file, &mut reader)?);
+            }
+        }
+        let mut moved = 0;
+        let mut local_only = 0;
+        for file in files {
+            if sheet_tracks.contains(&file) {
+                continue;
+            }
+            let Some(sha1) = self.sha1(manager, &file)? else {
+                local_only += 1;
+                continue;
+            };
+            let Some(info) = manager.get_rom_info_by_sha1(sha1, &file)? else {
+                continue;
+            };
+            let target = self.root.join(layout.render(&info));
+            if target == file || !self.make_room(prompter, &target, &file)? {
+                continue;
+            }
+            self.move_file(&file, &target)?;
+            log::debug!(
+                "Moved \"{}\" to \"{}\"",
+                self.describe(&file),
+                self.describe(&target)
+            );
+            let stem = dump_stem(&file);
+            for companion in companions.take(&stem) {
+                let companion_target = companion_target(&companion, &stem, &target);
+                if self.make_room(prompter, &companion_target, &companion)? {
+                    self.move_file(&companion, &companion_target)?;
+                }
+            }
+            moved += 1;
+        }
+        if local_only > 0 {
+            log::info!(
+                "{local_only} dump(s) can't be identified without downloading them (cuesheets, GDIs, and compressed images), so were left alone, along with their tracks"
+            );
+        }
+        Ok(moved)
+    }
+
+    /// Verifies the library's dumps against the catalog by hashing them as they're read
+    ///
+    /// Dumps which can only be verified as local files fail with an error saying so.
+    pub fn verify(
+        &self,
+        manager: &DumpManager,
+        ignore: &[String],
+    ) -> Result<Vec<(String, ndumplib::Result<ROMStatus>)>> {
+        let mut results = Vec::new();
+        for entry in self.files(ignore)? {
+            let result = match self.sha1(manager, &entry.path) {
+                Ok(Some(sha1)) => manager.verify_sha1(sha1),
+                Ok(None) => Err(ndumplib::Error::new_original(
+                    "Can't be verified without downloading it",
+                )),
+                Err(err) => Err(ndumplib::Error::new_original(err.message)),
+            };
+            results.push((self.describe(&entry.path), result));
+        }
+        Ok(results)
+    }
+}
@@ -0,0 +1,160 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use ndumplib::{RunTimings, Warning};
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use crate::error::{CliError, ExitCode, Result};
+
+/// The version of the `--summary-json` file's format, raised whenever it changes
+pub const SUMMARY_OUTPUT_VERSION: u32 = 3;
+
+static COUNTS: Mutex<BTreeMap<&'static str, usize>> = Mutex::new(BTreeMap::new());
+
+/// Adds to how many things the run did, like "imported" dumps, for the summary and notifications
+pub fn record_count(name: &'static str, count: usize) {
+    *COUNTS.lock().unwrap().entry(name).or_insert(0) += count;
+}
+
+/// How many things the run did, by what they were (see [record_count])
+pub fn counts() -> BTreeMap<&'static str, usize> {
+    COUNTS.lock().unwrap().clone()
+}
+
+/// Starts counting again, for each of `daemon`'s jobs
+pub fn reset_counts() {
+    COUNTS.lock().unwrap().clear();
+}
+
+#[derive(Serialize)]
+struct SummaryWarning<'a> {
+    kind: &'static str,
+    message: &'a str,
+}
+
+#[derive(Serialize)]
+struct RunSummary<'a> {
+    version: u32,
+    exit_code: i32,
+    error: Option<&'a str>,
+    warnings: Vec<SummaryWarning<'a>>,
+    counts: BTreeMap<&'static str, usize>,
+    /// Seconds spent in each stage
+    stages: BTreeMap<&'static str, f64>,
+    peak_memory_bytes: Option<u64>,
+}
+
+/// A JSON Schema describing the file written by `--summary-json`
+pub fn summary_output_schema() -> Value {
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "type": "object",
+        "properties": {
+            "version": { "type": "integer" },
+            "exit_code": { "type": "integer" },
+            "error": { "type": ["string", "null"], "description": "The error which ended the command, if any" },
+            "warnings": {
+                "type": "array",
+                "description": "Problems which didn't stop the command, in the order they happened",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "kind": { "enum": ["skipped-file", "heuristic-match", "recovery"] },
+                        "message": { "type": "string" }
+                    },
+                    "required": ["kind", "message"],
+                    "additionalProperties": false
+                }
+            },
+            "counts": {
+                "type": "object",
+                "description": "How many things the command did, like dumps \"imported\" or \"moved\"",
+                "additionalProperties": { "type": "integer" }
+            },
+            "stages": {
+                "type": "object",
+                "description": "Seconds spent downloading, parsing, importing, hashing, and converting (summed over threads)",
+                "additionalProperties": { "type": "number" }
+            },
+            "peak_memory_bytes": { "type": ["integer", "null"], "description": "Only reported on Linux" }
+        },
+        "required": ["version", "exit_code", "error", "warnings", "counts", "stages", "peak_memory_bytes"],
+        "additionalProperties": false
+    })
+}
+
+/// Counts the run's warnings by kind, since they may have scrolled by long before it ended
+pub fn log_warnings(warnings: &[Warning]) {
+    if warnings.is_empty() {
+        return;
+    }
+    let mut counts = BTreeMap::new();
+    for warning in warnings {
+        *counts.entry(warning.kind).or_insert(0) += 1;
+    }
+    let counts: Vec<String> = counts
+        .into_iter()
+        .map(|(kind, count)| format!("{count} {}", kind.name()))
+        .collect();
+    log::warn!(
+        "Finished with {} warning(s): {}",
+        warnings.len(),
+        counts.join(", ")
+    );
+}
+
+/// Logs the time spent in each stage at debug level, for `--verbose`
+pub fn log_timings(timings: &RunTimings) {
+    for (stage, duration) in &timings.stages {
+        log::debug!(
+            "Time spent on {}: {:.3}s",
+            stage.name(),
+            duration.as_secs_f64()
+        );
+    }
+    if let Some(peak_memory) = timings.peak_memory {
+        log::debug!(
+            "Peak memory: {:.1} MiB",
+            peak_memory as f64 / (1024.0 * 1024.0)
+        );
+    }
+}
+
+/// Writes the run's outcome, warnings, and timings to `path` as JSON
+pub fn write(
+    path: &str,
+    outcome: &Result<()>,
+    warnings: &[Warning],
+    timings: &RunTimings,
+) -> Result<()> {
+    let summary = RunSummary {
+        version: SUMMARY_OUTPUT_VERSION,
+        exit_code: match outcome {
+            Ok(()) => 0,
+            Err(err) => err.code as i32,
+        },
+        error: outcome.as_ref().err().map(|err| err.message.as_str()),
+        warnings: warnings
+            .iter()
+            .map(|warning| SummaryWarning {
+                kind: warning.kind.name(),
+                message: &warning.message,
+            })
+            .collect(),
+        counts: counts(),
+        stages: timings
+            .stages
+            .iter()
+            .map(|(stage, duration)| (stage.name(), duration.as_secs_f64()))
+            .collect(),
+        peak_memory_bytes: timings.peak_memory,
+    };
+    let content = serde_json::to_string_pretty(&summary)
+        .map_err(|err| CliError::new(ExitCode::Failure, err.to_string()))?;
+    std::fs::write(path, content).map_err(|err| {
+        CliError::new(
+            ExitCode::IO,
+            format!("Failed to write summary \"{path}\": {err}"),
+        )
+    })
+}